@@ -0,0 +1,178 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+
+use clvm_rs::allocator::Allocator;
+use clvm_rs::cost::Cost;
+use clvm_rs::int_allocator::IntAllocator;
+use clvm_rs::node::Node;
+use clvm_rs::op_table::{op_table_for, CHIA_OPCODES};
+use clvm_rs::run_program::run_program;
+use clvm_rs::serialize::node_from_bytes;
+use clvm_rs::tree_hash::{tree_hash, tree_hash_parallel};
+
+mod workloads;
+use workloads::{
+    benchmark_dir, generator_spends, load_bundled, load_optional, BLOCK_GENERATOR, CAT_SPEND,
+    COMPRESSED_GENERATOR,
+};
+
+const MAX_COST: Cost = Cost::new(11000000000);
+
+fn deserialize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("deserialize");
+    for name in &[BLOCK_GENERATOR, COMPRESSED_GENERATOR] {
+        let p = load_bundled(name).unwrap();
+        let mut a = IntAllocator::new();
+        group.bench_function(*name, |b| {
+            b.iter(|| {
+                a.reset();
+                node_from_bytes(&mut a, &p.program).unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn run_generator(c: &mut Criterion) {
+    let mut group = c.benchmark_group("run_generator");
+    group.sample_size(20);
+    for name in &[BLOCK_GENERATOR, COMPRESSED_GENERATOR] {
+        let p = load_bundled(name).unwrap();
+        let mut a = IntAllocator::new();
+        group.bench_function(*name, |b| {
+            b.iter(|| {
+                a.reset();
+                let program = node_from_bytes(&mut a, &p.program).unwrap();
+                let env = node_from_bytes(&mut a, &p.env).unwrap();
+                let table = op_table_for(&CHIA_OPCODES);
+                run_program(
+                    &mut a,
+                    &program,
+                    &env,
+                    1,
+                    2,
+                    MAX_COST,
+                    Box::new(table),
+                    None,
+                )
+                .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+// the standard transaction puzzle, with every spend of the block
+fn standard_puzzle(c: &mut Criterion) {
+    let p = load_bundled(BLOCK_GENERATOR).unwrap();
+    let mut a = IntAllocator::new();
+    let generator = node_from_bytes(&mut a, &p.program).unwrap();
+    let env = node_from_bytes(&mut a, &p.env).unwrap();
+    let spends = generator_spends(&mut a, generator, env, MAX_COST).unwrap();
+
+    let mut group = c.benchmark_group("standard_puzzle");
+    group.bench_function("single spend", |b| {
+        let (puzzle, solution) = spends[0];
+        b.iter(|| {
+            let table = op_table_for(&CHIA_OPCODES);
+            run_program(
+                &mut a,
+                &puzzle,
+                &solution,
+                1,
+                2,
+                MAX_COST,
+                Box::new(table),
+                None,
+            )
+            .unwrap()
+        })
+    });
+    group.sample_size(10);
+    group.bench_function("all spends", |b| {
+        b.iter(|| {
+            for (puzzle, solution) in &spends {
+                let table = op_table_for(&CHIA_OPCODES);
+                run_program(
+                    &mut a,
+                    puzzle,
+                    solution,
+                    1,
+                    2,
+                    MAX_COST,
+                    Box::new(table),
+                    None,
+                )
+                .unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+// a CAT spend, if there's one in the benchmark directory (see CAT_SPEND)
+fn cat_puzzle(c: &mut Criterion) {
+    let dir = benchmark_dir();
+    let p = match load_optional(&dir, CAT_SPEND).unwrap() {
+        Some(p) => p,
+        None => {
+            eprintln!(
+                "no {}.hex in {}, skipping cat_puzzle",
+                CAT_SPEND,
+                dir.display()
+            );
+            return;
+        }
+    };
+    let mut a = IntAllocator::new();
+    let puzzle = node_from_bytes(&mut a, &p.program).unwrap();
+    let solution = node_from_bytes(&mut a, &p.env).unwrap();
+
+    let mut group = c.benchmark_group("cat_puzzle");
+    group.bench_function("single spend", |b| {
+        b.iter(|| {
+            let table = op_table_for(&CHIA_OPCODES);
+            run_program(
+                &mut a,
+                &puzzle,
+                &solution,
+                1,
+                2,
+                MAX_COST,
+                Box::new(table),
+                None,
+            )
+            .unwrap()
+        })
+    });
+    group.finish();
+}
+
+fn hashing(c: &mut Criterion) {
+    let p = load_bundled(BLOCK_GENERATOR).unwrap();
+    let mut a = IntAllocator::new();
+    let generator = node_from_bytes(&mut a, &p.program).unwrap();
+    let env = node_from_bytes(&mut a, &p.env).unwrap();
+    let spends = generator_spends(&mut a, generator, env, MAX_COST).unwrap();
+    let puzzle = spends[0].0;
+
+    let mut group = c.benchmark_group("tree_hash");
+    group.bench_function("standard puzzle", |b| {
+        b.iter(|| tree_hash(&Node::new(&a, puzzle)))
+    });
+    group.bench_function("block", |b| b.iter(|| tree_hash(&Node::new(&a, generator))));
+    group.bench_function("block (parallel)", |b| {
+        b.iter(|| tree_hash_parallel(&Node::new(&a, generator)))
+    });
+    group.finish();
+    let _ = a.null();
+}
+
+criterion_group!(
+    benches,
+    deserialize,
+    run_generator,
+    standard_puzzle,
+    cat_puzzle,
+    hashing
+);
+criterion_main!(benches);
@@ -0,0 +1,308 @@
+// loading the workloads bundled in the benchmark/ directory, for the
+// benchmarks and `clvm_rs bench`, which includes this file by its path. It
+// isn't part of the library: benchmark_dir() defaults to where the source
+// tree was when it was built, and the generators are built in. Each of them
+// uses only some of it
+#![allow(dead_code)]
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use clvm_rs::cost::Cost;
+use clvm_rs::generator::{parse_spends, GeneratorError};
+use clvm_rs::int_allocator::IntAllocator;
+use clvm_rs::node::Node;
+use clvm_rs::op_table::{op_table_for, CHIA_OPCODES};
+use clvm_rs::reduction::EvalErr;
+use clvm_rs::run_program::run_program;
+use clvm_rs::serialize::{node_from_bytes, node_to_bytes};
+
+// a program and its environment, both serialized
+pub struct BenchProgram {
+    pub name: String,
+    pub program: Vec<u8>,
+    pub env: Vec<u8>,
+}
+
+// a block generator with 2000 standard transaction spends
+pub const BLOCK_GENERATOR: &str = "block-2000";
+// a compressed block generator. Its environment is the decompressor
+pub const COMPRESSED_GENERATOR: &str = "compressed-2000";
+
+// the standard transaction puzzle, run with the solution of the first spend in
+// block-2000
+pub const STANDARD_SPEND: &str = "standard-spend";
+
+// a CAT spend: the CAT puzzle, curried with its inner puzzle, run with the
+// spend's solution. It isn't bundled. Once cat-spend.hex and cat-spend.envhex
+// (exported from a full node) are in the directory, it's one of the standard
+// workloads
+pub const CAT_SPEND: &str = "cat-spend";
+
+const MAX_COST: Cost = Cost::new(11000000000);
+
+// the bundled generators, built in so that an installed `clvm_rs bench` can
+// run them without the source tree
+const BUILT_IN: [(&str, &str, &str); 2] = [
+    (
+        BLOCK_GENERATOR,
+        include_str!("../../benchmark/block-2000.hex"),
+        include_str!("../../benchmark/block-2000.envhex"),
+    ),
+    (
+        COMPRESSED_GENERATOR,
+        include_str!("../../benchmark/compressed-2000.hex"),
+        include_str!("../../benchmark/compressed-2000.envhex"),
+    ),
+];
+
+// $CLVM_RS_BENCHMARK_DIR, so an installed binary can find the workloads, or
+// else the benchmark directory of the source tree this was built from
+pub fn benchmark_dir() -> PathBuf {
+    match env::var_os("CLVM_RS_BENCHMARK_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => Path::new(env!("CARGO_MANIFEST_DIR")).join("benchmark"),
+    }
+}
+
+// hex, decoded. Surrounding whitespace is ignored
+fn decode_hex(s: &str) -> io::Result<Vec<u8>> {
+    hex::decode(s.trim()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+// the contents of a hex file, decoded
+pub fn read_hex(path: &Path) -> io::Result<Vec<u8>> {
+    decode_hex(&fs::read_to_string(path)?)
+}
+
+// the program <name>.hex and its environment <name>.envhex
+pub fn load_program(dir: &Path, name: &str) -> io::Result<BenchProgram> {
+    Ok(BenchProgram {
+        name: name.to_string(),
+        program: read_hex(&dir.join(format!("{}.hex", name)))?,
+        env: read_hex(&dir.join(format!("{}.envhex", name)))?,
+    })
+}
+
+pub fn load_bundled(name: &str) -> io::Result<BenchProgram> {
+    load_workload(&benchmark_dir(), name)
+}
+
+// load_program(), or the built-in copy of a bundled generator if it isn't in
+// the directory
+pub fn load_workload(dir: &Path, name: &str) -> io::Result<BenchProgram> {
+    if let Some(p) = load_optional(dir, name)? {
+        return Ok(p);
+    }
+    match BUILT_IN.iter().find(|(n, _, _)| *n == name) {
+        Some((_, program, env)) => Ok(BenchProgram {
+            name: name.to_string(),
+            program: decode_hex(program)?,
+            env: decode_hex(env)?,
+        }),
+        None => load_program(dir, name),
+    }
+}
+
+// load_program(), or None if there's no <name>.hex
+pub fn load_optional(dir: &Path, name: &str) -> io::Result<Option<BenchProgram>> {
+    match load_program(dir, name) {
+        Ok(p) => Ok(Some(p)),
+        Err(_) if !dir.join(format!("{}.hex", name)).exists() => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+// the workloads `clvm_rs bench` runs by default: a standard spend, a CAT
+// spend if there is one and the two block generators
+pub fn standard_workloads(dir: &Path) -> io::Result<Vec<BenchProgram>> {
+    let block = load_workload(dir, BLOCK_GENERATOR)?;
+    let mut workloads = vec![standard_spend(&block)?];
+    workloads.extend(load_optional(dir, CAT_SPEND)?);
+    workloads.push(block);
+    workloads.push(load_workload(dir, COMPRESSED_GENERATOR)?);
+    Ok(workloads)
+}
+
+fn standard_spend(block: &BenchProgram) -> io::Result<BenchProgram> {
+    let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+    let mut a = IntAllocator::new();
+    let generator = node_from_bytes(&mut a, &block.program).map_err(|e| invalid(e.to_string()))?;
+    let env = node_from_bytes(&mut a, &block.env).map_err(|e| invalid(e.to_string()))?;
+    let spends = generator_spends(&mut a, generator, env, MAX_COST)
+        .map_err(|e| invalid(format!("{}: {}", block.name, e.1)))?;
+    let (puzzle, solution) = match spends.first() {
+        Some(spend) => *spend,
+        None => return Err(invalid(format!("{}: no spends", block.name))),
+    };
+    Ok(BenchProgram {
+        name: STANDARD_SPEND.to_string(),
+        program: node_to_bytes(&Node::new(&a, puzzle)).map_err(|e| invalid(e.to_string()))?,
+        env: node_to_bytes(&Node::new(&a, solution)).map_err(|e| invalid(e.to_string()))?,
+    })
+}
+
+// how long each step of a workload took, at best over a number of iterations
+pub struct Timings {
+    pub deserialize: Duration,
+    pub run: Duration,
+    pub serialize: Duration,
+    pub cost: Cost,
+    // the size of the serialized result
+    pub output_len: usize,
+}
+
+// deserializes, runs and serializes the result of the program, `iterations`
+// times, keeping the fastest time of each step
+pub fn time_program(p: &BenchProgram, iterations: u32) -> Result<Timings, String> {
+    let mut a = IntAllocator::new();
+    let mut t = Timings {
+        deserialize: Duration::from_secs(u64::MAX),
+        run: Duration::from_secs(u64::MAX),
+        serialize: Duration::from_secs(u64::MAX),
+        cost: Cost::ZERO,
+        output_len: 0,
+    };
+    for _ in 0..iterations.max(1) {
+        a.reset();
+        let start = Instant::now();
+        let program = node_from_bytes(&mut a, &p.program).map_err(|e| e.to_string())?;
+        let env = node_from_bytes(&mut a, &p.env).map_err(|e| e.to_string())?;
+        let deserialized = Instant::now();
+        let r = run_program(
+            &mut a,
+            &program,
+            &env,
+            1,
+            2,
+            MAX_COST,
+            Box::new(op_table_for(&CHIA_OPCODES)),
+            None,
+        )
+        .map_err(|e| e.1.to_string())?;
+        let ran = Instant::now();
+        let output = node_to_bytes(&Node::new(&a, r.1)).map_err(|e| e.to_string())?;
+        let serialized = Instant::now();
+
+        t.deserialize = t.deserialize.min(deserialized - start);
+        t.run = t.run.min(ran - deserialized);
+        t.serialize = t.serialize.min(serialized - ran);
+        t.cost = r.0;
+        t.output_len = output.len();
+    }
+    Ok(t)
+}
+
+// runs a block generator and returns the (puzzle, solution) of every spend
+// it outputs. Each spend is ((parent_id amount) (puzzle solution))
+pub fn generator_spends(
+    a: &mut IntAllocator,
+    generator: i32,
+    env: i32,
+    max_cost: Cost,
+) -> Result<Vec<(i32, i32)>, EvalErr<i32>> {
+    let r = run_program(
+        a,
+        &generator,
+        &env,
+        1,
+        2,
+        max_cost,
+        Box::new(op_table_for(&CHIA_OPCODES)),
+        None,
+    )?;
+    match parse_spends(a, &r.1) {
+        Ok(spends) => Ok(spends.iter().map(|s| (s.puzzle, s.solution)).collect()),
+        Err(GeneratorError::MalformedSpend(spend)) => Err(EvalErr::new(spend, "malformed spend")),
+        Err(_) => unreachable!(),
+    }
+}
+
+#[test]
+fn test_generator_spends() {
+    for name in &[BLOCK_GENERATOR, COMPRESSED_GENERATOR] {
+        let p = load_bundled(name).unwrap();
+        let mut a = IntAllocator::new();
+        let generator = node_from_bytes(&mut a, &p.program).unwrap();
+        let env = node_from_bytes(&mut a, &p.env).unwrap();
+        let spends = generator_spends(&mut a, generator, env, Cost::new(11000000000)).unwrap();
+        assert!(spends.len() > 1000);
+
+        let (puzzle, solution) = spends[0];
+        let table = op_table_for(&CHIA_OPCODES);
+        let r = run_program(
+            &mut a,
+            &puzzle,
+            &solution,
+            1,
+            2,
+            Cost::new(11000000000),
+            Box::new(table),
+            None,
+        );
+        assert!(r.is_ok());
+    }
+}
+
+#[test]
+fn test_time_program() {
+    let workloads = standard_workloads(&benchmark_dir()).unwrap();
+    let names: Vec<&str> = workloads.iter().map(|p| p.name.as_str()).collect();
+    assert_eq!(
+        names,
+        [STANDARD_SPEND, BLOCK_GENERATOR, COMPRESSED_GENERATOR]
+    );
+
+    let t = time_program(&workloads[0], 2).unwrap();
+    assert!(t.cost > Cost::ZERO);
+    assert!(t.output_len > 0);
+
+    let broken = BenchProgram {
+        name: "broken".to_string(),
+        program: vec![0xff],
+        env: vec![0x80],
+    };
+    assert!(time_program(&broken, 1).is_err());
+}
+
+#[test]
+fn test_cat_workload() {
+    // a directory with only a CAT spend: the generators are built in. Any
+    // program will do for the spend, so it's the standard one
+    let dir = env::temp_dir().join(format!("clvm_rs_workloads_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    assert_eq!(
+        load_optional(&dir, CAT_SPEND).unwrap().map(|p| p.name),
+        None
+    );
+    let workloads = standard_workloads(&dir).unwrap();
+    let names: Vec<&str> = workloads.iter().map(|p| p.name.as_str()).collect();
+    assert_eq!(
+        names,
+        [STANDARD_SPEND, BLOCK_GENERATOR, COMPRESSED_GENERATOR]
+    );
+    let bundled = load_program(&benchmark_dir(), BLOCK_GENERATOR).unwrap();
+    assert_eq!(workloads[1].program, bundled.program);
+    assert!(load_workload(&dir, "factorial").is_err());
+
+    let spend = standard_spend(&load_workload(&dir, BLOCK_GENERATOR).unwrap()).unwrap();
+    fs::write(dir.join("cat-spend.hex"), hex::encode(&spend.program)).unwrap();
+    // without its environment it's an error, not left out
+    assert!(standard_workloads(&dir).is_err());
+    fs::write(dir.join("cat-spend.envhex"), hex::encode(&spend.env)).unwrap();
+    let workloads = standard_workloads(&dir).unwrap();
+    let names: Vec<&str> = workloads.iter().map(|p| p.name.as_str()).collect();
+    assert_eq!(
+        names,
+        [
+            STANDARD_SPEND,
+            CAT_SPEND,
+            BLOCK_GENERATOR,
+            COMPRESSED_GENERATOR
+        ]
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,5 @@
+fn main() {
+    // links the node.js addon against the N-API symbols node provides at load time
+    #[cfg(feature = "nodejs")]
+    napi_build::setup();
+}
@@ -0,0 +1,19 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use clvm_rs::int_allocator::IntAllocator;
+use clvm_rs::node::Node;
+use clvm_rs::serialize::{node_from_bytes, node_from_bytes_backrefs, node_to_bytes};
+
+// whatever the deserializer accepts, serializing it again gives canonical
+// bytes, which deserialize to the same tree
+fuzz_target!(|data: &[u8]| {
+    let mut a = IntAllocator::new();
+    if let Ok(node) = node_from_bytes(&mut a, data) {
+        let b1 = node_to_bytes(&Node::new(&a, node)).unwrap();
+        let node = node_from_bytes(&mut a, &b1).unwrap();
+        let b2 = node_to_bytes(&Node::new(&a, node)).unwrap();
+        assert_eq!(b1, b2);
+    }
+    let _ = node_from_bytes_backrefs(&mut IntAllocator::new(), data);
+});
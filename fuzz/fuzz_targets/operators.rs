@@ -0,0 +1,22 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use clvm_rs::fuzzing::ArbitraryTree;
+use clvm_rs::int_allocator::IntAllocator;
+use clvm_rs::op_table::{op_table_for, CHIA_OPCODES};
+
+const MAX_COST: u64 = 1_000_000;
+
+// every operator, called directly with arbitrary arguments, either fails
+// with an error or returns a result. It never panics
+fuzz_target!(|input: (u8, ArbitraryTree)| {
+    let (opcode, args) = input;
+    let table = op_table_for::<IntAllocator>(&CHIA_OPCODES);
+    let op = match table.get(&[opcode]) {
+        Some(op) => op,
+        None => return,
+    };
+    let mut a = IntAllocator::new();
+    let args = args.node(&mut a);
+    let _ = op.call(&mut a, args, MAX_COST);
+});
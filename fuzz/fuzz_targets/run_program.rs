@@ -0,0 +1,28 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use clvm_rs::fuzzing::ArbitraryTree;
+use clvm_rs::int_allocator::IntAllocator;
+use clvm_rs::op_table::{op_table_for, CHIA_OPCODES};
+use clvm_rs::run_program::run_program;
+
+// the cost of a run never goes past the limit it was given
+const MAX_COST: u64 = 1_000_000;
+
+fuzz_target!(|input: (ArbitraryTree, ArbitraryTree)| {
+    let mut a = IntAllocator::new();
+    let program = input.0.node(&mut a);
+    let env = input.1.node(&mut a);
+    if let Ok(r) = run_program(
+        &mut a,
+        &program,
+        &env,
+        1,
+        2,
+        MAX_COST,
+        Box::new(op_table_for(&CHIA_OPCODES)),
+        None,
+    ) {
+        assert!(r.0 <= MAX_COST);
+    }
+});
@@ -0,0 +1,123 @@
+/*
+ * C interface to clvm_rs. Build the library with
+ *
+ *   cargo build --release --no-default-features --features ffi
+ *
+ * Nodes are int32_t handles into an allocator. They stay valid until the
+ * allocator is reset or freed. Every function returns CLVM_OK or one of the
+ * CLVM_ERR_* codes, and after a failure clvm_last_error(),
+ * clvm_last_error_code() and clvm_last_error_node() describe it.
+ */
+
+#ifndef CLVM_H
+#define CLVM_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* These values are stable. New codes may be added, but existing ones never
+ * change meaning */
+#define CLVM_OK 0
+/* a null pointer, or a node handle not from this allocator */
+#define CLVM_ERR_INVALID_ARG 1
+/* the serialized input is truncated or malformed */
+#define CLVM_ERR_SERIALIZATION 2
+/* the program failed, for any reason not covered by a more specific code */
+#define CLVM_ERR_EVAL 3
+/* an internal error (a panic) was caught */
+#define CLVM_ERR_INTERNAL 4
+/* the program needed more than max_cost */
+#define CLVM_ERR_COST_EXCEEDED 5
+/* the program raised (operator x). clvm_last_error_node() is the list of
+ * arguments it raised with */
+#define CLVM_ERR_RAISE 6
+/* a limit of the allocator, or of the serialization format, was exceeded */
+#define CLVM_ERR_LIMIT 7
+/* the host failed to allocate memory */
+#define CLVM_ERR_OUT_OF_MEMORY 8
+
+/* returned by a clvm_operator_fn for an opcode it doesn't implement */
+#define CLVM_OP_UNHANDLED (-1)
+
+typedef struct ClvmAllocator ClvmAllocator;
+
+/* memory owned by the library, release it with clvm_buffer_free() */
+typedef struct {
+    uint8_t *data;
+    size_t len;
+} ClvmBuffer;
+
+ClvmAllocator *clvm_allocator_new(void);
+void clvm_allocator_free(ClvmAllocator *a);
+/* invalidates all node handles, but keeps the memory for reuse */
+int32_t clvm_allocator_reset(ClvmAllocator *a);
+
+void clvm_buffer_free(ClvmBuffer buf);
+
+/* the message of the last failed call on this thread, or NULL. Valid until
+ * the next failing call on the same thread */
+const char *clvm_last_error(void);
+/* the code the last failed call on this thread returned, or CLVM_OK */
+int32_t clvm_last_error_code(void);
+/* writes the node the last failed call on this thread refers to, if it was
+ * an evaluation error, to out_node. The node belongs to the allocator that
+ * call used. Returns CLVM_ERR_INVALID_ARG if there is no such node */
+int32_t clvm_last_error_node(int32_t *out_node);
+
+int32_t clvm_deserialize(ClvmAllocator *a, const uint8_t *data, size_t len,
+                         int32_t *out_node);
+int32_t clvm_serialize(const ClvmAllocator *a, int32_t node, ClvmBuffer *out);
+
+/* deserializes a node whose bytes arrive in pieces, e.g. from a socket,
+ * without buffering all of them first */
+typedef struct ClvmParser ClvmParser;
+
+ClvmParser *clvm_parser_new(void);
+void clvm_parser_free(ClvmParser *p);
+/* parses as much of data as possible into nodes of a, and writes how many
+ * bytes were used to out_used. That's all of them unless the node was
+ * completed before the end of data. Every call must use the same allocator,
+ * which must not be reset in between. After a failure the parser can only be
+ * freed */
+int32_t clvm_parser_feed(ClvmParser *p, ClvmAllocator *a, const uint8_t *data,
+                         size_t len, size_t *out_used);
+/* 1 once a complete node has been read, otherwise 0 */
+int32_t clvm_parser_is_complete(const ClvmParser *p);
+/* writes the node to out_node, or returns CLVM_ERR_INVALID_ARG if it isn't
+ * complete yet */
+int32_t clvm_parser_node(const ClvmParser *p, int32_t *out_node);
+
+/* runs program with args, using chia's operator table. A max_cost of 0
+ * means no limit */
+int32_t clvm_run(ClvmAllocator *a, int32_t program, int32_t args,
+                 uint64_t max_cost, uint64_t *out_cost, int32_t *out_node);
+
+/* an operator implemented by the host, called with the opcode and argument
+ * list of every operator chia's table doesn't implement. It may allocate
+ * nodes in a. Returns CLVM_OK after setting *out_cost and *out_node,
+ * CLVM_OP_UNHANDLED to leave the opcode to the unknown operator rules,
+ * CLVM_ERR_RAISE to raise *out_node, or any other code to fail the program */
+typedef int32_t (*clvm_operator_fn)(void *ctx, ClvmAllocator *a,
+                                    const uint8_t *op, size_t op_len,
+                                    int32_t args, uint64_t max_cost,
+                                    uint64_t *out_cost, int32_t *out_node);
+
+/* like clvm_run(), but offers operators chia's table doesn't implement to f
+ * first, passing it ctx */
+int32_t clvm_run_with_operators(ClvmAllocator *a, int32_t program,
+                                int32_t args, uint64_t max_cost,
+                                clvm_operator_fn f, void *ctx,
+                                uint64_t *out_cost, int32_t *out_node);
+
+/* writes the 32 byte tree hash of node to out */
+int32_t clvm_tree_hash(const ClvmAllocator *a, int32_t node, uint8_t *out);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,30 @@
+package net.chia.clvm;
+
+// Native CLVM, implemented in src/jni.rs. Build the library with
+// `cargo build --release --no-default-features --features jni` and put it on
+// java.library.path (or bundle it in the APK's jniLibs on Android).
+//
+// Programs and values are serialized CLVM, except for fromBytes() and
+// toBytes(), which convert between the serialized form and nested arrays: an
+// atom is a byte[] and a pair is an Object[] of length 2. Every method throws
+// ClvmException on failure.
+public final class Clvm {
+    static {
+        System.loadLibrary("clvm_rs");
+    }
+
+    private Clvm() {}
+
+    // runs a program with chia's operators. maxCost must not be negative
+    public static native RunResult runProgram(byte[] program, byte[] args, long maxCost);
+
+    public static native Object fromBytes(byte[] blob);
+
+    public static native byte[] toBytes(Object value);
+
+    // the sha256 tree hash of a serialized value
+    public static native byte[] treeHash(byte[] blob);
+
+    // the program with the (serialized) arguments bound to it
+    public static native byte[] curry(byte[] program, byte[][] args);
+}
@@ -0,0 +1,7 @@
+package net.chia.clvm;
+
+public class ClvmException extends RuntimeException {
+    public ClvmException(String message) {
+        super(message);
+    }
+}
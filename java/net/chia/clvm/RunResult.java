@@ -0,0 +1,12 @@
+package net.chia.clvm;
+
+public final class RunResult {
+    public final long cost;
+    // the serialized result
+    public final byte[] result;
+
+    public RunResult(long cost, byte[] result) {
+        this.cost = cost;
+        this.result = result;
+    }
+}
@@ -0,0 +1,70 @@
+package net.chia.clvm;
+
+import java.util.Arrays;
+
+// Checks for the natives in src/jni.rs. Build the library as described in
+// Clvm.java, then from java/:
+//
+//   javac -d build net/chia/clvm/*.java test/net/chia/clvm/*.java
+//   java -Xcheck:jni -Djava.library.path=<dir of the library> -cp build net.chia.clvm.ClvmTest
+//
+// -Xcheck:jni warns if a native holds more local references than it reserved,
+// which Android's JVM fails on, so the output should be just "ok".
+public final class ClvmTest {
+    private static void check(boolean ok, String what) {
+        if (!ok) {
+            throw new AssertionError(what);
+        }
+    }
+
+    // a list of n one-byte atoms, serialized
+    private static byte[] list(int n) {
+        byte[] blob = new byte[2 * n + 1];
+        for (int i = 0; i < n; i++) {
+            blob[2 * i] = (byte) 0xff;
+            blob[2 * i + 1] = (byte) (1 + i % 0x7f);
+        }
+        blob[2 * n] = (byte) 0x80;
+        return blob;
+    }
+
+    // n pairs nested in their first element, ((((() . ()) . ()) ...) . ()),
+    // serialized
+    private static byte[] leftNested(int n) {
+        byte[] blob = new byte[2 * n + 1];
+        Arrays.fill(blob, 0, n, (byte) 0xff);
+        Arrays.fill(blob, n, 2 * n + 1, (byte) 0x80);
+        return blob;
+    }
+
+    private static void checkRoundTrip(byte[] blob, String what) {
+        Object value = Clvm.fromBytes(blob);
+        check(Arrays.equals(Clvm.toBytes(value), blob), what);
+    }
+
+    public static void main(String[] args) {
+        // (+ 2 5) with (3 4)
+        RunResult r = Clvm.runProgram(
+                new byte[] {(byte) 0xff, 0x10, (byte) 0xff, 0x02, (byte) 0xff, 0x05, (byte) 0x80},
+                new byte[] {(byte) 0xff, 0x03, (byte) 0xff, 0x04, (byte) 0x80},
+                0);
+        check(Arrays.equals(r.result, new byte[] {0x07}), "runProgram");
+
+        Object[] pair = (Object[]) Clvm.fromBytes(new byte[] {(byte) 0xff, 0x01, (byte) 0x80});
+        check(Arrays.equals((byte[]) pair[0], new byte[] {0x01}), "fromBytes");
+        check(((byte[]) pair[1]).length == 0, "fromBytes");
+
+        // long lists and deep trees, which mustn't take a local reference
+        // per node
+        checkRoundTrip(list(10000), "list");
+        checkRoundTrip(leftNested(10000), "left nested");
+
+        try {
+            Clvm.toBytes(new Object[] {new byte[0]});
+            check(false, "toBytes of a bad pair");
+        } catch (ClvmException e) {
+            check(e.getMessage().equals("pairs must be arrays of length 2"), e.getMessage());
+        }
+        System.out.println("ok");
+    }
+}
@@ -3,6 +3,9 @@
  * of type `T`. The objects must live until the allocator disappears.
  *
  */
+use alloc::vec::Vec;
+
+use crate::number::{number_to_atom_bytes, Number};
 use crate::reduction::EvalErr;
 
 pub enum SExp<T, B> {
@@ -29,6 +32,29 @@ pub trait Allocator {
         end: u32,
     ) -> Result<Self::Ptr, EvalErr<Self::Ptr>>;
 
+    // create a new atom of the integer, in the canonical encoding every
+    // operator produces (see number_to_atom_bytes()): no redundant leading
+    // 0x00 or 0xff bytes, and zero as the empty atom
+    fn new_number(&mut self, n: Number) -> Result<Self::Ptr, EvalErr<Self::Ptr>> {
+        self.new_atom(&number_to_atom_bytes(&n))
+    }
+
+    // create a new atom of the given atoms' bytes, one after the other.
+    // total_len is their combined length. This copies them to a temporary
+    // buffer first, allocators that can should copy them straight into the
+    // new atom instead
+    fn new_concat(
+        &mut self,
+        total_len: usize,
+        nodes: &[Self::AtomBuf],
+    ) -> Result<Self::Ptr, EvalErr<Self::Ptr>> {
+        let mut v = Vec::with_capacity(total_len);
+        for node in nodes {
+            v.extend_from_slice(self.buf(node));
+        }
+        self.new_atom(&v)
+    }
+
     // The lifetime here is a bit special because IntAllocator and ArcAllocator
     // have slightly different requirements. With IntAllocator, all buffers are
     // owned by the allocator, with ArcAllocator all buffers have shared
@@ -39,4 +65,31 @@ pub trait Allocator {
     fn sexp(&self, node: &Self::Ptr) -> SExp<Self::Ptr, Self::AtomBuf>;
     fn null(&self) -> Self::Ptr;
     fn one(&self) -> Self::Ptr;
+
+    // the value of a single byte atom, or None for any other length. The
+    // evaluator checks every operator against the quote and apply keywords
+    // with this, so it should avoid touching the atom's bytes when the length
+    // alone rules it out
+    fn atom_byte(&self, node: &Self::AtomBuf) -> Option<u8> {
+        match self.buf(node) {
+            [b] => Some(*b),
+            _ => None,
+        }
+    }
+
+    // the bytes of memory the allocator holds on to, if it keeps track
+    fn arena_bytes(&self) -> Option<usize> {
+        None
+    }
+
+    // a hint that this many atoms, pairs and bytes of atom storage are about
+    // to be allocated, so storage can be grown once up front
+    fn reserve(
+        &mut self,
+        _atoms: usize,
+        _pairs: usize,
+        _atom_bytes: usize,
+    ) -> Result<(), EvalErr<Self::Ptr>> {
+        Ok(())
+    }
 }
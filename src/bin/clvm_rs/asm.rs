@@ -0,0 +1,128 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use clvm_rs::binutils::{assemble_with, disassemble_with};
+use clvm_rs::int_allocator::IntAllocator;
+use clvm_rs::io_utils::decode_blob;
+use clvm_rs::node::Node;
+use clvm_rs::serialize::{node_from_bytes, node_from_bytes_backrefs};
+
+use crate::input::{dialect_arg, keywords, keywords_arg, read_arg};
+use crate::output::serialized_hex;
+
+pub fn asm_subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("asm")
+        .about("Serializes a program in text form to hex, like opc")
+        .arg(
+            Arg::with_name("program")
+                .required(true)
+                .help("The program. @path reads it from a file and - from stdin"),
+        )
+        .arg(dialect_arg())
+        .arg(keywords_arg())
+        .arg(
+            Arg::with_name("backrefs")
+                .long("backrefs")
+                .help("Compress the output with back references"),
+        )
+}
+
+pub fn disasm_subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("disasm")
+        .about("Shows a serialized program, in hex or binary, in text form, like opd")
+        .arg(
+            Arg::with_name("program")
+                .required(true)
+                .help("The program. @path reads it from a file and - from stdin"),
+        )
+        .arg(dialect_arg())
+        .arg(keywords_arg())
+        .arg(
+            Arg::with_name("backrefs")
+                .long("backrefs")
+                .help("Accept back references"),
+        )
+}
+
+pub fn asm(m: &ArgMatches<'_>) -> Result<String, String> {
+    let arg = m.value_of("program").unwrap();
+    let text = String::from_utf8(read_arg(arg)?).map_err(|_| format!("{}: not utf-8", arg))?;
+    let mut a = IntAllocator::new();
+    let node =
+        assemble_with(&keywords(m)?, &mut a, &text).map_err(|e| format!("{}: {}", arg, e))?;
+    serialized_hex(&a, node, m.is_present("backrefs"))
+}
+
+pub fn disasm(m: &ArgMatches<'_>) -> Result<String, String> {
+    let arg = m.value_of("program").unwrap();
+    let blob = decode_blob(&read_arg(arg)?);
+    let mut a = IntAllocator::new();
+    let node = if m.is_present("backrefs") {
+        node_from_bytes_backrefs(&mut a, &blob)
+    } else {
+        node_from_bytes(&mut a, &blob)
+    }
+    .map_err(|e| format!("{}: {}", arg, e))?;
+    Ok(disassemble_with(&keywords(m)?, &Node::new(&a, node)))
+}
+
+#[cfg(test)]
+fn run_args(args: &[&str]) -> Result<String, String> {
+    let m = App::new("test")
+        .subcommand(asm_subcommand())
+        .subcommand(disasm_subcommand())
+        .get_matches_from(std::iter::once("test").chain(args.iter().cloned()));
+    match m.subcommand() {
+        ("asm", Some(m)) => asm(m),
+        ("disasm", Some(m)) => disasm(m),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn test_asm() {
+    assert_eq!(
+        run_args(&["asm", "(+ (q . 2) 5)"]).unwrap(),
+        "ff10ffff0102ff0580"
+    );
+    assert_eq!(
+        run_args(&["asm", "-d", "default", "(+ (q . 2) 5)"]).unwrap(),
+        "ff0cffff0102ff0580"
+    );
+    assert_eq!(
+        run_args(&["asm", "--backrefs", "(\"foo\" . \"foo\")"]).unwrap(),
+        "ff83666f6ffe02"
+    );
+    assert!(run_args(&["asm", "(+ 2"]).is_err());
+
+    assert_eq!(
+        run_args(&["disasm", "ff10ffff0102ff0580"]).unwrap(),
+        "(+ (q . 2) 5)"
+    );
+    assert_eq!(
+        run_args(&["disasm", "--dialect", "default", "ff0cffff0102ff0580"]).unwrap(),
+        "(+ (q . 2) 5)"
+    );
+    assert_eq!(
+        run_args(&["disasm", "--backrefs", "ff83666f6ffe02"]).unwrap(),
+        "(\"foo\" . \"foo\")"
+    );
+    assert!(run_args(&["disasm", "ff10"]).is_err());
+
+    // extension operators round trip with a keyword table
+    let path = std::env::temp_dir().join("clvm_rs_test_keywords.txt");
+    std::fs::write(&path, "0x3b bls_verify\n").unwrap();
+    let path = path.to_str().unwrap();
+    assert_eq!(
+        run_args(&["asm", "-k", path, "(bls_verify 1 (0x7f))"]).unwrap(),
+        "ff3bff01ffff7f8080"
+    );
+    assert_eq!(
+        run_args(&["disasm", "-k", path, "ff3bff01ffff7f8080"]).unwrap(),
+        "(bls_verify 1 (0x7f))"
+    );
+    assert_eq!(
+        run_args(&["disasm", "ff3bff01ffff7f8080"]).unwrap(),
+        "(59 1 (127))"
+    );
+    assert!(run_args(&["asm", "-k", "/nonexistent", "()"]).is_err());
+}
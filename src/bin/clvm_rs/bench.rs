@@ -0,0 +1,178 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use clvm_rs::err_utils::json_string;
+
+use crate::output::json_object;
+use crate::workloads::{benchmark_dir, load_program, standard_workloads, time_program};
+
+pub fn subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("bench")
+        .about("Times deserializing, running and serializing a standard set of programs")
+        .arg(
+            Arg::with_name("dir")
+                .short("d")
+                .long("dir")
+                .takes_value(true)
+                .help(
+                    "Where the workloads are. Defaults to $CLVM_RS_BENCHMARK_DIR, or the \
+                     benchmark directory of the source tree. The generators are built in",
+                ),
+        )
+        .arg(
+            Arg::with_name("workload")
+                .short("w")
+                .long("workload")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help(
+                    "Also run <dir>/<name>.hex with <dir>/<name>.envhex. <dir>/cat-spend \
+                     is run by default when it's there",
+                ),
+        )
+        .arg(
+            Arg::with_name("iterations")
+                .short("n")
+                .long("iterations")
+                .takes_value(true)
+                .default_value("5")
+                .help("Run each workload this many times, and report the fastest"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .possible_values(&["text", "json"])
+                .default_value("text"),
+        )
+}
+
+fn millis(d: Duration) -> String {
+    format!("{:.3}", d.as_secs_f64() * 1000.0)
+}
+
+pub fn bench(m: &ArgMatches<'_>) -> Result<String, String> {
+    let dir = match m.value_of("dir") {
+        Some(dir) => PathBuf::from(dir),
+        None => benchmark_dir(),
+    };
+    let iterations = m.value_of("iterations").unwrap();
+    let iterations: u32 = iterations
+        .parse()
+        .map_err(|_| format!("invalid iterations: {}", iterations))?;
+
+    let mut workloads =
+        standard_workloads(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    for name in m.values_of("workload").into_iter().flatten() {
+        workloads.push(load_program(&dir, name).map_err(|e| format!("{}: {}", name, e))?);
+    }
+
+    let json = m.value_of("output") == Some("json");
+    let mut rows = Vec::new();
+    for p in &workloads {
+        let t = time_program(p, iterations).map_err(|e| format!("{}: {}", p.name, e))?;
+        rows.push(if json {
+            json_object(&[
+                ("name", json_string(&p.name)),
+                ("cost", t.cost.to_string()),
+                ("input_bytes", (p.program.len() + p.env.len()).to_string()),
+                ("output_bytes", t.output_len.to_string()),
+                ("deserialize_ms", millis(t.deserialize)),
+                ("run_ms", millis(t.run)),
+                ("serialize_ms", millis(t.serialize)),
+            ])
+        } else {
+            format!(
+                "{:<20} {:>12} {:>10} {:>10} {:>14} {:>10} {:>12}",
+                p.name,
+                t.cost,
+                p.program.len() + p.env.len(),
+                t.output_len,
+                millis(t.deserialize),
+                millis(t.run),
+                millis(t.serialize)
+            )
+        });
+    }
+    Ok(if json {
+        format!("[{}]", rows.join(","))
+    } else {
+        let header = format!(
+            "{:<20} {:>12} {:>10} {:>10} {:>14} {:>10} {:>12}",
+            "workload", "cost", "in bytes", "out bytes", "deserialize ms", "run ms", "serialize ms"
+        );
+        format!("{}\n{}", header, rows.join("\n"))
+    })
+}
+
+#[test]
+fn test_bench() {
+    let m = App::new("test")
+        .subcommand(subcommand())
+        .get_matches_from(vec![
+            "test",
+            "bench",
+            "-n",
+            "1",
+            "-w",
+            "factorial",
+            "-o",
+            "json",
+        ]);
+    assert!(bench(m.subcommand_matches("bench").unwrap()).is_err());
+
+    let m = App::new("test")
+        .subcommand(subcommand())
+        .get_matches_from(vec!["test", "bench", "-n", "1"]);
+    let out = bench(m.subcommand_matches("bench").unwrap()).unwrap();
+    let names: Vec<&str> = out
+        .lines()
+        .map(|l| l.split_whitespace().next().unwrap())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "workload",
+            "standard-spend",
+            "block-2000",
+            "compressed-2000"
+        ]
+    );
+
+    // a directory with a CAT spend, (q . 1) here, and none of the generators,
+    // like an installed binary's
+    let dir = std::env::temp_dir().join("clvm_rs_test_bench");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("cat-spend.hex"), "ff0101").unwrap();
+    std::fs::write(dir.join("cat-spend.envhex"), "80").unwrap();
+    let m = App::new("test")
+        .subcommand(subcommand())
+        .get_matches_from(vec![
+            "test",
+            "bench",
+            "-n",
+            "1",
+            "-d",
+            dir.to_str().unwrap(),
+        ]);
+    let out = bench(m.subcommand_matches("bench").unwrap()).unwrap();
+    let names: Vec<&str> = out
+        .lines()
+        .map(|l| l.split_whitespace().next().unwrap())
+        .collect();
+    assert_eq!(
+        names,
+        [
+            "workload",
+            "standard-spend",
+            "cat-spend",
+            "block-2000",
+            "compressed-2000"
+        ]
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,114 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use clvm_rs::binutils::disassemble_for;
+use clvm_rs::diff::diff;
+use clvm_rs::int_allocator::IntAllocator;
+use clvm_rs::node::Node;
+use clvm_rs::number::atom_bytes_to_decimal;
+
+use crate::input::{dialect, dialect_arg, parse_arg, InputFormat};
+
+// longer subtrees are cut short
+const MAX_TEXT: usize = 200;
+
+pub fn subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("diff")
+        .about("Shows where two serialized programs differ")
+        .arg(
+            Arg::with_name("left")
+                .required(true)
+                .help("A program, in hex or binary. @path reads it from a file and - from stdin"),
+        )
+        .arg(
+            Arg::with_name("right")
+                .required(true)
+                .help("The program to compare it with"),
+        )
+        .arg(
+            Arg::with_name("backrefs")
+                .long("backrefs")
+                .help("Accept back references"),
+        )
+        .arg(
+            Arg::with_name("max")
+                .short("n")
+                .long("max")
+                .takes_value(true)
+                .default_value("10")
+                .help("Show at most this many differences. 0 shows them all"),
+        )
+        .arg(dialect_arg())
+}
+
+fn shorten(mut text: String) -> String {
+    if text.len() > MAX_TEXT {
+        let mut end = MAX_TEXT;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+        text.push_str("...");
+    }
+    text
+}
+
+// nothing if the programs are the same. Otherwise each difference is its path
+// followed by the subtrees there, and the exit code is 1, like diff(1)
+pub fn diff_programs(m: &ArgMatches<'_>) -> Result<String, String> {
+    let format = InputFormat {
+        serialized: true,
+        backrefs: m.is_present("backrefs"),
+    };
+    let max = m.value_of("max").unwrap();
+    let max: usize = max.parse().map_err(|_| format!("invalid max: {}", max))?;
+    let dialect = dialect(m);
+
+    let mut a = IntAllocator::new();
+    let left = parse_arg(&mut a, m.value_of("left").unwrap(), format)?;
+    let right = parse_arg(&mut a, m.value_of("right").unwrap(), format)?;
+    let differences = diff(&a, left, right, max);
+    if differences.is_empty() {
+        return Ok(String::new());
+    }
+    let text = |node| shorten(disassemble_for(&dialect, &Node::new(&a, node)));
+    let report: Vec<String> = differences
+        .iter()
+        .map(|d| {
+            format!(
+                "at path {}:\n- {}\n+ {}",
+                atom_bytes_to_decimal(&d.path),
+                text(d.left),
+                text(d.right)
+            )
+        })
+        .collect();
+    Err(report.join("\n"))
+}
+
+#[test]
+fn test_diff() {
+    let run = |args: &[&str]| {
+        let m = App::new("test")
+            .subcommand(subcommand())
+            .get_matches_from(std::iter::once("test").chain(args.iter().cloned()));
+        diff_programs(m.subcommand_matches("diff").unwrap())
+    };
+    // (1 (100 101) 4) against (1 (100 . 102) 6)
+    let left = "ff01ffff64ff6580ff0480";
+    let right = "ff01ffff6466ff0680";
+    assert_eq!(run(&["diff", left, left]), Ok(String::new()));
+    assert_eq!(
+        run(&["diff", left, right]),
+        Err("at path 13:\n- (101)\n+ 102\nat path 11:\n- 4\n+ 6".to_string())
+    );
+    assert_eq!(
+        run(&["diff", "-n", "1", left, right]),
+        Err("at path 13:\n- (101)\n+ 102".to_string())
+    );
+    assert!(run(&["diff", left, "ff01"])
+        .unwrap_err()
+        .contains("end of input"));
+
+    let long = "a".repeat(300);
+    assert_eq!(shorten(long).len(), MAX_TEXT + 3);
+}
@@ -0,0 +1,114 @@
+use clap::{Arg, ArgMatches};
+
+use clvm_rs::binutils::{assemble, Keywords};
+use clvm_rs::int_allocator::IntAllocator;
+use clvm_rs::io_utils::{decode_blob, read_input};
+use clvm_rs::op_table::{dialect_by_name, Dialect, DivRounding};
+use clvm_rs::serialize::{node_from_bytes, node_from_bytes_backrefs};
+use clvm_rs::symbols::SymbolTable;
+
+const DIALECT_NAMES: [&str; 2] = ["chia", "default"];
+
+// how a program or environment argument is given on the command line
+#[derive(Clone, Copy)]
+pub struct InputFormat {
+    // serialized (hex or binary) rather than text
+    pub serialized: bool,
+    // serialized input may use back references
+    pub backrefs: bool,
+}
+
+// the contents of an argument. "@path" reads the file at path and "-" reads
+// stdin, anything else is taken literally
+pub fn read_arg(arg: &str) -> Result<Vec<u8>, String> {
+    if arg == "-" {
+        read_input("-").map_err(|e| format!("stdin: {}", e))
+    } else if let Some(path) = arg.strip_prefix('@') {
+        read_input(path).map_err(|e| format!("{}: {}", path, e))
+    } else {
+        Ok(arg.as_bytes().to_vec())
+    }
+}
+
+pub fn parse_arg(a: &mut IntAllocator, arg: &str, format: InputFormat) -> Result<i32, String> {
+    let data = read_arg(arg)?;
+    if format.serialized {
+        let blob = decode_blob(&data);
+        let r = if format.backrefs {
+            node_from_bytes_backrefs(a, &blob)
+        } else {
+            node_from_bytes(a, &blob)
+        };
+        r.map_err(|e| format!("{}: {}", arg, e))
+    } else {
+        let text = String::from_utf8(data).map_err(|_| format!("{}: not utf-8", arg))?;
+        assemble(a, &text).map_err(|e| format!("{}: {}", arg, e))
+    }
+}
+
+pub fn dialect_arg() -> Arg<'static, 'static> {
+    Arg::with_name("dialect")
+        .short("d")
+        .long("dialect")
+        .takes_value(true)
+        .possible_values(&DIALECT_NAMES)
+        .default_value("chia")
+        .help("The keyword table")
+}
+
+pub fn dialect(m: &ArgMatches<'_>) -> Dialect {
+    div_rounding(dialect_by_name(m.value_of("dialect").unwrap()).unwrap(), m)
+}
+
+pub fn floor_div_arg() -> Arg<'static, 'static> {
+    Arg::with_name("floor-div")
+        .long("floor-div")
+        .help("Round / toward negative infinity, like divmod, rather than as chia always has")
+}
+
+// the dialect with / rounding as --floor-div says
+pub fn div_rounding(dialect: Dialect, m: &ArgMatches<'_>) -> Dialect {
+    if m.is_present("floor-div") {
+        dialect.with_div(DivRounding::Floor)
+    } else {
+        dialect
+    }
+}
+
+pub fn keywords_arg() -> Arg<'static, 'static> {
+    Arg::with_name("keywords")
+        .short("k")
+        .long("keywords")
+        .takes_value(true)
+        .help("A file of extra keywords, a line each: an opcode (e.g. 0x3b) and its keyword")
+}
+
+// the dialect's keywords, and any from --keywords. With extra keywords,
+// other unknown operators are shown in hex
+pub fn keywords(m: &ArgMatches<'_>) -> Result<Keywords, String> {
+    let mut ret = Keywords::for_dialect(&dialect(m));
+    if let Some(path) = m.value_of("keywords") {
+        let data = read_input(path).map_err(|e| format!("{}: {}", path, e))?;
+        let text = String::from_utf8(data).map_err(|_| format!("{}: not utf-8", path))?;
+        ret.extend_from_text(&text)
+            .map_err(|e| format!("{}: {}", path, e))?;
+        ret.hex_unknown_ops = true;
+    }
+    Ok(ret)
+}
+
+pub fn symbols_arg() -> Arg<'static, 'static> {
+    Arg::with_name("symbols")
+        .short("s")
+        .long("symbols")
+        .takes_value(true)
+        .help("A chialisp .sym file, to name functions by")
+}
+
+// the table from --symbols, or an empty one
+pub fn symbols(m: &ArgMatches<'_>) -> Result<SymbolTable, String> {
+    match m.value_of("symbols") {
+        Some(path) => SymbolTable::read_sym_file(path),
+        None => Ok(SymbolTable::new()),
+    }
+}
@@ -0,0 +1,69 @@
+// the clvm_rs command line tool. Build it with the cli feature:
+//
+//   cargo build --release --no-default-features --features cli
+
+use std::process::exit;
+
+use clap::{App, AppSettings};
+
+mod asm;
+mod bench;
+mod diff;
+mod input;
+mod output;
+mod profile;
+mod repl;
+mod run;
+mod spec;
+mod treehash;
+mod verify;
+// shared with the benchmarks
+#[path = "../../../benches/workloads/mod.rs"]
+mod workloads;
+
+fn main() {
+    let m = App::new("clvm_rs")
+        .about("Tools for running and inspecting CLVM programs")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(run::subcommand())
+        .subcommand(asm::asm_subcommand())
+        .subcommand(asm::disasm_subcommand())
+        .subcommand(profile::subcommand())
+        .subcommand(bench::subcommand())
+        .subcommand(verify::subcommand())
+        .subcommand(repl::subcommand())
+        .subcommand(treehash::subcommand())
+        .subcommand(diff::subcommand())
+        .subcommand(spec::subcommand())
+        .get_matches();
+
+    let r = match m.subcommand() {
+        ("run", Some(m)) => run::run(m),
+        ("asm", Some(m)) => asm::asm(m),
+        ("disasm", Some(m)) => asm::disasm(m),
+        ("profile", Some(m)) => profile::profile(m),
+        ("bench", Some(m)) => bench::bench(m),
+        ("verify", Some(m)) => verify::verify(m),
+        ("repl", Some(m)) => repl::repl(m),
+        ("treehash", Some(m)) => treehash::treehash(m),
+        ("diff", Some(m)) => diff::diff_programs(m),
+        ("spec", Some(m)) => spec::spec(m),
+        _ => unreachable!(),
+    };
+    // diff and spec fail with their report, like diff(1), so it belongs on
+    // stdout. Every other failure is an error message
+    let report = matches!(m.subcommand_name(), Some("diff") | Some("spec"));
+    match r {
+        // subcommands that write their own output return nothing
+        Ok(out) if out.is_empty() => {}
+        Ok(out) => println!("{}", out),
+        Err(msg) if report => {
+            println!("{}", msg);
+            exit(1);
+        }
+        Err(msg) => {
+            eprintln!("{}", msg);
+            exit(1);
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use clvm_rs::binutils::disassemble;
+use clvm_rs::err_utils::json_string;
+use clvm_rs::int_allocator::IntAllocator;
+use clvm_rs::node::Node;
+use clvm_rs::serialize::{node_to_bytes, node_to_bytes_backrefs};
+
+pub const OUTPUT_FORMATS: [&str; 3] = ["text", "hex", "json"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    Hex,
+    Json,
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(Self::Text),
+            "hex" => Some(Self::Hex),
+            "json" => Some(Self::Json),
+            _ => None,
+        }
+    }
+}
+
+pub fn serialized_hex(a: &IntAllocator, node: i32, backrefs: bool) -> Result<String, String> {
+    let node = Node::new(a, node);
+    let r = if backrefs {
+        node_to_bytes_backrefs(&node)
+    } else {
+        node_to_bytes(&node)
+    };
+    r.map(hex::encode).map_err(|e| e.to_string())
+}
+
+// a JSON object with the given fields, whose values are already JSON
+pub fn json_object(fields: &[(&str, String)]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(k, v)| format!("{}:{}", json_string(k), v))
+        .collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+// the node in the given format. JSON has both the hex serialization and the
+// text form
+pub fn format_node(
+    a: &IntAllocator,
+    node: i32,
+    format: OutputFormat,
+    backrefs: bool,
+) -> Result<String, String> {
+    Ok(match format {
+        OutputFormat::Text => disassemble(&Node::new(a, node)),
+        OutputFormat::Hex => serialized_hex(a, node, backrefs)?,
+        OutputFormat::Json => json_object(&node_fields(a, node, backrefs)?),
+    })
+}
+
+pub fn node_fields(
+    a: &IntAllocator,
+    node: i32,
+    backrefs: bool,
+) -> Result<Vec<(&'static str, String)>, String> {
+    Ok(vec![
+        ("hex", json_string(&serialized_hex(a, node, backrefs)?)),
+        ("text", json_string(&disassemble(&Node::new(a, node)))),
+    ])
+}
+
+#[test]
+fn test_format_node() {
+    use clvm_rs::allocator::Allocator;
+
+    let mut a = IntAllocator::new();
+    let foo = a.new_atom(b"foo").unwrap();
+    let null = a.null();
+    let node = a.new_pair(foo, null).unwrap();
+    let f = |format| format_node(&a, node, format, false).unwrap();
+    assert_eq!(f(OutputFormat::Text), "(\"foo\")");
+    assert_eq!(f(OutputFormat::Hex), "ff83666f6f80");
+    assert_eq!(
+        f(OutputFormat::Json),
+        r#"{"hex":"ff83666f6f80","text":"(\"foo\")"}"#
+    );
+}
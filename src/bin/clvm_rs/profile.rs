@@ -0,0 +1,152 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use clvm_rs::binutils::{disassemble, keyword_for_opcode};
+use clvm_rs::cost::Cost;
+use clvm_rs::int_allocator::IntAllocator;
+use clvm_rs::io_utils::write_output;
+use clvm_rs::node::Node;
+use clvm_rs::op_table::CHIA_DIALECT;
+use clvm_rs::profile::{Profiler, Stats};
+use clvm_rs::run_program::{run_program_with_params, RunParams};
+
+use crate::input::{div_rounding, symbols, symbols_arg};
+use crate::run::{operators, parse_cost, parse_program, program_args};
+
+pub fn subcommand() -> App<'static, 'static> {
+    program_args(
+        SubCommand::with_name("profile")
+            .about("Runs a program and shows what its cost is spent on"),
+    )
+    .arg(symbols_arg())
+    .arg(
+        Arg::with_name("collapsed")
+            .long("collapsed")
+            .takes_value(true)
+            .help(
+                "Write the cost of each stack of functions here, for flamegraph tools. - is stdout",
+            ),
+    )
+}
+
+fn opcode_label(opcode: &Option<Vec<u8>>) -> String {
+    match opcode {
+        None => "(eval)".to_string(),
+        Some(o) => match keyword_for_opcode(&CHIA_DIALECT, o) {
+            Some(kw) => kw.to_string(),
+            None => format!("0x{}", hex::encode(o)),
+        },
+    }
+}
+
+fn table(title: &str, rows: &[(String, Stats)], total: Cost) -> String {
+    let width = rows
+        .iter()
+        .map(|r| r.0.len())
+        .chain(std::iter::once(title.len()))
+        .max()
+        .unwrap();
+    let mut out = format!(
+        "{:<w$} {:>10} {:>14} {:>7}\n",
+        title,
+        "calls",
+        "cost",
+        "%",
+        w = width
+    );
+    for (label, stats) in rows {
+        let share = if total == Cost::ZERO {
+            0.0
+        } else {
+            stats.cost.get() as f64 * 100.0 / total.get() as f64
+        };
+        out += &format!(
+            "{:<w$} {:>10} {:>14} {:>7.2}\n",
+            label,
+            stats.calls,
+            stats.cost,
+            share,
+            w = width
+        );
+    }
+    out
+}
+
+// the result and cost, then the cost by opcode and by function
+pub fn profile(m: &ArgMatches<'_>) -> Result<String, String> {
+    let mut a = IntAllocator::new();
+    let (program, env) = parse_program(&mut a, m)?;
+    let max_cost = parse_cost(m)?;
+    let symbols = symbols(m)?;
+
+    let mut profiler = Profiler::new();
+    let r = run_program_with_params(
+        &mut a,
+        &program,
+        &env,
+        operators(&div_rounding(CHIA_DIALECT, m), m.is_present("strict")),
+        None,
+        RunParams::new()
+            .with_max_cost(max_cost)
+            .with_tracer(&mut profiler),
+    );
+    // a failed run is still worth profiling, up to where it failed
+    let (mut out, total) = match r {
+        Ok(r) => (
+            format!("cost = {}\n{}\n", r.0, disassemble(&Node::new(&a, r.1))),
+            r.0,
+        ),
+        Err(e) => {
+            let ops = profiler.op_costs();
+            let spent = ops.iter().map(|o| o.1.cost).sum();
+            (
+                format!(
+                    "FAIL: {} {}\ncost = {}\n",
+                    e.1,
+                    disassemble(&Node::new(&a, e.0)),
+                    spent
+                ),
+                spent,
+            )
+        }
+    };
+
+    let ops: Vec<(String, Stats)> = profiler
+        .op_costs()
+        .iter()
+        .map(|(opcode, stats)| (opcode_label(opcode), *stats))
+        .collect();
+    out += "\n";
+    out += &table("opcode", &ops, total);
+    out += "\n";
+    out += &table("function", &profiler.subroutine_costs(&a, &symbols), total);
+
+    if let Some(path) = m.value_of("collapsed") {
+        let mut stacks = profiler.collapsed_stacks(&a, &symbols).join("\n");
+        stacks.push('\n');
+        write_output(path, stacks.as_bytes()).map_err(|e| format!("{}: {}", path, e))?;
+    }
+    Ok(out.trim_end().to_string())
+}
+
+#[test]
+fn test_profile() {
+    let m = App::new("test")
+        .subcommand(subcommand())
+        .get_matches_from(vec!["test", "profile", "(a (q + 2 5) (q 3 4))"]);
+    let out = profile(m.subcommand_matches("profile").unwrap()).unwrap();
+    let mut lines = out.lines();
+    assert_eq!(lines.next(), Some("cost = 987"));
+    assert_eq!(lines.next(), Some("7"));
+    let row = |label: &str| {
+        out.lines()
+            .find(|l| l.split_whitespace().next() == Some(label))
+            .unwrap()
+            .split_whitespace()
+            .skip(1)
+            .take(2)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(row("+"), ["1", "755"]);
+    assert_eq!(row("q"), ["2", "40"]);
+    assert_eq!(row("a"), ["1", "90"]);
+}
@@ -0,0 +1,238 @@
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+
+use clap::{App, ArgMatches, SubCommand};
+
+use clvm_rs::allocator::{Allocator, SExp};
+use clvm_rs::binutils::{assemble_for, disassemble_for};
+use clvm_rs::cost::Cost;
+use clvm_rs::int_allocator::IntAllocator;
+use clvm_rs::io_utils::{decode_blob, read_input};
+use clvm_rs::node::Node;
+use clvm_rs::op_table::Dialect;
+use clvm_rs::run_program::run_program;
+use clvm_rs::serialize::node_from_bytes;
+
+use crate::input::{dialect, dialect_arg, floor_div_arg};
+use crate::run::{max_cost_arg, operators, parse_cost, strict_arg};
+
+const HELP: &str = "\
+<expr>               run expr with the environment, e.g. (+ 2 5)
+:env <expr>          set the environment, () to begin with
+:def <name> <expr>   run expr and bind its result to $name
+:load <name> <path>  bind the program in a file to $name. Files of hex digits
+                     and binary files are serialized, anything else is text
+:names               list the bound names
+:help                show this
+:quit                exit
+
+$name in an expression is replaced by the quoted value bound to it";
+
+pub fn subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("repl")
+        .about("Evaluates expressions interactively")
+        .arg(dialect_arg())
+        .arg(max_cost_arg())
+        .arg(strict_arg())
+        .arg(floor_div_arg())
+}
+
+// a session. Everything lives in one allocator, so bound values stay valid
+pub struct Repl {
+    a: IntAllocator,
+    dialect: Dialect,
+    strict: bool,
+    max_cost: Cost,
+    env: i32,
+    names: BTreeMap<String, i32>,
+}
+
+// splits off the first word of s
+fn word(s: &str) -> (&str, &str) {
+    let s = s.trim();
+    match s.find(char::is_whitespace) {
+        Some(i) => (&s[..i], s[i..].trim_start()),
+        None => (s, ""),
+    }
+}
+
+impl Repl {
+    pub fn new(dialect: Dialect, strict: bool, max_cost: Cost) -> Self {
+        let a = IntAllocator::new();
+        let env = a.null();
+        Repl {
+            a,
+            dialect,
+            strict,
+            max_cost,
+            env,
+            names: BTreeMap::new(),
+        }
+    }
+
+    fn text(&self, node: i32) -> String {
+        disassemble_for(&self.dialect, &Node::new(&self.a, node))
+    }
+
+    // the output for a line of input, or None at the end of the session
+    pub fn line(&mut self, line: &str) -> Option<Result<String, String>> {
+        let (command, rest) = word(line);
+        Some(match command {
+            "" => Ok(String::new()),
+            ":quit" | ":q" => return None,
+            ":help" => Ok(HELP.to_string()),
+            ":env" => self.parse(rest).map(|env| {
+                self.env = env;
+                self.text(env)
+            }),
+            ":def" => {
+                let (name, expr) = word(rest);
+                self.eval(expr).map(|(_, value)| self.bind(name, value))
+            }
+            ":load" => {
+                let (name, path) = word(rest);
+                self.load(path).map(|value| self.bind(name, value))
+            }
+            ":names" => Ok(self
+                .names
+                .iter()
+                .map(|(name, value)| format!("${} = {}", name, self.text(*value)))
+                .collect::<Vec<_>>()
+                .join("\n")),
+            c if c.starts_with(':') => Err(format!("unknown command {}, try :help", c)),
+            _ => self
+                .eval(line)
+                .map(|(cost, value)| format!("cost = {}\n{}", cost, self.text(value))),
+        })
+    }
+
+    fn bind(&mut self, name: &str, value: i32) -> String {
+        self.names.insert(name.to_string(), value);
+        format!("${} = {}", name, self.text(value))
+    }
+
+    // assembles text, replacing $names with their values
+    fn parse(&mut self, text: &str) -> Result<i32, String> {
+        if text.is_empty() {
+            return Err("missing expression".to_string());
+        }
+        let node = assemble_for(&self.dialect, &mut self.a, text)?;
+        self.substitute(node)
+    }
+
+    fn substitute(&mut self, node: i32) -> Result<i32, String> {
+        match self.a.sexp(&node) {
+            SExp::Atom(b) => {
+                let name = match self.a.buf(&b).strip_prefix(b"$") {
+                    Some(name) if !name.is_empty() => String::from_utf8_lossy(name).to_string(),
+                    _ => return Ok(node),
+                };
+                let value = *self
+                    .names
+                    .get(&name)
+                    .ok_or_else(|| format!("unbound name ${}", name))?;
+                let q = self
+                    .a
+                    .new_atom(&[self.dialect.quote_kw])
+                    .map_err(|e| e.1.to_string())?;
+                self.a.new_pair(q, value).map_err(|e| e.1.to_string())
+            }
+            SExp::Pair(first, rest) => {
+                let new_first = self.substitute(first)?;
+                let new_rest = self.substitute(rest)?;
+                if new_first == first && new_rest == rest {
+                    Ok(node)
+                } else {
+                    self.a
+                        .new_pair(new_first, new_rest)
+                        .map_err(|e| e.1.to_string())
+                }
+            }
+        }
+    }
+
+    // runs an expression with the environment
+    fn eval(&mut self, text: &str) -> Result<(Cost, i32), String> {
+        let program = self.parse(text)?;
+        let env = self.env;
+        run_program(
+            &mut self.a,
+            &program,
+            &env,
+            self.dialect.quote_kw,
+            self.dialect.apply_kw,
+            self.max_cost,
+            operators(&self.dialect, self.strict),
+            None,
+        )
+        .map(|r| (r.0, r.1))
+        .map_err(|e| format!("FAIL: {} {}", e.1, self.text(e.0)))
+    }
+
+    fn load(&mut self, path: &str) -> Result<i32, String> {
+        let data = read_input(path).map_err(|e| format!("{}: {}", path, e))?;
+        match std::str::from_utf8(&data).map(str::trim) {
+            Ok(text) if !text.is_empty() && !text.bytes().all(|c| c.is_ascii_hexdigit()) => {
+                self.parse(text)
+            }
+            _ => node_from_bytes(&mut self.a, &decode_blob(&data)).map_err(|e| e.to_string()),
+        }
+        .map_err(|e| format!("{}: {}", path, e))
+    }
+}
+
+pub fn repl(m: &ArgMatches<'_>) -> Result<String, String> {
+    let mut repl = Repl::new(dialect(m), m.is_present("strict"), parse_cost(m)?);
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("> ");
+        io::stdout().flush().map_err(|e| e.to_string())?;
+        let line = match lines.next() {
+            Some(line) => line.map_err(|e| e.to_string())?,
+            None => {
+                // leave the shell's prompt on a line of its own
+                println!();
+                break;
+            }
+        };
+        match repl.line(&line) {
+            None => break,
+            Some(Ok(out)) if out.is_empty() => {}
+            Some(Ok(out)) | Some(Err(out)) => println!("{}", out),
+        }
+    }
+    Ok(String::new())
+}
+
+#[test]
+fn test_repl() {
+    use clvm_rs::op_table::{CHIA_DIALECT, DEFAULT_DIALECT};
+
+    let mut r = Repl::new(CHIA_DIALECT, false, Cost::ZERO);
+    let mut line = |l: &str| r.line(l).unwrap();
+    assert_eq!(line("(+ (q . 2) (q . 5))"), Ok("cost = 796\n7".to_string()));
+    assert_eq!(line(""), Ok(String::new()));
+    // shown the way opd shows it, with a keyword at the head of the list
+    assert_eq!(line(":env (3 4)"), Ok("(i 4)".to_string()));
+    assert_eq!(line("(+ 2 5)"), Ok("cost = 856\n7".to_string()));
+    assert_eq!(line(":def add (q + 2 5)"), Ok("$add = (+ 2 5)".to_string()));
+    assert_eq!(line(":def x (q . 10)"), Ok("$x = 10".to_string()));
+    assert_eq!(line(":names"), Ok("$add = (+ 2 5)\n$x = 10".to_string()));
+    assert!(line("(a $add (c (q . 1) (c $x ())))")
+        .unwrap()
+        .ends_with("\n11"));
+    assert_eq!(line("$y"), Err("unbound name $y".to_string()));
+    assert!(line("(x)").unwrap_err().starts_with("FAIL: clvm raise"));
+    assert!(line("(+ 2").is_err());
+    assert!(line(":foo").is_err());
+    assert!(line(":load p /nonexistent").is_err());
+    assert!(r.line(":quit").is_none());
+
+    // the dialect picks the keywords, and the operators they run
+    let mut r = Repl::new(DEFAULT_DIALECT, false, Cost::ZERO);
+    assert_eq!(
+        r.line("(+ (q . 2) (q . 5))").unwrap().unwrap(),
+        "cost = 796\n7"
+    );
+}
@@ -0,0 +1,281 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use clvm_rs::allocator::Allocator;
+use clvm_rs::binutils::disassemble;
+use clvm_rs::cost::Cost;
+use clvm_rs::err_utils::json_string;
+use clvm_rs::int_allocator::IntAllocator;
+use clvm_rs::node::Node;
+use clvm_rs::op_table::{Dialect, HostOpTable, HostOperators, CHIA_DIALECT};
+use clvm_rs::reduction::{EvalErr, Response};
+use clvm_rs::run_program::{run_program, run_program_with_params, OperatorHandler, RunParams};
+use clvm_rs::trace::NdjsonTracer;
+
+use crate::input::{div_rounding, floor_div_arg, parse_arg, symbols, symbols_arg, InputFormat};
+use crate::output::{format_node, json_object, node_fields, OutputFormat, OUTPUT_FORMATS};
+
+pub fn subcommand() -> App<'static, 'static> {
+    program_args(SubCommand::with_name("run").about("Runs a program, like brun"))
+        .arg(
+            Arg::with_name("cost")
+                .short("c")
+                .long("cost")
+                .help("Print the cost (text output only, it's always in the JSON)"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .possible_values(&OUTPUT_FORMATS)
+                .default_value("text"),
+        )
+        .arg(symbols_arg().help("A chialisp .sym file, to name the functions in errors by"))
+        .arg(
+            Arg::with_name("trace")
+                .long("trace")
+                .takes_value(true)
+                .conflicts_with("symbols")
+                .help("Write every operator call here as a line of JSON. - is stdout"),
+        )
+}
+
+// the arguments of subcommands that run a program
+pub fn program_args(app: App<'static, 'static>) -> App<'static, 'static> {
+    app.arg(
+        Arg::with_name("program")
+            .required(true)
+            .help("The program. @path reads it from a file and - from stdin"),
+    )
+    .arg(Arg::with_name("env").help("The environment, read the same way. Defaults to ()"))
+    .arg(
+        Arg::with_name("hex")
+            .short("x")
+            .long("hex")
+            .help("The program and environment are serialized, in hex or binary"),
+    )
+    .arg(
+        Arg::with_name("backrefs")
+            .long("backrefs")
+            .help("Accept back references in serialized input, and use them in hex output"),
+    )
+    .arg(max_cost_arg())
+    .arg(strict_arg())
+    .arg(floor_div_arg())
+}
+
+pub fn max_cost_arg() -> Arg<'static, 'static> {
+    Arg::with_name("max-cost")
+        .short("m")
+        .long("max-cost")
+        .takes_value(true)
+        .default_value("0")
+        .help("Fail once the program costs more than this. 0 means no limit")
+}
+
+pub fn strict_arg() -> Arg<'static, 'static> {
+    Arg::with_name("strict")
+        .long("strict")
+        .help("Fail on unknown operators, as the mempool does")
+}
+
+// in strict mode, every operator the dialect doesn't implement is an error
+#[derive(Clone)]
+struct Strict;
+
+impl HostOperators<IntAllocator> for Strict {
+    fn op(
+        &self,
+        allocator: &mut IntAllocator,
+        op: <IntAllocator as Allocator>::AtomBuf,
+        _args: &i32,
+        _max_cost: Cost,
+    ) -> Option<Response<i32>> {
+        let op = allocator.buf(&op).to_vec();
+        Some(match allocator.new_atom(&op) {
+            Ok(node) => Err(EvalErr::new(node, "unimplemented operator")),
+            Err(e) => Err(e),
+        })
+    }
+}
+
+pub fn operators(dialect: &Dialect, strict: bool) -> Box<dyn OperatorHandler<IntAllocator>> {
+    let table = dialect.operators();
+    if strict {
+        Box::new(HostOpTable {
+            table,
+            host: Strict,
+        })
+    } else {
+        Box::new(table)
+    }
+}
+
+// the program and environment given to program_args()
+pub fn parse_program(a: &mut IntAllocator, m: &ArgMatches<'_>) -> Result<(i32, i32), String> {
+    let format = InputFormat {
+        serialized: m.is_present("hex"),
+        backrefs: m.is_present("backrefs"),
+    };
+    let program = parse_arg(a, m.value_of("program").unwrap(), format)?;
+    let env = match m.value_of("env") {
+        Some(env) => parse_arg(a, env, format)?,
+        None => a.null(),
+    };
+    Ok((program, env))
+}
+
+pub fn parse_cost(m: &ArgMatches<'_>) -> Result<Cost, String> {
+    let v = m.value_of("max-cost").unwrap();
+    v.parse().map_err(|_| format!("invalid max cost: {}", v))
+}
+
+// the output of a successful run, or the error message of a failed one
+pub fn run(m: &ArgMatches<'_>) -> Result<String, String> {
+    let output = OutputFormat::from_name(m.value_of("output").unwrap()).unwrap();
+    let backrefs = m.is_present("backrefs");
+    let mut a = IntAllocator::new();
+    let (program, env) = parse_program(&mut a, m)?;
+    let max_cost = parse_cost(m)?;
+
+    let dialect = div_rounding(CHIA_DIALECT, m);
+    let ops = operators(&dialect, m.is_present("strict"));
+    let r = if let Some(path) = m.value_of("trace") {
+        let out: Box<dyn Write> = if path == "-" {
+            Box::new(io::stdout())
+        } else {
+            Box::new(File::create(path).map_err(|e| format!("{}: {}", path, e))?)
+        };
+        let mut tracer = NdjsonTracer::new(BufWriter::new(out));
+        let params = RunParams::new()
+            .with_max_cost(max_cost)
+            .with_tracer(&mut tracer);
+        let r = run_program_with_params(&mut a, &program, &env, ops, None, params);
+        tracer.finish().map_err(|e| format!("{}: {}", path, e))?;
+        r
+    } else if m.is_present("symbols") {
+        let symbols = symbols(m)?;
+        let params = RunParams::new()
+            .with_max_cost(max_cost)
+            .with_symbols(&symbols);
+        run_program_with_params(&mut a, &program, &env, ops, None, params)
+    } else {
+        run_program(&mut a, &program, &env, 1, 2, max_cost, ops, None)
+    };
+    match r {
+        Ok(r) => {
+            let result = format_node(&a, r.1, output, backrefs)?;
+            Ok(match output {
+                OutputFormat::Json => {
+                    let mut fields = vec![("cost", r.0.to_string())];
+                    fields.extend(node_fields(&a, r.1, backrefs)?);
+                    json_object(&fields)
+                }
+                _ if m.is_present("cost") => format!("cost = {}\n{}", r.0, result),
+                _ => result,
+            })
+        }
+        Err(e) => Err(match output {
+            OutputFormat::Json => json_object(&[
+                ("error", json_string(&e.1.to_string())),
+                ("node", json_string(&disassemble(&Node::new(&a, e.0)))),
+            ]),
+            _ => {
+                // with symbols, the backtrace follows the message
+                let msg = e.1.to_string();
+                let (msg, backtrace) = match msg.find('\n') {
+                    Some(i) => msg.split_at(i),
+                    None => (msg.as_str(), ""),
+                };
+                format!(
+                    "FAIL: {} {}{}",
+                    msg,
+                    disassemble(&Node::new(&a, e.0)),
+                    backtrace
+                )
+            }
+        }),
+    }
+}
+
+#[cfg(test)]
+fn run_args(args: &[&str]) -> Result<String, String> {
+    let m = App::new("test")
+        .subcommand(subcommand())
+        .get_matches_from(std::iter::once("test").chain(args.iter().cloned()));
+    run(m.subcommand_matches("run").unwrap())
+}
+
+#[test]
+fn test_run() {
+    assert_eq!(run_args(&["run", "(+ 2 5)", "(3 4)"]).unwrap(), "7");
+    assert_eq!(
+        run_args(&["run", "-c", "(+ 2 5)", "(3 4)"]).unwrap(),
+        "cost = 856\n7"
+    );
+    assert_eq!(
+        run_args(&["run", "-x", "ff10ff02ff0580", "ff03ff0480", "-o", "hex"]).unwrap(),
+        "07"
+    );
+    assert_eq!(
+        run_args(&["run", "(q . \"foo\")", "-o", "json"]).unwrap(),
+        r#"{"cost":20,"hex":"83666f6f","text":"\"foo\""}"#
+    );
+
+    // unknown operators only fail in strict mode
+    assert_eq!(run_args(&["run", "(0x7f)"]).unwrap(), "()");
+    assert_eq!(
+        run_args(&["run", "--strict", "(0x7f)"]).unwrap_err(),
+        "FAIL: unimplemented operator 127"
+    );
+    assert_eq!(
+        run_args(&["run", "-m", "10", "(+ 2 5)", "(3 4)"]).unwrap_err(),
+        "FAIL: cost exceeded 10"
+    );
+    assert_eq!(
+        run_args(&["run", "(x (q . 1))", "-o", "json"]).unwrap_err(),
+        r#"{"error":"clvm raise","node":"(q)"}"#
+    );
+    assert!(run_args(&["run", "(+ 2"]).is_err());
+
+    // / only rounds -1/2 down with --floor-div
+    assert_eq!(run_args(&["run", "(/ (q . -1) (q . 2))"]).unwrap(), "()");
+    assert_eq!(
+        run_args(&["run", "--floor-div", "(/ (q . -1) (q . 2))"]).unwrap(),
+        "-1"
+    );
+
+    // back references are accepted in serialized input
+    assert_eq!(
+        run_args(&["run", "-x", "--backrefs", "ff01ff83666f6ffe02"]).unwrap(),
+        "(\"foo\" . \"foo\")"
+    );
+
+    // errors name the functions that failed, from a .sym file
+    let mut a = IntAllocator::new();
+    let raise = clvm_rs::binutils::assemble(&mut a, "(x 2)").unwrap();
+    let hash = clvm_rs::tree_hash::tree_hash(&Node::new(&a, raise));
+    let mut table = clvm_rs::symbols::SymbolTable::new();
+    table.insert(hash, "fail");
+    let path = std::env::temp_dir().join("clvm_rs_test_run.sym");
+    let path = path.to_str().unwrap();
+    table.write_sym_file(path).unwrap();
+    let e = run_args(&["run", "-s", path, "(a (q x 2) 1)", "(1000)"]).unwrap_err();
+    assert!(e.starts_with("FAIL: clvm raise in `fail` (depth 2) (1000)\n  #1 `fail`\n  #0 "));
+    assert!(run_args(&["run", "-s", "/nonexistent", "()"]).is_err());
+
+    // a line of trace per operator call
+    let path = std::env::temp_dir().join("clvm_rs_test_run.ndjson");
+    let path = path.to_str().unwrap();
+    assert_eq!(
+        run_args(&["run", "--trace", path, "(+ 2 (* 5 5))", "(3 4)"]).unwrap(),
+        "19"
+    );
+    let trace = std::fs::read_to_string(path).unwrap();
+    let ops: Vec<&str> = trace.lines().map(|l| &l[..9]).collect();
+    assert_eq!(ops, ["{\"op\":\"*\"", "{\"op\":\"+\""]);
+    std::fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,60 @@
+use std::path::Path;
+
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use clvm_rs::spec::{run_spec, Verdict};
+
+pub fn subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("spec")
+        .about("Runs CLVM specification test vectors and prints a pass/fail matrix")
+        .arg(
+            Arg::with_name("dir")
+                .required(true)
+                .help("The vectors: a directory of .txt files, in the clvm_tools test format"),
+        )
+        .arg(
+            Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .help("Also list the cases that were skipped, and why"),
+        )
+}
+
+// the matrix, followed by every failed case. It's an error if any failed
+pub fn spec(m: &ArgMatches<'_>) -> Result<String, String> {
+    let dir = m.value_of("dir").unwrap();
+    let (results, matrix) = run_spec(Path::new(dir)).map_err(|e| format!("{}: {}", dir, e))?;
+    let mut out = matrix.to_string();
+    for r in &results {
+        let show = match r.verdict {
+            Verdict::Pass => false,
+            Verdict::Fail(_) => true,
+            Verdict::Skip(_) => m.is_present("verbose"),
+        };
+        if show {
+            out.push_str(&format!("\n{}", r));
+        }
+    }
+    let out = out.trim_end().to_string();
+    if matrix.failed() > 0 {
+        Err(out)
+    } else {
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+fn spec_args(args: &[&str]) -> Result<String, String> {
+    let m = App::new("test")
+        .subcommand(subcommand())
+        .get_matches_from(std::iter::once("test").chain(args.iter().cloned()));
+    spec(m.subcommand_matches("spec").unwrap())
+}
+
+#[test]
+fn test_spec() {
+    let dir = clvm_rs::spec::spec_dir();
+    let out = spec_args(&["spec", dir.to_str().unwrap()]).unwrap();
+    assert!(out.starts_with("                pass   fail   skip\noperators"));
+    assert!(spec_args(&["spec", "/nonexistent"]).is_err());
+}
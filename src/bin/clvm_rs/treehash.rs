@@ -0,0 +1,69 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use clvm_rs::int_allocator::IntAllocator;
+use clvm_rs::io_utils::{decode_blob, write_output};
+use clvm_rs::node::Node;
+use clvm_rs::serialize::{node_from_bytes_backrefs, tree_hash_from_bytes};
+use clvm_rs::tree_hash::tree_hash;
+
+use crate::input::read_arg;
+
+pub fn subcommand() -> App<'static, 'static> {
+    SubCommand::with_name("treehash")
+        .about("Computes the sha256 tree hash of a serialized program")
+        .arg(
+            Arg::with_name("program")
+                .required(true)
+                .help("The program, in hex or binary. @path reads it from a file and - from stdin"),
+        )
+        .arg(
+            Arg::with_name("backrefs")
+                .long("backrefs")
+                .help("Accept back references"),
+        )
+        .arg(
+            Arg::with_name("raw")
+                .short("r")
+                .long("raw")
+                .help("Write the 32 byte hash to stdout, rather than hex"),
+        )
+}
+
+pub fn treehash(m: &ArgMatches<'_>) -> Result<String, String> {
+    let arg = m.value_of("program").unwrap();
+    let blob = decode_blob(&read_arg(arg)?);
+    // back references can only be resolved by building the node
+    let hash = if m.is_present("backrefs") {
+        let mut a = IntAllocator::new();
+        node_from_bytes_backrefs(&mut a, &blob).map(|node| tree_hash(&Node::new(&a, node)))
+    } else {
+        tree_hash_from_bytes(&blob)
+    }
+    .map_err(|e| format!("{}: {}", arg, e))?;
+
+    if m.is_present("raw") {
+        write_output("-", &hash).map_err(|e| format!("stdout: {}", e))?;
+        Ok(String::new())
+    } else {
+        Ok(hex::encode(hash))
+    }
+}
+
+#[test]
+fn test_treehash() {
+    let hash = |args: &[&str]| {
+        let m = App::new("test")
+            .subcommand(subcommand())
+            .get_matches_from(std::iter::once("test").chain(args.iter().cloned()));
+        treehash(m.subcommand_matches("treehash").unwrap())
+    };
+    // the tree hash of ()
+    let nil = "4bf5122f344554c53bde2ebb8cd2b7e3d1600ad631c385a5d7cce23c7785459a";
+    assert_eq!(hash(&["treehash", "80"]).unwrap(), nil);
+    assert_eq!(
+        hash(&["treehash", "ff83666f6f83666f6f"]).unwrap(),
+        hash(&["treehash", "--backrefs", "ff83666f6ffe02"]).unwrap()
+    );
+    assert!(hash(&["treehash", "ff83666f6ffe02"]).is_err());
+    assert!(hash(&["treehash", "ff80"]).is_err());
+}
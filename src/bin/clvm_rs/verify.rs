@@ -0,0 +1,108 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+
+use clvm_rs::int_allocator::IntAllocator;
+use clvm_rs::io_utils::decode_blob;
+use clvm_rs::serialize::{verify_serialization, VerifyLimits};
+
+use crate::input::read_arg;
+
+pub fn subcommand() -> App<'static, 'static> {
+    let limit = |name: &'static str, help: &'static str| {
+        Arg::with_name(name)
+            .long(name)
+            .takes_value(true)
+            .default_value("0")
+            .help(help)
+    };
+    SubCommand::with_name("verify")
+        .about("Checks that a serialized blob is one node, canonically encoded and within limits")
+        .arg(
+            Arg::with_name("blob")
+                .required(true)
+                .help("The blob, in hex or binary. @path reads it from a file and - from stdin"),
+        )
+        .arg(limit(
+            "max-bytes",
+            "The largest blob allowed. 0 means no limit",
+        ))
+        .arg(limit(
+            "max-atom-len",
+            "The largest atom allowed. 0 means no limit",
+        ))
+        .arg(limit(
+            "max-depth",
+            "How many pairs may enclose a node. 0 means no limit",
+        ))
+        .arg(
+            Arg::with_name("backrefs")
+                .long("backrefs")
+                .help("Allow back references"),
+        )
+}
+
+fn parse_limit(m: &ArgMatches<'_>, name: &str) -> Result<usize, String> {
+    let v = m.value_of(name).unwrap();
+    v.parse().map_err(|_| format!("invalid {}: {}", name, v))
+}
+
+// a summary of the blob, or where it went wrong along with the bytes there
+pub fn verify(m: &ArgMatches<'_>) -> Result<String, String> {
+    let limits = VerifyLimits {
+        max_bytes: parse_limit(m, "max-bytes")?,
+        max_atom_len: parse_limit(m, "max-atom-len")?,
+        max_depth: parse_limit(m, "max-depth")?,
+        allow_backrefs: m.is_present("backrefs"),
+    };
+    let blob = decode_blob(&read_arg(m.value_of("blob").unwrap())?);
+    let mut a = IntAllocator::new();
+    match verify_serialization(&mut a, &blob, &limits) {
+        Ok(r) => Ok(format!(
+            "OK: {} bytes, {} atoms, {} pairs, {} back references, depth {}",
+            r.bytes, r.atoms, r.pairs, r.backrefs, r.max_depth
+        )),
+        Err(e) => {
+            let end = blob.len().min(e.offset + 8);
+            let context = blob.get(e.offset..end).unwrap_or(&[]);
+            Err(format!(
+                "FAIL at byte {}: {} ({})",
+                e.offset,
+                e.violation,
+                hex::encode(context)
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+fn verify_args(args: &[&str]) -> Result<String, String> {
+    let m = App::new("test")
+        .subcommand(subcommand())
+        .get_matches_from(std::iter::once("test").chain(args.iter().cloned()));
+    verify(m.subcommand_matches("verify").unwrap())
+}
+
+#[test]
+fn test_verify() {
+    assert_eq!(
+        verify_args(&["verify", "ff83666f6f80"]).unwrap(),
+        "OK: 6 bytes, 2 atoms, 1 pairs, 0 back references, depth 1"
+    );
+    assert_eq!(
+        verify_args(&["verify", "ff8105ff8080"]).unwrap_err(),
+        "FAIL at byte 1: non-canonical length prefix (8105ff8080)"
+    );
+    assert_eq!(
+        verify_args(&["verify", "ff83666f6f"]).unwrap_err(),
+        "FAIL at byte 5: unexpected end of input ()"
+    );
+    assert_eq!(
+        verify_args(&["verify", "--max-atom-len", "2", "ff83666f6f80"]).unwrap_err(),
+        "FAIL at byte 1: atom too large (83666f6f80)"
+    );
+    assert_eq!(
+        verify_args(&["verify", "ff83666f6ffe02"]).unwrap_err(),
+        "FAIL at byte 5: back reference not allowed (fe02)"
+    );
+    assert!(verify_args(&["verify", "--backrefs", "ff83666f6ffe02"]).is_ok());
+    assert!(verify_args(&["verify", "--max-depth", "x", "80"]).is_err());
+}
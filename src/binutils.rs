@@ -0,0 +1,568 @@
+// Converts between nodes and clvm's text form, as used by `opc`, `opd` and
+// `brun`. Keywords depend on the dialect: in chia's, q is 1, a is 2, + is 16
+// and so on.
+//
+// When assembling, integers (decimal or 0x hex), "quoted strings" and keywords
+// become atoms, and any other symbol becomes an atom of its utf-8 bytes. When
+// disassembling, the first atom of a list is shown as a keyword if it is one.
+// Other atoms longer than two bytes are shown as strings if they're printable,
+// atoms of up to four bytes in their shortest form as integers, and anything
+// else as hex
+//
+// A Keywords table can be used instead of a dialect's, to give extension
+// operators (of any length) names. Printing with one shows atoms at the head
+// of a list that aren't keywords in hex, so opcodes keep their exact bytes
+
+use crate::allocator::{Allocator, SExp};
+use crate::node::Node;
+use crate::number::{atom_bytes_to_decimal, decimal_to_atom_bytes, is_canonical_int};
+use crate::op_table::{Dialect, CHIA_DIALECT};
+
+// the keyword of each native operator
+const OPERATOR_KEYWORDS: [(&str, &str); 30] = [
+    ("op_if", "i"),
+    ("op_cons", "c"),
+    ("op_first", "f"),
+    ("op_rest", "r"),
+    ("op_listp", "l"),
+    ("op_raise", "x"),
+    ("op_eq", "="),
+    ("op_gr_bytes", ">s"),
+    ("op_sha256", "sha256"),
+    ("op_substr", "substr"),
+    ("op_strlen", "strlen"),
+    ("op_concat", "concat"),
+    ("op_add", "+"),
+    ("op_subtract", "-"),
+    ("op_multiply", "*"),
+    ("op_div", "/"),
+    ("op_divmod", "divmod"),
+    ("op_gr", ">"),
+    ("op_ash", "ash"),
+    ("op_lsh", "lsh"),
+    ("op_logand", "logand"),
+    ("op_logior", "logior"),
+    ("op_logxor", "logxor"),
+    ("op_lognot", "lognot"),
+    ("op_point_add", "point_add"),
+    ("op_pubkey_for_exp", "pubkey_for_exp"),
+    ("op_not", "not"),
+    ("op_any", "any"),
+    ("op_all", "all"),
+    ("op_softfork", "softfork"),
+];
+
+pub fn keyword_for_opcode(dialect: &Dialect, opcode: &[u8]) -> Option<&'static str> {
+    let opcode = match opcode {
+        [b] => *b,
+        _ => return None,
+    };
+    if opcode == dialect.quote_kw {
+        return Some("q");
+    }
+    if opcode == dialect.apply_kw {
+        return Some("a");
+    }
+    let (_, name) = dialect.opcodes.iter().find(|(o, _)| *o == opcode)?;
+    OPERATOR_KEYWORDS
+        .iter()
+        .find(|(op, _)| op == name)
+        .map(|(_, kw)| *kw)
+}
+
+pub fn opcode_for_keyword(dialect: &Dialect, kw: &str) -> Option<u8> {
+    match kw {
+        "q" => return Some(dialect.quote_kw),
+        "a" => return Some(dialect.apply_kw),
+        _ => {}
+    }
+    let (name, _) = OPERATOR_KEYWORDS.iter().find(|(_, k)| *k == kw)?;
+    dialect
+        .opcodes
+        .iter()
+        .find(|(_, op)| op == name)
+        .map(|(opcode, _)| *opcode)
+}
+
+// operator keywords, by opcode
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keywords {
+    entries: Vec<(Vec<u8>, String)>,
+    // show atoms at the head of a list that aren't keywords in hex
+    pub hex_unknown_ops: bool,
+}
+
+impl Default for Keywords {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Keywords {
+    // an empty table
+    pub fn new() -> Self {
+        Keywords {
+            entries: Vec::new(),
+            hex_unknown_ops: true,
+        }
+    }
+
+    // the keywords of a dialect. Unknown opcodes print as they always have,
+    // as integers
+    pub fn for_dialect(dialect: &Dialect) -> Self {
+        let mut ret = Keywords {
+            entries: Vec::new(),
+            hex_unknown_ops: false,
+        };
+        ret.insert(&[dialect.quote_kw], "q");
+        ret.insert(&[dialect.apply_kw], "a");
+        for (opcode, _) in dialect.opcodes.iter() {
+            if let Some(kw) = keyword_for_opcode(dialect, &[*opcode]) {
+                ret.insert(&[*opcode], kw);
+            }
+        }
+        ret
+    }
+
+    // adds a keyword, replacing any other entry for the opcode or keyword
+    pub fn insert(&mut self, opcode: &[u8], keyword: &str) {
+        self.entries
+            .retain(|(op, kw)| op.as_slice() != opcode && kw != keyword);
+        self.entries.push((opcode.to_vec(), keyword.to_string()));
+    }
+
+    pub fn with(mut self, opcode: &[u8], keyword: &str) -> Self {
+        self.insert(opcode, keyword);
+        self
+    }
+
+    // adds the keywords in a text table, a line each: an opcode (an integer,
+    // or 0x and hex) and its keyword. # starts a comment
+    pub fn extend_from_text(&mut self, text: &str) -> Result<(), String> {
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let opcode = match fields.as_slice() {
+                [op, _] => hex_atom(op).or_else(|| decimal_to_atom_bytes(op)),
+                _ => None,
+            };
+            match opcode {
+                Some(opcode) if !opcode.is_empty() => self.insert(&opcode, fields[1]),
+                _ => return Err(format!("line {}: expected an opcode and a keyword", i + 1)),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn keyword(&self, opcode: &[u8]) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(op, _)| op.as_slice() == opcode)
+            .map(|(_, kw)| kw.as_str())
+    }
+
+    pub fn opcode(&self, keyword: &str) -> Option<&[u8]> {
+        self.entries
+            .iter()
+            .find(|(_, kw)| kw == keyword)
+            .map(|(op, _)| op.as_slice())
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) enum Token<'a> {
+    Open,
+    Close,
+    Dot,
+    Quoted(&'a str),
+    Symbol(&'a str),
+}
+
+pub(crate) struct Tokenizer<'a> {
+    pub text: &'a str,
+    pub pos: usize,
+}
+
+impl<'a> Tokenizer<'a> {
+    pub fn next_token(&mut self) -> Result<Option<(usize, Token<'a>)>, String> {
+        let bytes = self.text.as_bytes();
+        // skip whitespace and ; comments
+        while self.pos < bytes.len() {
+            if bytes[self.pos].is_ascii_whitespace() {
+                self.pos += 1;
+            } else if bytes[self.pos] == b';' {
+                while self.pos < bytes.len() && bytes[self.pos] != b'\n' {
+                    self.pos += 1;
+                }
+            } else {
+                break;
+            }
+        }
+        let start = self.pos;
+        let c = match bytes.get(start) {
+            Some(c) => *c,
+            None => return Ok(None),
+        };
+        let token = match c {
+            b'(' => {
+                self.pos += 1;
+                Token::Open
+            }
+            b')' => {
+                self.pos += 1;
+                Token::Close
+            }
+            b'"' | b'\'' => {
+                let end = match self.text[start + 1..].find(c as char) {
+                    Some(len) => start + 1 + len,
+                    None => return Err(format!("unterminated string at {}", start)),
+                };
+                self.pos = end + 1;
+                Token::Quoted(&self.text[start + 1..end])
+            }
+            _ => {
+                while self.pos < bytes.len()
+                    && !bytes[self.pos].is_ascii_whitespace()
+                    && !b"();".contains(&bytes[self.pos])
+                {
+                    self.pos += 1;
+                }
+                match &self.text[start..self.pos] {
+                    "." => Token::Dot,
+                    s => Token::Symbol(s),
+                }
+            }
+        };
+        Ok(Some((start, token)))
+    }
+}
+
+// the atom a 0x symbol stands for. Hex atoms keep their leading zeros. An
+// odd number of digits is padded with one
+pub(crate) fn hex_atom(s: &str) -> Option<Vec<u8>> {
+    if s.len() > 2 && (s.starts_with("0x") || s.starts_with("0X")) {
+        let digits = &s[2..];
+        let padded = if digits.len() % 2 == 1 {
+            format!("0{}", digits)
+        } else {
+            digits.to_string()
+        };
+        hex::decode(padded).ok()
+    } else {
+        None
+    }
+}
+
+fn atom_for_symbol(keywords: &Keywords, s: &str) -> Vec<u8> {
+    if let Some(v) = hex_atom(s) {
+        return v;
+    }
+    if let Some(v) = decimal_to_atom_bytes(s) {
+        return v;
+    }
+    if let Some(opcode) = keywords.opcode(s) {
+        return opcode.to_vec();
+    }
+    s.as_bytes().to_vec()
+}
+
+enum AssembleOp {
+    // parse an item
+    Item,
+    // parse the rest of a list, after its items so far
+    Rest,
+    // the ) after the item following a .
+    Close,
+    // make a pair of the last two values
+    Cons,
+}
+
+// parses clvm's text form into a node, with chia's keywords
+pub fn assemble<T: Allocator>(allocator: &mut T, text: &str) -> Result<T::Ptr, String> {
+    assemble_for(&CHIA_DIALECT, allocator, text)
+}
+
+pub fn assemble_for<T: Allocator>(
+    dialect: &Dialect,
+    allocator: &mut T,
+    text: &str,
+) -> Result<T::Ptr, String> {
+    assemble_with(&Keywords::for_dialect(dialect), allocator, text)
+}
+
+pub fn assemble_with<T: Allocator>(
+    keywords: &Keywords,
+    allocator: &mut T,
+    text: &str,
+) -> Result<T::Ptr, String> {
+    let mut tokens = Tokenizer { text, pos: 0 };
+    let mut values: Vec<T::Ptr> = Vec::new();
+    let mut ops = vec![AssembleOp::Item];
+
+    while let Some(op) = ops.pop() {
+        match op {
+            AssembleOp::Item => {
+                let atom = match tokens.next_token()? {
+                    None => return Err("unexpected end of input".into()),
+                    Some((_, Token::Open)) => {
+                        ops.push(AssembleOp::Rest);
+                        continue;
+                    }
+                    Some((pos, Token::Close)) => return Err(format!("unexpected ) at {}", pos)),
+                    Some((pos, Token::Dot)) => return Err(format!("unexpected . at {}", pos)),
+                    Some((_, Token::Quoted(s))) => s.as_bytes().to_vec(),
+                    Some((_, Token::Symbol(s))) => atom_for_symbol(keywords, s),
+                };
+                values.push(allocator.new_atom(&atom).map_err(|e| e.1)?);
+            }
+            AssembleOp::Rest => {
+                let saved = tokens.pos;
+                match tokens.next_token()? {
+                    None => return Err("missing )".into()),
+                    Some((_, Token::Close)) => values.push(allocator.null()),
+                    Some((_, Token::Dot)) => {
+                        ops.push(AssembleOp::Close);
+                        ops.push(AssembleOp::Item);
+                    }
+                    Some(_) => {
+                        tokens.pos = saved;
+                        ops.push(AssembleOp::Cons);
+                        ops.push(AssembleOp::Rest);
+                        ops.push(AssembleOp::Item);
+                    }
+                }
+            }
+            AssembleOp::Close => match tokens.next_token()? {
+                Some((_, Token::Close)) => {}
+                Some((pos, _)) => return Err(format!("expected ) at {}", pos)),
+                None => return Err("missing )".into()),
+            },
+            AssembleOp::Cons => {
+                let rest = values.pop().unwrap();
+                let first = values.pop().unwrap();
+                values.push(allocator.new_pair(first, rest).map_err(|e| e.1)?);
+            }
+        }
+    }
+    if let Some((pos, _)) = tokens.next_token()? {
+        return Err(format!("unexpected text at {}", pos));
+    }
+    Ok(values.pop().unwrap())
+}
+
+pub(crate) fn is_printable(atom: &[u8]) -> bool {
+    atom.iter()
+        .all(|b| (0x20..0x7f).contains(b) && *b != b'"' && *b != b'\'')
+}
+
+fn text_for_atom(keywords: &Keywords, atom: &[u8], allow_keyword: bool) -> String {
+    if atom.is_empty() {
+        return "()".to_string();
+    }
+    if allow_keyword {
+        if let Some(kw) = keywords.keyword(atom) {
+            return kw.to_string();
+        }
+        if keywords.hex_unknown_ops {
+            return format!("0x{}", hex::encode(atom));
+        }
+    }
+    if atom.len() > 2 && is_printable(atom) {
+        return format!("\"{}\"", String::from_utf8_lossy(atom));
+    }
+    if atom.len() <= 4 && is_canonical_int(atom) {
+        return atom_bytes_to_decimal(atom);
+    }
+    format!("0x{}", hex::encode(atom))
+}
+
+enum DisassembleOp<P> {
+    // a node, and whether it's the first item of a list
+    Node(P, bool),
+    // the rest of a list, after its first item
+    Rest(P),
+}
+
+// the text form of a node, with chia's keywords
+pub fn disassemble<T: Allocator>(node: &Node<T>) -> String {
+    disassemble_for(&CHIA_DIALECT, node)
+}
+
+pub fn disassemble_for<T: Allocator>(dialect: &Dialect, node: &Node<T>) -> String {
+    disassemble_with(&Keywords::for_dialect(dialect), node)
+}
+
+pub fn disassemble_with<T: Allocator>(keywords: &Keywords, node: &Node<T>) -> String {
+    let a = node.allocator;
+    let mut out = String::new();
+    let mut ops = vec![DisassembleOp::Node(node.node.clone(), false)];
+    while let Some(op) = ops.pop() {
+        match op {
+            DisassembleOp::Node(n, is_head) => match a.sexp(&n) {
+                SExp::Atom(atom) => out.push_str(&text_for_atom(keywords, a.buf(&atom), is_head)),
+                SExp::Pair(first, rest) => {
+                    out.push('(');
+                    ops.push(DisassembleOp::Rest(rest));
+                    ops.push(DisassembleOp::Node(first, true));
+                }
+            },
+            DisassembleOp::Rest(n) => match a.sexp(&n) {
+                SExp::Atom(atom) => {
+                    let atom = a.buf(&atom);
+                    if !atom.is_empty() {
+                        out.push_str(" . ");
+                        out.push_str(&text_for_atom(keywords, atom, false));
+                    }
+                    out.push(')');
+                }
+                SExp::Pair(first, rest) => {
+                    out.push(' ');
+                    ops.push(DisassembleOp::Rest(rest));
+                    ops.push(DisassembleOp::Node(first, false));
+                }
+            },
+        }
+    }
+    out
+}
+
+#[test]
+fn test_assemble() {
+    use crate::int_allocator::IntAllocator;
+    use crate::serialize::node_to_bytes;
+
+    let mut a = IntAllocator::new();
+    let mut asm = |text: &str| -> Result<String, String> {
+        let node = assemble(&mut a, text)?;
+        Ok(hex::encode(node_to_bytes(&Node::new(&a, node)).unwrap()))
+    };
+    assert_eq!(asm("()").unwrap(), "80");
+    assert_eq!(asm("0").unwrap(), "80");
+    assert_eq!(asm("1").unwrap(), "01");
+    assert_eq!(asm("-1").unwrap(), "81ff");
+    assert_eq!(asm("128").unwrap(), "820080");
+    assert_eq!(asm("0x0001").unwrap(), "820001");
+    assert_eq!(asm("0xabc").unwrap(), "820abc");
+    assert_eq!(asm("\"foo\"").unwrap(), "83666f6f");
+    assert_eq!(asm("'foo bar'").unwrap(), "87666f6f20626172");
+    assert_eq!(asm("foo").unwrap(), "83666f6f");
+    assert_eq!(asm("(+ (q . 2) 5)").unwrap(), "ff10ffff0102ff0580");
+    assert_eq!(asm("(a . b)").unwrap(), "ff0262");
+    assert_eq!(asm(" ; a comment\n(1 ()) ").unwrap(), "ff01ff8080");
+
+    assert!(asm("(1 2").is_err());
+    assert!(asm("(1 . 2 3)").is_err());
+    assert!(asm("(1 .)").is_err());
+    assert!(asm("1 2").is_err());
+    assert!(asm(")").is_err());
+    assert!(asm("\"foo").is_err());
+    assert!(asm("").is_err());
+}
+
+#[test]
+fn test_disassemble() {
+    use crate::int_allocator::IntAllocator;
+    use crate::serialize::node_from_bytes;
+
+    let mut a = IntAllocator::new();
+    let mut disasm = |blob: &str| {
+        let node = node_from_bytes(&mut a, &hex::decode(blob).unwrap()).unwrap();
+        disassemble(&Node::new(&a, node))
+    };
+    assert_eq!(disasm("80"), "()");
+    assert_eq!(disasm("ff10ffff0102ff0580"), "(+ (q . 2) 5)");
+    assert_eq!(disasm("ff0180"), "(q)");
+    assert_eq!(disasm("ff80ff0180"), "(() 1)");
+    assert_eq!(disasm("81ff"), "-1");
+    assert_eq!(disasm("820080"), "128");
+    assert_eq!(disasm("820001"), "0x0001");
+    assert_eq!(disasm("83666f6f"), "\"foo\"");
+    assert_eq!(disasm("8400000001"), "0x00000001");
+    assert_eq!(disasm("8501020304ff"), "0x01020304ff");
+    // keywords only at the head of a list
+    assert_eq!(disasm("ff10ff10ff1080"), "(+ 16 16)");
+    assert_eq!(disasm("ffff1080ff1080"), "((+) 16)");
+
+    // and back again
+    for text in &["(+ (q . 2) 5)", "(a (q 2 \"foo\" 0x01020304ff) (c -1 ()))"] {
+        let node = assemble(&mut a, text).unwrap();
+        assert_eq!(disassemble(&Node::new(&a, node)), *text);
+    }
+}
+
+#[test]
+fn test_dialects() {
+    use crate::int_allocator::IntAllocator;
+    use crate::op_table::DEFAULT_DIALECT;
+    use crate::serialize::node_to_bytes;
+
+    assert_eq!(keyword_for_opcode(&CHIA_DIALECT, &[16]), Some("+"));
+    assert_eq!(keyword_for_opcode(&DEFAULT_DIALECT, &[12]), Some("+"));
+    assert_eq!(keyword_for_opcode(&CHIA_DIALECT, &[15]), None);
+    assert_eq!(keyword_for_opcode(&CHIA_DIALECT, &[0, 16]), None);
+    assert_eq!(opcode_for_keyword(&CHIA_DIALECT, "q"), Some(1));
+    assert_eq!(opcode_for_keyword(&DEFAULT_DIALECT, "sha256"), Some(11));
+    assert_eq!(opcode_for_keyword(&CHIA_DIALECT, "foo"), None);
+
+    // every chia operator has a keyword
+    for (opcode, _) in CHIA_DIALECT.opcodes.iter() {
+        assert!(keyword_for_opcode(&CHIA_DIALECT, &[*opcode]).is_some());
+    }
+
+    let mut a = IntAllocator::new();
+    let node = assemble_for(&DEFAULT_DIALECT, &mut a, "(+ (q . 2) 5)").unwrap();
+    let blob = node_to_bytes(&Node::new(&a, node)).unwrap();
+    assert_eq!(hex::encode(blob), "ff0cffff0102ff0580");
+    let node = Node::new(&a, node);
+    assert_eq!(disassemble_for(&DEFAULT_DIALECT, &node), "(+ (q . 2) 5)");
+    assert_eq!(disassemble(&node), "(substr (q . 2) 5)");
+}
+
+#[test]
+fn test_keywords() {
+    use crate::int_allocator::IntAllocator;
+    use crate::serialize::node_to_bytes;
+
+    // chia's keywords, plus two extension operators, one of them two bytes
+    let mut keywords = Keywords::for_dialect(&CHIA_DIALECT)
+        .with(&[0x3b], "bls_verify")
+        .with(&[0x3c, 0x2c], "secp_verify");
+    keywords.hex_unknown_ops = true;
+    assert_eq!(keywords.keyword(&[16]), Some("+"));
+    assert_eq!(keywords.opcode("secp_verify"), Some(&[0x3c, 0x2c][..]));
+
+    let mut a = IntAllocator::new();
+    let text = "(bls_verify (secp_verify 1 2) (0x7f) (+ 59))";
+    let node = assemble_with(&keywords, &mut a, text).unwrap();
+    let blob = node_to_bytes(&Node::new(&a, node)).unwrap();
+    assert_eq!(
+        hex::encode(blob),
+        "ff3bffff823c2cff01ff0280ffff7f80ffff10ff3b8080"
+    );
+    assert_eq!(disassemble_with(&keywords, &Node::new(&a, node)), text);
+    // without them, the operators are just numbers
+    assert_eq!(
+        disassemble(&Node::new(&a, node)),
+        "(59 (15404 1 2) (127) (+ 59))"
+    );
+
+    // a keyword can be moved to another opcode, and the old one forgotten
+    let keywords = keywords.with(&[0x3d], "bls_verify");
+    assert_eq!(keywords.keyword(&[0x3b]), None);
+    assert_eq!(keywords.opcode("bls_verify"), Some(&[0x3d][..]));
+
+    let mut keywords = Keywords::new();
+    keywords
+        .extend_from_text("# extensions\n0x3b bls_verify\n\n60 foo  # sixty\n")
+        .unwrap();
+    assert_eq!(keywords.keyword(&[0x3b]), Some("bls_verify"));
+    assert_eq!(keywords.keyword(&[60]), Some("foo"));
+    assert_eq!(
+        Keywords::new().extend_from_text("0x3b").unwrap_err(),
+        "line 1: expected an opcode and a keyword"
+    );
+    assert!(Keywords::new().extend_from_text("() foo").is_err());
+}
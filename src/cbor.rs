@@ -0,0 +1,250 @@
+// Trees as CBOR (RFC 8949), for embedding them in protocols that already
+// speak it. An atom is a byte string and a pair is an array of two items.
+// Nothing else is a tree, so decoding rejects every other kind of item. Only
+// the deterministic encoding is accepted: lengths are as short as possible
+// and never indefinite, which makes the encoding of a tree unique
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::allocator::{Allocator, SExp};
+use crate::node::Node;
+use crate::reduction::EvalErr;
+
+const BYTE_STRING: u8 = 0x40;
+const ARRAY: u8 = 0x80;
+const MAJOR_TYPE_MASK: u8 = 0xe0;
+
+#[derive(Debug, PartialEq)]
+pub enum CborError {
+    // the input ended in the middle of an item
+    UnexpectedEof,
+    // an item that isn't a byte string or an array of two (the initial byte
+    // is given)
+    UnexpectedItem(u8),
+    // a length that isn't in its shortest form, or is indefinite
+    NonCanonical,
+    // there's more input after the tree
+    TrailingBytes,
+    // the allocator refused to create a node
+    LimitExceeded(String),
+}
+
+impl fmt::Display for CborError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedEof => write!(f, "unexpected end of input"),
+            Self::UnexpectedItem(b) => write!(f, "unexpected cbor item 0x{:02x}", b),
+            Self::NonCanonical => write!(f, "non-canonical length"),
+            Self::TrailingBytes => write!(f, "trailing bytes"),
+            Self::LimitExceeded(msg) => write!(f, "limit exceeded: {}", msg),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for CborError {}
+
+impl<T> From<EvalErr<T>> for CborError {
+    fn from(v: EvalErr<T>) -> Self {
+        Self::LimitExceeded(v.1.into())
+    }
+}
+
+// the initial byte(s) of an item: its major type and a length, in the
+// shortest form
+fn write_head(out: &mut Vec<u8>, major: u8, len: u64) {
+    if len < 24 {
+        out.push(major | len as u8);
+    } else if len <= 0xff {
+        out.extend_from_slice(&[major | 24, len as u8]);
+    } else if len <= 0xffff {
+        out.push(major | 25);
+        out.extend_from_slice(&(len as u16).to_be_bytes());
+    } else if len <= 0xffff_ffff {
+        out.push(major | 26);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    } else {
+        out.push(major | 27);
+        out.extend_from_slice(&len.to_be_bytes());
+    }
+}
+
+pub fn node_to_cbor<T: Allocator>(node: &Node<T>) -> Vec<u8> {
+    let mut out = Vec::new();
+    let a = node.allocator;
+    let mut values: Vec<T::Ptr> = vec![node.node.clone()];
+    while let Some(v) = values.pop() {
+        match a.sexp(&v) {
+            SExp::Atom(atom) => {
+                let buf = a.buf(&atom);
+                write_head(&mut out, BYTE_STRING, buf.len() as u64);
+                out.extend_from_slice(buf);
+            }
+            SExp::Pair(left, right) => {
+                write_head(&mut out, ARRAY, 2);
+                values.push(right);
+                values.push(left);
+            }
+        }
+    }
+    out
+}
+
+// the major type and length of the item at *pos
+fn read_head(b: &[u8], pos: &mut usize) -> Result<(u8, u64), CborError> {
+    let initial = *b.get(*pos).ok_or(CborError::UnexpectedEof)?;
+    *pos += 1;
+    let size = match initial & 0x1f {
+        n if n < 24 => return Ok((initial & MAJOR_TYPE_MASK, n as u64)),
+        24 => 1,
+        25 => 2,
+        26 => 4,
+        27 => 8,
+        31 => return Err(CborError::NonCanonical),
+        _ => return Err(CborError::UnexpectedItem(initial)),
+    };
+    if b.len() - *pos < size {
+        return Err(CborError::UnexpectedEof);
+    }
+    let mut len: u64 = 0;
+    for byte in &b[*pos..*pos + size] {
+        len = len << 8 | *byte as u64;
+    }
+    *pos += size;
+    // a shorter head could have held this length
+    let min = if size == 1 { 24 } else { 1 << (size * 4) };
+    if len < min {
+        return Err(CborError::NonCanonical);
+    }
+    Ok((initial & MAJOR_TYPE_MASK, len))
+}
+
+enum ParseOp {
+    Item,
+    Cons,
+}
+
+pub fn node_from_cbor<T: Allocator>(allocator: &mut T, b: &[u8]) -> Result<T::Ptr, CborError> {
+    let mut pos = 0;
+    let mut values: Vec<T::Ptr> = Vec::new();
+    let mut ops = vec![ParseOp::Item];
+    while let Some(op) = ops.pop() {
+        match op {
+            ParseOp::Item => {
+                let initial = b.get(pos).cloned().unwrap_or(0);
+                match read_head(b, &mut pos)? {
+                    (BYTE_STRING, len) => {
+                        if ((b.len() - pos) as u64) < len {
+                            return Err(CborError::UnexpectedEof);
+                        }
+                        let end = pos + len as usize;
+                        values.push(allocator.new_atom(&b[pos..end])?);
+                        pos = end;
+                    }
+                    (ARRAY, 2) => {
+                        ops.push(ParseOp::Cons);
+                        ops.push(ParseOp::Item);
+                        ops.push(ParseOp::Item);
+                    }
+                    _ => return Err(CborError::UnexpectedItem(initial)),
+                }
+            }
+            ParseOp::Cons => {
+                let right = values.pop().unwrap();
+                let left = values.pop().unwrap();
+                values.push(allocator.new_pair(left, right)?);
+            }
+        }
+    }
+    if pos != b.len() {
+        return Err(CborError::TrailingBytes);
+    }
+    Ok(values.pop().unwrap())
+}
+
+#[cfg(test)]
+use crate::int_allocator::IntAllocator;
+
+#[cfg(test)]
+use crate::serialize::{node_from_bytes, node_to_bytes};
+
+#[cfg(test)]
+fn cbor_roundtrip(a: &mut IntAllocator, node: i32) -> Vec<u8> {
+    let cbor = node_to_cbor(&Node::new(a, node));
+    let decoded = node_from_cbor(a, &cbor).unwrap();
+    assert_eq!(
+        node_to_bytes(&Node::new(a, decoded)).unwrap(),
+        node_to_bytes(&Node::new(a, node)).unwrap()
+    );
+    cbor
+}
+
+#[test]
+fn test_node_to_cbor() {
+    let mut a = IntAllocator::new();
+    let mut cbor = |serialized: &[u8]| {
+        let node = node_from_bytes(&mut a, serialized).unwrap();
+        cbor_roundtrip(&mut a, node)
+    };
+    // ()
+    assert_eq!(cbor(&[0x80]), [0x40]);
+    // 1
+    assert_eq!(cbor(&[0x01]), [0x41, 0x01]);
+    // (1 . 2)
+    assert_eq!(cbor(&[0xff, 0x01, 0x02]), [0x82, 0x41, 0x01, 0x41, 0x02]);
+    // (1 2)
+    assert_eq!(
+        cbor(&[0xff, 0x01, 0xff, 0x02, 0x80]),
+        [0x82, 0x41, 0x01, 0x82, 0x41, 0x02, 0x40]
+    );
+
+    // atoms at the boundaries of each length encoding
+    for (len, head) in &[
+        (23_usize, vec![0x57]),
+        (24, vec![0x58, 24]),
+        (255, vec![0x58, 0xff]),
+        (256, vec![0x59, 0x01, 0x00]),
+        (65535, vec![0x59, 0xff, 0xff]),
+        (65536, vec![0x5a, 0x00, 0x01, 0x00, 0x00]),
+    ] {
+        let node = a.new_atom(&vec![0x55; *len]).unwrap();
+        let cbor = cbor_roundtrip(&mut a, node);
+        assert_eq!(&cbor[..head.len()], &head[..]);
+        assert_eq!(cbor.len(), head.len() + len);
+    }
+}
+
+#[test]
+fn test_node_from_cbor_errors() {
+    let mut a = IntAllocator::new();
+    let mut err = |b: &[u8]| node_from_cbor(&mut a, b).unwrap_err();
+
+    assert_eq!(err(&[]), CborError::UnexpectedEof);
+    assert_eq!(err(&[0x82, 0x40]), CborError::UnexpectedEof);
+    assert_eq!(err(&[0x43, 0x01, 0x02]), CborError::UnexpectedEof);
+    assert_eq!(err(&[0x59, 0x01]), CborError::UnexpectedEof);
+    assert_eq!(err(&[0x40, 0x40]), CborError::TrailingBytes);
+
+    // an integer, a text string, arrays of other lengths, a map and null
+    assert_eq!(err(&[0x01]), CborError::UnexpectedItem(0x01));
+    assert_eq!(err(&[0x61, 0x61]), CborError::UnexpectedItem(0x61));
+    assert_eq!(err(&[0x81, 0x40]), CborError::UnexpectedItem(0x81));
+    assert_eq!(
+        err(&[0x83, 0x40, 0x40, 0x40]),
+        CborError::UnexpectedItem(0x83)
+    );
+    assert_eq!(err(&[0xa0]), CborError::UnexpectedItem(0xa0));
+    assert_eq!(err(&[0x82, 0x40, 0xf6]), CborError::UnexpectedItem(0xf6));
+    // a reserved length encoding
+    assert_eq!(err(&[0x5c]), CborError::UnexpectedItem(0x5c));
+
+    // lengths that fit in a shorter head, and indefinite lengths
+    assert_eq!(err(&[0x58, 0x01, 0x01]), CborError::NonCanonical);
+    assert_eq!(err(&[0x59, 0x00, 0xff]), CborError::NonCanonical);
+    assert_eq!(err(&[0x98, 0x02, 0x40, 0x40]), CborError::NonCanonical);
+    assert_eq!(err(&[0x5f, 0x41, 0x01, 0xff]), CborError::NonCanonical);
+    assert_eq!(err(&[0x9f, 0x40, 0x40, 0xff]), CborError::NonCanonical);
+}
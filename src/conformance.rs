@@ -0,0 +1,284 @@
+// Differential testing of the evaluator against another implementation, such
+// as the python clvm. Generated programs are run here and through a callback
+// into the reference, and any difference in result, cost or the kind of
+// failure is reported. Error messages aren't compared, implementations word
+// them differently. Like the reference operators, this is only compiled with
+// the "testing" feature
+use std::fmt;
+
+use crate::allocator::Allocator;
+use crate::cost::Cost;
+use crate::int_allocator::IntAllocator;
+use crate::node::Node;
+use crate::op_table::{op_table_for, CHIA_OPCODES};
+use crate::reduction::{ErrKind, EvalErr};
+use crate::reference::Rng;
+use crate::run_program::run_program;
+use crate::serialize::{node_from_bytes, node_to_bytes};
+
+// how a run failed, as far as implementations are expected to agree. The
+// classes are the same as the error codes of the C API
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Failure {
+    // the program or environment couldn't be deserialized
+    Serialization,
+    CostExceeded,
+    // the program raised with x
+    Raise,
+    // an allocator limit, or the host's memory, ran out
+    Limit,
+    // any other error
+    Eval,
+}
+
+impl Failure {
+    pub fn classify<P>(e: &EvalErr<P>) -> Self {
+        match e.2 {
+            ErrKind::LimitExceeded | ErrKind::OutOfMemory => Failure::Limit,
+            ErrKind::Eval if e.1 == "cost exceeded" => Failure::CostExceeded,
+            ErrKind::Eval if e.1 == "clvm raise" => Failure::Raise,
+            ErrKind::Eval => Failure::Eval,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    // the cost and the serialized result
+    Success(Cost, Vec<u8>),
+    // the class of the failure and its message
+    Failure(Failure, String),
+}
+
+impl Outcome {
+    // whether two implementations behaved the same, ignoring the wording of
+    // error messages
+    pub fn agrees(&self, other: &Outcome) -> bool {
+        match (self, other) {
+            (Outcome::Success(c1, r1), Outcome::Success(c2, r2)) => c1 == c2 && r1 == r2,
+            (Outcome::Failure(f1, _), Outcome::Failure(f2, _)) => f1 == f2,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Success(cost, result) => {
+                write!(f, "cost {} result {}", cost, hex::encode(result))
+            }
+            Outcome::Failure(class, msg) => write!(f, "{:?} failure: {}", class, msg),
+        }
+    }
+}
+
+// a program the two implementations disagree on
+#[derive(Clone, Debug, PartialEq)]
+pub struct Divergence {
+    pub program: Vec<u8>,
+    pub env: Vec<u8>,
+    pub max_cost: Cost,
+    pub ours: Outcome,
+    pub reference: Outcome,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "program {} env {} max cost {}: expected {}, got {}",
+            hex::encode(&self.program),
+            hex::encode(&self.env),
+            self.max_cost,
+            self.reference,
+            self.ours
+        )
+    }
+}
+
+// runs a serialized program with the chia operators, the way the reference
+// is expected to
+pub fn run_serialized(program: &[u8], env: &[u8], max_cost: Cost) -> Outcome {
+    let mut a = IntAllocator::new();
+    let (program, env) = match (
+        node_from_bytes(&mut a, program),
+        node_from_bytes(&mut a, env),
+    ) {
+        (Ok(p), Ok(e)) => (p, e),
+        (Err(e), _) | (_, Err(e)) => {
+            return Outcome::Failure(Failure::Serialization, e.to_string())
+        }
+    };
+    match run_program(
+        &mut a,
+        &program,
+        &env,
+        1,
+        2,
+        max_cost,
+        Box::new(op_table_for(&CHIA_OPCODES)),
+        None,
+    ) {
+        Ok(r) => match node_to_bytes(&Node::new(&a, r.1)) {
+            Ok(result) => Outcome::Success(r.0, result),
+            Err(e) => Outcome::Failure(Failure::Limit, e.to_string()),
+        },
+        Err(e) => Outcome::Failure(Failure::classify(&e), e.1.to_string()),
+    }
+}
+
+// runs one program here and through the reference, which is passed the
+// serialized program, environment and cost limit. None if they agree
+pub fn check_program<F>(
+    program: &[u8],
+    env: &[u8],
+    max_cost: Cost,
+    reference: &mut F,
+) -> Option<Divergence>
+where
+    F: FnMut(&[u8], &[u8], Cost) -> Outcome,
+{
+    let ours = run_serialized(program, env, max_cost);
+    let expected = reference(program, env, max_cost);
+    if ours.agrees(&expected) {
+        None
+    } else {
+        Some(Divergence {
+            program: program.to_vec(),
+            env: env.to_vec(),
+            max_cost,
+            ours,
+            reference: expected,
+        })
+    }
+}
+
+// a random program: operators applied to nested operators, quoted atoms
+// and arguments from the environment
+fn generate_node(a: &mut IntAllocator, rng: &mut Rng, depth: usize) -> i32 {
+    if depth == 0 || rng.below(3) == 0 {
+        return match rng.below(3) {
+            0 => {
+                let q = a.one();
+                let atom = a.new_atom(&rng.atom()).unwrap();
+                a.new_pair(q, atom).unwrap()
+            }
+            1 => a.new_atom(&[1 + rng.below(15) as u8]).unwrap(),
+            _ => a.null(),
+        };
+    }
+    let opcode = CHIA_OPCODES[rng.below(CHIA_OPCODES.len())].0;
+    let mut args = a.null();
+    for _ in 0..rng.below(4) {
+        let arg = generate_node(a, rng, depth - 1);
+        args = a.new_pair(arg, args).unwrap();
+    }
+    let op = a.new_atom(&[opcode]).unwrap();
+    a.new_pair(op, args).unwrap()
+}
+
+// a random serialized program and environment
+pub fn generate(rng: &mut Rng) -> (Vec<u8>, Vec<u8>) {
+    let mut a = IntAllocator::new();
+    let program = generate_node(&mut a, rng, 4);
+    let mut env = a.null();
+    for _ in 0..rng.below(4) {
+        let atom = a.new_atom(&rng.atom()).unwrap();
+        env = a.new_pair(atom, env).unwrap();
+    }
+    (
+        node_to_bytes(&Node::new(&a, program)).unwrap(),
+        node_to_bytes(&Node::new(&a, env)).unwrap(),
+    )
+}
+
+// runs `iterations` generated programs through both implementations and
+// returns every divergence. The same seed generates the same programs
+pub fn run_conformance<F>(
+    seed: u64,
+    iterations: usize,
+    max_cost: Cost,
+    mut reference: F,
+) -> Vec<Divergence>
+where
+    F: FnMut(&[u8], &[u8], Cost) -> Outcome,
+{
+    let mut rng = Rng::new(seed);
+    let mut ret = Vec::new();
+    for _ in 0..iterations {
+        let (program, env) = generate(&mut rng);
+        if let Some(d) = check_program(&program, &env, max_cost, &mut reference) {
+            ret.push(d);
+        }
+    }
+    ret
+}
+
+#[test]
+fn test_run_serialized() {
+    // (+ 2 5) with (3 4)
+    let program = hex::decode("ff10ff02ff0580").unwrap();
+    let env = hex::decode("ff03ff0480").unwrap();
+    assert_eq!(
+        run_serialized(&program, &env, Cost::ZERO),
+        Outcome::Success(Cost::new(856), vec![7])
+    );
+    match run_serialized(&program, &env, Cost::new(10)) {
+        Outcome::Failure(Failure::CostExceeded, _) => {}
+        o => panic!("unexpected {:?}", o),
+    }
+    // (x (q . 1))
+    let raise = hex::decode("ff08ffff010180").unwrap();
+    match run_serialized(&raise, &[0x80], Cost::ZERO) {
+        Outcome::Failure(Failure::Raise, msg) => assert_eq!(msg, "clvm raise"),
+        o => panic!("unexpected {:?}", o),
+    }
+    match run_serialized(&[0xff], &[0x80], Cost::ZERO) {
+        Outcome::Failure(Failure::Serialization, _) => {}
+        o => panic!("unexpected {:?}", o),
+    }
+}
+
+#[test]
+fn test_run_conformance() {
+    // the evaluator agrees with itself, whatever the error messages say
+    let same = |p: &[u8], e: &[u8], c: Cost| match run_serialized(p, e, c) {
+        Outcome::Failure(f, _) => Outcome::Failure(f, "reworded".to_string()),
+        o => o,
+    };
+    assert_eq!(run_conformance(1, 500, Cost::new(100_000), same), vec![]);
+
+    // the same seed generates the same programs
+    let mut r1 = Rng::new(7);
+    let mut r2 = Rng::new(7);
+    assert_eq!(generate(&mut r1), generate(&mut r2));
+
+    // a reference that charges one more for everything differs on every
+    // program that succeeds
+    let off_by_one = |p: &[u8], e: &[u8], c: Cost| match run_serialized(p, e, c) {
+        Outcome::Success(cost, r) => Outcome::Success(cost + Cost::new(1), r),
+        o => o,
+    };
+    let successes = {
+        let mut rng = Rng::new(2);
+        (0..200)
+            .filter(|_| {
+                let (p, e) = generate(&mut rng);
+                matches!(
+                    run_serialized(&p, &e, Cost::new(100_000)),
+                    Outcome::Success(..)
+                )
+            })
+            .count()
+    };
+    assert!(successes > 0);
+    let divergences = run_conformance(2, 200, Cost::new(100_000), off_by_one);
+    assert_eq!(divergences.len(), successes);
+    let d = &divergences[0];
+    assert_eq!(d.max_cost, Cost::new(100_000));
+    match (&d.ours, &d.reference) {
+        (Outcome::Success(c1, _), Outcome::Success(c2, _)) => assert_eq!(*c1 + Cost::new(1), *c2),
+        o => panic!("unexpected {:?}", o),
+    }
+}
@@ -0,0 +1,99 @@
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::allocator::{Allocator, SExp};
+use crate::reduction::EvalErr;
+
+enum CopyOp<P> {
+    Copy(P),
+    // both halves of this pair have been copied
+    Cons(P),
+}
+
+// copies a tree from one allocator to another (which may be of a different
+// kind) without serializing it. A subtree that's shared in the source is
+// copied once and shared in the copy too, so the copy is no larger than the
+// source. nil and one map to the destination's preallocated ones
+pub fn copy_node<A, B>(src: &A, node: A::Ptr, dst: &mut B) -> Result<B::Ptr, EvalErr<B::Ptr>>
+where
+    A: Allocator,
+    A::Ptr: Ord,
+    B: Allocator,
+{
+    let mut copies: BTreeMap<A::Ptr, B::Ptr> = BTreeMap::new();
+    let mut values: Vec<B::Ptr> = Vec::new();
+    let mut ops = vec![CopyOp::Copy(node)];
+    while let Some(op) = ops.pop() {
+        match op {
+            CopyOp::Copy(node) => {
+                if let Some(copy) = copies.get(&node) {
+                    values.push(copy.clone());
+                    continue;
+                }
+                match src.sexp(&node) {
+                    SExp::Atom(buf) => {
+                        let copy = match src.buf(&buf) {
+                            [] => dst.null(),
+                            [1] => dst.one(),
+                            atom => dst.new_atom(atom)?,
+                        };
+                        copies.insert(node, copy.clone());
+                        values.push(copy);
+                    }
+                    SExp::Pair(first, rest) => {
+                        ops.push(CopyOp::Cons(node));
+                        ops.push(CopyOp::Copy(rest));
+                        ops.push(CopyOp::Copy(first));
+                    }
+                }
+            }
+            CopyOp::Cons(node) => {
+                let rest = values.pop().unwrap();
+                let first = values.pop().unwrap();
+                let copy = dst.new_pair(first, rest)?;
+                copies.insert(node, copy.clone());
+                values.push(copy);
+            }
+        }
+    }
+    Ok(values.pop().unwrap())
+}
+
+#[test]
+fn test_copy_node() {
+    use crate::int_allocator::{IntAllocator, WideIntAllocator};
+    use crate::node::Node;
+    use crate::serialize::{node_from_bytes, node_to_bytes};
+
+    // (("foo" . (1 2 3)) . ("foo" . (1 2 3))), sharing the halves
+    let mut a = IntAllocator::new();
+    let half = node_from_bytes(&mut a, &hex::decode("ff83666f6fff01ff02ff0380").unwrap()).unwrap();
+    let root = a.new_pair(half, half).unwrap();
+    let bytes = node_to_bytes(&Node::new(&a, root)).unwrap();
+
+    // the copy fits in the 5 pairs of the source, not the 9 of the tree
+    let mut b = WideIntAllocator::with_limits(5, 5);
+    let copy = copy_node(&a, root, &mut b).unwrap();
+    assert_eq!(node_to_bytes(&Node::new(&b, copy)).unwrap(), bytes);
+    match b.sexp(&copy) {
+        SExp::Pair(first, rest) => assert_eq!(first, rest),
+        SExp::Atom(_) => panic!("expected pair"),
+    }
+
+    // running out of room in the destination is an error, not a panic
+    let mut b = IntAllocator::with_limits(5, 4);
+    assert!(copy_node(&a, root, &mut b).is_err());
+
+    // deep trees don't overflow the stack
+    let mut list = a.null();
+    for _ in 0..100000 {
+        list = a.new_pair(a.one(), list).unwrap();
+    }
+    let mut b = IntAllocator::new();
+    let copy = copy_node(&a, list, &mut b).unwrap();
+    assert_eq!(
+        node_to_bytes(&Node::new(&b, copy)).unwrap(),
+        node_to_bytes(&Node::new(&a, list)).unwrap()
+    );
+}
@@ -1,12 +1,147 @@
+use core::fmt;
+use core::iter::Sum;
+use core::ops::{Add, AddAssign, Div, Mul};
+use core::str::FromStr;
+
 use crate::allocator::Allocator;
 use crate::reduction::EvalErr;
 
-pub type Cost = u64;
+// Costs only have saturating arithmetic, so that no sum or product of them
+// can wrap around, whether or not its operands are bounded by a cost check
+// (e.g. sizes of atoms that can be gigabytes, or multipliers). A cost that
+// would overflow becomes Cost::MAX, which run_program() treats as exceeded
+// even without a limit, instead of wrapping around to a small cost. The
+// checked_ methods are for callers that need to tell the two apart
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Cost(u64);
+
+impl Cost {
+    pub const ZERO: Cost = Cost(0);
+    pub const MAX: Cost = Cost(u64::MAX);
+
+    pub const fn new(cost: u64) -> Self {
+        Cost(cost)
+    }
+
+    pub const fn get(self) -> u64 {
+        self.0
+    }
+
+    pub fn checked_add(self, rhs: Cost) -> Option<Cost> {
+        self.0.checked_add(rhs.0).map(Cost)
+    }
+
+    pub fn checked_mul(self, rhs: u64) -> Option<Cost> {
+        self.0.checked_mul(rhs).map(Cost)
+    }
+
+    pub fn saturating_add(self, rhs: Cost) -> Cost {
+        Cost(self.0.saturating_add(rhs.0))
+    }
+
+    pub fn saturating_sub(self, rhs: Cost) -> Cost {
+        Cost(self.0.saturating_sub(rhs.0))
+    }
+
+    pub fn saturating_mul(self, rhs: u64) -> Cost {
+        Cost(self.0.saturating_mul(rhs))
+    }
+}
+
+impl From<u64> for Cost {
+    fn from(cost: u64) -> Self {
+        Cost(cost)
+    }
+}
+
+impl From<Cost> for u64 {
+    fn from(cost: Cost) -> Self {
+        cost.0
+    }
+}
+
+impl FromStr for Cost {
+    type Err = core::num::ParseIntError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse().map(Cost)
+    }
+}
+
+impl fmt::Display for Cost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Add for Cost {
+    type Output = Cost;
+    fn add(self, rhs: Cost) -> Cost {
+        self.saturating_add(rhs)
+    }
+}
+
+impl AddAssign for Cost {
+    fn add_assign(&mut self, rhs: Cost) {
+        *self = self.saturating_add(rhs);
+    }
+}
+
+// a cost per unit times a number of units, e.g. bytes or arguments
+impl Mul<u64> for Cost {
+    type Output = Cost;
+    fn mul(self, rhs: u64) -> Cost {
+        self.saturating_mul(rhs)
+    }
+}
+
+impl Mul<usize> for Cost {
+    type Output = Cost;
+    fn mul(self, rhs: usize) -> Cost {
+        // usize is at most 64 bits on every target rust supports
+        self.saturating_mul(rhs as u64)
+    }
+}
+
+impl Div<u64> for Cost {
+    type Output = Cost;
+    fn div(self, rhs: u64) -> Cost {
+        Cost(self.0 / rhs)
+    }
+}
+
+impl Sum for Cost {
+    fn sum<I: Iterator<Item = Cost>>(iter: I) -> Cost {
+        iter.fold(Cost::ZERO, Add::add)
+    }
+}
 
 pub fn check_cost<A: Allocator>(a: &A, cost: Cost, max_cost: Cost) -> Result<(), EvalErr<A::Ptr>> {
     if cost > max_cost {
-        Err(EvalErr(a.null(), "cost exceeded".into()))
+        Err(EvalErr::new(a.null(), "cost exceeded"))
     } else {
         Ok(())
     }
 }
+
+#[test]
+fn test_cost_saturates() {
+    assert_eq!(Cost::MAX + Cost::new(1), Cost::MAX);
+    assert_eq!(Cost::new(u64::MAX / 2) * 3_u64, Cost::MAX);
+    assert_eq!(Cost::new(3) * usize::MAX, Cost::MAX);
+    let mut cost = Cost::new(u64::MAX - 1);
+    cost += Cost::new(2);
+    assert_eq!(cost, Cost::MAX);
+    assert_eq!(Cost::new(1).saturating_sub(Cost::new(2)), Cost::ZERO);
+    assert_eq!(
+        [Cost::MAX, Cost::new(1)].iter().copied().sum::<Cost>(),
+        Cost::MAX
+    );
+
+    assert_eq!(Cost::MAX.checked_add(Cost::new(1)), None);
+    assert_eq!(Cost::new(2).checked_mul(3), Some(Cost::new(6)));
+    assert_eq!(Cost::new(7) * 6_u64 / 4, Cost::new(10));
+    assert_eq!(u64::from(Cost::new(5)), 5);
+    assert_eq!(Cost::from(5), Cost::new(5));
+    assert_eq!("11000000000".parse(), Ok(Cost::new(11_000_000_000)));
+    assert_eq!(Cost::new(11_000_000_000).to_string(), "11000000000");
+}
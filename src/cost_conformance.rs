@@ -0,0 +1,386 @@
+// A suite that pins down the exact cost of every operator. A matrix of
+// invocations, over a range of argument counts and sizes, is run through an
+// operator table and each charged cost is compared with a cost model. The
+// chia model below restates the cost table from scratch rather than reusing
+// the constants in core_ops and more_ops, so a refactor of cost accounting
+// that changes any charged cost is caught. A downstream change to the cost
+// table passes its own model (and operators). Like the reference operators,
+// this is only compiled with the "testing" feature
+//
+// first and rest take a pair, and raise always fails, so they aren't in the
+// matrix. Their costs are constants, covered by the vectors
+
+use std::fmt;
+
+use bls12_381::G1Affine;
+
+use crate::allocator::Allocator;
+use crate::cost::Cost;
+use crate::int_allocator::IntAllocator;
+use crate::number::{
+    number_bits, number_from_u8, number_from_unsigned_u8, number_to_atom_bytes, number_to_u64,
+    Number,
+};
+use crate::op_table::{opcode_by_name, Op};
+use crate::reduction::Reduction;
+
+// the argument sizes, in bytes, run_cost_suite() uses
+pub const DEFAULT_SIZES: [usize; 9] = [0, 1, 2, 7, 8, 31, 32, 33, 200];
+
+// and the most arguments it passes to a variadic operator
+pub const DEFAULT_MAX_ARGS: usize = 4;
+
+// an operator invocation: the name it has in opcode_by_name() and its
+// arguments, which are all atoms
+#[derive(Clone, Debug, PartialEq)]
+pub struct CostCase {
+    pub op: &'static str,
+    pub args: Vec<Vec<u8>>,
+}
+
+// an invocation that wasn't charged what the model expects. `got` is the
+// error message if it failed
+#[derive(Clone, Debug, PartialEq)]
+pub struct CostMismatch {
+    pub case: CostCase,
+    pub expected: Cost,
+    pub got: Result<Cost, String>,
+}
+
+impl fmt::Display for CostMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sizes: Vec<String> = self.case.args.iter().map(|a| a.len().to_string()).collect();
+        write!(
+            f,
+            "{} with argument sizes [{}]: expected cost {}, ",
+            self.case.op,
+            sizes.join(", "),
+            self.expected
+        )?;
+        match &self.got {
+            Ok(cost) => write!(f, "got {}", cost),
+            Err(msg) => write!(f, "failed: {}", msg),
+        }
+    }
+}
+
+// a canonical integer atom of exactly `size` bytes. `seed` varies the
+// contents, and the sign when `signed` is set
+fn int_of_size(size: usize, seed: usize, signed: bool) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(size);
+    for i in 0..size {
+        ret.push((0x5a ^ (i * 37 + seed * 11)) as u8);
+    }
+    if let Some(first) = ret.first_mut() {
+        // never 0x00 or 0xff, so there is no redundant leading byte
+        let top = if signed && seed % 2 == 1 { 0xc0 } else { 0x40 };
+        *first = top | (seed % 0x20) as u8;
+    }
+    ret
+}
+
+fn int_atom(v: i64) -> Vec<u8> {
+    number_to_atom_bytes(&v.into())
+}
+
+// every operator with `count` arguments, one for each size in turn
+fn variadic(ops: &[&'static str], count: usize, sizes: &[usize], signed: bool) -> Vec<CostCase> {
+    let mut ret = Vec::new();
+    for op in ops {
+        // all arguments the same size, then mixed sizes
+        for size in sizes {
+            let args = (0..count).map(|i| int_of_size(*size, i, signed)).collect();
+            ret.push(CostCase { op, args });
+        }
+        let args = (0..count)
+            .map(|i| int_of_size(sizes[i % sizes.len()], i, signed))
+            .collect();
+        ret.push(CostCase { op, args });
+    }
+    ret
+}
+
+// the invocations to check: each operator with every argument size and,
+// for variadic operators, up to `max_args` arguments
+pub fn cost_matrix(sizes: &[usize], max_args: usize) -> Vec<CostCase> {
+    let mut ret = Vec::new();
+    if sizes.is_empty() {
+        return ret;
+    }
+    let case = |op, args| CostCase { op, args };
+
+    for count in 0..=max_args {
+        ret.extend(variadic(
+            &[
+                "op_add",
+                "op_subtract",
+                "op_multiply",
+                "op_logand",
+                "op_logior",
+                "op_logxor",
+                "op_concat",
+                "op_sha256",
+                "op_any",
+                "op_all",
+            ],
+            count,
+            sizes,
+            true,
+        ));
+        // only valid points can be added
+        let point = G1Affine::generator().to_compressed().to_vec();
+        ret.push(case("op_point_add", vec![point; count]));
+    }
+
+    for s0 in sizes {
+        for s1 in sizes {
+            let a0 = int_of_size(*s0, 1, true);
+            let a1 = int_of_size(*s1, 2, true);
+            for op in &["op_eq", "op_gr", "op_gr_bytes", "op_cons"] {
+                ret.push(case(op, vec![a0.clone(), a1.clone()]));
+            }
+            ret.push(case("op_if", vec![a0.clone(), a1.clone(), a0.clone()]));
+            // positive, and not dividing by zero
+            if *s1 > 0 {
+                let a0 = int_of_size(*s0, 0, false);
+                let a1 = int_of_size(*s1, 2, false);
+                ret.push(case("op_div", vec![a0.clone(), a1.clone()]));
+                ret.push(case("op_divmod", vec![a0, a1]));
+            }
+        }
+
+        let a0 = int_of_size(*s0, 1, true);
+        for op in &[
+            "op_lognot",
+            "op_strlen",
+            "op_not",
+            "op_listp",
+            "op_pubkey_for_exp",
+        ] {
+            ret.push(case(op, vec![a0.clone()]));
+        }
+        for shift in &[-20, -8, -1, 0, 1, 8, 20] {
+            for op in &["op_ash", "op_lsh"] {
+                ret.push(case(op, vec![a0.clone(), int_atom(*shift)]));
+            }
+        }
+        ret.push(case("op_substr", vec![a0.clone(), int_atom(0)]));
+        ret.push(case(
+            "op_substr",
+            vec![a0.clone(), int_atom(0), int_atom(*s0 as i64)],
+        ));
+        // the cost is the argument, so keep it small
+        if (1..=4).contains(s0) {
+            ret.push(case("op_softfork", vec![int_of_size(*s0, 0, false)]));
+        }
+    }
+    ret
+}
+
+// the atom an integer operator returns
+fn result_len(n: &Number) -> u64 {
+    number_to_atom_bytes(n).len() as u64
+}
+
+fn limbs(n: &Number) -> u64 {
+    number_bits(n).div_ceil(8)
+}
+
+// a shift amount, which fits in an i32
+fn shift_amount(atom: &[u8]) -> Option<i32> {
+    if atom.len() > 4 {
+        return None;
+    }
+    let mut ret: i32 = if atom.first() >= Some(&0x80) { -1 } else { 0 };
+    for b in atom {
+        ret = ret << 8 | *b as i32;
+    }
+    Some(ret)
+}
+
+// the cost chia's operators charge, including the base cost. None for
+// operators the model doesn't know. It's plain arithmetic on u64, as the
+// arguments it's given are small
+pub fn chia_cost(op: &str, args: &[Vec<u8>]) -> Option<Cost> {
+    const MALLOC_PER_BYTE: u64 = 10;
+
+    let count = args.len() as u64;
+    let bytes: u64 = args.iter().map(|a| a.len() as u64).sum();
+    let len = |i: usize| args[i].len() as u64;
+    let int = |i: usize| number_from_u8(&args[i]);
+
+    let cost = match op {
+        "op_if" => 33,
+        "op_cons" => 50,
+        "op_first" => 30,
+        "op_rest" => 30,
+        "op_listp" => 19,
+        "op_eq" => 117 + bytes,
+        "op_sha256" => 87 + 134 * count + 2 * bytes + 32 * MALLOC_PER_BYTE,
+        "op_add" | "op_subtract" => {
+            let mut total: Number = 0.into();
+            for i in 0..args.len() {
+                if i > 0 && op == "op_subtract" {
+                    total -= int(i);
+                } else {
+                    total += int(i);
+                }
+            }
+            99 + 320 * count + 3 * bytes + result_len(&total) * MALLOC_PER_BYTE
+        }
+        "op_multiply" => {
+            let mut cost = 92;
+            let mut total: Number = 1.into();
+            for i in 0..args.len() {
+                if i > 0 {
+                    let l0 = if i == 1 { len(0) } else { limbs(&total) };
+                    let l1 = len(i);
+                    cost += 885 + 6 * (l0 + l1) + l0 * l1 / 128;
+                }
+                total *= int(i);
+            }
+            cost + result_len(&total) * MALLOC_PER_BYTE
+        }
+        "op_div" | "op_divmod" => {
+            // floor division of positive operands
+            let q = int(0) / int(1);
+            let r = int(0) - int(1) * &q;
+            if op == "op_div" {
+                988 + 4 * (len(0) + len(1)) + result_len(&q) * MALLOC_PER_BYTE
+            } else {
+                1116 + 6 * (len(0) + len(1)) + (result_len(&q) + result_len(&r)) * MALLOC_PER_BYTE
+            }
+        }
+        "op_gr" => 498 + 2 * bytes,
+        "op_gr_bytes" => 117 + bytes,
+        "op_strlen" => 173 + bytes + int_atom(len(0) as i64).len() as u64 * MALLOC_PER_BYTE,
+        "op_substr" => 1,
+        "op_concat" => 142 + 135 * count + 3 * bytes + bytes * MALLOC_PER_BYTE,
+        "op_logand" | "op_logior" | "op_logxor" => {
+            let mut total = if op == "op_logand" {
+                (-1).into()
+            } else {
+                0.into()
+            };
+            for i in 0..args.len() {
+                match op {
+                    "op_logand" => total &= int(i),
+                    "op_logior" => total |= int(i),
+                    _ => total ^= int(i),
+                }
+            }
+            100 + 264 * count + 3 * bytes + result_len(&total) * MALLOC_PER_BYTE
+        }
+        "op_lognot" => 331 + 3 * bytes + result_len(&!int(0)) * MALLOC_PER_BYTE,
+        "op_ash" | "op_lsh" => {
+            let (base, v) = if op == "op_ash" {
+                (596, int(0))
+            } else {
+                (277, number_from_unsigned_u8(&args[0]))
+            };
+            let shift = shift_amount(&args[1])?;
+            let v = if shift > 0 { v << shift } else { v >> -shift };
+            base + 3 * (len(0) + limbs(&v)) + result_len(&v) * MALLOC_PER_BYTE
+        }
+        "op_not" => 200,
+        "op_any" | "op_all" => 200 + 300 * count,
+        "op_pubkey_for_exp" => 1325730 + 38 * bytes + 48 * MALLOC_PER_BYTE,
+        "op_point_add" => 101094 + 1343980 * count + 48 * MALLOC_PER_BYTE,
+        "op_softfork" => number_to_u64(&int(0))?,
+        _ => return None,
+    };
+    Some(Cost::new(cost))
+}
+
+fn charged(op: Op<IntAllocator>, args: &[Vec<u8>]) -> Result<Cost, String> {
+    let mut a = IntAllocator::new();
+    let mut list = a.null();
+    for arg in args.iter().rev() {
+        let atom = a.new_atom(arg).map_err(|e| e.1.to_string())?;
+        list = a.new_pair(atom, list).map_err(|e| e.1.to_string())?;
+    }
+    match op.call(&mut a, list, Cost::MAX) {
+        Ok(Reduction(cost, _)) => Ok(cost),
+        Err(e) => Err(e.1.to_string()),
+    }
+}
+
+// runs every case through the operator `lookup` returns for its name and
+// compares the cost with what `model` expects. Cases the model has no cost
+// for are skipped. Returns the mismatches
+pub fn check_costs<L, M>(cases: &[CostCase], lookup: L, model: M) -> Vec<CostMismatch>
+where
+    L: Fn(&str) -> Option<Op<IntAllocator>>,
+    M: Fn(&str, &[Vec<u8>]) -> Option<Cost>,
+{
+    let mut ret = Vec::new();
+    for case in cases {
+        let expected = match model(case.op, &case.args) {
+            Some(cost) => cost,
+            None => continue,
+        };
+        let got = match lookup(case.op) {
+            Some(op) => charged(op, &case.args),
+            None => Err("unknown operator".to_string()),
+        };
+        if got != Ok(expected) {
+            ret.push(CostMismatch {
+                case: case.clone(),
+                expected,
+                got,
+            });
+        }
+    }
+    ret
+}
+
+// checks this crate's operators against the chia cost model
+pub fn run_cost_suite() -> Vec<CostMismatch> {
+    check_costs(
+        &cost_matrix(&DEFAULT_SIZES, DEFAULT_MAX_ARGS),
+        opcode_by_name,
+        chia_cost,
+    )
+}
+
+#[test]
+fn test_cost_suite() {
+    let failures = run_cost_suite();
+    for f in &failures {
+        println!("{}", f);
+    }
+    assert!(failures.is_empty());
+
+    // every operator that takes atoms is in the matrix
+    let cases = cost_matrix(&DEFAULT_SIZES, DEFAULT_MAX_ARGS);
+    for (_, name) in crate::op_table::CHIA_OPCODES.iter() {
+        if !["op_first", "op_rest", "op_raise"].contains(name) {
+            assert!(cases.iter().any(|c| c.op == *name), "{} not covered", name);
+        }
+    }
+}
+
+#[test]
+fn test_cost_mismatch() {
+    // a change to the cost table is caught
+    let cases = cost_matrix(&[0, 3], 2);
+    let model = |op: &str, args: &[Vec<u8>]| {
+        let cost = chia_cost(op, args)?;
+        Some(if op == "op_concat" {
+            cost + Cost::new(1)
+        } else {
+            cost
+        })
+    };
+    let failures = check_costs(&cases, opcode_by_name, model);
+    assert!(!failures.is_empty());
+    assert!(failures.iter().all(|f| f.case.op == "op_concat"));
+    assert_eq!(
+        failures[0].to_string(),
+        "op_concat with argument sizes []: expected cost 143, got 142"
+    );
+
+    let failures = check_costs(&cases, |_| None, chia_cost);
+    assert_eq!(failures.len(), cases.len());
+    assert_eq!(failures[0].got, Err("unknown operator".to_string()));
+}
@@ -0,0 +1,96 @@
+use crate::allocator::Allocator;
+use crate::reduction::EvalErr;
+
+// curried programs are built from the keywords of chia's operator table (see
+// op_table::CHIA_OPCODES)
+const QUOTE_KW: u8 = 1;
+const APPLY_KW: u8 = 2;
+const CONS_KW: u8 = 4;
+
+// bind the leading arguments of a program. The result is
+// (a (q . program) (c (q . arg1) (c (q . arg2) ... 1))), which runs the
+// original program with the bound arguments in front of its own
+pub fn curry<A: Allocator>(
+    a: &mut A,
+    program: A::Ptr,
+    args: &[A::Ptr],
+) -> Result<A::Ptr, EvalErr<A::Ptr>> {
+    let quote = a.new_atom(&[QUOTE_KW])?;
+    let apply = a.new_atom(&[APPLY_KW])?;
+    let cons = a.new_atom(&[CONS_KW])?;
+
+    // the path 1 is the whole environment the curried program is called with
+    let mut env = a.one();
+    for arg in args.iter().rev() {
+        let quoted = a.new_pair(quote.clone(), arg.clone())?;
+        let rest = a.new_pair(env, a.null())?;
+        let rest = a.new_pair(quoted, rest)?;
+        env = a.new_pair(cons.clone(), rest)?;
+    }
+    let quoted = a.new_pair(quote, program)?;
+    let rest = a.new_pair(env, a.null())?;
+    let rest = a.new_pair(quoted, rest)?;
+    a.new_pair(apply, rest)
+}
+
+#[test]
+fn test_curry() {
+    use crate::cost::Cost;
+    use crate::int_allocator::IntAllocator;
+    use crate::node::Node;
+    use crate::op_table::{op_table_for, CHIA_OPCODES};
+    use crate::run_program::run_program;
+    use crate::serialize::node_to_bytes;
+
+    let mut a = IntAllocator::new();
+
+    // (- 2 5)
+    let minus = a.new_atom(&[17]).unwrap();
+    let two = a.new_atom(&[2]).unwrap();
+    let five = a.new_atom(&[5]).unwrap();
+    let args = a.new_pair(five, a.null()).unwrap();
+    let args = a.new_pair(two, args).unwrap();
+    let program = a.new_pair(minus, args).unwrap();
+
+    let ten = a.new_atom(&[10]).unwrap();
+    let curried = curry(&mut a, program, &[ten]).unwrap();
+    // (a (q 17 2 5) (c (q . 10) 1))
+    assert_eq!(
+        hex::encode(node_to_bytes(&Node::new(&a, curried)).unwrap()),
+        "ff02ffff01ff11ff02ff0580ffff04ffff010aff018080"
+    );
+
+    // the remaining argument comes from the environment
+    let three = a.new_atom(&[3]).unwrap();
+    let env = a.new_pair(three, a.null()).unwrap();
+    let table = op_table_for(&CHIA_OPCODES);
+    let r = run_program(
+        &mut a,
+        &curried,
+        &env,
+        1,
+        2,
+        Cost::new(100000),
+        Box::new(table),
+        None,
+    )
+    .unwrap();
+    assert_eq!(a.atom(&r.1), &[7]);
+
+    // with nothing to bind, the environment is passed through as is
+    let curried = curry(&mut a, program, &[]).unwrap();
+    let table = op_table_for(&CHIA_OPCODES);
+    let env = a.new_pair(ten, env).unwrap();
+    let r = run_program(
+        &mut a,
+        &curried,
+        &env,
+        1,
+        2,
+        Cost::new(100000),
+        Box::new(table),
+        None,
+    )
+    .unwrap();
+    assert_eq!(a.atom(&r.1), &[7]);
+}
@@ -0,0 +1,146 @@
+// Structural comparison of two trees, for finding out why two programs that
+// should be the same (e.g. a puzzle and its reveal) aren't. Differences are
+// located by path, as in clvm's environment lookup: 1 is the root, and a path
+// followed by a 0 bit goes to the first of a pair and a 1 bit to the rest,
+// so 2 is the first of the root and 5 the first of its rest.
+
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::allocator::{Allocator, SExp};
+
+// a place where the trees differ. left and right are the subtrees there
+pub struct Difference<P> {
+    pub path: Vec<u8>,
+    pub left: P,
+    pub right: P,
+}
+
+// the path atom for the steps down from the root (true is rest). It's a
+// positive integer, so it has a leading 0 byte when the top bit is set
+fn path_atom(steps: &[bool]) -> Vec<u8> {
+    let len = steps.len() / 8 + 1;
+    let mut ret = vec![0_u8; len];
+    for (i, rest) in steps.iter().enumerate() {
+        if *rest {
+            ret[len - 1 - i / 8] |= 1 << (i % 8);
+        }
+    }
+    ret[len - 1 - steps.len() / 8] |= 1 << (steps.len() % 8);
+    if ret[0] & 0x80 != 0 {
+        ret.insert(0, 0);
+    }
+    ret
+}
+
+// two nodes to compare, how many steps lead to their parent, and the step
+// from there
+struct Compare<P> {
+    left: P,
+    right: P,
+    depth: usize,
+    step: Option<bool>,
+}
+
+// the differences between two trees, in the order they're serialized: atoms
+// with different values, and places where one tree has an atom and the other a
+// pair. At most `limit` are returned, 0 meaning all of them
+pub fn diff<T: Allocator>(
+    allocator: &T,
+    left: T::Ptr,
+    right: T::Ptr,
+    limit: usize,
+) -> Vec<Difference<T::Ptr>> {
+    let mut ret = Vec::new();
+    let mut steps: Vec<bool> = Vec::new();
+    let mut ops = vec![Compare {
+        left,
+        right,
+        depth: 0,
+        step: None,
+    }];
+    while let Some(Compare {
+        left: l,
+        right: r,
+        depth,
+        step,
+    }) = ops.pop()
+    {
+        steps.truncate(depth);
+        if let Some(step) = step {
+            steps.push(step);
+        }
+        let differs = match (allocator.sexp(&l), allocator.sexp(&r)) {
+            (SExp::Pair(l_first, l_rest), SExp::Pair(r_first, r_rest)) => {
+                ops.push(Compare {
+                    left: l_rest,
+                    right: r_rest,
+                    depth: steps.len(),
+                    step: Some(true),
+                });
+                ops.push(Compare {
+                    left: l_first,
+                    right: r_first,
+                    depth: steps.len(),
+                    step: Some(false),
+                });
+                false
+            }
+            (SExp::Atom(l_buf), SExp::Atom(r_buf)) => {
+                allocator.buf(&l_buf) != allocator.buf(&r_buf)
+            }
+            _ => true,
+        };
+        if differs {
+            ret.push(Difference {
+                path: path_atom(&steps),
+                left: l,
+                right: r,
+            });
+            if ret.len() == limit {
+                break;
+            }
+        }
+    }
+    ret
+}
+
+#[test]
+fn test_path_atom() {
+    assert_eq!(path_atom(&[]), [1]);
+    assert_eq!(path_atom(&[false]), [2]);
+    assert_eq!(path_atom(&[true]), [3]);
+    assert_eq!(path_atom(&[true, false]), [5]);
+    assert_eq!(path_atom(&[false; 7]), [0, 0x80]);
+    assert_eq!(path_atom(&[true; 8]), [1, 0xff]);
+}
+
+#[test]
+fn test_diff() {
+    use crate::int_allocator::IntAllocator;
+    use crate::run_program::traverse_path;
+    use crate::serialize::node_from_bytes;
+
+    let mut a = IntAllocator::new();
+    let mut parse = |blob: &str| node_from_bytes(&mut a, &hex::decode(blob).unwrap()).unwrap();
+    // (1 (2 3) 4) against (1 (2 . 5) 6)
+    let left = parse("ff01ffff02ff0380ff0480");
+    let right = parse("ff01ffff0205ff0680");
+    let same = parse("ff01ffff02ff0380ff0480");
+
+    assert!(diff(&a, left, same, 0).is_empty());
+    let d = diff(&a, left, right, 0);
+    let paths: Vec<&[u8]> = d.iter().map(|d| d.path.as_slice()).collect();
+    assert_eq!(paths, [&[0x0d][..], &[0x0b][..]]);
+    for d in &d {
+        assert_eq!(traverse_path(&a, &d.path, &left).unwrap().1, d.left);
+        assert_eq!(traverse_path(&a, &d.path, &right).unwrap().1, d.right);
+    }
+    assert_eq!(diff(&a, left, right, 1).len(), 1);
+
+    // an atom against a pair
+    let atom = a.one();
+    let d = diff(&a, left, atom, 0);
+    assert_eq!(d.len(), 1);
+    assert_eq!(d[0].path, [1]);
+}
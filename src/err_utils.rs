@@ -1,8 +1,31 @@
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::fmt;
+
 use crate::allocator::Allocator;
-use crate::reduction::EvalErr;
+use crate::cost::Cost;
+use crate::node::Node;
+use crate::reduction::{ErrKind, ErrMsg, EvalErr};
+use crate::serialize::node_to_bytes_limit;
+use crate::tree_hash::tree_hash;
+
+// the number of bytes of the offending node's serialization an ErrReport
+// keeps
+const ERR_SNIPPET_LEN: usize = 256;
+
+pub fn err<T, P>(node: P, msg: impl Into<ErrMsg>) -> Result<T, EvalErr<P>> {
+    Err(EvalErr(node, msg.into(), ErrKind::Eval))
+}
+
+pub fn limit_err<T, P>(node: P, msg: impl Into<ErrMsg>) -> Result<T, EvalErr<P>> {
+    Err(EvalErr(node, msg.into(), ErrKind::LimitExceeded))
+}
 
-pub fn err<T, P>(node: P, msg: &str) -> Result<T, EvalErr<P>> {
-    Err(EvalErr(node, msg.into()))
+pub fn oom_err<T, P>(node: P) -> Result<T, EvalErr<P>> {
+    Err(EvalErr(node, "out of memory".into(), ErrKind::OutOfMemory))
 }
 
 // TODO: if we pass in A::Ptr instead of A::AtomBuf, we don't have to allocate a
@@ -11,9 +34,245 @@ pub fn err<T, P>(node: P, msg: &str) -> Result<T, EvalErr<P>> {
 pub fn u8_err<A: Allocator, T>(
     allocator: &mut A,
     o: &A::AtomBuf,
-    msg: &str,
+    msg: &'static str,
 ) -> Result<T, EvalErr<A::Ptr>> {
     let op = allocator.buf(o);
     let buf = op.to_vec();
     err(allocator.new_atom(&buf)?, msg)
 }
+
+// the first few bytes of the canonical serialization of an error's node. It
+// doesn't borrow the allocator, so it can outlive it and end up in a log
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrSnippet {
+    pub bytes: Vec<u8>,
+    pub truncated: bool,
+}
+
+impl fmt::Display for ErrSnippet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(&self.bytes))?;
+        if self.truncated {
+            write!(f, "...")?;
+        }
+        Ok(())
+    }
+}
+
+fn err_snippet<A: Allocator>(allocator: &A, node: &A::Ptr, max_len: usize) -> ErrSnippet {
+    match node_to_bytes_limit(&Node::new(allocator, node.clone()), max_len) {
+        Ok((bytes, truncated)) => ErrSnippet { bytes, truncated },
+        // this can only happen for atoms too large to serialize at all
+        Err(_) => ErrSnippet {
+            bytes: Vec::new(),
+            truncated: true,
+        },
+    }
+}
+
+// A failed evaluation in a form that can be shipped elsewhere: the kind and
+// message of the error, a snippet of the offending node, the cost spent until
+// the failure and the tree hashes of the active function frames (outermost
+// first)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ErrReport {
+    pub kind: ErrKind,
+    pub msg: String,
+    pub node: ErrSnippet,
+    pub cost: Cost,
+    pub backtrace: Vec<[u8; 32]>,
+}
+
+// version byte of the to_bytes() format
+const REPORT_VERSION: u8 = 1;
+
+impl ErrReport {
+    pub fn new<A: Allocator>(
+        allocator: &A,
+        err: &EvalErr<A::Ptr>,
+        cost: Cost,
+        frames: &[A::Ptr],
+    ) -> Self {
+        ErrReport {
+            kind: err.2,
+            msg: err.1.to_string(),
+            node: err_snippet(allocator, &err.0, ERR_SNIPPET_LEN),
+            cost,
+            backtrace: frames
+                .iter()
+                .map(|f| tree_hash(&Node::new(allocator, f.clone())))
+                .collect(),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let backtrace: Vec<String> = self
+            .backtrace
+            .iter()
+            .map(|h| format!("\"{}\"", hex::encode(h)))
+            .collect();
+        format!(
+            "{{\"kind\":\"{}\",\"msg\":{},\"node\":\"{}\",\"node_truncated\":{},\"cost\":{},\"backtrace\":[{}]}}",
+            self.kind.as_str(),
+            json_string(&self.msg),
+            hex::encode(&self.node.bytes),
+            self.node.truncated,
+            self.cost,
+            backtrace.join(",")
+        )
+    }
+
+    // a compact binary form. All integers are big endian:
+    // version (u8), kind (u8), cost (u64), message length (u32), message,
+    // truncated flag (u8), node length (u32), node, frame count (u32), hashes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut ret = vec![
+            REPORT_VERSION,
+            match self.kind {
+                ErrKind::Eval => 0,
+                ErrKind::LimitExceeded => 1,
+                ErrKind::OutOfMemory => 2,
+            },
+        ];
+        ret.extend_from_slice(&self.cost.get().to_be_bytes());
+        ret.extend_from_slice(&(self.msg.len() as u32).to_be_bytes());
+        ret.extend_from_slice(self.msg.as_bytes());
+        ret.push(self.node.truncated as u8);
+        ret.extend_from_slice(&(self.node.bytes.len() as u32).to_be_bytes());
+        ret.extend_from_slice(&self.node.bytes);
+        ret.extend_from_slice(&(self.backtrace.len() as u32).to_be_bytes());
+        for h in &self.backtrace {
+            ret.extend_from_slice(h);
+        }
+        ret
+    }
+
+    pub fn from_bytes(buf: &[u8]) -> Option<Self> {
+        let mut r = ByteReader(buf);
+        if r.take(1)?[0] != REPORT_VERSION {
+            return None;
+        }
+        let kind = match r.take(1)?[0] {
+            0 => ErrKind::Eval,
+            1 => ErrKind::LimitExceeded,
+            2 => ErrKind::OutOfMemory,
+            _ => return None,
+        };
+        let cost = Cost::new(u64::from_be_bytes(r.take(8)?.try_into().ok()?));
+        let len = r.u32()? as usize;
+        let msg = String::from_utf8(r.take(len)?.to_vec()).ok()?;
+        let truncated = r.take(1)?[0] != 0;
+        let len = r.u32()? as usize;
+        let bytes = r.take(len)?.to_vec();
+        let count = r.u32()? as usize;
+        let mut backtrace = Vec::new();
+        for _ in 0..count {
+            backtrace.push(r.take(32)?.try_into().ok()?);
+        }
+        if !r.0.is_empty() {
+            return None;
+        }
+        Some(ErrReport {
+            kind,
+            msg,
+            node: ErrSnippet { bytes, truncated },
+            cost,
+            backtrace,
+        })
+    }
+}
+
+struct ByteReader<'a>(&'a [u8]);
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.0.len() < len {
+            return None;
+        }
+        let (ret, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(ret)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.take(4)?.try_into().ok()?))
+    }
+}
+
+// quote and escape a string for inclusion in a JSON document
+pub fn json_string(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len() + 2);
+    ret.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => ret.push_str("\\\""),
+            '\\' => ret.push_str("\\\\"),
+            '\n' => ret.push_str("\\n"),
+            '\r' => ret.push_str("\\r"),
+            '\t' => ret.push_str("\\t"),
+            c if (c as u32) < 0x20 => ret.push_str(&format!("\\u{:04x}", c as u32)),
+            c => ret.push(c),
+        }
+    }
+    ret.push('"');
+    ret
+}
+
+#[test]
+fn test_err_report() {
+    use crate::int_allocator::IntAllocator;
+
+    let mut a = IntAllocator::new();
+    let atom = a.new_atom(&[1, 2, 3]).unwrap();
+    let frame = a.one();
+    let e = EvalErr::new(atom, "bad \"thing\"\n");
+    let report = ErrReport::new(&a, &e, Cost::new(1234), &[frame]);
+    assert_eq!(
+        report.to_json(),
+        "{\"kind\":\"eval\",\"msg\":\"bad \\\"thing\\\"\\n\",\"node\":\"83010203\",\"node_truncated\":false,\"cost\":1234,\"backtrace\":[\"9dcf97a184f32623d11a73124ceb99a5709b083721e878a16d78f596718ba7b2\"]}"
+    );
+
+    let bytes = report.to_bytes();
+    assert_eq!(ErrReport::from_bytes(&bytes), Some(report));
+
+    // truncated and trailing garbage are rejected
+    assert_eq!(ErrReport::from_bytes(&bytes[..bytes.len() - 1]), None);
+    let mut longer = bytes;
+    longer.push(0);
+    assert_eq!(ErrReport::from_bytes(&longer), None);
+}
+
+#[test]
+fn test_err_snippet() {
+    use crate::int_allocator::IntAllocator;
+
+    let mut a = IntAllocator::new();
+    let atom = a.new_atom(&[1, 2, 3]).unwrap();
+    let pair = a.new_pair(atom, a.null()).unwrap();
+
+    let s = err_snippet(&a, &pair, 100);
+    assert_eq!(s.bytes, &[0xff, 0x83, 1, 2, 3, 0x80]);
+    assert!(!s.truncated);
+    assert_eq!(s.to_string(), "ff8301020380");
+
+    // exactly fits
+    let s = err_snippet(&a, &pair, 6);
+    assert!(!s.truncated);
+
+    let s = err_snippet(&a, &pair, 3);
+    assert_eq!(s.bytes, &[0xff, 0x83, 1]);
+    assert!(s.truncated);
+    assert_eq!(s.to_string(), "ff8301...");
+
+    // a large atom is cut short without being serialized in full
+    let big = a.new_atom(&[0x55; 100000]).unwrap();
+    let s = err_snippet(&a, &big, 8);
+    assert_eq!(s.bytes.len(), 8);
+    assert!(s.truncated);
+
+    // a report keeps one, so the node can be logged once the allocator is gone
+    let e = EvalErr::new(big, "clvm raise");
+    let report = ErrReport::new(&a, &e, Cost::ZERO, &[]);
+    assert_eq!(report.node.bytes.len(), ERR_SNIPPET_LEN);
+    assert!(report.node.truncated);
+}
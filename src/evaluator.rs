@@ -0,0 +1,234 @@
+// A long lived evaluator, for servers that run many programs. It owns one
+// allocator, reused for every call: whatever a call allocates is rolled back
+// when it returns, so memory is only allocated as the arena grows to fit the
+// largest program seen. Programs loaded with load_program() (e.g. the
+// standard puzzles) and atoms from intern_atom() live below that rollback
+// point, so they're deserialized once and stay valid for the evaluator's
+// lifetime
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::allocator::Allocator;
+use crate::cost::Cost;
+use crate::int_allocator::{Checkpoint, IntAllocator};
+use crate::node::Node;
+use crate::op_table::{Dialect, OpTable};
+use crate::reduction::EvalErr;
+use crate::run_program::run_program;
+use crate::serialize::{node_from_bytes, node_to_bytes, SerializeError};
+use crate::tree_hash::tree_hash;
+
+#[derive(Debug, PartialEq)]
+pub struct Evaluation {
+    pub cost: Cost,
+    // serialized, since the result itself is rolled back with the rest
+    pub result: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum EvaluatorError {
+    // the program or environment isn't a serialized tree
+    Deserialize(String),
+    // evaluation failed on this (serialized) node
+    Eval { node: Vec<u8>, msg: String },
+}
+
+impl fmt::Display for EvaluatorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Deserialize(msg) => write!(f, "deserialization failed: {}", msg),
+            Self::Eval { node, msg } => write!(f, "{}: {}", msg, hex::encode(node)),
+        }
+    }
+}
+
+impl std::error::Error for EvaluatorError {}
+
+impl From<SerializeError> for EvaluatorError {
+    fn from(e: SerializeError) -> Self {
+        Self::Deserialize(e.to_string())
+    }
+}
+
+pub struct Evaluator {
+    allocator: IntAllocator,
+    dialect: Dialect,
+    // the operators, and so the cost of each one
+    operators: OpTable<IntAllocator>,
+    // everything allocated before this is kept between calls
+    base: Checkpoint,
+    // loaded programs, by their serialized form
+    programs: HashMap<Vec<u8>, i32>,
+    atoms: HashMap<Vec<u8>, i32>,
+    // tree hashes of kept nodes
+    hashes: HashMap<i32, [u8; 32]>,
+}
+
+impl Evaluator {
+    pub fn new(dialect: Dialect) -> Self {
+        let allocator = IntAllocator::new();
+        let base = allocator.checkpoint();
+        Evaluator {
+            allocator,
+            dialect,
+            operators: dialect.operators(),
+            base,
+            programs: HashMap::new(),
+            atoms: HashMap::new(),
+            hashes: HashMap::new(),
+        }
+    }
+
+    // replaces the dialect's operators, e.g. with ones that cost differently
+    pub fn with_operators(mut self, operators: OpTable<IntAllocator>) -> Self {
+        self.operators = operators;
+        self
+    }
+
+    pub fn dialect(&self) -> &Dialect {
+        &self.dialect
+    }
+
+    // the kept nodes, i.e. those returned by load_program() and intern_atom()
+    pub fn allocator(&self) -> &IntAllocator {
+        &self.allocator
+    }
+
+    // deserializes a program to keep, or returns the one loaded from the same
+    // bytes before. evaluate() uses it for these bytes from now on
+    pub fn load_program(&mut self, program: &[u8]) -> Result<i32, EvaluatorError> {
+        if let Some(node) = self.programs.get(program) {
+            return Ok(*node);
+        }
+        let node = node_from_bytes(&mut self.allocator, program)?;
+        self.base = self.allocator.checkpoint();
+        self.programs.insert(program.to_vec(), node);
+        Ok(node)
+    }
+
+    // an atom to keep, allocated once per distinct value
+    pub fn intern_atom(&mut self, buf: &[u8]) -> Result<i32, EvalErr<i32>> {
+        if let Some(node) = self.atoms.get(buf) {
+            return Ok(*node);
+        }
+        let node = self.allocator.new_atom(buf)?;
+        self.base = self.allocator.checkpoint();
+        self.atoms.insert(buf.to_vec(), node);
+        Ok(node)
+    }
+
+    // the tree hash of a kept node, computed once
+    pub fn tree_hash(&mut self, node: i32) -> [u8; 32] {
+        let allocator = &self.allocator;
+        *self
+            .hashes
+            .entry(node)
+            .or_insert_with(|| tree_hash(&Node::new(allocator, node)))
+    }
+
+    // runs a serialized program on a serialized environment. A program that
+    // was loaded isn't deserialized again. Everything allocated is rolled
+    // back before returning
+    pub fn evaluate(
+        &mut self,
+        program: &[u8],
+        env: &[u8],
+        max_cost: Cost,
+    ) -> Result<Evaluation, EvaluatorError> {
+        let r = self.evaluate_inner(program, env, max_cost);
+        // nothing else rolls the allocator back, so base is never stale
+        self.allocator
+            .restore_checkpoint(&mut self.base)
+            .expect("base checkpoint is current");
+        r
+    }
+
+    fn evaluate_inner(
+        &mut self,
+        program: &[u8],
+        env: &[u8],
+        max_cost: Cost,
+    ) -> Result<Evaluation, EvaluatorError> {
+        let a = &mut self.allocator;
+        let program = match self.programs.get(program) {
+            Some(node) => *node,
+            None => node_from_bytes(a, program)?,
+        };
+        let env = node_from_bytes(a, env)?;
+        let r = run_program(
+            a,
+            &program,
+            &env,
+            self.dialect.quote_kw,
+            self.dialect.apply_kw,
+            max_cost,
+            Box::new(self.operators.clone()),
+            None,
+        );
+        match r {
+            Ok(r) => Ok(Evaluation {
+                cost: r.0,
+                result: node_to_bytes(&Node::new(a, r.1)).unwrap(),
+            }),
+            Err(e) => Err(EvaluatorError::Eval {
+                node: node_to_bytes(&Node::new(a, e.0)).unwrap(),
+                msg: e.1.to_string(),
+            }),
+        }
+    }
+}
+
+#[test]
+fn test_evaluator() {
+    use crate::op_table::CHIA_DIALECT;
+
+    // what the allocator holds
+    let held = |e: &Evaluator| {
+        let stats = e.allocator().stats();
+        (stats.pairs, stats.atoms, stats.atom_bytes)
+    };
+
+    let mut e = Evaluator::new(CHIA_DIALECT);
+    // (+ 2 5) with (3 4)
+    let program = hex::decode("ff10ff02ff0580").unwrap();
+    let env = hex::decode("ff03ff0480").unwrap();
+    let r = e.evaluate(&program, &env, Cost::ZERO).unwrap();
+    assert_eq!(r.result, [0x07]);
+    // nothing is left behind
+    let empty = (0, 0, 0);
+    assert_eq!(held(&e), empty);
+
+    // a loaded program is kept, and gives the same results
+    let node = e.load_program(&program).unwrap();
+    assert_eq!(e.load_program(&program).unwrap(), node);
+    let kept = held(&e);
+    assert_ne!(kept, empty);
+    assert_eq!(e.evaluate(&program, &env, Cost::ZERO).unwrap(), r);
+    assert_eq!(held(&e), kept);
+    assert_eq!(
+        e.tree_hash(node),
+        tree_hash(&Node::new(e.allocator(), node))
+    );
+
+    let atom = e.intern_atom(b"foobar").unwrap();
+    assert_eq!(e.intern_atom(b"foobar").unwrap(), atom);
+    assert_eq!(e.allocator().atom(&atom), b"foobar");
+
+    // failures are rolled back too
+    let kept = held(&e);
+    // (x 2) raises with its arguments, (3)
+    let r = e.evaluate(&hex::decode("ff08ff0280").unwrap(), &env, Cost::ZERO);
+    assert_eq!(
+        r,
+        Err(EvaluatorError::Eval {
+            node: vec![0xff, 0x03, 0x80],
+            msg: "clvm raise".to_string()
+        })
+    );
+    assert!(matches!(
+        e.evaluate(&program, &[0xff], Cost::ZERO),
+        Err(EvaluatorError::Deserialize(_))
+    ));
+    assert_eq!(held(&e), kept);
+}
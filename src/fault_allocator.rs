@@ -0,0 +1,288 @@
+// An allocator that fails on purpose, to test the error paths of the
+// evaluator and operators. It wraps another allocator and makes new_atom(),
+// new_pair() and new_substr() fail, as if the host ran out of memory, either
+// after a number of successful allocations or at random. Like the reference
+// operators, this is only compiled with the "testing" feature
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use crate::allocator::{Allocator, SExp};
+use crate::err_utils::oom_err;
+use crate::int_allocator::IntAllocator;
+use crate::reduction::{ErrKind, EvalErr};
+use crate::reference::Rng;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FaultMode {
+    // every allocation after the first n fails
+    After(usize),
+    // each allocation fails with probability 1 / n, from a seeded generator
+    // so runs are reproducible
+    OneIn(usize, u64),
+}
+
+pub struct FaultAllocator<A: Allocator> {
+    inner: A,
+    mode: FaultMode,
+    rng: Rng,
+    allocations: usize,
+    faults: usize,
+}
+
+impl<A: Allocator> FaultAllocator<A> {
+    pub fn new(inner: A, mode: FaultMode) -> Self {
+        let seed = match mode {
+            FaultMode::OneIn(_, seed) => seed,
+            FaultMode::After(_) => 0,
+        };
+        FaultAllocator {
+            inner,
+            mode,
+            rng: Rng::new(seed),
+            allocations: 0,
+            faults: 0,
+        }
+    }
+
+    pub fn fail_after(inner: A, n: usize) -> Self {
+        Self::new(inner, FaultMode::After(n))
+    }
+
+    pub fn fail_one_in(inner: A, n: usize, seed: u64) -> Self {
+        Self::new(inner, FaultMode::OneIn(n, seed))
+    }
+
+    // the allocations that succeeded
+    pub fn allocations(&self) -> usize {
+        self.allocations
+    }
+
+    // the allocations that were made to fail
+    pub fn faults(&self) -> usize {
+        self.faults
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+
+    fn fault(&mut self) -> bool {
+        let fail = match self.mode {
+            FaultMode::After(n) => self.allocations >= n,
+            FaultMode::OneIn(n, _) => n != 0 && self.rng.below(n) == 0,
+        };
+        if fail {
+            self.faults += 1;
+        } else {
+            self.allocations += 1;
+        }
+        fail
+    }
+}
+
+impl<A: Allocator> Allocator for FaultAllocator<A> {
+    type Ptr = A::Ptr;
+    type AtomBuf = A::AtomBuf;
+
+    fn new_atom(&mut self, v: &[u8]) -> Result<Self::Ptr, EvalErr<Self::Ptr>> {
+        if self.fault() {
+            return oom_err(self.inner.null());
+        }
+        self.inner.new_atom(v)
+    }
+
+    fn new_pair(
+        &mut self,
+        first: Self::Ptr,
+        rest: Self::Ptr,
+    ) -> Result<Self::Ptr, EvalErr<Self::Ptr>> {
+        if self.fault() {
+            return oom_err(self.inner.null());
+        }
+        self.inner.new_pair(first, rest)
+    }
+
+    fn new_substr(
+        &mut self,
+        node: Self::Ptr,
+        start: u32,
+        end: u32,
+    ) -> Result<Self::Ptr, EvalErr<Self::Ptr>> {
+        if self.fault() {
+            return oom_err(self.inner.null());
+        }
+        self.inner.new_substr(node, start, end)
+    }
+
+    fn new_concat(
+        &mut self,
+        total_len: usize,
+        nodes: &[Self::AtomBuf],
+    ) -> Result<Self::Ptr, EvalErr<Self::Ptr>> {
+        if self.fault() {
+            return oom_err(self.inner.null());
+        }
+        self.inner.new_concat(total_len, nodes)
+    }
+
+    fn atom<'a>(&'a self, node: &'a Self::Ptr) -> &'a [u8] {
+        self.inner.atom(node)
+    }
+
+    fn buf<'a>(&'a self, node: &'a Self::AtomBuf) -> &'a [u8] {
+        self.inner.buf(node)
+    }
+
+    fn atom_byte(&self, node: &Self::AtomBuf) -> Option<u8> {
+        self.inner.atom_byte(node)
+    }
+
+    fn sexp(&self, node: &Self::Ptr) -> SExp<Self::Ptr, Self::AtomBuf> {
+        self.inner.sexp(node)
+    }
+
+    fn null(&self) -> Self::Ptr {
+        self.inner.null()
+    }
+
+    fn one(&self) -> Self::Ptr {
+        self.inner.one()
+    }
+
+    fn reserve(
+        &mut self,
+        atoms: usize,
+        pairs: usize,
+        atom_bytes: usize,
+    ) -> Result<(), EvalErr<Self::Ptr>> {
+        self.inner.reserve(atoms, pairs, atom_bytes)
+    }
+}
+
+// Runs `run` once with every allocation succeeding, then again with the
+// first, second, third... allocation failing, until a run doesn't need the
+// failing one. Every run that hits the failure must return an out of memory
+// error: not panic, and not report it as some other error. Returns the
+// number of runs, or a description of the first that misbehaved
+pub fn check_allocation_failures<T, F>(mut run: F) -> Result<usize, String>
+where
+    F: FnMut(&mut FaultAllocator<IntAllocator>) -> Result<T, EvalErr<i32>>,
+{
+    let mut a = FaultAllocator::fail_after(IntAllocator::new(), usize::MAX);
+    catch_unwind(AssertUnwindSafe(|| run(&mut a).map(|_| ())))
+        .map_err(|_| "panicked without any failing allocation".to_string())?
+        .ok();
+    let total = a.allocations();
+
+    for n in 0..total {
+        let mut a = FaultAllocator::fail_after(IntAllocator::new(), n);
+        let r = catch_unwind(AssertUnwindSafe(|| run(&mut a).map(|_| ())))
+            .map_err(|_| format!("panicked when allocation {} failed", n))?;
+        match r {
+            Err(e) if e.2 == ErrKind::OutOfMemory => {}
+            // the run didn't need the allocation after all, e.g. it failed
+            // on its own before getting to it
+            _ if a.faults() == 0 => {}
+            Ok(_) => return Err(format!("succeeded even though allocation {} failed", n)),
+            Err(e) => {
+                return Err(format!(
+                    "failed with \"{}\" rather than out of memory when allocation {} failed",
+                    e.1, n
+                ))
+            }
+        }
+    }
+    Ok(total + 1)
+}
+
+#[test]
+fn test_fault_allocator() {
+    let mut a = FaultAllocator::fail_after(IntAllocator::new(), 2);
+    let one = a.new_atom(&[1]).unwrap();
+    let pair = a.new_pair(one, one).unwrap();
+    let e = a.new_atom(&[2]).unwrap_err();
+    assert_eq!(e.2, ErrKind::OutOfMemory);
+    assert!(a.new_pair(pair, pair).is_err());
+    assert!(a.new_substr(one, 0, 1).is_err());
+    assert!(a.new_concat(0, &[]).is_err());
+    assert_eq!(a.allocations(), 2);
+    assert_eq!(a.faults(), 4);
+    // what was allocated stays readable
+    assert_eq!(a.atom(&one), [1]);
+    match a.sexp(&pair) {
+        SExp::Pair(f, r) => assert_eq!((f, r), (one, one)),
+        SExp::Atom(_) => panic!("expected pair"),
+    }
+
+    // failing at random is reproducible from the seed
+    let pattern = |seed| {
+        let mut a = FaultAllocator::fail_one_in(IntAllocator::new(), 4, seed);
+        (0..100)
+            .map(|_| a.new_atom(&[]).is_ok())
+            .collect::<Vec<bool>>()
+    };
+    assert_eq!(pattern(1), pattern(1));
+    assert_ne!(pattern(1), pattern(2));
+    let failures = pattern(1).iter().filter(|ok| !**ok).count();
+    assert!(failures > 5 && failures < 50);
+}
+
+#[test]
+fn test_check_allocation_failures() {
+    use crate::conformance::generate;
+    use crate::cost::Cost;
+    use crate::op_table::{op_table_for, CHIA_OPCODES};
+    use crate::run_program::run_program;
+    use crate::serialize::{node_from_bytes, SerializeError};
+
+    // the deserializer reports running out of memory as an io error
+    let parse = |a: &mut FaultAllocator<IntAllocator>, b: &[u8]| {
+        node_from_bytes(a, b).map_err(|e| match e {
+            SerializeError::Io(ref io) if io.kind() == std::io::ErrorKind::OutOfMemory => {
+                EvalErr(a.null(), "out of memory".into(), ErrKind::OutOfMemory)
+            }
+            e => EvalErr(a.null(), e.to_string().into(), ErrKind::Eval),
+        })
+    };
+
+    // every generated program, and every golden vector, fails cleanly
+    // whichever allocation runs out
+    let mut programs = Vec::new();
+    let mut rng = Rng::new(3);
+    for _ in 0..100 {
+        programs.push(generate(&mut rng));
+    }
+    let vectors = crate::vectors::load_vectors(&crate::vectors::vectors_dir()).unwrap();
+    programs.extend(vectors.into_iter().map(|v| (v.program, v.env)));
+
+    for (program, env) in &programs {
+        let r = check_allocation_failures(|a| {
+            let program = parse(a, program)?;
+            let env = parse(a, env)?;
+            run_program(
+                a,
+                &program,
+                &env,
+                1,
+                2,
+                Cost::new(100_000),
+                Box::new(op_table_for(&CHIA_OPCODES)),
+                None,
+            )
+        });
+        if let Err(e) = r {
+            panic!("{} {}: {}", hex::encode(program), hex::encode(env), e);
+        }
+    }
+
+    // a function that swallows the failure is caught
+    let r = check_allocation_failures(|a| {
+        let _ = a.new_atom(&[1]);
+        Ok::<(), EvalErr<i32>>(())
+    });
+    assert_eq!(r.unwrap_err(), "succeeded even though allocation 0 failed");
+}
@@ -0,0 +1,791 @@
+// A C ABI for embedding the VM from other languages, declared in
+// include/clvm.h. Nodes are referred to by i32 handles into an allocator the
+// caller owns, and byte buffers returned to the caller are freed with
+// clvm_buffer_free(). Every function returns one of the CLVM_* codes, and on
+// failure the details can be retrieved with clvm_last_error(),
+// clvm_last_error_code() and clvm_last_error_node()
+
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::io;
+use std::os::raw::{c_char, c_void};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use crate::allocator::Allocator;
+use crate::cost::Cost;
+use crate::int_allocator::IntAllocator;
+use crate::node::Node;
+use crate::op_table::{op_table_for, HostOpTable, HostOperators, CHIA_OPCODES};
+use crate::reduction::{ErrKind, EvalErr, Reduction, Response};
+use crate::run_program::run_program;
+use crate::serialize::{node_from_bytes, node_to_bytes, IncrementalParser, SerializeError};
+use crate::tree_hash::tree_hash;
+
+// These values are part of the ABI. New codes may be added, but existing ones
+// never change meaning
+pub const CLVM_OK: i32 = 0;
+// a null pointer, or a node handle not from this allocator
+pub const CLVM_ERR_INVALID_ARG: i32 = 1;
+// the serialized input is truncated or malformed
+pub const CLVM_ERR_SERIALIZATION: i32 = 2;
+// the program failed, for any reason not covered by a more specific code
+pub const CLVM_ERR_EVAL: i32 = 3;
+// a panic was caught at the boundary
+pub const CLVM_ERR_INTERNAL: i32 = 4;
+// the program needed more than max_cost
+pub const CLVM_ERR_COST_EXCEEDED: i32 = 5;
+// the program raised an exception (operator x). clvm_last_error_node() is the
+// list of arguments it raised with
+pub const CLVM_ERR_RAISE: i32 = 6;
+// a limit of the allocator, or of the serialization format, was exceeded
+pub const CLVM_ERR_LIMIT: i32 = 7;
+// the host failed to allocate memory
+pub const CLVM_ERR_OUT_OF_MEMORY: i32 = 8;
+
+// returned by a ClvmOperatorFn for an opcode it doesn't implement
+pub const CLVM_OP_UNHANDLED: i32 = -1;
+
+#[repr(transparent)]
+pub struct ClvmAllocator {
+    a: IntAllocator,
+}
+
+// an IncrementalParser. It's poisoned by a failed feed, since it can't
+// continue after an error
+pub struct ClvmParser {
+    p: IncrementalParser<i32>,
+    failed: bool,
+}
+
+#[repr(C)]
+pub struct ClvmBuffer {
+    pub data: *mut u8,
+    pub len: usize,
+}
+
+// the last failure on a thread. code is CLVM_OK until a call fails
+#[derive(Default)]
+struct LastError {
+    code: i32,
+    msg: CString,
+    // the node an evaluation error refers to
+    node: Option<i32>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<LastError> = RefCell::new(LastError::default());
+}
+
+fn set_last_error(code: i32, msg: &str, node: Option<i32>) {
+    // interior nul bytes can't be represented, and never appear in our
+    // messages anyway
+    let msg = CString::new(msg.replace('\0', " ")).unwrap();
+    LAST_ERROR.with(|e| *e.borrow_mut() = LastError { code, msg, node });
+}
+
+fn fail(code: i32, msg: &str) -> i32 {
+    set_last_error(code, msg, None);
+    code
+}
+
+fn fail_eval(e: &EvalErr<i32>) -> i32 {
+    let code = match e.2 {
+        ErrKind::LimitExceeded => CLVM_ERR_LIMIT,
+        ErrKind::OutOfMemory => CLVM_ERR_OUT_OF_MEMORY,
+        ErrKind::Eval if e.1 == "cost exceeded" => CLVM_ERR_COST_EXCEEDED,
+        ErrKind::Eval if e.1 == "clvm raise" => CLVM_ERR_RAISE,
+        ErrKind::Eval => CLVM_ERR_EVAL,
+    };
+    set_last_error(code, &e.1.to_string(), Some(e.0));
+    code
+}
+
+fn fail_serialize(e: &SerializeError) -> i32 {
+    let code = match e {
+        SerializeError::LimitExceeded(_) => CLVM_ERR_LIMIT,
+        SerializeError::Io(e) if e.kind() == io::ErrorKind::OutOfMemory => CLVM_ERR_OUT_OF_MEMORY,
+        _ => CLVM_ERR_SERIALIZATION,
+    };
+    fail(code, &e.to_string())
+}
+
+// run f, turning a panic into CLVM_ERR_INTERNAL rather than unwinding into
+// the caller
+fn guard<F: FnOnce() -> i32>(f: F) -> i32 {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(code) => code,
+        Err(_) => fail(CLVM_ERR_INTERNAL, "internal error"),
+    }
+}
+
+fn buffer_from_vec(v: Vec<u8>) -> ClvmBuffer {
+    let mut v = v.into_boxed_slice();
+    let buf = ClvmBuffer {
+        data: v.as_mut_ptr(),
+        len: v.len(),
+    };
+    std::mem::forget(v);
+    buf
+}
+
+fn checked_node(a: &ClvmAllocator, node: i32) -> Result<i32, i32> {
+    if a.a.contains(node) {
+        Ok(node)
+    } else {
+        Err(fail(CLVM_ERR_INVALID_ARG, "invalid node handle"))
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn clvm_allocator_new() -> *mut ClvmAllocator {
+    Box::into_raw(Box::new(ClvmAllocator {
+        a: IntAllocator::new(),
+    }))
+}
+
+/// # Safety
+/// a must come from clvm_allocator_new() and not be used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn clvm_allocator_free(a: *mut ClvmAllocator) {
+    if !a.is_null() {
+        drop(Box::from_raw(a));
+    }
+}
+
+// invalidates every node handle of the allocator, but keeps its memory for
+// reuse
+/// # Safety
+/// a must come from clvm_allocator_new()
+#[no_mangle]
+pub unsafe extern "C" fn clvm_allocator_reset(a: *mut ClvmAllocator) -> i32 {
+    match a.as_mut() {
+        Some(a) => {
+            a.a.reset();
+            CLVM_OK
+        }
+        None => fail(CLVM_ERR_INVALID_ARG, "null allocator"),
+    }
+}
+
+/// # Safety
+/// buf must have been returned by this library, and not freed already
+#[no_mangle]
+pub unsafe extern "C" fn clvm_buffer_free(buf: ClvmBuffer) {
+    if !buf.data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+            buf.data, buf.len,
+        )));
+    }
+}
+
+// the message of the last failed call on this thread, or null. The pointer
+// is valid until the next failing call on the same thread
+#[no_mangle]
+pub extern "C" fn clvm_last_error() -> *const c_char {
+    LAST_ERROR.with(|e| {
+        let e = e.borrow();
+        if e.code == CLVM_OK {
+            ptr::null()
+        } else {
+            e.msg.as_ptr()
+        }
+    })
+}
+
+// the code the last failed call on this thread returned, or CLVM_OK
+#[no_mangle]
+pub extern "C" fn clvm_last_error_code() -> i32 {
+    LAST_ERROR.with(|e| e.borrow().code)
+}
+
+// the node the last failed call on this thread refers to, if it was an
+// evaluation error. It belongs to the allocator that call used
+/// # Safety
+/// out_node must point to a writable i32
+#[no_mangle]
+pub unsafe extern "C" fn clvm_last_error_node(out_node: *mut i32) -> i32 {
+    if out_node.is_null() {
+        return fail(CLVM_ERR_INVALID_ARG, "null pointer");
+    }
+    match LAST_ERROR.with(|e| e.borrow().node) {
+        Some(node) => {
+            *out_node = node;
+            CLVM_OK
+        }
+        None => CLVM_ERR_INVALID_ARG,
+    }
+}
+
+/// # Safety
+/// a must come from clvm_allocator_new(), data must point to len readable
+/// bytes and out_node to a writable i32
+#[no_mangle]
+pub unsafe extern "C" fn clvm_deserialize(
+    a: *mut ClvmAllocator,
+    data: *const u8,
+    len: usize,
+    out_node: *mut i32,
+) -> i32 {
+    guard(|| {
+        let a = match a.as_mut() {
+            Some(a) => a,
+            None => return fail(CLVM_ERR_INVALID_ARG, "null allocator"),
+        };
+        if (data.is_null() && len > 0) || out_node.is_null() {
+            return fail(CLVM_ERR_INVALID_ARG, "null pointer");
+        }
+        let blob = if len == 0 {
+            &[]
+        } else {
+            slice::from_raw_parts(data, len)
+        };
+        match node_from_bytes(&mut a.a, blob) {
+            Ok(node) => {
+                *out_node = node;
+                CLVM_OK
+            }
+            Err(e) => fail_serialize(&e),
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn clvm_parser_new() -> *mut ClvmParser {
+    Box::into_raw(Box::new(ClvmParser {
+        p: IncrementalParser::new(),
+        failed: false,
+    }))
+}
+
+/// # Safety
+/// p must come from clvm_parser_new() and not be used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn clvm_parser_free(p: *mut ClvmParser) {
+    if !p.is_null() {
+        drop(Box::from_raw(p));
+    }
+}
+
+// parses as much of data as possible into nodes of a, and writes how many
+// bytes of it were used to out_used. That's all of them unless the node was
+// completed before the end of data. Every call for a parser must use the same
+// allocator, and it must not be reset in between
+/// # Safety
+/// p must come from clvm_parser_new(), a from clvm_allocator_new(), data
+/// must point to len readable bytes and out_used to a writable size_t
+#[no_mangle]
+pub unsafe extern "C" fn clvm_parser_feed(
+    p: *mut ClvmParser,
+    a: *mut ClvmAllocator,
+    data: *const u8,
+    len: usize,
+    out_used: *mut usize,
+) -> i32 {
+    guard(|| {
+        let (p, a) = match (p.as_mut(), a.as_mut()) {
+            (Some(p), Some(a)) => (p, a),
+            _ => return fail(CLVM_ERR_INVALID_ARG, "null parser or allocator"),
+        };
+        if (data.is_null() && len > 0) || out_used.is_null() {
+            return fail(CLVM_ERR_INVALID_ARG, "null pointer");
+        }
+        if p.failed {
+            return fail(CLVM_ERR_INVALID_ARG, "parser failed earlier");
+        }
+        let chunk = if len == 0 {
+            &[]
+        } else {
+            slice::from_raw_parts(data, len)
+        };
+        match p.p.feed(&mut a.a, chunk) {
+            Ok(used) => {
+                *out_used = used;
+                CLVM_OK
+            }
+            Err(e) => {
+                p.failed = true;
+                fail_serialize(&e)
+            }
+        }
+    })
+}
+
+// 1 once the parser has read a complete node, otherwise 0
+/// # Safety
+/// p must come from clvm_parser_new()
+#[no_mangle]
+pub unsafe extern "C" fn clvm_parser_is_complete(p: *const ClvmParser) -> i32 {
+    match p.as_ref() {
+        Some(p) => p.p.is_complete() as i32,
+        None => 0,
+    }
+}
+
+/// # Safety
+/// p must come from clvm_parser_new() and out_node must point to a writable
+/// i32
+#[no_mangle]
+pub unsafe extern "C" fn clvm_parser_node(p: *const ClvmParser, out_node: *mut i32) -> i32 {
+    let p = match p.as_ref() {
+        Some(p) => p,
+        None => return fail(CLVM_ERR_INVALID_ARG, "null parser"),
+    };
+    if out_node.is_null() {
+        return fail(CLVM_ERR_INVALID_ARG, "null pointer");
+    }
+    match p.p.node() {
+        Some(node) => {
+            *out_node = node;
+            CLVM_OK
+        }
+        None => fail(CLVM_ERR_INVALID_ARG, "incomplete node"),
+    }
+}
+
+/// # Safety
+/// a must come from clvm_allocator_new() and out must point to a writable
+/// ClvmBuffer
+#[no_mangle]
+pub unsafe extern "C" fn clvm_serialize(
+    a: *const ClvmAllocator,
+    node: i32,
+    out: *mut ClvmBuffer,
+) -> i32 {
+    guard(|| {
+        let a = match a.as_ref() {
+            Some(a) => a,
+            None => return fail(CLVM_ERR_INVALID_ARG, "null allocator"),
+        };
+        if out.is_null() {
+            return fail(CLVM_ERR_INVALID_ARG, "null pointer");
+        }
+        let node = match checked_node(a, node) {
+            Ok(node) => node,
+            Err(code) => return code,
+        };
+        match node_to_bytes(&Node::new(&a.a, node)) {
+            Ok(blob) => {
+                *out = buffer_from_vec(blob);
+                CLVM_OK
+            }
+            Err(e) => fail_serialize(&e),
+        }
+    })
+}
+
+// runs program with args using chia's operator table. A max_cost of 0 means
+// no limit
+/// # Safety
+/// a must come from clvm_allocator_new(), out_cost and out_node must point to
+/// writable values
+#[no_mangle]
+pub unsafe extern "C" fn clvm_run(
+    a: *mut ClvmAllocator,
+    program: i32,
+    args: i32,
+    max_cost: u64,
+    out_cost: *mut u64,
+    out_node: *mut i32,
+) -> i32 {
+    guard(|| {
+        let a = match a.as_mut() {
+            Some(a) => a,
+            None => return fail(CLVM_ERR_INVALID_ARG, "null allocator"),
+        };
+        if out_cost.is_null() || out_node.is_null() {
+            return fail(CLVM_ERR_INVALID_ARG, "null pointer");
+        }
+        let (program, args) = match (checked_node(a, program), checked_node(a, args)) {
+            (Ok(program), Ok(args)) => (program, args),
+            (Err(code), _) | (_, Err(code)) => return code,
+        };
+        let table = op_table_for(&CHIA_OPCODES);
+        match run_program(
+            &mut a.a,
+            &program,
+            &args,
+            1,
+            2,
+            Cost::new(max_cost),
+            Box::new(table),
+            None,
+        ) {
+            Ok(r) => {
+                *out_cost = r.0.get();
+                *out_node = r.1;
+                CLVM_OK
+            }
+            Err(e) => fail_eval(&e),
+        }
+    })
+}
+
+// an operator implemented by the host. It's called with the opcode and the
+// argument list of every operator chia's table doesn't implement, and returns
+// CLVM_OK after setting *out_cost and *out_node, CLVM_OP_UNHANDLED to leave
+// the opcode to the unknown operator rules, CLVM_ERR_RAISE to raise *out_node
+// or any other code to fail the program
+pub type ClvmOperatorFn = unsafe extern "C" fn(
+    ctx: *mut c_void,
+    a: *mut ClvmAllocator,
+    op: *const u8,
+    op_len: usize,
+    args: i32,
+    max_cost: u64,
+    out_cost: *mut u64,
+    out_node: *mut i32,
+) -> i32;
+
+#[derive(Clone)]
+struct ClvmOperators {
+    f: ClvmOperatorFn,
+    ctx: *mut c_void,
+}
+
+impl HostOperators<IntAllocator> for ClvmOperators {
+    fn op(
+        &self,
+        allocator: &mut IntAllocator,
+        op: <IntAllocator as Allocator>::AtomBuf,
+        args: &i32,
+        max_cost: Cost,
+    ) -> Option<Response<i32>> {
+        let op = allocator.buf(&op).to_vec();
+        let mut cost: u64 = 0;
+        let mut node: i32 = *args;
+        let code = unsafe {
+            (self.f)(
+                self.ctx,
+                allocator as *mut IntAllocator as *mut ClvmAllocator,
+                op.as_ptr(),
+                op.len(),
+                *args,
+                max_cost.get(),
+                &mut cost,
+                &mut node,
+            )
+        };
+        let valid = allocator.contains(node);
+        match code {
+            CLVM_OP_UNHANDLED => None,
+            CLVM_OK if valid => Some(Ok(Reduction(Cost::new(cost), node))),
+            CLVM_ERR_RAISE if valid => Some(Err(EvalErr::new(node, "clvm raise"))),
+            _ => Some(Err(EvalErr::new(*args, "host operator failed"))),
+        }
+    }
+}
+
+// like clvm_run(), but operators chia's table doesn't implement are first
+// offered to f, which is passed ctx on every call
+/// # Safety
+/// a must come from clvm_allocator_new(), out_cost and out_node must point to
+/// writable values and f must be safe to call with ctx
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn clvm_run_with_operators(
+    a: *mut ClvmAllocator,
+    program: i32,
+    args: i32,
+    max_cost: u64,
+    f: ClvmOperatorFn,
+    ctx: *mut c_void,
+    out_cost: *mut u64,
+    out_node: *mut i32,
+) -> i32 {
+    guard(|| {
+        let a = match a.as_mut() {
+            Some(a) => a,
+            None => return fail(CLVM_ERR_INVALID_ARG, "null allocator"),
+        };
+        if out_cost.is_null() || out_node.is_null() {
+            return fail(CLVM_ERR_INVALID_ARG, "null pointer");
+        }
+        let (program, args) = match (checked_node(a, program), checked_node(a, args)) {
+            (Ok(program), Ok(args)) => (program, args),
+            (Err(code), _) | (_, Err(code)) => return code,
+        };
+        let table = HostOpTable {
+            table: op_table_for(&CHIA_OPCODES),
+            host: ClvmOperators { f, ctx },
+        };
+        match run_program(
+            &mut a.a,
+            &program,
+            &args,
+            1,
+            2,
+            Cost::new(max_cost),
+            Box::new(table),
+            None,
+        ) {
+            Ok(r) => {
+                *out_cost = r.0.get();
+                *out_node = r.1;
+                CLVM_OK
+            }
+            Err(e) => fail_eval(&e),
+        }
+    })
+}
+
+/// # Safety
+/// a must come from clvm_allocator_new() and out must point to 32 writable
+/// bytes
+#[no_mangle]
+pub unsafe extern "C" fn clvm_tree_hash(a: *const ClvmAllocator, node: i32, out: *mut u8) -> i32 {
+    guard(|| {
+        let a = match a.as_ref() {
+            Some(a) => a,
+            None => return fail(CLVM_ERR_INVALID_ARG, "null allocator"),
+        };
+        if out.is_null() {
+            return fail(CLVM_ERR_INVALID_ARG, "null pointer");
+        }
+        let node = match checked_node(a, node) {
+            Ok(node) => node,
+            Err(code) => return code,
+        };
+        let hash = tree_hash(&Node::new(&a.a, node));
+        ptr::copy_nonoverlapping(hash.as_ptr(), out, 32);
+        CLVM_OK
+    })
+}
+
+#[test]
+fn test_ffi() {
+    use std::ffi::CStr;
+
+    unsafe {
+        let a = clvm_allocator_new();
+
+        // (+ 2 5) with the environment (3 4)
+        let program = hex::decode("ff10ff02ff0580").unwrap();
+        let env = hex::decode("ff03ff0480").unwrap();
+        let mut program_node = 0;
+        let mut env_node = 0;
+        assert_eq!(
+            clvm_deserialize(a, program.as_ptr(), program.len(), &mut program_node),
+            CLVM_OK
+        );
+        assert_eq!(
+            clvm_deserialize(a, env.as_ptr(), env.len(), &mut env_node),
+            CLVM_OK
+        );
+
+        let mut cost = 0;
+        let mut result = 0;
+        assert_eq!(
+            clvm_run(a, program_node, env_node, 0, &mut cost, &mut result),
+            CLVM_OK
+        );
+        assert!(cost > 0);
+
+        let mut buf = ClvmBuffer {
+            data: ptr::null_mut(),
+            len: 0,
+        };
+        assert_eq!(clvm_serialize(a, result, &mut buf), CLVM_OK);
+        assert_eq!(slice::from_raw_parts(buf.data, buf.len), &[7]);
+        clvm_buffer_free(buf);
+
+        let mut hash = [0_u8; 32];
+        assert_eq!(clvm_tree_hash(a, result, hash.as_mut_ptr()), CLVM_OK);
+        assert_eq!(hash, tree_hash(&Node::new(&(*a).a, result)));
+
+        // errors
+        let truncated = [0xff_u8, 0x01];
+        assert_eq!(
+            clvm_deserialize(a, truncated.as_ptr(), truncated.len(), &mut result),
+            CLVM_ERR_SERIALIZATION
+        );
+        assert!(!clvm_last_error().is_null());
+
+        assert_eq!(clvm_last_error_code(), CLVM_ERR_SERIALIZATION);
+        let mut node = 0;
+        assert_eq!(clvm_last_error_node(&mut node), CLVM_ERR_INVALID_ARG);
+
+        assert_eq!(
+            clvm_run(a, env_node, env_node, 0, &mut cost, &mut result),
+            CLVM_ERR_EVAL
+        );
+        let msg = CStr::from_ptr(clvm_last_error()).to_str().unwrap();
+        assert!(!msg.is_empty());
+        assert_eq!(clvm_last_error_code(), CLVM_ERR_EVAL);
+
+        assert_eq!(
+            clvm_run(a, program_node, env_node, 1, &mut cost, &mut result),
+            CLVM_ERR_COST_EXCEEDED
+        );
+
+        // (x (q . 5))
+        let raise = hex::decode("ff08ffff010580").unwrap();
+        let mut raise_node = 0;
+        assert_eq!(
+            clvm_deserialize(a, raise.as_ptr(), raise.len(), &mut raise_node),
+            CLVM_OK
+        );
+        assert_eq!(
+            clvm_run(a, raise_node, env_node, 0, &mut cost, &mut result),
+            CLVM_ERR_RAISE
+        );
+        assert_eq!(clvm_last_error_node(&mut node), CLVM_OK);
+        let mut buf = ClvmBuffer {
+            data: ptr::null_mut(),
+            len: 0,
+        };
+        assert_eq!(clvm_serialize(a, node, &mut buf), CLVM_OK);
+        assert_eq!(slice::from_raw_parts(buf.data, buf.len), &[0xff, 5, 0x80]);
+        clvm_buffer_free(buf);
+
+        let mut buf = ClvmBuffer {
+            data: ptr::null_mut(),
+            len: 0,
+        };
+        assert_eq!(clvm_serialize(a, 1000, &mut buf), CLVM_ERR_INVALID_ARG);
+        assert!(buf.data.is_null());
+        assert_eq!(
+            clvm_run(ptr::null_mut(), 0, 0, 0, &mut cost, &mut result),
+            CLVM_ERR_INVALID_ARG
+        );
+
+        assert_eq!(clvm_allocator_reset(a), CLVM_OK);
+        assert_eq!(
+            clvm_tree_hash(a, program_node, hash.as_mut_ptr()),
+            CLVM_ERR_INVALID_ARG
+        );
+        clvm_allocator_free(a);
+    }
+}
+
+#[test]
+fn test_ffi_run_with_operators() {
+    // opcode 0x77 returns its argument list, 0x78 raises it and 0x79 fails
+    unsafe extern "C" fn host(
+        ctx: *mut c_void,
+        _a: *mut ClvmAllocator,
+        op: *const u8,
+        op_len: usize,
+        args: i32,
+        _max_cost: u64,
+        out_cost: *mut u64,
+        out_node: *mut i32,
+    ) -> i32 {
+        *(ctx as *mut u32) += 1;
+        match slice::from_raw_parts(op, op_len) {
+            [0x77] => {
+                *out_cost = 7;
+                *out_node = args;
+                CLVM_OK
+            }
+            [0x78] => {
+                *out_node = args;
+                CLVM_ERR_RAISE
+            }
+            [0x79] => CLVM_ERR_EVAL,
+            _ => CLVM_OP_UNHANDLED,
+        }
+    }
+
+    use std::ffi::CStr;
+
+    unsafe {
+        let a = clvm_allocator_new();
+        let null = (*a).a.null();
+        let mut calls: u32 = 0;
+        let ctx = &mut calls as *mut u32 as *mut c_void;
+        let run = |program: &str, cost: &mut u64, result: &mut i32| {
+            let program = hex::decode(program).unwrap();
+            let mut node = 0;
+            assert_eq!(
+                clvm_deserialize(a, program.as_ptr(), program.len(), &mut node),
+                CLVM_OK
+            );
+            clvm_run_with_operators(a, node, null, 0, host, ctx, cost, result)
+        };
+
+        let mut cost = 0;
+        let mut result = 0;
+        // (0x77 (q . 1) (q . 2))
+        assert_eq!(
+            run("ff77ffff0101ffff010280", &mut cost, &mut result),
+            CLVM_OK
+        );
+        assert_eq!(cost, 20 + 20 + 1 + 7);
+        let mut buf = ClvmBuffer {
+            data: ptr::null_mut(),
+            len: 0,
+        };
+        assert_eq!(clvm_serialize(a, result, &mut buf), CLVM_OK);
+        assert_eq!(
+            slice::from_raw_parts(buf.data, buf.len),
+            &[0xff, 1, 0xff, 2, 0x80]
+        );
+        clvm_buffer_free(buf);
+
+        // (0x78 (q . 1))
+        assert_eq!(
+            run("ff78ffff010180", &mut cost, &mut result),
+            CLVM_ERR_RAISE
+        );
+        // (0x79 (q . 1))
+        assert_eq!(run("ff79ffff010180", &mut cost, &mut result), CLVM_ERR_EVAL);
+        assert_eq!(
+            CStr::from_ptr(clvm_last_error()).to_str().unwrap(),
+            "host operator failed"
+        );
+        // an unhandled opcode falls through to the unknown operator rules
+        assert_eq!(run("ff7affff010180", &mut cost, &mut result), CLVM_OK);
+        assert_eq!(calls, 4);
+
+        clvm_allocator_free(a);
+    }
+}
+
+#[test]
+fn test_ffi_parser() {
+    unsafe {
+        let a = clvm_allocator_new();
+        let p = clvm_parser_new();
+
+        // (+ 2 5) followed by a byte that isn't part of it
+        let blob = hex::decode("ff10ff02ff058001").unwrap();
+        let mut node = 0;
+        let mut used = 0;
+        assert_eq!(clvm_parser_node(p, &mut node), CLVM_ERR_INVALID_ARG);
+        assert_eq!(clvm_parser_feed(p, a, blob.as_ptr(), 3, &mut used), CLVM_OK);
+        assert_eq!(used, 3);
+        assert_eq!(clvm_parser_is_complete(p), 0);
+        assert_eq!(
+            clvm_parser_feed(p, a, blob[3..].as_ptr(), blob.len() - 3, &mut used),
+            CLVM_OK
+        );
+        assert_eq!(used, 4);
+        assert_eq!(clvm_parser_is_complete(p), 1);
+        assert_eq!(clvm_parser_node(p, &mut node), CLVM_OK);
+
+        let mut buf = ClvmBuffer {
+            data: ptr::null_mut(),
+            len: 0,
+        };
+        assert_eq!(clvm_serialize(a, node, &mut buf), CLVM_OK);
+        assert_eq!(slice::from_raw_parts(buf.data, buf.len), &blob[..7]);
+        clvm_buffer_free(buf);
+        clvm_parser_free(p);
+
+        // a bad length prefix poisons the parser
+        let p = clvm_parser_new();
+        let bad = [0xfe_u8, 0, 0, 0, 0, 0, 0];
+        assert_eq!(
+            clvm_parser_feed(p, a, bad.as_ptr(), bad.len(), &mut used),
+            CLVM_ERR_SERIALIZATION
+        );
+        assert_eq!(
+            clvm_parser_feed(p, a, blob.as_ptr(), blob.len(), &mut used),
+            CLVM_ERR_INVALID_ARG
+        );
+        clvm_parser_free(p);
+        clvm_allocator_free(a);
+    }
+}
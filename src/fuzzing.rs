@@ -0,0 +1,148 @@
+// Generating trees from fuzzer input, for the targets in fuzz/ and for
+// downstream fuzzers. Trees are built straight into an allocator, or kept
+// serialized in an ArbitraryTree, which implements arbitrary::Arbitrary.
+//
+// Atoms are biased toward what makes programs do something: nil, opcodes,
+// small integers and paths, with arbitrary bytes now and then. The number of
+// nodes is bounded, so a fuzzer can't spend its time building huge trees
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+use crate::allocator::Allocator;
+use crate::int_allocator::IntAllocator;
+use crate::node::Node;
+use crate::serialize::{node_from_bytes, node_to_bytes};
+
+// the most nodes (atoms and pairs) a generated tree has
+pub const MAX_NODES: usize = 1000;
+
+// the longest arbitrary atom
+const MAX_ATOM_LEN: usize = 64;
+
+enum BuildOp {
+    Node,
+    Cons,
+}
+
+fn arbitrary_atom(u: &mut Unstructured<'_>) -> Result<Vec<u8>> {
+    Ok(match u.int_in_range(0..=4)? {
+        0 => Vec::new(),
+        // an opcode (or quote and apply) in chia's keyword table
+        1 => vec![u.int_in_range(1..=36)?],
+        // a small integer, or a path into the environment
+        2 => vec![u.int_in_range(0..=0x7f)?],
+        3 => {
+            let v: i16 = u.arbitrary()?;
+            v.to_be_bytes().to_vec()
+        }
+        _ => {
+            let len = u.int_in_range(0..=MAX_ATOM_LEN)?;
+            u.bytes(len)?.to_vec()
+        }
+    })
+}
+
+// a tree of at most max_nodes nodes, built from the fuzzer's input. Once the
+// input runs out, the rest of the tree is nil
+pub fn arbitrary_node<T: Allocator>(
+    u: &mut Unstructured<'_>,
+    allocator: &mut T,
+    max_nodes: usize,
+) -> Result<T::Ptr> {
+    let mut values: Vec<T::Ptr> = Vec::new();
+    let mut ops = vec![BuildOp::Node];
+    // the nodes not yet generated, but already promised to a pair
+    let mut pending: usize = 1;
+    let mut nodes: usize = 0;
+    while let Some(op) = ops.pop() {
+        match op {
+            BuildOp::Node => {
+                pending -= 1;
+                nodes += 1;
+                // a pair adds two pending nodes, which have to fit
+                if nodes + pending + 2 <= max_nodes && !u.is_empty() && u.ratio(1, 2)? {
+                    pending += 2;
+                    ops.push(BuildOp::Cons);
+                    ops.push(BuildOp::Node);
+                    ops.push(BuildOp::Node);
+                } else {
+                    let atom = if u.is_empty() {
+                        Vec::new()
+                    } else {
+                        arbitrary_atom(u)?
+                    };
+                    let node = allocator
+                        .new_atom(&atom)
+                        .map_err(|_| arbitrary::Error::IncorrectFormat)?;
+                    values.push(node);
+                }
+            }
+            BuildOp::Cons => {
+                let rest = values.pop().unwrap();
+                let first = values.pop().unwrap();
+                let pair = allocator
+                    .new_pair(first, rest)
+                    .map_err(|_| arbitrary::Error::IncorrectFormat)?;
+                values.push(pair);
+            }
+        }
+    }
+    Ok(values.pop().unwrap())
+}
+
+// a generated tree, serialized so it doesn't need an allocator to live in
+#[derive(Clone, Debug, PartialEq)]
+pub struct ArbitraryTree {
+    pub serialized: Vec<u8>,
+}
+
+impl ArbitraryTree {
+    pub fn node<T: Allocator>(&self, allocator: &mut T) -> T::Ptr {
+        node_from_bytes(allocator, &self.serialized).expect("generated trees are valid")
+    }
+}
+
+impl<'a> Arbitrary<'a> for ArbitraryTree {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let mut a = IntAllocator::new();
+        let node = arbitrary_node(u, &mut a, MAX_NODES)?;
+        let serialized =
+            node_to_bytes(&Node::new(&a, node)).map_err(|_| arbitrary::Error::IncorrectFormat)?;
+        Ok(ArbitraryTree { serialized })
+    }
+
+    fn size_hint(_depth: usize) -> (usize, Option<usize>) {
+        (0, None)
+    }
+}
+
+#[test]
+fn test_arbitrary_node() {
+    use crate::serialize::scan_node_counts;
+
+    // the same input always gives the same tree
+    let data: Vec<u8> = (0..4000_u32).map(|i| (i * 7919 % 251) as u8).collect();
+    let t1 = ArbitraryTree::arbitrary(&mut Unstructured::new(&data)).unwrap();
+    let t2 = ArbitraryTree::arbitrary(&mut Unstructured::new(&data)).unwrap();
+    assert_eq!(t1, t2);
+
+    let counts = scan_node_counts(&t1.serialized).unwrap();
+    assert!(counts.pairs > 0);
+
+    // the node limit holds, however much input there is
+    for max_nodes in &[1, 2, 3, 10, 100] {
+        let mut a = IntAllocator::new();
+        let mut u = Unstructured::new(&data);
+        let node = arbitrary_node(&mut u, &mut a, *max_nodes).unwrap();
+        let blob = node_to_bytes(&Node::new(&a, node)).unwrap();
+        let counts = scan_node_counts(&blob).unwrap();
+        assert!(counts.pairs * 2 < *max_nodes);
+    }
+
+    // no input is nil
+    let t = ArbitraryTree::arbitrary(&mut Unstructured::new(&[])).unwrap();
+    assert_eq!(t.serialized, [0x80]);
+    let mut a = IntAllocator::new();
+    let node = t.node(&mut a);
+    assert!(Node::new(&a, node).nullp());
+}
@@ -0,0 +1,237 @@
+// Running block generators. A generator is a program whose output is the
+// list of coin spends in a block. It's run with the arguments
+// (DESERIALIZE_MOD (ref ...)), where the refs are the serialized generators of
+// earlier blocks it refers to, and DESERIALIZE_MOD is a program that parses
+// serialized programs, so a generator can reuse the puzzles in them. Each spend
+// in the output is ((parent_id amount) (puzzle solution))
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::allocator::{Allocator, SExp};
+use crate::cost::Cost;
+use crate::node::Node;
+use crate::op_table::{op_table_for, CHIA_OPCODES};
+use crate::reduction::EvalErr;
+use crate::run_program::run_program;
+use crate::serialize::{node_from_bytes, SerializeError};
+
+// chialisp_deserialisation.clvm, serialized
+const DESERIALIZE_MOD_HEX: &str = concat!(
+    "ff02ffff01ff05ffff02ff3effff04ff02ffff04ff05ff8080808080ffff04ffff01ffffff81ff7fff81df81",
+    "bfffffff02ffff03ffff09ff0bffff01818080ffff01ff04ff80ffff04ff05ff808080ffff01ff02ffff03ff",
+    "ff0aff0bff1880ffff01ff02ff1affff04ff02ffff04ffff02ffff03ffff0aff0bff1c80ffff01ff02ffff03",
+    "ffff0aff0bff1480ffff01ff08ffff018c62616420656e636f64696e6780ffff01ff04ffff0effff18ffff01",
+    "1fff0b80ffff0cff05ff80ffff01018080ffff04ffff0cff05ffff010180ff80808080ff0180ffff01ff04ff",
+    "ff18ffff013fff0b80ffff04ff05ff80808080ff0180ff80808080ffff01ff04ff0bffff04ff05ff80808080",
+    "ff018080ff0180ff04ffff0cff15ff80ff0980ffff04ffff0cff15ff0980ff808080ffff04ffff04ff05ff13",
+    "80ffff04ff2bff808080ffff02ff16ffff04ff02ffff04ff09ffff04ffff02ff3effff04ff02ffff04ff15ff",
+    "80808080ff8080808080ff02ffff03ffff09ffff0cff05ff80ffff010180ff1080ffff01ff02ff2effff04ff",
+    "02ffff04ffff02ff3effff04ff02ffff04ffff0cff05ffff010180ff80808080ff80808080ffff01ff02ff12",
+    "ffff04ff02ffff04ffff0cff05ffff010180ffff04ffff0cff05ff80ffff010180ff808080808080ff0180ff",
+    "018080",
+);
+
+// a coin spend from a generator's output, as nodes
+#[derive(Clone, Debug, PartialEq)]
+pub struct Spend<P> {
+    pub parent_id: P,
+    pub amount: P,
+    pub puzzle: P,
+    pub solution: P,
+}
+
+#[derive(Debug)]
+pub enum GeneratorError<P> {
+    // the generator, or one of the refs, isn't a valid serialized program
+    Deserialize(SerializeError),
+    // running the generator failed
+    Eval(EvalErr<P>),
+    // an item of the output (given) isn't a spend
+    MalformedSpend(P),
+}
+
+impl<P> fmt::Display for GeneratorError<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Deserialize(e) => write!(f, "invalid generator: {}", e),
+            Self::Eval(e) => write!(f, "{}", e.1),
+            Self::MalformedSpend(_) => write!(f, "malformed spend"),
+        }
+    }
+}
+
+impl<P> From<EvalErr<P>> for GeneratorError<P> {
+    fn from(e: EvalErr<P>) -> Self {
+        Self::Eval(e)
+    }
+}
+
+impl<P> From<SerializeError> for GeneratorError<P> {
+    fn from(e: SerializeError) -> Self {
+        Self::Deserialize(e)
+    }
+}
+
+// the cost of running a generator, and its spends
+pub type GeneratorResult<P> = Result<(Cost, Vec<Spend<P>>), GeneratorError<P>>;
+
+pub fn deserialize_mod() -> Vec<u8> {
+    hex::decode(DESERIALIZE_MOD_HEX).unwrap()
+}
+
+// the arguments a generator is run with. Each ref is a serialized generator
+// of an earlier block
+pub fn generator_args<T: Allocator>(
+    allocator: &mut T,
+    refs: &[&[u8]],
+) -> Result<T::Ptr, GeneratorError<T::Ptr>> {
+    let mut list = allocator.null();
+    for r in refs.iter().rev() {
+        let atom = allocator.new_atom(r)?;
+        list = allocator.new_pair(atom, list)?;
+    }
+    let nul = allocator.null();
+    let list = allocator.new_pair(list, nul)?;
+    let deserializer = node_from_bytes(allocator, &deserialize_mod())?;
+    Ok(allocator.new_pair(deserializer, list)?)
+}
+
+fn first_rest<T: Allocator>(allocator: &T, node: &T::Ptr) -> Option<(T::Ptr, T::Ptr)> {
+    match allocator.sexp(node) {
+        SExp::Pair(first, rest) => Some((first, rest)),
+        SExp::Atom(_) => None,
+    }
+}
+
+// a list of exactly two items
+fn two<T: Allocator>(allocator: &T, node: &T::Ptr) -> Option<(T::Ptr, T::Ptr)> {
+    let (a, rest) = first_rest(allocator, node)?;
+    let (b, rest) = first_rest(allocator, &rest)?;
+    if Node::new(allocator, rest).nullp() {
+        Some((a, b))
+    } else {
+        None
+    }
+}
+
+// the spends in a generator's output
+pub fn parse_spends<T: Allocator>(
+    allocator: &T,
+    output: &T::Ptr,
+) -> Result<Vec<Spend<T::Ptr>>, GeneratorError<T::Ptr>> {
+    let mut spends = Vec::new();
+    let mut list = output.clone();
+    while let Some((spend, rest)) = first_rest(allocator, &list) {
+        let parsed = two(allocator, &spend).and_then(|(coin, puzzle_solution)| {
+            let (parent_id, amount) = two(allocator, &coin)?;
+            let (puzzle, solution) = two(allocator, &puzzle_solution)?;
+            Some(Spend {
+                parent_id,
+                amount,
+                puzzle,
+                solution,
+            })
+        });
+        match parsed {
+            Some(s) => spends.push(s),
+            None => return Err(GeneratorError::MalformedSpend(spend)),
+        }
+        list = rest;
+    }
+    Ok(spends)
+}
+
+// runs the serialized generator `program` with the refs it uses, with chia's
+// operators. Returns the cost and the spends
+pub fn run_generator<T: Allocator + 'static>(
+    allocator: &mut T,
+    program: &[u8],
+    refs: &[&[u8]],
+    max_cost: Cost,
+) -> GeneratorResult<T::Ptr>
+where
+    <T as Allocator>::Ptr: 'static,
+{
+    let program = node_from_bytes(allocator, program)?;
+    let args = generator_args(allocator, refs)?;
+    let r = run_program(
+        allocator,
+        &program,
+        &args,
+        1,
+        2,
+        max_cost,
+        Box::new(op_table_for(&CHIA_OPCODES)),
+        None,
+    )?;
+    Ok((r.0, parse_spends(allocator, &r.1)?))
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_run_generator() {
+    use crate::binutils::assemble;
+    use crate::int_allocator::IntAllocator;
+    use crate::serialize::node_to_bytes;
+
+    // a hex file from the workloads in benchmark/
+    let bundled = |name: &str| {
+        let path = format!("{}/benchmark/{}", env!("CARGO_MANIFEST_DIR"), name);
+        hex::decode(std::fs::read_to_string(path).unwrap().trim()).unwrap()
+    };
+    let mut a = IntAllocator::new();
+
+    // the compressed benchmark is run with the deserializer too
+    let env = node_from_bytes(&mut a, &bundled("compressed-2000.envhex")).unwrap();
+    let (deserializer, _) = first_rest(&a, &env).unwrap();
+    assert_eq!(
+        node_to_bytes(&Node::new(&a, deserializer)).unwrap(),
+        deserialize_mod()
+    );
+
+    let block = bundled("block-2000.hex");
+    let (cost, spends) = run_generator(&mut a, &block, &[], Cost::new(11000000000)).unwrap();
+    assert!(cost > Cost::ZERO);
+    assert_eq!(spends.len(), 2000);
+    assert_eq!(a.atom(&spends[1].parent_id)[31], 1);
+    assert_eq!(a.atom(&spends[1].amount), &[0x01, 0x86, 0xa0]);
+
+    // a generator that outputs the spends of its second ref, deserialized
+    let generator = assemble(&mut a, "(a 2 (c (f (r 5)) ()))").unwrap();
+    let generator = node_to_bytes(&Node::new(&a, generator)).unwrap();
+    let spends = assemble(&mut a, "(((0x0101 1000) ((q . 1) (5))))").unwrap();
+    let spends = node_to_bytes(&Node::new(&a, spends)).unwrap();
+    let (_, spends) = run_generator(&mut a, &generator, &[&[0x80], &spends], Cost::ZERO).unwrap();
+    assert_eq!(spends.len(), 1);
+    assert_eq!(a.atom(&spends[0].parent_id), &[1, 1]);
+    assert_eq!(a.atom(&spends[0].amount), &[0x03, 0xe8]);
+    assert_eq!(
+        node_to_bytes(&Node::new(&a, spends[0].solution)).unwrap(),
+        [0xff, 0x05, 0x80]
+    );
+
+    // the generator doesn't parse, fails, or doesn't output spends
+    assert!(matches!(
+        run_generator(&mut a, &[0xff], &[], Cost::ZERO),
+        Err(GeneratorError::Deserialize(_))
+    ));
+    // (x)
+    assert!(matches!(
+        run_generator(&mut a, &[0xff, 0x08, 0x80], &[], Cost::ZERO),
+        Err(GeneratorError::Eval(_))
+    ));
+    // (q 1)
+    assert!(matches!(
+        run_generator(&mut a, &[0xff, 0x01, 0xff, 0x01, 0x80], &[], Cost::ZERO),
+        Err(GeneratorError::MalformedSpend(_))
+    ));
+    // (q)
+    assert_eq!(
+        run_generator(&mut a, &[0xff, 0x01, 0x80], &[], Cost::ZERO)
+            .unwrap()
+            .1,
+        vec![]
+    );
+}
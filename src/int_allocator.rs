@@ -1,75 +1,795 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::TryInto;
+use core::fmt::Debug;
+use core::hash::Hash;
+
 use crate::allocator::{Allocator, SExp};
-use crate::err_utils::err;
+use crate::err_utils::{err, limit_err, oom_err};
 use crate::reduction::EvalErr;
+use crate::sync_allocator::FrozenAllocator;
 
-#[derive(Clone, Copy)]
-pub struct IntAtomBuf {
-    start: u32,
-    end: u32,
+// the width of the offsets and node pointers of an allocator. u32 keeps nodes
+// small, and is what IntAllocator uses. u64 lifts the limits on the number of
+// nodes and bytes of atoms, for embedders that need the headroom more than
+// the memory
+pub trait IntIndex: Copy + Debug + 'static {
+    // pairs are non-negative, atoms negative, like the i32 of IntAllocator
+    type Ptr: Copy + Debug + Eq + Hash;
+    // an atom's entry in the atom table: its start and end offsets into the
+    // atom buffer, or the atom itself if it fits
+    type Slot: Copy + Default + AsRef<[u8]> + AsMut<[u8]>;
+
+    // the most bytes of atoms, and the most atoms or pairs, an allocator can
+    // hold
+    const MAX_BYTES: usize;
+    const MAX_NODES: usize;
+
+    fn from_usize(v: usize) -> Self;
+    fn to_usize(self) -> usize;
+
+    fn pair_ptr(idx: usize) -> Self::Ptr;
+    fn atom_ptr(idx: usize) -> Self::Ptr;
+    fn is_atom(node: Self::Ptr) -> bool;
+    // the index into the pair vectors, or for atoms, the atom vectors
+    fn index(node: Self::Ptr) -> usize;
+
+    fn range_slot(start: usize, end: usize) -> Self::Slot;
+    fn slot_range(slot: &Self::Slot) -> (usize, usize);
+}
+
+macro_rules! int_index {
+    ($offset:ty, $ptr:ty, $width:expr) => {
+        impl IntIndex for $offset {
+            type Ptr = $ptr;
+            type Slot = [u8; $width * 2];
+
+            const MAX_BYTES: usize = <$offset>::MAX as usize;
+            const MAX_NODES: usize = <$ptr>::MAX as usize;
+
+            fn from_usize(v: usize) -> Self {
+                v as $offset
+            }
+
+            fn to_usize(self) -> usize {
+                self as usize
+            }
+
+            fn pair_ptr(idx: usize) -> $ptr {
+                idx as $ptr
+            }
+
+            fn atom_ptr(idx: usize) -> $ptr {
+                -(idx as $ptr) - 1
+            }
+
+            fn is_atom(node: $ptr) -> bool {
+                node < 0
+            }
+
+            fn index(node: $ptr) -> usize {
+                if node >= 0 {
+                    node as usize
+                } else {
+                    // node + 1 can't overflow, unlike -node
+                    -(node + 1) as usize
+                }
+            }
+
+            fn range_slot(start: usize, end: usize) -> Self::Slot {
+                let mut slot = [0; $width * 2];
+                slot[..$width].copy_from_slice(&(start as $offset).to_ne_bytes());
+                slot[$width..].copy_from_slice(&(end as $offset).to_ne_bytes());
+                slot
+            }
+
+            fn slot_range(slot: &Self::Slot) -> (usize, usize) {
+                let (start, end) = slot.split_at($width);
+                (
+                    <$offset>::from_ne_bytes(start.try_into().unwrap()) as usize,
+                    <$offset>::from_ne_bytes(end.try_into().unwrap()) as usize,
+                )
+            }
+        }
+    };
 }
 
+int_index!(u32, i32, 4);
+int_index!(u64, i64, 8);
+
+// the length of an atom whose slot holds offsets into the atom buffer, rather
+// than the atom
+const IN_BUFFER: u8 = u8::MAX;
+
+// an atom's slot and length, as in the atom table
 #[derive(Clone, Copy)]
-pub struct IntPair {
-    first: i32,
-    rest: i32,
+pub struct GenericIntAtomBuf<I: IntIndex> {
+    slot: I::Slot,
+    len: u8,
 }
 
-pub struct IntAllocator {
-    // this is effectively a grow-only stack where atoms are allocated. Atoms
-    // are immutable, so once they are created, they will stay around until the
-    // program completes
+pub type IntAtomBuf = GenericIntAtomBuf<u32>;
+
+// the state of an IntAllocator at some point, to roll back to later
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Checkpoint {
+    u8s: usize,
+    atoms: usize,
+    pairs: usize,
+    atom_bytes: usize,
+    generation: u64,
+}
+
+// what an allocator holds. nil and one are preallocated and not counted, nor
+// is the file of an allocator made by with_mmap()
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct AllocatorStats {
+    pub pairs: usize,
+    pub atoms: usize,
+    pub atom_bytes: usize,
+    // the most memory the atoms and pairs have taken up at once since the
+    // allocator was made or reset, through any rollbacks
+    pub peak_bytes: usize,
+    // held, whether in use or not, as capacity_bytes()
+    pub capacity_bytes: usize,
+}
+
+pub struct GenericIntAllocator<I: IntIndex> {
+    // this is effectively a grow-only stack where atoms too large for their
+    // slot are allocated. Atoms are immutable, so once they are created, they
+    // will stay around until the program completes
     u8_vec: Vec<u8>,
 
-    // storage for all pairs (positive indices)
-    pair_vec: Vec<IntPair>,
+    // the file given to with_mmap(). Its bytes come before u8_vec's in the
+    // offsets of the atom table, and they're only ever read, so the OS loads
+    // them as they're used rather than all up front
+    #[cfg(feature = "mmap")]
+    mmap: Option<memmap2::Mmap>,
+
+    // storage for all pairs (positive indices), as one array per field. The
+    // fields are node pointers rather than u32 indices, since either can be
+    // an atom, i.e. negative. A u32 is the same size as the i32 pointer of
+    // IntAllocator, but would need its own way of marking atoms, converted on
+    // every sexp()
+    pair_first: Vec<I::Ptr>,
+    pair_rest: Vec<I::Ptr>,
 
-    // storage for all atoms (negative indices).
-    // node index -1 refers to index 0 in this vector, -2 refers to 1 and so
-    // on.
-    atom_vec: Vec<IntAtomBuf>,
+    // storage for all atoms (negative indices). node index -1 refers to index
+    // 0 in these vectors, -2 refers to 1 and so on. Most atoms are small
+    // (nil, 1, small ints), so an atom that fits in its slot is kept there,
+    // next to its length, rather than copied to u8_vec. Others have the
+    // length IN_BUFFER, and their slot is their range of u8_vec
+    atom_slots: Vec<I::Slot>,
+    atom_lens: Vec<u8>,
+
+    // the bytes of the atoms made by new_atom(), wherever they're kept
+    atom_bytes: usize,
+
+    // allocation fails with a LimitExceeded error past these, as set by
+    // with_limits()
+    max_atom_bytes: usize,
+    max_pairs: usize,
+
+    // the high-water mark of used_bytes(). It's only updated before
+    // truncating, as until then used_bytes() only grows
+    peak_bytes: usize,
+
+    // the atoms that reset() keeps: nil, one and the mapped file, if any
+    fixed_atoms: usize,
+    // the start of u8_vec that the fixed atoms take up, which reset() keeps
+    // too. Only load() sets it, as a dump has the mapped file's bytes in it
+    fixed_bytes: usize,
+
+    // bumped whenever nodes are dropped, which invalidates every checkpoint
+    // taken before
+    generation: u64,
 }
 
-impl Default for IntAllocator {
+pub type IntAllocator = GenericIntAllocator<u32>;
+
+// for validators that need more than 2^31 nodes, or 4 GiB of atoms
+pub type WideIntAllocator = GenericIntAllocator<u64>;
+
+impl<I: IntIndex> Default for GenericIntAllocator<I> {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl IntAllocator {
+impl<I: IntIndex> GenericIntAllocator<I> {
     pub fn new() -> Self {
+        Self::with_capacity(512, 1024 * 1024)
+    }
+
+    // an allocator with room for about this many nodes, half atoms and half
+    // pairs, and atom_bytes bytes of atoms before it has to grow. A
+    // serialized tree of n bytes has at most n nodes and n bytes of atoms,
+    // so callers deserializing one can size the allocator to it rather than
+    // start with the (much larger) default of new()
+    pub fn with_capacity(nodes: usize, atom_bytes: usize) -> Self {
         let mut r = Self {
-            u8_vec: Vec::new(),
-            pair_vec: Vec::new(),
-            atom_vec: Vec::new(),
+            u8_vec: Vec::with_capacity(atom_bytes),
+            #[cfg(feature = "mmap")]
+            mmap: None,
+            pair_first: Vec::with_capacity(nodes / 2),
+            pair_rest: Vec::with_capacity(nodes / 2),
+            atom_slots: Vec::with_capacity(nodes / 2),
+            atom_lens: Vec::with_capacity(nodes / 2),
+            atom_bytes: 0,
+            max_atom_bytes: usize::MAX,
+            max_pairs: I::MAX_NODES,
+            peak_bytes: 0,
+            fixed_atoms: 2,
+            fixed_bytes: 0,
+            generation: 0,
         };
-        r.u8_vec.reserve(1024 * 1024);
-        r.atom_vec.reserve(256);
-        r.pair_vec.reserve(256);
-        r.u8_vec.push(1_u8);
         // Preallocated empty list
-        r.atom_vec.push(IntAtomBuf { start: 0, end: 0 });
+        r.push_atom(&[]);
         // Preallocated 1
-        r.atom_vec.push(IntAtomBuf { start: 0, end: 1 });
+        r.push_atom(&[1]);
+        r
+    }
+
+    // an allocator that fails new_atom() once atoms take up more than
+    // max_atom_bytes, and new_pair() once there are max_pairs pairs, so the
+    // memory an untrusted program can use is bounded no matter its cost.
+    // Atoms made by new_substr() share their bytes, so they don't count
+    pub fn with_limits(max_atom_bytes: usize, max_pairs: usize) -> Self {
+        let mut r = Self::new();
+        r.max_atom_bytes = max_atom_bytes;
+        r.max_pairs = max_pairs.min(I::MAX_NODES);
         r
     }
+
+    // the file given to with_mmap(), as an atom. Deserializing it with
+    // node_from_atom() leaves the atoms in the file
+    pub fn mapped_atom(&self) -> Option<I::Ptr> {
+        if self.fixed_atoms > 2 {
+            Some(I::atom_ptr(2))
+        } else {
+            None
+        }
+    }
+
+    #[cfg(feature = "mmap")]
+    fn mapped_bytes(&self) -> &[u8] {
+        match &self.mmap {
+            Some(map) => map,
+            None => &[],
+        }
+    }
+
+    #[cfg(not(feature = "mmap"))]
+    fn mapped_bytes(&self) -> &[u8] {
+        &[]
+    }
+
+    // the length of the atom buffer: the mapped bytes, then u8_vec
+    fn buffer_len(&self) -> usize {
+        self.mapped_bytes().len() + self.u8_vec.len()
+    }
+
+    // a range of the atom buffer. No atom straddles the mapped bytes and
+    // u8_vec
+    fn buffer(&self, start: usize, end: usize) -> &[u8] {
+        let mapped = self.mapped_bytes();
+        if end <= mapped.len() {
+            &mapped[start..end]
+        } else {
+            &self.u8_vec[start - mapped.len()..end - mapped.len()]
+        }
+    }
+
+    fn slot_len() -> usize {
+        core::mem::size_of::<I::Slot>()
+    }
+
+    // an atom that fits in its slot. The atom table must have room for it
+    fn push_atom(&mut self, v: &[u8]) {
+        let mut slot = I::Slot::default();
+        slot.as_mut()[..v.len()].copy_from_slice(v);
+        self.atom_slots.push(slot);
+        self.atom_lens.push(v.len() as u8);
+    }
+
+    fn push_range(&mut self, start: usize, end: usize) {
+        self.atom_slots.push(I::range_slot(start, end));
+        self.atom_lens.push(IN_BUFFER);
+    }
+
+    fn reserve_atoms(&mut self, atoms: usize) -> bool {
+        self.atom_slots.try_reserve(atoms).is_ok() && self.atom_lens.try_reserve(atoms).is_ok()
+    }
+
+    // appends a range of the atom buffer to u8_vec
+    fn extend_from_buffer(&mut self, start: usize, end: usize) {
+        #[cfg(feature = "mmap")]
+        if let Some(map) = &self.mmap {
+            if end <= map.len() {
+                self.u8_vec.extend_from_slice(&map[start..end]);
+                return;
+            }
+        }
+        let mapped = self.mapped_bytes().len();
+        self.u8_vec.extend_from_within(start - mapped..end - mapped);
+    }
+
+    // makes room for one more atom of len bytes, if the limits allow it
+    fn make_room_for_atom(&mut self, len: usize) -> Result<(), EvalErr<I::Ptr>> {
+        let inline = len <= Self::slot_len();
+        if self.max_atom_bytes.saturating_sub(self.atom_bytes) < len
+            || (!inline && I::MAX_BYTES - self.buffer_len() < len)
+        {
+            return limit_err(self.null(), "atom buffer full");
+        }
+        if self.atom_count() == I::MAX_NODES {
+            return limit_err(self.null(), "too many atoms");
+        }
+        if !self.reserve_atoms(1) || (!inline && self.u8_vec.try_reserve(len).is_err()) {
+            return oom_err(self.null());
+        }
+        Ok(())
+    }
+
+    fn reserve_pairs(&mut self, pairs: usize) -> bool {
+        self.pair_first.try_reserve(pairs).is_ok() && self.pair_rest.try_reserve(pairs).is_ok()
+    }
+
+    fn atom_count(&self) -> usize {
+        self.atom_lens.len()
+    }
+
+    fn atom_buf(&self, node: I::Ptr) -> GenericIntAtomBuf<I> {
+        let idx = I::index(node);
+        GenericIntAtomBuf {
+            slot: self.atom_slots[idx],
+            len: self.atom_lens[idx],
+        }
+    }
+
+    // the range of the atom buffer an atom with this slot and length refers
+    // to, if it isn't in its slot
+    fn buffer_range(slot: &I::Slot, len: u8) -> Option<(usize, usize)> {
+        if len == IN_BUFFER {
+            Some(I::slot_range(slot))
+        } else {
+            None
+        }
+    }
+
+    // drop all atoms and pairs, except the preallocated nil and one (and the
+    // mapped file), while
+    // keeping the allocated capacity. This lets a validator reuse one
+    // allocator per worker rather than allocating a new one for every
+    // program. All previously returned Ptrs are invalid after this
+    pub fn reset(&mut self) {
+        self.peak_bytes = 0;
+        self.generation += 1;
+        self.u8_vec.truncate(self.fixed_bytes);
+        self.atom_slots.truncate(self.fixed_atoms);
+        self.atom_lens.truncate(self.fixed_atoms);
+        self.atom_bytes = 0;
+        self.pair_first.clear();
+        self.pair_rest.clear();
+    }
+
+    // whether node refers to an atom or pair of this allocator. Ptrs handed
+    // in from outside of rust (see ffi.rs) are checked with this
+    pub fn contains(&self, node: I::Ptr) -> bool {
+        if I::is_atom(node) {
+            I::index(node) < self.atom_count()
+        } else {
+            I::index(node) < self.pair_first.len()
+        }
+    }
+
+    // everything is allocated by appending, so a checkpoint is just the
+    // current lengths (and generation), and rolling back is truncating to
+    // them. Neither copies anything, so speculative evaluation can be tried
+    // and discarded cheaply
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            u8s: self.u8_vec.len(),
+            atoms: self.atom_count(),
+            pairs: self.pair_first.len(),
+            atom_bytes: self.atom_bytes,
+            generation: self.generation,
+        }
+    }
+
+    // whether the checkpoint can be restored: nothing has been rolled back
+    // (by restoring a checkpoint, reset() or compact()) since it was taken
+    // or last restored
+    pub fn can_restore(&self, cp: &Checkpoint) -> bool {
+        cp.generation == self.generation
+    }
+
+    // drop every atom and pair allocated since the checkpoint was taken. Ptrs
+    // to them are invalid afterwards, the ones from before stay valid. Every
+    // other checkpoint is stale afterwards, and restoring one fails, rather
+    // than leaving atoms that point past the end of the atom buffer. cp
+    // itself is brought up to date, so it can be restored again
+    pub fn restore_checkpoint(&mut self, cp: &mut Checkpoint) -> Result<(), EvalErr<I::Ptr>> {
+        if !self.can_restore(cp) {
+            return err(self.null(), "stale checkpoint");
+        }
+        self.generation += 1;
+        cp.generation = self.generation;
+        self.peak_bytes = self.peak_bytes.max(self.used_bytes());
+        self.u8_vec.truncate(cp.u8s);
+        self.atom_slots.truncate(cp.atoms);
+        self.atom_lens.truncate(cp.atoms);
+        self.atom_bytes = cp.atom_bytes;
+        self.pair_first.truncate(cp.pairs);
+        self.pair_rest.truncate(cp.pairs);
+        Ok(())
+    }
+
+    // drops every node the roots don't reach, and returns the roots as they
+    // are afterwards. This is for long-lived allocators, to reclaim the
+    // intermediate nodes of past evaluations without starting over. The
+    // nodes that are kept stay in the same order, in storage sized to them.
+    // Any other Ptrs, and checkpoints, are invalid afterwards. Substrings
+    // keep sharing their bytes, and only the bytes of the atoms that are kept
+    // count towards the atom byte limit, so it's never more than before
+    pub fn compact(&mut self, roots: &[I::Ptr]) -> Vec<I::Ptr> {
+        self.peak_bytes = self.peak_bytes.max(self.used_bytes());
+        self.generation += 1;
+
+        // mark what's reachable
+        let mut atom_live = vec![false; self.atom_count()];
+        let mut pair_live = vec![false; self.pair_first.len()];
+        for live in &mut atom_live[..self.fixed_atoms] {
+            *live = true;
+        }
+        let mut stack = roots.to_vec();
+        while let Some(node) = stack.pop() {
+            let idx = I::index(node);
+            if I::is_atom(node) {
+                atom_live[idx] = true;
+            } else if !pair_live[idx] {
+                pair_live[idx] = true;
+                stack.push(self.pair_first[idx]);
+                stack.push(self.pair_rest[idx]);
+            }
+        }
+
+        // copy it, in order. Pairs only refer to earlier nodes, so their
+        // children have been moved by the time they are
+        let mapped = self.mapped_bytes().len();
+        let atoms = atom_live.iter().filter(|live| **live).count();
+        let pairs = pair_live.iter().filter(|live| **live).count();
+        let mut atom_index = vec![0; self.atom_count()];
+        let mut pair_index = vec![0; self.pair_first.len()];
+
+        // copy the bytes of the live atoms in the buffer, by position, so
+        // that overlapping ones (substrings) are copied once. The mapped file
+        // isn't ours to move
+        let mut ranges: Vec<(usize, usize, usize)> = (0..self.atom_count())
+            .filter(|idx| atom_live[*idx])
+            .filter_map(|idx| {
+                match Self::buffer_range(&self.atom_slots[idx], self.atom_lens[idx]) {
+                    Some((start, end)) if start >= mapped => Some((start, end, idx)),
+                    _ => None,
+                }
+            })
+            .collect();
+        ranges.sort_unstable();
+        let mut new_start = vec![0; self.atom_count()];
+        let mut u8_vec = Vec::new();
+        // where the bytes copied last end in the old buffer. They end u8_vec
+        let mut run_end = 0;
+        for (start, end, idx) in ranges {
+            run_end = run_end.max(start);
+            if end > run_end {
+                u8_vec.extend_from_slice(&self.u8_vec[run_end - mapped..end - mapped]);
+                run_end = end;
+            }
+            new_start[idx] = mapped + u8_vec.len() - (run_end - start);
+        }
+
+        let mut atom_slots = Vec::with_capacity(atoms);
+        let mut atom_lens = Vec::with_capacity(atoms);
+        for idx in (0..self.atom_count()).filter(|idx| atom_live[*idx]) {
+            atom_index[idx] = atom_slots.len();
+            let (slot, len) = (self.atom_slots[idx], self.atom_lens[idx]);
+            match Self::buffer_range(&slot, len) {
+                Some((start, end)) if start >= mapped => {
+                    let new_start = new_start[idx];
+                    atom_slots.push(I::range_slot(new_start, new_start + end - start));
+                }
+                _ => atom_slots.push(slot),
+            }
+            atom_lens.push(len);
+        }
+        let moved = |node: I::Ptr, pair_index: &[usize]| {
+            if I::is_atom(node) {
+                I::atom_ptr(atom_index[I::index(node)])
+            } else {
+                I::pair_ptr(pair_index[I::index(node)])
+            }
+        };
+        let mut pair_first = Vec::with_capacity(pairs);
+        let mut pair_rest = Vec::with_capacity(pairs);
+        for idx in (0..self.pair_first.len()).filter(|idx| pair_live[*idx]) {
+            pair_index[idx] = pair_first.len();
+            pair_first.push(moved(self.pair_first[idx], &pair_index));
+            pair_rest.push(moved(self.pair_rest[idx], &pair_index));
+        }
+
+        self.u8_vec = u8_vec;
+        self.atom_slots = atom_slots;
+        self.atom_lens = atom_lens;
+        // the fixed atoms are live, and first, so they're copied in place.
+        // Atoms in their slots count, as in new_atom(). Substrings there
+        // didn't, but can't be told apart, hence the cap
+        let inline_bytes: usize = self.atom_lens[self.fixed_atoms..]
+            .iter()
+            .filter(|len| **len != IN_BUFFER)
+            .map(|len| *len as usize)
+            .sum();
+        let kept_bytes = self.u8_vec.len() - self.fixed_bytes + inline_bytes;
+        self.atom_bytes = kept_bytes.min(self.atom_bytes);
+        self.pair_first = pair_first;
+        self.pair_rest = pair_rest;
+        roots.iter().map(|root| moved(*root, &pair_index)).collect()
+    }
+
+    pub fn stats(&self) -> AllocatorStats {
+        AllocatorStats {
+            pairs: self.pair_first.len(),
+            atoms: self.atom_count() - self.fixed_atoms,
+            atom_bytes: self.atom_bytes,
+            peak_bytes: self.peak_bytes.max(self.used_bytes()),
+            capacity_bytes: self.capacity_bytes(),
+        }
+    }
+
+    // the bytes of heap memory the atoms and pairs take up
+    fn used_bytes(&self) -> usize {
+        self.u8_vec.len()
+            + self.atom_count() * (Self::slot_len() + 1)
+            + self.pair_first.len() * 2 * core::mem::size_of::<I::Ptr>()
+    }
+
+    // the number of bytes of heap memory held on to, whether in use or not
+    pub fn capacity_bytes(&self) -> usize {
+        self.u8_vec.capacity()
+            + self.atom_slots.capacity() * Self::slot_len()
+            + self.atom_lens.capacity()
+            + (self.pair_first.capacity() + self.pair_rest.capacity())
+                * core::mem::size_of::<I::Ptr>()
+    }
 }
 
-impl Allocator for IntAllocator {
-    type Ptr = i32;
-    type AtomBuf = IntAtomBuf;
+impl IntAllocator {
+    // seals the allocator, so its nodes can be shared between threads. See
+    // sync_allocator
+    pub fn freeze(self) -> FrozenAllocator {
+        FrozenAllocator::new(self)
+    }
+}
 
-    fn new_atom(&mut self, v: &[u8]) -> Result<Self::Ptr, EvalErr<Self::Ptr>> {
-        let start = self.u8_vec.len() as u32;
-        if ((u32::MAX - start) as usize) < v.len() {
-            return err(self.null(), "out of memory");
+#[cfg(feature = "mmap")]
+impl<I: IntIndex> GenericIntAllocator<I> {
+    // an allocator whose atom buffer starts with a file, mapped into memory
+    // rather than read, as mapped_atom(). This is for blobs too large to
+    // keep in RAM, like multi-GB block generators: the pages of the file are
+    // loaded as they're read, and can be dropped again. The file must not be
+    // modified while it's mapped
+    pub fn with_mmap(path: &str) -> std::io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        if map.len() > I::MAX_BYTES {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "file too large for the allocator's offsets",
+            ));
+        }
+        let mut r = Self::new();
+        if map.len() <= Self::slot_len() {
+            r.push_atom(&map);
+        } else {
+            r.push_range(0, map.len());
+            r.mmap = Some(map);
+        }
+        r.fixed_atoms = 3;
+        Ok(r)
+    }
+}
+
+// the header of a dump(): the magic bytes, the index width, the number of
+// fixed atoms, then the atom, pair and buffer counts and atom_bytes as u64.
+// It's followed by the atoms (their length, then their slot as the inline
+// bytes or the start and end offsets), the pairs (first and rest) and the atom
+// buffer. All integers are little endian, and as wide as the index unless
+// stated otherwise. A node is its index, with the top bit set for atoms
+#[cfg(feature = "std")]
+const DUMP_MAGIC: &[u8; 8] = b"CLVMARN1";
+#[cfg(feature = "std")]
+const DUMP_HEADER_LEN: usize = 8 + 2 + 4 * 8;
+
+#[cfg(feature = "std")]
+fn invalid_dump(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(feature = "std")]
+fn put_uint(out: &mut Vec<u8>, v: usize, width: usize) {
+    out.extend_from_slice(&(v as u64).to_le_bytes()[..width]);
+}
+
+#[cfg(feature = "std")]
+fn get_uint(buf: &[u8]) -> usize {
+    let mut v = [0; 8];
+    v[..buf.len()].copy_from_slice(buf);
+    u64::from_le_bytes(v) as usize
+}
+
+#[cfg(feature = "std")]
+impl<I: IntIndex> GenericIntAllocator<I> {
+    fn width() -> usize {
+        Self::slot_len() / 2
+    }
+
+    fn atom_flag() -> usize {
+        1 << (Self::width() * 8 - 1)
+    }
+
+    // writes every atom and pair, so load() can restore the allocator with
+    // the same Ptrs. This is for caching trees that were expensive to build,
+    // like fully parsed standard puzzles. The bytes of a with_mmap() file
+    // are written out too, and are read back into memory
+    pub fn dump<W: std::io::Write>(&self, mut w: W) -> std::io::Result<()> {
+        let width = Self::width();
+        let mut out = Vec::with_capacity(
+            DUMP_HEADER_LEN
+                + self.atom_count() * (1 + 2 * width)
+                + self.pair_first.len() * 2 * width,
+        );
+        out.extend_from_slice(DUMP_MAGIC);
+        out.push(width as u8);
+        out.push(self.fixed_atoms as u8);
+        for v in &[
+            self.atom_count(),
+            self.pair_first.len(),
+            self.buffer_len(),
+            self.atom_bytes,
+        ] {
+            put_uint(&mut out, *v, 8);
+        }
+        for (slot, len) in self.atom_slots.iter().zip(&self.atom_lens) {
+            out.push(*len);
+            match Self::buffer_range(slot, *len) {
+                Some((start, end)) => {
+                    put_uint(&mut out, start, width);
+                    put_uint(&mut out, end, width);
+                }
+                None => out.extend_from_slice(slot.as_ref()),
+            }
+        }
+        for (first, rest) in self.pair_first.iter().zip(&self.pair_rest) {
+            for node in &[*first, *rest] {
+                let flag = if I::is_atom(*node) {
+                    Self::atom_flag()
+                } else {
+                    0
+                };
+                put_uint(&mut out, I::index(*node) | flag, width);
+            }
+        }
+        w.write_all(&out)?;
+        w.write_all(self.mapped_bytes())?;
+        w.write_all(&self.u8_vec)
+    }
+
+    // an allocator as written by dump(). Everything is checked, so that a
+    // corrupt dump is an error here rather than a panic (or a cycle) later
+    pub fn load<R: std::io::Read>(mut r: R) -> std::io::Result<Self> {
+        let mut buf = Vec::new();
+        r.read_to_end(&mut buf)?;
+        if buf.len() < DUMP_HEADER_LEN || &buf[..8] != DUMP_MAGIC {
+            return Err(invalid_dump("not an allocator dump"));
+        }
+        let width = Self::width();
+        if buf[8] as usize != width {
+            return Err(invalid_dump("dump is of a different index width"));
+        }
+        let fixed_atoms = buf[9] as usize;
+        let header: Vec<usize> = buf[10..DUMP_HEADER_LEN].chunks(8).map(get_uint).collect();
+        let (atoms, pairs, buffer_len, atom_bytes) = (header[0], header[1], header[2], header[3]);
+        if atoms > I::MAX_NODES
+            || pairs > I::MAX_NODES
+            || buffer_len > I::MAX_BYTES
+            || !(2..=3).contains(&fixed_atoms)
+            || atoms < fixed_atoms
+        {
+            return Err(invalid_dump("dump is out of range"));
+        }
+        let atoms_len = atoms * (1 + 2 * width);
+        let pairs_len = pairs * 2 * width;
+        if (buf.len() - DUMP_HEADER_LEN) as u128
+            != atoms_len as u128 + pairs_len as u128 + buffer_len as u128
+        {
+            return Err(invalid_dump("dump has the wrong length"));
+        }
+
+        let mut a = Self::with_capacity(atoms + pairs, buffer_len);
+        a.atom_slots.clear();
+        a.atom_lens.clear();
+        let (atom_table, rest) = buf[DUMP_HEADER_LEN..].split_at(atoms_len);
+        let (pair_table, buffer) = rest.split_at(pairs_len);
+        for atom in atom_table.chunks(1 + 2 * width) {
+            let (len, slot_bytes) = (atom[0], &atom[1..]);
+            if len == IN_BUFFER {
+                let start = get_uint(&slot_bytes[..width]);
+                let end = get_uint(&slot_bytes[width..]);
+                if start > end || end > buffer_len || end - start <= Self::slot_len() {
+                    return Err(invalid_dump("atom out of range"));
+                }
+                a.push_range(start, end);
+            } else {
+                if len as usize > Self::slot_len() {
+                    return Err(invalid_dump("atom out of range"));
+                }
+                a.push_atom(&slot_bytes[..len as usize]);
+            }
+        }
+        if !a.atom(&a.null()).is_empty() || a.atom(&a.one()) != [1_u8] {
+            return Err(invalid_dump("dump doesn't start with nil and one"));
+        }
+        // the mapped file, if it was large enough not to fit in its slot, is
+        // at the start of the buffer
+        let fixed_bytes = match fixed_atoms {
+            3 => match Self::buffer_range(&a.atom_slots[2], a.atom_lens[2]) {
+                Some((0, end)) => end,
+                Some(_) => return Err(invalid_dump("mapped atom out of range")),
+                None => 0,
+            },
+            _ => 0,
+        };
+        for (idx, pair) in pair_table.chunks(2 * width).enumerate() {
+            let mut nodes = pair.chunks(width).map(|v| {
+                let v = get_uint(v);
+                if v & Self::atom_flag() != 0 {
+                    let v = v & !Self::atom_flag();
+                    (v < atoms).then(|| I::atom_ptr(v))
+                } else {
+                    // pairs only ever refer to earlier ones
+                    (v < idx).then(|| I::pair_ptr(v))
+                }
+            });
+            match (nodes.next().unwrap(), nodes.next().unwrap()) {
+                (Some(first), Some(rest)) => {
+                    a.pair_first.push(first);
+                    a.pair_rest.push(rest);
+                }
+                _ => return Err(invalid_dump("pair out of range")),
+            }
         }
-        self.u8_vec.extend_from_slice(v);
-        let end = self.u8_vec.len() as u32;
-        if self.atom_vec.len() == i32::MAX as usize {
-            return err(self.null(), "too many atoms");
+        a.u8_vec.extend_from_slice(buffer);
+        a.atom_bytes = atom_bytes;
+        a.fixed_atoms = fixed_atoms;
+        a.fixed_bytes = fixed_bytes;
+        Ok(a)
+    }
+}
+
+impl<I: IntIndex> Allocator for GenericIntAllocator<I> {
+    type Ptr = I::Ptr;
+    type AtomBuf = GenericIntAtomBuf<I>;
+
+    fn new_atom(&mut self, v: &[u8]) -> Result<Self::Ptr, EvalErr<Self::Ptr>> {
+        self.make_room_for_atom(v.len())?;
+        if v.len() <= Self::slot_len() {
+            self.push_atom(v);
+        } else {
+            let start = self.buffer_len();
+            self.u8_vec.extend_from_slice(v);
+            self.push_range(start, self.buffer_len());
         }
-        self.atom_vec.push(IntAtomBuf { start, end });
-        Ok(-(self.atom_vec.len() as i32))
+        self.atom_bytes += v.len();
+        Ok(I::atom_ptr(self.atom_count() - 1))
     }
 
     fn new_pair(
@@ -77,11 +797,15 @@ impl Allocator for IntAllocator {
         first: Self::Ptr,
         rest: Self::Ptr,
     ) -> Result<Self::Ptr, EvalErr<Self::Ptr>> {
-        let r = self.pair_vec.len() as i32;
-        if self.pair_vec.len() == i32::MAX as usize {
-            return err(self.null(), "too many pairs");
+        let r = I::pair_ptr(self.pair_first.len());
+        if self.pair_first.len() >= self.max_pairs {
+            return limit_err(self.null(), "too many pairs");
+        }
+        if !self.reserve_pairs(1) {
+            return oom_err(self.null());
         }
-        self.pair_vec.push(IntPair { first, rest });
+        self.pair_first.push(first);
+        self.pair_rest.push(rest);
         Ok(r)
     }
 
@@ -91,11 +815,11 @@ impl Allocator for IntAllocator {
         start: u32,
         end: u32,
     ) -> Result<Self::Ptr, EvalErr<Self::Ptr>> {
-        if node >= 0 {
+        if !I::is_atom(node) {
             return err(node, "(internal error) substr expected atom, got pair");
         }
-        let atom = self.atom_vec[(-node - 1) as usize];
-        let atom_len = atom.end - atom.start;
+        let atom_len = self.atom(&node).len();
+        let (start, end) = (start as usize, end as usize);
         if start > atom_len {
             return err(node, "substr start out of bounds");
         }
@@ -105,40 +829,672 @@ impl Allocator for IntAllocator {
         if end < start {
             return err(node, "substr invalid bounds");
         }
-        self.atom_vec.push(IntAtomBuf {
-            start: atom.start + start,
-            end: atom.start + end,
-        });
-        Ok(-(self.atom_vec.len() as i32))
+        if self.atom_count() == I::MAX_NODES {
+            return limit_err(node, "too many atoms");
+        }
+        if !self.reserve_atoms(1) {
+            return oom_err(node);
+        }
+        let idx = I::index(node);
+        if end - start <= Self::slot_len() {
+            // it's cheaper to copy a few bytes than to point at them
+            let mut slot = I::Slot::default();
+            slot.as_mut()[..end - start].copy_from_slice(&self.atom(&node)[start..end]);
+            self.atom_slots.push(slot);
+            self.atom_lens.push((end - start) as u8);
+        } else {
+            // the atom is longer still, so it's in the atom buffer
+            let (atom_start, _) = I::slot_range(&self.atom_slots[idx]);
+            self.push_range(atom_start + start, atom_start + end);
+        }
+        Ok(I::atom_ptr(self.atom_count() - 1))
+    }
+
+    fn new_concat(
+        &mut self,
+        total_len: usize,
+        nodes: &[Self::AtomBuf],
+    ) -> Result<Self::Ptr, EvalErr<Self::Ptr>> {
+        self.make_room_for_atom(total_len)?;
+        if total_len <= Self::slot_len() {
+            let mut slot = I::Slot::default();
+            let mut len = 0;
+            for node in nodes {
+                let buf = self.buf(node);
+                slot.as_mut()[len..len + buf.len()].copy_from_slice(buf);
+                len += buf.len();
+            }
+            self.atom_slots.push(slot);
+            self.atom_lens.push(total_len as u8);
+        } else {
+            let start = self.buffer_len();
+            for node in nodes {
+                match Self::buffer_range(&node.slot, node.len) {
+                    Some((start, end)) => self.extend_from_buffer(start, end),
+                    None => self
+                        .u8_vec
+                        .extend_from_slice(&node.slot.as_ref()[..node.len as usize]),
+                }
+            }
+            self.push_range(start, self.buffer_len());
+        }
+        debug_assert_eq!(
+            self.atom(&I::atom_ptr(self.atom_count() - 1)).len(),
+            total_len
+        );
+        self.atom_bytes += total_len;
+        Ok(I::atom_ptr(self.atom_count() - 1))
     }
 
     fn atom<'a>(&'a self, node: &'a Self::Ptr) -> &'a [u8] {
-        if *node >= 0 {
+        if !I::is_atom(*node) {
             panic!("expected atom, got pair");
         }
-        let atom = self.atom_vec[(-*node - 1) as usize];
-        &self.u8_vec[atom.start as usize..atom.end as usize]
+        let idx = I::index(*node);
+        let slot = &self.atom_slots[idx];
+        match Self::buffer_range(slot, self.atom_lens[idx]) {
+            Some((start, end)) => self.buffer(start, end),
+            None => &slot.as_ref()[..self.atom_lens[idx] as usize],
+        }
     }
 
     fn buf<'a>(&'a self, node: &'a Self::AtomBuf) -> &'a [u8] {
-        &self.u8_vec[node.start as usize..node.end as usize]
+        match Self::buffer_range(&node.slot, node.len) {
+            Some((start, end)) => self.buffer(start, end),
+            None => &node.slot.as_ref()[..node.len as usize],
+        }
+    }
+
+    fn atom_byte(&self, node: &Self::AtomBuf) -> Option<u8> {
+        // atoms in the atom buffer are longer than a slot
+        if node.len == 1 {
+            Some(node.slot.as_ref()[0])
+        } else {
+            None
+        }
     }
 
     fn sexp(&self, node: &Self::Ptr) -> SExp<Self::Ptr, Self::AtomBuf> {
-        if *node >= 0 {
-            let pair = self.pair_vec[*node as usize];
-            SExp::Pair(pair.first, pair.rest)
+        if I::is_atom(*node) {
+            SExp::Atom(self.atom_buf(*node))
         } else {
-            let atom = self.atom_vec[(-*node - 1) as usize];
-            SExp::Atom(atom)
+            let idx = I::index(*node);
+            SExp::Pair(self.pair_first[idx], self.pair_rest[idx])
         }
     }
 
     fn null(&self) -> Self::Ptr {
-        -1
+        I::atom_ptr(0)
     }
 
     fn one(&self) -> Self::Ptr {
-        -2
+        I::atom_ptr(1)
+    }
+
+    fn arena_bytes(&self) -> Option<usize> {
+        Some(self.capacity_bytes())
+    }
+
+    fn reserve(
+        &mut self,
+        atoms: usize,
+        pairs: usize,
+        atom_bytes: usize,
+    ) -> Result<(), EvalErr<Self::Ptr>> {
+        // there's no point holding on to more than the limits allow
+        let pairs = pairs.min(self.max_pairs.saturating_sub(self.pair_first.len()));
+        let atom_bytes = atom_bytes.min(self.max_atom_bytes.saturating_sub(self.atom_bytes));
+        if !self.reserve_atoms(atoms)
+            || !self.reserve_pairs(pairs)
+            || self.u8_vec.try_reserve(atom_bytes).is_err()
+        {
+            return oom_err(self.null());
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_reset() {
+    let mut a = IntAllocator::new();
+    let atom = a.new_atom(&[1, 2, 3]).unwrap();
+    let pair = a.new_pair(atom, a.null()).unwrap();
+    assert_eq!(pair, 0);
+    for _ in 0..1000 {
+        a.new_pair(atom, pair).unwrap();
+    }
+    let capacity = a.capacity_bytes();
+
+    a.reset();
+    assert_eq!(a.capacity_bytes(), capacity);
+    assert_eq!(a.atom(&a.null()), &[] as &[u8]);
+    assert_eq!(a.atom(&a.one()), &[1]);
+
+    // allocation starts over from the beginning
+    let atom = a.new_atom(&[4, 5]).unwrap();
+    assert_eq!(atom, -3);
+    assert_eq!(a.atom(&atom), &[4, 5]);
+    assert_eq!(a.new_pair(atom, atom).unwrap(), 0);
+}
+
+#[test]
+fn test_pairs_and_atoms() {
+    let mut a = IntAllocator::new();
+    let foo = a.new_atom(b"foo").unwrap();
+    let bar = a.new_atom(b"bar").unwrap();
+    let oo = a.new_substr(foo, 1, 3).unwrap();
+    assert_eq!((foo, bar, oo), (-3, -4, -5));
+    assert_eq!(a.atom(&oo), b"oo");
+
+    let p0 = a.new_pair(foo, bar).unwrap();
+    let p1 = a.new_pair(p0, oo).unwrap();
+    assert_eq!((p0, p1), (0, 1));
+    match a.sexp(&p1) {
+        SExp::Pair(first, rest) => {
+            assert_eq!(first, p0);
+            assert_eq!(rest, oo);
+        }
+        _ => panic!("expected pair"),
+    }
+    match a.sexp(&bar) {
+        SExp::Atom(buf) => assert_eq!(a.buf(&buf), b"bar"),
+        _ => panic!("expected atom"),
+    }
+}
+
+#[test]
+fn test_atom_byte() {
+    let mut a = IntAllocator::new();
+    let atom_byte = |a: &IntAllocator, node: i32| match a.sexp(&node) {
+        SExp::Atom(buf) => a.atom_byte(&buf),
+        _ => panic!("expected atom"),
+    };
+    let q = a.new_atom(&[1]).unwrap();
+    let two = a.new_atom(&[0, 2]).unwrap();
+    let o = a.new_substr(two, 1, 2).unwrap();
+    assert_eq!(atom_byte(&a, a.null()), None);
+    assert_eq!(atom_byte(&a, a.one()), Some(1));
+    assert_eq!(atom_byte(&a, q), Some(1));
+    assert_eq!(atom_byte(&a, two), None);
+    assert_eq!(atom_byte(&a, o), Some(2));
+}
+
+#[test]
+fn test_inline_atoms() {
+    fn check<I: IntIndex>(a: &mut GenericIntAllocator<I>) {
+        let bytes: Vec<u8> = (1..=40).collect();
+        for len in 0..=bytes.len() {
+            let atom = a.new_atom(&bytes[..len]).unwrap();
+            assert_eq!(a.atom(&atom), &bytes[..len]);
+            match a.sexp(&atom) {
+                SExp::Atom(buf) => {
+                    assert_eq!(a.buf(&buf), &bytes[..len]);
+                    assert_eq!(a.atom_byte(&buf), if len == 1 { Some(1) } else { None });
+                }
+                _ => panic!("expected atom"),
+            }
+            // substrings on both sides of the slot size
+            for start in 0..len {
+                let sub = a.new_substr(atom, start as u32, len as u32).unwrap();
+                assert_eq!(a.atom(&sub), &bytes[start..len]);
+            }
+        }
+        assert_eq!(a.stats().atom_bytes, 40 * 41 / 2);
+    }
+
+    // only the atoms that don't fit in a slot take up the atom buffer
+    let mut a = IntAllocator::new();
+    check(&mut a);
+    assert_eq!(a.checkpoint().u8s, (9..=40).sum::<usize>());
+    let mut a = WideIntAllocator::new();
+    check(&mut a);
+    assert_eq!(a.checkpoint().u8s, (17..=40).sum::<usize>());
+}
+
+#[test]
+fn test_with_capacity() {
+    let mut a = IntAllocator::with_capacity(100, 1000);
+    assert_eq!(a.stats().capacity_bytes, 1000 + 50 * 9 + 2 * 50 * 4);
+    let before = a.capacity_bytes();
+    let mut list = a.null();
+    for _ in 0..48 {
+        let atom = a.new_atom(&[7; 20]).unwrap();
+        list = a.new_pair(atom, list).unwrap();
     }
+    assert_eq!(a.capacity_bytes(), before);
+
+    // it grows past the capacity as needed
+    let mut a = IntAllocator::with_capacity(0, 0);
+    let atom = a.new_atom(&[7; 20]).unwrap();
+    let pair = a.new_pair(atom, atom).unwrap();
+    assert_eq!(a.atom(&atom), &[7; 20]);
+    assert!(a.contains(pair));
+}
+
+#[test]
+fn test_compact() {
+    use crate::node::Node;
+    use crate::serialize::{node_from_bytes, node_to_bytes};
+
+    let mut a = IntAllocator::new();
+    let mut garbage = a.null();
+    for i in 0..1000_u32 {
+        let atom = a.new_atom(&[i as u8; 30]).unwrap();
+        garbage = a.new_pair(atom, garbage).unwrap();
+    }
+    // ("foo" <a 100 byte atom> . "oo"), sharing a subtree
+    let bytes = hex::decode(format!("ff83666f6fffc064{}826f6f", "77".repeat(100))).unwrap();
+    let tree = node_from_bytes(&mut a, &bytes).unwrap();
+    let shared = a.new_pair(tree, tree).unwrap();
+    let long = a.new_atom(&[9; 50]).unwrap();
+    let sub = a.new_substr(long, 10, 30).unwrap();
+    for _ in 0..1000 {
+        a.new_pair(a.one(), a.null()).unwrap();
+    }
+    let shared_bytes = node_to_bytes(&Node::new(&a, shared)).unwrap();
+
+    let roots = a.compact(&[shared, sub, a.one()]);
+    assert_eq!(
+        node_to_bytes(&Node::new(&a, roots[0])).unwrap(),
+        shared_bytes
+    );
+    assert_eq!(a.atom(&roots[1]), &[9; 20]);
+    assert_eq!(roots[2], a.one());
+    // the shared subtree is still shared, and nothing else is left
+    let stats = a.stats();
+    assert_eq!((stats.pairs, stats.atoms), (3, 4));
+    // every atom kept counts, the substring too, now that its bytes are a
+    // copy
+    assert_eq!(stats.atom_bytes, 3 + 100 + 2 + 20);
+    assert_eq!(a.checkpoint().u8s, 100 + 20);
+    assert!(stats.capacity_bytes < 1024);
+    assert!(stats.peak_bytes > 1000 * 30);
+
+    // and it's usable as before
+    let atom = a.new_atom(&[5; 40]).unwrap();
+    let pair = a.new_pair(atom, roots[0]).unwrap();
+    assert_eq!(a.atom(&atom), &[5; 40]);
+    assert_eq!(pair, 3);
+    assert!(a.compact(&[]).is_empty());
+    assert_eq!(a.stats().pairs, 0);
+    assert_eq!(a.atom(&a.one()), &[1]);
+}
+
+#[test]
+fn test_compact_keeps_stats() {
+    let mut a = IntAllocator::new();
+    let small = a.new_atom(b"foo").unwrap();
+    let long = a.new_atom(&[9; 50]).unwrap();
+    let inline_sub = a.new_substr(long, 0, 2).unwrap();
+    let sub = a.new_substr(long, 10, 30).unwrap();
+    let concat = a
+        .new_concat(53, &[a.atom_buf(small), a.atom_buf(long)])
+        .unwrap();
+    let mut root = a.null();
+    for node in &[small, long, inline_sub, sub, concat] {
+        root = a.new_pair(*node, root).unwrap();
+    }
+    let before = a.stats();
+    assert_eq!(before.atom_bytes, 3 + 50 + 53);
+
+    // compacting with everything reachable changes nothing
+    a.compact(&[root]);
+    let after = a.stats();
+    assert_eq!(
+        (after.pairs, after.atoms, after.atom_bytes, after.peak_bytes),
+        (
+            before.pairs,
+            before.atoms,
+            before.atom_bytes,
+            before.peak_bytes
+        )
+    );
+}
+
+#[test]
+fn test_compact_substr() {
+    let mut a = IntAllocator::with_limits(100, 100);
+    let bytes: Vec<u8> = (0..100).collect();
+    let atom = a.new_atom(&bytes).unwrap();
+    // overlapping substrings, which share the atom's bytes
+    let ranges = [(0, 90), (10, 100), (0, 90), (20, 40), (1, 3)];
+    let subs: Vec<_> = ranges
+        .iter()
+        .map(|(start, end)| a.new_substr(atom, *start, *end).unwrap())
+        .collect();
+    assert_eq!(a.stats().atom_bytes, 100);
+
+    // they still share them once the atom is gone
+    let subs = a.compact(&subs);
+    for (sub, (start, end)) in subs.iter().zip(ranges.iter()) {
+        assert_eq!(a.atom(sub), &bytes[*start as usize..*end as usize]);
+    }
+    assert_eq!(a.stats().atom_bytes, 100);
+    assert_eq!(a.checkpoint().u8s, 100);
+    assert_eq!(a.new_atom(&[1]).unwrap_err().1, "atom buffer full");
+
+    // and only what's left of them counts, including the short one copied
+    // into its slot
+    let subs = a.compact(&subs[3..]);
+    assert_eq!(a.atom(&subs[0]), &bytes[20..40]);
+    assert_eq!(a.atom(&subs[1]), &bytes[1..3]);
+    assert_eq!(a.stats().atom_bytes, 20 + 2);
+    let atom = a.new_atom(&[7; 78]).unwrap();
+    assert_eq!(a.atom(&atom), &[7; 78]);
+    assert_eq!(a.new_atom(&[1]).unwrap_err().1, "atom buffer full");
+}
+
+#[test]
+fn test_concat() {
+    fn check<I: IntIndex>(a: &mut GenericIntAllocator<I>) {
+        let bytes: Vec<u8> = (1..=40).collect();
+        let atoms: Vec<_> = [&bytes[..3], &bytes[3..20], &[], &bytes[20..]]
+            .iter()
+            .map(|v| a.new_atom(v).unwrap())
+            .collect();
+        let bufs: Vec<_> = atoms
+            .iter()
+            .map(|atom| match a.sexp(atom) {
+                SExp::Atom(buf) => buf,
+                SExp::Pair(..) => panic!("expected atom"),
+            })
+            .collect();
+        // results that fit in a slot, and ones that don't
+        for n in 0..=bufs.len() {
+            let len = [0, 3, 20, 20, 40][n];
+            let atom = a.new_concat(len, &bufs[..n]).unwrap();
+            assert_eq!(a.atom(&atom), &bytes[..len]);
+        }
+        assert_eq!(a.stats().atom_bytes, 40 + 3 + 20 + 20 + 40);
+    }
+
+    let mut a = IntAllocator::new();
+    check(&mut a);
+    let mut a = WideIntAllocator::new();
+    check(&mut a);
+
+    // the limits apply as they do to new_atom()
+    let mut a = IntAllocator::with_limits(30, 0);
+    let atom = a.new_atom(&[7; 20]).unwrap();
+    let buf = match a.sexp(&atom) {
+        SExp::Atom(buf) => buf,
+        SExp::Pair(..) => panic!("expected atom"),
+    };
+    assert!(a.new_concat(40, &[buf, buf]).is_err());
+}
+
+#[test]
+fn test_checkpoint() {
+    let mut a = IntAllocator::new();
+    let foo = a.new_atom(b"foo").unwrap();
+    let pair = a.new_pair(foo, a.null()).unwrap();
+    let mut cp = a.checkpoint();
+
+    let bar = a.new_atom(b"bar").unwrap();
+    a.new_pair(bar, pair).unwrap();
+    a.new_substr(foo, 1, 2).unwrap();
+    assert_ne!(a.checkpoint(), cp);
+
+    a.restore_checkpoint(&mut cp).unwrap();
+    assert_eq!(a.checkpoint(), cp);
+    assert_eq!(a.atom(&foo), b"foo");
+    match a.sexp(&pair) {
+        SExp::Pair(first, rest) => assert_eq!((first, rest), (foo, a.null())),
+        _ => panic!("expected pair"),
+    }
+
+    // allocation continues where the checkpoint was taken
+    assert_eq!(a.new_atom(b"baz").unwrap(), bar);
+    assert_eq!(a.atom(&bar), b"baz");
+    assert_eq!(a.new_pair(bar, bar).unwrap(), pair + 1);
+
+    // and the same checkpoint can be restored again
+    a.restore_checkpoint(&mut cp).unwrap();
+    assert_eq!(a.checkpoint(), cp);
+
+    // but not one that was rolled back past, even when as many atoms and
+    // bytes have been allocated since
+    a.new_atom(b"barbarbarbar").unwrap();
+    let mut later = a.checkpoint();
+    a.restore_checkpoint(&mut cp).unwrap();
+    assert!(!a.can_restore(&later));
+    a.new_atom(b"barbarbarbar").unwrap();
+    assert_eq!(a.atom_count(), later.atoms);
+    assert!(!a.can_restore(&later));
+    assert!(a.can_restore(&cp));
+    let stale = later;
+    let e = a.restore_checkpoint(&mut later).unwrap_err();
+    assert_eq!(e.1, "stale checkpoint");
+    // nothing is touched
+    assert_eq!(later, stale);
+    assert_eq!(a.atom_count(), later.atoms);
+    assert!(a.can_restore(&cp));
+
+    // nor any other, once one has been restored
+    let earlier = cp;
+    let mut later = a.checkpoint();
+    a.restore_checkpoint(&mut later).unwrap();
+    assert!(!a.can_restore(&earlier));
+    assert!(!a.can_restore(&cp));
+
+    // reset() and compact() drop nodes too
+    let cp = a.checkpoint();
+    a.reset();
+    assert!(!a.can_restore(&cp));
+    let cp = a.checkpoint();
+    assert!(a.can_restore(&cp));
+    a.compact(&[]);
+    assert!(!a.can_restore(&cp));
+    assert!(a.can_restore(&a.checkpoint()));
+}
+
+#[test]
+fn test_stats() {
+    let mut a = IntAllocator::new();
+    let empty = a.stats();
+    assert_eq!((empty.pairs, empty.atoms, empty.atom_bytes), (0, 0, 0));
+    // nil and one, in their slots
+    assert_eq!(empty.peak_bytes, 2 * 9);
+    assert_eq!(empty.capacity_bytes, a.capacity_bytes());
+
+    let mut cp = a.checkpoint();
+    let foo = a.new_atom(b"foo").unwrap();
+    a.new_substr(foo, 1, 2).unwrap();
+    a.new_pair(foo, foo).unwrap();
+    let stats = a.stats();
+    assert_eq!((stats.pairs, stats.atoms, stats.atom_bytes), (1, 2, 3));
+    assert_eq!(stats.peak_bytes, empty.peak_bytes + 2 * 9 + 8);
+
+    // the peak survives rolling back, but not a reset
+    a.restore_checkpoint(&mut cp).unwrap();
+    let rolled_back = a.stats();
+    assert_eq!((rolled_back.pairs, rolled_back.atoms), (0, 0));
+    assert_eq!(rolled_back.peak_bytes, stats.peak_bytes);
+    a.new_atom(b"quux").unwrap();
+    assert_eq!(a.stats().peak_bytes, stats.peak_bytes);
+    a.reset();
+    assert_eq!(a.stats(), empty);
+}
+
+#[test]
+fn test_limits() {
+    use crate::reduction::ErrKind;
+
+    let mut a = IntAllocator::with_limits(10, 3);
+    let atom = a.new_atom(b"0123456").unwrap();
+    a.new_atom(b"789").unwrap();
+    let e = a.new_atom(b"a").unwrap_err();
+    assert_eq!(e.2, ErrKind::LimitExceeded);
+    assert_eq!(e.1.to_string(), "atom buffer full");
+    // substrings don't take up more bytes
+    let sub = a.new_substr(atom, 1, 5).unwrap();
+    assert_eq!(a.atom(&sub), b"1234");
+    a.new_atom(b"").unwrap();
+
+    for _ in 0..3 {
+        a.new_pair(atom, atom).unwrap();
+    }
+    let e = a.new_pair(atom, atom).unwrap_err();
+    assert_eq!(e.2, ErrKind::LimitExceeded);
+    assert_eq!(e.1.to_string(), "too many pairs");
+
+    // the limits stay through a reset, and so does the space under them
+    a.reset();
+    a.new_atom(b"0123456789").unwrap();
+    assert!(a.new_atom(b"a").is_err());
+    for _ in 0..3 {
+        a.new_pair(atom, atom).unwrap();
+    }
+    assert!(a.new_pair(atom, atom).is_err());
+
+    // and reserving past them doesn't hold on to more memory than they allow
+    let mut a = IntAllocator::with_limits(10, 3);
+    let capacity = a.capacity_bytes();
+    a.reserve(0, 1_000_000, 1_000_000_000).unwrap();
+    assert_eq!(a.capacity_bytes(), capacity);
+}
+
+#[test]
+fn test_wide_allocator() {
+    use crate::node::Node;
+    use crate::serialize::{node_from_bytes, node_to_bytes};
+
+    let mut a = WideIntAllocator::new();
+    let foo = a.new_atom(b"foo").unwrap();
+    let oo = a.new_substr(foo, 1, 3).unwrap();
+    let pair = a.new_pair(foo, oo).unwrap();
+    assert_eq!((foo, oo, pair), (-3_i64, -4, 0));
+    assert_eq!(a.atom(&oo), b"oo");
+    assert!(a.contains(pair) && a.contains(oo));
+    assert!(!a.contains(1) && !a.contains(-5) && !a.contains(i64::MIN));
+    // node slots are twice the size of IntAllocator's
+    assert_eq!(
+        a.capacity_bytes() - IntAllocator::new().capacity_bytes(),
+        256 * 4 * 4
+    );
+
+    // and the rest of the crate works with it like with any allocator
+    let bytes = hex::decode("ff83666f6fff826f6f80").unwrap();
+    let node = node_from_bytes(&mut a, &bytes).unwrap();
+    assert_eq!(node_to_bytes(&Node::new(&a, node)).unwrap(), bytes);
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn test_mmap() {
+    use crate::node::Node;
+    use crate::serialize::{node_from_atom, node_to_bytes};
+
+    // ("foo" <a 100 byte atom> "foobarbazqux"), whose atoms all end up in
+    // the file
+    let mut bytes = hex::decode("ff83666f6fffc064").unwrap();
+    bytes.extend_from_slice(&[7; 100]);
+    bytes.extend_from_slice(&hex::decode("ff8c666f6f62617262617a71757880").unwrap());
+    let path = std::env::temp_dir().join(format!("clvm_rs_mmap_{}.bin", std::process::id()));
+    std::fs::write(&path, &bytes).unwrap();
+    let mut a = IntAllocator::with_mmap(path.to_str().unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let blob = a.mapped_atom().unwrap();
+    assert_eq!(a.atom(&blob), &bytes[..]);
+    let node = node_from_atom(&mut a, blob).unwrap();
+    assert_eq!(node_to_bytes(&Node::new(&a, node)).unwrap(), bytes);
+    assert_eq!(a.stats().atom_bytes, 0);
+    assert_eq!(a.checkpoint().u8s, 0);
+
+    // atoms made afterwards go after the file
+    let mut cp = a.checkpoint();
+    let long = a.new_atom(&[9; 20]).unwrap();
+    let sub = a.new_substr(long, 5, 15).unwrap();
+    assert_eq!(a.atom(&long), &[9; 20]);
+    assert_eq!(a.atom(&sub), &[9; 10]);
+    a.restore_checkpoint(&mut cp).unwrap();
+    assert_eq!(a.stats().atoms, a.atom_count() - 3);
+
+    // resetting keeps the file
+    a.reset();
+    assert_eq!(a.stats().atoms, 0);
+    assert_eq!(a.atom(&blob), &bytes[..]);
+
+    // a dump of it has the file's bytes in its buffer, and keeps them too
+    let mut dump = Vec::new();
+    a.dump(&mut dump).unwrap();
+    let mut b = IntAllocator::load(&dump[..]).unwrap();
+    assert_eq!(b.mapped_atom(), Some(blob));
+    b.new_atom(&[9; 20]).unwrap();
+    b.reset();
+    let long = b.new_atom(&[5; 200]).unwrap();
+    assert_eq!(b.atom(&blob), &bytes[..]);
+    assert_eq!(b.atom(&long), &[5; 200][..]);
+    assert_eq!(b.stats().atom_bytes, 200);
+    let roots = b.compact(&[long]);
+    assert_eq!(b.atom(&blob), &bytes[..]);
+    assert_eq!(b.atom(&roots[0]), &[5; 200][..]);
+    assert_eq!(b.stats().atom_bytes, 200);
+
+    // small files are copied into their slot
+    std::fs::write(&path, b"tiny").unwrap();
+    let a = WideIntAllocator::with_mmap(path.to_str().unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(a.atom(&a.mapped_atom().unwrap()), b"tiny");
+    assert!(IntAllocator::new().mapped_atom().is_none());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_dump() {
+    use crate::node::Node;
+    use crate::serialize::{node_from_bytes, node_to_bytes};
+
+    fn check<I: IntIndex>() {
+        let mut a = GenericIntAllocator::<I>::new();
+        // (("foo" . <a 100 byte atom>) "oo" . 7), with a substr
+        let bytes = hex::decode(format!("ffff83666f6fc064{}ff826f6f07", "77".repeat(100))).unwrap();
+        let node = node_from_bytes(&mut a, &bytes).unwrap();
+        let long = match a.sexp(&node) {
+            SExp::Pair(first, _) => match a.sexp(&first) {
+                SExp::Pair(_, rest) => rest,
+                SExp::Atom(_) => panic!("expected pair"),
+            },
+            SExp::Atom(_) => panic!("expected pair"),
+        };
+        let sub = a.new_substr(long, 10, 90).unwrap();
+
+        let mut dump = Vec::new();
+        a.dump(&mut dump).unwrap();
+        let b = GenericIntAllocator::<I>::load(&dump[..]).unwrap();
+        // the same Ptrs refer to the same nodes
+        assert_eq!(node_to_bytes(&Node::new(&b, node)).unwrap(), bytes);
+        assert_eq!(b.atom(&sub), &[0x77; 80][..]);
+        let (sa, sb) = (a.stats(), b.stats());
+        assert_eq!(
+            (sb.pairs, sb.atoms, sb.atom_bytes),
+            (sa.pairs, sa.atoms, sa.atom_bytes)
+        );
+        assert_eq!(b.checkpoint(), a.checkpoint());
+
+        // corrupt dumps are errors
+        assert!(GenericIntAllocator::<I>::load(&dump[..dump.len() - 1]).is_err());
+        assert!(GenericIntAllocator::<I>::load(&dump[1..]).is_err());
+        let mut bad = dump.clone();
+        bad.push(0);
+        assert!(GenericIntAllocator::<I>::load(&bad[..]).is_err());
+    }
+    check::<u32>();
+    check::<u64>();
+
+    // a dump can only be loaded with the width it was made with
+    let mut dump = Vec::new();
+    IntAllocator::new().dump(&mut dump).unwrap();
+    assert!(IntAllocator::load(&dump[..]).is_ok());
+    assert!(WideIntAllocator::load(&dump[..]).is_err());
+
+    // a pair can't refer to itself, or to a later one
+    let mut a = IntAllocator::new();
+    let pair = a.new_pair(a.null(), a.null()).unwrap();
+    let mut dump = Vec::new();
+    a.dump(&mut dump).unwrap();
+    let at = dump.len() - 8;
+    dump[at..at + 4].copy_from_slice(&(pair as u32).to_le_bytes());
+    assert!(IntAllocator::load(&dump[..]).is_err());
 }
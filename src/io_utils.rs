@@ -0,0 +1,161 @@
+// reading and writing serialized nodes through files and stdin/stdout. This
+// only uses std::fs and std::io, so the same code works natively and under
+// WASI, where the runtime decides which directories are visible (e.g.
+// `wasmtime --dir .`). A path of "-" means stdin or stdout
+
+use std::fs;
+use std::io::{self, Read, Write};
+
+use crate::allocator::Allocator;
+use crate::node::Node;
+use crate::serialize::{node_from_bytes, node_to_bytes, SerializeError, SerializeResult};
+
+pub fn read_input(path: &str) -> io::Result<Vec<u8>> {
+    if path == "-" {
+        let mut buf = Vec::new();
+        io::stdin().read_to_end(&mut buf)?;
+        Ok(buf)
+    } else {
+        fs::read(path)
+    }
+}
+
+pub fn write_output(path: &str, data: &[u8]) -> io::Result<()> {
+    if path == "-" {
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        out.write_all(data)?;
+        out.flush()
+    } else {
+        fs::write(path, data)
+    }
+}
+
+// serialized programs are stored either as binary or, like brun and opc
+// produce them, as hex text. Text that's entirely hex (ignoring surrounding
+// whitespace) is decoded, anything else is taken to be binary
+pub fn decode_blob(data: &[u8]) -> Vec<u8> {
+    let text = trim_ascii(data);
+    if !text.is_empty() && text.iter().all(u8::is_ascii_hexdigit) {
+        if let Ok(v) = hex::decode(text) {
+            return v;
+        }
+    }
+    data.to_vec()
+}
+
+fn trim_ascii(mut data: &[u8]) -> &[u8] {
+    while let [first, rest @ ..] = data {
+        if !first.is_ascii_whitespace() {
+            break;
+        }
+        data = rest;
+    }
+    while let [rest @ .., last] = data {
+        if !last.is_ascii_whitespace() {
+            break;
+        }
+        data = rest;
+    }
+    data
+}
+
+pub fn node_from_path<T: Allocator>(allocator: &mut T, path: &str) -> SerializeResult<T::Ptr> {
+    let data = read_input(path).map_err(SerializeError::Io)?;
+    node_from_bytes(allocator, &decode_blob(&data))
+}
+
+// writes node to path, as hex text followed by a newline if as_hex is set
+pub fn node_to_path<T: Allocator>(node: &Node<T>, path: &str, as_hex: bool) -> SerializeResult<()> {
+    let mut data = node_to_bytes(node)?;
+    if as_hex {
+        data = hex::encode(data).into_bytes();
+        data.push(b'\n');
+    }
+    write_output(path, &data).map_err(SerializeError::Io)
+}
+
+// chialisp build output: a `.clvm.hex` file is the serialized program in hex,
+// followed by a newline. Unlike node_from_path(), binary isn't accepted
+pub fn read_clvm_hex<T: Allocator>(allocator: &mut T, path: &str) -> SerializeResult<T::Ptr> {
+    let data = read_input(path).map_err(SerializeError::Io)?;
+    let blob = hex::decode(trim_ascii(&data)).map_err(|e| {
+        SerializeError::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path, e),
+        ))
+    })?;
+    node_from_bytes(allocator, &blob)
+}
+
+pub fn write_clvm_hex<T: Allocator>(node: &Node<T>, path: &str) -> SerializeResult<()> {
+    node_to_path(node, path, true)
+}
+
+#[test]
+fn test_decode_blob() {
+    assert_eq!(decode_blob(b"ff0180"), vec![0xff, 0x01, 0x80]);
+    assert_eq!(decode_blob(b"  FF0180\n"), vec![0xff, 0x01, 0x80]);
+    // binary, or text that isn't hex, is passed through
+    assert_eq!(decode_blob(&[0xff, 0x01, 0x80]), vec![0xff, 0x01, 0x80]);
+    assert_eq!(decode_blob(b"ff018"), b"ff018".to_vec());
+    assert_eq!(decode_blob(b"\n"), b"\n".to_vec());
+}
+
+#[test]
+fn test_node_path_round_trip() {
+    use crate::int_allocator::IntAllocator;
+
+    let mut a = IntAllocator::new();
+    let one = a.one();
+    let null = a.null();
+    let node = a.new_pair(one, null).unwrap();
+
+    let dir = std::env::temp_dir();
+    for (name, as_hex) in &[
+        ("clvm_rs_io_utils.hex", true),
+        ("clvm_rs_io_utils.bin", false),
+    ] {
+        let path = dir.join(name);
+        let path = path.to_str().unwrap();
+        node_to_path(&Node::new(&a, node), path, *as_hex).unwrap();
+        let back = node_from_path(&mut a, path).unwrap();
+        assert_eq!(
+            node_to_bytes(&Node::new(&a, back)).unwrap(),
+            vec![0xff, 0x01, 0x80]
+        );
+        fs::remove_file(path).unwrap();
+    }
+    assert!(matches!(
+        node_from_path(&mut a, dir.join("clvm_rs_missing").to_str().unwrap()),
+        Err(SerializeError::Io(_))
+    ));
+}
+
+#[test]
+fn test_clvm_hex() {
+    use crate::int_allocator::IntAllocator;
+
+    let mut a = IntAllocator::new();
+    let one = a.one();
+    let null = a.null();
+    let node = a.new_pair(one, null).unwrap();
+
+    let path = std::env::temp_dir().join("clvm_rs_io_utils.clvm.hex");
+    let path = path.to_str().unwrap();
+    write_clvm_hex(&Node::new(&a, node), path).unwrap();
+    assert_eq!(fs::read(path).unwrap(), b"ff0180\n");
+    let back = read_clvm_hex(&mut a, path).unwrap();
+    assert_eq!(
+        node_to_bytes(&Node::new(&a, back)).unwrap(),
+        vec![0xff, 0x01, 0x80]
+    );
+
+    // binary isn't hex
+    fs::write(path, [0xff, 0x01, 0x80]).unwrap();
+    assert!(matches!(
+        read_clvm_hex(&mut a, path),
+        Err(SerializeError::Io(_))
+    ));
+    fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,624 @@
+// An intermediate representation of clvm's text form that remembers where
+// everything came from, laid out the way clvm_tools' IR is so trees can be
+// passed between the two. Every IR node is itself a clvm tree:
+//
+//   ((<type> . <offset>) . <value>)
+//   (<type> . <value>)                  when there's no source offset
+//
+// The type is an atom naming it in ascii ("CONS", "INT", "SYM" and so on).
+// A CONS node's value is a pair of IR nodes, and every other node's value is
+// an atom. The offset is the byte offset in the source text the node was read
+// from, and a SourceFile turns it into a file, line and column.
+//
+// read_ir() parses text, ir_to_sexp() turns IR into the plain tree assemble()
+// would have produced, and sexp_to_ir() goes the other way, typing atoms the
+// way disassemble() shows them. write_ir() prints IR back as text
+
+use std::fmt;
+
+use crate::allocator::{Allocator, SExp};
+use crate::binutils::{
+    hex_atom, is_printable, keyword_for_opcode, opcode_for_keyword, Token, Tokenizer,
+};
+use crate::number::{
+    atom_bytes_to_decimal, decimal_to_atom_bytes, is_canonical_int, number_from_u8,
+    number_to_atom_bytes, number_to_u64, Number,
+};
+use crate::op_table::Dialect;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IrType {
+    Cons,
+    Null,
+    Int,
+    Hex,
+    Quotes,
+    DoubleQuote,
+    SingleQuote,
+    Symbol,
+    Operator,
+    Code,
+    Node,
+}
+
+const IR_TYPES: [(IrType, &[u8]); 11] = [
+    (IrType::Cons, b"CONS"),
+    (IrType::Null, b"NULL"),
+    (IrType::Int, b"INT"),
+    (IrType::Hex, b"HEX"),
+    (IrType::Quotes, b"Q"),
+    (IrType::DoubleQuote, b"DQ"),
+    (IrType::SingleQuote, b"SQ"),
+    (IrType::Symbol, b"SYM"),
+    (IrType::Operator, b"OP"),
+    (IrType::Code, b"CODE"),
+    (IrType::Node, b"NODE"),
+];
+
+impl IrType {
+    // the atom clvm_tools uses for the type
+    pub fn tag(self) -> &'static [u8] {
+        IR_TYPES.iter().find(|(t, _)| *t == self).unwrap().1
+    }
+
+    pub fn from_tag(tag: &[u8]) -> Option<Self> {
+        IR_TYPES.iter().find(|(_, n)| *n == tag).map(|(t, _)| *t)
+    }
+}
+
+// a position in a source file. Lines and columns count from 1, and columns
+// are in characters
+#[derive(Clone, Debug, PartialEq)]
+pub struct Srcloc {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Srcloc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)
+    }
+}
+
+// text to read IR from, along with its name for locations
+pub struct SourceFile<'a> {
+    pub name: &'a str,
+    pub text: &'a str,
+    // the byte offset each line starts at
+    line_starts: Vec<usize>,
+}
+
+impl<'a> SourceFile<'a> {
+    pub fn new(name: &'a str, text: &'a str) -> Self {
+        let mut line_starts = vec![0];
+        line_starts.extend(text.match_indices('\n').map(|(i, _)| i + 1));
+        SourceFile {
+            name,
+            text,
+            line_starts,
+        }
+    }
+
+    // the location of a byte offset. Offsets past the end are at the end
+    pub fn location(&self, offset: usize) -> Srcloc {
+        let offset = offset.min(self.text.len());
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(i) => i,
+            Err(i) => i - 1,
+        };
+        let start = self.line_starts[line];
+        let column = self
+            .text
+            .get(start..offset)
+            .map_or(offset - start, |s| s.chars().count());
+        Srcloc {
+            file: self.name.to_string(),
+            line: line + 1,
+            column: column + 1,
+        }
+    }
+}
+
+// a syntax error, with where it is
+#[derive(Clone, Debug, PartialEq)]
+pub struct IrError {
+    pub location: Srcloc,
+    pub message: String,
+}
+
+impl fmt::Display for IrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+fn alloc_err<P>(e: crate::reduction::EvalErr<P>) -> String {
+    e.1.to_string()
+}
+
+// an IR node of the given type and value
+pub fn ir_new<T: Allocator>(
+    a: &mut T,
+    ty: IrType,
+    value: T::Ptr,
+    offset: Option<usize>,
+) -> Result<T::Ptr, String> {
+    let mut ty = a.new_atom(ty.tag()).map_err(alloc_err)?;
+    if let Some(offset) = offset {
+        let offset = a
+            .new_atom(&number_to_atom_bytes(&Number::from(offset)))
+            .map_err(alloc_err)?;
+        ty = a.new_pair(ty, offset).map_err(alloc_err)?;
+    }
+    a.new_pair(ty, value).map_err(alloc_err)
+}
+
+pub fn ir_atom<T: Allocator>(
+    a: &mut T,
+    ty: IrType,
+    atom: &[u8],
+    offset: Option<usize>,
+) -> Result<T::Ptr, String> {
+    let value = a.new_atom(atom).map_err(alloc_err)?;
+    ir_new(a, ty, value, offset)
+}
+
+pub fn ir_cons<T: Allocator>(
+    a: &mut T,
+    first: T::Ptr,
+    rest: T::Ptr,
+    offset: Option<usize>,
+) -> Result<T::Ptr, String> {
+    let value = a.new_pair(first, rest).map_err(alloc_err)?;
+    ir_new(a, IrType::Cons, value, offset)
+}
+
+pub fn ir_null<T: Allocator>(a: &mut T, offset: Option<usize>) -> Result<T::Ptr, String> {
+    ir_atom(a, IrType::Null, &[], offset)
+}
+
+fn pair<T: Allocator>(a: &T, node: &T::Ptr) -> Option<(T::Ptr, T::Ptr)> {
+    match a.sexp(node) {
+        SExp::Pair(first, rest) => Some((first, rest)),
+        SExp::Atom(_) => None,
+    }
+}
+
+// None if the node isn't IR
+pub fn ir_type<T: Allocator>(a: &T, node: &T::Ptr) -> Option<IrType> {
+    let (ty, _) = pair(a, node)?;
+    let ty = match pair(a, &ty) {
+        Some((ty, _)) => ty,
+        None => ty,
+    };
+    match a.sexp(&ty) {
+        SExp::Atom(buf) => IrType::from_tag(a.buf(&buf)),
+        SExp::Pair(_, _) => None,
+    }
+}
+
+pub fn ir_offset<T: Allocator>(a: &T, node: &T::Ptr) -> Option<usize> {
+    let (ty, _) = pair(a, node)?;
+    let (_, offset) = pair(a, &ty)?;
+    match a.sexp(&offset) {
+        SExp::Atom(buf) => number_to_u64(&number_from_u8(a.buf(&buf))).map(|v| v as usize),
+        SExp::Pair(_, _) => None,
+    }
+}
+
+pub fn ir_location<T: Allocator>(a: &T, node: &T::Ptr, source: &SourceFile) -> Option<Srcloc> {
+    ir_offset(a, node).map(|offset| source.location(offset))
+}
+
+pub fn ir_val<T: Allocator>(a: &T, node: &T::Ptr) -> Option<T::Ptr> {
+    pair(a, node).map(|(_, value)| value)
+}
+
+// the two halves of a CONS node
+pub fn ir_pair<T: Allocator>(a: &T, node: &T::Ptr) -> Option<(T::Ptr, T::Ptr)> {
+    if ir_type(a, node)? != IrType::Cons {
+        return None;
+    }
+    pair(a, &ir_val(a, node)?)
+}
+
+// the value of any other node
+pub fn ir_as_atom<T: Allocator>(a: &T, node: &T::Ptr) -> Option<Vec<u8>> {
+    if ir_type(a, node)? == IrType::Cons {
+        return None;
+    }
+    match a.sexp(&ir_val(a, node)?) {
+        SExp::Atom(buf) => Some(a.buf(&buf).to_vec()),
+        SExp::Pair(_, _) => None,
+    }
+}
+
+enum ReadOp {
+    // read an item
+    Item,
+    // read the rest of a list, whose ( was at the offset. The flag is set
+    // before its first item
+    Rest(usize, bool),
+    // the ) after the item following a .
+    Close,
+    // make a CONS of the last two values, at the offset
+    Cons(usize),
+}
+
+fn ir_for_token<T: Allocator>(
+    a: &mut T,
+    offset: usize,
+    token: Token,
+    quote: Option<u8>,
+) -> Result<T::Ptr, String> {
+    let (ty, atom) = match token {
+        Token::Quoted(s) => {
+            let ty = if quote == Some(b'\'') {
+                IrType::SingleQuote
+            } else {
+                IrType::Quotes
+            };
+            (ty, s.as_bytes().to_vec())
+        }
+        Token::Symbol(s) => {
+            if let Some(v) = hex_atom(s) {
+                (IrType::Hex, v)
+            } else if let Some(v) = decimal_to_atom_bytes(s) {
+                (IrType::Int, v)
+            } else {
+                (IrType::Symbol, s.as_bytes().to_vec())
+            }
+        }
+        _ => unreachable!(),
+    };
+    ir_atom(a, ty, &atom, Some(offset))
+}
+
+// parses text into IR, recording the offset of every node. Errors say where
+// in the source they are
+pub fn read_ir<T: Allocator>(a: &mut T, source: &SourceFile) -> Result<T::Ptr, IrError> {
+    let err = |offset: usize, message: String| IrError {
+        location: source.location(offset),
+        message,
+    };
+    let text = source.text;
+    let mut tokens = Tokenizer { text, pos: 0 };
+    let mut values: Vec<T::Ptr> = Vec::new();
+    let mut ops = vec![ReadOp::Item];
+
+    while let Some(op) = ops.pop() {
+        let token = tokens.next_token().map_err(|e| err(tokens.pos, e))?;
+        let end = text.len();
+        match op {
+            ReadOp::Item => match token {
+                None => return Err(err(end, "unexpected end of input".into())),
+                Some((pos, Token::Open)) => ops.push(ReadOp::Rest(pos, true)),
+                Some((pos, Token::Close)) => return Err(err(pos, "unexpected )".into())),
+                Some((pos, Token::Dot)) => return Err(err(pos, "unexpected .".into())),
+                Some((pos, token)) => {
+                    let quote = text.as_bytes().get(pos).cloned();
+                    let node = ir_for_token(a, pos, token, quote).map_err(|e| err(pos, e))?;
+                    values.push(node);
+                }
+            },
+            ReadOp::Rest(open, empty) => match token {
+                None => return Err(err(open, "missing )".into())),
+                Some((pos, Token::Close)) => {
+                    // () is where its ( is
+                    let pos = if empty { open } else { pos };
+                    values.push(ir_null(a, Some(pos)).map_err(|e| err(pos, e))?);
+                }
+                Some((pos, Token::Dot)) if empty => return Err(err(pos, "unexpected .".into())),
+                Some((_, Token::Dot)) => {
+                    ops.push(ReadOp::Close);
+                    ops.push(ReadOp::Item);
+                }
+                Some((pos, _)) => {
+                    // the first pair of a list is where its ( is, the others
+                    // where their item is
+                    tokens.pos = pos;
+                    ops.push(ReadOp::Cons(if empty { open } else { pos }));
+                    ops.push(ReadOp::Rest(open, false));
+                    ops.push(ReadOp::Item);
+                }
+            },
+            ReadOp::Close => match token {
+                Some((_, Token::Close)) => {}
+                Some((pos, _)) => return Err(err(pos, "expected )".into())),
+                None => return Err(err(end, "missing )".into())),
+            },
+            ReadOp::Cons(offset) => {
+                // that wasn't a token for this op, put it back
+                if let Some((pos, _)) = token {
+                    tokens.pos = pos;
+                }
+                let rest = values.pop().unwrap();
+                let first = values.pop().unwrap();
+                let node = ir_cons(a, first, rest, Some(offset)).map_err(|e| err(offset, e))?;
+                values.push(node);
+            }
+        }
+    }
+    if let Some((pos, _)) = tokens.next_token().map_err(|e| err(tokens.pos, e))? {
+        return Err(err(pos, "unexpected text".into()));
+    }
+    Ok(values.pop().unwrap())
+}
+
+enum ConvertOp<P> {
+    Node(P),
+    Cons,
+}
+
+fn not_ir<T: Allocator>(a: &T, node: &T::Ptr) -> String {
+    let offset = ir_offset(a, node).map_or(String::new(), |o| format!(" at {}", o));
+    format!("invalid IR node{}", offset)
+}
+
+// the plain tree IR stands for, the same one assemble_for() gives for the
+// text. Symbols that are keywords become their opcodes
+pub fn ir_to_sexp<T: Allocator>(
+    dialect: &Dialect,
+    a: &mut T,
+    ir: &T::Ptr,
+) -> Result<T::Ptr, String> {
+    let mut values: Vec<T::Ptr> = Vec::new();
+    let mut ops = vec![ConvertOp::Node(ir.clone())];
+    while let Some(op) = ops.pop() {
+        match op {
+            ConvertOp::Node(node) => {
+                if let Some((first, rest)) = ir_pair(a, &node) {
+                    ops.push(ConvertOp::Cons);
+                    ops.push(ConvertOp::Node(rest));
+                    ops.push(ConvertOp::Node(first));
+                    continue;
+                }
+                let ty = ir_type(a, &node).ok_or_else(|| not_ir(a, &node))?;
+                let atom = ir_as_atom(a, &node).ok_or_else(|| not_ir(a, &node))?;
+                let atom = match ty {
+                    IrType::Symbol => match std::str::from_utf8(&atom)
+                        .ok()
+                        .and_then(|s| opcode_for_keyword(dialect, s))
+                    {
+                        Some(opcode) => vec![opcode],
+                        None => atom,
+                    },
+                    _ => atom,
+                };
+                values.push(a.new_atom(&atom).map_err(alloc_err)?);
+            }
+            ConvertOp::Cons => {
+                let rest = values.pop().unwrap();
+                let first = values.pop().unwrap();
+                values.push(a.new_pair(first, rest).map_err(alloc_err)?);
+            }
+        }
+    }
+    Ok(values.pop().unwrap())
+}
+
+// the type disassemble() shows an atom as
+fn type_for_atom(atom: &[u8]) -> IrType {
+    if atom.is_empty() {
+        IrType::Null
+    } else if atom.len() > 2 && is_printable(atom) {
+        IrType::Quotes
+    } else if atom.len() <= 4 && is_canonical_int(atom) {
+        IrType::Int
+    } else {
+        IrType::Hex
+    }
+}
+
+enum ToIrOp<P> {
+    // a node, and whether it's the first item of a list
+    Node(P, bool),
+    // the rest of a list, after its first item
+    Rest(P),
+    Cons,
+}
+
+// IR for a plain tree, without offsets. As in disassemble_for(), an atom at
+// the head of a list that's a keyword becomes a symbol
+pub fn sexp_to_ir<T: Allocator>(
+    dialect: &Dialect,
+    a: &mut T,
+    node: &T::Ptr,
+) -> Result<T::Ptr, String> {
+    let mut values: Vec<T::Ptr> = Vec::new();
+    let mut ops = vec![ToIrOp::Node(node.clone(), false)];
+    while let Some(op) = ops.pop() {
+        let (node, is_head) = match op {
+            ToIrOp::Node(node, is_head) => (node, is_head),
+            ToIrOp::Rest(node) => {
+                if let Some((first, rest)) = pair(a, &node) {
+                    ops.push(ToIrOp::Cons);
+                    ops.push(ToIrOp::Rest(rest));
+                    ops.push(ToIrOp::Node(first, false));
+                    continue;
+                }
+                (node, false)
+            }
+            ToIrOp::Cons => {
+                let rest = values.pop().unwrap();
+                let first = values.pop().unwrap();
+                values.push(ir_cons(a, first, rest, None)?);
+                continue;
+            }
+        };
+        let atom = match a.sexp(&node) {
+            SExp::Pair(first, rest) => {
+                ops.push(ToIrOp::Cons);
+                ops.push(ToIrOp::Rest(rest));
+                ops.push(ToIrOp::Node(first, true));
+                continue;
+            }
+            SExp::Atom(buf) => a.buf(&buf).to_vec(),
+        };
+        let keyword = if is_head {
+            keyword_for_opcode(dialect, &atom)
+        } else {
+            None
+        };
+        values.push(match keyword {
+            Some(kw) => ir_atom(a, IrType::Symbol, kw.as_bytes(), None)?,
+            None => ir_atom(a, type_for_atom(&atom), &atom, None)?,
+        });
+    }
+    Ok(values.pop().unwrap())
+}
+
+fn text_for_ir<T: Allocator>(a: &T, node: &T::Ptr) -> Result<String, String> {
+    let ty = ir_type(a, node).ok_or_else(|| not_ir(a, node))?;
+    let atom = ir_as_atom(a, node).ok_or_else(|| not_ir(a, node))?;
+    Ok(match ty {
+        IrType::Null => "()".to_string(),
+        IrType::Int => atom_bytes_to_decimal(&atom),
+        IrType::Quotes | IrType::DoubleQuote => {
+            format!("\"{}\"", String::from_utf8_lossy(&atom))
+        }
+        IrType::SingleQuote => format!("'{}'", String::from_utf8_lossy(&atom)),
+        IrType::Symbol | IrType::Operator => String::from_utf8_lossy(&atom).to_string(),
+        _ => format!("0x{}", hex::encode(&atom)),
+    })
+}
+
+// IR as text. write_ir(sexp_to_ir(node)) is disassemble_for(node)
+pub fn write_ir<T: Allocator>(a: &T, ir: &T::Ptr) -> Result<String, String> {
+    let mut out = String::new();
+    // a node, or (with false) the rest of a list after its first item
+    let mut ops = vec![(ir.clone(), true)];
+    while let Some((node, is_item)) = ops.pop() {
+        match (ir_pair(a, &node), is_item) {
+            (Some((first, rest)), true) => {
+                out.push('(');
+                ops.push((rest, false));
+                ops.push((first, true));
+            }
+            (Some((first, rest)), false) => {
+                out.push(' ');
+                ops.push((rest, false));
+                ops.push((first, true));
+            }
+            (None, true) => out.push_str(&text_for_ir(a, &node)?),
+            (None, false) => {
+                if ir_type(a, &node) != Some(IrType::Null) {
+                    out.push_str(" . ");
+                    out.push_str(&text_for_ir(a, &node)?);
+                }
+                out.push(')');
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+fn serialized<T: Allocator>(a: &T, node: T::Ptr) -> String {
+    use crate::node::Node;
+    hex::encode(crate::serialize::node_to_bytes(&Node::new(a, node)).unwrap())
+}
+
+#[test]
+fn test_read_ir() {
+    use crate::binutils::assemble;
+    use crate::int_allocator::IntAllocator;
+    use crate::op_table::CHIA_DIALECT;
+
+    let mut a = IntAllocator::new();
+    let text = "; a comment\n(+ (q . 0x0002)\n   \"foo\" 'bar' -5 sym ())";
+    let source = SourceFile::new("test.clvm", text);
+    let ir = read_ir(&mut a, &source).unwrap();
+
+    // the IR says where each node came from, and what it was
+    assert_eq!(ir_type(&a, &ir), Some(IrType::Cons));
+    assert_eq!(
+        ir_location(&a, &ir, &source).unwrap().to_string(),
+        "test.clvm:2:1"
+    );
+    let mut items = Vec::new();
+    let mut node = ir;
+    while let Some((first, rest)) = ir_pair(&a, &node) {
+        items.push(first);
+        node = rest;
+    }
+    let described: Vec<(IrType, String)> = items
+        .iter()
+        .map(|n| {
+            let loc = ir_location(&a, n, &source).unwrap();
+            (
+                ir_type(&a, n).unwrap(),
+                format!("{}:{}", loc.line, loc.column),
+            )
+        })
+        .collect();
+    let at = |ty, pos: &str| (ty, pos.to_string());
+    assert_eq!(
+        described,
+        vec![
+            at(IrType::Symbol, "2:2"),
+            at(IrType::Cons, "2:4"),
+            at(IrType::Quotes, "3:4"),
+            at(IrType::SingleQuote, "3:10"),
+            at(IrType::Int, "3:16"),
+            at(IrType::Symbol, "3:19"),
+            at(IrType::Null, "3:23"),
+        ]
+    );
+    assert_eq!(ir_as_atom(&a, &items[4]), Some(vec![0xfb]));
+
+    // converted, it's what assemble() makes of the text
+    let sexp = ir_to_sexp(&CHIA_DIALECT, &mut a, &ir).unwrap();
+    let expected = assemble(&mut a, text).unwrap();
+    assert_eq!(serialized(&a, sexp), serialized(&a, expected));
+    assert_eq!(
+        write_ir(&a, &ir).unwrap(),
+        "(+ (q . 0x0002) \"foo\" 'bar' -5 sym ())"
+    );
+
+    // errors point at the problem
+    let err = |text: &str| {
+        let mut a = IntAllocator::new();
+        read_ir(&mut a, &SourceFile::new("f", text))
+            .unwrap_err()
+            .to_string()
+    };
+    assert_eq!(err("(1\n  2 . 3 4)"), "f:2:9: expected )");
+    assert_eq!(err("(1 2"), "f:1:1: missing )");
+    assert_eq!(err("\n\n )"), "f:3:2: unexpected )");
+    assert_eq!(err("1 2"), "f:1:3: unexpected text");
+    assert_eq!(err(""), "f:1:1: unexpected end of input");
+}
+
+#[test]
+fn test_sexp_to_ir() {
+    use crate::binutils::{assemble, disassemble};
+    use crate::int_allocator::IntAllocator;
+    use crate::node::Node;
+    use crate::op_table::CHIA_DIALECT;
+
+    let mut a = IntAllocator::new();
+    for text in &[
+        "()",
+        "(+ (q . 2) 5)",
+        "(a (q 2 \"foo\" 0x01020304ff) (c -1 ()))",
+        "((+) 16 . 0x0001)",
+    ] {
+        let node = assemble(&mut a, text).unwrap();
+        let ir = sexp_to_ir(&CHIA_DIALECT, &mut a, &node).unwrap();
+        assert_eq!(ir_offset(&a, &ir), None);
+        assert_eq!(
+            write_ir(&a, &ir).unwrap(),
+            disassemble(&Node::new(&a, node))
+        );
+        let back = ir_to_sexp(&CHIA_DIALECT, &mut a, &ir).unwrap();
+        assert_eq!(serialized(&a, back), serialized(&a, node));
+    }
+
+    // a tree that isn't IR is rejected
+    let node = assemble(&mut a, "(1 2)").unwrap();
+    assert!(ir_to_sexp(&CHIA_DIALECT, &mut a, &node).is_err());
+    assert_eq!(IrType::from_tag(IrType::Symbol.tag()), Some(IrType::Symbol));
+    assert_eq!(IrType::from_tag(b"FOO"), None);
+}
@@ -0,0 +1,313 @@
+// JNI bindings for the JVM and Android, for the natives declared in
+// java/net/chia/clvm/Clvm.java. Programs and values are passed serialized,
+// except to fromBytes() and toBytes(), which convert between the serialized
+// form and nested arrays: an atom is a byte[] and a pair is an Object[2].
+// Failures are thrown as ClvmException. Programs use chia's operator table,
+// where quote is 1 and apply is 2
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+use jni_rs::objects::{JClass, JObject, JValue};
+use jni_rs::sys::{jbyteArray, jint, jlong, jobject, jobjectArray};
+use jni_rs::JNIEnv;
+
+use crate::allocator::{Allocator, SExp};
+use crate::cost::Cost;
+use crate::curry::curry;
+use crate::int_allocator::IntAllocator;
+use crate::node::Node;
+use crate::op_table::{op_table_for, CHIA_OPCODES};
+use crate::run_program::run_program;
+use crate::serialize::{node_from_bytes, node_to_bytes};
+use crate::tree_hash::tree_hash;
+
+const EXCEPTION_CLASS: &str = "net/chia/clvm/ClvmException";
+const RUN_RESULT_CLASS: &str = "net/chia/clvm/RunResult";
+
+fn err<E: ToString>(e: E) -> String {
+    e.to_string()
+}
+
+// run f, turning an error or a panic into a ClvmException rather than
+// unwinding into the JVM. The JVM ignores the return value while an exception
+// is pending, so `null` is just a placeholder
+fn guard<T, F: FnOnce() -> Result<T, String>>(env: &JNIEnv, null: T, f: F) -> T {
+    let msg = match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(v)) => return v,
+        Ok(Err(msg)) => msg,
+        Err(_) => "internal error".to_string(),
+    };
+    // a failed JNI call may already have thrown, e.g. an OutOfMemoryError
+    if !env.exception_check().unwrap_or(true) {
+        let _ = env.throw_new(EXCEPTION_CLASS, msg);
+    }
+    null
+}
+
+fn parse(env: &JNIEnv, a: &mut IntAllocator, blob: jbyteArray) -> Result<i32, String> {
+    let blob = env.convert_byte_array(blob).map_err(err)?;
+    node_from_bytes(a, &blob).map_err(err)
+}
+
+fn serialize(env: &JNIEnv, a: &IntAllocator, node: i32) -> Result<jbyteArray, String> {
+    let blob = node_to_bytes(&Node::new(a, node)).map_err(err)?;
+    env.byte_array_from_slice(&blob).map_err(err)
+}
+
+// the local references a conversion holds on to while it works through a
+// tree: the arrays it's still filling in, or reading from. The JVM only
+// guarantees a native call 16 of them (and Android fails past its limit), so
+// everything else is deleted as soon as it's been used, and more are
+// reserved when a tree is deep enough to need them
+struct LocalRefs<'a, 'b> {
+    env: &'b JNIEnv<'a>,
+    held: usize,
+    reserved: usize,
+}
+
+// for the references a step makes and deletes again, and the ones the JVM
+// takes itself
+const SPARE_LOCAL_REFS: usize = 8;
+
+impl<'a, 'b> LocalRefs<'a, 'b> {
+    fn new(env: &'b JNIEnv<'a>) -> Self {
+        LocalRefs {
+            env,
+            held: 0,
+            reserved: 16,
+        }
+    }
+
+    fn hold(&mut self) -> Result<(), String> {
+        self.held += 1;
+        if self.held + SPARE_LOCAL_REFS > self.reserved {
+            self.reserved *= 2;
+            self.env
+                .ensure_local_capacity(self.reserved as jint)
+                .map_err(err)?;
+        }
+        Ok(())
+    }
+
+    fn release(&mut self, obj: JObject<'a>) -> Result<(), String> {
+        self.held -= 1;
+        self.env.delete_local_ref(obj).map_err(err)
+    }
+}
+
+// an atom as a byte[], or a pair as an Object[2] whose elements are set later.
+// The caller looks up the class once, as each lookup by name makes a local
+// reference
+fn new_java_node<'a>(
+    env: &JNIEnv<'a>,
+    object_class: JClass<'a>,
+    a: &IntAllocator,
+    node: i32,
+) -> Result<JObject<'a>, String> {
+    match a.sexp(&node) {
+        SExp::Atom(buf) => {
+            let atom = env.byte_array_from_slice(a.buf(&buf)).map_err(err)?;
+            Ok(JObject::from(atom))
+        }
+        SExp::Pair(..) => {
+            let pair = env
+                .new_object_array(2, object_class, JObject::null())
+                .map_err(err)?;
+            Ok(JObject::from(pair))
+        }
+    }
+}
+
+fn to_java<'a>(env: &JNIEnv<'a>, a: &IntAllocator, node: i32) -> Result<JObject<'a>, String> {
+    // arrays are made before their elements, and each is let go of once its
+    // rest (the last element set) is, so only the pairs whose rest is still
+    // to come are held at a time. For a list that's just one
+    let mut refs = LocalRefs::new(env);
+    let object_class = env.find_class("java/lang/Object").map_err(err)?;
+    let root = new_java_node(env, object_class, a, node)?;
+    let mut ops: Vec<(JObject<'a>, i32, i32)> = Vec::new();
+    if let SExp::Pair(first, rest) = a.sexp(&node) {
+        ops.push((root, 1, rest));
+        ops.push((root, 0, first));
+    }
+    while let Some((array, index, node)) = ops.pop() {
+        let value = new_java_node(env, object_class, a, node)?;
+        env.set_object_array_element(array.into_inner(), index, value)
+            .map_err(err)?;
+        match a.sexp(&node) {
+            SExp::Pair(first, rest) => {
+                refs.hold()?;
+                ops.push((value, 1, rest));
+                ops.push((value, 0, first));
+            }
+            SExp::Atom(_) => env.delete_local_ref(value).map_err(err)?,
+        }
+        if index == 1 && array.into_inner() != root.into_inner() {
+            refs.release(array)?;
+        }
+    }
+    Ok(root)
+}
+
+enum FromJavaOp<'a> {
+    Visit(JObject<'a>),
+    Cons,
+}
+
+fn from_java<'a>(
+    env: &JNIEnv<'a>,
+    a: &mut IntAllocator,
+    value: JObject<'a>,
+) -> Result<i32, String> {
+    let atom_class = env.find_class("[B").map_err(err)?;
+    let pair_class = env.find_class("[Ljava/lang/Object;").map_err(err)?;
+    // the elements are deleted once they're converted, so only the rests
+    // still to be visited are held at a time. For a list that's just one
+    let mut refs = LocalRefs::new(env);
+    let mut ops = vec![FromJavaOp::Visit(value)];
+    let mut values: Vec<i32> = Vec::new();
+    while let Some(op) = ops.pop() {
+        match op {
+            FromJavaOp::Visit(v) => {
+                // null is an instance of every class
+                if v.is_null() {
+                    return Err("expected byte[] or Object[], got null".into());
+                }
+                if env.is_instance_of(v, atom_class).map_err(err)? {
+                    let blob = env.convert_byte_array(v.into_inner()).map_err(err)?;
+                    values.push(a.new_atom(&blob).map_err(|e| e.1.to_string())?);
+                } else if env.is_instance_of(v, pair_class).map_err(err)? {
+                    let pair: jobjectArray = v.into_inner();
+                    if env.get_array_length(pair).map_err(err)? != 2 {
+                        return Err("pairs must be arrays of length 2".into());
+                    }
+                    refs.hold()?;
+                    refs.hold()?;
+                    ops.push(FromJavaOp::Cons);
+                    ops.push(FromJavaOp::Visit(
+                        env.get_object_array_element(pair, 1).map_err(err)?,
+                    ));
+                    ops.push(FromJavaOp::Visit(
+                        env.get_object_array_element(pair, 0).map_err(err)?,
+                    ));
+                } else {
+                    return Err("expected byte[] or Object[]".into());
+                }
+                // the caller's reference isn't ours to delete
+                if v.into_inner() != value.into_inner() {
+                    refs.release(v)?;
+                }
+            }
+            FromJavaOp::Cons => {
+                let right = values.pop().unwrap();
+                let left = values.pop().unwrap();
+                values.push(a.new_pair(left, right).map_err(|e| e.1.to_string())?);
+            }
+        }
+    }
+    Ok(values.pop().unwrap())
+}
+
+#[no_mangle]
+pub extern "system" fn Java_net_chia_clvm_Clvm_runProgram(
+    env: JNIEnv,
+    _class: JClass,
+    program: jbyteArray,
+    args: jbyteArray,
+    max_cost: jlong,
+) -> jobject {
+    guard(&env, ptr::null_mut(), || {
+        if max_cost < 0 {
+            return Err("maxCost must not be negative".into());
+        }
+        let mut a = IntAllocator::new();
+        let program = parse(&env, &mut a, program)?;
+        let args = parse(&env, &mut a, args)?;
+        let r = run_program(
+            &mut a,
+            &program,
+            &args,
+            1,
+            2,
+            Cost::new(max_cost as u64),
+            Box::new(op_table_for(&CHIA_OPCODES)),
+            None,
+        )
+        .map_err(|e| e.1.to_string())?;
+        let result = serialize(&env, &a, r.1)?;
+        let obj = env
+            .new_object(
+                RUN_RESULT_CLASS,
+                "(J[B)V",
+                &[
+                    JValue::Long(r.0.get() as jlong),
+                    JValue::Object(JObject::from(result)),
+                ],
+            )
+            .map_err(err)?;
+        Ok(obj.into_inner())
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_net_chia_clvm_Clvm_fromBytes(
+    env: JNIEnv,
+    _class: JClass,
+    blob: jbyteArray,
+) -> jobject {
+    guard(&env, ptr::null_mut(), || {
+        let mut a = IntAllocator::new();
+        let node = parse(&env, &mut a, blob)?;
+        Ok(to_java(&env, &a, node)?.into_inner())
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_net_chia_clvm_Clvm_toBytes(
+    env: JNIEnv,
+    _class: JClass,
+    value: JObject,
+) -> jbyteArray {
+    guard(&env, ptr::null_mut(), || {
+        let mut a = IntAllocator::new();
+        let node = from_java(&env, &mut a, value)?;
+        serialize(&env, &a, node)
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_net_chia_clvm_Clvm_treeHash(
+    env: JNIEnv,
+    _class: JClass,
+    blob: jbyteArray,
+) -> jbyteArray {
+    guard(&env, ptr::null_mut(), || {
+        let mut a = IntAllocator::new();
+        let node = parse(&env, &mut a, blob)?;
+        let hash = tree_hash(&Node::new(&a, node));
+        env.byte_array_from_slice(&hash).map_err(err)
+    })
+}
+
+#[no_mangle]
+pub extern "system" fn Java_net_chia_clvm_Clvm_curry(
+    env: JNIEnv,
+    _class: JClass,
+    program: jbyteArray,
+    args: jobjectArray,
+) -> jbyteArray {
+    guard(&env, ptr::null_mut(), || {
+        let mut a = IntAllocator::new();
+        let program = parse(&env, &mut a, program)?;
+        let count = env.get_array_length(args).map_err(err)?;
+        let mut arg_nodes = Vec::with_capacity(count as usize);
+        for i in 0..count {
+            let arg = env.get_object_array_element(args, i).map_err(err)?;
+            arg_nodes.push(parse(&env, &mut a, arg.into_inner())?);
+            env.delete_local_ref(arg).map_err(err)?;
+        }
+        let curried = curry(&mut a, program, &arg_nodes).map_err(|e| e.1.to_string())?;
+        serialize(&env, &a, curried)
+    })
+}
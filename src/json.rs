@@ -0,0 +1,388 @@
+// Converting JSON values to trees and back, for committing application data
+// into puzzles and reading it out again. Trees don't say what type they are,
+// so both directions follow a schema:
+//
+// Null      null, as ()
+// Bool      true as 1 and false as ()
+// Int       an integer, as its atom (e.g. 1000 is 0x03e8)
+// String    a string, as an atom of its utf-8
+// Hex       a string of hex digits, as an atom of those bytes
+// Optional  null as (), or the value v as (v)
+// List      an array, as a list of its items
+// Tuple     an array with a fixed number of items of given types
+// Object    an object with the declared keys, as a list of the values in the
+//           declared order. The keys themselves aren't stored
+
+use serde_json::{Map, Number as JsonNumber, Value};
+use std::fmt;
+
+use crate::allocator::{Allocator, SExp};
+use crate::number::{number_from_u8, number_to_atom_bytes, FastNumber, Number};
+use crate::reduction::EvalErr;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Schema {
+    Null,
+    Bool,
+    Int,
+    String,
+    Hex,
+    Optional(Box<Schema>),
+    List(Box<Schema>),
+    Tuple(Vec<Schema>),
+    Object(Vec<(String, Schema)>),
+}
+
+impl Schema {
+    pub fn optional(item: Schema) -> Self {
+        Schema::Optional(Box::new(item))
+    }
+
+    pub fn list(item: Schema) -> Self {
+        Schema::List(Box::new(item))
+    }
+
+    pub fn object(fields: &[(&str, Schema)]) -> Self {
+        Schema::Object(
+            fields
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.clone()))
+                .collect(),
+        )
+    }
+}
+
+// what went wrong, and where: a path into the JSON value like $.outputs[2]
+#[derive(Clone, Debug, PartialEq)]
+pub struct JsonError {
+    pub path: String,
+    pub msg: String,
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.msg)
+    }
+}
+
+impl std::error::Error for JsonError {}
+
+fn json_err<T>(path: &str, msg: impl Into<String>) -> Result<T, JsonError> {
+    Err(JsonError {
+        path: path.to_string(),
+        msg: msg.into(),
+    })
+}
+
+fn alloc_err<P>(path: &str) -> impl Fn(EvalErr<P>) -> JsonError + '_ {
+    move |e| JsonError {
+        path: path.to_string(),
+        msg: e.1.to_string(),
+    }
+}
+
+fn new_list<T: Allocator>(
+    allocator: &mut T,
+    items: Vec<T::Ptr>,
+    path: &str,
+) -> Result<T::Ptr, JsonError> {
+    let mut list = allocator.null();
+    for item in items.into_iter().rev() {
+        list = allocator.new_pair(item, list).map_err(alloc_err(path))?;
+    }
+    Ok(list)
+}
+
+fn json_to_node_at<T: Allocator>(
+    allocator: &mut T,
+    value: &Value,
+    schema: &Schema,
+    path: &str,
+) -> Result<T::Ptr, JsonError> {
+    let atom = match (schema, value) {
+        (Schema::Null, Value::Null) | (Schema::Bool, Value::Bool(false)) => vec![],
+        (Schema::Bool, Value::Bool(true)) => vec![1],
+        (Schema::Int, Value::Number(n)) => {
+            let n: Number = match (n.as_i64(), n.as_u64()) {
+                (Some(v), _) => v.into(),
+                (None, Some(v)) => v.into(),
+                _ => return json_err(path, "expected an integer"),
+            };
+            number_to_atom_bytes(&n)
+        }
+        (Schema::String, Value::String(s)) => s.as_bytes().to_vec(),
+        (Schema::Hex, Value::String(s)) => match hex::decode(s) {
+            Ok(v) => v,
+            Err(_) => return json_err(path, "expected hex"),
+        },
+        (Schema::Optional(_), Value::Null) => vec![],
+        (Schema::Optional(item), v) => {
+            let item = json_to_node_at(allocator, v, item, path)?;
+            return new_list(allocator, vec![item], path);
+        }
+        (Schema::List(item), Value::Array(values)) => {
+            let mut items = Vec::with_capacity(values.len());
+            for (i, v) in values.iter().enumerate() {
+                let path = format!("{}[{}]", path, i);
+                items.push(json_to_node_at(allocator, v, item, &path)?);
+            }
+            return new_list(allocator, items, path);
+        }
+        (Schema::Tuple(schemas), Value::Array(values)) => {
+            if values.len() != schemas.len() {
+                return json_err(path, format!("expected {} items", schemas.len()));
+            }
+            let mut items = Vec::with_capacity(values.len());
+            for (i, (v, s)) in values.iter().zip(schemas).enumerate() {
+                let path = format!("{}[{}]", path, i);
+                items.push(json_to_node_at(allocator, v, s, &path)?);
+            }
+            return new_list(allocator, items, path);
+        }
+        (Schema::Object(fields), Value::Object(map)) => {
+            if let Some(k) = map.keys().find(|k| !fields.iter().any(|f| &f.0 == *k)) {
+                return json_err(path, format!("unexpected key {:?}", k));
+            }
+            let mut items = Vec::with_capacity(fields.len());
+            for (k, s) in fields {
+                let path = format!("{}.{}", path, k);
+                match map.get(k) {
+                    Some(v) => items.push(json_to_node_at(allocator, v, s, &path)?),
+                    None => return json_err(&path, "missing"),
+                }
+            }
+            return new_list(allocator, items, path);
+        }
+        (s, _) => return json_err(path, format!("expected {}", expected(s))),
+    };
+    allocator.new_atom(&atom).map_err(alloc_err(path))
+}
+
+fn expected(schema: &Schema) -> &'static str {
+    match schema {
+        Schema::Null => "null",
+        Schema::Bool => "a bool",
+        Schema::Int => "an integer",
+        Schema::String | Schema::Hex => "a string",
+        Schema::Optional(_) => "a value or null",
+        Schema::List(_) | Schema::Tuple(_) => "an array",
+        Schema::Object(_) => "an object",
+    }
+}
+
+pub fn json_to_node<T: Allocator>(
+    allocator: &mut T,
+    value: &Value,
+    schema: &Schema,
+) -> Result<T::Ptr, JsonError> {
+    json_to_node_at(allocator, value, schema, "$")
+}
+
+// the items of a list that must be exactly `len` long, if given
+fn list_items<T: Allocator>(
+    allocator: &T,
+    node: &T::Ptr,
+    len: Option<usize>,
+    path: &str,
+) -> Result<Vec<T::Ptr>, JsonError> {
+    let mut items = Vec::new();
+    let mut node = node.clone();
+    loop {
+        match allocator.sexp(&node) {
+            SExp::Pair(first, rest) => {
+                items.push(first);
+                node = rest;
+            }
+            SExp::Atom(a) if allocator.buf(&a).is_empty() => break,
+            SExp::Atom(_) => return json_err(path, "expected a list"),
+        }
+    }
+    match len {
+        Some(len) if len != items.len() => json_err(path, format!("expected {} items", len)),
+        _ => Ok(items),
+    }
+}
+
+fn node_to_json_at<T: Allocator>(
+    allocator: &T,
+    node: &T::Ptr,
+    schema: &Schema,
+    path: &str,
+) -> Result<Value, JsonError> {
+    let atom = match allocator.sexp(node) {
+        SExp::Atom(_) => Some(allocator.atom(node)),
+        SExp::Pair(_, _) => None,
+    };
+    Ok(match (schema, atom) {
+        (Schema::Null, Some([])) => Value::Null,
+        (Schema::Bool, Some([])) => Value::Bool(false),
+        (Schema::Bool, Some([1])) => Value::Bool(true),
+        (Schema::Int, Some(buf)) => {
+            let n = number_from_u8(buf);
+            if number_to_atom_bytes(&n) != buf {
+                return json_err(path, "non-canonical integer");
+            }
+            let n = match FastNumber::from_number(n) {
+                FastNumber::Small(v) if (0..=u64::MAX as i128).contains(&v) => {
+                    JsonNumber::from(v as u64)
+                }
+                FastNumber::Small(v) if (i64::MIN as i128..0).contains(&v) => {
+                    JsonNumber::from(v as i64)
+                }
+                _ => return json_err(path, "integer out of range"),
+            };
+            Value::Number(n)
+        }
+        (Schema::String, Some(buf)) => match std::str::from_utf8(buf) {
+            Ok(s) => Value::String(s.to_string()),
+            Err(_) => return json_err(path, "expected utf-8"),
+        },
+        (Schema::Hex, Some(buf)) => Value::String(hex::encode(buf)),
+        (Schema::Optional(_), Some([])) => Value::Null,
+        (Schema::Optional(item), None) => {
+            let items = list_items(allocator, node, Some(1), path)?;
+            node_to_json_at(allocator, &items[0], item, path)?
+        }
+        (Schema::List(item), _) => {
+            let items = list_items(allocator, node, None, path)?;
+            let mut values = Vec::with_capacity(items.len());
+            for (i, n) in items.iter().enumerate() {
+                let path = format!("{}[{}]", path, i);
+                values.push(node_to_json_at(allocator, n, item, &path)?);
+            }
+            Value::Array(values)
+        }
+        (Schema::Tuple(schemas), _) => {
+            let items = list_items(allocator, node, Some(schemas.len()), path)?;
+            let mut values = Vec::with_capacity(items.len());
+            for (i, (n, s)) in items.iter().zip(schemas).enumerate() {
+                let path = format!("{}[{}]", path, i);
+                values.push(node_to_json_at(allocator, n, s, &path)?);
+            }
+            Value::Array(values)
+        }
+        (Schema::Object(fields), _) => {
+            let items = list_items(allocator, node, Some(fields.len()), path)?;
+            let mut map = Map::new();
+            for (n, (k, s)) in items.iter().zip(fields) {
+                let path = format!("{}.{}", path, k);
+                map.insert(k.clone(), node_to_json_at(allocator, n, s, &path)?);
+            }
+            Value::Object(map)
+        }
+        (Schema::Null, _) => return json_err(path, "expected ()"),
+        (Schema::Bool, _) => return json_err(path, "expected 1 or ()"),
+        (Schema::Optional(_), _) => return json_err(path, "expected () or a list of one"),
+        _ => return json_err(path, "expected an atom"),
+    })
+}
+
+// the inverse of json_to_node() for the same schema
+pub fn node_to_json<T: Allocator>(
+    allocator: &T,
+    node: &T::Ptr,
+    schema: &Schema,
+) -> Result<Value, JsonError> {
+    node_to_json_at(allocator, node, schema, "$")
+}
+
+#[cfg(test)]
+use crate::int_allocator::IntAllocator;
+
+#[cfg(test)]
+use crate::{binutils::disassemble, node::Node};
+
+#[test]
+fn test_json_round_trip() {
+    use serde_json::json;
+
+    let schema = Schema::object(&[
+        ("name", Schema::String),
+        ("amount", Schema::Int),
+        ("puzzle_hash", Schema::Hex),
+        ("memo", Schema::optional(Schema::String)),
+        ("flags", Schema::list(Schema::Bool)),
+        ("range", Schema::Tuple(vec![Schema::Int, Schema::Int])),
+        ("none", Schema::Null),
+    ]);
+    let value = json!({
+        "range": [-1, 18446744073709551615u64],
+        "flags": [true, false],
+        "memo": null,
+        "puzzle_hash": "cafe",
+        "amount": 1000,
+        "name": "coin",
+        "none": null,
+    });
+
+    let mut a = IntAllocator::new();
+    let node = json_to_node(&mut a, &value, &schema).unwrap();
+    // in the declared order
+    assert_eq!(
+        disassemble(&Node::new(&a, node)),
+        "(\"coin\" 1000 -13570 () (q ()) (-1 0x00ffffffffffffffff) ())"
+    );
+    assert_eq!(node_to_json(&a, &node, &schema).unwrap(), value);
+
+    let value = json!({
+        "range": [0, 0],
+        "flags": [],
+        "memo": "hello",
+        "puzzle_hash": "",
+        "amount": -5,
+        "name": "",
+        "none": null,
+    });
+    let node = json_to_node(&mut a, &value, &schema).unwrap();
+    assert_eq!(
+        disassemble(&Node::new(&a, node)),
+        "(() -5 () (\"hello\") () (() ()) ())"
+    );
+    assert_eq!(node_to_json(&a, &node, &schema).unwrap(), value);
+}
+
+#[test]
+fn test_json_errors() {
+    use crate::binutils::assemble;
+    use serde_json::json;
+
+    let schema = Schema::object(&[("a", Schema::Int), ("b", Schema::list(Schema::Hex))]);
+    let mut a = IntAllocator::new();
+    let mut to_node = |v: Value| json_to_node(&mut a, &v, &schema).unwrap_err().to_string();
+    assert_eq!(to_node(json!([])), "$: expected an object");
+    assert_eq!(to_node(json!({"a": 1})), "$.b: missing");
+    assert_eq!(
+        to_node(json!({"a": 1, "b": [], "c": 2})),
+        "$: unexpected key \"c\""
+    );
+    assert_eq!(
+        to_node(json!({"a": 1.5, "b": []})),
+        "$.a: expected an integer"
+    );
+    assert_eq!(
+        to_node(json!({"a": 1, "b": ["00", "xyz"]})),
+        "$.b[1]: expected hex"
+    );
+
+    let mut to_json = |text: &str| {
+        let node = assemble(&mut a, text).unwrap();
+        node_to_json(&a, &node, &schema).unwrap_err().to_string()
+    };
+    assert_eq!(to_json("(1)"), "$: expected 2 items");
+    assert_eq!(to_json("(1 2 3)"), "$: expected 2 items");
+    assert_eq!(to_json("((1) ())"), "$.a: expected an atom");
+    assert_eq!(to_json("(0x0001 ())"), "$.a: non-canonical integer");
+    assert_eq!(
+        to_json("(0x010000000000000000 ())"),
+        "$.a: integer out of range"
+    );
+    assert_eq!(to_json("(1 (2 . 3))"), "$.b: expected a list");
+
+    let node = assemble(&mut a, "(2)").unwrap();
+    assert_eq!(
+        node_to_json(&a, &node, &Schema::optional(Schema::Bool))
+            .unwrap_err()
+            .to_string(),
+        "$: expected 1 or ()"
+    );
+}
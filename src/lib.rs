@@ -1,16 +1,84 @@
-mod allocator;
-mod core_ops;
-mod cost;
-mod err_utils;
-mod int_allocator;
-mod more_ops;
-mod node;
-mod number;
-mod op_utils;
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+// strings and collections come from alloc, which std re-exports, so the core
+// VM only needs an allocator
+extern crate alloc;
+
+pub mod allocator;
+#[cfg(feature = "std")]
+pub mod binutils;
+pub mod cbor;
+#[cfg(feature = "testing")]
+pub mod conformance;
+pub mod copy;
+pub mod core_ops;
+pub mod cost;
+#[cfg(feature = "testing")]
+pub mod cost_conformance;
+pub mod curry;
+pub mod diff;
+pub mod err_utils;
+#[cfg(feature = "std")]
+pub mod evaluator;
+#[cfg(feature = "testing")]
+pub mod fault_allocator;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+pub mod generator;
+pub mod int_allocator;
+#[cfg(feature = "std")]
+pub mod io_utils;
+#[cfg(feature = "std")]
+pub mod ir;
+#[cfg(feature = "jni")]
+mod jni;
+#[cfg(feature = "json")]
+pub mod json;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod modular;
+pub mod more_ops;
+pub mod node;
+#[cfg(feature = "nodejs")]
+mod nodejs;
+pub mod number;
+pub mod op_table;
+pub mod op_utils;
+#[cfg(feature = "std")]
+pub mod profile;
+#[cfg(feature = "std")]
+pub mod program_store;
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
+#[cfg(feature = "py-bindings")]
 mod py;
-mod reduction;
-mod run_program;
-mod serialize;
+mod read_cache_lookup;
+#[cfg(feature = "std")]
+pub mod recording;
+pub mod reduction;
+#[cfg(feature = "testing")]
+pub mod reference;
+#[cfg(feature = "async")]
+pub mod run_async;
+pub mod run_program;
+pub mod serialize;
+pub mod sha256;
+#[cfg(feature = "std")]
+pub mod spec;
+#[cfg(feature = "testing")]
+pub mod strategies;
+#[cfg(feature = "std")]
+pub mod symbols;
+pub mod sync_allocator;
+#[cfg(feature = "std")]
+pub mod trace;
+pub mod tree_hash;
+#[cfg(feature = "testing")]
+pub mod vectors;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,232 @@
+// Counters for the health of the VM, for exporting to e.g. Prometheus. The
+// evaluator reports to a process wide recorder, set with set_recorder(), so
+// embedders don't have to wrap every call site. Counters is a recorder that
+// keeps totals and renders them in the Prometheus text format; anything else
+// (e.g. an adapter to a metrics library) implements MetricsRecorder
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use lazy_static::lazy_static;
+
+use crate::cost::Cost;
+
+// every method defaults to doing nothing, so a recorder only implements the
+// ones it cares about
+pub trait MetricsRecorder: Send + Sync {
+    fn evaluation_started(&self) {}
+
+    // cost is the total cost of the evaluation, or what it had spent when it
+    // failed
+    fn evaluation_completed(&self, _cost: Cost) {}
+    fn evaluation_failed(&self, _cost: Cost) {}
+
+    // the memory held by the allocator after an evaluation, for allocators
+    // that keep track of it
+    fn arena_bytes(&self, _bytes: usize) {}
+
+    // an operator that isn't implemented natively ran, as an unknown
+    // operator
+    fn fallback_op(&self, _opcode: &[u8]) {}
+}
+
+lazy_static! {
+    static ref RECORDER: RwLock<Option<Arc<dyn MetricsRecorder>>> = RwLock::new(None);
+}
+
+pub fn set_recorder(recorder: Arc<dyn MetricsRecorder>) {
+    *RECORDER.write().unwrap() = Some(recorder);
+}
+
+pub fn clear_recorder() {
+    *RECORDER.write().unwrap() = None;
+}
+
+// calls f with the recorder, if there is one
+pub(crate) fn record<F: FnOnce(&dyn MetricsRecorder)>(f: F) {
+    if let Some(recorder) = &*RECORDER.read().unwrap() {
+        f(recorder.as_ref());
+    }
+}
+
+#[derive(Default)]
+pub struct Counters {
+    started: AtomicU64,
+    completed: AtomicU64,
+    failed: AtomicU64,
+    cost: AtomicU64,
+    arena_bytes: AtomicU64,
+    fallback_ops: AtomicU64,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Snapshot {
+    pub started: u64,
+    pub completed: u64,
+    pub failed: u64,
+    // of completed and failed evaluations
+    pub cost: u64,
+    // as last reported
+    pub arena_bytes: u64,
+    pub fallback_ops: u64,
+}
+
+impl Counters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            started: self.started.load(Ordering::Relaxed),
+            completed: self.completed.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            cost: self.cost.load(Ordering::Relaxed),
+            arena_bytes: self.arena_bytes.load(Ordering::Relaxed),
+            fallback_ops: self.fallback_ops.load(Ordering::Relaxed),
+        }
+    }
+
+    // the counters in the Prometheus text exposition format, for serving
+    // from a /metrics endpoint
+    pub fn prometheus(&self) -> String {
+        let s = self.snapshot();
+        let mut out = String::new();
+        for (name, kind, help, value) in &[
+            (
+                "clvm_evaluations_started_total",
+                "counter",
+                "Evaluations started",
+                s.started,
+            ),
+            (
+                "clvm_evaluations_completed_total",
+                "counter",
+                "Evaluations that returned a result",
+                s.completed,
+            ),
+            (
+                "clvm_evaluations_failed_total",
+                "counter",
+                "Evaluations that failed",
+                s.failed,
+            ),
+            (
+                "clvm_cost_total",
+                "counter",
+                "Cost spent by finished evaluations",
+                s.cost,
+            ),
+            (
+                "clvm_arena_bytes",
+                "gauge",
+                "Memory held by the allocator after the last evaluation",
+                s.arena_bytes,
+            ),
+            (
+                "clvm_fallback_ops_total",
+                "counter",
+                "Unknown operators run",
+                s.fallback_ops,
+            ),
+        ] {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} {}", name, kind).unwrap();
+            writeln!(out, "{} {}", name, value).unwrap();
+        }
+        out
+    }
+}
+
+impl MetricsRecorder for Counters {
+    fn evaluation_started(&self) {
+        self.started.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn evaluation_completed(&self, cost: Cost) {
+        self.completed.fetch_add(1, Ordering::Relaxed);
+        self.cost.fetch_add(cost.get(), Ordering::Relaxed);
+    }
+
+    fn evaluation_failed(&self, cost: Cost) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+        self.cost.fetch_add(cost.get(), Ordering::Relaxed);
+    }
+
+    fn arena_bytes(&self, bytes: usize) {
+        self.arena_bytes.store(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn fallback_op(&self, _opcode: &[u8]) {
+        self.fallback_ops.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[test]
+fn test_counters() {
+    let c = Counters::new();
+    c.evaluation_started();
+    c.evaluation_started();
+    c.evaluation_completed(Cost::new(100));
+    c.evaluation_failed(Cost::new(20));
+    c.arena_bytes(4096);
+    c.fallback_op(&[0x7f]);
+    assert_eq!(
+        c.snapshot(),
+        Snapshot {
+            started: 2,
+            completed: 1,
+            failed: 1,
+            cost: 120,
+            arena_bytes: 4096,
+            fallback_ops: 1,
+        }
+    );
+    let text = c.prometheus();
+    assert!(text.contains(
+        "# HELP clvm_evaluations_started_total Evaluations started\n\
+         # TYPE clvm_evaluations_started_total counter\n\
+         clvm_evaluations_started_total 2\n"
+    ));
+    assert!(text.contains("\nclvm_cost_total 120\n"));
+    assert!(text.contains("# TYPE clvm_arena_bytes gauge\nclvm_arena_bytes 4096\n"));
+}
+
+#[test]
+fn test_recorder() {
+    use crate::allocator::Allocator;
+    use crate::binutils::assemble;
+    use crate::int_allocator::IntAllocator;
+    use crate::op_table::{op_table_for, CHIA_DIALECT};
+    use crate::run_program::run_program;
+
+    let counters = Arc::new(Counters::new());
+    set_recorder(counters.clone());
+    let mut a = IntAllocator::new();
+    let env = a.null();
+    let run = |a: &mut IntAllocator, program: &str| {
+        let program = assemble(a, program).unwrap();
+        run_program(
+            a,
+            &program,
+            &env,
+            1,
+            2,
+            Cost::ZERO,
+            Box::new(op_table_for(CHIA_DIALECT.opcodes)),
+            None,
+        )
+    };
+    run(&mut a, "(0x7f (q . 1))").unwrap();
+    run(&mut a, "(x)").unwrap_err();
+    clear_recorder();
+    // other tests may be running programs at the same time
+    let s = counters.snapshot();
+    assert!(s.started >= 2);
+    assert!(s.completed >= 1);
+    assert!(s.failed >= 1);
+    assert!(s.fallback_ops >= 1);
+    assert!(s.cost > 0);
+    assert!(s.arena_bytes > 0);
+}
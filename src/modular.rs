@@ -0,0 +1,153 @@
+use alloc::vec::Vec;
+
+use crate::number::{
+    number_from_u8, number_mod_pow, number_sign, number_to_atom_bytes, Number, Sign,
+};
+
+// Modular arithmetic over atoms. Operands are interpreted the same way
+// operators interpret integer atoms (signed, big-endian) and results are
+// minimally encoded atoms in the range [0, modulus). Off-chain code that needs
+// to agree with on-chain results should use these rather than reimplementing
+// the reduction and encoding rules. A Modulus is decoded once, so code that
+// uses the same modulus repeatedly should keep it rather than use the
+// one-off helpers below
+#[derive(Debug, Clone, PartialEq)]
+pub struct Modulus {
+    m: Number,
+}
+
+impl Modulus {
+    // returns None unless the atom encodes a positive integer
+    pub fn from_atom(buf: &[u8]) -> Option<Self> {
+        let m = number_from_u8(buf);
+        if number_sign(&m) != Sign::Plus {
+            return None;
+        }
+        Some(Modulus { m })
+    }
+
+    pub fn value(&self) -> &Number {
+        &self.m
+    }
+
+    // reduce into [0, m). The remainder operator truncates towards zero, so
+    // negative inputs need to be shifted up
+    pub fn reduce_number(&self, n: &Number) -> Number {
+        let r: Number = n % &self.m;
+        if number_sign(&r) == Sign::Minus {
+            r + &self.m
+        } else {
+            r
+        }
+    }
+
+    pub fn reduce(&self, a: &[u8]) -> Vec<u8> {
+        number_to_atom_bytes(&self.reduce_number(&number_from_u8(a)))
+    }
+
+    pub fn add(&self, a: &[u8], b: &[u8]) -> Vec<u8> {
+        let r = number_from_u8(a) + number_from_u8(b);
+        number_to_atom_bytes(&self.reduce_number(&r))
+    }
+
+    pub fn sub(&self, a: &[u8], b: &[u8]) -> Vec<u8> {
+        let r = number_from_u8(a) - number_from_u8(b);
+        number_to_atom_bytes(&self.reduce_number(&r))
+    }
+
+    pub fn mul(&self, a: &[u8], b: &[u8]) -> Vec<u8> {
+        let r = number_from_u8(a) * number_from_u8(b);
+        number_to_atom_bytes(&self.reduce_number(&r))
+    }
+
+    // returns None for negative exponents
+    pub fn pow(&self, base: &[u8], exp: &[u8]) -> Option<Vec<u8>> {
+        let exp = number_from_u8(exp);
+        if number_sign(&exp) == Sign::Minus {
+            return None;
+        }
+        let base = self.reduce_number(&number_from_u8(base));
+        let r = number_mod_pow(&base, &exp, &self.m);
+        Some(number_to_atom_bytes(&r))
+    }
+}
+
+// convenience wrappers for one-off operations. These return None if the
+// modulus isn't positive
+pub fn mod_add(a: &[u8], b: &[u8], modulus: &[u8]) -> Option<Vec<u8>> {
+    Modulus::from_atom(modulus).map(|m| m.add(a, b))
+}
+
+pub fn mod_sub(a: &[u8], b: &[u8], modulus: &[u8]) -> Option<Vec<u8>> {
+    Modulus::from_atom(modulus).map(|m| m.sub(a, b))
+}
+
+pub fn mod_mul(a: &[u8], b: &[u8], modulus: &[u8]) -> Option<Vec<u8>> {
+    Modulus::from_atom(modulus).map(|m| m.mul(a, b))
+}
+
+pub fn mod_pow(base: &[u8], exp: &[u8], modulus: &[u8]) -> Option<Vec<u8>> {
+    Modulus::from_atom(modulus).and_then(|m| m.pow(base, exp))
+}
+
+#[test]
+fn test_modulus() {
+    assert_eq!(Modulus::from_atom(&[]), None);
+    assert_eq!(Modulus::from_atom(&[0]), None);
+    assert_eq!(Modulus::from_atom(&[0xff]), None);
+
+    let m = Modulus::from_atom(&[7]).unwrap();
+    assert_eq!(m.reduce(&[]), Vec::<u8>::new());
+    assert_eq!(m.reduce(&[7]), Vec::<u8>::new());
+    assert_eq!(m.reduce(&[9]), vec![2]);
+    // -1 mod 7 is 6, not -1
+    assert_eq!(m.reduce(&[0xff]), vec![6]);
+
+    assert_eq!(m.add(&[5], &[4]), vec![2]);
+    assert_eq!(m.sub(&[3], &[4]), vec![6]);
+    assert_eq!(m.mul(&[3], &[0xfd]), vec![5]);
+
+    // results are minimally encoded, with a leading zero when the top bit is
+    // set
+    let m = Modulus::from_atom(&[0x00, 0xfb]).unwrap();
+    assert_eq!(m.add(&[0x00, 0xfa], &[]), vec![0x00, 0xfa]);
+    assert_eq!(m.add(&[0x00, 0xfa], &[1]), Vec::<u8>::new());
+    assert_eq!(m.sub(&[], &[1]), vec![0x00, 0xfa]);
+}
+
+#[test]
+fn test_mod_helpers() {
+    assert_eq!(mod_add(&[1], &[2], &[]), None);
+    assert_eq!(mod_sub(&[1], &[2], &[0x80]), None);
+    assert_eq!(mod_mul(&[1], &[2], &[0]), None);
+
+    assert_eq!(mod_add(&[10], &[20], &[11]), Some(vec![8]));
+    assert_eq!(mod_sub(&[10], &[20], &[11]), Some(vec![1]));
+    assert_eq!(mod_mul(&[10], &[20], &[11]), Some(vec![2]));
+
+    // a modulus wider than the operands
+    let big = [0x01, 0, 0, 0, 0, 0, 0, 0, 0];
+    assert_eq!(
+        mod_mul(&[0x00, 0xff, 0xff, 0xff, 0xff], &[0x01, 0, 0, 0, 0], &big),
+        Some(vec![0x00, 0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0])
+    );
+}
+
+#[test]
+fn test_mod_pow() {
+    assert_eq!(mod_pow(&[2], &[10], &[]), None);
+    assert_eq!(mod_pow(&[2], &[0xff], &[7]), None);
+
+    // 2^10 = 1024 = 146 * 7 + 2
+    assert_eq!(mod_pow(&[2], &[10], &[7]), Some(vec![2]));
+    assert_eq!(mod_pow(&[5], &[], &[7]), Some(vec![1]));
+    // (-2)^3 = -8, which is 6 mod 7
+    assert_eq!(mod_pow(&[0xfe], &[3], &[7]), Some(vec![6]));
+    // 3^200 mod 1000003
+    let m = Modulus::from_atom(&[0x0f, 0x42, 0x43]).unwrap();
+    let mut expected = vec![1];
+    for _ in 0..200 {
+        expected = m.mul(&expected, &[3]);
+    }
+    assert_eq!(m.pow(&[3], &[0x00, 200]), Some(expected));
+}
@@ -1,9 +1,10 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
 use bls12_381::{G1Affine, G1Projective, Scalar};
-use num_bigint::{BigUint, Sign};
-use std::convert::TryFrom;
-use std::ops::BitAndAssign;
-use std::ops::BitOrAssign;
-use std::ops::BitXorAssign;
+use core::ops::BitAndAssign;
+use core::ops::BitOrAssign;
+use core::ops::BitXorAssign;
 
 use lazy_static::lazy_static;
 
@@ -11,97 +12,107 @@ use crate::allocator::Allocator;
 use crate::cost::{check_cost, Cost};
 use crate::err_utils::{err, u8_err};
 use crate::node::Node;
-use crate::number::{number_from_u8, ptr_from_number, Number};
+use crate::number::{
+    fast_number_from_u8, number_bits, number_from_hex, number_from_u8, number_from_unsigned_u8,
+    number_sign, number_to_magnitude_le, number_to_u64, ptr_from_fast_number, FastNumber, Number,
+    Sign,
+};
 use crate::op_utils::{
-    arg_count, atom, check_arg_count, i32_atom, int_atom, two_ints, u32_from_u8,
+    arg_count, arg_iter, atom, atom_ptr, atom_ref, check_arg_count, check_int_size, i32_atom,
+    int_atom, int_atom_ptr, shift_atom, two_ints, u32_from_u8,
 };
 use crate::reduction::{Reduction, Response};
 use crate::serialize::node_to_bytes;
+use crate::sha256::Sha256;
 
-#[cfg(windows)]
-use sha2::{Digest, Sha256};
-
-#[cfg(unix)]
-use openssl::sha;
+// The *_BASE_COST of an operator is charged by the dispatcher (see OpTable),
+// the operators themselves only return the cost that depends on their
+// arguments
 
 // We ascribe some additional cost per byte for operations that allocate new atoms
-const MALLOC_COST_PER_BYTE: Cost = 10;
+const MALLOC_COST_PER_BYTE: Cost = Cost::new(10);
 
-const ARITH_BASE_COST: Cost = 99;
-const ARITH_COST_PER_ARG: Cost = 320;
-const ARITH_COST_PER_BYTE: Cost = 3;
+pub const ARITH_BASE_COST: Cost = Cost::new(99);
+pub const ARITH_COST_PER_ARG: Cost = Cost::new(320);
+pub const ARITH_COST_PER_BYTE: Cost = Cost::new(3);
 
-const LOG_BASE_COST: Cost = 100;
-const LOG_COST_PER_ARG: Cost = 264;
-const LOG_COST_PER_BYTE: Cost = 3;
+pub const LOG_BASE_COST: Cost = Cost::new(100);
+pub const LOG_COST_PER_ARG: Cost = Cost::new(264);
+pub const LOG_COST_PER_BYTE: Cost = Cost::new(3);
 
-const LOGNOT_BASE_COST: Cost = 331;
-const LOGNOT_COST_PER_BYTE: Cost = 3;
+pub const LOGNOT_BASE_COST: Cost = Cost::new(331);
+pub const LOGNOT_COST_PER_BYTE: Cost = Cost::new(3);
 
-const MUL_BASE_COST: Cost = 92;
-const MUL_COST_PER_OP: Cost = 885;
-const MUL_LINEAR_COST_PER_BYTE: Cost = 6;
-const MUL_SQUARE_COST_PER_BYTE_DIVIDER: Cost = 128;
+pub const MUL_BASE_COST: Cost = Cost::new(92);
+pub const MUL_COST_PER_OP: Cost = Cost::new(885);
+pub const MUL_LINEAR_COST_PER_BYTE: Cost = Cost::new(6);
+const MUL_SQUARE_COST_PER_BYTE_DIVIDER: u64 = 128;
 
-const GR_BASE_COST: Cost = 498;
-const GR_COST_PER_BYTE: Cost = 2;
+pub const GR_BASE_COST: Cost = Cost::new(498);
+pub const GR_COST_PER_BYTE: Cost = Cost::new(2);
 
-const GRS_BASE_COST: Cost = 117;
-const GRS_COST_PER_BYTE: Cost = 1;
+pub const GRS_BASE_COST: Cost = Cost::new(117);
+pub const GRS_COST_PER_BYTE: Cost = Cost::new(1);
 
-const STRLEN_BASE_COST: Cost = 173;
-const STRLEN_COST_PER_BYTE: Cost = 1;
+pub const STRLEN_BASE_COST: Cost = Cost::new(173);
+pub const STRLEN_COST_PER_BYTE: Cost = Cost::new(1);
 
-const CONCAT_BASE_COST: Cost = 142;
-const CONCAT_COST_PER_ARG: Cost = 135;
-const CONCAT_COST_PER_BYTE: Cost = 3;
+pub const SUBSTR_BASE_COST: Cost = Cost::new(1);
 
-const DIVMOD_BASE_COST: Cost = 1116;
-const DIVMOD_COST_PER_BYTE: Cost = 6;
+pub const CONCAT_BASE_COST: Cost = Cost::new(142);
+pub const CONCAT_COST_PER_ARG: Cost = Cost::new(135);
+pub const CONCAT_COST_PER_BYTE: Cost = Cost::new(3);
 
-const DIV_BASE_COST: Cost = 988;
-const DIV_COST_PER_BYTE: Cost = 4;
+pub const DIVMOD_BASE_COST: Cost = Cost::new(1116);
+pub const DIVMOD_COST_PER_BYTE: Cost = Cost::new(6);
 
-const SHA256_BASE_COST: Cost = 87;
-const SHA256_COST_PER_ARG: Cost = 134;
-const SHA256_COST_PER_BYTE: Cost = 2;
+pub const DIV_BASE_COST: Cost = Cost::new(988);
+pub const DIV_COST_PER_BYTE: Cost = Cost::new(4);
 
-const ASHIFT_BASE_COST: Cost = 596;
-const ASHIFT_COST_PER_BYTE: Cost = 3;
+pub const SHA256_BASE_COST: Cost = Cost::new(87);
+pub const SHA256_COST_PER_ARG: Cost = Cost::new(134);
+pub const SHA256_COST_PER_BYTE: Cost = Cost::new(2);
 
-const LSHIFT_BASE_COST: Cost = 277;
-const LSHIFT_COST_PER_BYTE: Cost = 3;
+pub const ASHIFT_BASE_COST: Cost = Cost::new(596);
+pub const ASHIFT_COST_PER_BYTE: Cost = Cost::new(3);
 
-const BOOL_BASE_COST: Cost = 200;
-const BOOL_COST_PER_ARG: Cost = 300;
+pub const LSHIFT_BASE_COST: Cost = Cost::new(277);
+pub const LSHIFT_COST_PER_BYTE: Cost = Cost::new(3);
+
+pub const BOOL_BASE_COST: Cost = Cost::new(200);
+pub const BOOL_COST_PER_ARG: Cost = Cost::new(300);
 
 // Raspberry PI 4 is about 7.679960 / 1.201742 = 6.39 times slower
 // in the point_add benchmark
 
 // increased from 31592 to better model Raspberry PI
-const POINT_ADD_BASE_COST: Cost = 101094;
+pub const POINT_ADD_BASE_COST: Cost = Cost::new(101094);
 // increased from 419994 to better model Raspberry PI
-const POINT_ADD_COST_PER_ARG: Cost = 1343980;
+pub const POINT_ADD_COST_PER_ARG: Cost = Cost::new(1343980);
 
 // Raspberry PI 4 is about 2.833543 / 0.447859 = 6.32686 times slower
 // in the pubkey benchmark
 
 // increased from 419535 to better model Raspberry PI
-const PUBKEY_BASE_COST: Cost = 1325730;
+pub const PUBKEY_BASE_COST: Cost = Cost::new(1325730);
 // increased from 12 to closer model Raspberry PI
-const PUBKEY_COST_PER_BYTE: Cost = 38;
+pub const PUBKEY_COST_PER_BYTE: Cost = Cost::new(38);
+
+fn limbs_for_bits(bits: u64) -> usize {
+    bits.div_ceil(8) as usize
+}
 
 fn limbs_for_int(v: &Number) -> usize {
-    ((v.bits() + 7) / 8) as usize
+    limbs_for_bits(number_bits(v))
 }
 
 fn new_atom_and_cost<T: Allocator>(a: &mut T, cost: Cost, buf: &[u8]) -> Response<T::Ptr> {
-    let c = buf.len() as Cost * MALLOC_COST_PER_BYTE;
+    let c = MALLOC_COST_PER_BYTE * buf.len();
     Ok(Reduction(cost + c, a.new_atom(buf)?))
 }
 
 fn malloc_cost<T: Allocator>(a: &T, cost: Cost, ptr: T::Ptr) -> Reduction<T::Ptr> {
-    let c = a.atom(&ptr).len() as Cost * MALLOC_COST_PER_BYTE;
+    let c = MALLOC_COST_PER_BYTE * a.atom(&ptr).len();
     Reduction(cost + c, ptr)
 }
 
@@ -141,6 +152,11 @@ pub fn op_unknown<A: Allocator>(
 
     let op = allocator.buf(&o);
 
+    #[cfg(feature = "tracing")]
+    tracing::debug!(opcode = %hex::encode(op), "unknown operator");
+    #[cfg(feature = "metrics")]
+    crate::metrics::record(|m| m.fallback_op(op));
+
     if op.is_empty() || (op.len() >= 2 && op[0] == 0xff && op[1] == 0xff) {
         return u8_err(allocator, &o, "reserved operator");
     }
@@ -154,24 +170,20 @@ pub fn op_unknown<A: Allocator>(
     };
 
     let mut cost = match cost_function {
-        0 => 1,
+        0 => Cost::new(1),
         1 => {
-            let mut cost = ARITH_BASE_COST as u64;
+            let mut cost = ARITH_BASE_COST;
             let mut byte_count: u64 = 0;
             for arg in Node::new(allocator, args) {
-                cost += ARITH_COST_PER_ARG as u64;
+                cost += ARITH_COST_PER_ARG;
                 let blob = int_atom(&arg, "unknown op")?;
-                byte_count += blob.len() as u64;
-                check_cost(
-                    allocator,
-                    cost + (byte_count as Cost * ARITH_COST_PER_BYTE),
-                    max_cost,
-                )?;
+                byte_count = byte_count.saturating_add(blob.len() as u64);
+                check_cost(allocator, cost + ARITH_COST_PER_BYTE * byte_count, max_cost)?;
             }
-            cost + (byte_count * ARITH_COST_PER_BYTE as u64)
+            cost + ARITH_COST_PER_BYTE * byte_count
         }
         2 => {
-            let mut cost = MUL_BASE_COST as u64;
+            let mut cost = MUL_BASE_COST;
             let mut first_iter: bool = true;
             let mut l0: u64 = 0;
             for arg in Node::new(allocator, args) {
@@ -182,40 +194,41 @@ pub fn op_unknown<A: Allocator>(
                     continue;
                 }
                 let l1 = blob.len() as u64;
-                cost += MUL_COST_PER_OP as u64;
-                cost += (l0 + l1) * MUL_LINEAR_COST_PER_BYTE as u64;
-                cost += (l0 * l1) / MUL_SQUARE_COST_PER_BYTE_DIVIDER as u64;
-                l0 += l1;
+                cost += MUL_COST_PER_OP;
+                cost += MUL_LINEAR_COST_PER_BYTE * l0.saturating_add(l1);
+                // l0 is the size of every argument so far, which is unbounded
+                cost += Cost::new(l0.saturating_mul(l1) / MUL_SQUARE_COST_PER_BYTE_DIVIDER);
+                l0 = l0.saturating_add(l1);
                 check_cost(allocator, cost, max_cost)?;
             }
             cost
         }
         3 => {
-            let mut cost = CONCAT_BASE_COST as u64;
+            let mut cost = CONCAT_BASE_COST;
             let mut total_size: u64 = 0;
             for arg in Node::new(allocator, args) {
-                cost += CONCAT_COST_PER_ARG as u64;
+                cost += CONCAT_COST_PER_ARG;
                 let blob = atom(&arg, "unknown op")?;
-                total_size += blob.len() as u64;
+                total_size = total_size.saturating_add(blob.len() as u64);
                 check_cost(
                     allocator,
-                    cost + total_size as Cost * CONCAT_COST_PER_BYTE,
+                    cost + CONCAT_COST_PER_BYTE * total_size,
                     max_cost,
                 )?;
             }
-            cost + total_size * CONCAT_COST_PER_BYTE as u64
+            cost + CONCAT_COST_PER_BYTE * total_size
         }
-        _ => 1,
+        _ => Cost::new(1),
     };
 
-    assert!(cost > 0);
+    assert!(cost > Cost::ZERO);
 
     check_cost(allocator, cost, max_cost)?;
-    cost *= cost_multiplier + 1;
-    if cost > u32::MAX as u64 {
+    cost = cost * (cost_multiplier + 1);
+    if cost > Cost::new(u32::MAX as u64) {
         u8_err(allocator, &o, "invalid operator")
     } else {
-        Ok(Reduction(cost as Cost, allocator.null()))
+        Ok(Reduction(cost, allocator.null()))
     }
 }
 
@@ -228,7 +241,7 @@ fn test_op_unknown<A: Allocator>(buf: &[u8], a: &mut A, n: A::Ptr) -> Response<A
         SExp::Atom(abuf) => abuf,
         _ => panic!("shouldn't happen"),
     };
-    op_unknown(a, abuf, n, 1000000)
+    op_unknown(a, abuf, n, Cost::new(1000000))
 }
 
 #[test]
@@ -260,7 +273,7 @@ fn test_unknown_op_reserved() {
     let buf = vec![0xff];
     assert_eq!(
         test_op_unknown(&buf, &mut a, null),
-        Ok(Reduction(142, null))
+        Ok(Reduction(Cost::new(142), null))
     );
 
     // leading zeros count, so this is not considered an ffff-prefix
@@ -268,7 +281,7 @@ fn test_unknown_op_reserved() {
     // the cost is 0xffff00 = 16776960 plus the implied 1
     assert_eq!(
         test_op_unknown(&buf, &mut a, null),
-        Ok(Reduction(16776961, null))
+        Ok(Reduction(Cost::new(16776961), null))
     );
 }
 
@@ -279,90 +292,73 @@ fn test_lenient_mode_last_bits() {
     // the last 6 bits are ignored for computing cost
     let buf = vec![0x3c, 0x3f];
     let null = a.null();
-    assert_eq!(test_op_unknown(&buf, &mut a, null), Ok(Reduction(61, null)));
+    assert_eq!(
+        test_op_unknown(&buf, &mut a, null),
+        Ok(Reduction(Cost::new(61), null))
+    );
 
     let buf = vec![0x3c, 0x0f];
-    assert_eq!(test_op_unknown(&buf, &mut a, null), Ok(Reduction(61, null)));
+    assert_eq!(
+        test_op_unknown(&buf, &mut a, null),
+        Ok(Reduction(Cost::new(61), null))
+    );
 
     let buf = vec![0x3c, 0x00];
-    assert_eq!(test_op_unknown(&buf, &mut a, null), Ok(Reduction(61, null)));
+    assert_eq!(
+        test_op_unknown(&buf, &mut a, null),
+        Ok(Reduction(Cost::new(61), null))
+    );
 
     let buf = vec![0x3c, 0x2c];
-    assert_eq!(test_op_unknown(&buf, &mut a, null), Ok(Reduction(61, null)));
+    assert_eq!(
+        test_op_unknown(&buf, &mut a, null),
+        Ok(Reduction(Cost::new(61), null))
+    );
 }
 
-#[cfg(windows)]
 pub fn op_sha256<T: Allocator>(a: &mut T, input: T::Ptr, max_cost: Cost) -> Response<T::Ptr> {
-    let mut cost = SHA256_BASE_COST;
+    let mut cost = Cost::ZERO;
     let mut byte_count: usize = 0;
     let mut hasher = Sha256::new();
-    for arg in Node::new(a, input) {
-        cost += SHA256_COST_PER_ARG;
-        check_cost(
-            a,
-            cost + byte_count as Cost * SHA256_COST_PER_BYTE,
-            max_cost,
-        )?;
-        let blob = atom(&arg, "sha256")?;
-        byte_count += blob.len();
-        hasher.input(blob);
-    }
-    cost += byte_count as Cost * SHA256_COST_PER_BYTE;
-    new_atom_and_cost(a, cost, &hasher.result())
-}
-
-#[cfg(unix)]
-pub fn op_sha256<T: Allocator>(a: &mut T, input: T::Ptr, max_cost: Cost) -> Response<T::Ptr> {
-    let mut cost = SHA256_BASE_COST;
-    let mut byte_count: usize = 0;
-    let mut hasher = sha::Sha256::new();
-    for arg in Node::new(a, input) {
+    for arg in arg_iter(a, input) {
         cost += SHA256_COST_PER_ARG;
-        check_cost(
-            a,
-            cost + byte_count as Cost * SHA256_COST_PER_BYTE,
-            max_cost,
-        )?;
-        let blob = atom(&arg, "sha256")?;
-        byte_count += blob.len();
+        check_cost(a, cost + SHA256_COST_PER_BYTE * byte_count, max_cost)?;
+        let blob = atom_ptr(a, &arg, "sha256")?;
+        byte_count = byte_count.saturating_add(blob.len());
         hasher.update(blob);
     }
-    cost += byte_count as Cost * SHA256_COST_PER_BYTE;
+    cost += SHA256_COST_PER_BYTE * byte_count;
     new_atom_and_cost(a, cost, &hasher.finish())
 }
 
 pub fn op_add<T: Allocator>(a: &mut T, input: T::Ptr, max_cost: Cost) -> Response<T::Ptr> {
-    let mut cost = ARITH_BASE_COST;
+    let mut cost = Cost::ZERO;
     let mut byte_count: usize = 0;
-    let mut total: Number = 0.into();
-    for arg in Node::new(a, input) {
+    let mut total = FastNumber::Small(0);
+    for arg in arg_iter(a, input) {
         cost += ARITH_COST_PER_ARG;
-        check_cost(
-            a,
-            cost + (byte_count as Cost * ARITH_COST_PER_BYTE),
-            max_cost,
-        )?;
-        let blob = int_atom(&arg, "+")?;
-        let v: Number = number_from_u8(blob);
-        byte_count += blob.len();
+        check_cost(a, cost + (ARITH_COST_PER_BYTE * byte_count), max_cost)?;
+        let blob = int_atom_ptr(a, &arg, "+")?;
+        let v = fast_number_from_u8(blob);
+        byte_count = byte_count.saturating_add(blob.len());
         total += v;
     }
-    let total = ptr_from_number(a, &total)?;
-    cost += byte_count as Cost * ARITH_COST_PER_BYTE;
+    let total = ptr_from_fast_number(a, &total)?;
+    cost += ARITH_COST_PER_BYTE * byte_count;
     Ok(malloc_cost(a, cost, total))
 }
 
 pub fn op_subtract<T: Allocator>(a: &mut T, input: T::Ptr, max_cost: Cost) -> Response<T::Ptr> {
-    let mut cost = ARITH_BASE_COST;
+    let mut cost = Cost::ZERO;
     let mut byte_count: usize = 0;
-    let mut total: Number = 0.into();
+    let mut total = FastNumber::Small(0);
     let mut is_first = true;
-    for arg in Node::new(a, input) {
+    for arg in arg_iter(a, input) {
         cost += ARITH_COST_PER_ARG;
-        check_cost(a, cost + byte_count as Cost * ARITH_COST_PER_BYTE, max_cost)?;
-        let blob = int_atom(&arg, "-")?;
-        let v: Number = number_from_u8(blob);
-        byte_count += blob.len();
+        check_cost(a, cost + ARITH_COST_PER_BYTE * byte_count, max_cost)?;
+        let blob = int_atom_ptr(a, &arg, "-")?;
+        let v = fast_number_from_u8(blob);
+        byte_count = byte_count.saturating_add(blob.len());
         if is_first {
             total += v;
         } else {
@@ -370,44 +366,75 @@ pub fn op_subtract<T: Allocator>(a: &mut T, input: T::Ptr, max_cost: Cost) -> Re
         };
         is_first = false;
     }
-    let total = ptr_from_number(a, &total)?;
-    cost += byte_count as Cost * ARITH_COST_PER_BYTE;
+    let total = ptr_from_fast_number(a, &total)?;
+    cost += ARITH_COST_PER_BYTE * byte_count;
     Ok(malloc_cost(a, cost, total))
 }
 
 pub fn op_multiply<T: Allocator>(a: &mut T, input: T::Ptr, max_cost: Cost) -> Response<T::Ptr> {
-    let mut cost: Cost = MUL_BASE_COST;
+    op_multiply_limited(a, input, max_cost, 0)
+}
+
+// op_multiply, but failing rather than producing an integer of more than
+// max_int_bytes bytes. 0 means no limit
+pub fn op_multiply_limited<T: Allocator>(
+    a: &mut T,
+    input: T::Ptr,
+    max_cost: Cost,
+    max_int_bytes: usize,
+) -> Response<T::Ptr> {
+    let mut cost = Cost::ZERO;
     let mut first_iter: bool = true;
-    let mut total: Number = 1.into();
+    let mut total = FastNumber::Small(1);
     let mut l0: usize = 0;
-    for arg in Node::new(a, input) {
+    for arg in arg_iter(a, input) {
         check_cost(a, cost, max_cost)?;
-        let blob = int_atom(&arg, "*")?;
+        let blob = int_atom_ptr(a, &arg, "*")?;
         if first_iter {
             l0 = blob.len();
-            total = number_from_u8(blob);
+            total = fast_number_from_u8(blob);
             first_iter = false;
             continue;
         }
         let l1 = blob.len();
 
-        total *= number_from_u8(blob);
+        check_int_size(
+            &Node::new(a, arg.clone()),
+            l0.saturating_add(l1),
+            max_int_bytes,
+            "*",
+        )?;
+        total *= fast_number_from_u8(blob);
         cost += MUL_COST_PER_OP;
 
-        cost += (l0 + l1) as Cost * MUL_LINEAR_COST_PER_BYTE;
-        cost += (l0 * l1) as Cost / MUL_SQUARE_COST_PER_BYTE_DIVIDER;
+        cost += MUL_LINEAR_COST_PER_BYTE * l0.saturating_add(l1);
+        // in u64 rather than usize, which is only 32 bits on wasm. The sizes
+        // are only bounded by max_int_bytes, which is unlimited by default
+        cost += Cost::new((l0 as u64).saturating_mul(l1 as u64) / MUL_SQUARE_COST_PER_BYTE_DIVIDER);
 
-        l0 = limbs_for_int(&total);
+        l0 = limbs_for_bits(total.bits());
     }
-    let total = ptr_from_number(a, &total)?;
+    let total = ptr_from_fast_number(a, &total)?;
     Ok(malloc_cost(a, cost, total))
 }
 
 pub fn op_div<T: Allocator>(a: &mut T, input: T::Ptr, _max_cost: Cost) -> Response<T::Ptr> {
+    div(a, input, false)
+}
+
+// op_div, but rounding every negative quotient toward negative infinity, like
+// op_divmod. op_div only does that when the quotient doesn't truncate to 0,
+// so e.g. (/ -1 2) is 0 rather than -1. Dialects pick between them with
+// DivRounding
+pub fn op_div_floor<T: Allocator>(a: &mut T, input: T::Ptr, _max_cost: Cost) -> Response<T::Ptr> {
+    div(a, input, true)
+}
+
+fn div<T: Allocator>(a: &mut T, input: T::Ptr, floor: bool) -> Response<T::Ptr> {
     let args = Node::new(a, input);
     let (a0, l0, a1, l1) = two_ints(&args, "/")?;
-    let cost = DIV_BASE_COST + ((l0 + l1) as Cost) * DIV_COST_PER_BYTE;
-    if a1.sign() == Sign::NoSign {
+    let cost = DIV_COST_PER_BYTE * l0.saturating_add(l1);
+    if number_sign(&a1) == Sign::NoSign {
         args.first()?.err("div with 0")
     } else {
         let q = &a0 / &a1;
@@ -415,12 +442,17 @@ pub fn op_div<T: Allocator>(a: &mut T, input: T::Ptr, _max_cost: Cost) -> Respon
 
         // rust rounds division towards zero, but we want division to round
         // toward negative infinity.
-        let q = if q.sign() == Sign::Minus && r.sign() != Sign::NoSign {
+        let negative = if floor {
+            number_sign(&a0) != number_sign(&a1)
+        } else {
+            number_sign(&q) == Sign::Minus
+        };
+        let q = if negative && number_sign(&r) != Sign::NoSign {
             q - 1
         } else {
             q
         };
-        let q1 = ptr_from_number(a, &q)?;
+        let q1 = a.new_number(q)?;
         Ok(malloc_cost(a, cost, q1))
     }
 }
@@ -428,27 +460,27 @@ pub fn op_div<T: Allocator>(a: &mut T, input: T::Ptr, _max_cost: Cost) -> Respon
 pub fn op_divmod<T: Allocator>(a: &mut T, input: T::Ptr, _max_cost: Cost) -> Response<T::Ptr> {
     let args = Node::new(a, input);
     let (a0, l0, a1, l1) = two_ints(&args, "divmod")?;
-    let cost = DIVMOD_BASE_COST + ((l0 + l1) as Cost) * DIVMOD_COST_PER_BYTE;
-    if a1.sign() == Sign::NoSign {
+    let cost = DIVMOD_COST_PER_BYTE * l0.saturating_add(l1);
+    if number_sign(&a1) == Sign::NoSign {
         args.first()?.err("divmod with 0")
     } else {
         let q = &a0 / &a1;
         let r = &a0 - &a1 * &q;
 
-        let signed_quotient =
-            (a0.sign() == Sign::Minus || a1.sign() == Sign::Minus) && a0.sign() != a1.sign();
+        let signed_quotient = (number_sign(&a0) == Sign::Minus || number_sign(&a1) == Sign::Minus)
+            && number_sign(&a0) != number_sign(&a1);
 
         // rust rounds division towards zero, but we want division to round
         // toward negative infinity.
-        let (q, r) = if signed_quotient && r.sign() != Sign::NoSign {
+        let (q, r) = if signed_quotient && number_sign(&r) != Sign::NoSign {
             (q - 1, r + &a1)
         } else {
             (q, r)
         };
-        let q1 = ptr_from_number(a, &q)?;
-        let r1 = ptr_from_number(a, &r)?;
+        let q1 = a.new_number(q)?;
+        let r1 = a.new_number(r)?;
 
-        let c = (a.atom(&q1).len() + a.atom(&r1).len()) as Cost * MALLOC_COST_PER_BYTE;
+        let c = MALLOC_COST_PER_BYTE * (a.atom(&q1).len() + a.atom(&r1).len());
         let r: T::Ptr = a.new_pair(q1, r1)?;
         Ok(Reduction(cost + c, r))
     }
@@ -461,10 +493,10 @@ pub fn op_gr<T: Allocator>(a: &mut T, input: T::Ptr, _max_cost: Cost) -> Respons
     let a1 = args.rest()?.first()?;
     let v0 = int_atom(&a0, ">")?;
     let v1 = int_atom(&a1, ">")?;
-    let cost = GR_BASE_COST + (v0.len() + v1.len()) as Cost * GR_COST_PER_BYTE;
+    let cost = GR_COST_PER_BYTE * (v0.len() + v1.len());
     Ok(Reduction(
         cost,
-        if number_from_u8(v0) > number_from_u8(v1) {
+        if fast_number_from_u8(v0) > fast_number_from_u8(v1) {
             a.one()
         } else {
             a.null()
@@ -479,7 +511,7 @@ pub fn op_gr_bytes<T: Allocator>(a: &mut T, input: T::Ptr, _max_cost: Cost) -> R
     let a1 = args.rest()?.first()?;
     let v0 = atom(&a0, ">s")?;
     let v1 = atom(&a1, ">s")?;
-    let cost = GRS_BASE_COST + (v0.len() + v1.len()) as Cost * GRS_COST_PER_BYTE;
+    let cost = GRS_COST_PER_BYTE * (v0.len() + v1.len());
     Ok(Reduction(cost, if v0 > v1 { a.one() } else { a.null() }))
 }
 
@@ -490,8 +522,8 @@ pub fn op_strlen<T: Allocator>(a: &mut T, input: T::Ptr, _max_cost: Cost) -> Res
     let v0 = atom(&a0, "strlen")?;
     let size = v0.len();
     let size_num: Number = size.into();
-    let size_node = ptr_from_number(a, &size_num)?;
-    let cost = STRLEN_BASE_COST + size as Cost * STRLEN_COST_PER_BYTE;
+    let size_node = a.new_number(size_num)?;
+    let cost = STRLEN_COST_PER_BYTE * size;
     Ok(malloc_cost(a, cost, size_node))
 }
 
@@ -501,8 +533,7 @@ pub fn op_substr<T: Allocator>(a: &mut T, input: T::Ptr, _max_cost: Cost) -> Res
     if !(2..=3).contains(&ac) {
         return args.err("substr takes exactly 2 or 3 arguments");
     }
-    let a0 = args.first()?;
-    let s0 = atom(&a0, "substr")?;
+    let s0 = atom_ref(a, args.first()?.node, "substr")?;
     let size = s0.len();
     let rest = args.rest()?;
     let i1 = i32_atom(&rest.first()?, "substr")?;
@@ -516,85 +547,88 @@ pub fn op_substr<T: Allocator>(a: &mut T, input: T::Ptr, _max_cost: Cost) -> Res
     if i2 < 0 || i1 < 0 || i2 as usize > size || i2 < i1 {
         args.err("invalid indices for substr")
     } else {
-        let atom_node = a0.node;
-        let r = a.new_substr(atom_node, i1 as u32, i2 as u32)?;
-        let cost: Cost = 1;
-        Ok(Reduction(cost, r))
+        let r = a.new_substr(s0.node, i1 as u32, i2 as u32)?;
+        Ok(Reduction(Cost::ZERO, r))
     }
 }
 
 pub fn op_concat<T: Allocator>(a: &mut T, input: T::Ptr, max_cost: Cost) -> Response<T::Ptr> {
-    let args = Node::new(a, input);
-    let mut cost = CONCAT_BASE_COST;
+    let mut cost = Cost::ZERO;
     let mut total_size: usize = 0;
-    for arg in &args {
+    let mut atoms = Vec::new();
+    for arg in arg_iter(a, input) {
         cost += CONCAT_COST_PER_ARG;
-        check_cost(
-            a,
-            cost + total_size as Cost * CONCAT_COST_PER_BYTE,
-            max_cost,
-        )?;
-        let blob = atom(&arg, "concat")?;
-        total_size += blob.len();
+        check_cost(a, cost + CONCAT_COST_PER_BYTE * total_size, max_cost)?;
+        let blob = atom_ref(a, arg, "concat")?;
+        total_size = total_size.saturating_add(blob.len());
+        atoms.push(blob.buf().clone());
     }
 
-    cost += total_size as Cost * CONCAT_COST_PER_BYTE;
+    cost += CONCAT_COST_PER_BYTE * total_size;
     check_cost(a, cost, max_cost)?;
-    let mut v: Vec<u8> = Vec::with_capacity(total_size);
-
-    for arg in args {
-        let blob = arg.atom().unwrap();
-        v.extend_from_slice(blob);
-    }
+    let c = MALLOC_COST_PER_BYTE * total_size;
+    Ok(Reduction(cost + c, a.new_concat(total_size, &atoms)?))
+}
 
-    new_atom_and_cost(a, cost, &v)
+pub fn op_ash<T: Allocator>(a: &mut T, input: T::Ptr, max_cost: Cost) -> Response<T::Ptr> {
+    op_ash_limited(a, input, max_cost, 0)
 }
 
-pub fn op_ash<T: Allocator>(a: &mut T, input: T::Ptr, _max_cost: Cost) -> Response<T::Ptr> {
+pub fn op_ash_limited<T: Allocator>(
+    a: &mut T,
+    input: T::Ptr,
+    _max_cost: Cost,
+    max_int_bytes: usize,
+) -> Response<T::Ptr> {
     let args = Node::new(a, input);
     check_arg_count(&args, 2, "ash")?;
     let a0 = args.first()?;
     let b0 = int_atom(&a0, "ash")?;
     let i0 = number_from_u8(b0);
     let l0 = b0.len();
-    let rest = args.rest()?;
-    let a1 = i32_atom(&rest.first()?, "ash")?;
-    if a1.abs() > 65535 {
-        return args.rest()?.first()?.err("shift too large");
+    let a1 = shift_atom(&args.rest()?.first()?, "ash")?;
+    if a1 > 0 {
+        check_int_size(&args, l0 + limbs_for_bits(a1 as u64), max_int_bytes, "ash")?;
     }
 
     let v: Number = if a1 > 0 { i0 << a1 } else { i0 >> -a1 };
     let l1 = limbs_for_int(&v);
-    let r = ptr_from_number(a, &v)?;
-    let cost = ASHIFT_BASE_COST + ((l0 + l1) as Cost) * ASHIFT_COST_PER_BYTE;
+    let r = a.new_number(v)?;
+    let cost = ASHIFT_COST_PER_BYTE * l0.saturating_add(l1);
     Ok(malloc_cost(a, cost, r))
 }
 
-pub fn op_lsh<T: Allocator>(a: &mut T, input: T::Ptr, _max_cost: Cost) -> Response<T::Ptr> {
+pub fn op_lsh<T: Allocator>(a: &mut T, input: T::Ptr, max_cost: Cost) -> Response<T::Ptr> {
+    op_lsh_limited(a, input, max_cost, 0)
+}
+
+pub fn op_lsh_limited<T: Allocator>(
+    a: &mut T,
+    input: T::Ptr,
+    _max_cost: Cost,
+    max_int_bytes: usize,
+) -> Response<T::Ptr> {
     let args = Node::new(a, input);
     check_arg_count(&args, 2, "lsh")?;
     let a0 = args.first()?;
     let b0 = int_atom(&a0, "lsh")?;
-    let i0 = BigUint::from_bytes_be(b0);
+    let i0 = number_from_unsigned_u8(b0);
     let l0 = b0.len();
-    let rest = args.rest()?;
-    let a1 = i32_atom(&rest.first()?, "lsh")?;
-    if a1.abs() > 65535 {
-        return args.rest()?.first()?.err("shift too large");
+    let a1 = shift_atom(&args.rest()?.first()?, "lsh")?;
+    if a1 > 0 {
+        check_int_size(&args, l0 + limbs_for_bits(a1 as u64), max_int_bytes, "lsh")?;
     }
 
-    let i0: Number = i0.into();
-
     let v: Number = if a1 > 0 { i0 << a1 } else { i0 >> -a1 };
 
     let l1 = limbs_for_int(&v);
-    let r = ptr_from_number(a, &v)?;
-    let cost = LSHIFT_BASE_COST + ((l0 + l1) as Cost) * LSHIFT_COST_PER_BYTE;
+    let r = a.new_number(v)?;
+    let cost = LSHIFT_COST_PER_BYTE * l0.saturating_add(l1);
     Ok(malloc_cost(a, cost, r))
 }
 
 fn binop_reduction<T: Allocator>(
-    op_name: &str,
+    op_name: &'static str,
     a: &mut T,
     initial_value: Number,
     input: T::Ptr,
@@ -603,17 +637,17 @@ fn binop_reduction<T: Allocator>(
 ) -> Response<T::Ptr> {
     let mut total = initial_value;
     let mut arg_size: usize = 0;
-    let mut cost = LOG_BASE_COST;
-    for arg in Node::new(a, input) {
-        let blob = int_atom(&arg, op_name)?;
+    let mut cost = Cost::ZERO;
+    for arg in arg_iter(a, input) {
+        let blob = int_atom_ptr(a, &arg, op_name)?;
         let n0 = number_from_u8(blob);
         op_f(&mut total, &n0);
         arg_size += blob.len();
         cost += LOG_COST_PER_ARG;
-        check_cost(a, cost + (arg_size as Cost * LOG_COST_PER_BYTE), max_cost)?;
+        check_cost(a, cost + (LOG_COST_PER_BYTE * arg_size), max_cost)?;
     }
-    cost += arg_size as Cost * LOG_COST_PER_BYTE;
-    let total = ptr_from_number(a, &total)?;
+    cost += LOG_COST_PER_BYTE * arg_size;
+    let total = a.new_number(total)?;
     Ok(malloc_cost(a, cost, total))
 }
 
@@ -651,8 +685,8 @@ pub fn op_lognot<T: Allocator>(a: &mut T, input: T::Ptr, _max_cost: Cost) -> Res
     let v0 = int_atom(&a0, "lognot")?;
     let mut n: Number = number_from_u8(v0);
     n = !n;
-    let cost = LOGNOT_BASE_COST + ((v0.len() as Cost) * LOGNOT_COST_PER_BYTE);
-    let r = ptr_from_number(a, &n)?;
+    let cost = LOGNOT_COST_PER_BYTE * v0.len();
+    let r = a.new_number(n)?;
     Ok(malloc_cost(a, cost, r))
 }
 
@@ -660,13 +694,12 @@ pub fn op_not<T: Allocator>(a: &mut T, input: T::Ptr, _max_cost: Cost) -> Respon
     let args = Node::new(a, input);
     check_arg_count(&args, 1, "not")?;
     let r: T::Ptr = args.from_bool(!args.first()?.as_bool()).node;
-    let cost = BOOL_BASE_COST;
-    Ok(Reduction(cost, r))
+    Ok(Reduction(Cost::ZERO, r))
 }
 
 pub fn op_any<T: Allocator>(a: &mut T, input: T::Ptr, max_cost: Cost) -> Response<T::Ptr> {
     let args = Node::new(a, input);
-    let mut cost = BOOL_BASE_COST;
+    let mut cost = Cost::ZERO;
     let mut is_any = false;
     for arg in &args {
         cost += BOOL_COST_PER_ARG;
@@ -679,7 +712,7 @@ pub fn op_any<T: Allocator>(a: &mut T, input: T::Ptr, max_cost: Cost) -> Respons
 
 pub fn op_all<T: Allocator>(a: &mut T, input: T::Ptr, max_cost: Cost) -> Response<T::Ptr> {
     let args = Node::new(a, input);
-    let mut cost = BOOL_BASE_COST;
+    let mut cost = Cost::ZERO;
     let mut is_all = true;
     for arg in &args {
         cost += BOOL_COST_PER_ARG;
@@ -695,11 +728,11 @@ pub fn op_softfork<T: Allocator>(a: &mut T, input: T::Ptr, max_cost: Cost) -> Re
     match args.pair() {
         Some((p1, _)) => {
             let n: Number = number_from_u8(int_atom(&p1, "softfork")?);
-            if n.sign() == Sign::Plus {
-                if n > Number::from(max_cost) {
+            if number_sign(&n) == Sign::Plus {
+                if n > Number::from(max_cost.get()) {
                     return err(a.null(), "cost exceeded");
                 }
-                let cost: Cost = TryFrom::try_from(&n).unwrap();
+                let cost = Cost::new(number_to_u64(&n).unwrap());
                 Ok(Reduction(cost, args.null().node))
             } else {
                 args.err("cost must be > 0")
@@ -712,8 +745,7 @@ pub fn op_softfork<T: Allocator>(a: &mut T, input: T::Ptr, max_cost: Cost) -> Re
 lazy_static! {
     static ref GROUP_ORDER: Number = {
         let order_as_hex = b"73EDA753299D7D483339D80809A1D80553BDA402FFFE5BFEFFFFFFFF00000001";
-        let n = BigUint::parse_bytes(order_as_hex, 16).unwrap();
-        n.into()
+        number_from_hex(order_as_hex).unwrap()
     };
 }
 
@@ -721,7 +753,7 @@ fn mod_group_order(n: Number) -> Number {
     let order = GROUP_ORDER.clone();
     let divisor: Number = &n / &order;
     let remainder: Number = &n - &divisor * &order;
-    if remainder.sign() == Sign::Minus {
+    if number_sign(&remainder) == Sign::Minus {
         order + remainder
     } else {
         remainder
@@ -729,7 +761,7 @@ fn mod_group_order(n: Number) -> Number {
 }
 
 fn number_to_scalar(n: Number) -> Scalar {
-    let (sign, as_u8): (Sign, Vec<u8>) = n.to_bytes_le();
+    let (sign, as_u8): (Sign, Vec<u8>) = number_to_magnitude_le(&n);
     let mut scalar_array: [u8; 32] = [0; 32];
     scalar_array[..as_u8.len()].clone_from_slice(&as_u8[..]);
     let exp: Scalar = Scalar::from_bytes(&scalar_array).unwrap();
@@ -751,7 +783,7 @@ pub fn op_pubkey_for_exp<T: Allocator>(
 
     let v0 = int_atom(&a0, "pubkey_for_exp")?;
     let exp: Number = mod_group_order(number_from_u8(v0));
-    let cost = PUBKEY_BASE_COST + (v0.len() as Cost) * PUBKEY_COST_PER_BYTE;
+    let cost = PUBKEY_COST_PER_BYTE * v0.len();
     let exp: Scalar = number_to_scalar(exp);
     let point: G1Projective = G1Affine::generator() * exp;
     let point: G1Affine = point.into();
@@ -761,7 +793,7 @@ pub fn op_pubkey_for_exp<T: Allocator>(
 
 pub fn op_point_add<T: Allocator>(a: &mut T, input: T::Ptr, max_cost: Cost) -> Response<T::Ptr> {
     let args = Node::new(a, input);
-    let mut cost = POINT_ADD_BASE_COST;
+    let mut cost = Cost::ZERO;
     let mut total: G1Projective = G1Projective::identity();
     for arg in &args {
         let blob = atom(&arg, "point_add")?;
@@ -781,7 +813,7 @@ pub fn op_point_add<T: Allocator>(a: &mut T, input: T::Ptr, max_cost: Cost) -> R
         if !is_ok {
             let blob: String = hex::encode(node_to_bytes(&arg).unwrap());
             let msg = format!("point_add expects blob, got {}: Length of bytes object not equal to G1Element::SIZE", blob);
-            return args.err(&msg);
+            return args.err(msg);
         }
     }
     let total: G1Affine = total.into();
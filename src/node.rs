@@ -1,5 +1,6 @@
 use super::allocator::{Allocator, SExp};
-use std::fmt;
+use crate::run_program::traverse_path;
+use core::fmt;
 
 pub struct Node<'a, T: Allocator> {
     pub allocator: &'a T,
@@ -41,15 +42,13 @@ impl<'a, T: Allocator> Node<'a, T> {
     }
 
     pub fn arg_count_is(&self, mut count: usize) -> bool {
-        let mut ptr: Self = self.clone();
+        let mut ptr = self.node.clone();
         loop {
-            if count == 0 {
-                return ptr.nullp();
-            }
-            match ptr.sexp() {
-                SExp::Pair(_, new_ptr) => {
-                    ptr = ptr.with_node(new_ptr).clone();
+            match self.allocator.sexp(&ptr) {
+                SExp::Pair(_, new_ptr) if count > 0 => {
+                    ptr = new_ptr;
                 }
+                SExp::Atom(a) if count == 0 => return self.allocator.buf(&a).is_empty(),
                 _ => return false,
             }
             count -= 1;
@@ -78,6 +77,32 @@ impl<'a, T: Allocator> Node<'a, T> {
             self.null()
         }
     }
+
+    // follows a chialisp style accessor, like "rrf" for the third item of a
+    // list: each f (first) or r (rest) is applied in turn, from the left.
+    // None if a step goes into an atom, or for any other character
+    pub fn at(&self, path: &str) -> Option<Self> {
+        let mut node = self.clone();
+        for c in path.chars() {
+            let (first, rest) = node.pair()?;
+            node = match c {
+                'f' => first,
+                'r' => rest,
+                _ => return None,
+            };
+        }
+        Some(node)
+    }
+
+    // follows a path atom, as used to look up the environment: 1 is the root,
+    // and the bits below the top one (least significant first) pick first (0)
+    // or rest (1). So 2 is the first item of a list, 5 the second and 11 the
+    // third. None if a step goes into an atom
+    pub fn at_path(&self, path: &[u8]) -> Option<Self> {
+        traverse_path(self.allocator, path, &self.node)
+            .ok()
+            .map(|r| self.with_node(r.1))
+    }
 }
 
 impl<'a, T: Allocator> PartialEq for Node<'a, T> {
@@ -134,3 +159,40 @@ impl<'a, T: Allocator> Iterator for Node<'a, T> {
         }
     }
 }
+
+#[test]
+fn test_at() {
+    use crate::int_allocator::IntAllocator;
+    use crate::serialize::node_from_bytes;
+
+    let mut a = IntAllocator::new();
+    // (1 (2 3) 4)
+    let serialized = [
+        0xff, 0x01, 0xff, 0xff, 0x02, 0xff, 0x03, 0x80, 0xff, 0x04, 0x80,
+    ];
+    let node = node_from_bytes(&mut a, &serialized).unwrap();
+    let node = Node::new(&a, node);
+    let atom = |n: Option<Node<IntAllocator>>| n.unwrap().atom().unwrap().to_vec();
+
+    assert_eq!(node.at("").unwrap(), node);
+    assert_eq!(node.at_path(&[1]).unwrap(), node);
+    assert_eq!(atom(node.at("f")), [1]);
+    assert_eq!(atom(node.at_path(&[2])), [1]);
+    assert_eq!(atom(node.at("rff")), [2]);
+    assert_eq!(atom(node.at_path(&[9])), [2]);
+    assert_eq!(atom(node.at("rfrf")), [3]);
+    assert_eq!(atom(node.at_path(&[21])), [3]);
+    assert_eq!(atom(node.at("rrf")), [4]);
+    assert_eq!(atom(node.at_path(&[11])), [4]);
+    assert!(node.at("rrr").unwrap().nullp());
+    assert!(node.at_path(&[15]).unwrap().nullp());
+    // leading zero bytes don't change the path, and 0 is nil
+    assert_eq!(atom(node.at_path(&[0, 11])), [4]);
+    assert!(node.at_path(&[]).unwrap().nullp());
+
+    // into an atom, or not an accessor
+    assert!(node.at("ff").is_none());
+    assert!(node.at_path(&[4]).is_none());
+    assert!(node.at("rrrf").is_none());
+    assert!(node.at("x").is_none());
+}
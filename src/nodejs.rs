@@ -0,0 +1,206 @@
+use napi::{CallContext, Env, Error, JsBuffer, JsNumber, JsObject, JsUnknown, Result, Status};
+use napi_derive::{js_function, module_exports};
+
+use crate::allocator::{Allocator, SExp};
+use crate::cost::Cost;
+use crate::curry::curry as curry_node;
+use crate::int_allocator::IntAllocator;
+use crate::node::Node;
+use crate::op_table::{op_table_for, CHIA_OPCODES};
+use crate::run_program::run_program as run_node;
+use crate::serialize::{node_from_bytes as from_bytes, node_to_bytes as to_bytes};
+use crate::tree_hash::tree_hash as hash_node;
+
+// Node.js bindings, the N-API counterpart of src/wasm.rs. Programs and values
+// are passed serialized as Buffers, except to node_from_bytes() and
+// node_to_bytes(), which convert between the serialized form and nested
+// arrays: an atom is a Buffer and a pair is a two element Array. Programs use
+// chia's operator table, where quote is 1 and apply is 2
+
+fn js_err<E: ToString>(e: E) -> Error {
+    Error::new(Status::GenericFailure, e.to_string())
+}
+
+fn parse(a: &mut IntAllocator, blob: &[u8]) -> Result<i32> {
+    from_bytes(a, blob).map_err(js_err)
+}
+
+fn serialize(a: &IntAllocator, node: i32) -> Result<Vec<u8>> {
+    to_bytes(&Node::new(a, node)).map_err(js_err)
+}
+
+// run_program(program: Buffer, args: Buffer, max_cost: number)
+//   -> { cost: number, result: Buffer }
+#[js_function(3)]
+fn run_program(ctx: CallContext) -> Result<JsObject> {
+    let program = ctx.get::<JsBuffer>(0)?.into_value()?;
+    let args = ctx.get::<JsBuffer>(1)?.into_value()?;
+    let max_cost = ctx.get::<JsNumber>(2)?.get_int64()?;
+    if max_cost < 0 {
+        return Err(js_err("max_cost must not be negative"));
+    }
+
+    let mut a = IntAllocator::new();
+    let program = parse(&mut a, &program)?;
+    let args = parse(&mut a, &args)?;
+    let table = op_table_for(&CHIA_OPCODES);
+    let r = run_node(
+        &mut a,
+        &program,
+        &args,
+        1,
+        2,
+        Cost::new(max_cost as u64),
+        Box::new(table),
+        None,
+    )
+    .map_err(|e| js_err(e.1))?;
+    let result = serialize(&a, r.1)?;
+
+    let mut obj = ctx.env.create_object()?;
+    obj.set_named_property("cost", ctx.env.create_int64(r.0.get() as i64)?)?;
+    obj.set_named_property(
+        "result",
+        ctx.env.create_buffer_with_data(result)?.into_raw(),
+    )?;
+    Ok(obj)
+}
+
+enum ToJsOp {
+    Visit(i32),
+    Cons,
+}
+
+fn to_js(env: &Env, a: &IntAllocator, node: i32) -> Result<JsUnknown> {
+    let mut ops = vec![ToJsOp::Visit(node)];
+    let mut values: Vec<JsUnknown> = Vec::new();
+    while let Some(op) = ops.pop() {
+        match op {
+            ToJsOp::Visit(node) => match a.sexp(&node) {
+                SExp::Atom(buf) => {
+                    let bytes = a.buf(&buf).to_vec();
+                    values.push(
+                        env.create_buffer_with_data(bytes)?
+                            .into_raw()
+                            .into_unknown(),
+                    );
+                }
+                SExp::Pair(left, right) => {
+                    ops.push(ToJsOp::Cons);
+                    ops.push(ToJsOp::Visit(right));
+                    ops.push(ToJsOp::Visit(left));
+                }
+            },
+            ToJsOp::Cons => {
+                let right = values.pop().unwrap();
+                let left = values.pop().unwrap();
+                let mut pair = env.create_array_with_length(2)?;
+                pair.set_element(0, left)?;
+                pair.set_element(1, right)?;
+                values.push(pair.into_unknown());
+            }
+        }
+    }
+    Ok(values.pop().unwrap())
+}
+
+enum FromJsOp {
+    Visit(JsUnknown),
+    Cons,
+}
+
+fn from_js(a: &mut IntAllocator, value: JsUnknown) -> Result<i32> {
+    let mut ops = vec![FromJsOp::Visit(value)];
+    let mut values: Vec<i32> = Vec::new();
+    while let Some(op) = ops.pop() {
+        match op {
+            FromJsOp::Visit(v) => {
+                if v.is_buffer()? {
+                    let bytes = unsafe { v.cast::<JsBuffer>() }.into_value()?;
+                    values.push(a.new_atom(&bytes).map_err(|e| js_err(e.1))?);
+                } else if v.is_array()? {
+                    let pair = unsafe { v.cast::<JsObject>() };
+                    if pair.get_array_length()? != 2 {
+                        return Err(js_err("pairs must be arrays of length 2"));
+                    }
+                    ops.push(FromJsOp::Cons);
+                    ops.push(FromJsOp::Visit(pair.get_element::<JsUnknown>(1)?));
+                    ops.push(FromJsOp::Visit(pair.get_element::<JsUnknown>(0)?));
+                } else {
+                    return Err(js_err("expected Buffer or Array"));
+                }
+            }
+            FromJsOp::Cons => {
+                let right = values.pop().unwrap();
+                let left = values.pop().unwrap();
+                values.push(a.new_pair(left, right).map_err(|e| js_err(e.1))?);
+            }
+        }
+    }
+    Ok(values.pop().unwrap())
+}
+
+// node_from_bytes(blob: Buffer) -> Buffer | Array
+#[js_function(1)]
+fn node_from_bytes(ctx: CallContext) -> Result<JsUnknown> {
+    let blob = ctx.get::<JsBuffer>(0)?.into_value()?;
+    let mut a = IntAllocator::new();
+    let node = parse(&mut a, &blob)?;
+    to_js(ctx.env, &a, node)
+}
+
+// node_to_bytes(value: Buffer | Array) -> Buffer
+#[js_function(1)]
+fn node_to_bytes(ctx: CallContext) -> Result<JsBuffer> {
+    let mut a = IntAllocator::new();
+    let node = from_js(&mut a, ctx.get::<JsUnknown>(0)?)?;
+    let blob = serialize(&a, node)?;
+    Ok(ctx.env.create_buffer_with_data(blob)?.into_raw())
+}
+
+// tree_hash(blob: Buffer) -> Buffer, the tree hash of a serialized value
+#[js_function(1)]
+fn tree_hash(ctx: CallContext) -> Result<JsBuffer> {
+    let blob = ctx.get::<JsBuffer>(0)?.into_value()?;
+    let mut a = IntAllocator::new();
+    let node = parse(&mut a, &blob)?;
+    let hash = hash_node(&Node::new(&a, node));
+    Ok(ctx.env.create_buffer_with_data(hash.to_vec())?.into_raw())
+}
+
+// curry(program: Buffer, args: Buffer[]) -> Buffer, the serialized program
+// with the serialized arguments bound to it
+#[js_function(2)]
+fn curry(ctx: CallContext) -> Result<JsBuffer> {
+    let program = ctx.get::<JsBuffer>(0)?.into_value()?;
+    let args = ctx.get::<JsObject>(1)?;
+    if !args.is_array()? {
+        return Err(js_err("curry arguments must be an array of Buffer"));
+    }
+
+    let mut a = IntAllocator::new();
+    let program = parse(&mut a, &program)?;
+    let count = args.get_array_length()?;
+    let mut arg_nodes = Vec::with_capacity(count as usize);
+    for i in 0..count {
+        let arg = args.get_element::<JsUnknown>(i)?;
+        if !arg.is_buffer()? {
+            return Err(js_err("curry arguments must be Buffer"));
+        }
+        let blob = unsafe { arg.cast::<JsBuffer>() }.into_value()?;
+        arg_nodes.push(parse(&mut a, &blob)?);
+    }
+    let curried = curry_node(&mut a, program, &arg_nodes).map_err(|e| js_err(e.1))?;
+    let blob = serialize(&a, curried)?;
+    Ok(ctx.env.create_buffer_with_data(blob)?.into_raw())
+}
+
+#[module_exports]
+fn init(mut exports: JsObject) -> Result<()> {
+    exports.create_named_method("run_program", run_program)?;
+    exports.create_named_method("node_from_bytes", node_from_bytes)?;
+    exports.create_named_method("node_to_bytes", node_to_bytes)?;
+    exports.create_named_method("tree_hash", tree_hash)?;
+    exports.create_named_method("curry", curry)?;
+    Ok(())
+}
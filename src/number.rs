@@ -1,25 +1,279 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
 use crate::allocator::Allocator;
 use crate::node::Node;
 use crate::reduction::EvalErr;
 
-use num_bigint::BigInt;
-pub type Number = BigInt;
+// `Number` is the integer type operators compute with. Operators only rely on
+// the std arithmetic, bitwise, shift and comparison traits on it, plus the
+// functions in this module. Everything specific to the bigint library lives in
+// `backend`, so swapping it out means providing another `backend` module with
+// the same items.
+//
+// num-bigint is the canonical backend. Any other backend must produce
+// identical atoms (and therefore identical costs) for every input, or it will
+// fork from consensus.
+mod backend {
+    use alloc::vec::Vec;
+    use core::convert::TryFrom;
+    use num_bigint::BigInt;
+
+    pub use num_bigint::Sign;
+    pub type Number = BigInt;
+
+    pub fn from_signed_bytes_be(v: &[u8]) -> Number {
+        Number::from_signed_bytes_be(v)
+    }
+
+    pub fn from_unsigned_bytes_be(v: &[u8]) -> Number {
+        Number::from_bytes_be(Sign::Plus, v)
+    }
+
+    pub fn to_signed_bytes_be(n: &Number) -> Vec<u8> {
+        n.to_signed_bytes_be()
+    }
+
+    pub fn to_magnitude_bytes_le(n: &Number) -> (Sign, Vec<u8>) {
+        n.to_bytes_le()
+    }
+
+    pub fn sign(n: &Number) -> Sign {
+        n.sign()
+    }
+
+    pub fn bits(n: &Number) -> u64 {
+        n.bits()
+    }
+
+    pub fn to_u64(n: &Number) -> Option<u64> {
+        u64::try_from(n).ok()
+    }
+
+    pub fn to_i128(n: &Number) -> Option<i128> {
+        i128::try_from(n).ok()
+    }
+
+    pub fn from_hex(v: &[u8]) -> Option<Number> {
+        Number::parse_bytes(v, 16)
+    }
+
+    pub fn from_decimal(v: &[u8]) -> Option<Number> {
+        Number::parse_bytes(v, 10)
+    }
+
+    pub fn mod_pow(base: &Number, exp: &Number, m: &Number) -> Number {
+        base.modpow(exp, m)
+    }
+}
+
+pub use backend::{Number, Sign};
+
+pub fn number_sign(n: &Number) -> Sign {
+    backend::sign(n)
+}
+
+// the number of bits needed to represent the magnitude of `n`
+pub fn number_bits(n: &Number) -> u64 {
+    backend::bits(n)
+}
+
+pub fn number_to_u64(n: &Number) -> Option<u64> {
+    backend::to_u64(n)
+}
+
+// two's complement big-endian bytes, straight from the backend. Unlike
+// number_to_atom_bytes(), zero is encoded as a single 0 byte
+pub fn number_to_signed_bytes(n: &Number) -> Vec<u8> {
+    backend::to_signed_bytes_be(n)
+}
+
+// the sign and the little-endian bytes of the magnitude of `n`
+pub fn number_to_magnitude_le(n: &Number) -> (Sign, Vec<u8>) {
+    backend::to_magnitude_bytes_le(n)
+}
+
+pub fn number_from_hex(v: &[u8]) -> Option<Number> {
+    backend::from_hex(v)
+}
 
+// base ^ exp mod m. The base and exponent must not be negative and the modulus
+// must be positive
+pub fn number_mod_pow(base: &Number, exp: &Number, m: &Number) -> Number {
+    backend::mod_pow(base, exp, m)
+}
+
+// interpret the atom as an unsigned big-endian integer
+pub fn number_from_unsigned_u8(v: &[u8]) -> Number {
+    backend::from_unsigned_bytes_be(v)
+}
+
+// The canonical atom encoding of an integer: signed, big-endian and minimal.
+// Zero is the empty atom, and there is a leading 0x00 (or 0xff) byte only when
+// the next byte's top bit would otherwise flip the sign. This is the encoding
+// every operator produces, and number_from_atom_bytes() is its inverse.
+pub fn number_to_atom_bytes(item: &Number) -> Vec<u8> {
+    let mut bytes: Vec<u8> = backend::to_signed_bytes_be(item);
+
+    // make number minimal by removing leading zeros
+    let mut start = 0;
+    while start < bytes.len() && bytes[start] == 0 {
+        if start + 1 < bytes.len() && (bytes[start + 1] & 0x80 == 0x80) {
+            break;
+        }
+        start += 1;
+    }
+    bytes.drain(..start);
+    bytes
+}
+
+// Allocator::new_number(), for a number that's only borrowed
 pub fn ptr_from_number<T: Allocator>(
     allocator: &mut T,
     item: &Number,
 ) -> Result<T::Ptr, EvalErr<T::Ptr>> {
-    let bytes: Vec<u8> = item.to_signed_bytes_be();
-    let mut slice = bytes.as_slice();
+    allocator.new_atom(&number_to_atom_bytes(item))
+}
 
-    // make number minimal by removing leading zeros
-    while (!slice.is_empty()) && (slice[0] == 0) {
-        if slice.len() > 1 && (slice[1] & 0x80 == 0x80) {
+// An integer decoded from an atom. Values that fit in an i128 are kept there,
+// so arithmetic on them doesn't touch the heap. Operations that overflow fall
+// back to `Number`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FastNumber {
+    Small(i128),
+    Big(Number),
+}
+
+impl FastNumber {
+    // results that fit in an i128 go back to the small representation, so an
+    // intermediate overflow doesn't make the rest of the operation allocate
+    pub fn from_number(n: Number) -> Self {
+        match backend::to_i128(&n) {
+            Some(v) => FastNumber::Small(v),
+            None => FastNumber::Big(n),
+        }
+    }
+
+    pub fn to_number(&self) -> Number {
+        match self {
+            FastNumber::Small(v) => Number::from(*v),
+            FastNumber::Big(v) => v.clone(),
+        }
+    }
+
+    pub fn into_number(self) -> Number {
+        match self {
+            FastNumber::Small(v) => Number::from(v),
+            FastNumber::Big(v) => v,
+        }
+    }
+
+    // the number of bits needed to represent the magnitude, like number_bits()
+    pub fn bits(&self) -> u64 {
+        match self {
+            FastNumber::Small(v) => (128 - v.unsigned_abs().leading_zeros()) as u64,
+            FastNumber::Big(v) => number_bits(v),
+        }
+    }
+}
+
+impl core::ops::AddAssign for FastNumber {
+    fn add_assign(&mut self, rhs: Self) {
+        if let (FastNumber::Small(l), FastNumber::Small(r)) = (&*self, &rhs) {
+            if let Some(v) = l.checked_add(*r) {
+                *self = FastNumber::Small(v);
+                return;
+            }
+        }
+        let l = core::mem::replace(self, FastNumber::Small(0));
+        *self = FastNumber::from_number(l.into_number() + rhs.into_number());
+    }
+}
+
+impl core::ops::SubAssign for FastNumber {
+    fn sub_assign(&mut self, rhs: Self) {
+        if let (FastNumber::Small(l), FastNumber::Small(r)) = (&*self, &rhs) {
+            if let Some(v) = l.checked_sub(*r) {
+                *self = FastNumber::Small(v);
+                return;
+            }
+        }
+        let l = core::mem::replace(self, FastNumber::Small(0));
+        *self = FastNumber::from_number(l.into_number() - rhs.into_number());
+    }
+}
+
+impl core::ops::MulAssign for FastNumber {
+    fn mul_assign(&mut self, rhs: Self) {
+        if let (FastNumber::Small(l), FastNumber::Small(r)) = (&*self, &rhs) {
+            if let Some(v) = l.checked_mul(*r) {
+                *self = FastNumber::Small(v);
+                return;
+            }
+        }
+        let l = core::mem::replace(self, FastNumber::Small(0));
+        *self = FastNumber::from_number(l.into_number() * rhs.into_number());
+    }
+}
+
+impl PartialOrd for FastNumber {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        match (self, other) {
+            (FastNumber::Small(l), FastNumber::Small(r)) => l.partial_cmp(r),
+            _ => self.to_number().partial_cmp(&other.to_number()),
+        }
+    }
+}
+
+// like number_from_u8(), but atoms of up to 16 bytes are decoded without
+// allocating
+pub fn fast_number_from_u8(v: &[u8]) -> FastNumber {
+    if v.len() > 16 {
+        return FastNumber::Big(number_from_u8(v));
+    }
+    if v.is_empty() {
+        return FastNumber::Small(0);
+    }
+    let mut ret: i128 = if (v[0] & 0x80) != 0 { -1 } else { 0 };
+    for b in v {
+        ret = (ret << 8) | (*b as i128);
+    }
+    FastNumber::Small(ret)
+}
+
+// the canonical atom encoding of a small integer, on the stack. The atom is
+// `buf[start..]` of the returned (buf, start)
+fn small_int_atom(v: i128) -> ([u8; 16], usize) {
+    let bytes = v.to_be_bytes();
+    if v == 0 {
+        return (bytes, bytes.len());
+    }
+    // strip leading bytes that are just sign extension of the next one
+    let mut start = 0;
+    while start < bytes.len() - 1 {
+        let next_negative = (bytes[start + 1] & 0x80) != 0;
+        if (bytes[start] == 0 && !next_negative) || (bytes[start] == 0xff && next_negative) {
+            start += 1;
+        } else {
             break;
         }
-        slice = &slice[1..];
     }
-    allocator.new_atom(slice)
+    (bytes, start)
+}
+
+// small values are encoded on the stack and copied straight into the
+// allocator's buffer, without any intermediate heap allocation
+pub fn ptr_from_fast_number<T: Allocator>(
+    allocator: &mut T,
+    item: &FastNumber,
+) -> Result<T::Ptr, EvalErr<T::Ptr>> {
+    match item {
+        FastNumber::Small(v) => {
+            let (buf, start) = small_int_atom(*v);
+            allocator.new_atom(&buf[start..])
+        }
+        FastNumber::Big(v) => ptr_from_number(allocator, v),
+    }
 }
 
 impl<T: Allocator> From<&Node<'_, T>> for Option<Number> {
@@ -34,7 +288,67 @@ pub fn number_from_u8(v: &[u8]) -> Number {
     if len == 0 {
         0.into()
     } else {
-        Number::from_signed_bytes_be(v)
+        backend::from_signed_bytes_be(v)
+    }
+}
+
+// decode an integer atom. Any atom is accepted, including non-canonical ones
+// with redundant leading bytes. Use is_canonical_int() to reject those
+pub fn number_from_atom_bytes(v: &[u8]) -> Number {
+    number_from_u8(v)
+}
+
+// true if the atom is the minimal encoding of its integer value, i.e. it's
+// what number_to_atom_bytes() would produce
+pub fn is_canonical_int(v: &[u8]) -> bool {
+    match v {
+        [] => true,
+        [0] => false,
+        [0, next, ..] => (next & 0x80) != 0,
+        [0xff, next, ..] => (next & 0x80) == 0,
+        _ => true,
+    }
+}
+
+// parse a decimal integer, as written in CLVM source. An optional leading '-'
+// is allowed, leading zeros are ignored and "-0" is 0. Anything else (empty
+// strings, '+', whitespace, separators) is rejected. Values that fit in an
+// i128 are parsed without going through the bigint library
+pub fn number_from_decimal(s: &str) -> Option<Number> {
+    let (negative, digits) = match s.as_bytes() {
+        [b'-', rest @ ..] => (true, rest),
+        rest => (false, rest),
+    };
+    if digits.is_empty() || !digits.iter().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    // 38 digits always fit in an i128
+    if digits.len() <= 38 {
+        let mut v: i128 = 0;
+        for c in digits {
+            v = v * 10 + (c - b'0') as i128;
+        }
+        return Some(Number::from(if negative { -v } else { v }));
+    }
+    let v = backend::from_decimal(digits)?;
+    Some(if negative { -v } else { v })
+}
+
+pub fn number_to_decimal(n: &Number) -> String {
+    n.to_string()
+}
+
+// decimal string to canonical integer atom, see number_from_decimal()
+pub fn decimal_to_atom_bytes(s: &str) -> Option<Vec<u8>> {
+    number_from_decimal(s).map(|n| number_to_atom_bytes(&n))
+}
+
+// format an integer atom in decimal. Atoms of up to 16 bytes are formatted
+// without going through the bigint library
+pub fn atom_bytes_to_decimal(v: &[u8]) -> String {
+    match fast_number_from_u8(v) {
+        FastNumber::Small(n) => n.to_string(),
+        FastNumber::Big(n) => number_to_decimal(&n),
     }
 }
 
@@ -100,3 +414,195 @@ fn test_ptr_from_number() {
     let ptr = ptr_from_number(&mut a, &num).unwrap();
     assert_eq!(&[0x40, 0x00], &a.atom(&ptr));
 }
+
+#[test]
+fn test_new_number() {
+    let mut a = IntAllocator::new();
+    for (n, canonical) in &[
+        (0, &[][..]),
+        (1, &[1]),
+        (-1, &[0xff]),
+        (127, &[0x7f]),
+        (128, &[0x00, 0x80]),
+        (-128, &[0x80]),
+        (-129, &[0xff, 0x7f]),
+        (255, &[0x00, 0xff]),
+        (0x8000, &[0x00, 0x80, 0x00]),
+    ] {
+        let ptr = a.new_number(Number::from(*n)).unwrap();
+        assert_eq!(a.atom(&ptr), *canonical);
+        assert_eq!(number_from_u8(a.atom(&ptr)), Number::from(*n));
+    }
+}
+
+#[test]
+fn test_backend_helpers() {
+    assert_eq!(number_from_unsigned_u8(&[0xff]), Number::from(255));
+    assert_eq!(number_from_unsigned_u8(&[]), Number::from(0));
+    assert_eq!(number_sign(&number_from_u8(&[0xff])), Sign::Minus);
+    assert_eq!(number_sign(&number_from_u8(&[])), Sign::NoSign);
+    assert_eq!(number_bits(&number_from_u8(&[0x01, 0x00])), 9);
+    assert_eq!(number_to_u64(&number_from_u8(&[0xff])), None);
+    assert_eq!(number_to_u64(&number_from_u8(&[0x00, 0xff])), Some(255));
+    assert_eq!(
+        number_to_magnitude_le(&number_from_u8(&[0xfe, 0xff])),
+        (Sign::Minus, vec![0x01, 0x01])
+    );
+    assert_eq!(number_from_hex(b"ff"), Some(Number::from(255)));
+}
+
+#[test]
+fn test_fast_number() {
+    let mut a = IntAllocator::new();
+
+    let values: Vec<Vec<u8>> = vec![
+        vec![],
+        vec![0],
+        vec![1],
+        vec![0x7f],
+        vec![0x80],
+        vec![0xff],
+        vec![0x00, 0x80],
+        vec![0xff, 0x7f],
+        vec![0x7f; 16],
+        vec![0x80; 16],
+        vec![0xff; 16],
+        vec![0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+        vec![0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        vec![0x55; 17],
+        vec![0xaa; 33],
+    ];
+
+    for l in &values {
+        let fl = fast_number_from_u8(l);
+        let nl = number_from_u8(l);
+        assert_eq!(fl.to_number(), nl);
+        assert_eq!(fl.bits(), number_bits(&nl));
+
+        let p0 = ptr_from_fast_number(&mut a, &fl).unwrap();
+        let p1 = ptr_from_number(&mut a, &nl).unwrap();
+        assert_eq!(a.atom(&p0), a.atom(&p1));
+
+        for r in &values {
+            let fr = fast_number_from_u8(r);
+            let nr = number_from_u8(r);
+
+            let mut v = fl.clone();
+            v += fr.clone();
+            assert_eq!(v.into_number(), &nl + &nr);
+
+            let mut v = fl.clone();
+            v -= fr.clone();
+            assert_eq!(v.into_number(), &nl - &nr);
+
+            let mut v = fl.clone();
+            v *= fr.clone();
+            assert_eq!(v.into_number(), &nl * &nr);
+
+            assert_eq!(fl.partial_cmp(&fr), nl.partial_cmp(&nr));
+        }
+    }
+}
+
+#[test]
+fn test_number_to_atom_bytes() {
+    let cases: &[(&[u8], &[u8])] = &[
+        (&[], &[]),
+        (&[0], &[]),
+        (&[0, 0, 0], &[]),
+        (&[1], &[1]),
+        (&[0, 0, 1], &[1]),
+        (&[0x7f], &[0x7f]),
+        (&[0, 0x80], &[0, 0x80]),
+        (&[0, 0, 0x80], &[0, 0x80]),
+        (&[0x80], &[0x80]),
+        (&[0xff], &[0xff]),
+        (&[0xff, 0xff], &[0xff]),
+        (&[0xff, 0x7f], &[0xff, 0x7f]),
+        (&[0xff, 0xff, 0x80], &[0x80]),
+    ];
+    for (input, canonical) in cases {
+        let n = number_from_atom_bytes(input);
+        assert_eq!(number_to_atom_bytes(&n), canonical.to_vec());
+        assert_eq!(number_from_atom_bytes(canonical), n);
+        assert!(is_canonical_int(canonical));
+        assert_eq!(is_canonical_int(input), input == canonical);
+    }
+}
+
+#[test]
+fn test_decimal() {
+    for (s, expected) in &[
+        ("0", "0"),
+        ("-0", "0"),
+        ("000", "0"),
+        ("007", "7"),
+        ("-007", "-7"),
+        ("127", "127"),
+        ("-128", "-128"),
+        (
+            "99999999999999999999999999999999999999",
+            "99999999999999999999999999999999999999",
+        ),
+        (
+            "-100000000000000000000000000000000000000",
+            "-100000000000000000000000000000000000000",
+        ),
+        ("0000000000000000000000000000000000000000000001", "1"),
+        (
+            "123456789012345678901234567890123456789012345678901234567890",
+            "123456789012345678901234567890123456789012345678901234567890",
+        ),
+    ] {
+        let n = number_from_decimal(s).unwrap();
+        assert_eq!(number_to_decimal(&n), *expected);
+        let atom = decimal_to_atom_bytes(s).unwrap();
+        assert!(is_canonical_int(&atom));
+        assert_eq!(number_from_atom_bytes(&atom), n);
+        assert_eq!(atom_bytes_to_decimal(&atom), *expected);
+    }
+
+    for s in &["", "-", "+1", " 1", "1 ", "1_000", "--1", "0x10", "1.0"] {
+        assert_eq!(number_from_decimal(s), None);
+        assert_eq!(decimal_to_atom_bytes(s), None);
+    }
+
+    assert_eq!(decimal_to_atom_bytes("128").unwrap(), vec![0x00, 0x80]);
+    assert_eq!(decimal_to_atom_bytes("-1").unwrap(), vec![0xff]);
+    assert_eq!(decimal_to_atom_bytes("0").unwrap(), Vec::<u8>::new());
+    assert_eq!(atom_bytes_to_decimal(&[0, 0, 0x80]), "128");
+    assert_eq!(
+        atom_bytes_to_decimal(&[0x80; 17]),
+        number_from_u8(&[0x80; 17]).to_string()
+    );
+}
+
+#[test]
+fn test_fast_number_normalize() {
+    // overflowing an i128 spills into a bigint, and coming back in range
+    // returns to the small representation
+    let mut v = FastNumber::Small(i128::MAX);
+    v += FastNumber::Small(1);
+    assert!(matches!(v, FastNumber::Big(_)));
+    v -= FastNumber::Small(2);
+    assert_eq!(v, FastNumber::Small(i128::MAX - 1));
+
+    let mut v = FastNumber::Small(i128::MIN);
+    v *= FastNumber::Small(-1);
+    assert!(matches!(v, FastNumber::Big(_)));
+    v *= FastNumber::Small(0);
+    assert_eq!(v, FastNumber::Small(0));
+
+    assert_eq!(
+        FastNumber::from_number(number_from_u8(&[0xff; 20])),
+        FastNumber::Small(-1)
+    );
+
+    for v in &[0, 1, -1, 127, 128, -128, -129, i128::MAX, i128::MIN] {
+        let (buf, start) = small_int_atom(*v);
+        assert_eq!(
+            buf[start..].to_vec(),
+            number_to_atom_bytes(&Number::from(*v))
+        );
+    }
+}
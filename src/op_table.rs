@@ -0,0 +1,1197 @@
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::allocator::Allocator;
+use crate::core_ops::{
+    op_cons, op_eq, op_first, op_if, op_listp, op_raise, op_rest, CONS_COST, EQ_BASE_COST,
+    EQ_COST_PER_BYTE, FIRST_COST, IF_COST, LISTP_COST, REST_COST,
+};
+use crate::cost::Cost;
+use crate::err_utils::err;
+use crate::more_ops::{
+    op_add, op_all, op_any, op_ash, op_ash_limited, op_concat, op_div, op_div_floor, op_divmod,
+    op_gr, op_gr_bytes, op_logand, op_logior, op_lognot, op_logxor, op_lsh, op_lsh_limited,
+    op_multiply, op_multiply_limited, op_not, op_point_add, op_pubkey_for_exp, op_sha256,
+    op_softfork, op_strlen, op_substr, op_subtract, op_unknown, ARITH_BASE_COST,
+    ARITH_COST_PER_ARG, ARITH_COST_PER_BYTE, ASHIFT_BASE_COST, ASHIFT_COST_PER_BYTE,
+    BOOL_BASE_COST, BOOL_COST_PER_ARG, CONCAT_BASE_COST, CONCAT_COST_PER_ARG, CONCAT_COST_PER_BYTE,
+    DIVMOD_BASE_COST, DIVMOD_COST_PER_BYTE, DIV_BASE_COST, DIV_COST_PER_BYTE, GRS_BASE_COST,
+    GRS_COST_PER_BYTE, GR_BASE_COST, GR_COST_PER_BYTE, LOGNOT_BASE_COST, LOGNOT_COST_PER_BYTE,
+    LOG_BASE_COST, LOG_COST_PER_ARG, LOG_COST_PER_BYTE, LSHIFT_BASE_COST, LSHIFT_COST_PER_BYTE,
+    MUL_BASE_COST, MUL_COST_PER_OP, MUL_LINEAR_COST_PER_BYTE, POINT_ADD_BASE_COST,
+    POINT_ADD_COST_PER_ARG, PUBKEY_BASE_COST, PUBKEY_COST_PER_BYTE, SHA256_BASE_COST,
+    SHA256_COST_PER_ARG, SHA256_COST_PER_BYTE, STRLEN_BASE_COST, STRLEN_COST_PER_BYTE,
+    SUBSTR_BASE_COST,
+};
+use crate::op_utils::u32_from_u8;
+use crate::reduction::{Reduction, Response};
+use crate::run_program::{OpParams, OperatorHandler};
+
+pub type OpFn<T> = fn(&mut T, <T as Allocator>::Ptr, Cost) -> Response<<T as Allocator>::Ptr>;
+
+// an operator that takes the largest integer (in bytes) it may produce, see
+// OpParams::max_int_bytes
+pub type LimitedOpFn<T> =
+    fn(&mut T, <T as Allocator>::Ptr, Cost, usize) -> Response<<T as Allocator>::Ptr>;
+
+// an operator together with its base cost. The base cost is charged here,
+// once, so operator bodies only account for the cost that depends on their
+// arguments
+pub struct Op<T: Allocator> {
+    pub f: OpFn<T>,
+    pub base_cost: Cost,
+    // f with a limit on the size of the integers it produces, for the
+    // operators whose result can be far larger than their arguments
+    pub limited: Option<LimitedOpFn<T>>,
+}
+
+impl<T: Allocator> Clone for Op<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: Allocator> Copy for Op<T> {}
+
+impl<T: Allocator> Op<T> {
+    pub fn call(self, allocator: &mut T, args: T::Ptr, max_cost: Cost) -> Response<T::Ptr> {
+        self.call_with_params(allocator, None, args, max_cost, &OpParams::default())
+    }
+
+    // `op` is the opcode the operator was called by, for looking up its base
+    // cost in params.cost_table. Without it, the base cost is the operator's
+    pub fn call_with_params(
+        self,
+        allocator: &mut T,
+        op: Option<&T::AtomBuf>,
+        args: T::Ptr,
+        max_cost: Cost,
+        params: &OpParams,
+    ) -> Response<T::Ptr> {
+        let base_cost = match op {
+            Some(op) if !params.cost_table.is_empty() => params
+                .cost_table
+                .base_cost(allocator.buf(op))
+                .unwrap_or(self.base_cost),
+            _ => self.base_cost,
+        };
+        // the operator sees the budget that's left after the base cost. If
+        // that's already exhausted, its first cost check fails
+        let max_cost = max_cost.saturating_sub(base_cost);
+        let Reduction(cost, r) = match self.limited {
+            Some(f) if params.max_int_bytes != 0 => {
+                f(allocator, args, max_cost, params.max_int_bytes)?
+            }
+            _ => (self.f)(allocator, args, max_cost)?,
+        };
+        Ok(Reduction(cost.saturating_add(base_cost), r))
+    }
+}
+
+// Base costs that replace those of the operators for some opcodes, so that a
+// run can price operators differently (e.g. to try out a new cost) without
+// building its own OpTable. Only the base cost changes, what an operator
+// charges for its arguments is the same. Opcodes it doesn't list, and opcodes
+// handled by the host or as unknown operators, cost what they always do
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CostTable {
+    base_costs: BTreeMap<Vec<u8>, Cost>,
+}
+
+impl CostTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_base_cost(mut self, op: &[u8], base_cost: Cost) -> Self {
+        self.base_costs.insert(op.to_vec(), base_cost);
+        self
+    }
+
+    pub fn base_cost(&self, op: &[u8]) -> Option<Cost> {
+        self.base_costs.get(op).copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.base_costs.is_empty()
+    }
+}
+
+type Slot<T> = Option<(Vec<u8>, Op<T>)>;
+
+// An opcode -> operator table built once from all registered opcodes. It's a
+// perfect hash: the seed and table size are chosen so that no two opcodes
+// share a slot, so a lookup is one hash, one index and one comparison no
+// matter how long the opcode is. That way dialects with multi-byte operators
+// dispatch as cheaply as the core single-byte set.
+pub struct OpTable<T: Allocator> {
+    seed: u64,
+    mask: usize,
+    slots: Vec<Slot<T>>,
+}
+
+impl<T: Allocator> Clone for OpTable<T> {
+    fn clone(&self) -> Self {
+        OpTable {
+            seed: self.seed,
+            mask: self.mask,
+            slots: self.slots.clone(),
+        }
+    }
+}
+
+// FNV-1a, with the seed mixed into the offset basis
+fn hash_opcode(seed: u64, op: &[u8]) -> u64 {
+    let mut h: u64 = 0xcbf29ce484222325 ^ seed.wrapping_mul(0x9e3779b97f4a7c15);
+    for b in op {
+        h ^= *b as u64;
+        h = h.wrapping_mul(0x100000001b3);
+    }
+    h ^ (h >> 29)
+}
+
+impl<T: Allocator> OpTable<T> {
+    // later entries for the same opcode replace earlier ones
+    pub fn new(entries: &[(Vec<u8>, Op<T>)]) -> Self {
+        let mut ops: Vec<(Vec<u8>, Op<T>)> = Vec::with_capacity(entries.len());
+        for (op, f) in entries {
+            match ops.iter_mut().find(|e| e.0 == *op) {
+                Some(e) => e.1 = *f,
+                None => ops.push((op.clone(), *f)),
+            }
+        }
+
+        let mut size = (ops.len() * 2).next_power_of_two().max(1);
+        loop {
+            // with at least twice as many slots as opcodes, a collision-free
+            // seed is usually found within a few attempts. If not, grow
+            for seed in 0..64 {
+                if let Some(slots) = Self::place(&ops, seed, size) {
+                    return OpTable {
+                        seed,
+                        mask: size - 1,
+                        slots,
+                    };
+                }
+            }
+            size *= 2;
+        }
+    }
+
+    fn place(ops: &[(Vec<u8>, Op<T>)], seed: u64, size: usize) -> Option<Vec<Slot<T>>> {
+        let mut slots = vec![None; size];
+        for (op, f) in ops {
+            let idx = hash_opcode(seed, op) as usize & (size - 1);
+            if slots[idx].is_some() {
+                return None;
+            }
+            slots[idx] = Some((op.clone(), *f));
+        }
+        Some(slots)
+    }
+
+    pub fn get(&self, op: &[u8]) -> Option<Op<T>> {
+        let idx = hash_opcode(self.seed, op) as usize & self.mask;
+        match &self.slots[idx] {
+            Some((k, f)) if k.as_slice() == op => Some(*f),
+            _ => None,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.iter().filter(|s| s.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // fails if an operator is on the quote or apply opcode, where the
+    // evaluator would never call it, or if quote and apply are the same
+    pub fn check_keywords(&self, quote_kw: u8, apply_kw: u8) -> Result<(), DialectError> {
+        if quote_kw == apply_kw {
+            return Err(DialectError::KeywordCollision(quote_kw));
+        }
+        for kw in &[quote_kw, apply_kw] {
+            if self.get(&[*kw]).is_some() {
+                return Err(DialectError::KeywordCollision(*kw));
+            }
+        }
+        Ok(())
+    }
+}
+
+// every native operator along with the base cost the dispatcher charges for it
+pub fn opcode_by_name<T: Allocator>(name: &str) -> Option<Op<T>> {
+    let opcode_lookup: [(OpFn<T>, &str, Cost); 31] = [
+        (op_if, "op_if", IF_COST),
+        (op_cons, "op_cons", CONS_COST),
+        (op_first, "op_first", FIRST_COST),
+        (op_rest, "op_rest", REST_COST),
+        (op_listp, "op_listp", LISTP_COST),
+        (op_raise, "op_raise", Cost::ZERO),
+        (op_eq, "op_eq", EQ_BASE_COST),
+        (op_sha256, "op_sha256", SHA256_BASE_COST),
+        (op_add, "op_add", ARITH_BASE_COST),
+        (op_subtract, "op_subtract", ARITH_BASE_COST),
+        (op_multiply, "op_multiply", MUL_BASE_COST),
+        (op_divmod, "op_divmod", DIVMOD_BASE_COST),
+        (op_substr, "op_substr", SUBSTR_BASE_COST),
+        (op_strlen, "op_strlen", STRLEN_BASE_COST),
+        (op_point_add, "op_point_add", POINT_ADD_BASE_COST),
+        (op_pubkey_for_exp, "op_pubkey_for_exp", PUBKEY_BASE_COST),
+        (op_concat, "op_concat", CONCAT_BASE_COST),
+        (op_gr, "op_gr", GR_BASE_COST),
+        (op_gr_bytes, "op_gr_bytes", GRS_BASE_COST),
+        (op_logand, "op_logand", LOG_BASE_COST),
+        (op_logior, "op_logior", LOG_BASE_COST),
+        (op_logxor, "op_logxor", LOG_BASE_COST),
+        (op_lognot, "op_lognot", LOGNOT_BASE_COST),
+        (op_ash, "op_ash", ASHIFT_BASE_COST),
+        (op_lsh, "op_lsh", LSHIFT_BASE_COST),
+        (op_not, "op_not", BOOL_BASE_COST),
+        (op_any, "op_any", BOOL_BASE_COST),
+        (op_all, "op_all", BOOL_BASE_COST),
+        (op_softfork, "op_softfork", Cost::ZERO),
+        (op_div, "op_div", DIV_BASE_COST),
+        (op_div_floor, "op_div_floor", DIV_BASE_COST),
+    ];
+    let name: &[u8] = name.as_ref();
+    for (f, op, base_cost) in opcode_lookup.iter() {
+        let pu8: &[u8] = op.as_ref();
+        if pu8 == name {
+            let limited: Option<LimitedOpFn<T>> = match *op {
+                "op_multiply" => Some(op_multiply_limited),
+                "op_ash" => Some(op_ash_limited),
+                "op_lsh" => Some(op_lsh_limited),
+                _ => None,
+            };
+            return Some(Op {
+                f: *f,
+                base_cost: *base_cost,
+                limited,
+            });
+        }
+    }
+    None
+}
+
+// the opcodes serialize_and_run_program() assigns, as (opcode, operator name)
+pub const DEFAULT_OPCODES: [(u8, &str); 30] = [
+    (4, "op_if"),
+    (5, "op_cons"),
+    (6, "op_first"),
+    (7, "op_rest"),
+    (8, "op_listp"),
+    (9, "op_raise"),
+    (10, "op_eq"),
+    (11, "op_sha256"),
+    (12, "op_add"),
+    (13, "op_subtract"),
+    (14, "op_multiply"),
+    (15, "op_divmod"),
+    (16, "op_substr"),
+    (17, "op_strlen"),
+    (18, "op_point_add"),
+    (19, "op_pubkey_for_exp"),
+    (20, "op_concat"),
+    (22, "op_gr"),
+    (23, "op_gr_bytes"),
+    (24, "op_logand"),
+    (25, "op_logior"),
+    (26, "op_logxor"),
+    (27, "op_lognot"),
+    (28, "op_ash"),
+    (29, "op_lsh"),
+    (30, "op_not"),
+    (31, "op_any"),
+    (32, "op_all"),
+    (33, "op_softfork"),
+    (34, "op_div"),
+];
+
+// the opcodes of chia's keyword table, where quote is 1 and apply is 2
+pub const CHIA_OPCODES: [(u8, &str); 30] = [
+    (3, "op_if"),
+    (4, "op_cons"),
+    (5, "op_first"),
+    (6, "op_rest"),
+    (7, "op_listp"),
+    (8, "op_raise"),
+    (9, "op_eq"),
+    (10, "op_gr_bytes"),
+    (11, "op_sha256"),
+    (12, "op_substr"),
+    (13, "op_strlen"),
+    (14, "op_concat"),
+    (16, "op_add"),
+    (17, "op_subtract"),
+    (18, "op_multiply"),
+    (19, "op_div"),
+    (20, "op_divmod"),
+    (21, "op_gr"),
+    (22, "op_ash"),
+    (23, "op_lsh"),
+    (24, "op_logand"),
+    (25, "op_logior"),
+    (26, "op_logxor"),
+    (27, "op_lognot"),
+    (29, "op_point_add"),
+    (30, "op_pubkey_for_exp"),
+    (32, "op_not"),
+    (33, "op_any"),
+    (34, "op_all"),
+    (36, "op_softfork"),
+];
+
+// how the dialect's / rounds a negative quotient. Historical is what chia
+// has always run, and rounds toward zero when the quotient truncates to 0
+// (see op_div_floor). Floor always rounds toward negative infinity, like
+// divmod does in both
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DivRounding {
+    Historical,
+    Floor,
+}
+
+// a set of opcodes along with the quote and apply opcodes run_program() is
+// given, so tools can pick one by name
+#[derive(Clone, Copy, Debug)]
+pub struct Dialect {
+    pub name: &'static str,
+    pub opcodes: &'static [(u8, &'static str)],
+    pub quote_kw: u8,
+    pub apply_kw: u8,
+    pub div: DivRounding,
+}
+
+pub const CHIA_DIALECT: Dialect = Dialect {
+    name: "chia",
+    opcodes: &CHIA_OPCODES,
+    quote_kw: 1,
+    apply_kw: 2,
+    div: DivRounding::Historical,
+};
+
+pub const DEFAULT_DIALECT: Dialect = Dialect {
+    name: "default",
+    opcodes: &DEFAULT_OPCODES,
+    quote_kw: 1,
+    apply_kw: 2,
+    div: DivRounding::Historical,
+};
+
+pub const DIALECTS: [Dialect; 2] = [CHIA_DIALECT, DEFAULT_DIALECT];
+
+pub fn dialect_by_name(name: &str) -> Option<Dialect> {
+    DIALECTS.iter().find(|d| d.name == name).copied()
+}
+
+// why a set of opcodes can't be turned into an operator table
+#[derive(Clone, Debug, PartialEq)]
+pub enum DialectError {
+    // more than one operator is assigned this opcode
+    DuplicateOpcode(Vec<u8>),
+    // quote and apply are the same opcode, or an operator is assigned it
+    KeywordCollision(u8),
+    // there's no native operator, and so no cost, by this name
+    UnknownOperator(String),
+}
+
+impl fmt::Display for DialectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DialectError::DuplicateOpcode(opcode) => {
+                write!(f, "opcode 0x")?;
+                for b in opcode {
+                    write!(f, "{:02x}", b)?;
+                }
+                write!(f, " is assigned more than one operator")
+            }
+            DialectError::KeywordCollision(kw) => {
+                write!(
+                    f,
+                    "opcode {} is used by more than one of quote, apply and the operators",
+                    kw
+                )
+            }
+            DialectError::UnknownOperator(name) => write!(f, "unknown operator {:?}", name),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DialectError {}
+
+// checks (opcode, operator name) pairs before they're made into a table:
+// every operator must be a native one, with a cost, and each opcode may only
+// be assigned once. op_table_for() and OpTable::new() don't check either
+pub fn check_opcodes<'a, I>(opcodes: I) -> Result<(), DialectError>
+where
+    I: IntoIterator<Item = (&'a [u8], &'a str)>,
+{
+    let mut seen: Vec<&[u8]> = Vec::new();
+    for (opcode, name) in opcodes {
+        if native_op_info(name).is_none() {
+            return Err(DialectError::UnknownOperator(name.to_string()));
+        }
+        if seen.contains(&opcode) {
+            return Err(DialectError::DuplicateOpcode(opcode.to_vec()));
+        }
+        seen.push(opcode);
+    }
+    Ok(())
+}
+
+// what running an opcode does in a dialect
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OpKind {
+    // quote or apply, which the evaluator handles itself
+    Keyword,
+    // one of the dialect's operators
+    Native,
+    // not an operator of the dialect. The host is offered it (in the python
+    // bindings, the python operators), and otherwise it's a no-op that costs
+    // what its encoding says. Strict mode fails on it instead
+    Fallback,
+    // reserved (empty, or starting with 0xffff) or with a cost multiplier that
+    // doesn't fit in 32 bits. Running it always fails
+    Unknown,
+}
+
+// how many arguments an operator takes, as it checks them
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Arity {
+    Exact(usize),
+    // between the two, inclusive
+    Range(usize, usize),
+    AtLeast(usize),
+}
+
+// the linear part of an operator's cost, in the arguments it's given. Some
+// operators also charge for the size of their result, and multiply for the
+// product of its operand sizes. The cost of softfork is its first argument
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct OpCost {
+    pub base: Cost,
+    pub per_arg: Cost,
+    pub per_byte: Cost,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OpInfo {
+    pub kind: OpKind,
+    // the name in opcode_by_name() of a native operator, or "q" and "a"
+    pub name: Option<&'static str>,
+    pub cost: OpCost,
+    pub arity: Arity,
+}
+
+const fn op_cost(base: Cost, per_arg: Cost, per_byte: Cost) -> OpCost {
+    OpCost {
+        base,
+        per_arg,
+        per_byte,
+    }
+}
+
+// the cost and arity of every native operator, by name
+fn native_op_info(name: &str) -> Option<(OpCost, Arity)> {
+    let info = match name {
+        "op_if" => (op_cost(IF_COST, Cost::ZERO, Cost::ZERO), Arity::Exact(3)),
+        "op_cons" => (op_cost(CONS_COST, Cost::ZERO, Cost::ZERO), Arity::Exact(2)),
+        "op_first" => (op_cost(FIRST_COST, Cost::ZERO, Cost::ZERO), Arity::Exact(1)),
+        "op_rest" => (op_cost(REST_COST, Cost::ZERO, Cost::ZERO), Arity::Exact(1)),
+        "op_listp" => (op_cost(LISTP_COST, Cost::ZERO, Cost::ZERO), Arity::Exact(1)),
+        "op_raise" => (
+            op_cost(Cost::ZERO, Cost::ZERO, Cost::ZERO),
+            Arity::AtLeast(0),
+        ),
+        "op_eq" => (
+            op_cost(EQ_BASE_COST, Cost::ZERO, EQ_COST_PER_BYTE),
+            Arity::Exact(2),
+        ),
+        "op_sha256" => (
+            op_cost(SHA256_BASE_COST, SHA256_COST_PER_ARG, SHA256_COST_PER_BYTE),
+            Arity::AtLeast(0),
+        ),
+        "op_add" | "op_subtract" => (
+            op_cost(ARITH_BASE_COST, ARITH_COST_PER_ARG, ARITH_COST_PER_BYTE),
+            Arity::AtLeast(0),
+        ),
+        "op_multiply" => (
+            op_cost(MUL_BASE_COST, MUL_COST_PER_OP, MUL_LINEAR_COST_PER_BYTE),
+            Arity::AtLeast(0),
+        ),
+        "op_divmod" => (
+            op_cost(DIVMOD_BASE_COST, Cost::ZERO, DIVMOD_COST_PER_BYTE),
+            Arity::Exact(2),
+        ),
+        "op_div" | "op_div_floor" => (
+            op_cost(DIV_BASE_COST, Cost::ZERO, DIV_COST_PER_BYTE),
+            Arity::Exact(2),
+        ),
+        "op_substr" => (
+            op_cost(SUBSTR_BASE_COST, Cost::ZERO, Cost::ZERO),
+            Arity::Range(2, 3),
+        ),
+        "op_strlen" => (
+            op_cost(STRLEN_BASE_COST, Cost::ZERO, STRLEN_COST_PER_BYTE),
+            Arity::Exact(1),
+        ),
+        "op_point_add" => (
+            op_cost(POINT_ADD_BASE_COST, POINT_ADD_COST_PER_ARG, Cost::ZERO),
+            Arity::AtLeast(0),
+        ),
+        "op_pubkey_for_exp" => (
+            op_cost(PUBKEY_BASE_COST, Cost::ZERO, PUBKEY_COST_PER_BYTE),
+            Arity::Exact(1),
+        ),
+        "op_concat" => (
+            op_cost(CONCAT_BASE_COST, CONCAT_COST_PER_ARG, CONCAT_COST_PER_BYTE),
+            Arity::AtLeast(0),
+        ),
+        "op_gr" => (
+            op_cost(GR_BASE_COST, Cost::ZERO, GR_COST_PER_BYTE),
+            Arity::Exact(2),
+        ),
+        "op_gr_bytes" => (
+            op_cost(GRS_BASE_COST, Cost::ZERO, GRS_COST_PER_BYTE),
+            Arity::Exact(2),
+        ),
+        "op_logand" | "op_logior" | "op_logxor" => (
+            op_cost(LOG_BASE_COST, LOG_COST_PER_ARG, LOG_COST_PER_BYTE),
+            Arity::AtLeast(0),
+        ),
+        "op_lognot" => (
+            op_cost(LOGNOT_BASE_COST, Cost::ZERO, LOGNOT_COST_PER_BYTE),
+            Arity::Exact(1),
+        ),
+        "op_ash" => (
+            op_cost(ASHIFT_BASE_COST, Cost::ZERO, ASHIFT_COST_PER_BYTE),
+            Arity::Exact(2),
+        ),
+        "op_lsh" => (
+            op_cost(LSHIFT_BASE_COST, Cost::ZERO, LSHIFT_COST_PER_BYTE),
+            Arity::Exact(2),
+        ),
+        "op_not" => (
+            op_cost(BOOL_BASE_COST, Cost::ZERO, Cost::ZERO),
+            Arity::Exact(1),
+        ),
+        "op_any" | "op_all" => (
+            op_cost(BOOL_BASE_COST, BOOL_COST_PER_ARG, Cost::ZERO),
+            Arity::AtLeast(0),
+        ),
+        "op_softfork" => (
+            op_cost(Cost::ZERO, Cost::ZERO, Cost::ZERO),
+            Arity::AtLeast(1),
+        ),
+        _ => return None,
+    };
+    Some(info)
+}
+
+impl Dialect {
+    // fails on what would make the dialect misbehave at runtime, rather than
+    // on the first program that uses it
+    pub fn validate(&self) -> Result<(), DialectError> {
+        check_opcodes(
+            self.opcodes
+                .iter()
+                .map(|(opcode, name)| (core::slice::from_ref(opcode), *name)),
+        )?;
+        if self.quote_kw == self.apply_kw {
+            return Err(DialectError::KeywordCollision(self.quote_kw));
+        }
+        match self
+            .opcodes
+            .iter()
+            .find(|(opcode, _)| *opcode == self.quote_kw || *opcode == self.apply_kw)
+        {
+            Some((opcode, _)) => Err(DialectError::KeywordCollision(*opcode)),
+            None => Ok(()),
+        }
+    }
+
+    // the dialect's operators, once it's been validated
+    pub fn op_table<T: Allocator>(&self) -> Result<OpTable<T>, DialectError> {
+        self.validate()?;
+        Ok(self.operators())
+    }
+
+    // the dialect's operators, with / rounding as the dialect says. Unlike
+    // op_table_for(self.opcodes), which always has the historical op_div
+    pub fn operators<T: Allocator>(&self) -> OpTable<T> {
+        let opcodes: Vec<(u8, &str)> = self
+            .opcodes
+            .iter()
+            .map(|(opcode, name)| (*opcode, self.native_name(name)))
+            .collect();
+        op_table_for(&opcodes)
+    }
+
+    pub fn with_div(mut self, div: DivRounding) -> Self {
+        self.div = div;
+        self
+    }
+
+    // the native operator the dialect runs for the one named in its opcodes
+    fn native_name<'a>(&self, name: &'a str) -> &'a str {
+        match (self.div, name) {
+            (DivRounding::Floor, "op_div") => "op_div_floor",
+            _ => name,
+        }
+    }
+
+    // what the opcode would do in a program run with this dialect's
+    // operators, for checking programs before they're submitted. For
+    // fallback opcodes the cost is op_unknown()'s, as encoded in the opcode
+    pub fn supports(&self, opcode: &[u8]) -> OpInfo {
+        if opcode.len() == 1 {
+            for (kw, name) in &[(self.quote_kw, "q"), (self.apply_kw, "a")] {
+                if opcode[0] == *kw {
+                    return OpInfo {
+                        kind: OpKind::Keyword,
+                        name: Some(name),
+                        cost: OpCost::default(),
+                        arity: Arity::AtLeast(0),
+                    };
+                }
+            }
+            let native = self
+                .opcodes
+                .iter()
+                .find(|(o, _)| *o == opcode[0])
+                .map(|(_, name)| self.native_name(name))
+                .and_then(|name| native_op_info(name).map(|info| (name, info)));
+            if let Some((name, (cost, arity))) = native {
+                return OpInfo {
+                    kind: OpKind::Native,
+                    name: Some(name),
+                    cost,
+                    arity,
+                };
+            }
+        }
+
+        let unknown = OpInfo {
+            kind: OpKind::Unknown,
+            name: None,
+            cost: OpCost::default(),
+            arity: Arity::AtLeast(0),
+        };
+        if opcode.is_empty() || (opcode.len() >= 2 && opcode[0] == 0xff && opcode[1] == 0xff) {
+            return unknown;
+        }
+        let multiplier = match u32_from_u8(&opcode[0..opcode.len() - 1]) {
+            Some(v) => v as u64 + 1,
+            None => return unknown,
+        };
+        // the top two bits of the last byte pick the cost function
+        let cost = match opcode[opcode.len() - 1] >> 6 {
+            0 => op_cost(Cost::new(1), Cost::ZERO, Cost::ZERO),
+            1 => op_cost(ARITH_BASE_COST, ARITH_COST_PER_ARG, ARITH_COST_PER_BYTE),
+            2 => op_cost(MUL_BASE_COST, MUL_COST_PER_OP, MUL_LINEAR_COST_PER_BYTE),
+            _ => op_cost(CONCAT_BASE_COST, CONCAT_COST_PER_ARG, CONCAT_COST_PER_BYTE),
+        };
+        OpInfo {
+            kind: OpKind::Fallback,
+            name: None,
+            cost: op_cost(
+                cost.base * multiplier,
+                cost.per_arg * multiplier,
+                cost.per_byte * multiplier,
+            ),
+            arity: Arity::AtLeast(0),
+        }
+    }
+}
+
+// an OpTable with the native operators for the given (opcode, operator name)
+// pairs. Names without a native operator are skipped, see check_opcodes()
+pub fn op_table_for<T: Allocator>(opcodes: &[(u8, &str)]) -> OpTable<T> {
+    let entries: Vec<(Vec<u8>, Op<T>)> = opcodes
+        .iter()
+        .filter_map(|(opcode, name)| opcode_by_name(name).map(|op| (vec![*opcode], op)))
+        .collect();
+    OpTable::new(&entries)
+}
+
+// what an opcode no table or host implements does: it fails in strict mode,
+// and follows the unknown operator rules otherwise
+fn op_unknown_with_params<T: Allocator>(
+    allocator: &mut T,
+    o: <T as Allocator>::AtomBuf,
+    args: &T::Ptr,
+    max_cost: Cost,
+    params: &OpParams,
+) -> Response<<T as Allocator>::Ptr> {
+    if params.strict {
+        let op = allocator.buf(&o).to_vec();
+        let node = allocator.new_atom(&op)?;
+        return err(node, "unimplemented operator");
+    }
+    op_unknown(allocator, o, args.clone(), max_cost)
+}
+
+// an OpTable on its own can drive run_program(). Opcodes not in the table
+// are treated as unknown operators (lenient, unless the run is strict)
+impl<T: Allocator> OperatorHandler<T> for OpTable<T> {
+    fn op(
+        &mut self,
+        allocator: &mut T,
+        o: <T as Allocator>::AtomBuf,
+        args: &T::Ptr,
+        max_cost: Cost,
+    ) -> Response<<T as Allocator>::Ptr> {
+        self.op_with_params(allocator, o, args, max_cost, &OpParams::default())
+    }
+
+    fn op_with_params(
+        &mut self,
+        allocator: &mut T,
+        o: <T as Allocator>::AtomBuf,
+        args: &T::Ptr,
+        max_cost: Cost,
+        params: &OpParams,
+    ) -> Response<<T as Allocator>::Ptr> {
+        if let Some(f) = self.get(allocator.buf(&o)) {
+            return f.call_with_params(allocator, Some(&o), args.clone(), max_cost, params);
+        }
+        op_unknown_with_params(allocator, o, args, max_cost, params)
+    }
+}
+
+// Operators supplied by the embedder, for the opcodes an OpTable doesn't
+// implement natively. The python bindings hand them to a python callable, the
+// C and JavaScript bindings to a host callback. Returning None leaves the
+// opcode to the unknown operator rules
+pub trait HostOperators<T: Allocator> {
+    fn op(
+        &self,
+        allocator: &mut T,
+        op: <T as Allocator>::AtomBuf,
+        args: &T::Ptr,
+        max_cost: Cost,
+    ) -> Option<Response<<T as Allocator>::Ptr>>;
+}
+
+// an OpTable that offers the opcodes it doesn't know to the host
+pub struct HostOpTable<T: Allocator, H> {
+    pub table: OpTable<T>,
+    pub host: H,
+}
+
+impl<T: Allocator, H: Clone> Clone for HostOpTable<T, H> {
+    fn clone(&self) -> Self {
+        HostOpTable {
+            table: self.table.clone(),
+            host: self.host.clone(),
+        }
+    }
+}
+
+impl<T: Allocator, H: HostOperators<T>> OperatorHandler<T> for HostOpTable<T, H> {
+    fn op(
+        &mut self,
+        allocator: &mut T,
+        o: <T as Allocator>::AtomBuf,
+        args: &T::Ptr,
+        max_cost: Cost,
+    ) -> Response<<T as Allocator>::Ptr> {
+        self.op_with_params(allocator, o, args, max_cost, &OpParams::default())
+    }
+
+    fn op_with_params(
+        &mut self,
+        allocator: &mut T,
+        o: <T as Allocator>::AtomBuf,
+        args: &T::Ptr,
+        max_cost: Cost,
+        params: &OpParams,
+    ) -> Response<<T as Allocator>::Ptr> {
+        if let Some(f) = self.table.get(allocator.buf(&o)) {
+            return f.call_with_params(allocator, Some(&o), args.clone(), max_cost, params);
+        }
+        if let Some(r) = self.host.op(allocator, o.clone(), args, max_cost) {
+            #[cfg(feature = "tracing")]
+            tracing::trace!(opcode = %hex::encode(allocator.buf(&o)), "host operator");
+            return r;
+        }
+        op_unknown_with_params(allocator, o, args, max_cost, params)
+    }
+}
+
+#[cfg(test)]
+fn op<T: Allocator>(f: OpFn<T>, base_cost: Cost) -> Op<T> {
+    Op {
+        f,
+        base_cost,
+        limited: None,
+    }
+}
+
+#[test]
+fn test_op_table() {
+    use crate::core_ops::{op_cons, op_first, op_rest};
+    use crate::int_allocator::IntAllocator;
+    use crate::more_ops::{op_add, op_subtract};
+
+    let table: OpTable<IntAllocator> = OpTable::new(&[
+        (vec![4], op(op_cons, Cost::new(50))),
+        (vec![5], op(op_first, Cost::new(30))),
+        (vec![6], op(op_rest, Cost::new(30))),
+        (vec![0x10], op(op_subtract, Cost::new(99))),
+        // the last registration wins
+        (vec![0x10], op(op_add, Cost::new(99))),
+        (vec![0xff, 0x00, 0x01], op(op_subtract, Cost::new(99))),
+        (vec![], op(op_first, Cost::new(30))),
+    ]);
+    assert_eq!(table.len(), 6);
+
+    let mut a = IntAllocator::new();
+    let one = a.one();
+    let args = a.new_pair(one, a.null()).unwrap();
+    let args = a.new_pair(one, args).unwrap();
+
+    let sum = table
+        .get(&[0x10])
+        .unwrap()
+        .call(&mut a, args, Cost::new(1000000))
+        .unwrap();
+    assert_eq!(a.atom(&sum.1), &[2]);
+    let diff = table
+        .get(&[0xff, 0x00, 0x01])
+        .unwrap()
+        .call(&mut a, args, Cost::new(1000000))
+        .unwrap();
+    assert_eq!(a.atom(&diff.1), &[] as &[u8]);
+    assert!(table.get(&[]).is_some());
+
+    assert!(table.get(&[7]).is_none());
+    assert!(table.get(&[0xff, 0x00]).is_none());
+    assert!(table.get(&[0x10, 0x00]).is_none());
+
+    let empty: OpTable<IntAllocator> = OpTable::new(&[]);
+    assert!(empty.is_empty());
+    assert!(empty.get(&[1]).is_none());
+
+    // all single byte opcodes at once
+    let all: Vec<(Vec<u8>, Op<IntAllocator>)> = (0..=255_u8)
+        .map(|i| (vec![i], op(op_add, Cost::new(99))))
+        .collect();
+    let table = OpTable::new(&all);
+    assert_eq!(table.len(), 256);
+    for i in 0..=255_u8 {
+        assert!(table.get(&[i]).is_some());
+    }
+}
+
+#[test]
+fn test_op_base_cost() {
+    use crate::core_ops::op_cons;
+    use crate::int_allocator::IntAllocator;
+    use crate::more_ops::op_add;
+
+    let mut a = IntAllocator::new();
+    let one = a.one();
+    let args = a.new_pair(one, a.null()).unwrap();
+    let args = a.new_pair(one, args).unwrap();
+
+    // the base cost is added to what the operator returns
+    let cons = op(op_cons, Cost::new(50));
+    assert_eq!(
+        cons.call(&mut a, args, Cost::new(1000)).unwrap().0,
+        Cost::new(50)
+    );
+
+    // (+ 1 1) costs 2 args * 320 + 2 bytes * 3 + 1 byte allocated * 10
+    let add = op(op_add, Cost::new(99));
+    assert_eq!(
+        add.call(&mut a, args, Cost::new(1000)).unwrap().0,
+        Cost::new(99 + 640 + 6 + 10)
+    );
+
+    // the operator only gets the budget left over after the base cost
+    assert!(add.call(&mut a, args, Cost::new(99 + 642)).is_err());
+    assert!(add.call(&mut a, args, Cost::new(99 + 643)).is_ok());
+    assert!(add.call(&mut a, args, Cost::new(50)).is_err());
+}
+
+#[test]
+fn test_op_table_for() {
+    use crate::int_allocator::IntAllocator;
+    use crate::run_program::run_program;
+
+    let table: OpTable<IntAllocator> = op_table_for(&DEFAULT_OPCODES);
+    assert_eq!(table.len(), DEFAULT_OPCODES.len());
+    assert!(table.get(&[21]).is_none());
+    assert!(table.get(&[12]).is_some());
+
+    let table: OpTable<IntAllocator> = op_table_for(&CHIA_OPCODES);
+    assert_eq!(table.len(), CHIA_OPCODES.len());
+    assert!(table.get(&[15]).is_none());
+
+    // (+ (q . 2) (q . 3))
+    let mut a = IntAllocator::new();
+    let q = a.one();
+    let two = a.new_atom(&[2]).unwrap();
+    let three = a.new_atom(&[3]).unwrap();
+    let plus = a.new_atom(&[16]).unwrap();
+    let q2 = a.new_pair(q, two).unwrap();
+    let q3 = a.new_pair(q, three).unwrap();
+    let args = a.new_pair(q3, a.null()).unwrap();
+    let args = a.new_pair(q2, args).unwrap();
+    let program = a.new_pair(plus, args).unwrap();
+    let null = a.null();
+
+    let r = run_program(
+        &mut a,
+        &program,
+        &null,
+        1,
+        2,
+        Cost::new(100000),
+        Box::new(table),
+        None,
+    )
+    .unwrap();
+    assert_eq!(a.atom(&r.1), &[5]);
+}
+
+#[test]
+fn test_host_op_table() {
+    use crate::int_allocator::IntAllocator;
+    use crate::node::Node;
+    use crate::run_program::run_program;
+    use crate::serialize::{node_from_bytes, node_to_bytes};
+
+    // implements opcode 0x77 as "return the argument list", and nothing else
+    struct Echo;
+
+    impl HostOperators<IntAllocator> for Echo {
+        fn op(
+            &self,
+            allocator: &mut IntAllocator,
+            op: <IntAllocator as Allocator>::AtomBuf,
+            args: &i32,
+            _max_cost: Cost,
+        ) -> Option<Response<i32>> {
+            if allocator.buf(&op) == [0x77] {
+                Some(Ok(Reduction(Cost::new(7), *args)))
+            } else {
+                None
+            }
+        }
+    }
+
+    let run = |program: &str| {
+        let mut a = IntAllocator::new();
+        let program = node_from_bytes(&mut a, &hex::decode(program).unwrap()).unwrap();
+        let null = a.null();
+        let table = HostOpTable {
+            table: op_table_for(&CHIA_OPCODES),
+            host: Echo,
+        };
+        run_program(
+            &mut a,
+            &program,
+            &null,
+            1,
+            2,
+            Cost::ZERO,
+            Box::new(table),
+            None,
+        )
+        .map(|r| (r.0, node_to_bytes(&Node::new(&a, r.1)).unwrap()))
+        .map_err(|e| e.1.to_string())
+    };
+
+    // (0x77 (q . 1) (q . 2)) is answered by the host
+    let (cost, result) = run("ff77ffff0101ffff010280").unwrap();
+    assert_eq!(result, hex::decode("ff01ff0280").unwrap());
+    // two quotes, evaluating the operands and the host's cost
+    assert_eq!(cost, Cost::new(20 + 20 + 1 + 7));
+
+    // native operators still win: (+ (q . 1) (q . 2))
+    let (_, result) = run("ff10ffff0101ffff010280").unwrap();
+    assert_eq!(result, [3]);
+
+    // what neither knows is an unknown operator, and those are legal as long
+    // as they don't start with 0xffff
+    assert!(run("ff78ffff0101ffff010280").is_ok());
+    assert_eq!(
+        run("ff82ffffffff0101ffff010280").unwrap_err(),
+        "reserved operator"
+    );
+}
+
+#[test]
+fn test_dialect_supports() {
+    let info = CHIA_DIALECT.supports(&[16]);
+    assert_eq!(info.kind, OpKind::Native);
+    assert_eq!(info.name, Some("op_add"));
+    assert_eq!(
+        info.cost,
+        op_cost(Cost::new(99), Cost::new(320), Cost::new(3))
+    );
+    assert_eq!(info.arity, Arity::AtLeast(0));
+
+    let info = CHIA_DIALECT.supports(&[3]);
+    assert_eq!(info.name, Some("op_if"));
+    assert_eq!(info.arity, Arity::Exact(3));
+    assert_eq!(CHIA_DIALECT.supports(&[12]).arity, Arity::Range(2, 3));
+    assert_eq!(CHIA_DIALECT.supports(&[1]).kind, OpKind::Keyword);
+    assert_eq!(CHIA_DIALECT.supports(&[2]).name, Some("a"));
+
+    // every operator of every dialect is described
+    for d in DIALECTS.iter() {
+        for (opcode, name) in d.opcodes.iter() {
+            let info = d.supports(&[*opcode]);
+            assert_eq!(info.kind, OpKind::Native);
+            assert_eq!(info.name, Some(*name));
+            assert_eq!(
+                info.cost.base,
+                opcode_by_name::<crate::int_allocator::IntAllocator>(name)
+                    .unwrap()
+                    .base_cost
+            );
+        }
+    }
+
+    // 16 is + in chia's table, but substr in the default one, where 21 isn't
+    // an operator
+    assert_eq!(DEFAULT_DIALECT.supports(&[16]).name, Some("op_substr"));
+    let info = DEFAULT_DIALECT.supports(&[0x15]);
+    assert_eq!(info.kind, OpKind::Fallback);
+    assert_eq!(info.cost, op_cost(Cost::new(1), Cost::ZERO, Cost::ZERO));
+
+    // a multiplier of 0x3c + 1, with the add cost function
+    let info = CHIA_DIALECT.supports(&[0x3c, 0x40]);
+    assert_eq!(info.kind, OpKind::Fallback);
+    assert_eq!(
+        info.cost,
+        op_cost(Cost::new(99 * 61), Cost::new(320 * 61), Cost::new(3 * 61))
+    );
+    // and the concat one
+    assert_eq!(
+        CHIA_DIALECT.supports(&[0xc0]).cost,
+        op_cost(Cost::new(142), Cost::new(135), Cost::new(3))
+    );
+
+    assert_eq!(CHIA_DIALECT.supports(&[]).kind, OpKind::Unknown);
+    assert_eq!(
+        CHIA_DIALECT.supports(&[0xff, 0xff, 0x01]).kind,
+        OpKind::Unknown
+    );
+    assert_eq!(
+        CHIA_DIALECT.supports(&[1, 2, 3, 4, 5, 6]).kind,
+        OpKind::Unknown
+    );
+}
+
+#[test]
+fn test_dialect_validate() {
+    use crate::int_allocator::IntAllocator;
+
+    for dialect in &DIALECTS {
+        assert_eq!(dialect.validate(), Ok(()));
+        let table: OpTable<IntAllocator> = dialect.op_table().unwrap();
+        assert_eq!(table.len(), dialect.opcodes.len());
+        assert_eq!(table.check_keywords(1, 2), Ok(()));
+    }
+
+    let dialect = |opcodes, quote_kw, apply_kw| Dialect {
+        name: "test",
+        opcodes,
+        quote_kw,
+        apply_kw,
+        div: DivRounding::Historical,
+    };
+    assert_eq!(
+        dialect(&[(3, "op_if"), (4, "op_cons"), (3, "op_first")], 1, 2).validate(),
+        Err(DialectError::DuplicateOpcode(vec![3]))
+    );
+    assert_eq!(
+        dialect(&[(3, "op_if"), (4, "op_frobnicate")], 1, 2).validate(),
+        Err(DialectError::UnknownOperator("op_frobnicate".to_string()))
+    );
+    assert_eq!(
+        dialect(&[(3, "op_if"), (2, "op_cons")], 1, 2).validate(),
+        Err(DialectError::KeywordCollision(2))
+    );
+    assert_eq!(
+        dialect(&[(3, "op_if")], 1, 1).validate(),
+        Err(DialectError::KeywordCollision(1))
+    );
+    assert!(dialect(&[(3, "op_if")], 1, 3)
+        .op_table::<IntAllocator>()
+        .is_err());
+
+    let table: OpTable<IntAllocator> = op_table_for(&[(1, "op_if")]);
+    assert_eq!(
+        table.check_keywords(1, 2),
+        Err(DialectError::KeywordCollision(1))
+    );
+    assert_eq!(
+        DialectError::DuplicateOpcode(vec![0xff, 0x01]).to_string(),
+        "opcode 0xff01 is assigned more than one operator"
+    );
+}
+
+#[test]
+fn test_div_rounding() {
+    use crate::int_allocator::IntAllocator;
+    use crate::number::{number_from_u8, ptr_from_number, Number};
+    use crate::run_program::run_program;
+
+    let div = |dialect: Dialect, a0: i64, a1: i64| -> Number {
+        let mut a = IntAllocator::new();
+        let n0 = ptr_from_number(&mut a, &Number::from(a0)).unwrap();
+        let n1 = ptr_from_number(&mut a, &Number::from(a1)).unwrap();
+        let env = a.new_pair(n1, a.null()).unwrap();
+        let env = a.new_pair(n0, env).unwrap();
+        // (/ 2 5)
+        let program =
+            crate::serialize::node_from_bytes(&mut a, &[0xff, 19, 0xff, 2, 0xff, 5, 0x80]).unwrap();
+        let r = run_program(
+            &mut a,
+            &program,
+            &env,
+            dialect.quote_kw,
+            dialect.apply_kw,
+            Cost::ZERO,
+            Box::new(dialect.operators()),
+            None,
+        )
+        .unwrap();
+        number_from_u8(a.atom(&r.1))
+    };
+    let floor = CHIA_DIALECT.with_div(DivRounding::Floor);
+    assert_eq!(floor.validate(), Ok(()));
+    for (a0, a1, historical, floored) in &[
+        (7, 2, 3, 3),
+        (-7, 2, -4, -4),
+        (7, -2, -4, -4),
+        (-7, -2, 3, 3),
+        (-1, 2, 0, -1),
+        (1, -2, 0, -1),
+        (-1, -2, 0, 0),
+        (0, -2, 0, 0),
+        (-4, 2, -2, -2),
+    ] {
+        assert_eq!(div(CHIA_DIALECT, *a0, *a1), Number::from(*historical));
+        assert_eq!(div(floor, *a0, *a1), Number::from(*floored));
+    }
+
+    assert_eq!(CHIA_DIALECT.supports(&[19]).name, Some("op_div"));
+    assert_eq!(floor.supports(&[19]).name, Some("op_div_floor"));
+    assert_eq!(
+        floor.supports(&[19]).cost,
+        CHIA_DIALECT.supports(&[19]).cost
+    );
+}
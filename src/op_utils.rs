@@ -1,39 +1,89 @@
-use crate::allocator::Allocator;
-use crate::err_utils::err;
+use crate::allocator::{Allocator, SExp};
+use crate::err_utils::{err, limit_err};
 use crate::node::Node;
 use crate::number::{number_from_u8, Number};
-use crate::reduction::EvalErr;
+use crate::reduction::{ErrMsg, EvalErr};
+
+// the largest shift amount accepted by ash and lsh
+pub const MAX_SHIFT: i32 = 65535;
+
+// fail if an operator is about to produce an integer of (up to) `size` bytes
+// and that exceeds `max_int_bytes`. 0 means no limit. This is meant to be
+// called *before* computing the result, with an upper bound of its size
+pub fn check_int_size<T: Allocator>(
+    args: &Node<T>,
+    size: usize,
+    max_int_bytes: usize,
+    op_name: &'static str,
+) -> Result<(), EvalErr<T::Ptr>> {
+    if max_int_bytes != 0 && size > max_int_bytes {
+        limit_err(
+            args.node.clone(),
+            ErrMsg::Op(op_name, "result exceeds max integer size"),
+        )
+    } else {
+        Ok(())
+    }
+}
+
+// parse the shift amount (exponent) of ash and lsh, rejecting values whose
+// magnitude exceeds MAX_SHIFT
+pub fn shift_atom<A: Allocator>(
+    args: &Node<A>,
+    op_name: &'static str,
+) -> Result<i32, EvalErr<A::Ptr>> {
+    let v = i32_atom(args, op_name)?;
+    if v.abs() > MAX_SHIFT {
+        return args.err("shift too large");
+    }
+    Ok(v)
+}
 
 pub fn check_arg_count<T: Allocator>(
     args: &Node<T>,
     expected: usize,
-    name: &str,
+    name: &'static str,
 ) -> Result<(), EvalErr<T::Ptr>> {
     if arg_count(args, expected) != expected {
-        args.err(&format!(
-            "{} takes exactly {} argument{}",
-            name,
-            expected,
-            if expected == 1 { "" } else { "s" }
-        ))
+        args.err(ErrMsg::ArgCount(name, expected))
     } else {
         Ok(())
     }
 }
 
 pub fn arg_count<T: Allocator>(args: &Node<T>, return_early_if_exceeds: usize) -> usize {
-    let mut count = 0;
-    // It would be nice to have a trait that wouldn't require us to copy every
-    // node
-    let mut ptr = args.clone();
-    while let Some((_, next)) = ptr.pair() {
-        ptr = next.clone();
-        count += 1;
-        if count > return_early_if_exceeds {
-            break;
-        };
+    arg_iter(args.allocator, args.node.clone())
+        .take(return_early_if_exceeds.saturating_add(1))
+        .count()
+}
+
+// iterates over the items of a list as plain pointers, without wrapping each
+// one in a Node. Like iterating over a Node, it stops at the first atom,
+// whether or not it's nil
+pub struct ArgIter<'a, T: Allocator> {
+    allocator: &'a T,
+    next: T::Ptr,
+}
+
+pub fn arg_iter<T: Allocator>(allocator: &T, args: T::Ptr) -> ArgIter<'_, T> {
+    ArgIter {
+        allocator,
+        next: args,
+    }
+}
+
+impl<'a, T: Allocator> Iterator for ArgIter<'a, T> {
+    type Item = T::Ptr;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.allocator.sexp(&self.next) {
+            SExp::Pair(first, rest) => {
+                self.next = rest;
+                Some(first)
+            }
+            SExp::Atom(_) => None,
+        }
     }
-    count
 }
 
 #[test]
@@ -71,30 +121,132 @@ fn test_arg_count() {
     assert_eq!(arg_count(&count_3_args, 4), 3);
 }
 
+#[test]
+fn test_arg_iter() {
+    use crate::int_allocator::IntAllocator;
+
+    let mut a = IntAllocator::new();
+    let one = a.one();
+    let null = a.null();
+    let list = a.new_pair(null, one).unwrap();
+    let list = a.new_pair(one, list).unwrap();
+
+    // iteration stops at the terminating atom, even if it isn't nil
+    let items: Vec<i32> = arg_iter(&a, list).collect();
+    assert_eq!(items, vec![one, null]);
+    assert_eq!(arg_iter(&a, null).count(), 0);
+    assert_eq!(arg_iter(&a, one).count(), 0);
+
+    assert_eq!(int_atom_ptr(&a, &one, "+").unwrap(), &[1]);
+    let e = int_atom_ptr(&a, &list, "+").unwrap_err();
+    assert_eq!(e.0, list);
+    assert_eq!(e.1, "+ requires int args");
+    let e = atom_ptr(&a, &list, "concat").unwrap_err();
+    assert_eq!(e.1, "concat on list");
+}
+
 pub fn int_atom<'a, T: Allocator>(
     args: &'a Node<T>,
-    op_name: &str,
+    op_name: &'static str,
 ) -> Result<&'a [u8], EvalErr<T::Ptr>> {
-    match args.atom() {
-        Some(a) => Ok(a),
-        _ => args.err(&format!("{} requires int args", op_name)),
+    int_atom_ptr(args.allocator, &args.node, op_name)
+}
+
+pub fn int_atom_ptr<'a, T: Allocator>(
+    allocator: &'a T,
+    node: &'a T::Ptr,
+    op_name: &'static str,
+) -> Result<&'a [u8], EvalErr<T::Ptr>> {
+    match allocator.sexp(node) {
+        SExp::Atom(_) => Ok(allocator.atom(node)),
+        _ => err(node.clone(), ErrMsg::Op(op_name, "requires int args")),
     }
 }
 
 // rename to atom()
 pub fn atom<'a, T: Allocator>(
     args: &'a Node<T>,
-    op_name: &str,
+    op_name: &'static str,
 ) -> Result<&'a [u8], EvalErr<T::Ptr>> {
-    match args.atom() {
-        Some(a) => Ok(a),
-        _ => args.err(&format!("{} on list", op_name)),
+    atom_ptr(args.allocator, &args.node, op_name)
+}
+
+pub fn atom_ptr<'a, T: Allocator>(
+    allocator: &'a T,
+    node: &'a T::Ptr,
+    op_name: &'static str,
+) -> Result<&'a [u8], EvalErr<T::Ptr>> {
+    match allocator.sexp(node) {
+        SExp::Atom(_) => Ok(allocator.atom(node)),
+        _ => err(node.clone(), ErrMsg::Op(op_name, "on list")),
+    }
+}
+
+// an atom argument, looked up once. It keeps the node, to build errors and
+// substrings from, along with the atom's buffer handle. Reading the contents
+// again doesn't go back through sexp() or the node table
+pub struct AtomRef<'a, A: Allocator> {
+    allocator: &'a A,
+    pub node: A::Ptr,
+    buf: A::AtomBuf,
+}
+
+impl<'a, A: Allocator> AtomRef<'a, A> {
+    pub fn as_slice(&self) -> &[u8] {
+        self.allocator.buf(&self.buf)
+    }
+
+    pub fn buf(&self) -> &A::AtomBuf {
+        &self.buf
+    }
+
+    pub fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.as_slice().is_empty()
+    }
+}
+
+pub fn atom_ref<'a, A: Allocator>(
+    allocator: &'a A,
+    node: A::Ptr,
+    op_name: &'static str,
+) -> Result<AtomRef<'a, A>, EvalErr<A::Ptr>> {
+    match allocator.sexp(&node) {
+        SExp::Atom(buf) => Ok(AtomRef {
+            allocator,
+            node,
+            buf,
+        }),
+        _ => err(node, ErrMsg::Op(op_name, "on list")),
     }
 }
 
+#[test]
+fn test_atom_ref() {
+    use crate::int_allocator::IntAllocator;
+
+    let mut a = IntAllocator::new();
+    let atom = a.new_atom(&[1, 2, 3]).unwrap();
+    let pair = a.new_pair(atom, atom).unwrap();
+
+    let r = atom_ref(&a, atom, "substr").unwrap();
+    assert_eq!(r.node, atom);
+    assert_eq!(r.as_slice(), &[1, 2, 3]);
+    assert_eq!(r.len(), 3);
+    assert!(!r.is_empty());
+    assert!(atom_ref(&a, a.null(), "substr").unwrap().is_empty());
+
+    let e = atom_ref(&a, pair, "substr").err().unwrap();
+    assert_eq!(e.0, pair);
+    assert_eq!(e.1, "substr on list");
+}
+
 pub fn two_ints<T: Allocator>(
     args: &Node<T>,
-    op_name: &str,
+    op_name: &'static str,
 ) -> Result<(Number, usize, Number, usize), EvalErr<T::Ptr>> {
     check_arg_count(args, 2, op_name)?;
     let a0 = args.first()?;
@@ -104,27 +256,30 @@ pub fn two_ints<T: Allocator>(
     Ok((number_from_u8(n0), n0.len(), number_from_u8(n1), n1.len()))
 }
 
-fn u32_from_u8_impl(buf: &[u8], signed: bool) -> Option<u32> {
+// decode a big-endian integer of at most `max_len` bytes. Leading zeros are
+// not stripped, so they count towards the length. When `signed` is set, the
+// result is sign-extended to the full u128
+fn u128_from_u8_impl(buf: &[u8], signed: bool, max_len: usize) -> Option<u128> {
     if buf.is_empty() {
         return Some(0);
     }
 
-    // too many bytes for u32
-    if buf.len() > 4 {
+    // too many bytes for the target type
+    if buf.len() > max_len {
         return None;
     }
 
     let sign_extend = (buf[0] & 0x80) != 0;
-    let mut ret: u32 = if signed && sign_extend { 0xffffffff } else { 0 };
+    let mut ret: u128 = if signed && sign_extend { u128::MAX } else { 0 };
     for b in buf {
         ret <<= 8;
-        ret |= *b as u32;
+        ret |= *b as u128;
     }
     Some(ret)
 }
 
 pub fn u32_from_u8(buf: &[u8]) -> Option<u32> {
-    u32_from_u8_impl(buf, false)
+    u128_from_u8_impl(buf, false, 4).map(|v| v as u32)
 }
 
 #[test]
@@ -154,7 +309,7 @@ fn test_u32_from_u8() {
 }
 
 pub fn i32_from_u8(buf: &[u8]) -> Option<i32> {
-    u32_from_u8_impl(buf, true).map(|v| v as i32)
+    u128_from_u8_impl(buf, true, 4).map(|v| v as i32)
 }
 
 #[test]
@@ -184,18 +339,111 @@ fn test_i32_from_u8() {
     assert_eq!(i32_from_u8(&[0x7d, 0xcc, 0x55, 0x88, 0xf3]), None);
 }
 
-pub fn i32_atom<A: Allocator>(args: &Node<A>, op_name: &str) -> Result<i32, EvalErr<A::Ptr>> {
+pub fn u64_from_u8(buf: &[u8]) -> Option<u64> {
+    u128_from_u8_impl(buf, false, 8).map(|v| v as u64)
+}
+
+#[test]
+fn test_u64_from_u8() {
+    assert_eq!(u64_from_u8(&[]), Some(0));
+    assert_eq!(u64_from_u8(&[0xcc]), Some(0xcc));
+    assert_eq!(u64_from_u8(&[0xcc, 0x55, 0x88, 0xf3]), Some(0xcc5588f3));
+    assert_eq!(
+        u64_from_u8(&[0xcc, 0x55, 0x88, 0xf3, 0x01, 0x02, 0x03, 0x04]),
+        Some(0xcc5588f301020304)
+    );
+    assert_eq!(u64_from_u8(&[0xff; 8]), Some(0xffffffffffffffff));
+
+    // leading zeros are not stripped, and not allowed beyond 8 bytes
+    assert_eq!(u64_from_u8(&[0x00]), Some(0));
+    assert_eq!(u64_from_u8(&[0x00; 8]), Some(0));
+    assert_eq!(
+        u64_from_u8(&[0x00, 0xcc, 0x55, 0x88, 0xf3, 0x01, 0x02, 0x03]),
+        Some(0xcc5588f3010203)
+    );
+    assert_eq!(u64_from_u8(&[0x00; 9]), None);
+    assert_eq!(
+        u64_from_u8(&[0x00, 0xcc, 0x55, 0x88, 0xf3, 0x01, 0x02, 0x03, 0x04]),
+        None
+    );
+
+    // overflow, too many bytes
+    assert_eq!(u64_from_u8(&[0x01, 0, 0, 0, 0, 0, 0, 0, 0]), None);
+    assert_eq!(u64_from_u8(&[0xff; 9]), None);
+}
+
+pub fn i64_from_u8(buf: &[u8]) -> Option<i64> {
+    u128_from_u8_impl(buf, true, 8).map(|v| v as i64)
+}
+
+#[test]
+fn test_i64_from_u8() {
+    assert_eq!(i64_from_u8(&[]), Some(0));
+    assert_eq!(i64_from_u8(&[0xcc]), Some(-52));
+    assert_eq!(i64_from_u8(&[0x7f]), Some(127));
+    assert_eq!(i64_from_u8(&[0xcc, 0x55, 0x88, 0xf3]), Some(-866809613));
+    assert_eq!(
+        i64_from_u8(&[0x7f, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]),
+        Some(i64::MAX)
+    );
+    assert_eq!(i64_from_u8(&[0x80, 0, 0, 0, 0, 0, 0, 0]), Some(i64::MIN));
+
+    assert_eq!(i64_from_u8(&[0xff]), Some(-1));
+    assert_eq!(i64_from_u8(&[0xff; 8]), Some(-1));
+
+    // leading zeros are not stripped, and not allowed beyond 8 bytes
+    assert_eq!(i64_from_u8(&[0x00]), Some(0));
+    assert_eq!(i64_from_u8(&[0x00, 0xcc]), Some(0xcc));
+    assert_eq!(
+        i64_from_u8(&[0x00, 0xcc, 0x55, 0x88, 0xf3, 0x01, 0x02, 0x03]),
+        Some(0xcc5588f3010203)
+    );
+    assert_eq!(i64_from_u8(&[0x00; 9]), None);
+
+    // any atom larger than 8 bytes is rejected
+    assert_eq!(i64_from_u8(&[0x00, 0x80, 0, 0, 0, 0, 0, 0, 0]), None);
+    assert_eq!(i64_from_u8(&[0xff; 9]), None);
+}
+
+pub fn u128_from_u8(buf: &[u8]) -> Option<u128> {
+    u128_from_u8_impl(buf, false, 16)
+}
+
+#[test]
+fn test_u128_from_u8() {
+    assert_eq!(u128_from_u8(&[]), Some(0));
+    assert_eq!(u128_from_u8(&[0xcc]), Some(0xcc));
+    assert_eq!(
+        u128_from_u8(&[0xcc, 0x55, 0x88, 0xf3, 0x01, 0x02, 0x03, 0x04, 0x05]),
+        Some(0xcc5588f30102030405)
+    );
+    assert_eq!(u128_from_u8(&[0xff; 16]), Some(u128::MAX));
+
+    // leading zeros are not stripped, and not allowed beyond 16 bytes
+    assert_eq!(u128_from_u8(&[0x00; 16]), Some(0));
+    assert_eq!(u128_from_u8(&[0x00; 17]), None);
+
+    // overflow, too many bytes
+    let mut buf = [0_u8; 17];
+    buf[0] = 1;
+    assert_eq!(u128_from_u8(&buf), None);
+}
+
+pub fn i32_atom<A: Allocator>(
+    args: &Node<A>,
+    op_name: &'static str,
+) -> Result<i32, EvalErr<A::Ptr>> {
     let buf = match args.atom() {
         Some(a) => a,
         _ => {
-            return args.err(&format!("{} requires int32 args", op_name));
+            return args.err(ErrMsg::Op(op_name, "requires int32 args"));
         }
     };
     match i32_from_u8(buf) {
         Some(v) => Ok(v),
-        _ => args.err(&format!(
-            "{} requires int32 args (with no leading zeros)",
-            op_name
+        _ => args.err(ErrMsg::Op(
+            op_name,
+            "requires int32 args (with no leading zeros)",
         )),
     }
 }
@@ -215,7 +463,29 @@ impl<'a, A: Allocator> Node<'a, A> {
         }
     }
 
-    pub fn err<T>(&self, msg: &str) -> Result<T, EvalErr<A::Ptr>> {
+    pub fn err<T>(&self, msg: impl Into<ErrMsg>) -> Result<T, EvalErr<A::Ptr>> {
         err(self.node.clone(), msg)
     }
 }
+
+#[test]
+fn test_check_int_size() {
+    use crate::int_allocator::IntAllocator;
+    use crate::reduction::ErrKind;
+
+    let mut a = IntAllocator::new();
+    let null = a.null();
+    let n = Node::new(&a, null);
+    assert!(check_int_size(&n, 1000, 1000, "*").is_ok());
+    let e = check_int_size(&n, 1001, 1000, "*").unwrap_err();
+    assert_eq!(e.kind(), ErrKind::LimitExceeded);
+    assert_eq!(e.1, "* result exceeds max integer size");
+    // 0 is no limit
+    assert!(check_int_size(&n, usize::MAX, 0, "*").is_ok());
+
+    let shift = a.new_atom(&[0x01, 0x00, 0x00]).unwrap();
+    let e = shift_atom(&Node::new(&a, shift), "ash").unwrap_err();
+    assert_eq!(e.1, "shift too large");
+    let shift = a.new_atom(&[0x00, 0xff, 0xff]).unwrap();
+    assert_eq!(shift_atom(&Node::new(&a, shift), "ash").unwrap(), 65535);
+}
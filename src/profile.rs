@@ -0,0 +1,242 @@
+// Collects where a program spends its cost, as the tracer of a run (see
+// RunParams::with_tracer()).
+// Cost is broken down by operator, and by the stack of programs invoked via
+// apply that were executing when it was charged. Those programs are told apart
+// by tree hash and named from a chialisp .sym file, when there is one
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::allocator::Allocator;
+use crate::cost::Cost;
+use crate::node::Node;
+use crate::run_program::CostTracer;
+use crate::symbols::SymbolTable;
+use crate::tree_hash::tree_hash;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub calls: u64,
+    pub cost: Cost,
+}
+
+// one entry per distinct stack of programs. Entry 0 is the empty stack, above
+// the top level program
+struct Frame<P> {
+    parent: usize,
+    program: Option<P>,
+    calls: u64,
+    // the cost charged while this was the innermost frame
+    cost: Cost,
+}
+
+pub struct Profiler<P> {
+    // by opcode. None is the cost of evaluation that isn't an operator or
+    // quote: looking up arguments and building operand lists
+    ops: HashMap<Option<Vec<u8>>, Stats>,
+    frames: Vec<Frame<P>>,
+    children: HashMap<(usize, P), usize>,
+    current: usize,
+}
+
+impl<P: Clone + Eq + Hash> Default for Profiler<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: Clone + Eq + Hash> Profiler<P> {
+    pub fn new() -> Self {
+        Profiler {
+            ops: HashMap::new(),
+            frames: vec![Frame {
+                parent: 0,
+                program: None,
+                calls: 0,
+                cost: Cost::ZERO,
+            }],
+            children: HashMap::new(),
+            current: 0,
+        }
+    }
+
+    // the cost of each opcode, most expensive first
+    pub fn op_costs(&self) -> Vec<(Option<Vec<u8>>, Stats)> {
+        let mut ret: Vec<(Option<Vec<u8>>, Stats)> =
+            self.ops.iter().map(|(k, v)| (k.clone(), *v)).collect();
+        ret.sort_by(|a, b| b.1.cost.cmp(&a.1.cost).then_with(|| a.0.cmp(&b.0)));
+        ret
+    }
+
+    // the cost charged in each program (not counting the programs it
+    // invokes), by name, most expensive first
+    pub fn subroutine_costs<A: Allocator<Ptr = P>>(
+        &self,
+        allocator: &A,
+        symbols: &SymbolTable,
+    ) -> Vec<(String, Stats)> {
+        let labels = self.labels(allocator, symbols);
+        let mut by_name = HashMap::<&str, Stats>::new();
+        for (frame, label) in self.frames.iter().zip(labels.iter()).skip(1) {
+            let stats = by_name.entry(label).or_default();
+            stats.calls += frame.calls;
+            stats.cost += frame.cost;
+        }
+        let mut ret: Vec<(String, Stats)> = by_name
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect();
+        ret.sort_by(|a, b| b.1.cost.cmp(&a.1.cost).then_with(|| a.0.cmp(&b.0)));
+        ret
+    }
+
+    // the cost of each stack, outermost program first, in the collapsed stack
+    // format flamegraph tools read: "main;foo;bar 1234"
+    pub fn collapsed_stacks<A: Allocator<Ptr = P>>(
+        &self,
+        allocator: &A,
+        symbols: &SymbolTable,
+    ) -> Vec<String> {
+        let labels = self.labels(allocator, symbols);
+        let mut ret = Vec::new();
+        for (i, frame) in self.frames.iter().enumerate().skip(1) {
+            if frame.cost == Cost::ZERO {
+                continue;
+            }
+            let mut stack = Vec::new();
+            let mut f = i;
+            while f != 0 {
+                stack.push(labels[f].as_str());
+                f = self.frames[f].parent;
+            }
+            stack.reverse();
+            ret.push(format!("{} {}", stack.join(";"), frame.cost));
+        }
+        ret.sort();
+        ret
+    }
+
+    // the name of every frame's program, or a prefix of its tree hash
+    fn labels<A: Allocator<Ptr = P>>(&self, allocator: &A, symbols: &SymbolTable) -> Vec<String> {
+        let mut cache = HashMap::<P, String>::new();
+        self.frames
+            .iter()
+            .map(|frame| match &frame.program {
+                None => String::new(),
+                Some(p) => cache
+                    .entry(p.clone())
+                    .or_insert_with(|| {
+                        let hash = tree_hash(&Node::new(allocator, p.clone()));
+                        match symbols.get(&hash) {
+                            Some(name) => name.to_string(),
+                            None => hex::encode(&hash[..8]),
+                        }
+                    })
+                    .clone(),
+            })
+            .collect()
+    }
+}
+
+impl<P: Clone + Eq + Hash> CostTracer<P> for Profiler<P> {
+    fn enter(&mut self, program: &P) {
+        let key = (self.current, program.clone());
+        let next = self.frames.len();
+        let current = self.current;
+        let frame = *self.children.entry(key).or_insert(next);
+        if frame == next {
+            self.frames.push(Frame {
+                parent: current,
+                program: Some(program.clone()),
+                calls: 0,
+                cost: Cost::ZERO,
+            });
+        }
+        self.frames[frame].calls += 1;
+        self.current = frame;
+    }
+
+    fn leave(&mut self) {
+        self.current = self.frames[self.current].parent;
+    }
+
+    fn charge(&mut self, opcode: Option<&[u8]>, cost: Cost) {
+        self.frames[self.current].cost += cost;
+        let stats = self.ops.entry(opcode.map(|o| o.to_vec())).or_default();
+        if opcode.is_some() {
+            stats.calls += 1;
+        }
+        stats.cost += cost;
+    }
+}
+
+#[test]
+fn test_profiler() {
+    use crate::int_allocator::IntAllocator;
+    use crate::op_table::{op_table_for, CHIA_OPCODES};
+    use crate::run_program::{run_program, run_program_with_params, RunParams};
+    use crate::serialize::node_from_bytes;
+
+    // (a (q . (+ 2 5)) (q . (3 4))), calling (+ 2 5) with (3 4)
+    let mut a = IntAllocator::new();
+    let blob = hex::decode("ff02ffff01ff10ff02ff0580ffff01ff03ff048080").unwrap();
+    let program = node_from_bytes(&mut a, &blob).unwrap();
+    let null = a.null();
+
+    let mut profiler = Profiler::new();
+    let r = run_program_with_params(
+        &mut a,
+        &program,
+        &null,
+        Box::new(op_table_for(&CHIA_OPCODES)),
+        None,
+        RunParams::new().with_tracer(&mut profiler),
+    )
+    .unwrap();
+    let expected = run_program(
+        &mut a,
+        &program,
+        &null,
+        1,
+        2,
+        Cost::ZERO,
+        Box::new(op_table_for(&CHIA_OPCODES)),
+        None,
+    )
+    .unwrap();
+    assert_eq!(r.0, expected.0);
+
+    // every step's cost is accounted for, by opcode and by stack
+    let ops = profiler.op_costs();
+    assert_eq!(ops.iter().map(|o| o.1.cost).sum::<Cost>(), r.0);
+    let op = |opcode: &[u8]| {
+        ops.iter()
+            .find(|o| o.0.as_deref() == Some(opcode))
+            .unwrap()
+            .1
+    };
+    assert_eq!(op(&[16]).calls, 1);
+    assert_eq!(op(&[2]).calls, 1);
+    assert_eq!(op(&[2]).cost, Cost::new(90));
+    assert_eq!(op(&[1]).calls, 2);
+    assert_eq!(op(&[1]).cost, Cost::new(40));
+
+    let mut symbols = SymbolTable::new();
+    let add = Node::new(&a, program).rest().unwrap().first().unwrap();
+    let add = add.rest().unwrap().node;
+    symbols.insert(tree_hash(&Node::new(&a, add)), "add");
+    let subs = profiler.subroutine_costs(&a, &symbols);
+    assert_eq!(subs.len(), 2);
+    assert_eq!(subs.iter().map(|s| s.1.cost).sum::<Cost>(), r.0);
+    let add_stats = subs.iter().find(|s| s.0 == "add").unwrap().1;
+    assert_eq!(add_stats.calls, 1);
+    // the top level program only evaluates its operands: 1 for the operand
+    // list and two quotes. The apply is charged to the program it calls
+    assert_eq!(add_stats.cost, r.0.saturating_sub(Cost::new(41)));
+
+    let stacks = profiler.collapsed_stacks(&a, &symbols);
+    let main = hex::encode(&tree_hash(&Node::new(&a, program))[..8]);
+    assert_eq!(stacks.len(), 2);
+    assert!(stacks.contains(&format!("{} 41", main)));
+    assert!(stacks.contains(&format!("{};add {}", main, add_stats.cost)));
+}
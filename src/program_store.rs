@@ -0,0 +1,564 @@
+// A read-only store of programs that are used over and over (e.g. the standard
+// puzzles), in a form that can be evaluated in place. The store is a flat
+// table of nodes, so it can be memory mapped (see open(), with the `mmap`
+// feature) and a StoreAllocator reads a node from it only when evaluation
+// traverses it, rather than deserializing every program up front. Only the
+// pages that are touched are ever loaded. The structure of the store is
+// checked once, when it's opened, so traversing it can't fail.
+//
+// The layout, with all integers little endian:
+//
+//   b"CLVMSTR1", program count: u32, node count: u32, atom bytes: u32
+//   programs: (tree hash: [u8; 32], root: u32), sorted by tree hash
+//   nodes: (a: u32, b: u32). For a pair, the index of its first and rest.
+//     For an atom, its start and end in the atom bytes, with the top bit of
+//     a set. Children come before their parents
+//   atom bytes
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::sync::Arc;
+
+use crate::allocator::{Allocator, SExp};
+use crate::err_utils::{err, limit_err, oom_err};
+use crate::int_allocator::{IntAllocator, IntAtomBuf};
+use crate::node::Node;
+use crate::reduction::EvalErr;
+use crate::sha256::sha256;
+
+const MAGIC: &[u8; 8] = b"CLVMSTR1";
+const HEADER_LEN: usize = 20;
+const PROGRAM_LEN: usize = 36;
+const NODE_LEN: usize = 8;
+const ATOM_FLAG: u32 = 0x8000_0000;
+
+#[derive(Debug)]
+pub enum StoreError {
+    // it doesn't start with the magic bytes
+    BadMagic,
+    // the tables don't fit in the store, or there are bytes after them
+    BadLength,
+    // the programs aren't sorted by tree hash, or one is there twice
+    Unsorted,
+    // a program's root isn't in the node table
+    BadRoot(usize),
+    // a pair refers to itself or a later node, or an atom is out of bounds
+    BadNode(usize),
+    // build_store() was given more nodes or atom bytes than a store can hold
+    TooLarge,
+    Io(std::io::Error),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "not a program store"),
+            Self::BadLength => write!(f, "program store has the wrong length"),
+            Self::Unsorted => write!(f, "programs are not sorted by tree hash"),
+            Self::BadRoot(i) => write!(f, "program {} has an invalid root", i),
+            Self::BadNode(i) => write!(f, "node {} is invalid", i),
+            Self::TooLarge => write!(f, "programs are too large for a program store"),
+            Self::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<std::io::Error> for StoreError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+fn u32_at(b: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(b[pos..pos + 4].try_into().unwrap())
+}
+
+// writes a store holding these programs. Programs, and subtrees, with the same
+// tree hash are only stored once. Fails if there are too many nodes or atom
+// bytes for the u32 offsets and indices of the layout
+pub fn build_store<T: Allocator>(programs: &[Node<T>]) -> Result<Vec<u8>, StoreError> {
+    // node indices and atom offsets can't have ATOM_FLAG set
+    let index = |n: usize| match u32::try_from(n) {
+        Ok(n) if n & ATOM_FLAG == 0 => Ok(n),
+        _ => Err(StoreError::TooLarge),
+    };
+    let mut roots: Vec<([u8; 32], u32)> = Vec::new();
+    let mut nodes: Vec<u8> = Vec::new();
+    let mut atoms: Vec<u8> = Vec::new();
+    // the index of every node stored so far, by tree hash
+    let mut stored: HashMap<[u8; 32], u32> = HashMap::new();
+    for program in programs {
+        let a = program.allocator;
+        // the indices and tree hashes of the finished children
+        let mut done: Vec<(u32, [u8; 32])> = Vec::new();
+        let mut ops = vec![(program.node.clone(), false)];
+        while let Some((n, children_done)) = ops.pop() {
+            let (hash, entry) = match a.sexp(&n) {
+                SExp::Atom(atom) => (sha256(&[&[1], a.buf(&atom)]), Entry::Atom(atom)),
+                SExp::Pair(first, rest) => {
+                    if !children_done {
+                        ops.push((n, true));
+                        ops.push((rest, false));
+                        ops.push((first, false));
+                        continue;
+                    }
+                    let rest = done.pop().unwrap();
+                    let first = done.pop().unwrap();
+                    let hash = sha256(&[&[2], &first.1, &rest.1]);
+                    (hash, Entry::Pair(first.0, rest.0))
+                }
+            };
+            if let Some(i) = stored.get(&hash) {
+                done.push((*i, hash));
+                continue;
+            }
+            let (first, rest) = match entry {
+                Entry::Atom(atom) => {
+                    let start = index(atoms.len())?;
+                    atoms.extend_from_slice(a.buf(&atom));
+                    (start | ATOM_FLAG, index(atoms.len())?)
+                }
+                Entry::Pair(first, rest) => (first, rest),
+            };
+            let i = index(nodes.len() / NODE_LEN)?;
+            nodes.extend_from_slice(&first.to_le_bytes());
+            nodes.extend_from_slice(&rest.to_le_bytes());
+            stored.insert(hash, i);
+            done.push((i, hash));
+        }
+        let (root, hash) = done.pop().unwrap();
+        if !roots.iter().any(|r| r.0 == hash) {
+            roots.push((hash, root));
+        }
+    }
+    roots.sort();
+
+    let mut out = MAGIC.to_vec();
+    out.extend_from_slice(&index(roots.len())?.to_le_bytes());
+    out.extend_from_slice(&index(nodes.len() / NODE_LEN)?.to_le_bytes());
+    out.extend_from_slice(&index(atoms.len())?.to_le_bytes());
+    for (hash, root) in &roots {
+        out.extend_from_slice(hash);
+        out.extend_from_slice(&root.to_le_bytes());
+    }
+    out.extend_from_slice(&nodes);
+    out.extend_from_slice(&atoms);
+    Ok(out)
+}
+
+// a node for build_store() to write, once its children are written
+enum Entry<B> {
+    Atom(B),
+    Pair(u32, u32),
+}
+
+pub struct ProgramStore<B: AsRef<[u8]>> {
+    bytes: B,
+    program_count: usize,
+    node_count: usize,
+    nodes_start: usize,
+    atoms_start: usize,
+}
+
+impl<B: AsRef<[u8]>> ProgramStore<B> {
+    // checks the structure of the store in `bytes` (e.g. a memory mapped
+    // file). It doesn't check the tree hashes
+    pub fn new(bytes: B) -> Result<Self, StoreError> {
+        let b = bytes.as_ref();
+        if b.len() < HEADER_LEN {
+            return Err(StoreError::BadLength);
+        }
+        if &b[..8] != MAGIC {
+            return Err(StoreError::BadMagic);
+        }
+        let program_count = u32_at(b, 8) as u64;
+        let node_count = u32_at(b, 12) as u64;
+        let atom_len = u32_at(b, 16);
+        let nodes_start = HEADER_LEN as u64 + program_count * PROGRAM_LEN as u64;
+        let atoms_start = nodes_start + node_count * NODE_LEN as u64;
+        if b.len() as u64 != atoms_start + atom_len as u64 {
+            return Err(StoreError::BadLength);
+        }
+        // they all fit in b, so in a usize
+        let (program_count, node_count) = (program_count as usize, node_count as usize);
+        let (nodes_start, atoms_start) = (nodes_start as usize, atoms_start as usize);
+        for i in 0..program_count {
+            let pos = HEADER_LEN + i * PROGRAM_LEN;
+            if i > 0 && b[pos - PROGRAM_LEN..pos - 4] >= b[pos..pos + 32] {
+                return Err(StoreError::Unsorted);
+            }
+            if u32_at(b, pos + 32) as usize >= node_count {
+                return Err(StoreError::BadRoot(i));
+            }
+        }
+        for i in 0..node_count {
+            let first = u32_at(b, nodes_start + i * NODE_LEN);
+            let rest = u32_at(b, nodes_start + i * NODE_LEN + 4);
+            let valid = if first & ATOM_FLAG != 0 {
+                first & !ATOM_FLAG <= rest && rest <= atom_len
+            } else {
+                (first as usize) < i && (rest as usize) < i
+            };
+            if !valid {
+                return Err(StoreError::BadNode(i));
+            }
+        }
+        Ok(ProgramStore {
+            bytes,
+            program_count,
+            node_count,
+            nodes_start,
+            atoms_start,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.program_count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.program_count == 0
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.node_count
+    }
+
+    // the root node of the program with this tree hash
+    pub fn program(&self, hash: &[u8; 32]) -> Option<u32> {
+        let b = self.bytes.as_ref();
+        let hash_at =
+            |i: usize| &b[HEADER_LEN + i * PROGRAM_LEN..HEADER_LEN + i * PROGRAM_LEN + 32];
+        let (mut lo, mut hi) = (0, self.program_count);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            match hash_at(mid).cmp(&hash[..]) {
+                Ordering::Less => lo = mid + 1,
+                Ordering::Greater => hi = mid,
+                Ordering::Equal => {
+                    return Some(u32_at(b, HEADER_LEN + mid * PROGRAM_LEN + 32));
+                }
+            }
+        }
+        None
+    }
+
+    fn node(&self, index: u32) -> SExp<u32, (u32, u32)> {
+        let b = self.bytes.as_ref();
+        let pos = self.nodes_start + index as usize * NODE_LEN;
+        let first = u32_at(b, pos);
+        let rest = u32_at(b, pos + 4);
+        if first & ATOM_FLAG != 0 {
+            SExp::Atom((first & !ATOM_FLAG, rest))
+        } else {
+            SExp::Pair(first, rest)
+        }
+    }
+
+    fn atom_bytes(&self, start: u32, end: u32) -> &[u8] {
+        &self.bytes.as_ref()[self.atoms_start + start as usize..self.atoms_start + end as usize]
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl ProgramStore<memmap2::Mmap> {
+    // maps a store file into memory. The file must not be modified while
+    // it's mapped
+    pub fn open(path: &str) -> Result<Self, StoreError> {
+        let file = std::fs::File::open(path)?;
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        Self::new(map)
+    }
+}
+
+// a node allocated while running, or one in the store
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum StorePtr {
+    Atom(i32),
+    Pair(u32),
+    Stored(u32),
+}
+
+#[derive(Clone, Copy)]
+pub enum StoreAtomBuf {
+    Arena(IntAtomBuf),
+    Stored(u32, u32),
+}
+
+fn atom_err(e: EvalErr<i32>) -> EvalErr<StorePtr> {
+    EvalErr(StorePtr::Atom(e.0), e.1, e.2)
+}
+
+// an allocator for the nodes created while running, which can refer to the
+// nodes of a store as well. Stored nodes are never copied: a pair created
+// while running points straight at them. The store is shared, so any number
+// of allocators (e.g. one per worker) can use it at once
+pub struct StoreAllocator<B: AsRef<[u8]>> {
+    // only holds atoms
+    atoms: IntAllocator,
+    pairs: Vec<(StorePtr, StorePtr)>,
+    store: Arc<ProgramStore<B>>,
+}
+
+impl<B: AsRef<[u8]>> StoreAllocator<B> {
+    pub fn new(store: Arc<ProgramStore<B>>) -> Self {
+        StoreAllocator {
+            atoms: IntAllocator::new(),
+            pairs: Vec::new(),
+            store,
+        }
+    }
+
+    pub fn store(&self) -> &ProgramStore<B> {
+        &self.store
+    }
+
+    // the program with this tree hash, from the store
+    pub fn program(&self, hash: &[u8; 32]) -> Option<StorePtr> {
+        self.store.program(hash).map(StorePtr::Stored)
+    }
+
+    // drops everything allocated while running, the store is untouched
+    pub fn reset(&mut self) {
+        self.atoms.reset();
+        self.pairs.clear();
+    }
+}
+
+impl<B: AsRef<[u8]>> Allocator for StoreAllocator<B> {
+    type Ptr = StorePtr;
+    type AtomBuf = StoreAtomBuf;
+
+    fn new_atom(&mut self, v: &[u8]) -> Result<Self::Ptr, EvalErr<Self::Ptr>> {
+        self.atoms.new_atom(v).map(StorePtr::Atom).map_err(atom_err)
+    }
+
+    fn new_pair(
+        &mut self,
+        first: Self::Ptr,
+        rest: Self::Ptr,
+    ) -> Result<Self::Ptr, EvalErr<Self::Ptr>> {
+        let r = self.pairs.len() as u32;
+        if self.pairs.len() == u32::MAX as usize {
+            return limit_err(self.null(), "too many pairs");
+        }
+        if self.pairs.try_reserve(1).is_err() {
+            return oom_err(self.null());
+        }
+        self.pairs.push((first, rest));
+        Ok(StorePtr::Pair(r))
+    }
+
+    fn new_substr(
+        &mut self,
+        node: Self::Ptr,
+        start: u32,
+        end: u32,
+    ) -> Result<Self::Ptr, EvalErr<Self::Ptr>> {
+        match node {
+            StorePtr::Atom(n) => self
+                .atoms
+                .new_substr(n, start, end)
+                .map(StorePtr::Atom)
+                .map_err(atom_err),
+            StorePtr::Pair(_) => err(node, "(internal error) substr expected atom, got pair"),
+            StorePtr::Stored(n) => {
+                // the slice is copied out of the store
+                let (atom_start, atom_end) = match self.store.node(n) {
+                    SExp::Atom(range) => range,
+                    SExp::Pair(..) => {
+                        return err(node, "(internal error) substr expected atom, got pair");
+                    }
+                };
+                let atom_len = atom_end - atom_start;
+                if start > atom_len {
+                    return err(node, "substr start out of bounds");
+                }
+                if end > atom_len {
+                    return err(node, "substr end out of bounds");
+                }
+                if end < start {
+                    return err(node, "substr invalid bounds");
+                }
+                let store = &self.store;
+                self.atoms
+                    .new_atom(store.atom_bytes(atom_start + start, atom_start + end))
+                    .map(StorePtr::Atom)
+                    .map_err(atom_err)
+            }
+        }
+    }
+
+    fn atom<'a>(&'a self, node: &'a Self::Ptr) -> &'a [u8] {
+        match node {
+            StorePtr::Atom(n) => self.atoms.atom(n),
+            StorePtr::Pair(_) => panic!("expected atom, got pair"),
+            StorePtr::Stored(n) => match self.store.node(*n) {
+                SExp::Atom((start, end)) => self.store.atom_bytes(start, end),
+                SExp::Pair(..) => panic!("expected atom, got pair"),
+            },
+        }
+    }
+
+    fn buf<'a>(&'a self, node: &'a Self::AtomBuf) -> &'a [u8] {
+        match node {
+            StoreAtomBuf::Arena(buf) => self.atoms.buf(buf),
+            StoreAtomBuf::Stored(start, end) => self.store.atom_bytes(*start, *end),
+        }
+    }
+
+    fn sexp(&self, node: &Self::Ptr) -> SExp<Self::Ptr, Self::AtomBuf> {
+        match node {
+            StorePtr::Atom(n) => match self.atoms.sexp(n) {
+                SExp::Atom(buf) => SExp::Atom(StoreAtomBuf::Arena(buf)),
+                SExp::Pair(..) => unreachable!(),
+            },
+            StorePtr::Pair(n) => {
+                let (first, rest) = self.pairs[*n as usize];
+                SExp::Pair(first, rest)
+            }
+            StorePtr::Stored(n) => match self.store.node(*n) {
+                SExp::Atom((start, end)) => SExp::Atom(StoreAtomBuf::Stored(start, end)),
+                SExp::Pair(first, rest) => {
+                    SExp::Pair(StorePtr::Stored(first), StorePtr::Stored(rest))
+                }
+            },
+        }
+    }
+
+    fn null(&self) -> Self::Ptr {
+        StorePtr::Atom(self.atoms.null())
+    }
+
+    fn one(&self) -> Self::Ptr {
+        StorePtr::Atom(self.atoms.one())
+    }
+
+    fn reserve(
+        &mut self,
+        atoms: usize,
+        pairs: usize,
+        atom_bytes: usize,
+    ) -> Result<(), EvalErr<Self::Ptr>> {
+        if self.pairs.try_reserve(pairs).is_err() {
+            return oom_err(self.null());
+        }
+        self.atoms.reserve(atoms, 0, atom_bytes).map_err(atom_err)
+    }
+}
+
+#[cfg(test)]
+use crate::int_allocator::IntAllocator as TestAllocator;
+
+#[cfg(test)]
+use crate::serialize::{node_from_bytes, node_to_bytes};
+#[cfg(test)]
+use crate::tree_hash::tree_hash;
+
+#[test]
+fn test_program_store() {
+    use crate::cost::Cost;
+    use crate::op_table::{op_table_for, CHIA_DIALECT};
+    use crate::run_program::run_program;
+
+    // (+ 2 5) and (c (q . 0x1234) (r 1)), the second one twice
+    let programs = [
+        "ff10ff02ff0580",
+        "ff04ffff01821234ffff05ff018080",
+        "ff04ffff01821234ffff05ff018080",
+    ];
+    let mut a = TestAllocator::new();
+    let nodes: Vec<i32> = programs
+        .iter()
+        .map(|p| node_from_bytes(&mut a, &hex::decode(p).unwrap()).unwrap())
+        .collect();
+    let nodes: Vec<Node<TestAllocator>> = nodes.into_iter().map(|n| Node::new(&a, n)).collect();
+    let bytes = build_store(&nodes).unwrap();
+    let hashes: Vec<[u8; 32]> = nodes.iter().map(tree_hash).collect();
+    let store = Arc::new(ProgramStore::new(bytes.clone()).unwrap());
+    assert_eq!(store.len(), 2);
+    // 7 nodes in the first program and 11 in the second, which has its own
+    // copies of 5 and nil
+    assert_eq!(store.node_count(), 16);
+
+    let mut sa = StoreAllocator::new(store);
+    assert!(sa.program(&[0; 32]).is_none());
+    let env = [0xff, 0x03, 0xff, 0x04, 0x80];
+    for (p, hash) in programs.iter().zip(&hashes) {
+        let program = sa.program(hash).unwrap();
+        assert_eq!(
+            hex::encode(node_to_bytes(&Node::new(&sa, program)).unwrap()),
+            *p
+        );
+        assert_eq!(tree_hash(&Node::new(&sa, program)), *hash);
+
+        // running from the store gives the same result as running the
+        // deserialized program
+        let args = node_from_bytes(&mut sa, &env).unwrap();
+        let r = run_program(
+            &mut sa,
+            &program,
+            &args,
+            1,
+            2,
+            Cost::ZERO,
+            Box::new(op_table_for(CHIA_DIALECT.opcodes)),
+            None,
+        )
+        .unwrap();
+        let mut a = TestAllocator::new();
+        let program = node_from_bytes(&mut a, &hex::decode(p).unwrap()).unwrap();
+        let args = node_from_bytes(&mut a, &env).unwrap();
+        let expected = run_program(
+            &mut a,
+            &program,
+            &args,
+            1,
+            2,
+            Cost::ZERO,
+            Box::new(op_table_for(CHIA_DIALECT.opcodes)),
+            None,
+        )
+        .unwrap();
+        assert_eq!(r.0, expected.0);
+        assert_eq!(
+            node_to_bytes(&Node::new(&sa, r.1)).unwrap(),
+            node_to_bytes(&Node::new(&a, expected.1)).unwrap()
+        );
+    }
+
+    // substrings of stored atoms are copied out
+    let atom = sa.program(&hashes[1]).unwrap();
+    let atom = Node::new(&sa, atom).at("rfr").unwrap().node;
+    assert_eq!(sa.atom(&atom), [0x12, 0x34]);
+    let sub = sa.new_substr(atom, 1, 2).unwrap();
+    assert_eq!(sa.atom(&sub), [0x34]);
+    assert!(sa.new_substr(atom, 1, 3).is_err());
+
+    // the structure is checked
+    let err = |b: &[u8]| ProgramStore::new(b.to_vec()).err().unwrap();
+    assert!(matches!(err(&bytes[..10]), StoreError::BadLength));
+    assert!(matches!(
+        err(&bytes[..bytes.len() - 1]),
+        StoreError::BadLength
+    ));
+    let mut bad = bytes.clone();
+    bad[0] = b'X';
+    assert!(matches!(err(&bad), StoreError::BadMagic));
+    // swap the programs
+    let mut bad = bytes.clone();
+    bad[HEADER_LEN..HEADER_LEN + 2 * PROGRAM_LEN].rotate_left(PROGRAM_LEN);
+    assert!(matches!(err(&bad), StoreError::Unsorted));
+    let mut bad = bytes.clone();
+    bad[HEADER_LEN + 32] = 0xff;
+    assert!(matches!(err(&bad), StoreError::BadRoot(0)));
+    // make the first node a pair of itself
+    let nodes = HEADER_LEN + 2 * PROGRAM_LEN;
+    let mut bad = bytes;
+    bad[nodes..nodes + 8].copy_from_slice(&[0; 8]);
+    assert!(matches!(err(&bad), StoreError::BadNode(0)));
+}
@@ -0,0 +1,235 @@
+// Trees as protocol buffers, for gRPC services that carry programs and their
+// results. These are the messages prost generates from proto/clvm.proto, so
+// they can be used in place of generated code, or mixed with it. A tree is a
+// table of entries with children before their parents, and the root last
+
+use std::collections::HashMap;
+use std::fmt;
+
+use prost::Message;
+
+use crate::allocator::{Allocator, SExp};
+use crate::node::Node;
+use crate::reduction::EvalErr;
+use crate::sha256::sha256;
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Tree {
+    #[prost(message, repeated, tag = "1")]
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Entry {
+    #[prost(oneof = "entry::Value", tags = "1, 2")]
+    pub value: Option<entry::Value>,
+}
+
+pub mod entry {
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    pub enum Value {
+        #[prost(bytes, tag = "1")]
+        Atom(Vec<u8>),
+        #[prost(message, tag = "2")]
+        Pair(super::Pair),
+    }
+}
+
+// the indices of a pair's children in the table
+#[derive(Clone, PartialEq, Message)]
+pub struct Pair {
+    #[prost(uint64, tag = "1")]
+    pub first: u64,
+    #[prost(uint64, tag = "2")]
+    pub rest: u64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ProtoError {
+    // the table has no entries, so there's no root
+    Empty,
+    // the entry at this index is neither an atom nor a pair
+    MissingValue(usize),
+    // the pair at this index refers to itself or a later entry
+    InvalidIndex(usize),
+    // the allocator refused to create a node
+    LimitExceeded(String),
+}
+
+impl fmt::Display for ProtoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "empty tree"),
+            Self::MissingValue(i) => write!(f, "entry {} has no value", i),
+            Self::InvalidIndex(i) => write!(f, "entry {} refers to a later entry", i),
+            Self::LimitExceeded(msg) => write!(f, "limit exceeded: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ProtoError {}
+
+impl<T> From<EvalErr<T>> for ProtoError {
+    fn from(v: EvalErr<T>) -> Self {
+        Self::LimitExceeded(v.1.into())
+    }
+}
+
+fn write_tree<T: Allocator>(node: &Node<T>, backrefs: bool) -> Tree {
+    let a = node.allocator;
+    let mut entries: Vec<Entry> = Vec::new();
+    // the tree hash of each entry, and the index of the first entry with
+    // that hash, to find repeated subtrees. Only used with backrefs
+    let mut hashes: Vec<[u8; 32]> = Vec::new();
+    let mut seen: HashMap<[u8; 32], u64> = HashMap::new();
+    // indices of the finished children
+    let mut done: Vec<u64> = Vec::new();
+    let mut ops = vec![(node.node.clone(), false)];
+    while let Some((n, children_done)) = ops.pop() {
+        let (value, hash) = match a.sexp(&n) {
+            SExp::Atom(atom) => {
+                let buf = a.buf(&atom);
+                let hash = if backrefs {
+                    sha256(&[&[1], buf])
+                } else {
+                    [0; 32]
+                };
+                (entry::Value::Atom(buf.to_vec()), hash)
+            }
+            SExp::Pair(first, rest) => {
+                if !children_done {
+                    ops.push((n, true));
+                    ops.push((rest, false));
+                    ops.push((first, false));
+                    continue;
+                }
+                let rest = done.pop().unwrap();
+                let first = done.pop().unwrap();
+                let hash = if backrefs {
+                    sha256(&[&[2], &hashes[first as usize], &hashes[rest as usize]])
+                } else {
+                    [0; 32]
+                };
+                (entry::Value::Pair(Pair { first, rest }), hash)
+            }
+        };
+        if backrefs {
+            if let Some(index) = seen.get(&hash) {
+                done.push(*index);
+                continue;
+            }
+            seen.insert(hash, entries.len() as u64);
+            hashes.push(hash);
+        }
+        done.push(entries.len() as u64);
+        entries.push(Entry { value: Some(value) });
+    }
+    Tree { entries }
+}
+
+// every occurrence of a subtree gets its own entries, like node_to_bytes()
+pub fn node_to_proto<T: Allocator>(node: &Node<T>) -> Tree {
+    write_tree(node, false)
+}
+
+// identical subtrees share their entries, so repetitive trees (e.g. block
+// generators) are much smaller
+pub fn node_to_proto_backrefs<T: Allocator>(node: &Node<T>) -> Tree {
+    write_tree(node, true)
+}
+
+// accepts trees with or without shared entries
+pub fn node_from_proto<T: Allocator>(allocator: &mut T, tree: &Tree) -> Result<T::Ptr, ProtoError> {
+    if tree.entries.is_empty() {
+        return Err(ProtoError::Empty);
+    }
+    let mut nodes: Vec<T::Ptr> = Vec::with_capacity(tree.entries.len());
+    for (i, e) in tree.entries.iter().enumerate() {
+        let node = match &e.value {
+            Some(entry::Value::Atom(buf)) => allocator.new_atom(buf)?,
+            Some(entry::Value::Pair(p)) => {
+                if p.first >= i as u64 || p.rest >= i as u64 {
+                    return Err(ProtoError::InvalidIndex(i));
+                }
+                let first = nodes[p.first as usize].clone();
+                let rest = nodes[p.rest as usize].clone();
+                allocator.new_pair(first, rest)?
+            }
+            None => return Err(ProtoError::MissingValue(i)),
+        };
+        nodes.push(node);
+    }
+    Ok(nodes.pop().unwrap())
+}
+
+#[cfg(test)]
+use crate::int_allocator::IntAllocator;
+
+#[cfg(test)]
+use crate::serialize::{node_from_bytes, node_to_bytes};
+
+#[cfg(test)]
+fn atom(buf: &[u8]) -> Entry {
+    Entry {
+        value: Some(entry::Value::Atom(buf.to_vec())),
+    }
+}
+
+#[cfg(test)]
+fn pair(first: u64, rest: u64) -> Entry {
+    Entry {
+        value: Some(entry::Value::Pair(Pair { first, rest })),
+    }
+}
+
+#[test]
+fn test_node_to_proto() {
+    let mut a = IntAllocator::new();
+    // ((1 . 2) . (1 . 2))
+    let serialized = [0xff, 0xff, 0x01, 0x02, 0xff, 0x01, 0x02];
+    let node = node_from_bytes(&mut a, &serialized).unwrap();
+
+    let tree = node_to_proto(&Node::new(&a, node));
+    assert_eq!(
+        tree.entries,
+        [
+            atom(&[1]),
+            atom(&[2]),
+            pair(0, 1),
+            atom(&[1]),
+            atom(&[2]),
+            pair(3, 4),
+            pair(2, 5)
+        ]
+    );
+    let shared = node_to_proto_backrefs(&Node::new(&a, node));
+    assert_eq!(
+        shared.entries,
+        [atom(&[1]), atom(&[2]), pair(0, 1), pair(2, 2)]
+    );
+
+    // both round trip, through the wire format too
+    for t in &[tree, shared] {
+        let decoded = Tree::decode(&t.encode_to_vec()[..]).unwrap();
+        assert_eq!(&decoded, t);
+        let node = node_from_proto(&mut a, &decoded).unwrap();
+        assert_eq!(node_to_bytes(&Node::new(&a, node)).unwrap(), serialized);
+    }
+}
+
+#[test]
+fn test_node_from_proto_errors() {
+    let mut a = IntAllocator::new();
+    let mut err = |entries: Vec<Entry>| node_from_proto(&mut a, &Tree { entries }).unwrap_err();
+
+    assert_eq!(err(vec![]), ProtoError::Empty);
+    assert_eq!(
+        err(vec![atom(&[1]), Entry { value: None }]),
+        ProtoError::MissingValue(1)
+    );
+    assert_eq!(err(vec![pair(0, 0)]), ProtoError::InvalidIndex(0));
+    assert_eq!(
+        err(vec![atom(&[1]), pair(0, 2), atom(&[2])]),
+        ProtoError::InvalidIndex(1)
+    );
+}
@@ -1,24 +1,34 @@
 use std::collections::HashMap;
 
+use pyo3::class::gc::{PyGCProtocol, PyTraverseError, PyVisit};
 use pyo3::prelude::*;
-use pyo3::types::{PyDict, PyString};
+use pyo3::types::{PyBytes, PyDict, PyString};
 use pyo3::wrap_pyfunction;
 use pyo3::PyObject;
 
-use super::arc_allocator::ArcAllocator;
+use super::arc_allocator::{ArcAllocator, ArcSExp};
+use super::arena::Arena;
 use super::glue::{_py_run_program, _serialize_from_bytes, _serialize_to_bytes};
 use super::native_op_lookup::GenericNativeOpLookup;
 use super::py_node::PyNode;
 use super::run_program::{
-    __pyo3_get_function_deserialize_and_run_program, __pyo3_get_function_serialize_and_run_program,
+    __pyo3_get_function_deserialize_and_run_program,
+    __pyo3_get_function_run_serialized_program_with_params,
+    __pyo3_get_function_run_serialized_to_bytes, __pyo3_get_function_serialize_and_run_program,
+    __pyo3_get_function_start_recording, __pyo3_get_function_stop_recording, RunParams,
     STRICT_MODE,
 };
 use crate::cost::Cost;
+use crate::int_allocator::IntAllocator;
+use crate::node::Node;
+use crate::run_program::RunParams as NativeRunParams;
+use crate::serialize::node_from_bytes;
+use crate::tree_hash::tree_hash_parallel;
 
 type AllocatorT<'a> = ArcAllocator;
 type NodeClass = PyNode;
 
-#[pyclass]
+#[pyclass(gc)]
 pub struct NativeOpLookup {
     nol: usize, // Box<GenericNativeOpLookup<AllocatorT>>,
 }
@@ -26,11 +36,14 @@ pub struct NativeOpLookup {
 #[pymethods]
 impl NativeOpLookup {
     #[new]
-    fn new(opcode_lookup_by_name: HashMap<String, Vec<u8>>, unknown_op_callback: PyObject) -> Self {
-        Self::new_from_gnol(Box::new(GenericNativeOpLookup::new(
+    fn new(
+        opcode_lookup_by_name: HashMap<String, Vec<u8>>,
+        unknown_op_callback: PyObject,
+    ) -> PyResult<Self> {
+        Ok(Self::new_from_gnol(Box::new(GenericNativeOpLookup::new(
             opcode_lookup_by_name,
             unknown_op_callback,
-        )))
+        )?)))
     }
 }
 
@@ -55,6 +68,22 @@ impl NativeOpLookup {
     }
 }
 
+// the unknown operator callback is often a bound method of an object that
+// holds on to this lookup. Without these, that cycle is never collected
+#[pyproto]
+impl PyGCProtocol for NativeOpLookup {
+    fn __traverse__(&self, visit: PyVisit) -> Result<(), PyTraverseError> {
+        let gnol = unsafe { &*(self.nol as *const GenericNativeOpLookup<AllocatorT, NodeClass>) };
+        visit.call(gnol.py_callback())
+    }
+
+    fn __clear__(&mut self) {
+        let gnol = unsafe { &mut *(self.nol as *mut GenericNativeOpLookup<AllocatorT, NodeClass>) };
+        let gil = Python::acquire_gil();
+        gnol.clear_py_callback(gil.python());
+    }
+}
+
 #[pyfunction]
 #[allow(clippy::too_many_arguments)]
 fn py_run_program(
@@ -66,22 +95,49 @@ fn py_run_program(
     max_cost: Cost,
     op_lookup: Py<NativeOpLookup>,
     pre_eval: PyObject,
+) -> PyResult<(Cost, NodeClass)> {
+    let params = NativeRunParams::new()
+        .with_keywords(quote_kw, apply_kw)
+        .with_max_cost(max_cost);
+    run_with_params(py, program, args, op_lookup, pre_eval, params)
+}
+
+// py_run_program() with the keywords and limits in a RunParams, rather than
+// as positional arguments
+#[pyfunction]
+fn py_run_program_with_params(
+    py: Python,
+    program: &NodeClass,
+    args: &NodeClass,
+    params: &RunParams,
+    op_lookup: Py<NativeOpLookup>,
+    pre_eval: PyObject,
+) -> PyResult<(Cost, NodeClass)> {
+    run_with_params(py, program, args, op_lookup, pre_eval, params.into())
+}
+
+fn run_with_params(
+    py: Python,
+    program: &NodeClass,
+    args: &NodeClass,
+    op_lookup: Py<NativeOpLookup>,
+    pre_eval: PyObject,
+    params: NativeRunParams<'_, ArcSExp>,
 ) -> PyResult<(Cost, NodeClass)> {
     let mut allocator = allocator_for_py(py);
     let op_lookup: &PyCell<NativeOpLookup> = op_lookup.as_ref(py);
     let op_lookup: PyRef<NativeOpLookup> = op_lookup.borrow();
     let op_lookup: Box<GenericNativeOpLookup<AllocatorT, NodeClass>> =
         Box::new(op_lookup.gnol(py).to_owned());
+    op_lookup.check_keywords(params.quote_kw, params.apply_kw)?;
     _py_run_program(
         py,
         &mut allocator,
         program,
         args,
-        quote_kw,
-        apply_kw,
-        max_cost,
         op_lookup,
         pre_eval,
+        params,
     )
 }
 
@@ -106,7 +162,7 @@ const fn allocator_for_py(_py: Python) -> AllocatorT {
 }
 
 #[pyfunction]
-fn serialize_from_bytes(py: Python, blob: &[u8]) -> NodeClass {
+fn serialize_from_bytes(py: Python, blob: &[u8]) -> PyResult<NodeClass> {
     let mut allocator = allocator_for_py(py);
     _serialize_from_bytes(&mut allocator, blob)
 }
@@ -117,19 +173,37 @@ fn serialize_to_bytes(py: Python, sexp: &PyAny) -> PyResult<PyObject> {
     _serialize_to_bytes::<AllocatorT, NodeClass>(&allocator, py, sexp)
 }
 
+// the tree hash of a serialized program. Large trees are hashed on several
+// threads, with the GIL released
+#[pyfunction]
+fn tree_hash(py: Python, blob: &[u8]) -> PyResult<Py<PyBytes>> {
+    let mut allocator = IntAllocator::new();
+    let node = node_from_bytes(&mut allocator, blob)?;
+    let hash = py.allow_threads(|| tree_hash_parallel(&Node::new(&allocator, node)));
+    Ok(PyBytes::new(py, &hash).into())
+}
+
 /// This module is a python module implemented in Rust.
 #[pymodule]
 fn clvm_rs(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_function(wrap_pyfunction!(py_run_program, m)?)?;
+    m.add_function(wrap_pyfunction!(py_run_program_with_params, m)?)?;
     m.add_function(wrap_pyfunction!(serialize_from_bytes, m)?)?;
     m.add_function(wrap_pyfunction!(serialize_to_bytes, m)?)?;
+    m.add_function(wrap_pyfunction!(tree_hash, m)?)?;
 
     m.add_function(wrap_pyfunction!(serialize_and_run_program, m)?)?;
     m.add_function(wrap_pyfunction!(deserialize_and_run_program, m)?)?;
+    m.add_function(wrap_pyfunction!(run_serialized_program_with_params, m)?)?;
+    m.add_function(wrap_pyfunction!(run_serialized_to_bytes, m)?)?;
     m.add("STRICT_MODE", STRICT_MODE)?;
+    m.add_function(wrap_pyfunction!(start_recording, m)?)?;
+    m.add_function(wrap_pyfunction!(stop_recording, m)?)?;
 
     m.add_class::<PyNode>()?;
     m.add_class::<NativeOpLookup>()?;
+    m.add_class::<RunParams>()?;
+    m.add_class::<Arena>()?;
 
     m.add_function(wrap_pyfunction!(raise_eval_error, m)?)?;
 
@@ -28,6 +28,24 @@ lazy_static! {
     static ref ONE: Arc<Vec<u8>> = Arc::new(vec![1]);
 }
 
+// where a node's contents are stored. Nodes with the same id share their
+// storage (they're clones, or substrings with the same bounds), so they're the
+// same value
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NodeId {
+    Atom(*const Vec<u8>, u32, u32),
+    Pair(*const ArcSExp, *const ArcSExp),
+}
+
+impl ArcSExp {
+    pub fn id(&self) -> NodeId {
+        match self {
+            ArcSExp::Atom(a) => NodeId::Atom(Arc::as_ptr(&a.buf), a.start, a.end),
+            ArcSExp::Pair(p1, p2) => NodeId::Pair(Arc::as_ptr(p1), Arc::as_ptr(p2)),
+        }
+    }
+}
+
 impl Clone for ArcSExp {
     fn clone(&self) -> Self {
         match self {
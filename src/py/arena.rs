@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+
+use pyo3::prelude::*;
+
+use crate::int_allocator::{Checkpoint, IntAllocator};
+use crate::serialize::node_from_bytes;
+
+// an allocator python code holds on to and passes to the run functions,
+// instead of them using the one this thread keeps. Whatever a run allocates is
+// rolled back when it returns, so the memory is reused, while programs loaded
+// with load_program() are kept and shared by every run given the same bytes
+#[pyclass(unsendable)]
+pub struct Arena {
+    allocator: IntAllocator,
+    // everything allocated before this is kept between runs
+    base: Checkpoint,
+    // loaded programs, by their serialized form
+    programs: HashMap<Vec<u8>, i32>,
+}
+
+#[pymethods]
+impl Arena {
+    #[new]
+    fn new() -> Self {
+        let allocator = IntAllocator::new();
+        let base = allocator.checkpoint();
+        Arena {
+            allocator,
+            base,
+            programs: HashMap::new(),
+        }
+    }
+
+    // deserializes a program once, to keep until reset()
+    fn load_program(&mut self, program: &[u8]) -> PyResult<()> {
+        if !self.programs.contains_key(program) {
+            let node = node_from_bytes(&mut self.allocator, program)?;
+            self.base = self.allocator.checkpoint();
+            self.programs.insert(program.to_vec(), node);
+        }
+        Ok(())
+    }
+
+    // drops everything, loaded programs included, but keeps the memory for
+    // the next run
+    fn reset(&mut self) {
+        self.allocator.reset();
+        self.base = self.allocator.checkpoint();
+        self.programs.clear();
+    }
+
+    #[getter]
+    fn capacity_bytes(&self) -> usize {
+        self.allocator.capacity_bytes()
+    }
+}
+
+impl Arena {
+    // runs f with the allocator and the loaded programs, then rolls back
+    // what it allocated
+    pub fn run<R, F>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut IntAllocator, &HashMap<Vec<u8>, i32>) -> R,
+    {
+        let r = f(&mut self.allocator, &self.programs);
+        // reset() takes base again, so it's never stale
+        self.allocator
+            .restore_checkpoint(&mut self.base)
+            .expect("base checkpoint is current");
+        r
+    }
+}
@@ -1,3 +1,4 @@
+use pyo3::exceptions::{PyMemoryError, PyValueError};
 use pyo3::prelude::*;
 use pyo3::types::{PyBytes, PyDict, PyString};
 use pyo3::PyClass;
@@ -12,8 +13,27 @@ use crate::allocator::Allocator;
 use crate::cost::Cost;
 use crate::node::Node;
 use crate::reduction::{EvalErr, Reduction};
-use crate::run_program::{run_program, PostEval, PreEval};
-use crate::serialize::{node_from_bytes, node_to_bytes};
+use crate::run_program::{run_program_with_params, PostEval, PreEval, RunParams};
+use crate::serialize::{node_from_bytes, node_to_bytes, SerializeError};
+
+impl std::convert::From<SerializeError> for pyo3::PyErr {
+    fn from(v: SerializeError) -> Self {
+        PyValueError::new_err(v.to_string())
+    }
+}
+
+// costs are plain ints on the python side
+impl<'source> FromPyObject<'source> for Cost {
+    fn extract(ob: &'source PyAny) -> PyResult<Self> {
+        Ok(Cost::new(ob.extract()?))
+    }
+}
+
+impl IntoPy<PyObject> for Cost {
+    fn into_py(self, py: Python) -> PyObject {
+        self.get().into_py(py)
+    }
+}
 
 impl ToPyNode<PyNode> for ArcAllocator {
     fn to_pynode(&self, ptr: &Self::Ptr) -> PyNode {
@@ -48,17 +68,14 @@ where
     py_post_eval
 }
 
-#[allow(clippy::too_many_arguments)]
 pub fn _py_run_program<'p, 'a, 'n, A, N>(
     py: Python<'p>,
     allocator: &'a mut A,
     program: &'n N,
     args: &'n N,
-    quote_kw: u8,
-    apply_kw: u8,
-    max_cost: Cost,
     op_lookup: Box<GenericNativeOpLookup<A, N>>,
     pre_eval: PyObject,
+    params: RunParams<'_, A::Ptr>,
 ) -> PyResult<(Cost, N)>
 where
     A: 'static + Allocator + ToPyNode<N>,
@@ -77,33 +94,37 @@ where
                     Ok(py_post_eval) => Ok(post_eval_for_pyobject::<A>(py, py_post_eval)),
                     Err(ref err) => {
                         let program: Node<A> = Node::new(allocator, program.clone());
-                        program.err(&err.to_string())
+                        program.err(err.to_string())
                     }
                 }
             })
         }))
     };
 
-    let r: Result<Reduction<<A as Allocator>::Ptr>, EvalErr<<A as Allocator>::Ptr>> = run_program(
-        allocator,
-        &program.into(),
-        &args.into(),
-        quote_kw,
-        apply_kw,
-        max_cost,
-        op_lookup,
-        py_pre_eval_t,
-    );
+    let r: Result<Reduction<<A as Allocator>::Ptr>, EvalErr<<A as Allocator>::Ptr>> =
+        run_program_with_params(
+            allocator,
+            &program.into(),
+            &args.into(),
+            op_lookup,
+            py_pre_eval_t,
+            params,
+        );
     match r {
         Ok(reduction) => Ok((reduction.0, allocator.to_pynode(&reduction.1))),
+        Err(eval_err) if eval_err.is_operational() => {
+            Err(PyMemoryError::new_err(eval_err.1.to_string()))
+        }
         Err(eval_err) => {
             let node: PyObject = eval_err.0.to_object(py);
-            let s: String = eval_err.1;
+            let s: String = eval_err.1.into();
             let s1: &str = &s;
             let msg: &PyString = PyString::new(py, s1);
             match raise_eval_error(py, msg, node) {
                 Err(x) => Err(x),
-                _ => panic!(),
+                // raise_eval_error() only returns if the python code failed to
+                // raise, in which case we still have to report the error
+                Ok(_) => Err(PyValueError::new_err(s)),
             }
         }
     }
@@ -124,12 +145,15 @@ fn raise_eval_error(py: Python, msg: &PyString, sexp: PyObject) -> PyResult<PyOb
     }
 }
 
-pub fn _serialize_from_bytes<A: Allocator, N: PyClass>(allocator: &mut A, blob: &[u8]) -> N
+pub fn _serialize_from_bytes<A: Allocator, N: PyClass>(
+    allocator: &mut A,
+    blob: &[u8],
+) -> PyResult<N>
 where
     A: ToPyNode<N>,
 {
-    let n = node_from_bytes(allocator, blob).unwrap();
-    allocator.to_pynode(&n)
+    let n = node_from_bytes(allocator, blob)?;
+    Ok(allocator.to_pynode(&n))
 }
 
 pub fn _serialize_to_bytes<A: Allocator, N>(
@@ -143,7 +167,7 @@ where
 {
     let py_node: N = sexp.extract()?;
     let node_t: Node<A> = Node::new(allocator, py_node.into());
-    let blob = node_to_bytes(&node_t).unwrap();
+    let blob = node_to_bytes(&node_t)?;
     let pybytes = PyBytes::new(py, &blob);
     Ok(pybytes.to_object(py))
 }
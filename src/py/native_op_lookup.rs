@@ -1,16 +1,18 @@
 use std::collections::HashMap;
 use std::marker::PhantomData;
 
+use pyo3::exceptions::PyValueError;
 use pyo3::prelude::*;
 use pyo3::types::{PyString, PyTuple};
 use pyo3::PyClass;
 
 use crate::allocator::Allocator;
 use crate::cost::Cost;
+use crate::op_table::{HostOpTable, HostOperators};
 use crate::reduction::{EvalErr, Reduction, Response};
 use crate::run_program::OperatorHandler;
 
-use super::f_table::{f_lookup_for_hashmap, FLookup};
+use super::f_table::f_lookup_for_hashmap;
 
 use super::to_py_node::ToPyNode;
 
@@ -27,8 +29,47 @@ where
     let sexp: N = pyerr.pvalue(py).getattr("_sexp")?.extract()?;
     let node: P = sexp.into();
     let s: String = arg0.to_str()?.to_string();
-    Ok(EvalErr(node, s))
+    Ok(EvalErr::new(node, s))
 }
+// hands the operators the native table doesn't implement to a python
+// callable. It answers every opcode, so none of them reach the unknown
+// operator rules
+pub struct PyOperators<N> {
+    py_callback: PyObject,
+    phantom_data: PhantomData<N>,
+}
+
+impl<N> Clone for PyOperators<N> {
+    fn clone(&self) -> Self {
+        PyOperators {
+            py_callback: self.py_callback.clone(),
+            phantom_data: PhantomData,
+        }
+    }
+}
+
+impl<A, N> HostOperators<A> for PyOperators<N>
+where
+    A: Allocator + ToPyNode<N>,
+    <A as Allocator>::Ptr: From<N>,
+    N: PyClass + Clone + IntoPy<PyObject>,
+{
+    fn op(
+        &self,
+        allocator: &mut A,
+        o: A::AtomBuf,
+        argument_list: &<A as Allocator>::Ptr,
+        _max_cost: Cost,
+    ) -> Option<Response<<A as Allocator>::Ptr>> {
+        Some(call_py::<A, N>(
+            &self.py_callback,
+            allocator,
+            &o,
+            argument_list,
+        ))
+    }
+}
+
 #[derive(Clone)]
 pub struct GenericNativeOpLookup<A, N>
 where
@@ -36,9 +77,7 @@ where
     N: PyClass,
     <A as Allocator>::Ptr: From<N>,
 {
-    py_callback: PyObject,
-    f_lookup: FLookup<A>,
-    phantom_data: PhantomData<N>,
+    ops: HostOpTable<A, PyOperators<N>>,
 }
 
 impl<A, N> GenericNativeOpLookup<A, N>
@@ -50,14 +89,42 @@ where
     pub fn new(
         opcode_lookup_by_name: HashMap<String, Vec<u8>>,
         unknown_op_callback: PyObject,
-    ) -> Self {
-        let f_lookup = f_lookup_for_hashmap(opcode_lookup_by_name);
+    ) -> PyResult<Self> {
+        let f_lookup = f_lookup_for_hashmap(opcode_lookup_by_name)?;
 
-        Self {
-            py_callback: unknown_op_callback,
-            f_lookup,
-            phantom_data: PhantomData,
-        }
+        Ok(Self {
+            ops: HostOpTable {
+                table: f_lookup,
+                host: PyOperators {
+                    py_callback: unknown_op_callback,
+                    phantom_data: PhantomData,
+                },
+            },
+        })
+    }
+}
+
+impl<A, N> GenericNativeOpLookup<A, N>
+where
+    A: Allocator + ToPyNode<N>,
+    N: PyClass,
+    <A as Allocator>::Ptr: From<N>,
+{
+    // fails if the quote or apply keyword is one of the native operators
+    pub fn check_keywords(&self, quote_kw: u8, apply_kw: u8) -> PyResult<()> {
+        self.ops
+            .table
+            .check_keywords(quote_kw, apply_kw)
+            .map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    pub fn py_callback(&self) -> &PyObject {
+        &self.ops.host.py_callback
+    }
+
+    // drops the reference to the python operators, which are then None
+    pub fn clear_py_callback(&mut self, py: Python) {
+        self.ops.host.py_callback = py.None();
     }
 }
 
@@ -68,30 +135,21 @@ where
     <A as Allocator>::Ptr: From<N>,
 {
     fn op(
-        &self,
+        &mut self,
         allocator: &mut A,
         op: A::AtomBuf,
         argument_list: &<A as Allocator>::Ptr,
         max_cost: Cost,
     ) -> Response<<A as Allocator>::Ptr> {
-        eval_op::<A, N>(
-            &self.f_lookup,
-            &self.py_callback,
-            allocator,
-            &op,
-            argument_list,
-            max_cost,
-        )
+        self.ops.op(allocator, op, argument_list, max_cost)
     }
 }
 
-fn eval_op<A, N>(
-    f_lookup: &FLookup<A>,
+fn call_py<A, N>(
     py_callback: &PyObject,
     allocator: &mut A,
     o: &<A as Allocator>::AtomBuf,
     argument_list: &<A as Allocator>::Ptr,
-    max_cost: Cost,
 ) -> Response<<A as Allocator>::Ptr>
 where
     A: Allocator + ToPyNode<N>,
@@ -100,12 +158,6 @@ where
     N: IntoPy<PyObject>,
 {
     let op = allocator.buf(o);
-    if op.len() == 1 {
-        if let Some(f) = f_lookup[op[0] as usize] {
-            return f(allocator, argument_list.clone(), max_cost);
-        }
-    }
-
     Python::with_gil(|py| {
         let pynode: N = allocator.to_pynode(argument_list);
         let r1 = py_callback.call1(py, (op, pynode));
@@ -128,18 +180,22 @@ where
                     unwrap_or_eval_err(pair.get_item(1).extract(), argument_list, "expected node")?;
 
                 let node: <A as Allocator>::Ptr = py_node.into();
-                Ok(Reduction(i0 as Cost, node))
+                Ok(Reduction(Cost::new(i0 as u64), node))
             }
         }
     })
 }
 
-fn unwrap_or_eval_err<T, P>(obj: PyResult<T>, err_node: &P, msg: &str) -> Result<T, EvalErr<P>>
+fn unwrap_or_eval_err<T, P>(
+    obj: PyResult<T>,
+    err_node: &P,
+    msg: &'static str,
+) -> Result<T, EvalErr<P>>
 where
     P: Clone,
 {
     match obj {
-        Err(_py_err) => Err(EvalErr(err_node.clone(), msg.to_string())),
+        Err(_py_err) => Err(EvalErr::new(err_node.clone(), msg)),
         Ok(o) => Ok(o),
     }
 }
@@ -1,13 +1,17 @@
-use super::arc_allocator::{ArcAllocator, ArcSExp};
+use super::arc_allocator::{ArcAllocator, ArcSExp, NodeId};
 use crate::allocator::{Allocator, SExp};
+use crate::node::Node;
+use crate::number::{number_from_u8, number_to_atom_bytes, Number};
 use crate::reduction::EvalErr;
 use std::cell::RefCell;
+use std::collections::HashMap;
 
-use pyo3::exceptions::PyValueError;
+use pyo3::class::gc::{PyGCProtocol, PyTraverseError, PyVisit};
+use pyo3::exceptions::{PyTypeError, PyValueError};
 use pyo3::prelude::*;
-use pyo3::types::{PyBytes, PyTuple};
+use pyo3::types::{IntoPyDict, PyBytes, PyList, PyLong, PyString, PyTuple};
 
-#[pyclass(subclass, unsendable)]
+#[pyclass(subclass, unsendable, gc)]
 #[derive(Clone)]
 pub struct PyNode {
     node: ArcSExp,
@@ -29,7 +33,15 @@ fn extract_atom(allocator: &mut ArcAllocator, obj: &PyAny) -> PyResult<PyNode> {
 }
 
 fn extract_node<'a>(_allocator: &ArcAllocator, obj: &'a PyAny) -> PyResult<PyRef<'a, PyNode>> {
-    let ps: &PyCell<PyNode> = obj.downcast()?;
+    let ps: &PyCell<PyNode> = match obj.downcast() {
+        Ok(ps) => ps,
+        Err(_) => {
+            return Err(PyTypeError::new_err(format!(
+                "SExp tuple items must be nodes, got {}",
+                obj.get_type().name()
+            )));
+        }
+    };
     let node: PyRef<'a, PyNode> = ps.try_borrow()?;
     Ok(node)
 }
@@ -53,6 +65,141 @@ fn extract_tuple(allocator: &mut ArcAllocator, obj: &PyAny) -> PyResult<PyNode>
     Ok(py_node)
 }
 
+// the canonical atom for a python int, the same as clvm's int_to_bytes()
+fn int_atom_bytes(obj: &PyAny) -> PyResult<Vec<u8>> {
+    if let Ok(v) = obj.extract::<i64>() {
+        return Ok(number_to_atom_bytes(&Number::from(v)));
+    }
+    let bits: usize = obj.call_method0("bit_length")?.extract()?;
+    let kwargs = [("signed", true)].into_py_dict(obj.py());
+    let bytes = obj.call_method("to_bytes", ((bits + 8) >> 3, "big"), Some(kwargs))?;
+    let bytes: &[u8] = bytes.extract()?;
+    Ok(number_to_atom_bytes(&number_from_u8(bytes)))
+}
+
+enum FromPyOp<'a> {
+    Visit(&'a PyAny),
+    Nil,
+    Cons,
+}
+
+// converts a whole python tree in one pass instead of one PyNode at a time.
+// Besides PyNodes, bytes and 2-tuples it takes the plain values clvm's
+// SExp.to() does: ints become their canonical atoms, strs their utf-8 bytes,
+// None the empty atom and lists are nil terminated
+pub fn native_from_python(allocator: &mut ArcAllocator, obj: &PyAny) -> PyResult<ArcSExp> {
+    let mut ops = vec![FromPyOp::Visit(obj)];
+    let mut values: Vec<ArcSExp> = Vec::new();
+    while let Some(op) = ops.pop() {
+        match op {
+            FromPyOp::Visit(obj) => {
+                if let Ok(node) = obj.downcast::<PyCell<PyNode>>() {
+                    values.push(node.try_borrow()?.node.clone());
+                } else if let Ok(b) = obj.downcast::<PyBytes>() {
+                    values.push(allocator.new_atom(b.as_bytes())?);
+                } else if let Ok(v) = obj.downcast::<PyTuple>() {
+                    if v.len() != 2 {
+                        return Err(PyValueError::new_err("SExp tuples must be size 2"));
+                    }
+                    ops.push(FromPyOp::Cons);
+                    ops.push(FromPyOp::Visit(v.get_item(1)));
+                    ops.push(FromPyOp::Visit(v.get_item(0)));
+                } else if let Ok(v) = obj.downcast::<PyList>() {
+                    for _ in 0..v.len() {
+                        ops.push(FromPyOp::Cons);
+                    }
+                    ops.push(FromPyOp::Nil);
+                    for item in v.iter().rev() {
+                        ops.push(FromPyOp::Visit(item));
+                    }
+                } else if let Ok(s) = obj.downcast::<PyString>() {
+                    values.push(allocator.new_atom(s.to_str()?.as_bytes())?);
+                } else if obj.is_instance::<PyLong>()? {
+                    values.push(allocator.new_atom(&int_atom_bytes(obj)?)?);
+                } else if obj.is_none() {
+                    values.push(allocator.null());
+                } else {
+                    return Err(PyTypeError::new_err(format!(
+                        "expected bytes, str, int, None, tuple or list, got {}",
+                        obj.get_type().name()
+                    )));
+                }
+            }
+            FromPyOp::Nil => values.push(allocator.null()),
+            FromPyOp::Cons => {
+                let right = values.pop().unwrap();
+                let left = values.pop().unwrap();
+                values.push(allocator.new_pair(left, right)?);
+            }
+        }
+    }
+    Ok(values.pop().unwrap())
+}
+
+enum ToPyOp<'a> {
+    Visit(&'a ArcSExp),
+    Cons(NodeId),
+}
+
+// atoms up to this size that are equal become the same bytes object in
+// python_from_native(). That covers operators, integers and hashes, which
+// repeat a lot, while larger atoms aren't hashed just to find they're unique
+const MAX_INTERNED_ATOM_LEN: usize = 64;
+
+// the inverse of native_from_python(). The tree is built bottom up as plain
+// tuples and bytes, without a PyNode (and its cache) per node. Subtrees that
+// share their storage (e.g. from back references) become the same python
+// object, rather than a copy each time they appear, as do small atoms with the
+// same contents
+pub fn python_from_native(py: Python, node: &ArcSExp) -> PyObject {
+    let allocator = ArcAllocator::new();
+    let mut ops = vec![ToPyOp::Visit(node)];
+    let mut values: Vec<PyObject> = Vec::new();
+    let mut built: HashMap<NodeId, PyObject> = HashMap::new();
+    let mut interned: HashMap<&[u8], PyObject> = HashMap::new();
+    while let Some(op) = ops.pop() {
+        match op {
+            ToPyOp::Visit(node) => {
+                let id = node.id();
+                if let Some(obj) = built.get(&id) {
+                    values.push(obj.clone_ref(py));
+                    continue;
+                }
+                match node {
+                    ArcSExp::Atom(a) => {
+                        let buf = allocator.buf(a);
+                        let obj: PyObject = match interned.get(buf) {
+                            Some(obj) => obj.clone_ref(py),
+                            None => {
+                                let obj: PyObject = PyBytes::new(py, buf).into();
+                                if buf.len() <= MAX_INTERNED_ATOM_LEN {
+                                    interned.insert(buf, obj.clone_ref(py));
+                                }
+                                obj
+                            }
+                        };
+                        built.insert(id, obj.clone_ref(py));
+                        values.push(obj);
+                    }
+                    ArcSExp::Pair(left, right) => {
+                        ops.push(ToPyOp::Cons(id));
+                        ops.push(ToPyOp::Visit(right));
+                        ops.push(ToPyOp::Visit(left));
+                    }
+                }
+            }
+            ToPyOp::Cons(id) => {
+                let right = values.pop().unwrap();
+                let left = values.pop().unwrap();
+                let obj: PyObject = PyTuple::new(py, &[left, right]).into();
+                built.insert(id, obj.clone_ref(py));
+                values.push(obj);
+            }
+        }
+    }
+    values.pop().unwrap()
+}
+
 impl From<&ArcSExp> for PyNode {
     fn from(item: &ArcSExp) -> Self {
         item.clone().into()
@@ -65,10 +212,13 @@ impl From<ArcSExp> for PyNode {
     }
 }
 
+// plain python values are converted, as with native_from_python()
 impl<'source> FromPyObject<'source> for ArcSExp {
     fn extract(obj: &'source PyAny) -> PyResult<Self> {
-        let py_node: PyNode = obj.extract()?;
-        Ok(py_node.into())
+        if let Ok(py_node) = obj.extract::<PyNode>() {
+            return Ok(py_node.into());
+        }
+        native_from_python(&mut ArcAllocator::new(), obj)
     }
 }
 
@@ -86,15 +236,50 @@ impl PyNode {
     #[new]
     pub fn py_new(obj: &PyAny) -> PyResult<Self> {
         let mut allocator = ArcAllocator::new();
-        let node: Self = {
-            let n = extract_atom(&mut allocator, obj);
-            if let Ok(r) = n {
-                r
-            } else {
-                extract_tuple(&mut allocator, obj)?
+        if obj.is_instance::<PyBytes>()? {
+            extract_atom(&mut allocator, obj)
+        } else if obj.is_instance::<PyTuple>()? {
+            match extract_tuple(&mut allocator, obj) {
+                Ok(node) => Ok(node),
+                // items that aren't nodes yet are converted
+                Err(_) => Ok(Self::new(native_from_python(&mut allocator, obj)?)),
+            }
+        } else {
+            Ok(Self::new(native_from_python(&mut allocator, obj)?))
+        }
+    }
+
+    // builds a tree from nested values (see native_from_python()) in one pass
+    #[staticmethod]
+    pub fn from_python(obj: &PyAny) -> PyResult<Self> {
+        let mut allocator = ArcAllocator::new();
+        Ok(Self::new(native_from_python(&mut allocator, obj)?))
+    }
+
+    // the whole tree as nested tuples and bytes, built in one pass
+    pub fn to_python(&self, py: Python) -> PyObject {
+        python_from_native(py, &self.node)
+    }
+
+    // follows an accessor string like "rrf" (the third item of a list), or a
+    // path int like those the environment is looked up with. None if the path
+    // goes into an atom
+    pub fn at(&self, path: &PyAny) -> PyResult<Option<Self>> {
+        let allocator = ArcAllocator::new();
+        let node = Node::new(&allocator, self.node.clone());
+        let r = if let Ok(s) = path.extract::<&str>() {
+            if let Some(c) = s.chars().find(|c| *c != 'f' && *c != 'r') {
+                return Err(PyValueError::new_err(format!(
+                    "invalid accessor character {:?}",
+                    c
+                )));
             }
+            node.at(s)
+        } else {
+            let path: u64 = path.extract()?;
+            node.at_path(&path.to_be_bytes())
         };
-        Ok(node)
+        Ok(r.map(|n| Self::new(n.node)))
     }
 
     #[getter(pair)]
@@ -144,6 +329,23 @@ impl PyNode {
     }
 }
 
+// the cached python value can lead back to this node (e.g. through a
+// subclass's attributes), so the garbage collector has to see it to collect
+// such cycles
+#[pyproto]
+impl PyGCProtocol for PyNode {
+    fn __traverse__(&self, visit: PyVisit) -> Result<(), PyTraverseError> {
+        if let Some(obj) = &*self.pyobj.borrow() {
+            visit.call(obj)?;
+        }
+        Ok(())
+    }
+
+    fn __clear__(&mut self) {
+        self.pyobj.replace(None);
+    }
+}
+
 impl PyNode {
     pub const fn new(node: ArcSExp) -> Self {
         Self::new_cached(node, None)
@@ -1,3 +1,4 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
 
 use crate::allocator::Allocator;
@@ -6,15 +7,46 @@ use crate::err_utils::err;
 use crate::int_allocator::IntAllocator;
 use crate::more_ops::op_unknown;
 use crate::node::Node;
+use crate::op_table::{op_table_for, DEFAULT_OPCODES};
+use crate::py::arena::Arena;
 use crate::py::f_table::{f_lookup_for_hashmap, FLookup};
+use crate::recording::{self, is_recording, outcome_of, record_call, Call, Outcome};
 use crate::reduction::Response;
-use crate::run_program::{run_program, OperatorHandler};
+use crate::run_program::{run_program_with_params, OperatorHandler};
 use crate::serialize::{node_from_bytes, node_to_bytes};
 
+use lazy_static::lazy_static;
+use pyo3::exceptions::{PyMemoryError, PyValueError};
 use pyo3::prelude::*;
 use pyo3::types::{PyBytes, PyDict};
 
-pub const STRICT_MODE: u32 = 1;
+pub use crate::recording::STRICT_MODE;
+
+// allocators holding on to more than this are dropped rather than kept for
+// the next call, so one huge program doesn't pin its memory forever
+const MAX_RETAINED_ALLOCATOR_BYTES: usize = 64 * 1024 * 1024;
+
+thread_local! {
+    // each thread keeps the allocator from its last call, so back-to-back
+    // evaluations reuse its buffers (see IntAllocator::reset())
+    static ALLOCATOR: RefCell<Option<IntAllocator>> = RefCell::new(None);
+}
+
+fn take_allocator() -> IntAllocator {
+    match ALLOCATOR.with(|a| a.borrow_mut().take()) {
+        Some(mut a) => {
+            a.reset();
+            a
+        }
+        None => IntAllocator::new(),
+    }
+}
+
+fn recycle_allocator(allocator: IntAllocator) {
+    if allocator.capacity_bytes() <= MAX_RETAINED_ALLOCATOR_BYTES {
+        ALLOCATOR.with(|a| *a.borrow_mut() = Some(allocator));
+    }
+}
 
 struct OperatorHandlerWithMode<A: Allocator> {
     f_lookup: FLookup<A>,
@@ -23,17 +55,15 @@ struct OperatorHandlerWithMode<A: Allocator> {
 
 impl<A: Allocator> OperatorHandler<A> for OperatorHandlerWithMode<A> {
     fn op(
-        &self,
+        &mut self,
         allocator: &mut A,
         o: <A as Allocator>::AtomBuf,
         argument_list: &A::Ptr,
         max_cost: Cost,
     ) -> Response<<A as Allocator>::Ptr> {
         let op = &allocator.buf(&o);
-        if op.len() == 1 {
-            if let Some(f) = self.f_lookup[op[0] as usize] {
-                return f(allocator, argument_list.clone(), max_cost);
-            }
+        if let Some(f) = self.f_lookup.get(op) {
+            return f.call(allocator, argument_list.clone(), max_cost);
         }
         if self.strict {
             let buf = op.to_vec();
@@ -45,7 +75,23 @@ impl<A: Allocator> OperatorHandler<A> for OperatorHandlerWithMode<A> {
     }
 }
 
+// appends every call of serialize_and_run_program() and
+// deserialize_and_run_program() to a file, until stop_recording()
 #[pyfunction]
+pub fn start_recording(path: &str) -> PyResult<()> {
+    Ok(recording::start_recording(path)?)
+}
+
+#[pyfunction]
+pub fn stop_recording() {
+    recording::stop_recording();
+}
+
+// the run functions take an optional Arena to allocate in, rather than the
+// allocator this thread keeps
+#[allow(clippy::too_many_arguments)]
+#[pyfunction]
+#[args(arena = "None")]
 pub fn serialize_and_run_program(
     py: Python,
     program: &[u8],
@@ -54,44 +100,11 @@ pub fn serialize_and_run_program(
     apply_kw: u8,
     max_cost: Cost,
     flags: u32,
+    arena: Option<&PyCell<Arena>>,
 ) -> PyResult<(Cost, Py<PyBytes>)> {
     let mut opcode_lookup_by_name = HashMap::<String, Vec<u8>>::new();
-    for (v, s) in [
-        (4, "op_if"),
-        (5, "op_cons"),
-        (6, "op_first"),
-        (7, "op_rest"),
-        (8, "op_listp"),
-        (9, "op_raise"),
-        (10, "op_eq"),
-        (11, "op_sha256"),
-        (12, "op_add"),
-        (13, "op_subtract"),
-        (14, "op_multiply"),
-        (15, "op_divmod"),
-        (16, "op_substr"),
-        (17, "op_strlen"),
-        (18, "op_point_add"),
-        (19, "op_pubkey_for_exp"),
-        (20, "op_concat"),
-        (22, "op_gr"),
-        (23, "op_gr_bytes"),
-        (24, "op_logand"),
-        (25, "op_logior"),
-        (26, "op_logxor"),
-        (27, "op_lognot"),
-        (28, "op_ash"),
-        (29, "op_lsh"),
-        (30, "op_not"),
-        (31, "op_any"),
-        (32, "op_all"),
-        (33, "op_softfork"),
-        (34, "op_div"),
-    ]
-    .iter()
-    {
-        let v: Vec<u8> = vec![*v as u8];
-        opcode_lookup_by_name.insert(s.to_string(), v);
+    for (v, s) in DEFAULT_OPCODES.iter() {
+        opcode_lookup_by_name.insert(s.to_string(), vec![*v]);
     }
 
     deserialize_and_run_program(
@@ -103,11 +116,13 @@ pub fn serialize_and_run_program(
         opcode_lookup_by_name,
         max_cost,
         flags,
+        arena,
     )
 }
 
 #[allow(clippy::too_many_arguments)]
 #[pyfunction]
+#[args(arena = "None")]
 pub fn deserialize_and_run_program(
     py: Python,
     program: &[u8],
@@ -117,36 +132,262 @@ pub fn deserialize_and_run_program(
     opcode_lookup_by_name: HashMap<String, Vec<u8>>,
     max_cost: Cost,
     flags: u32,
+    arena: Option<&PyCell<Arena>>,
 ) -> PyResult<(Cost, Py<PyBytes>)> {
-    let mut allocator = IntAllocator::new();
-    let f_lookup = f_lookup_for_hashmap(opcode_lookup_by_name);
-    let strict: bool = (flags & STRICT_MODE) != 0;
-    let f: Box<dyn OperatorHandler<IntAllocator> + Send> =
-        Box::new(OperatorHandlerWithMode { f_lookup, strict });
-    let program = node_from_bytes(&mut allocator, program)?;
-    let args = node_from_bytes(&mut allocator, args)?;
+    let params = RunParams {
+        quote_kw,
+        apply_kw,
+        max_cost,
+        max_depth: 0,
+        max_heap: 0,
+        max_steps: 0,
+        flags,
+    };
+    with_allocator(arena, |allocator, programs| {
+        run_serialized_program(
+            py,
+            allocator,
+            programs,
+            program,
+            args,
+            opcode_lookup_by_name,
+            &params,
+        )
+    })
+}
 
-    let r = py.allow_threads(|| {
-        run_program(
-            &mut allocator,
-            &program,
-            &args,
+// runs f with the arena's allocator and loaded programs, or with this
+// thread's allocator and none
+fn with_allocator<R, F>(arena: Option<&PyCell<Arena>>, f: F) -> PyResult<R>
+where
+    F: FnOnce(&mut IntAllocator, &HashMap<Vec<u8>, i32>) -> PyResult<R>,
+{
+    match arena {
+        Some(arena) => arena.try_borrow_mut()?.run(f),
+        None => {
+            let mut allocator = take_allocator();
+            let r = f(&mut allocator, &HashMap::new());
+            recycle_allocator(allocator);
+            r
+        }
+    }
+}
+
+// the keywords and limits of an evaluation, for
+// run_serialized_program_with_params() and py_run_program_with_params(). The
+// limits are 0 when there are none
+#[pyclass]
+#[derive(Clone)]
+pub struct RunParams {
+    #[pyo3(get, set)]
+    pub quote_kw: u8,
+    #[pyo3(get, set)]
+    pub apply_kw: u8,
+    #[pyo3(get, set)]
+    pub max_cost: Cost,
+    #[pyo3(get, set)]
+    pub max_depth: usize,
+    #[pyo3(get, set)]
+    pub max_heap: usize,
+    #[pyo3(get, set)]
+    pub max_steps: u64,
+    #[pyo3(get, set)]
+    pub flags: u32,
+}
+
+#[pymethods]
+impl RunParams {
+    #[new]
+    #[args(
+        quote_kw = "1",
+        apply_kw = "2",
+        max_cost = "Cost::ZERO",
+        max_depth = "0",
+        max_heap = "0",
+        max_steps = "0",
+        flags = "0"
+    )]
+    fn new(
+        quote_kw: u8,
+        apply_kw: u8,
+        max_cost: Cost,
+        max_depth: usize,
+        max_heap: usize,
+        max_steps: u64,
+        flags: u32,
+    ) -> Self {
+        RunParams {
             quote_kw,
             apply_kw,
             max_cost,
-            f,
-            None,
+            max_depth,
+            max_heap,
+            max_steps,
+            flags,
+        }
+    }
+}
+
+impl<'a> From<&RunParams> for crate::run_program::RunParams<'a, i32> {
+    fn from(p: &RunParams) -> Self {
+        crate::run_program::RunParams::new()
+            .with_keywords(p.quote_kw, p.apply_kw)
+            .with_max_cost(p.max_cost)
+            .with_max_depth(p.max_depth)
+            .with_max_heap(p.max_heap)
+            .with_max_steps(p.max_steps)
+            .with_flags(p.flags)
+    }
+}
+
+// deserialize_and_run_program() with the keywords, limits and flags in a
+// RunParams. Without opcode_lookup_by_name, the default operators are used
+#[pyfunction]
+#[args(opcode_lookup_by_name = "None", arena = "None")]
+pub fn run_serialized_program_with_params(
+    py: Python,
+    program: &[u8],
+    args: &[u8],
+    params: &RunParams,
+    opcode_lookup_by_name: Option<HashMap<String, Vec<u8>>>,
+    arena: Option<&PyCell<Arena>>,
+) -> PyResult<(Cost, Py<PyBytes>)> {
+    let opcode_lookup_by_name = match opcode_lookup_by_name {
+        Some(lookup) => lookup,
+        None => DEFAULT_OPCODES
+            .iter()
+            .map(|(v, s)| (s.to_string(), vec![*v]))
+            .collect(),
+    };
+    with_allocator(arena, |allocator, programs| {
+        run_serialized_program(
+            py,
+            allocator,
+            programs,
+            program,
+            args,
+            opcode_lookup_by_name,
+            params,
         )
+    })
+}
+
+lazy_static! {
+    static ref DEFAULT_OPS: FLookup<IntAllocator> = op_table_for(&DEFAULT_OPCODES);
+}
+
+// serialize_and_run_program() for when the result is only passed on. No
+// python objects are built other than the result bytes, not even for errors,
+// the GIL is released while deserializing and serializing too, and the
+// allocator is dropped after the call. Errors are raised as ValueError(msg,
+// node), with the node serialized
+#[pyfunction]
+pub fn run_serialized_to_bytes(
+    py: Python,
+    program: &[u8],
+    args: &[u8],
+    quote_kw: u8,
+    apply_kw: u8,
+    max_cost: Cost,
+    flags: u32,
+) -> PyResult<(Cost, Py<PyBytes>)> {
+    DEFAULT_OPS
+        .check_keywords(quote_kw, apply_kw)
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let r = py.allow_threads(|| -> PyResult<(Outcome, bool)> {
+        let len = program.len() + args.len();
+        let mut allocator = IntAllocator::with_capacity(len, len);
+        let f: Box<dyn OperatorHandler<IntAllocator> + Send> = Box::new(OperatorHandlerWithMode {
+            f_lookup: DEFAULT_OPS.clone(),
+            strict: (flags & STRICT_MODE) != 0,
+        });
+        let program_node = node_from_bytes(&mut allocator, program)?;
+        let args_node = node_from_bytes(&mut allocator, args)?;
+        let params = crate::run_program::RunParams::new()
+            .with_keywords(quote_kw, apply_kw)
+            .with_max_cost(max_cost)
+            .with_flags(flags);
+        let r = run_program_with_params(&mut allocator, &program_node, &args_node, f, None, params);
+        let operational = matches!(&r, Err(e) if e.is_operational());
+        let outcome = outcome_of(&allocator, &r)?;
+        if is_recording() {
+            let call = Call {
+                program: program.to_vec(),
+                env: args.to_vec(),
+                quote_kw,
+                apply_kw,
+                max_cost,
+                flags,
+            };
+            record_call(call, outcome.clone())?;
+        }
+        Ok((outcome, operational))
+    })?;
+    match r {
+        (Outcome::Ok(cost, result), _) => Ok((cost, PyBytes::new(py, &result).into())),
+        (Outcome::Err(_, msg), true) => Err(PyMemoryError::new_err(msg)),
+        (Outcome::Err(node, msg), false) => Err(PyValueError::new_err((
+            msg,
+            PyBytes::new(py, &node).to_object(py),
+        ))),
+    }
+}
+
+// programs given in `programs` aren't deserialized again
+fn run_serialized_program(
+    py: Python,
+    allocator: &mut IntAllocator,
+    programs: &HashMap<Vec<u8>, i32>,
+    program: &[u8],
+    args: &[u8],
+    opcode_lookup_by_name: HashMap<String, Vec<u8>>,
+    params: &RunParams,
+) -> PyResult<(Cost, Py<PyBytes>)> {
+    let f_lookup = f_lookup_for_hashmap(opcode_lookup_by_name)?;
+    f_lookup
+        .check_keywords(params.quote_kw, params.apply_kw)
+        .map_err(|e| PyValueError::new_err(e.to_string()))?;
+    let strict: bool = (params.flags & STRICT_MODE) != 0;
+    let f: Box<dyn OperatorHandler<IntAllocator> + Send> =
+        Box::new(OperatorHandlerWithMode { f_lookup, strict });
+    let call = if is_recording() {
+        Some(Call {
+            program: program.to_vec(),
+            env: args.to_vec(),
+            quote_kw: params.quote_kw,
+            apply_kw: params.apply_kw,
+            max_cost: params.max_cost,
+            flags: params.flags,
+        })
+    } else {
+        None
+    };
+    let program = match programs.get(program) {
+        Some(node) => *node,
+        None => node_from_bytes(allocator, program)?,
+    };
+    let args = node_from_bytes(allocator, args)?;
+
+    // the native params may borrow a fuel meter or tracer, so they aren't
+    // Sync. They're built on the thread that runs them
+    let r = py.allow_threads(|| {
+        run_program_with_params(allocator, &program, &args, f, None, params.into())
     });
+    if let Some(call) = call {
+        record_call(call, outcome_of(&*allocator, &r)?)?;
+    }
     match r {
         Ok(reduction) => {
-            let node_as_blob = node_to_bytes(&Node::new(&allocator, reduction.1))?;
+            let node_as_blob = node_to_bytes(&Node::new(allocator, reduction.1))?;
             let node_as_bytes: Py<PyBytes> = PyBytes::new(py, &node_as_blob).into();
             Ok((reduction.0, node_as_bytes))
         }
+        Err(eval_err) if eval_err.is_operational() => {
+            Err(PyMemoryError::new_err(eval_err.1.to_string()))
+        }
         Err(eval_err) => {
-            let node_as_blob = node_to_bytes(&Node::new(&allocator, eval_err.0))?;
-            let msg = eval_err.1;
+            let node_as_blob = node_to_bytes(&Node::new(allocator, eval_err.0))?;
+            let msg: String = eval_err.1.into();
             let ctx: &PyDict = PyDict::new(py);
             ctx.set_item("msg", msg)?;
             ctx.set_item("node_as_blob", node_as_blob)?;
@@ -0,0 +1,212 @@
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::sha256::sha256 as hash_blobs;
+
+// node_to_bytes_backrefs() uses this to mirror the stack a deserializer builds
+// while reading back references, and to find paths into it. Nodes are
+// identified by their tree hash, so identical subtrees are interchangeable.
+//
+// The stack is a list, most recent item first, so the item at depth d is
+// reached by d rests and a first. Every pair is built by a cons of the top two
+// items, and stays reachable (inside the item that replaced them) from then
+// on. So we keep the pairs every node has been a child of, and paths are found
+// by walking up from a node to an item on the stack
+pub struct ReadCacheLookup {
+    // every hash seen so far is given an index into the vectors below
+    ids: BTreeMap<[u8; 32], usize>,
+    hashes: Vec<[u8; 32]>,
+    // the pairs each node is a child of, as (parent, 0 for first or 1 for
+    // rest), oldest first
+    parents: Vec<Vec<(usize, u8)>>,
+    // the positions each node has on the stack, in increasing order
+    positions: Vec<Vec<usize>>,
+    // the stack, bottom first
+    stack: Vec<usize>,
+    // the search in find_path() visited the nodes marked with its generation
+    visited: Vec<u32>,
+    generation: u32,
+}
+
+impl Default for ReadCacheLookup {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReadCacheLookup {
+    pub fn new() -> Self {
+        ReadCacheLookup {
+            ids: BTreeMap::new(),
+            hashes: Vec::new(),
+            parents: Vec::new(),
+            positions: Vec::new(),
+            stack: Vec::new(),
+            visited: Vec::new(),
+            generation: 0,
+        }
+    }
+
+    fn intern(&mut self, hash: [u8; 32]) -> usize {
+        if let Some(id) = self.ids.get(&hash) {
+            return *id;
+        }
+        let id = self.hashes.len();
+        self.ids.insert(hash, id);
+        self.hashes.push(hash);
+        self.parents.push(Vec::new());
+        self.positions.push(Vec::new());
+        self.visited.push(0);
+        id
+    }
+
+    pub fn push(&mut self, hash: [u8; 32]) {
+        let id = self.intern(hash);
+        self.positions[id].push(self.stack.len());
+        self.stack.push(id);
+    }
+
+    fn pop(&mut self) -> usize {
+        let id = self.stack.pop().expect("stack empty");
+        self.positions[id].pop();
+        id
+    }
+
+    // replaces the top two items with a pair of them
+    pub fn pop2_and_cons(&mut self) {
+        let right = self.pop();
+        let left = self.pop();
+        let pair = self.intern(hash_blobs(&[&[2], &self.hashes[left], &self.hashes[right]]));
+        // the same pair may be recorded more than once, find_path() skips
+        // parents it has seen
+        self.parents[left].push((pair, 0));
+        self.parents[right].push((pair, 1));
+        self.positions[pair].push(self.stack.len());
+        self.stack.push(pair);
+    }
+
+    // the shortest path from the root of the stack to a node with the hash id,
+    // encoded as a clvm path atom. Only paths that make a back reference
+    // (0xfe and the path atom) shorter than serialized_length are considered
+    pub fn find_path(&mut self, id: &[u8; 32], serialized_length: u64) -> Option<Vec<u8>> {
+        let start = *self.ids.get(id)?;
+        let mut max_steps = 0;
+        while max_steps < 256 && 1 + path_atom_size(max_steps + 1) < serialized_length {
+            max_steps += 1;
+        }
+        if max_steps == 0 {
+            return None;
+        }
+        self.generation += 1;
+        self.visited[start] = self.generation;
+
+        // the nodes visited, as (node, index of the child it was reached
+        // from, direction from the parent to that child, steps from the
+        // start node)
+        let mut nodes = vec![(start, usize::MAX, 0_u8, 0_usize)];
+        let mut best: Option<(usize, usize)> = None;
+        let mut next = 0;
+        while next < nodes.len() {
+            let (node, _, _, steps) = nodes[next];
+            if let Some(position) = self.positions[node].last() {
+                let total = steps + 1 + (self.stack.len() - 1 - position);
+                if total <= max_steps {
+                    max_steps = total - 1;
+                    best = Some((next, total));
+                }
+            }
+            // going further up takes at least two more steps
+            if steps + 2 <= max_steps {
+                for (parent, direction) in self.parents[node].iter().rev() {
+                    if self.visited[*parent] != self.generation {
+                        self.visited[*parent] = self.generation;
+                        nodes.push((*parent, next, *direction, steps + 1));
+                    }
+                }
+            }
+            next += 1;
+        }
+
+        // the directions from the start node up to the root
+        let (mut index, total) = best?;
+        let mut path = Vec::with_capacity(total);
+        while index != 0 {
+            let (_, child, direction, _) = nodes[index];
+            path.push(direction);
+            index = child;
+        }
+        path.reverse();
+        path.push(0);
+        path.resize(total, 1);
+        Some(reversed_path_to_atom(&path))
+    }
+}
+
+// the serialized size of a path atom for a path of this many steps
+fn path_atom_size(steps: usize) -> u64 {
+    // one bit per step, plus the sentinel bit
+    let bytes = (steps + 8) / 8;
+    if bytes == 1 && steps < 7 {
+        1
+    } else {
+        1 + bytes as u64
+    }
+}
+
+// path holds the directions from a node up to the root. The path atom has
+// the first step down from the root in its least significant bit, and a 1
+// bit above the last step
+fn reversed_path_to_atom(path: &[u8]) -> Vec<u8> {
+    let byte_count = (path.len() + 8) / 8;
+    let mut v = vec![0_u8; byte_count];
+    let mut index = byte_count - 1;
+    let mut mask: u8 = 1;
+    for p in path.iter().rev() {
+        if *p != 0 {
+            v[index] |= mask;
+        }
+        if mask == 0x80 {
+            index -= 1;
+            mask = 1;
+        } else {
+            mask <<= 1;
+        }
+    }
+    v[index] |= mask;
+    v
+}
+
+#[test]
+fn test_reversed_path_to_atom() {
+    assert_eq!(reversed_path_to_atom(&[]), vec![0b1]);
+    // first
+    assert_eq!(reversed_path_to_atom(&[0]), vec![0b10]);
+    // rest, then first
+    assert_eq!(reversed_path_to_atom(&[0, 1]), vec![0b101]);
+    assert_eq!(reversed_path_to_atom(&[1; 7]), vec![0xff]);
+    assert_eq!(reversed_path_to_atom(&[0; 8]), vec![0x01, 0x00]);
+    assert_eq!(path_atom_size(6), 1);
+    assert_eq!(path_atom_size(7), 2);
+    assert_eq!(path_atom_size(8), 3);
+}
+
+#[test]
+fn test_find_path() {
+    let atom = |b: u8| hash_blobs(&[&[1], &[b]]);
+    let mut rcl = ReadCacheLookup::new();
+    rcl.push(atom(5));
+    // the stack is (5), so 5 is its first
+    assert_eq!(rcl.find_path(&atom(5), 10), Some(vec![0b10]));
+    rcl.push(atom(6));
+    // (6 5)
+    assert_eq!(rcl.find_path(&atom(5), 10), Some(vec![0b101]));
+    assert_eq!(rcl.find_path(&atom(6), 10), Some(vec![0b10]));
+    assert_eq!(rcl.find_path(&atom(7), 10), None);
+    // too long to be worth it
+    assert_eq!(rcl.find_path(&atom(5), 2), None);
+    rcl.pop2_and_cons();
+    // ((5 . 6))
+    assert_eq!(rcl.find_path(&atom(5), 10), Some(vec![0b100]));
+    assert_eq!(rcl.find_path(&atom(6), 10), Some(vec![0b110]));
+}
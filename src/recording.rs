@@ -0,0 +1,420 @@
+// Recording the inputs and results of run_program() calls, and replaying them
+// later, so a change to the VM can be checked against the traffic a node
+// actually saw. Records are appended to a file, one per line:
+//
+//   <quote_kw> <apply_kw> <max_cost> <flags> <program> <env> ok <cost> <result>
+//   <quote_kw> <apply_kw> <max_cost> <flags> <program> <env> err <node> <message>
+//
+// with the program, environment, result and node serialized, in hex. A line
+// is written with a single call, so a crash at worst leaves the last line
+// incomplete, and replay() skips it.
+//
+// The operators aren't recorded. replay() asks the caller for them, and
+// default_operators() are the ones serialize_and_run_program() uses
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+
+use crate::allocator::Allocator;
+use crate::cost::Cost;
+use crate::int_allocator::IntAllocator;
+use crate::node::Node;
+use crate::op_table::{op_table_for, HostOpTable, HostOperators, DEFAULT_OPCODES};
+use crate::reduction::{EvalErr, Response};
+use crate::run_program::{run_program, OperatorHandler};
+use crate::serialize::{node_from_bytes, node_to_bytes};
+
+pub use crate::run_program::STRICT_MODE;
+
+// the inputs of one call
+#[derive(Clone, Debug, PartialEq)]
+pub struct Call {
+    pub program: Vec<u8>,
+    pub env: Vec<u8>,
+    pub quote_kw: u8,
+    pub apply_kw: u8,
+    pub max_cost: Cost,
+    pub flags: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    // the cost and the serialized result
+    Ok(Cost, Vec<u8>),
+    // the serialized node the error is about, and its message
+    Err(Vec<u8>, String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+    pub call: Call,
+    pub outcome: Outcome,
+}
+
+impl Record {
+    // the record as a line of the log, without the newline
+    pub fn to_line(&self) -> String {
+        let c = &self.call;
+        let outcome = match &self.outcome {
+            Outcome::Ok(cost, result) => format!("ok {} {}", cost, hex::encode(result)),
+            // the message is last, so it may contain spaces. Line breaks
+            // would split the record
+            Outcome::Err(node, msg) => {
+                format!("err {} {}", hex::encode(node), msg.replace('\n', " "))
+            }
+        };
+        format!(
+            "{} {} {} {} {} {} {}",
+            c.quote_kw,
+            c.apply_kw,
+            c.max_cost,
+            c.flags,
+            hex::encode(&c.program),
+            hex::encode(&c.env),
+            outcome
+        )
+    }
+
+    pub fn from_line(line: &str) -> Result<Self, String> {
+        let mut fields = line.splitn(9, ' ');
+        let mut next = |what: &str| fields.next().ok_or(format!("missing {}", what));
+
+        let quote_kw = parse_number(next("quote keyword")?, "quote keyword")?;
+        let apply_kw = parse_number(next("apply keyword")?, "apply keyword")?;
+        let max_cost = parse_number(next("max cost")?, "max cost")?;
+        let flags = parse_number(next("flags")?, "flags")?;
+        let program = parse_blob(next("program")?, "program")?;
+        let env = parse_blob(next("environment")?, "environment")?;
+        let outcome = match next("outcome")? {
+            "ok" => {
+                let cost = parse_number(next("cost")?, "cost")?;
+                Outcome::Ok(cost, parse_blob(next("result")?, "result")?)
+            }
+            "err" => {
+                let node = parse_blob(next("node")?, "node")?;
+                Outcome::Err(node, next("message")?.to_string())
+            }
+            o => return Err(format!("invalid outcome {}", o)),
+        };
+        Ok(Record {
+            call: Call {
+                program,
+                env,
+                quote_kw,
+                apply_kw,
+                max_cost,
+                flags,
+            },
+            outcome,
+        })
+    }
+}
+
+fn parse_number<T: FromStr>(s: &str, what: &str) -> Result<T, String> {
+    s.parse().map_err(|_| format!("invalid {}", what))
+}
+
+fn parse_blob(s: &str, what: &str) -> Result<Vec<u8>, String> {
+    hex::decode(s).map_err(|_| format!("invalid {}", what))
+}
+
+// the outcome of a call, as it's recorded
+pub fn outcome_of<T: Allocator>(allocator: &T, r: &Response<T::Ptr>) -> io::Result<Outcome> {
+    let blob = |node: &T::Ptr| {
+        node_to_bytes(&Node::new(allocator, node.clone()))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    };
+    Ok(match r {
+        Ok(r) => Outcome::Ok(r.0, blob(&r.1)?),
+        Err(e) => Outcome::Err(blob(&e.0)?, e.1.to_string()),
+    })
+}
+
+// appends records to a file. Several threads may share one
+pub struct Recorder {
+    file: Mutex<File>,
+}
+
+impl Recorder {
+    pub fn open(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Recorder {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn record(&self, record: &Record) -> io::Result<()> {
+        let mut line = record.to_line();
+        line.push('\n');
+        let mut file = self.file.lock().unwrap();
+        file.write_all(line.as_bytes())
+    }
+}
+
+lazy_static! {
+    // where the bindings record their calls to, if anywhere
+    static ref RECORDER: Mutex<Option<Recorder>> = Mutex::new(None);
+}
+
+// from now on, the bindings append every call they run to this file
+pub fn start_recording(path: &str) -> io::Result<()> {
+    let recorder = Recorder::open(path)?;
+    *RECORDER.lock().unwrap() = Some(recorder);
+    Ok(())
+}
+
+pub fn stop_recording() {
+    *RECORDER.lock().unwrap() = None;
+}
+
+pub fn is_recording() -> bool {
+    RECORDER.lock().unwrap().is_some()
+}
+
+// records a call, if recording was started. The result has to be serialized
+// for that, so callers check is_recording() first
+pub fn record_call(call: Call, outcome: Outcome) -> io::Result<()> {
+    match &*RECORDER.lock().unwrap() {
+        Some(recorder) => recorder.record(&Record { call, outcome }),
+        None => Ok(()),
+    }
+}
+
+// host operators that fail every operator the table doesn't implement
+#[derive(Clone)]
+pub struct Strict;
+
+impl<T: Allocator> HostOperators<T> for Strict {
+    fn op(
+        &self,
+        allocator: &mut T,
+        op: <T as Allocator>::AtomBuf,
+        _args: &T::Ptr,
+        _max_cost: Cost,
+    ) -> Option<Response<T::Ptr>> {
+        let op = allocator.buf(&op).to_vec();
+        Some(match allocator.new_atom(&op) {
+            Ok(node) => Err(EvalErr::new(node, "unimplemented operator")),
+            Err(e) => Err(e),
+        })
+    }
+}
+
+// the default opcodes, strict if the call's flags say so
+pub fn default_operators(call: &Call) -> Box<dyn OperatorHandler<IntAllocator>> {
+    let table = op_table_for(&DEFAULT_OPCODES);
+    if call.flags & STRICT_MODE != 0 {
+        Box::new(HostOpTable {
+            table,
+            host: Strict,
+        })
+    } else {
+        Box::new(table)
+    }
+}
+
+// runs a recorded call again
+pub fn run_call(
+    call: &Call,
+    operators: Box<dyn OperatorHandler<IntAllocator>>,
+) -> io::Result<Outcome> {
+    let invalid = |e: crate::serialize::SerializeError| {
+        io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+    };
+    let mut a = IntAllocator::new();
+    let program = node_from_bytes(&mut a, &call.program).map_err(invalid)?;
+    let env = node_from_bytes(&mut a, &call.env).map_err(invalid)?;
+    let r = run_program(
+        &mut a,
+        &program,
+        &env,
+        call.quote_kw,
+        call.apply_kw,
+        call.max_cost,
+        operators,
+        None,
+    );
+    outcome_of(&a, &r)
+}
+
+// a record whose call gave a different outcome when it was replayed
+#[derive(Clone, Debug, PartialEq)]
+pub struct Mismatch {
+    // counting from 1
+    pub line: usize,
+    pub record: Record,
+    pub replayed: Outcome,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReplayReport {
+    pub replayed: usize,
+    pub mismatches: Vec<Mismatch>,
+}
+
+// runs every call in a log again, with the operators `operators` returns for
+// it, and reports the ones whose outcome changed
+pub fn replay<R: BufRead, F>(mut log: R, mut operators: F) -> io::Result<ReplayReport>
+where
+    F: FnMut(&Call) -> Box<dyn OperatorHandler<IntAllocator>>,
+{
+    let mut report = ReplayReport::default();
+    let mut line = Vec::new();
+    let mut number = 0;
+    loop {
+        line.clear();
+        if log.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        number += 1;
+        // without a newline, the recording process died while writing
+        // the line
+        if line.pop() != Some(b'\n') {
+            break;
+        }
+        if line.is_empty() {
+            continue;
+        }
+        let record = std::str::from_utf8(&line)
+            .map_err(|_| "not utf-8".to_string())
+            .and_then(Record::from_line)
+            .map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", number, e),
+                )
+            })?;
+        let replayed = run_call(&record.call, operators(&record.call))?;
+        report.replayed += 1;
+        if replayed != record.outcome {
+            report.mismatches.push(Mismatch {
+                line: number,
+                record,
+                replayed,
+            });
+        }
+    }
+    Ok(report)
+}
+
+#[test]
+fn test_record_line() {
+    let record = Record {
+        call: Call {
+            program: vec![0xff, 0x10, 0xff, 0x02, 0xff, 0x05, 0x80],
+            env: vec![0x80],
+            quote_kw: 1,
+            apply_kw: 2,
+            max_cost: Cost::new(1000),
+            flags: STRICT_MODE,
+        },
+        outcome: Outcome::Err(vec![0x80], "+ requires int args".to_string()),
+    };
+    let line = record.to_line();
+    assert_eq!(
+        line,
+        "1 2 1000 1 ff10ff02ff0580 80 err 80 + requires int args"
+    );
+    assert_eq!(Record::from_line(&line).unwrap(), record);
+
+    let ok = Record {
+        outcome: Outcome::Ok(Cost::new(856), vec![7]),
+        ..record
+    };
+    assert_eq!(Record::from_line(&ok.to_line()).unwrap(), ok);
+
+    assert!(Record::from_line("1 2 1000 1 ff10").is_err());
+    assert!(Record::from_line("1 2 1000 1 80 80 maybe").is_err());
+    assert!(Record::from_line("1 2 x 1 80 80 ok 1 80").is_err());
+}
+
+#[test]
+fn test_replay() {
+    let path = std::env::temp_dir().join(format!("clvm_rs_replay_{}.log", std::process::id()));
+    let path = path.to_str().unwrap().to_string();
+    let _ = std::fs::remove_file(&path);
+
+    // (+ 2 5) with (3 4) (+ is 12 in the default opcodes), (0x7f) which
+    // fails only in strict mode, and one that runs out of cost
+    let calls = [
+        ("ff0cff02ff0580", "ff03ff0480", 0, 0),
+        ("ff7f80", "80", 0, 0),
+        ("ff7f80", "80", 0, STRICT_MODE),
+        ("ff0cff02ff0580", "ff03ff0480", 100, 0),
+    ];
+    let recorder = Recorder::open(&path).unwrap();
+    for (program, env, max_cost, flags) in &calls {
+        let call = Call {
+            program: hex::decode(program).unwrap(),
+            env: hex::decode(env).unwrap(),
+            quote_kw: 1,
+            apply_kw: 2,
+            max_cost: Cost::new(*max_cost),
+            flags: *flags,
+        };
+        let outcome = run_call(&call, default_operators(&call)).unwrap();
+        recorder.record(&Record { call, outcome }).unwrap();
+    }
+    drop(recorder);
+
+    let log = std::fs::read(&path).unwrap();
+    let text = String::from_utf8(log.clone()).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[0].ends_with(" ok 856 07"));
+    assert!(lines[1].ends_with(" ok 100 80"));
+    assert!(lines[2].ends_with(" err 7f unimplemented operator"));
+    assert!(lines[3].ends_with(" cost exceeded"));
+
+    // replaying with the same operators reproduces every outcome
+    let report = replay(&log[..], default_operators).unwrap();
+    assert_eq!(report.replayed, 4);
+    assert_eq!(report.mismatches, vec![]);
+
+    // if the VM starts treating unknown operators strictly, the lenient
+    // call changes
+    let strict = |_: &Call| -> Box<dyn OperatorHandler<IntAllocator>> {
+        Box::new(HostOpTable {
+            table: op_table_for(&DEFAULT_OPCODES),
+            host: Strict,
+        })
+    };
+    let report = replay(&log[..], strict).unwrap();
+    assert_eq!(report.mismatches.len(), 1);
+    assert_eq!(report.mismatches[0].line, 2);
+    assert_eq!(
+        report.mismatches[0].replayed,
+        Outcome::Err(vec![0x7f], "unimplemented operator".to_string())
+    );
+
+    // an incomplete last line is skipped, anything else malformed fails
+    let mut truncated = log.clone();
+    truncated.extend_from_slice(b"1 2 0 0 ff10");
+    assert_eq!(
+        replay(&truncated[..], default_operators).unwrap().replayed,
+        4
+    );
+    let mut corrupt = b"garbage\n".to_vec();
+    corrupt.extend_from_slice(&log);
+    assert!(replay(&corrupt[..], default_operators).is_err());
+
+    // the process-wide recorder
+    assert!(!is_recording());
+    start_recording(&path).unwrap();
+    assert!(is_recording());
+    record_call(
+        Record::from_line(lines[0]).unwrap().call,
+        Outcome::Ok(Cost::new(856), vec![7]),
+    )
+    .unwrap();
+    stop_recording();
+    assert!(!is_recording());
+    let log = std::fs::read(&path).unwrap();
+    assert_eq!(replay(&log[..], default_operators).unwrap().replayed, 5);
+
+    std::fs::remove_file(&path).unwrap();
+}
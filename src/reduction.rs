@@ -1,9 +1,194 @@
+use alloc::string::{String, ToString};
+use core::fmt;
+
 use crate::cost::Cost;
 
+// what an `EvalErr` means for the caller. Limits and plain evaluation errors
+// are the program's fault, running out of host memory is not
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrKind {
+    Eval,
+    // a limit of the allocator, or on the steps, depth or heap of a run, was
+    // exceeded
+    LimitExceeded,
+    // the host failed to allocate memory
+    OutOfMemory,
+}
+
+// The message of an `EvalErr`. Errors are often discarded without ever being
+// displayed (e.g. when speculatively evaluating), so the common messages are
+// built from static parts and only formatted when displayed. Messages are
+// equal when they read the same, however they were built.
+#[derive(Debug, Clone)]
+pub enum ErrMsg {
+    Static(&'static str),
+    Owned(String),
+    // "<op> takes exactly <n> argument(s)"
+    ArgCount(&'static str, usize),
+    // "<op> <what>", e.g. "+ requires int args"
+    Op(&'static str, &'static str),
+}
+
+impl fmt::Display for ErrMsg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrMsg::Static(s) => f.write_str(s),
+            ErrMsg::Owned(s) => f.write_str(s),
+            ErrMsg::ArgCount(name, expected) => write!(
+                f,
+                "{} takes exactly {} argument{}",
+                name,
+                expected,
+                if *expected == 1 { "" } else { "s" }
+            ),
+            ErrMsg::Op(name, what) => write!(f, "{} {}", name, what),
+        }
+    }
+}
+
+impl From<&'static str> for ErrMsg {
+    fn from(s: &'static str) -> Self {
+        ErrMsg::Static(s)
+    }
+}
+
+impl From<String> for ErrMsg {
+    fn from(s: String) -> Self {
+        ErrMsg::Owned(s)
+    }
+}
+
+impl From<ErrMsg> for String {
+    fn from(m: ErrMsg) -> Self {
+        match m {
+            ErrMsg::Owned(s) => s,
+            m => m.to_string(),
+        }
+    }
+}
+
+// compares formatted output against a string as it's written, without
+// building the whole message
+struct PrefixMatcher<'a>(&'a str);
+
+impl fmt::Write for PrefixMatcher<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if !self.0.starts_with(s) {
+            return Err(fmt::Error);
+        }
+        self.0 = &self.0[s.len()..];
+        Ok(())
+    }
+}
+
+impl PartialEq<str> for ErrMsg {
+    fn eq(&self, other: &str) -> bool {
+        match self {
+            ErrMsg::Static(s) => *s == other,
+            ErrMsg::Owned(s) => s == other,
+            m => {
+                let mut w = PrefixMatcher(other);
+                fmt::write(&mut w, format_args!("{}", m)).is_ok() && w.0.is_empty()
+            }
+        }
+    }
+}
+
+impl PartialEq for ErrMsg {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (ErrMsg::Static(s), m) | (m, ErrMsg::Static(s)) => m == *s,
+            (ErrMsg::Owned(s), m) | (m, ErrMsg::Owned(s)) => m == s.as_str(),
+            (m, other) => m == other.to_string().as_str(),
+        }
+    }
+}
+
+impl Eq for ErrMsg {}
+
+impl PartialEq<&str> for ErrMsg {
+    fn eq(&self, other: &&str) -> bool {
+        self == *other
+    }
+}
+
+impl PartialEq<String> for ErrMsg {
+    fn eq(&self, other: &String) -> bool {
+        self == other.as_str()
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
-pub struct EvalErr<T>(pub T, pub String);
+pub struct EvalErr<T>(pub T, pub ErrMsg, pub ErrKind);
+
+impl ErrKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrKind::Eval => "eval",
+            ErrKind::LimitExceeded => "limit_exceeded",
+            ErrKind::OutOfMemory => "out_of_memory",
+        }
+    }
+}
+
+impl<T> EvalErr<T> {
+    pub fn new(node: T, msg: impl Into<ErrMsg>) -> Self {
+        EvalErr(node, msg.into(), ErrKind::Eval)
+    }
+
+    pub fn kind(&self) -> ErrKind {
+        self.2
+    }
+
+    // true if the error is caused by the environment rather than the program
+    pub fn is_operational(&self) -> bool {
+        self.2 == ErrKind::OutOfMemory
+    }
+}
 
 #[derive(Debug, PartialEq)]
 pub struct Reduction<T>(pub Cost, pub T);
 
 pub type Response<T> = Result<Reduction<T>, EvalErr<T>>;
+
+#[test]
+fn test_err_msg() {
+    assert_eq!(
+        ErrMsg::ArgCount("f", 1).to_string(),
+        "f takes exactly 1 argument"
+    );
+    assert_eq!(
+        ErrMsg::ArgCount("c", 2).to_string(),
+        "c takes exactly 2 arguments"
+    );
+    assert_eq!(ErrMsg::Op("+", "requires int args"), "+ requires int args");
+    assert_eq!(ErrMsg::from("clvm raise"), "clvm raise");
+    assert_eq!(ErrMsg::from("x".to_string()), "x".to_string());
+    assert!(ErrMsg::Static("a") != "b");
+    assert!(ErrMsg::Op("+", "on list") != "+ on");
+    assert!(ErrMsg::Op("+", "on") != "+ on list");
+
+    let s: String = ErrMsg::Op("sha256", "on list").into();
+    assert_eq!(s, "sha256 on list");
+
+    // messages compare by their text, not by how they were built
+    let msgs = [
+        ErrMsg::Static("f takes exactly 1 argument"),
+        ErrMsg::Owned("f takes exactly 1 argument".to_string()),
+        ErrMsg::ArgCount("f", 1),
+        ErrMsg::Op("f", "takes exactly 1 argument"),
+        ErrMsg::Op("f takes", "exactly 1 argument"),
+    ];
+    for a in &msgs {
+        for b in &msgs {
+            assert_eq!(a, b);
+        }
+        assert!(*a != ErrMsg::ArgCount("f", 2));
+        assert!(*a != ErrMsg::Op("f", "takes exactly 1"));
+        assert!(*a != ErrMsg::Static("f takes exactly 1 argument "));
+    }
+    assert_eq!(
+        EvalErr::new(0, ErrMsg::Op("+", "on list")),
+        EvalErr::new(0, "+ on list")
+    );
+}
@@ -0,0 +1,269 @@
+// A deliberately simple implementation of the integer operators, used to
+// cross-check the optimized ones in more_ops. Everything here goes through
+// bigints, and results are made canonical by stripping bytes one at a time.
+// It's only compiled with the "testing" feature, so downstream conformance
+// suites can run the same checks.
+use crate::allocator::{Allocator, SExp};
+use crate::cost::Cost;
+use crate::int_allocator::IntAllocator;
+use crate::more_ops::{op_add, op_div, op_div_floor, op_divmod, op_gr, op_multiply, op_subtract};
+use crate::number::{is_canonical_int, number_from_u8, number_to_signed_bytes, Number};
+use crate::reduction::Response;
+
+fn to_atom(n: &Number) -> Vec<u8> {
+    let bytes = if *n == Number::from(0) {
+        vec![]
+    } else {
+        number_to_signed_bytes(n)
+    };
+    let mut slice = bytes.as_slice();
+    while !is_canonical_int(slice) {
+        slice = &slice[1..];
+    }
+    slice.to_vec()
+}
+
+fn floor_divmod(a: &Number, b: &Number) -> (Number, Number) {
+    let zero = Number::from(0);
+    let mut q = a / b;
+    let mut r = a - b * &q;
+    // truncating division rounds towards zero. When the remainder has the
+    // opposite sign of the divisor, step down once to round towards -inf
+    if r != zero && ((r < zero) != (*b < zero)) {
+        q -= 1;
+        r += b;
+    }
+    (q, r)
+}
+
+// op_div decides whether to round down by looking at the sign of the
+// truncated quotient. When that quotient is 0 (the operands have opposite
+// signs and |a| < |b|) it returns 0 where floor division gives -1. Changing
+// that would change consensus, so the harness tolerates this one case.
+// op_div_floor, for dialects that opt in, is checked without it
+pub fn div_rounds_to_zero(a: &[u8], b: &[u8]) -> bool {
+    let a = number_from_u8(a);
+    let b = number_from_u8(b);
+    let zero = Number::from(0);
+    b != zero && a != zero && &a / &b == zero && ((a < zero) != (b < zero))
+}
+
+pub fn ref_add(args: &[&[u8]]) -> Vec<u8> {
+    let mut total = Number::from(0);
+    for a in args {
+        total += number_from_u8(a);
+    }
+    to_atom(&total)
+}
+
+pub fn ref_subtract(args: &[&[u8]]) -> Vec<u8> {
+    let mut total = Number::from(0);
+    for (i, a) in args.iter().enumerate() {
+        if i == 0 {
+            total += number_from_u8(a);
+        } else {
+            total -= number_from_u8(a);
+        }
+    }
+    to_atom(&total)
+}
+
+pub fn ref_multiply(args: &[&[u8]]) -> Vec<u8> {
+    let mut total = Number::from(1);
+    for a in args {
+        total *= number_from_u8(a);
+    }
+    to_atom(&total)
+}
+
+// None means division by zero
+pub fn ref_div(a: &[u8], b: &[u8]) -> Option<Vec<u8>> {
+    let b = number_from_u8(b);
+    if b == Number::from(0) {
+        return None;
+    }
+    Some(to_atom(&floor_divmod(&number_from_u8(a), &b).0))
+}
+
+pub fn ref_divmod(a: &[u8], b: &[u8]) -> Option<(Vec<u8>, Vec<u8>)> {
+    let b = number_from_u8(b);
+    if b == Number::from(0) {
+        return None;
+    }
+    let (q, r) = floor_divmod(&number_from_u8(a), &b);
+    Some((to_atom(&q), to_atom(&r)))
+}
+
+pub fn ref_gr(a: &[u8], b: &[u8]) -> bool {
+    number_from_u8(a) > number_from_u8(b)
+}
+
+// a small xorshift generator, so the harness doesn't need a rand dependency
+// and every failure is reproducible from its seed
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed | 1)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    // integer atoms biased towards the interesting cases: empty, sign
+    // boundaries, redundant leading bytes and lengths around 8 and 16 bytes
+    pub fn atom(&mut self) -> Vec<u8> {
+        let len = match self.below(4) {
+            0 => self.below(3),
+            1 => 7 + self.below(3),
+            2 => 15 + self.below(3),
+            _ => self.below(40),
+        };
+        (0..len)
+            .map(|i| match self.below(6) {
+                0 => 0x00,
+                1 => 0xff,
+                2 if i == 0 => 0x80,
+                3 if i == 0 => 0x7f,
+                _ => self.next_u64() as u8,
+            })
+            .collect()
+    }
+}
+
+type OpFn = fn(&mut IntAllocator, i32, Cost) -> Response<i32>;
+
+fn run_op(op: OpFn, args: &[&[u8]]) -> Option<(IntAllocator, i32)> {
+    let mut a = IntAllocator::new();
+    let mut list = a.null();
+    for arg in args.iter().rev() {
+        let atom = a.new_atom(arg).ok()?;
+        list = a.new_pair(atom, list).ok()?;
+    }
+    let r = op(&mut a, list, Cost::MAX).ok()?;
+    Some((a, r.1))
+}
+
+fn atom_of(a: &IntAllocator, node: i32) -> Vec<u8> {
+    a.atom(&node).to_vec()
+}
+
+fn mismatch(name: &str, args: &[&[u8]], expected: &str, got: &str) -> String {
+    let args: Vec<String> = args.iter().map(hex::encode).collect();
+    format!(
+        "{} ({}): expected {} got {}",
+        name,
+        args.join(" "),
+        expected,
+        got
+    )
+}
+
+// run `iterations` random operations through both the production operators
+// and the reference implementation, returning a description of the first
+// disagreement
+pub fn cross_check(seed: u64, iterations: usize) -> Result<(), String> {
+    let mut rng = Rng::new(seed);
+    for _ in 0..iterations {
+        let count = 1 + rng.below(4);
+        let owned: Vec<Vec<u8>> = (0..count).map(|_| rng.atom()).collect();
+        let args: Vec<&[u8]> = owned.iter().map(|v| v.as_slice()).collect();
+
+        for (name, op, expected) in &[
+            ("+", op_add as OpFn, ref_add(&args)),
+            ("-", op_subtract as OpFn, ref_subtract(&args)),
+            ("*", op_multiply as OpFn, ref_multiply(&args)),
+        ] {
+            let got = run_op(*op, &args).map(|(a, r)| atom_of(&a, r));
+            if got.as_ref() != Some(expected) {
+                return Err(mismatch(
+                    name,
+                    &args,
+                    &hex::encode(expected),
+                    &format!("{:?}", got.map(hex::encode)),
+                ));
+            }
+        }
+
+        let pair = [args[0], *args.last().unwrap()];
+
+        let expected = ref_div(pair[0], pair[1]);
+        let got = run_op(op_div, &pair).map(|(a, r)| atom_of(&a, r));
+        if got != expected && !div_rounds_to_zero(pair[0], pair[1]) {
+            return Err(mismatch(
+                "/",
+                &pair,
+                &format!("{:?}", expected),
+                &format!("{:?}", got),
+            ));
+        }
+
+        let got = run_op(op_div_floor, &pair).map(|(a, r)| atom_of(&a, r));
+        if got != expected {
+            return Err(mismatch(
+                "/ (floor)",
+                &pair,
+                &format!("{:?}", expected),
+                &format!("{:?}", got),
+            ));
+        }
+
+        let expected = ref_divmod(pair[0], pair[1]);
+        let got = run_op(op_divmod, &pair).and_then(|(a, r)| match a.sexp(&r) {
+            SExp::Pair(q, r) => Some((atom_of(&a, q), atom_of(&a, r))),
+            SExp::Atom(_) => None,
+        });
+        if got != expected {
+            return Err(mismatch(
+                "divmod",
+                &pair,
+                &format!("{:?}", expected),
+                &format!("{:?}", got),
+            ));
+        }
+
+        let expected = ref_gr(pair[0], pair[1]);
+        let got = run_op(op_gr, &pair).map(|(a, r)| !a.atom(&r).is_empty());
+        if got != Some(expected) {
+            return Err(mismatch(
+                ">",
+                &pair,
+                &expected.to_string(),
+                &format!("{:?}", got),
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_reference_basics() {
+    assert_eq!(ref_add(&[]), Vec::<u8>::new());
+    assert_eq!(ref_add(&[&[0x7f], &[1]]), vec![0x00, 0x80]);
+    assert_eq!(ref_subtract(&[&[1], &[2]]), vec![0xff]);
+    assert_eq!(ref_multiply(&[]), vec![1]);
+    assert_eq!(ref_multiply(&[&[0xff], &[0xff]]), vec![1]);
+    assert_eq!(ref_div(&[7], &[0xfe]), Some(vec![0xfc]));
+    assert_eq!(ref_div(&[7], &[]), None);
+    assert_eq!(ref_divmod(&[0xf9], &[2]), Some((vec![0xfc], vec![1])));
+    assert!(ref_gr(&[0, 0x80], &[0x7f]));
+
+    assert!(div_rounds_to_zero(&[0xff], &[2]));
+    assert!(!div_rounds_to_zero(&[0xfd], &[2]));
+    assert!(!div_rounds_to_zero(&[1], &[2]));
+}
+
+#[test]
+fn test_cross_check() {
+    for seed in 1..5 {
+        cross_check(seed, 2000).unwrap();
+    }
+}
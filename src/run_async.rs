@@ -0,0 +1,189 @@
+// Runs programs from async code without blocking the executor: evaluation
+// happens on tokio's blocking thread pool (spawn_blocking()). The program,
+// arguments and result are passed serialized, so nothing borrowed from an
+// allocator has to cross threads. Dropping the future doesn't stop a blocking
+// task, so a program that should stop early is cancelled through its
+// CancelToken, which is checked at every step
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::cost::Cost;
+use crate::int_allocator::IntAllocator;
+use crate::node::Node;
+use crate::op_table::{op_table_for, CHIA_DIALECT};
+use crate::run_program::{run_program_with_params, FuelMeter, RunParams};
+use crate::serialize::{node_from_bytes, node_to_bytes, SerializeError};
+
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // stops every run holding a clone of this token, at its next step
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl FuelMeter for CancelToken {
+    fn consume(&mut self, _cost: Cost) -> bool {
+        !self.is_cancelled()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RunOutput {
+    pub cost: Cost,
+    // serialized
+    pub result: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RunError {
+    // the program or arguments aren't a serialized tree
+    Deserialize(String),
+    // evaluation failed on this (serialized) node
+    Eval { node: Vec<u8>, msg: String },
+    // the token was cancelled before evaluation finished
+    Cancelled,
+    // the blocking task panicked, or the runtime shut down under it
+    Join(String),
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Deserialize(msg) => write!(f, "deserialization failed: {}", msg),
+            Self::Eval { node, msg } => write!(f, "{}: {}", msg, hex::encode(node)),
+            Self::Cancelled => write!(f, "cancelled"),
+            Self::Join(msg) => write!(f, "evaluation task failed: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for RunError {}
+
+// the blocking part, with the chia dialect (quote is 1 and apply is 2)
+pub fn run_program_serialized(
+    program: &[u8],
+    args: &[u8],
+    max_cost: Cost,
+    mut token: CancelToken,
+) -> Result<RunOutput, RunError> {
+    let mut a = IntAllocator::new();
+    let deserialize_err = |e: SerializeError| RunError::Deserialize(e.to_string());
+    let program = node_from_bytes(&mut a, program).map_err(deserialize_err)?;
+    let args = node_from_bytes(&mut a, args).map_err(deserialize_err)?;
+    let r = run_program_with_params(
+        &mut a,
+        &program,
+        &args,
+        Box::new(op_table_for(CHIA_DIALECT.opcodes)),
+        None,
+        RunParams::new()
+            .with_max_cost(max_cost)
+            .with_fuel(&mut token),
+    );
+    match r {
+        Ok(r) => Ok(RunOutput {
+            cost: r.0,
+            result: node_to_bytes(&Node::new(&a, r.1)).unwrap(),
+        }),
+        Err(_) if token.is_cancelled() => Err(RunError::Cancelled),
+        Err(e) => Err(RunError::Eval {
+            node: node_to_bytes(&Node::new(&a, e.0)).unwrap(),
+            msg: e.1.to_string(),
+        }),
+    }
+}
+
+// like run_program_serialized(), on the blocking thread pool of the current
+// tokio runtime
+pub async fn run_program_async(
+    program: Vec<u8>,
+    args: Vec<u8>,
+    max_cost: Cost,
+    token: CancelToken,
+) -> Result<RunOutput, RunError> {
+    tokio::task::spawn_blocking(move || run_program_serialized(&program, &args, max_cost, token))
+        .await
+        .map_err(|e| RunError::Join(e.to_string()))?
+}
+
+#[cfg(test)]
+fn block_on<F: std::future::Future>(f: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap()
+        .block_on(f)
+}
+
+#[test]
+fn test_run_program_async() {
+    // (+ 2 5) with (3 4)
+    let program = hex::decode("ff10ff02ff0580").unwrap();
+    let args = hex::decode("ff03ff0480").unwrap();
+    let r = block_on(run_program_async(
+        program.clone(),
+        args.clone(),
+        Cost::ZERO,
+        CancelToken::new(),
+    ))
+    .unwrap();
+    assert_eq!(r.result, [0x07]);
+    assert_eq!(
+        r,
+        run_program_serialized(&program, &args, Cost::ZERO, CancelToken::new()).unwrap()
+    );
+
+    // (x 2) raises with its arguments, (3)
+    let program = hex::decode("ff08ff0280").unwrap();
+    let r = block_on(run_program_async(
+        program,
+        args.clone(),
+        Cost::ZERO,
+        CancelToken::new(),
+    ));
+    assert_eq!(
+        r,
+        Err(RunError::Eval {
+            node: hex::decode("ff0380").unwrap(),
+            msg: "clvm raise".to_string()
+        })
+    );
+
+    let r = block_on(run_program_async(
+        vec![0xff],
+        args,
+        Cost::ZERO,
+        CancelToken::new(),
+    ));
+    assert!(matches!(r, Err(RunError::Deserialize(_))));
+}
+
+#[test]
+fn test_cancel() {
+    // a program that applies itself forever: (a 1 1) with itself as args
+    let program = hex::decode("ff02ff01ff0180").unwrap();
+    let token = CancelToken::new();
+    let run = {
+        let token = token.clone();
+        std::thread::spawn(move || run_program_serialized(&program, &program, Cost::ZERO, token))
+    };
+    std::thread::sleep(std::time::Duration::from_millis(50));
+    token.cancel();
+    assert_eq!(run.join().unwrap(), Err(RunError::Cancelled));
+
+    // a token that's already cancelled stops the first step
+    let r = block_on(run_program_async(vec![0x01], vec![0x80], Cost::ZERO, token));
+    assert_eq!(r, Err(RunError::Cancelled));
+}
@@ -1,30 +1,130 @@
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+
 use crate::allocator::{Allocator, SExp};
 use crate::cost::Cost;
-use crate::err_utils::err;
+use crate::err_utils::{err, limit_err, ErrReport};
 use crate::node::Node;
+use crate::op_table::CostTable;
 use crate::reduction::{EvalErr, Reduction, Response};
+#[cfg(feature = "std")]
+use crate::symbols::SymbolTable;
+use crate::tree_hash::tree_hash;
 
-use crate::number::{ptr_from_number, Number};
+use crate::number::Number;
 
 // lowered from 46
-const QUOTE_COST: Cost = 20;
+const QUOTE_COST: Cost = Cost::new(20);
 // lowered from 138
-const APPLY_COST: Cost = 90;
+const APPLY_COST: Cost = Cost::new(90);
 
 // lowered from measured 147 per bit. It doesn't seem to take this long in
 // practice
-const TRAVERSE_BASE_COST: Cost = 40;
-const TRAVERSE_COST_PER_ZERO_BYTE: Cost = 4;
-const TRAVERSE_COST_PER_BIT: Cost = 4;
+const TRAVERSE_BASE_COST: Cost = Cost::new(40);
+const TRAVERSE_COST_PER_ZERO_BYTE: Cost = Cost::new(4);
+const TRAVERSE_COST_PER_BIT: Cost = Cost::new(4);
+
+// with the tracing feature, an event is emitted each time the cost passes a
+// multiple of this, so slow programs show their progress
+#[cfg(feature = "tracing")]
+const TRACE_COST_INTERVAL: u64 = 100_000_000;
 
+// Runs the operators. It's mutable, so a handler can keep state (counters,
+// caches, rate limits) across the operators of a run without RefCells
 pub trait OperatorHandler<T: Allocator> {
     fn op(
-        &self,
+        &mut self,
         allocator: &mut T,
         op: <T as Allocator>::AtomBuf,
         args: &<T as Allocator>::Ptr,
         max_cost: Cost,
     ) -> Response<<T as Allocator>::Ptr>;
+
+    // like op(), with the settings of the run that apply to operators (see
+    // OpParams). The evaluator calls this one. Handlers that don't apply them
+    // leave it to call op()
+    fn op_with_params(
+        &mut self,
+        allocator: &mut T,
+        op: <T as Allocator>::AtomBuf,
+        args: &<T as Allocator>::Ptr,
+        max_cost: Cost,
+        _params: &OpParams,
+    ) -> Response<<T as Allocator>::Ptr> {
+        self.op(allocator, op, args, max_cost)
+    }
+}
+
+// a RunParams flag: fail on unknown operators rather than treating them as
+// no-ops
+pub const STRICT_MODE: u32 = 1;
+
+// the settings of RunParams that apply to individual operators rather than to
+// the evaluator. The defaults are what run_program() uses
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OpParams {
+    // the largest integer, in bytes, that *, ash and lsh may produce. They
+    // fail before computing a larger one. 0 is no limit
+    pub max_int_bytes: usize,
+    // base costs to charge instead of the operators' own, by opcode
+    pub cost_table: CostTable,
+    // unknown operators fail with "unimplemented operator", see STRICT_MODE
+    pub strict: bool,
+}
+
+// a handler can also be lent to run_program(), as Box::new(&mut handler), to
+// look at its state once the run is done
+impl<T: Allocator, H: OperatorHandler<T> + ?Sized> OperatorHandler<T> for &mut H {
+    fn op(
+        &mut self,
+        allocator: &mut T,
+        op: <T as Allocator>::AtomBuf,
+        args: &<T as Allocator>::Ptr,
+        max_cost: Cost,
+    ) -> Response<<T as Allocator>::Ptr> {
+        (**self).op(allocator, op, args, max_cost)
+    }
+
+    fn op_with_params(
+        &mut self,
+        allocator: &mut T,
+        op: <T as Allocator>::AtomBuf,
+        args: &<T as Allocator>::Ptr,
+        max_cost: Cost,
+        params: &OpParams,
+    ) -> Response<<T as Allocator>::Ptr> {
+        (**self).op_with_params(allocator, op, args, max_cost, params)
+    }
+}
+
+// Meters evaluation against a host's own budget, like the fuel of a WASM
+// runtime. consume() is called with the cost of every step as it's charged,
+// so the fuel spent is exactly the CLVM cost, and a host that returns false
+// stops evaluation at the same step on every run
+pub trait FuelMeter {
+    fn consume(&mut self, cost: Cost) -> bool;
+}
+
+// Told where the cost of evaluation goes, for profiling. enter() and leave()
+// bracket the top level program and every program invoked via apply (the cost
+// of the apply itself is charged inside the program it enters). charge() is
+// called with the cost of every step, along with the opcode of the operator
+// (or quote) it ran, if any
+pub trait CostTracer<P> {
+    fn enter(&mut self, program: &P);
+    fn leave(&mut self);
+    fn charge(&mut self, opcode: Option<&[u8]>, cost: Cost);
+
+    // whether to call reduction(). It's off by default, since the arguments
+    // and result of every operator are tree hashed for it
+    fn wants_reductions(&self) -> bool {
+        false
+    }
+
+    // an operator (other than apply) returned, with the tree hashes of its
+    // arguments and result. The same step is then charge()d
+    fn reduction(&mut self, _opcode: &[u8], _args: &[u8; 32], _result: &[u8; 32], _cost: Cost) {}
 }
 
 pub type PreEval<A> = Box<
@@ -37,27 +137,44 @@ pub type PreEval<A> = Box<
 
 pub type PostEval<T> = dyn Fn(Option<&<T as Allocator>::Ptr>);
 
-#[repr(u8)]
-enum Operation {
+// the operations are decoded when they're pushed, so an Eval carries the
+// program and its arguments rather than a (program . args) pair allocated on
+// the value stack
+enum Operation<P> {
+    // call the operator below the operand list on the value stack
     Apply,
+    // pop a value and prepend it to the list below it
     Cons,
-    Eval,
-    Swap,
+    // evaluate a program with the given arguments, and push the result
+    Eval(P, P),
     PostEval,
+    PopFrame,
 }
 
 // `run_program` has two stacks: the operand stack (of `Node` objects) and the
-// operator stack (of RpcOperators)
+// operator stack (of Operations)
 
 pub struct RunProgramContext<'a, T: Allocator> {
     allocator: &'a mut T,
     quote_kw: u8,
     apply_kw: u8,
-    operator_lookup: Box<dyn OperatorHandler<T>>,
+    operator_lookup: Box<dyn OperatorHandler<T> + 'a>,
     pre_eval: Option<PreEval<T>>,
     posteval_stack: Vec<Box<PostEval<T>>>,
     val_stack: Vec<T::Ptr>,
-    op_stack: Vec<Operation>,
+    op_stack: Vec<Operation<T::Ptr>>,
+    // the programs invoked via apply that haven't returned yet, outermost
+    // first. Only tracked when we need to render a backtrace
+    frames: Option<Vec<T::Ptr>>,
+    // the cost charged so far, including when evaluation fails
+    cost: Cost,
+    fuel: Option<&'a mut dyn FuelMeter>,
+    tracer: Option<&'a mut dyn CostTracer<T::Ptr>>,
+    // the limits of RunParams other than the cost, 0 if there are none
+    max_depth: usize,
+    max_heap: usize,
+    max_steps: u64,
+    op_params: OpParams,
 }
 
 impl<'a, 'h, T: Allocator> RunProgramContext<'a, T> {
@@ -96,7 +213,7 @@ const fn first_non_zero(buf: &[u8]) -> usize {
     c
 }
 
-fn traverse_path<T: Allocator>(
+pub(crate) fn traverse_path<T: Allocator>(
     allocator: &T,
     node_index: &[u8],
     args: &T::Ptr,
@@ -107,7 +224,7 @@ fn traverse_path<T: Allocator>(
     let first_bit_byte_index = first_non_zero(node_index);
 
     let mut cost: Cost = TRAVERSE_BASE_COST
-        + (first_bit_byte_index as Cost) * TRAVERSE_COST_PER_ZERO_BYTE
+        + TRAVERSE_COST_PER_ZERO_BYTE * first_bit_byte_index
         + TRAVERSE_COST_PER_BIT;
 
     if first_bit_byte_index >= node_index.len() {
@@ -124,7 +241,7 @@ fn traverse_path<T: Allocator>(
         let is_bit_set: bool = (node_index[byte_idx] & bitmask) != 0;
         match allocator.sexp(&arg_list) {
             SExp::Atom(_) => {
-                return Err(EvalErr(arg_list, "path into atom".into()));
+                return Err(EvalErr::new(arg_list, "path into atom"));
             }
             SExp::Pair(left, right) => {
                 arg_list = (if is_bit_set { &right } else { &left }).clone();
@@ -149,10 +266,10 @@ fn augment_cost_errors<P: Clone>(
         return r;
     }
     let e = r.unwrap_err();
-    if &e.1 != "cost exceeded" {
+    if e.1 != "cost exceeded" {
         Err(e)
     } else {
-        Err(EvalErr(max_cost.clone(), e.1))
+        Err(EvalErr(max_cost.clone(), e.1, e.2))
     }
 }
 
@@ -161,7 +278,7 @@ impl<'a, 'h, T: Allocator> RunProgramContext<'a, T> {
         allocator: &'a mut T,
         quote_kw: u8,
         apply_kw: u8,
-        operator_lookup: Box<dyn OperatorHandler<T>>,
+        operator_lookup: Box<dyn OperatorHandler<T> + 'a>,
         pre_eval: Option<PreEval<T>>,
     ) -> Self {
         RunProgramContext {
@@ -173,25 +290,24 @@ impl<'a, 'h, T: Allocator> RunProgramContext<'a, T> {
             posteval_stack: Vec::new(),
             val_stack: Vec::new(),
             op_stack: Vec::new(),
+            frames: None,
+            cost: Cost::ZERO,
+            fuel: None,
+            tracer: None,
+            max_depth: 0,
+            max_heap: 0,
+            max_steps: 0,
+            op_params: OpParams::default(),
         }
     }
 
-    fn swap_op(&mut self) -> Result<Cost, EvalErr<T::Ptr>> {
-        /* Swap the top two operands. */
-        let v2 = self.pop()?;
-        let v1 = self.pop()?;
-        self.push(v2);
-        self.push(v1);
-        Ok(0)
-    }
-
     fn cons_op(&mut self) -> Result<Cost, EvalErr<T::Ptr>> {
         /* Join the top two operands. */
         let v1 = self.pop()?;
         let v2 = self.pop()?;
         let p = self.allocator.new_pair(v1, v2)?;
         self.push(p);
-        Ok(0)
+        Ok(Cost::ZERO)
     }
 }
 
@@ -206,33 +322,29 @@ where
         operand_list: &T::Ptr,
         args: &T::Ptr,
     ) -> Result<Cost, EvalErr<T::Ptr>> {
-        let op_atom = self.allocator.buf(op_buf);
         // special case check for quote
-        if op_atom.len() == 1 && op_atom[0] == self.quote_kw {
+        if self.allocator.atom_byte(op_buf) == Some(self.quote_kw) {
             self.push(operand_list.clone());
             Ok(QUOTE_COST)
         } else {
+            // the operands are evaluated last to first, each one consed onto
+            // the list of the ones after it
             self.op_stack.push(Operation::Apply);
             self.push(operator_node.clone());
+            self.push(self.allocator.null());
             let mut operands: T::Ptr = operand_list.clone();
             loop {
-                if Node::new(self.allocator, operands.clone()).nullp() {
-                    break;
-                }
-                self.op_stack.push(Operation::Cons);
-                self.op_stack.push(Operation::Eval);
-                self.op_stack.push(Operation::Swap);
                 match self.allocator.sexp(&operands) {
-                    SExp::Atom(_) => return err(operand_list.clone(), "bad operand list"),
                     SExp::Pair(first, rest) => {
-                        let new_pair = self.allocator.new_pair(first, args.clone())?;
-                        self.push(new_pair);
-                        operands = rest.clone();
+                        self.op_stack.push(Operation::Cons);
+                        self.op_stack.push(Operation::Eval(first, args.clone()));
+                        operands = rest;
                     }
+                    SExp::Atom(ref a) if self.allocator.buf(a).is_empty() => break,
+                    SExp::Atom(_) => return err(operand_list.clone(), "bad operand list"),
                 }
             }
-            self.push(self.allocator.null());
-            Ok(1)
+            Ok(Cost::new(1))
         }
     }
 
@@ -269,28 +381,21 @@ where
         self.eval_op_atom(&op_atom, &op_node, &op_list, args)
     }
 
-    fn eval_op(&mut self) -> Result<Cost, EvalErr<T::Ptr>> {
+    fn eval_op(&mut self, program: T::Ptr, args: T::Ptr) -> Result<Cost, EvalErr<T::Ptr>> {
         /*
-        Pop the top value and treat it as a (program, args) pair, and manipulate
-        the op & value stack to evaluate all the arguments and apply the operator.
+        Manipulate the op & value stack to evaluate all the arguments of the
+        program and apply the operator.
         */
+        let post_eval = match self.pre_eval {
+            None => None,
+            Some(ref pre_eval) => pre_eval(&mut self.allocator, &program, &args)?,
+        };
+        if let Some(post_eval) = post_eval {
+            self.posteval_stack.push(post_eval);
+            self.op_stack.push(Operation::PostEval);
+        };
 
-        let pair: T::Ptr = self.pop()?;
-        match self.allocator.sexp(&pair) {
-            SExp::Atom(_) => err(pair, "pair expected"),
-            SExp::Pair(program, args) => {
-                let post_eval = match self.pre_eval {
-                    None => None,
-                    Some(ref pre_eval) => pre_eval(&mut self.allocator, &program, &args)?,
-                };
-                if let Some(post_eval) = post_eval {
-                    self.posteval_stack.push(post_eval);
-                    self.op_stack.push(Operation::PostEval);
-                };
-
-                self.eval_pair(&program, &args)
-            }
-        }
+        self.eval_pair(&program, &args)
     }
 
     fn apply_op(&mut self, max_cost: Cost) -> Result<Cost, EvalErr<T::Ptr>> {
@@ -298,28 +403,49 @@ where
         let operator = self.pop()?;
         let opa = match self.allocator.sexp(&operator) {
             SExp::Pair(_, _) => {
-                return Err(EvalErr(operator, "internal error".into()));
+                return Err(EvalErr::new(operator, "internal error"));
             }
             SExp::Atom(opa) => opa,
         };
-        let op_atom = self.allocator.buf(&opa);
-        if op_atom.len() == 1 && op_atom[0] == self.apply_kw {
+        if self.allocator.atom_byte(&opa) == Some(self.apply_kw) {
             let operand_list = Node::new(self.allocator, operand_list);
             if operand_list.arg_count_is(2) {
                 let new_operator = operand_list.first()?;
                 let new_program = new_operator.node;
                 let new_args = operand_list.rest()?.first()?.node;
-                let new_pair = self.allocator.new_pair(new_program, new_args)?;
-                self.push(new_pair);
-                self.op_stack.push(Operation::Eval);
+                if let Some(frames) = &mut self.frames {
+                    frames.push(new_program.clone());
+                }
+                if let Some(tracer) = &mut self.tracer {
+                    tracer.enter(&new_program);
+                }
+                if self.frames.is_some() || self.tracer.is_some() {
+                    self.op_stack.push(Operation::PopFrame);
+                }
+                self.op_stack.push(Operation::Eval(new_program, new_args));
                 Ok(APPLY_COST)
             } else {
                 operand_list.err("apply requires exactly 2 parameters")
             }
         } else {
-            let r = self
-                .operator_lookup
-                .op(self.allocator, opa, &operand_list, max_cost)?;
+            let traced = match &self.tracer {
+                Some(tracer) if tracer.wants_reductions() => {
+                    Some(self.allocator.buf(&opa).to_vec())
+                }
+                _ => None,
+            };
+            let r = self.operator_lookup.op_with_params(
+                self.allocator,
+                opa,
+                &operand_list,
+                max_cost,
+                &self.op_params,
+            )?;
+            if let (Some(opcode), Some(tracer)) = (traced, &mut self.tracer) {
+                let args = tree_hash(&Node::new(self.allocator, operand_list));
+                let result = tree_hash(&Node::new(self.allocator, r.1.clone()));
+                tracer.reduction(&opcode, &args, &result, r.0);
+            }
             self.push(r.1);
             Ok(r.0)
         }
@@ -332,17 +458,52 @@ where
         args: &T::Ptr,
         max_cost: Cost,
     ) -> Response<T::Ptr> {
-        self.val_stack = vec![self.allocator.new_pair(program.clone(), args.clone())?];
-        self.op_stack = vec![Operation::Eval];
+        #[cfg(feature = "metrics")]
+        crate::metrics::record(|m| m.evaluation_started());
+        let r = self.run_program_inner(program, args, max_cost);
+        #[cfg(feature = "metrics")]
+        crate::metrics::record(|m| {
+            match &r {
+                Ok(_) => m.evaluation_completed(self.cost),
+                Err(_) => m.evaluation_failed(self.cost),
+            }
+            if let Some(bytes) = self.allocator.arena_bytes() {
+                m.arena_bytes(bytes);
+            }
+        });
+        r
+    }
+
+    fn run_program_inner(
+        &mut self,
+        program: &T::Ptr,
+        args: &T::Ptr,
+        max_cost: Cost,
+    ) -> Response<T::Ptr> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("run_program", max_cost = max_cost.get()).entered();
+        self.val_stack = Vec::new();
+        self.op_stack = vec![Operation::Eval(program.clone(), args.clone())];
+        if let Some(frames) = &mut self.frames {
+            *frames = vec![program.clone()];
+        }
+        if let Some(tracer) = &mut self.tracer {
+            tracer.enter(program);
+        }
 
         // max_cost is always in effect, and necessary to prevent wrap-around of
         // the cost integer.
-        let max_cost = if max_cost == 0 { Cost::MAX } else { max_cost };
+        let max_cost = if max_cost == Cost::ZERO {
+            Cost::MAX
+        } else {
+            max_cost
+        };
 
-        let max_cost_number: Number = max_cost.into();
-        let max_cost_ptr = ptr_from_number(self.allocator, &max_cost_number)?;
+        let max_cost_number: Number = max_cost.get().into();
+        let max_cost_ptr = self.allocator.new_number(max_cost_number)?;
 
-        let mut cost: Cost = 0;
+        self.cost = Cost::ZERO;
+        let mut steps: u64 = 0;
 
         loop {
             let top = self.op_stack.pop();
@@ -350,25 +511,99 @@ where
                 Some(f) => f,
                 None => break,
             };
-            cost += match op {
-                Operation::Apply => {
-                    augment_cost_errors(self.apply_op(max_cost - cost), &max_cost_ptr)?
-                }
+            steps += 1;
+            if self.max_steps != 0 && steps > self.max_steps {
+                return limit_err(self.allocator.null(), "too many steps");
+            }
+            let opcode = match self.tracer {
+                Some(_) => self.traced_opcode(&op),
+                None => None,
+            };
+            let step = match op {
+                Operation::Apply => augment_cost_errors(
+                    self.apply_op(max_cost.saturating_sub(self.cost)),
+                    &max_cost_ptr,
+                )?,
                 Operation::Cons => self.cons_op()?,
-                Operation::Eval => augment_cost_errors(self.eval_op(), &max_cost_ptr)?,
-                Operation::Swap => self.swap_op()?,
+                Operation::Eval(program, args) => {
+                    augment_cost_errors(self.eval_op(program, args), &max_cost_ptr)?
+                }
                 Operation::PostEval => {
                     let f = self.posteval_stack.pop().unwrap();
                     let peek: Option<&T::Ptr> = self.val_stack.last();
                     f(peek);
-                    0
+                    Cost::ZERO
+                }
+                Operation::PopFrame => {
+                    if let Some(frames) = &mut self.frames {
+                        frames.pop();
+                    }
+                    if let Some(tracer) = &mut self.tracer {
+                        tracer.leave();
+                    }
+                    Cost::ZERO
                 }
             };
-            if cost > max_cost {
-                return Err(EvalErr(max_cost_ptr, "cost exceeded".into()));
+            if let Some(tracer) = &mut self.tracer {
+                tracer.charge(opcode.as_deref(), step);
+            }
+            self.cost = self.cost.saturating_add(step);
+            #[cfg(feature = "tracing")]
+            if self.cost / TRACE_COST_INTERVAL
+                != self.cost.saturating_sub(step) / TRACE_COST_INTERVAL
+            {
+                tracing::trace!(cost = self.cost.get(), "cost checkpoint");
+            }
+            // a saturated cost is exceeded even when there's no limit
+            if self.cost > max_cost || self.cost == Cost::MAX {
+                return Err(EvalErr::new(max_cost_ptr, "cost exceeded"));
+            }
+            if let Some(fuel) = &mut self.fuel {
+                if !fuel.consume(step) {
+                    return err(self.allocator.null(), "fuel exhausted");
+                }
+            }
+            if self.max_depth != 0 && self.op_stack.len() > self.max_depth {
+                return limit_err(self.allocator.null(), "max depth exceeded");
+            }
+            if self.max_heap != 0 {
+                if let Some(bytes) = self.allocator.arena_bytes() {
+                    if bytes > self.max_heap {
+                        return limit_err(self.allocator.null(), "max heap exceeded");
+                    }
+                }
             }
         }
-        Ok(Reduction(cost, self.pop()?))
+        if let Some(tracer) = &mut self.tracer {
+            tracer.leave();
+        }
+        #[cfg(feature = "tracing")]
+        tracing::debug!(cost = self.cost.get(), "run_program finished");
+        Ok(Reduction(self.cost, self.pop()?))
+    }
+
+    // the opcode of the operator (or quote) the given operation runs, for the
+    // tracer
+    fn traced_opcode(&self, op: &Operation<T::Ptr>) -> Option<Vec<u8>> {
+        let operator = match op {
+            Operation::Apply => self.val_stack.get(self.val_stack.len().checked_sub(2)?)?,
+            Operation::Eval(program, _) => match self.allocator.sexp(program) {
+                SExp::Pair(first, _) => {
+                    if let SExp::Atom(b) = self.allocator.sexp(&first) {
+                        if self.allocator.atom_byte(&b) == Some(self.quote_kw) {
+                            return Some(vec![self.quote_kw]);
+                        }
+                    }
+                    return None;
+                }
+                SExp::Atom(_) => return None,
+            },
+            _ => return None,
+        };
+        match self.allocator.sexp(operator) {
+            SExp::Atom(b) => Some(self.allocator.buf(&b).to_vec()),
+            SExp::Pair(_, _) => None,
+        }
     }
 }
 
@@ -380,7 +615,7 @@ pub fn run_program<T: Allocator>(
     quote_kw: u8,
     apply_kw: u8,
     max_cost: Cost,
-    operator_lookup: Box<dyn OperatorHandler<T>>,
+    operator_lookup: Box<dyn OperatorHandler<T> + '_>,
     pre_eval: Option<PreEval<T>>,
 ) -> Response<T::Ptr>
 where
@@ -390,6 +625,208 @@ where
     rpc.run_program(program, args, max_cost)
 }
 
+// The limits and options of an evaluation, for run_program_with_params().
+// Built up from the defaults, which are the chia quote and apply keywords and
+// no limits at all, e.g.
+//
+//   RunParams::new()
+//       .with_max_cost(Cost::new(11_000_000_000))
+//       .with_max_depth(512)
+//
+// P is the allocator's Ptr, the type a tracer is given programs as
+pub struct RunParams<'a, P> {
+    pub quote_kw: u8,
+    pub apply_kw: u8,
+    // 0 is no limit, as with run_program()
+    pub max_cost: Cost,
+    // the most operations waiting to run at once, which is how deeply the
+    // evaluation is nested. 0 is no limit
+    pub max_depth: usize,
+    // the most memory the allocator may hold (including what it reserves up
+    // front), for allocators that keep track of it. 0 is no limit
+    pub max_heap: usize,
+    // the most evaluation steps. 0 is no limit
+    pub max_steps: u64,
+    // STRICT_MODE, or 0. The evaluator passes it on to the operators, see
+    // OpParams
+    pub flags: u32,
+    // see OpParams. 0 is no limit
+    pub max_int_bytes: usize,
+    // see OpParams. Empty, so every operator costs what it always does
+    pub cost_table: CostTable,
+    // errors are annotated with the names of the functions (from a chialisp
+    // .sym file) that were executing when it failed
+    #[cfg(feature = "std")]
+    pub symbols: Option<&'a SymbolTable>,
+    // every step's cost is also charged to it, and evaluation fails with
+    // "fuel exhausted" as soon as it refuses a step
+    pub fuel: Option<&'a mut dyn FuelMeter>,
+    // told the cost of every step and which programs were executing, for
+    // profiling
+    pub tracer: Option<&'a mut dyn CostTracer<P>>,
+}
+
+impl<'a, P> Default for RunParams<'a, P> {
+    fn default() -> Self {
+        RunParams {
+            quote_kw: 1,
+            apply_kw: 2,
+            max_cost: Cost::ZERO,
+            max_depth: 0,
+            max_heap: 0,
+            max_steps: 0,
+            flags: 0,
+            max_int_bytes: 0,
+            cost_table: CostTable::new(),
+            #[cfg(feature = "std")]
+            symbols: None,
+            fuel: None,
+            tracer: None,
+        }
+    }
+}
+
+impl<'a, P> RunParams<'a, P> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_keywords(mut self, quote_kw: u8, apply_kw: u8) -> Self {
+        self.quote_kw = quote_kw;
+        self.apply_kw = apply_kw;
+        self
+    }
+
+    pub fn with_max_cost(mut self, max_cost: Cost) -> Self {
+        self.max_cost = max_cost;
+        self
+    }
+
+    pub fn with_max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn with_max_heap(mut self, max_heap: usize) -> Self {
+        self.max_heap = max_heap;
+        self
+    }
+
+    pub fn with_max_steps(mut self, max_steps: u64) -> Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    pub fn with_flags(mut self, flags: u32) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    pub fn with_max_int_bytes(mut self, max_int_bytes: usize) -> Self {
+        self.max_int_bytes = max_int_bytes;
+        self
+    }
+
+    pub fn with_cost_table(mut self, cost_table: CostTable) -> Self {
+        self.cost_table = cost_table;
+        self
+    }
+
+    #[cfg(feature = "std")]
+    pub fn with_symbols(mut self, symbols: &'a SymbolTable) -> Self {
+        self.symbols = Some(symbols);
+        self
+    }
+
+    pub fn with_fuel(mut self, fuel: &'a mut dyn FuelMeter) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    pub fn with_tracer(mut self, tracer: &'a mut dyn CostTracer<P>) -> Self {
+        self.tracer = Some(tracer);
+        self
+    }
+
+    pub fn op_params(&self) -> OpParams {
+        OpParams {
+            max_int_bytes: self.max_int_bytes,
+            cost_table: self.cost_table.clone(),
+            strict: self.flags & STRICT_MODE != 0,
+        }
+    }
+}
+
+// like run_program(), with the keywords, limits and hooks taken from
+// `params`. The operators (and so what each of them costs) are still given
+// separately
+pub fn run_program_with_params<T: Allocator>(
+    allocator: &mut T,
+    program: &T::Ptr,
+    args: &T::Ptr,
+    operator_lookup: Box<dyn OperatorHandler<T> + '_>,
+    pre_eval: Option<PreEval<T>>,
+    params: RunParams<'_, T::Ptr>,
+) -> Response<T::Ptr>
+where
+    <T as Allocator>::Ptr: 'static,
+{
+    let mut rpc = RunProgramContext::new(
+        allocator,
+        params.quote_kw,
+        params.apply_kw,
+        operator_lookup,
+        pre_eval,
+    );
+    rpc.max_depth = params.max_depth;
+    rpc.max_heap = params.max_heap;
+    rpc.max_steps = params.max_steps;
+    rpc.op_params = params.op_params();
+    // each shortened to the run's lifetime, which Option can't do by itself
+    rpc.fuel = params.fuel.map(|fuel| fuel as _);
+    rpc.tracer = params.tracer.map(|tracer| tracer as _);
+    #[cfg(feature = "std")]
+    if params.symbols.is_some() {
+        rpc.frames = Some(Vec::new());
+    }
+    let r = rpc.run_program(program, args, params.max_cost);
+    #[cfg(feature = "std")]
+    if let (Err(e), Some(symbols)) = (&r, params.symbols) {
+        let frames = rpc.frames.take().unwrap_or_default();
+        let msg = symbols.render_err(rpc.allocator, e, &frames);
+        return Err(EvalErr(e.0.clone(), msg.into(), e.2));
+    }
+    r
+}
+
+// like run_program(), but a failure is returned as a self-contained report
+// (including the cost spent and the backtrace) that no longer refers to the
+// allocator
+#[allow(clippy::too_many_arguments)]
+pub fn run_program_with_report<T: Allocator>(
+    allocator: &mut T,
+    program: &T::Ptr,
+    args: &T::Ptr,
+    quote_kw: u8,
+    apply_kw: u8,
+    max_cost: Cost,
+    operator_lookup: Box<dyn OperatorHandler<T> + '_>,
+    pre_eval: Option<PreEval<T>>,
+) -> Result<Reduction<T::Ptr>, ErrReport>
+where
+    <T as Allocator>::Ptr: 'static,
+{
+    let mut rpc = RunProgramContext::new(allocator, quote_kw, apply_kw, operator_lookup, pre_eval);
+    rpc.frames = Some(Vec::new());
+    match rpc.run_program(program, args, max_cost) {
+        Ok(r) => Ok(r),
+        Err(e) => {
+            let frames = rpc.frames.take().unwrap_or_default();
+            Err(ErrReport::new(rpc.allocator, &e, rpc.cost, &frames))
+        }
+    }
+}
+
 #[test]
 fn test_msb_mask() {
     assert_eq!(msb_mask(0x0), 0x0);
@@ -427,57 +864,557 @@ fn test_traverse_path() {
     let n1 = a.new_atom(&[0, 1, 2]).unwrap();
     let n2 = a.new_atom(&[4, 5, 6]).unwrap();
 
-    assert_eq!(traverse_path(&a, &[0], &n1).unwrap(), Reduction(48, nul));
-    assert_eq!(traverse_path(&a, &[0b1], &n1).unwrap(), Reduction(44, n1));
-    assert_eq!(traverse_path(&a, &[0b1], &n2).unwrap(), Reduction(44, n2));
+    assert_eq!(
+        traverse_path(&a, &[0], &n1).unwrap(),
+        Reduction(Cost::new(48), nul)
+    );
+    assert_eq!(
+        traverse_path(&a, &[0b1], &n1).unwrap(),
+        Reduction(Cost::new(44), n1)
+    );
+    assert_eq!(
+        traverse_path(&a, &[0b1], &n2).unwrap(),
+        Reduction(Cost::new(44), n2)
+    );
 
     // cost for leading zeros
     assert_eq!(
         traverse_path(&a, &[0, 0, 0, 0], &n1).unwrap(),
-        Reduction(60, nul)
+        Reduction(Cost::new(60), nul)
     );
 
     let n3 = a.new_pair(n1, n2).unwrap();
-    assert_eq!(traverse_path(&a, &[0b1], &n3).unwrap(), Reduction(44, n3));
-    assert_eq!(traverse_path(&a, &[0b10], &n3).unwrap(), Reduction(48, n1));
-    assert_eq!(traverse_path(&a, &[0b11], &n3).unwrap(), Reduction(48, n2));
-    assert_eq!(traverse_path(&a, &[0b11], &n3).unwrap(), Reduction(48, n2));
+    assert_eq!(
+        traverse_path(&a, &[0b1], &n3).unwrap(),
+        Reduction(Cost::new(44), n3)
+    );
+    assert_eq!(
+        traverse_path(&a, &[0b10], &n3).unwrap(),
+        Reduction(Cost::new(48), n1)
+    );
+    assert_eq!(
+        traverse_path(&a, &[0b11], &n3).unwrap(),
+        Reduction(Cost::new(48), n2)
+    );
+    assert_eq!(
+        traverse_path(&a, &[0b11], &n3).unwrap(),
+        Reduction(Cost::new(48), n2)
+    );
 
     let list = a.new_pair(n1, nul).unwrap();
     let list = a.new_pair(n2, list).unwrap();
 
     assert_eq!(
         traverse_path(&a, &[0b10], &list).unwrap(),
-        Reduction(48, n2)
+        Reduction(Cost::new(48), n2)
     );
     assert_eq!(
         traverse_path(&a, &[0b101], &list).unwrap(),
-        Reduction(52, n1)
+        Reduction(Cost::new(52), n1)
     );
     assert_eq!(
         traverse_path(&a, &[0b111], &list).unwrap(),
-        Reduction(52, nul)
+        Reduction(Cost::new(52), nul)
     );
 
     // errors
     assert_eq!(
         traverse_path(&a, &[0b1011], &list).unwrap_err(),
-        EvalErr(nul, "path into atom".to_string())
+        EvalErr::new(nul, "path into atom")
     );
     assert_eq!(
         traverse_path(&a, &[0b1101], &list).unwrap_err(),
-        EvalErr(n1, "path into atom".to_string())
+        EvalErr::new(n1, "path into atom")
     );
     assert_eq!(
         traverse_path(&a, &[0b1001], &list).unwrap_err(),
-        EvalErr(n1, "path into atom".to_string())
+        EvalErr::new(n1, "path into atom")
     );
     assert_eq!(
         traverse_path(&a, &[0b1010], &list).unwrap_err(),
-        EvalErr(n2, "path into atom".to_string())
+        EvalErr::new(n2, "path into atom")
     );
     assert_eq!(
         traverse_path(&a, &[0b1110], &list).unwrap_err(),
-        EvalErr(n2, "path into atom".to_string())
+        EvalErr::new(n2, "path into atom")
+    );
+}
+
+#[cfg(test)]
+struct RaiseOnly;
+
+#[cfg(test)]
+impl<T: Allocator> OperatorHandler<T> for RaiseOnly {
+    fn op(
+        &mut self,
+        allocator: &mut T,
+        op: T::AtomBuf,
+        args: &T::Ptr,
+        _max_cost: Cost,
+    ) -> Response<T::Ptr> {
+        if allocator.buf(&op) == [8] {
+            err(args.clone(), "clvm raise")
+        } else {
+            err(args.clone(), "unimplemented operator")
+        }
+    }
+}
+
+#[test]
+fn test_run_with_fuel() {
+    use crate::int_allocator::IntAllocator;
+    use crate::op_table::{op_table_for, CHIA_OPCODES};
+    use crate::serialize::node_from_bytes;
+
+    struct Tank {
+        left: Cost,
+        spent: Cost,
+    }
+
+    impl FuelMeter for Tank {
+        fn consume(&mut self, cost: Cost) -> bool {
+            if cost > self.left {
+                return false;
+            }
+            self.left = self.left.saturating_sub(cost);
+            self.spent += cost;
+            true
+        }
+    }
+
+    let mut a = IntAllocator::new();
+    // (+ 2 5) with the environment (3 4)
+    let program = node_from_bytes(&mut a, &hex::decode("ff10ff02ff0580").unwrap()).unwrap();
+    let env = node_from_bytes(&mut a, &hex::decode("ff03ff0480").unwrap()).unwrap();
+    let run = |a: &mut IntAllocator, fuel: &mut Tank| {
+        run_program_with_params(
+            a,
+            &program,
+            &env,
+            Box::new(op_table_for(&CHIA_OPCODES)),
+            None,
+            RunParams::new().with_fuel(fuel),
+        )
+    };
+
+    // the fuel spent is the cost
+    let mut tank = Tank {
+        left: Cost::MAX,
+        spent: Cost::ZERO,
+    };
+    let cost = run(&mut a, &mut tank).unwrap().0;
+    assert_eq!(tank.spent, cost);
+
+    // one short of the cost stops it, at the same point every time
+    let mut tank = Tank {
+        left: cost.saturating_sub(Cost::new(1)),
+        spent: Cost::ZERO,
+    };
+    let e = run(&mut a, &mut tank).unwrap_err();
+    assert_eq!(e.1, "fuel exhausted");
+    let spent = tank.spent;
+    let mut tank = Tank {
+        left: cost.saturating_sub(Cost::new(1)),
+        spent: Cost::ZERO,
+    };
+    run(&mut a, &mut tank).unwrap_err();
+    assert_eq!(tank.spent, spent);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_run_with_symbols() {
+    use crate::int_allocator::IntAllocator;
+    use crate::tree_hash::tree_hash;
+
+    let mut a = IntAllocator::new();
+    let nul = a.null();
+    let one = a.one();
+    let two = a.new_atom(&[2]).unwrap();
+    let eight = a.new_atom(&[8]).unwrap();
+
+    // (a (q 8) 1)
+    let raise = a.new_pair(eight, nul).unwrap();
+    let quoted = a.new_pair(one, raise).unwrap();
+    let program = a.new_pair(one, nul).unwrap();
+    let program = a.new_pair(quoted, program).unwrap();
+    let program = a.new_pair(two, program).unwrap();
+
+    let mut symbols = SymbolTable::new();
+    symbols.insert(tree_hash(&Node::new(&a, raise)), "assert_my_coin_id");
+    let main_hash = hex::encode(&tree_hash(&Node::new(&a, program))[..8]);
+
+    let e = run_program_with_params(
+        &mut a,
+        &program,
+        &nul,
+        Box::new(RaiseOnly),
+        None,
+        RunParams::new().with_symbols(&symbols),
+    )
+    .unwrap_err();
+    assert_eq!(
+        e.1,
+        format!(
+            "clvm raise in `assert_my_coin_id` (depth 2)\n  #1 `assert_my_coin_id`\n  #0 {}",
+            main_hash
+        )
+    );
+
+    // without symbols, the error is the plain one
+    let e = run_program(
+        &mut a,
+        &program,
+        &nul,
+        1,
+        2,
+        Cost::ZERO,
+        Box::new(RaiseOnly),
+        None,
+    )
+    .unwrap_err();
+    assert_eq!(e.1, "clvm raise");
+}
+
+#[test]
+fn test_run_program_with_report() {
+    use crate::int_allocator::IntAllocator;
+    use crate::reduction::ErrKind;
+    use crate::tree_hash::tree_hash;
+
+    let mut a = IntAllocator::new();
+    let nul = a.null();
+    let one = a.one();
+    let two = a.new_atom(&[2]).unwrap();
+    let eight = a.new_atom(&[8]).unwrap();
+
+    // (a (q 8) 1)
+    let raise = a.new_pair(eight, nul).unwrap();
+    let quoted = a.new_pair(one, raise).unwrap();
+    let program = a.new_pair(one, nul).unwrap();
+    let program = a.new_pair(quoted, program).unwrap();
+    let program = a.new_pair(two, program).unwrap();
+
+    let report = run_program_with_report(
+        &mut a,
+        &program,
+        &nul,
+        1,
+        2,
+        Cost::ZERO,
+        Box::new(RaiseOnly),
+        None,
+    )
+    .unwrap_err();
+    assert_eq!(report.kind, ErrKind::Eval);
+    assert_eq!(report.msg, "clvm raise");
+    assert_eq!(report.node.bytes, &[0x80]);
+    assert!(report.cost > Cost::ZERO);
+    assert_eq!(
+        report.backtrace,
+        vec![
+            tree_hash(&Node::new(&a, program)),
+            tree_hash(&Node::new(&a, raise))
+        ]
+    );
+}
+
+#[test]
+fn test_run_program_with_params() {
+    use crate::int_allocator::IntAllocator;
+    use crate::op_table::{op_table_for, CHIA_OPCODES};
+    use crate::reduction::ErrKind;
+    use crate::serialize::node_from_bytes;
+
+    let mut a = IntAllocator::new();
+    // (+ 2 5) with the environment (3 4)
+    let program = node_from_bytes(&mut a, &hex::decode("ff10ff02ff0580").unwrap()).unwrap();
+    let env = node_from_bytes(&mut a, &hex::decode("ff03ff0480").unwrap()).unwrap();
+    let run = |a: &mut IntAllocator, params: RunParams<i32>| {
+        run_program_with_params(
+            a,
+            &program,
+            &env,
+            Box::new(op_table_for(&CHIA_OPCODES)),
+            None,
+            params,
+        )
+        .map(|r| r.0)
+        .map_err(|e| (e.1.to_string(), e.2))
+    };
+
+    // no limits is the same as run_program()
+    let cost = run(&mut a, RunParams::new()).unwrap();
+    let expected = run_program(
+        &mut a,
+        &program,
+        &env,
+        1,
+        2,
+        Cost::ZERO,
+        Box::new(op_table_for(&CHIA_OPCODES)),
+        None,
+    )
+    .unwrap();
+    assert_eq!(cost, expected.0);
+
+    // it takes 6 steps: eval the call, eval each argument and cons it onto
+    // the argument list, and apply
+    assert_eq!(run(&mut a, RunParams::new().with_max_steps(6)), Ok(cost));
+    assert_eq!(
+        run(&mut a, RunParams::new().with_max_steps(5)),
+        Err(("too many steps".to_string(), ErrKind::LimitExceeded))
+    );
+    assert_eq!(
+        run(
+            &mut a,
+            RunParams::new().with_max_cost(cost.saturating_sub(Cost::new(1)))
+        ),
+        Err(("cost exceeded".to_string(), ErrKind::Eval))
+    );
+    assert_eq!(
+        run(&mut a, RunParams::new().with_max_depth(1)),
+        Err(("max depth exceeded".to_string(), ErrKind::LimitExceeded))
+    );
+    assert!(run(&mut a, RunParams::new().with_max_depth(100)).is_ok());
+    assert_eq!(
+        run(&mut a, RunParams::new().with_max_heap(1000)),
+        Err(("max heap exceeded".to_string(), ErrKind::LimitExceeded))
+    );
+    assert!(run(&mut a, RunParams::new().with_max_heap(usize::MAX)).is_ok());
+    // with + as the quote keyword, the program just quotes (2 5)
+    assert_eq!(
+        run(&mut a, RunParams::new().with_keywords(0x10, 2)),
+        Ok(QUOTE_COST)
+    );
+}
+
+#[test]
+fn test_max_int_bytes() {
+    use crate::int_allocator::IntAllocator;
+    use crate::op_table::{op_table_for, CHIA_OPCODES};
+    use crate::serialize::node_from_bytes;
+
+    let mut a = IntAllocator::new();
+    // (* 2 5) with the environment (A B), two 600KB integers
+    let program = node_from_bytes(&mut a, &hex::decode("ff12ff02ff0580").unwrap()).unwrap();
+    let big = a.new_atom(&vec![0x7f; 600_000]).unwrap();
+    let null = a.null();
+    let env = a.new_pair(big, null).unwrap();
+    let env = a.new_pair(big, env).unwrap();
+    let run = |a: &mut IntAllocator, params: RunParams<i32>| {
+        run_program_with_params(
+            a,
+            &program,
+            &env,
+            Box::new(op_table_for(&CHIA_OPCODES)),
+            None,
+            params.with_max_cost(Cost::new(11_000_000_000)),
+        )
+        .map(|r| a.atom(&r.1).len())
+        .map_err(|e| e.1.to_string())
+    };
+
+    // there's no limit by default, only the cost
+    assert_eq!(run(&mut a, RunParams::new()), Ok(1_200_000));
+    assert_eq!(
+        run(&mut a, RunParams::new().with_max_int_bytes(1_000_000)),
+        Err("* result exceeds max integer size".to_string())
+    );
+    assert_eq!(
+        run(&mut a, RunParams::new().with_max_int_bytes(1_200_000)),
+        Ok(1_200_000)
+    );
+}
+
+#[test]
+fn test_cost_table() {
+    use crate::int_allocator::IntAllocator;
+    use crate::op_table::{op_table_for, CHIA_OPCODES};
+    use crate::serialize::node_from_bytes;
+
+    let mut a = IntAllocator::new();
+    // (+ 2 (f 5)) with the environment (3 (4))
+    let program = node_from_bytes(&mut a, &hex::decode("ff10ff02ffff05ff058080").unwrap()).unwrap();
+    let env = node_from_bytes(&mut a, &hex::decode("ff03ffff048080").unwrap()).unwrap();
+    let run = |a: &mut IntAllocator, params: RunParams<i32>| {
+        run_program_with_params(
+            a,
+            &program,
+            &env,
+            Box::new(op_table_for(&CHIA_OPCODES)),
+            None,
+            params,
+        )
+        .map(|r| r.0)
+        .map_err(|e| e.1.to_string())
+    };
+
+    let cost = run(&mut a, RunParams::new()).unwrap();
+    // + has a base cost of 99, the listed opcodes that aren't called (0x3c)
+    // don't matter
+    let table = CostTable::new()
+        .with_base_cost(&[16], Cost::new(1099))
+        .with_base_cost(&[0x3c], Cost::new(5));
+    let params = || RunParams::new().with_cost_table(table.clone());
+    assert_eq!(run(&mut a, params()), Ok(cost + Cost::new(1000)));
+    assert_eq!(
+        run(&mut a, params().with_max_cost(cost + Cost::new(999))),
+        Err("cost exceeded".to_string())
+    );
+    // so does f, whose base cost is 30
+    let table = CostTable::new().with_base_cost(&[5], Cost::ZERO);
+    assert_eq!(
+        run(&mut a, RunParams::new().with_cost_table(table)),
+        Ok(cost.saturating_sub(Cost::new(30)))
+    );
+}
+
+#[test]
+fn test_strict_mode() {
+    use crate::int_allocator::IntAllocator;
+    use crate::op_table::{op_table_for, HostOpTable, HostOperators, CHIA_OPCODES};
+    use crate::serialize::node_from_bytes;
+
+    struct NoHost;
+    impl HostOperators<IntAllocator> for NoHost {
+        fn op(
+            &self,
+            _allocator: &mut IntAllocator,
+            _op: <IntAllocator as Allocator>::AtomBuf,
+            _args: &i32,
+            _max_cost: Cost,
+        ) -> Option<Response<i32>> {
+            None
+        }
+    }
+
+    let mut a = IntAllocator::new();
+    // (0x7f), an operator no table implements
+    let program = node_from_bytes(&mut a, &hex::decode("ff7f80").unwrap()).unwrap();
+    let null = a.null();
+    let run = |a: &mut IntAllocator, host: bool, params: RunParams<i32>| {
+        let table = op_table_for(&CHIA_OPCODES);
+        let operators: Box<dyn OperatorHandler<IntAllocator>> = if host {
+            Box::new(HostOpTable {
+                table,
+                host: NoHost,
+            })
+        } else {
+            Box::new(table)
+        };
+        run_program_with_params(a, &program, &null, operators, None, params)
+            .map(|r| r.0)
+            .map_err(|e| e.1.to_string())
+    };
+
+    for host in &[false, true] {
+        assert!(run(&mut a, *host, RunParams::new()).is_ok());
+        assert_eq!(
+            run(&mut a, *host, RunParams::new().with_flags(STRICT_MODE)),
+            Err("unimplemented operator".to_string())
+        );
+    }
+}
+
+#[test]
+fn test_cost_saturates() {
+    use crate::core_ops::op_first;
+    use crate::int_allocator::IntAllocator;
+    use crate::op_table::{Op, OpTable};
+    use crate::serialize::node_from_bytes;
+
+    // f, with a base cost so large that adding anything to it overflows
+    let table: OpTable<IntAllocator> = OpTable::new(&[(
+        vec![5],
+        Op {
+            f: op_first,
+            base_cost: Cost::MAX,
+            limited: None,
+        },
+    )]);
+    let mut a = IntAllocator::new();
+    // (f (q 1 2))
+    let program = node_from_bytes(&mut a, &hex::decode("ff05ffff01ff01ff028080").unwrap()).unwrap();
+    let null = a.null();
+    // even without a limit, it's exceeded rather than wrapping around
+    let r = run_program(
+        &mut a,
+        &program,
+        &null,
+        1,
+        2,
+        Cost::ZERO,
+        Box::new(table),
+        None,
+    );
+    assert_eq!(r.unwrap_err().1.to_string(), "cost exceeded");
+}
+
+#[test]
+fn test_stateful_operator_handler() {
+    use crate::int_allocator::IntAllocator;
+    use crate::op_table::{op_table_for, OpTable, CHIA_OPCODES};
+    use crate::serialize::node_from_bytes;
+
+    // counts the operators it runs, and refuses to run more than `limit`
+    struct Limited {
+        table: OpTable<IntAllocator>,
+        calls: usize,
+        limit: usize,
+    }
+
+    impl OperatorHandler<IntAllocator> for Limited {
+        fn op(
+            &mut self,
+            allocator: &mut IntAllocator,
+            op: <IntAllocator as Allocator>::AtomBuf,
+            args: &i32,
+            max_cost: Cost,
+        ) -> Response<i32> {
+            self.calls += 1;
+            if self.calls > self.limit {
+                return err(*args, "too many operators");
+            }
+            self.table.op(allocator, op, args, max_cost)
+        }
+    }
+
+    let mut a = IntAllocator::new();
+    // (+ (+ 2 5) 5) with the environment (3 4)
+    let program =
+        node_from_bytes(&mut a, &hex::decode("ff10ffff10ff02ff0580ff0580").unwrap()).unwrap();
+    let env = node_from_bytes(&mut a, &hex::decode("ff03ff0480").unwrap()).unwrap();
+    let mut handler = Limited {
+        table: op_table_for(&CHIA_OPCODES),
+        calls: 0,
+        limit: 10,
+    };
+    let r = run_program(
+        &mut a,
+        &program,
+        &env,
+        1,
+        2,
+        Cost::ZERO,
+        Box::new(&mut handler),
+        None,
+    )
+    .unwrap();
+    assert_eq!(a.atom(&r.1), [11]);
+    assert_eq!(handler.calls, 2);
+
+    handler.limit = 3;
+    let r = run_program(
+        &mut a,
+        &program,
+        &env,
+        1,
+        2,
+        Cost::ZERO,
+        Box::new(&mut handler),
+        None,
     );
+    assert_eq!(r.unwrap_err().1.to_string(), "too many operators");
+    assert_eq!(handler.calls, 4);
 }
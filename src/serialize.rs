@@ -1,43 +1,166 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io::{Cursor, ErrorKind, Write};
+
+#[cfg(feature = "std")]
+use crate::reduction::ErrKind;
 use crate::reduction::EvalErr;
-use std::io::Cursor;
-use std::io::Read;
-use std::io::Write;
-use std::io::{Error, ErrorKind};
 
 use crate::allocator::{Allocator, SExp};
 use crate::node::Node;
+use crate::read_cache_lookup::ReadCacheLookup;
+use crate::run_program::traverse_path;
+use crate::sha256::sha256 as hash_blobs;
 
 const MAX_SINGLE_BYTE: u8 = 0x7f;
 const CONS_BOX_MARKER: u8 = 0xff;
+const BACK_REFERENCE: u8 = 0xfe;
+
+#[derive(Debug)]
+pub enum SerializeError {
+    // the input ended in the middle of a node
+    UnexpectedEof,
+    // an atom's length prefix is malformed or describes an atom larger than
+    // we support
+    InvalidLengthPrefix,
+    // a back reference's path leads into an atom
+    InvalidBackReference,
+    // the allocator refused to create a node, or an atom is too large to be
+    // encoded
+    LimitExceeded(String),
+    // any other error from the underlying reader or writer
+    #[cfg(feature = "std")]
+    Io(std::io::Error),
+}
+
+pub type SerializeResult<T> = Result<T, SerializeError>;
+
+impl fmt::Display for SerializeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnexpectedEof => write!(f, "unexpected end of input"),
+            Self::InvalidLengthPrefix => write!(f, "bad encoding"),
+            Self::InvalidBackReference => write!(f, "invalid back reference"),
+            Self::LimitExceeded(msg) => write!(f, "limit exceeded: {}", msg),
+            #[cfg(feature = "std")]
+            Self::Io(e) => write!(f, "io error: {}", e),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for SerializeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<std::io::Error> for SerializeError {
+    fn from(e: std::io::Error) -> Self {
+        if e.kind() == ErrorKind::UnexpectedEof {
+            Self::UnexpectedEof
+        } else {
+            Self::Io(e)
+        }
+    }
+}
+
+impl<T> From<EvalErr<T>> for SerializeError {
+    fn from(v: EvalErr<T>) -> Self {
+        match v.2 {
+            // without std there's no io::Error to report running out of
+            // memory with
+            #[cfg(feature = "std")]
+            ErrKind::OutOfMemory => {
+                Self::Io(std::io::Error::new(ErrorKind::OutOfMemory, v.1.to_string()))
+            }
+            _ => Self::LimitExceeded(v.1.into()),
+        }
+    }
+}
 
-fn bad_encoding() -> std::io::Error {
-    Error::new(ErrorKind::InvalidInput, "bad encoding")
+// where serialized bytes go. This is the part of std::io::Write we need, which
+// is available without std
+trait Sink {
+    fn put(&mut self, data: &[u8]) -> SerializeResult<()>;
 }
 
-fn internal_error() -> std::io::Error {
-    Error::new(ErrorKind::InvalidInput, "internal error")
+impl Sink for Vec<u8> {
+    fn put(&mut self, data: &[u8]) -> SerializeResult<()> {
+        self.extend_from_slice(data);
+        Ok(())
+    }
 }
 
-fn encode_size(f: &mut dyn Write, size: u64) -> std::io::Result<()> {
+#[cfg(feature = "std")]
+struct IoSink<'a>(&'a mut dyn Write);
+
+#[cfg(feature = "std")]
+impl Sink for IoSink<'_> {
+    fn put(&mut self, data: &[u8]) -> SerializeResult<()> {
+        self.0.write_all(data)?;
+        Ok(())
+    }
+}
+
+// the read side: a position in a serialized blob. Reading past the end is an
+// UnexpectedEof
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(buf: &'a [u8]) -> Self {
+        Reader { buf, pos: 0 }
+    }
+
+    fn read_byte(&mut self) -> SerializeResult<u8> {
+        let b = *self
+            .buf
+            .get(self.pos)
+            .ok_or(SerializeError::UnexpectedEof)?;
+        self.pos += 1;
+        Ok(b)
+    }
+
+    fn read_bytes(&mut self, len: u64) -> SerializeResult<&'a [u8]> {
+        if (self.buf.len().saturating_sub(self.pos) as u64) < len {
+            return Err(SerializeError::UnexpectedEof);
+        }
+        let start = self.pos;
+        self.pos += len as usize;
+        Ok(&self.buf[start..self.pos])
+    }
+}
+
+fn encode_size(f: &mut dyn Sink, size: u64) -> SerializeResult<()> {
     if size < 0x40 {
-        f.write_all(&[(0x80 | size) as u8])?;
+        f.put(&[(0x80 | size) as u8])?;
     } else if size < 0x2000 {
-        f.write_all(&[(0xc0 | (size >> 8)) as u8, ((size) & 0xff) as u8])?;
+        f.put(&[(0xc0 | (size >> 8)) as u8, ((size) & 0xff) as u8])?;
     } else if size < 0x10_0000 {
-        f.write_all(&[
+        f.put(&[
             (0xe0 | (size >> 16)) as u8,
             ((size >> 8) & 0xff) as u8,
             ((size) & 0xff) as u8,
         ])?;
     } else if size < 0x800_0000 {
-        f.write_all(&[
+        f.put(&[
             (0xf0 | (size >> 24)) as u8,
             ((size >> 16) & 0xff) as u8,
             ((size >> 8) & 0xff) as u8,
             ((size) & 0xff) as u8,
         ])?;
     } else if size < 0x4_0000_0000 {
-        f.write_all(&[
+        f.put(&[
             (0xf8 | (size >> 32)) as u8,
             ((size >> 24) & 0xff) as u8,
             ((size >> 16) & 0xff) as u8,
@@ -45,35 +168,37 @@ fn encode_size(f: &mut dyn Write, size: u64) -> std::io::Result<()> {
             ((size) & 0xff) as u8,
         ])?;
     } else {
-        return Err(Error::new(ErrorKind::InvalidData, "atom too big"));
+        return Err(SerializeError::LimitExceeded("atom too big".into()));
+    }
+    Ok(())
+}
+
+fn write_atom(f: &mut dyn Sink, atom: &[u8]) -> SerializeResult<()> {
+    let size = atom.len();
+    if size == 0 {
+        f.put(&[0x80_u8])?;
+    } else {
+        let atom0 = atom[0];
+        if size == 1 && (atom0 <= MAX_SINGLE_BYTE) {
+            f.put(&[atom0])?;
+        } else {
+            encode_size(f, size as u64)?;
+            f.put(atom)?;
+        }
     }
     Ok(())
 }
 
-pub fn node_to_stream<T: Allocator>(node: &Node<T>, f: &mut dyn Write) -> std::io::Result<()> {
+fn write_node<T: Allocator>(node: &Node<T>, f: &mut dyn Sink) -> SerializeResult<()> {
     let mut values: Vec<T::Ptr> = vec![node.node.clone()];
     let a = node.allocator;
     while !values.is_empty() {
         let v = values.pop().unwrap();
         let n = a.sexp(&v);
         match n {
-            SExp::Atom(atom_ptr) => {
-                let atom = a.buf(&atom_ptr);
-                let size = atom.len();
-                if size == 0 {
-                    f.write_all(&[0x80_u8])?;
-                } else {
-                    let atom0 = atom[0];
-                    if size == 1 && (atom0 <= MAX_SINGLE_BYTE) {
-                        f.write_all(&[atom0])?;
-                    } else {
-                        encode_size(f, size as u64)?;
-                        f.write_all(atom)?;
-                    }
-                }
-            }
+            SExp::Atom(atom_ptr) => write_atom(f, a.buf(&atom_ptr))?,
             SExp::Pair(left, right) => {
-                f.write_all(&[CONS_BOX_MARKER as u8])?;
+                f.put(&[CONS_BOX_MARKER as u8])?;
                 values.push(right);
                 values.push(left);
             }
@@ -82,13 +207,58 @@ pub fn node_to_stream<T: Allocator>(node: &Node<T>, f: &mut dyn Write) -> std::i
     Ok(())
 }
 
-fn decode_size(f: &mut dyn Read, initial_b: u8) -> std::io::Result<u64> {
+#[cfg(feature = "std")]
+pub fn node_to_stream<T: Allocator>(node: &Node<T>, f: &mut dyn Write) -> SerializeResult<()> {
+    write_node(node, &mut IoSink(f))
+}
+
+// a writer that keeps at most `limit` bytes and refuses anything beyond that,
+// which stops `write_node` early rather than serializing a huge tree only to
+// throw most of it away
+struct LimitedWriter {
+    buf: Vec<u8>,
+    limit: usize,
+    truncated: bool,
+}
+
+impl Sink for LimitedWriter {
+    fn put(&mut self, data: &[u8]) -> SerializeResult<()> {
+        let room = self.limit - self.buf.len();
+        if data.len() > room {
+            self.buf.extend_from_slice(&data[..room]);
+            self.truncated = true;
+            return Err(SerializeError::LimitExceeded("limit reached".into()));
+        }
+        self.buf.extend_from_slice(data);
+        Ok(())
+    }
+}
+
+// serialize at most `limit` bytes of `node`. The returned bool is true if the
+// serialization was cut short
+pub fn node_to_bytes_limit<T: Allocator>(
+    node: &Node<T>,
+    limit: usize,
+) -> SerializeResult<(Vec<u8>, bool)> {
+    let mut w = LimitedWriter {
+        buf: Vec::new(),
+        limit,
+        truncated: false,
+    };
+    match write_node(node, &mut w) {
+        Ok(()) => Ok((w.buf, false)),
+        Err(_) if w.truncated => Ok((w.buf, true)),
+        Err(e) => Err(e),
+    }
+}
+
+fn decode_size(f: &mut Reader<'_>, initial_b: u8) -> SerializeResult<u64> {
     // this function decodes the length prefix for an atom. Atoms whose value
     // fit in 7 bits don't have a length-prefix, so those should never be passed
     // to this function.
     debug_assert!((initial_b & 0x80) != 0);
     if (initial_b & 0x80) == 0 {
-        return Err(internal_error());
+        return Err(SerializeError::InvalidLengthPrefix);
     }
 
     let mut bit_count = 0;
@@ -103,93 +273,687 @@ fn decode_size(f: &mut dyn Read, initial_b: u8) -> std::io::Result<u64> {
     size_blob.resize(bit_count, 0);
     size_blob[0] = b;
     if bit_count > 1 {
-        let remaining_buffer = &mut size_blob[1..];
-        f.read_exact(remaining_buffer)?;
+        let remaining = f.read_bytes(bit_count as u64 - 1)?;
+        size_blob[1..].copy_from_slice(remaining);
     }
     // need to convert size_blob to an int
     let mut v: u64 = 0;
     if size_blob.len() > 6 {
-        return Err(bad_encoding());
+        return Err(SerializeError::InvalidLengthPrefix);
     }
     for b in &size_blob {
         v <<= 8;
         v += *b as u64;
     }
     if v >= 0x400000000 {
-        return Err(bad_encoding());
+        return Err(SerializeError::InvalidLengthPrefix);
     }
     Ok(v)
 }
 
+#[derive(Clone, Copy)]
 enum ParseOp {
     SExp,
     Cons,
 }
 
-impl<T> std::convert::From<EvalErr<T>> for std::io::Error {
-    fn from(v: EvalErr<T>) -> Self {
-        Self::new(ErrorKind::Other, v.1)
+// how much deserializing a blob will allocate. nil and one are preallocated
+// and not counted
+#[derive(Debug, Default, PartialEq)]
+pub struct NodeCounts {
+    pub atoms: usize,
+    pub pairs: usize,
+    pub atom_bytes: usize,
+}
+
+// walk a serialized node without building it
+pub fn scan_node_counts(b: &[u8]) -> SerializeResult<NodeCounts> {
+    let mut f = Reader::new(b);
+    let mut counts = NodeCounts::default();
+    // the number of nodes left to read. Every pair adds two
+    let mut pending: usize = 1;
+    while pending > 0 {
+        pending -= 1;
+        let byte = f.read_byte()?;
+        if byte == CONS_BOX_MARKER {
+            counts.pairs += 1;
+            pending += 2;
+        } else if byte == 0x01 || byte == 0x80 {
+        } else if byte <= MAX_SINGLE_BYTE {
+            counts.atoms += 1;
+            counts.atom_bytes += 1;
+        } else {
+            let blob_size = decode_size(&mut f, byte)?;
+            f.read_bytes(blob_size)?;
+            counts.atoms += 1;
+            counts.atom_bytes += blob_size as usize;
+        }
+    }
+    Ok(counts)
+}
+
+// the tree hash of a serialized node, computed as it's parsed, without
+// building the node
+pub fn tree_hash_from_bytes(b: &[u8]) -> SerializeResult<[u8; 32]> {
+    let mut f = Reader::new(b);
+    let mut hashes: Vec<[u8; 32]> = Vec::new();
+    let mut ops = vec![ParseOp::SExp];
+    while let Some(op) = ops.pop() {
+        match op {
+            ParseOp::SExp => {
+                let b = f.read_byte()?;
+                if b == CONS_BOX_MARKER {
+                    ops.push(ParseOp::Cons);
+                    ops.push(ParseOp::SExp);
+                    ops.push(ParseOp::SExp);
+                } else {
+                    let atom = read_atom(&mut f, b)?;
+                    hashes.push(hash_blobs(&[&[1], atom]));
+                }
+            }
+            ParseOp::Cons => {
+                let rest = hashes.pop().unwrap();
+                let first = hashes.pop().unwrap();
+                hashes.push(hash_blobs(&[&[2], &first, &rest]));
+            }
+        }
+    }
+    Ok(hashes.pop().unwrap())
+}
+
+// one step of a parse. If the input ends before the step is done, nothing
+// has been pushed or allocated, so the step can be retried with more input
+fn parse_op<T: Allocator>(
+    allocator: &mut T,
+    f: &mut Reader<'_>,
+    op: ParseOp,
+    ops: &mut Vec<ParseOp>,
+    values: &mut Vec<T::Ptr>,
+) -> SerializeResult<()> {
+    match op {
+        ParseOp::SExp => {
+            let b = f.read_byte()?;
+            if b == CONS_BOX_MARKER {
+                ops.push(ParseOp::Cons);
+                ops.push(ParseOp::SExp);
+                ops.push(ParseOp::SExp);
+            } else if b == 0x01 {
+                values.push(allocator.one());
+            } else if b == 0x80 {
+                values.push(allocator.null());
+            } else if b <= MAX_SINGLE_BYTE {
+                values.push(allocator.new_atom(&[b])?);
+            } else {
+                let blob_size = decode_size(f, b)?;
+                // the atom is copied straight from the input
+                let blob = f.read_bytes(blob_size)?;
+                values.push(allocator.new_atom(blob)?);
+            }
+        }
+        ParseOp::Cons => {
+            // cons
+            let v2 = values.pop();
+            let v1 = values.pop();
+            values.push(allocator.new_pair(v1.unwrap(), v2.unwrap())?);
+        }
     }
+    Ok(())
 }
 
+fn parse_node<T: Allocator>(allocator: &mut T, f: &mut Reader<'_>) -> SerializeResult<T::Ptr> {
+    let mut values: Vec<T::Ptr> = Vec::new();
+    let mut ops = vec![ParseOp::SExp];
+
+    while let Some(op) = ops.pop() {
+        parse_op(allocator, f, op, &mut ops, &mut values)?;
+    }
+    Ok(values.pop().unwrap())
+}
+
+// a push-style deserializer, for input that arrives in pieces (e.g. from a
+// socket). Nodes are built as soon as their bytes arrive, and only an
+// incomplete atom (or length prefix) is buffered between calls to feed().
+// After an error, the parser can't be used any more
+pub struct IncrementalParser<P> {
+    ops: Vec<ParseOp>,
+    values: Vec<P>,
+    pending: Vec<u8>,
+}
+
+impl<P: Clone> Default for IncrementalParser<P> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<P: Clone> IncrementalParser<P> {
+    pub fn new() -> Self {
+        IncrementalParser {
+            ops: vec![ParseOp::SExp],
+            values: Vec::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    // parses as much of data as it can, and returns how many bytes of it
+    // were used. That's all of them, unless the node was completed before
+    // the end of data. Every call must use the same allocator
+    pub fn feed<T: Allocator<Ptr = P>>(
+        &mut self,
+        allocator: &mut T,
+        data: &[u8],
+    ) -> SerializeResult<usize> {
+        if self.is_complete() {
+            return Ok(0);
+        }
+        let buffered = self.pending.len();
+        self.pending.extend_from_slice(data);
+        let mut f = Reader::new(&self.pending);
+        while let Some(op) = self.ops.pop() {
+            let start = f.pos;
+            match parse_op(allocator, &mut f, op, &mut self.ops, &mut self.values) {
+                Ok(()) => {}
+                Err(SerializeError::UnexpectedEof) => {
+                    self.ops.push(op);
+                    f.pos = start;
+                    break;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        let used = f.pos;
+        if self.is_complete() {
+            self.pending.clear();
+            Ok(used - buffered)
+        } else {
+            self.pending.drain(..used);
+            Ok(data.len())
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    // the parsed node, once it's complete
+    pub fn node(&self) -> Option<P> {
+        if self.is_complete() {
+            self.values.last().cloned()
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(feature = "std")]
 pub fn node_from_stream<T: Allocator>(
     allocator: &mut T,
     f: &mut Cursor<&[u8]>,
-) -> std::io::Result<T::Ptr> {
+) -> SerializeResult<T::Ptr> {
+    let mut r = Reader {
+        buf: f.get_ref(),
+        pos: f.position() as usize,
+    };
+    let ret = parse_node(allocator, &mut r);
+    f.set_position(r.pos as u64);
+    ret
+}
+
+pub fn node_from_bytes<T: Allocator>(allocator: &mut T, b: &[u8]) -> SerializeResult<T::Ptr> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!("deserialize", len = b.len()).entered();
+    // growing the allocator's storage once up front is a lot cheaper than
+    // growing it incrementally while parsing a large generator. If the blob
+    // is invalid, the parse below reports why
+    if let Ok(counts) = scan_node_counts(b) {
+        allocator.reserve(counts.atoms, counts.pairs, counts.atom_bytes)?;
+    }
+    parse_node(allocator, &mut Reader::new(b))
+}
+
+// one node of a blob parsed by node_from_atom(), in the order it's built
+enum AtomStep {
+    // a slice of the blob
+    Atom(u32, u32),
+    Nil,
+    One,
+    Cons,
+}
+
+// like node_from_bytes(), but for a blob that's already an atom in the
+// allocator (e.g. a program passed in an argument). The atoms are made with
+// new_substr(), so with allocators that support it they share the blob's
+// bytes rather than copying them
+pub fn node_from_atom<T: Allocator>(allocator: &mut T, blob: T::Ptr) -> SerializeResult<T::Ptr> {
+    // the parse borrows the blob from the allocator, so it only records where
+    // the atoms are. They're made afterwards
+    let steps = {
+        let mut f = Reader::new(allocator.atom(&blob));
+        if f.buf.len() > u32::MAX as usize {
+            return Err(SerializeError::LimitExceeded(String::from(
+                "blob too large to share",
+            )));
+        }
+        let mut steps = Vec::new();
+        let mut ops = vec![ParseOp::SExp];
+        while let Some(op) = ops.pop() {
+            match op {
+                ParseOp::SExp => {
+                    let b = f.read_byte()?;
+                    if b == CONS_BOX_MARKER {
+                        ops.push(ParseOp::Cons);
+                        ops.push(ParseOp::SExp);
+                        ops.push(ParseOp::SExp);
+                    } else if b == 0x01 {
+                        steps.push(AtomStep::One);
+                    } else if b == 0x80 {
+                        steps.push(AtomStep::Nil);
+                    } else {
+                        let len = read_atom(&mut f, b)?.len();
+                        steps.push(AtomStep::Atom((f.pos - len) as u32, f.pos as u32));
+                    }
+                }
+                ParseOp::Cons => steps.push(AtomStep::Cons),
+            }
+        }
+        steps
+    };
+
     let mut values: Vec<T::Ptr> = Vec::new();
+    for step in steps {
+        let node = match step {
+            AtomStep::Atom(start, end) => allocator.new_substr(blob.clone(), start, end)?,
+            AtomStep::Nil => allocator.null(),
+            AtomStep::One => allocator.one(),
+            AtomStep::Cons => {
+                let rest = values.pop().unwrap();
+                let first = values.pop().unwrap();
+                allocator.new_pair(first, rest)?
+            }
+        };
+        values.push(node);
+    }
+    Ok(values.pop().unwrap())
+}
+
+pub fn node_to_bytes<T: Allocator>(node: &Node<T>) -> SerializeResult<Vec<u8>> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!("serialize").entered();
+    let mut buffer = Vec::new();
+    write_node(node, &mut buffer)?;
+    Ok(buffer)
+}
+
+// Back references let a serialized node refer to an identical subtree that
+// was serialized earlier, which makes generators that repeat puzzles a lot
+// smaller. A back reference is 0xfe followed by an atom, a path (as in clvm's
+// environment lookup) into the stack of nodes parsed so far. The stack is a
+// list, most recent first, and a pair replaces its two halves on it once both
+// are parsed
+
+// like node_from_bytes(), but also accepts back references
+pub fn node_from_bytes_backrefs<T: Allocator>(
+    allocator: &mut T,
+    b: &[u8],
+) -> SerializeResult<T::Ptr> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!("deserialize", len = b.len(), backrefs = true).entered();
+    let mut f = Reader::new(b);
+    let mut values = allocator.null();
     let mut ops = vec![ParseOp::SExp];
 
-    let mut b = [0; 1];
-    loop {
-        let op = ops.pop();
-        if op.is_none() {
-            break;
-        }
-        match op.unwrap() {
+    while let Some(op) = ops.pop() {
+        match op {
             ParseOp::SExp => {
-                f.read_exact(&mut b)?;
-                if b[0] == CONS_BOX_MARKER {
+                let b = f.read_byte()?;
+                let node = if b == CONS_BOX_MARKER {
                     ops.push(ParseOp::Cons);
                     ops.push(ParseOp::SExp);
                     ops.push(ParseOp::SExp);
-                } else if b[0] == 0x01 {
-                    values.push(allocator.one());
-                } else if b[0] == 0x80 {
-                    values.push(allocator.null());
-                } else if b[0] <= MAX_SINGLE_BYTE {
-                    values.push(allocator.new_atom(&b)?);
+                    continue;
+                } else if b == BACK_REFERENCE {
+                    let b = f.read_byte()?;
+                    let path = read_atom(&mut f, b)?;
+                    traverse_path(allocator, path, &values)
+                        .map_err(|_| SerializeError::InvalidBackReference)?
+                        .1
+                } else if b == 0x01 {
+                    allocator.one()
+                } else if b == 0x80 {
+                    allocator.null()
                 } else {
-                    let blob_size = decode_size(f, b[0])?;
-                    if (f.get_ref().len() as u64) < blob_size {
-                        return Err(bad_encoding());
-                    }
-                    let mut blob: Vec<u8> = vec![0; blob_size as usize];
-                    f.read_exact(&mut blob)?;
-                    values.push(allocator.new_atom(&blob)?);
-                }
+                    let blob = read_atom(&mut f, b)?;
+                    allocator.new_atom(blob)?
+                };
+                values = allocator.new_pair(node, values)?;
             }
             ParseOp::Cons => {
-                // cons
-                let v2 = values.pop();
-                let v1 = values.pop();
-                values.push(allocator.new_pair(v1.unwrap(), v2.unwrap())?);
+                let (right, rest) = pop_value(allocator, &values);
+                let (left, rest) = pop_value(allocator, &rest);
+                let pair = allocator.new_pair(left, right)?;
+                values = allocator.new_pair(pair, rest)?;
             }
         }
     }
-    Ok(values.pop().unwrap())
+    Ok(pop_value(allocator, &values).0)
+}
+
+// the bytes of an atom whose first byte, b, was just read from f
+fn read_atom<'a>(f: &mut Reader<'a>, b: u8) -> SerializeResult<&'a [u8]> {
+    if b == 0x80 {
+        Ok(&[])
+    } else if b <= MAX_SINGLE_BYTE {
+        Ok(&f.buf[f.pos - 1..f.pos])
+    } else {
+        let blob_size = decode_size(f, b)?;
+        f.read_bytes(blob_size)
+    }
+}
+
+fn pop_value<T: Allocator>(allocator: &T, values: &T::Ptr) -> (T::Ptr, T::Ptr) {
+    match allocator.sexp(values) {
+        SExp::Pair(first, rest) => (first, rest),
+        // every Cons comes after the two values it pops
+        SExp::Atom(_) => panic!("parse stack underflow"),
+    }
+}
+
+// the tree hash and (plain) serialized length of every node of a tree, in the
+// order node_to_bytes_backrefs() visits them
+struct NodeInfo<P> {
+    node: P,
+    hash: [u8; 32],
+    serialized_length: u64,
+    children: Option<(usize, usize)>,
+}
+
+fn atom_serialized_length(atom: &[u8]) -> u64 {
+    let size = atom.len() as u64;
+    if size == 0 || (size == 1 && atom[0] <= MAX_SINGLE_BYTE) {
+        1
+    } else if size < 0x40 {
+        1 + size
+    } else if size < 0x2000 {
+        2 + size
+    } else if size < 0x10_0000 {
+        3 + size
+    } else if size < 0x800_0000 {
+        4 + size
+    } else {
+        5 + size
+    }
 }
 
-pub fn node_from_bytes<T: Allocator>(allocator: &mut T, b: &[u8]) -> std::io::Result<T::Ptr> {
-    let mut buffer = Cursor::new(b);
-    node_from_stream(allocator, &mut buffer)
+fn node_infos<T: Allocator>(node: &Node<T>) -> Vec<NodeInfo<T::Ptr>> {
+    let a = node.allocator;
+    let mut infos: Vec<NodeInfo<T::Ptr>> = Vec::new();
+    // indices into infos of the finished children
+    let mut done: Vec<usize> = Vec::new();
+    let mut ops = vec![(node.node.clone(), false)];
+    while let Some((n, children_done)) = ops.pop() {
+        match a.sexp(&n) {
+            SExp::Atom(atom) => {
+                let buf = a.buf(&atom);
+                done.push(infos.len());
+                infos.push(NodeInfo {
+                    hash: hash_blobs(&[&[1], buf]),
+                    serialized_length: atom_serialized_length(buf),
+                    node: n,
+                    children: None,
+                });
+            }
+            SExp::Pair(first, rest) => {
+                if children_done {
+                    let right = done.pop().unwrap();
+                    let left = done.pop().unwrap();
+                    done.push(infos.len());
+                    infos.push(NodeInfo {
+                        hash: hash_blobs(&[&[2], &infos[left].hash, &infos[right].hash]),
+                        serialized_length: 1
+                            + infos[left].serialized_length
+                            + infos[right].serialized_length,
+                        node: n,
+                        children: Some((left, right)),
+                    });
+                } else {
+                    ops.push((n, true));
+                    ops.push((rest, false));
+                    ops.push((first, false));
+                }
+            }
+        }
+    }
+    infos
 }
 
-pub fn node_to_bytes<T: Allocator>(node: &Node<T>) -> std::io::Result<Vec<u8>> {
-    let mut buffer = Cursor::new(Vec::new());
+// serializes node, replacing subtrees that were already serialized with back
+// references wherever that's shorter
+pub fn node_to_bytes_backrefs<T: Allocator>(node: &Node<T>) -> SerializeResult<Vec<u8>> {
+    #[cfg(feature = "tracing")]
+    let _span = tracing::debug_span!("serialize", backrefs = true).entered();
+    let a = node.allocator;
+    let infos = node_infos(node);
+    let mut f: Vec<u8> = Vec::new();
+    let mut lookup = ReadCacheLookup::new();
+    // what the deserializer will do next: parse a node, or cons the last two
+    let mut ops = vec![Some(infos.len() - 1)];
 
-    node_to_stream(node, &mut buffer)?;
-    let vec = buffer.into_inner();
-    Ok(vec)
+    while let Some(op) = ops.pop() {
+        let index = match op {
+            Some(index) => index,
+            None => {
+                lookup.pop2_and_cons();
+                continue;
+            }
+        };
+        let info = &infos[index];
+        if let Some(path) = lookup.find_path(&info.hash, info.serialized_length) {
+            f.put(&[BACK_REFERENCE])?;
+            write_atom(&mut f, &path)?;
+            lookup.push(info.hash);
+            continue;
+        }
+        match info.children {
+            Some((left, right)) => {
+                f.put(&[CONS_BOX_MARKER])?;
+                ops.push(None);
+                ops.push(Some(right));
+                ops.push(Some(left));
+            }
+            None => {
+                if let SExp::Atom(atom) = a.sexp(&info.node) {
+                    write_atom(&mut f, a.buf(&atom))?;
+                }
+                lookup.push(info.hash);
+            }
+        }
+    }
+    Ok(f)
+}
+
+// the limits verify_serialization() checks. 0 means no limit
+#[derive(Clone, Debug, Default)]
+pub struct VerifyLimits {
+    pub max_bytes: usize,
+    pub max_atom_len: usize,
+    // how many pairs may enclose a node
+    pub max_depth: usize,
+    pub allow_backrefs: bool,
+}
+
+// what a blob that passed verify_serialization() contains
+#[derive(Debug, Default, PartialEq)]
+pub struct VerifyReport {
+    pub bytes: usize,
+    pub atoms: usize,
+    pub pairs: usize,
+    pub backrefs: usize,
+    pub max_depth: usize,
+}
+
+// why verify_serialization() rejected a blob
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Violation {
+    UnexpectedEof,
+    InvalidLengthPrefix,
+    // an atom's length prefix is longer than it needs to be, or a single
+    // byte atom below 0x80 has one at all
+    NonCanonicalLength,
+    TrailingBytes,
+    TooLarge,
+    AtomTooLarge,
+    TooDeep,
+    BackReferenceNotAllowed,
+    InvalidBackReference,
+    AllocationFailed,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            Self::UnexpectedEof => "unexpected end of input",
+            Self::InvalidLengthPrefix => "bad encoding",
+            Self::NonCanonicalLength => "non-canonical length prefix",
+            Self::TrailingBytes => "trailing bytes after the node",
+            Self::TooLarge => "blob too large",
+            Self::AtomTooLarge => "atom too large",
+            Self::TooDeep => "nested too deep",
+            Self::BackReferenceNotAllowed => "back reference not allowed",
+            Self::InvalidBackReference => "invalid back reference",
+            Self::AllocationFailed => "allocation failed",
+        };
+        f.write_str(msg)
+    }
+}
+
+impl From<SerializeError> for Violation {
+    fn from(e: SerializeError) -> Self {
+        match e {
+            SerializeError::UnexpectedEof => Self::UnexpectedEof,
+            SerializeError::InvalidLengthPrefix => Self::InvalidLengthPrefix,
+            SerializeError::InvalidBackReference => Self::InvalidBackReference,
+            _ => Self::AllocationFailed,
+        }
+    }
+}
+
+// a violation, and the offset of the node it's in (or the end of the input,
+// if it ended early)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VerifyError {
+    pub offset: usize,
+    pub violation: Violation,
+}
+
+// the length prefix length an atom of the given size has when it's encoded
+// canonically
+fn canonical_prefix_len(atom: &[u8]) -> usize {
+    (atom_serialized_length(atom) - atom.len() as u64) as usize
+}
+
+// checks that b is exactly one node, in canonical form and within the
+// limits. Nodes are only built when back references are allowed, to check
+// where they lead
+pub fn verify_serialization<T: Allocator>(
+    allocator: &mut T,
+    b: &[u8],
+    limits: &VerifyLimits,
+) -> Result<VerifyReport, VerifyError> {
+    let fail = |offset: usize, violation: Violation| Err(VerifyError { offset, violation });
+    if limits.max_bytes != 0 && b.len() > limits.max_bytes {
+        return fail(limits.max_bytes, Violation::TooLarge);
+    }
+    let mut f = Reader::new(b);
+    let mut report = VerifyReport {
+        bytes: b.len(),
+        ..Default::default()
+    };
+    let mut values = allocator.null();
+    let mut ops = vec![ParseOp::SExp];
+    let mut depth = 0;
+
+    while let Some(op) = ops.pop() {
+        let start = f.pos;
+        let err = |e: SerializeError| {
+            let violation = Violation::from(e);
+            let offset = match violation {
+                Violation::UnexpectedEof => b.len(),
+                _ => start,
+            };
+            VerifyError { offset, violation }
+        };
+        match op {
+            ParseOp::SExp => {
+                if limits.max_depth != 0 && depth > limits.max_depth {
+                    return fail(start, Violation::TooDeep);
+                }
+                report.max_depth = report.max_depth.max(depth);
+                let byte = f.read_byte().map_err(err)?;
+                if byte == CONS_BOX_MARKER {
+                    report.pairs += 1;
+                    depth += 1;
+                    ops.push(ParseOp::Cons);
+                    ops.push(ParseOp::SExp);
+                    ops.push(ParseOp::SExp);
+                    continue;
+                }
+                let (atom_start, byte) = if byte == BACK_REFERENCE {
+                    if !limits.allow_backrefs {
+                        return fail(start, Violation::BackReferenceNotAllowed);
+                    }
+                    (f.pos, f.read_byte().map_err(err)?)
+                } else {
+                    (start, byte)
+                };
+                let atom = read_atom(&mut f, byte).map_err(err)?;
+                if f.pos - atom_start - atom.len() != canonical_prefix_len(atom) {
+                    return fail(start, Violation::NonCanonicalLength);
+                }
+                let is_backref = start != atom_start;
+                if is_backref {
+                    report.backrefs += 1;
+                } else {
+                    report.atoms += 1;
+                    if limits.max_atom_len != 0 && atom.len() > limits.max_atom_len {
+                        return fail(start, Violation::AtomTooLarge);
+                    }
+                }
+                if !limits.allow_backrefs {
+                    continue;
+                }
+                let node = if is_backref {
+                    traverse_path(allocator, atom, &values)
+                        .map_err(|_| err(SerializeError::InvalidBackReference))?
+                        .1
+                } else {
+                    allocator
+                        .new_atom(atom)
+                        .map_err(|e| err(SerializeError::from(e)))?
+                };
+                values = allocator
+                    .new_pair(node, values)
+                    .map_err(|e| err(SerializeError::from(e)))?;
+            }
+            ParseOp::Cons => {
+                depth -= 1;
+                if !limits.allow_backrefs {
+                    continue;
+                }
+                let (right, rest) = pop_value(allocator, &values);
+                let (left, rest) = pop_value(allocator, &rest);
+                let pair = allocator
+                    .new_pair(left, right)
+                    .map_err(|e| err(SerializeError::from(e)))?;
+                values = allocator
+                    .new_pair(pair, rest)
+                    .map_err(|e| err(SerializeError::from(e)))?;
+            }
+        }
+    }
+    if f.pos != b.len() {
+        return fail(f.pos, Violation::TrailingBytes);
+    }
+    Ok(report)
 }
 
 #[test]
@@ -227,12 +991,12 @@ fn test_encode_size() {
 #[test]
 fn test_decode_size() {
     // single-byte length prefix
-    let mut buffer = Cursor::new(&[]);
+    let mut buffer = Reader::new(&[]);
     assert_eq!(decode_size(&mut buffer, 0x80 | 0x20).unwrap(), 0x20);
 
     // two-byte length prefix
     let first = 0b11001111;
-    let mut buffer = Cursor::new(&[0xaa]);
+    let mut buffer = Reader::new(&[0xaa]);
     assert_eq!(decode_size(&mut buffer, first).unwrap(), 0xfaa);
 }
 
@@ -242,24 +1006,24 @@ fn test_large_decode_size() {
     // We don't support atoms this large and we should fail before attempting to
     // allocate this much memory
     let first = 0b11111110;
-    let mut buffer = Cursor::new(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+    let mut buffer = Reader::new(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
     let ret = decode_size(&mut buffer, first);
     let e = ret.unwrap_err();
-    assert_eq!(e.kind(), bad_encoding().kind());
+    assert!(matches!(e, SerializeError::InvalidLengthPrefix));
     assert_eq!(e.to_string(), "bad encoding");
 
     // this is still too large
     let first = 0b11111100;
-    let mut buffer = Cursor::new(&[0x4, 0, 0, 0, 0]);
+    let mut buffer = Reader::new(&[0x4, 0, 0, 0, 0]);
     let ret = decode_size(&mut buffer, first);
     let e = ret.unwrap_err();
-    assert_eq!(e.kind(), bad_encoding().kind());
+    assert!(matches!(e, SerializeError::InvalidLengthPrefix));
     assert_eq!(e.to_string(), "bad encoding");
 
     // But this is *just* within what we support
     // Still a very large blob, probably enough for a DoS attack
     let first = 0b11111100;
-    let mut buffer = Cursor::new(&[0x3, 0xff, 0xff, 0xff, 0xff]);
+    let mut buffer = Reader::new(&[0x3, 0xff, 0xff, 0xff, 0xff]);
     assert_eq!(decode_size(&mut buffer, first).unwrap(), 0x3ffffffff);
 }
 
@@ -267,8 +1031,283 @@ fn test_large_decode_size() {
 fn test_truncated_decode_size() {
     // the stream is truncated
     let first = 0b11111100;
-    let mut buffer = Cursor::new(&[0x4, 0, 0, 0]);
+    let mut buffer = Reader::new(&[0x4, 0, 0, 0]);
     let ret = decode_size(&mut buffer, first);
     let e = ret.unwrap_err();
-    assert_eq!(e.kind(), std::io::ErrorKind::UnexpectedEof);
+    assert!(matches!(e, SerializeError::UnexpectedEof));
+}
+
+#[test]
+fn test_node_from_bytes_errors() {
+    use crate::int_allocator::IntAllocator;
+    let mut a = IntAllocator::new();
+
+    // a cons box with only one child
+    let e = node_from_bytes(&mut a, &[0xff, 0x01]).unwrap_err();
+    assert!(matches!(e, SerializeError::UnexpectedEof));
+
+    // an atom claiming more bytes than the blob holds
+    let e = node_from_bytes(&mut a, &[0x83, 0x01]).unwrap_err();
+    assert!(matches!(e, SerializeError::UnexpectedEof));
+
+    // a length prefix with too many size bytes
+    let e = node_from_bytes(&mut a, &[0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]).unwrap_err();
+    assert!(matches!(e, SerializeError::InvalidLengthPrefix));
+}
+
+#[test]
+fn test_node_from_atom() {
+    use crate::int_allocator::IntAllocator;
+
+    // ("foo" (q . "a") () . 0x1234)
+    let bytes = hex::decode("ff83666f6fffff0161ff80821234").unwrap();
+    // room for the blob, and nothing more
+    let mut a = IntAllocator::with_limits(bytes.len(), 100);
+    let blob = a.new_atom(&bytes).unwrap();
+    let node = node_from_atom(&mut a, blob).unwrap();
+    assert_eq!(node_to_bytes(&Node::new(&a, node)).unwrap(), bytes);
+    // the atoms took none of the byte limit: long ones share the blob's
+    // bytes, and short ones are kept in their slots
+    assert!(a.new_atom(&[1]).is_err());
+
+    let mut a = IntAllocator::new();
+    let blob = a.new_atom(&[0xff, 0x83, 0x01]).unwrap();
+    let e = node_from_atom(&mut a, blob).unwrap_err();
+    assert!(matches!(e, SerializeError::UnexpectedEof));
+    let blob = a.new_atom(&[0x80]).unwrap();
+    assert_eq!(node_from_atom(&mut a, blob).unwrap(), a.null());
+}
+
+#[test]
+fn test_scan_node_counts() {
+    use crate::int_allocator::IntAllocator;
+
+    // nil, one and 0x80 are preallocated
+    assert_eq!(scan_node_counts(&[0x80]).unwrap(), NodeCounts::default());
+    assert_eq!(scan_node_counts(&[0x01]).unwrap(), NodeCounts::default());
+
+    // (2 . ("foo" . ()))
+    let blob = [0xff, 0x02, 0xff, 0x83, b'f', b'o', b'o', 0x80];
+    assert_eq!(
+        scan_node_counts(&blob).unwrap(),
+        NodeCounts {
+            atoms: 2,
+            pairs: 2,
+            atom_bytes: 4,
+        }
+    );
+
+    assert!(matches!(
+        scan_node_counts(&[0xff, 0x80]),
+        Err(SerializeError::UnexpectedEof)
+    ));
+    assert!(matches!(
+        scan_node_counts(&[0x83, b'f']),
+        Err(SerializeError::UnexpectedEof)
+    ));
+
+    let mut a = IntAllocator::new();
+    let node = node_from_bytes(&mut a, &blob).unwrap();
+    assert_eq!(node_to_bytes(&Node::new(&a, node)).unwrap(), blob.to_vec());
+}
+
+#[test]
+fn test_incremental_parser() {
+    use crate::int_allocator::IntAllocator;
+
+    // ("foo" 2 . "a long enough atom to need a length prefix")
+    let mut blob = vec![0xff, 0x83, b'f', b'o', b'o', 0xff, 0x02, 0xc0, 0x50];
+    blob.extend_from_slice(&[b'x'; 0x50]);
+
+    for chunk in &[1, 2, 7, blob.len()] {
+        let mut a = IntAllocator::new();
+        let mut p = IncrementalParser::new();
+        for piece in blob.chunks(*chunk) {
+            assert!(!p.is_complete());
+            assert!(p.node().is_none());
+            assert_eq!(p.feed(&mut a, piece).unwrap(), piece.len());
+        }
+        assert!(p.is_complete());
+        let node = p.node().unwrap();
+        assert_eq!(node_to_bytes(&Node::new(&a, node)).unwrap(), blob);
+        assert_eq!(p.feed(&mut a, &[0x80]).unwrap(), 0);
+    }
+
+    // the bytes after the node aren't used
+    let mut a = IntAllocator::new();
+    let mut p = IncrementalParser::new();
+    assert_eq!(p.feed(&mut a, &[0xff, 0x83, b'f']).unwrap(), 3);
+    assert_eq!(p.feed(&mut a, &[b'o', b'o', 0x80, 0x01, 0x02]).unwrap(), 3);
+    let node = p.node().unwrap();
+    assert_eq!(
+        node_to_bytes(&Node::new(&a, node)).unwrap(),
+        vec![0xff, 0x83, b'f', b'o', b'o', 0x80]
+    );
+
+    let mut p = IncrementalParser::new();
+    assert!(matches!(
+        p.feed(&mut a, &[0xff, 0xfe, 0, 0, 0, 0, 0, 0]),
+        Err(SerializeError::InvalidLengthPrefix)
+    ));
+}
+
+#[test]
+fn test_backrefs() {
+    use crate::int_allocator::IntAllocator;
+
+    let mut a = IntAllocator::new();
+    let plain = |a: &IntAllocator, node| hex::encode(node_to_bytes(&Node::new(a, node)).unwrap());
+
+    // ("foo" "foo"), with the rest referring to the whole stack, ("foo")
+    let node = node_from_bytes_backrefs(&mut a, &hex::decode("ff83666f6ffe01").unwrap()).unwrap();
+    assert_eq!(plain(&a, node), "ff83666f6fff83666f6f80");
+    // ("foo" . "foo"), with the rest referring to the first item on the stack
+    let node = node_from_bytes_backrefs(&mut a, &hex::decode("ff83666f6ffe02").unwrap()).unwrap();
+    assert_eq!(plain(&a, node), "ff83666f6f83666f6f");
+    // plain serializations parse the same
+    let node = node_from_bytes_backrefs(&mut a, &hex::decode("ff0180").unwrap()).unwrap();
+    assert_eq!(plain(&a, node), "ff0180");
+
+    // a path into an atom
+    assert!(matches!(
+        node_from_bytes_backrefs(&mut a, &hex::decode("ff83666f6ffe05").unwrap()),
+        Err(SerializeError::InvalidBackReference)
+    ));
+    assert!(matches!(
+        node_from_bytes_backrefs(&mut a, &hex::decode("fffe").unwrap()),
+        Err(SerializeError::UnexpectedEof)
+    ));
+
+    // a list repeating an atom, and a list of those lists
+    let atom = a.new_atom(&[0x42; 40]).unwrap();
+    let mut list = a.null();
+    for _ in 0..5 {
+        list = a.new_pair(atom, list).unwrap();
+    }
+    let mut lists = a.null();
+    for _ in 0..3 {
+        lists = a.new_pair(list, lists).unwrap();
+    }
+    for node in &[atom, list, lists, a.null(), a.one()] {
+        let compressed = node_to_bytes_backrefs(&Node::new(&a, *node)).unwrap();
+        let plain_bytes = node_to_bytes(&Node::new(&a, *node)).unwrap();
+        assert!(compressed.len() <= plain_bytes.len());
+        let back = node_from_bytes_backrefs(&mut a, &compressed).unwrap();
+        assert_eq!(node_to_bytes(&Node::new(&a, back)).unwrap(), plain_bytes);
+    }
+    let compressed = node_to_bytes_backrefs(&Node::new(&a, lists)).unwrap();
+    assert!(compressed.len() < 70);
+}
+
+#[test]
+fn test_verify_serialization() {
+    use crate::int_allocator::IntAllocator;
+
+    let verify = |blob: &str, limits: &VerifyLimits| {
+        let mut a = IntAllocator::new();
+        verify_serialization(&mut a, &hex::decode(blob).unwrap(), limits)
+    };
+    let fail = |offset, violation| Err(VerifyError { offset, violation });
+    let any = VerifyLimits::default();
+    let backrefs = VerifyLimits {
+        allow_backrefs: true,
+        ..Default::default()
+    };
+
+    // ("foo" . "foo")
+    assert_eq!(
+        verify("ff83666f6f83666f6f", &any),
+        Ok(VerifyReport {
+            bytes: 9,
+            atoms: 2,
+            pairs: 1,
+            backrefs: 0,
+            max_depth: 1,
+        })
+    );
+    assert_eq!(verify("80", &any).unwrap().atoms, 1);
+
+    // malformed
+    assert_eq!(
+        verify("ff83666f6f", &any),
+        fail(5, Violation::UnexpectedEof)
+    );
+    assert_eq!(verify("ff8383", &any), fail(3, Violation::UnexpectedEof));
+    assert_eq!(verify("8080", &any), fail(1, Violation::TrailingBytes));
+    assert_eq!(
+        verify("fffe0180", &any),
+        fail(1, Violation::BackReferenceNotAllowed)
+    );
+
+    // not canonical
+    assert_eq!(
+        verify("ff8105", &any),
+        fail(1, Violation::NonCanonicalLength)
+    );
+    assert_eq!(
+        verify("ff80c00105", &any),
+        fail(2, Violation::NonCanonicalLength)
+    );
+    assert_eq!(verify("c000", &any), fail(0, Violation::NonCanonicalLength));
+    assert_eq!(verify("8180", &any).unwrap().atoms, 1);
+
+    // limits
+    let limits = VerifyLimits {
+        max_bytes: 8,
+        ..Default::default()
+    };
+    assert_eq!(
+        verify("ff83666f6f83666f6f", &limits),
+        fail(8, Violation::TooLarge)
+    );
+    let limits = VerifyLimits {
+        max_atom_len: 2,
+        ..Default::default()
+    };
+    assert_eq!(
+        verify("ff0183666f6f", &limits),
+        fail(2, Violation::AtomTooLarge)
+    );
+    let limits = VerifyLimits {
+        max_depth: 1,
+        ..Default::default()
+    };
+    assert_eq!(verify("ff01ff0280", &limits), fail(3, Violation::TooDeep));
+    assert!(verify("ff0102", &limits).is_ok());
+
+    // back references
+    let r = verify("ff83666f6ffe02", &backrefs).unwrap();
+    assert_eq!((r.atoms, r.backrefs), (1, 1));
+    assert_eq!(
+        verify("ff83666f6ffe04", &backrefs),
+        fail(5, Violation::InvalidBackReference)
+    );
+    assert_eq!(
+        verify("ff83666f6ffe8102", &backrefs),
+        fail(5, Violation::NonCanonicalLength)
+    );
+}
+
+#[test]
+fn test_tree_hash_from_bytes() {
+    use crate::int_allocator::IntAllocator;
+    use crate::tree_hash::tree_hash;
+
+    let mut a = IntAllocator::new();
+    for blob in &[
+        "80",
+        "01",
+        "8400000001",
+        "ff83666f6fff80ff0180",
+        "ffff0102ff8203e880",
+    ] {
+        let blob = hex::decode(blob).unwrap();
+        let node = node_from_bytes(&mut a, &blob).unwrap();
+        assert_eq!(
+            tree_hash_from_bytes(&blob).unwrap(),
+            tree_hash(&Node::new(&a, node))
+        );
+    }
+    assert!(tree_hash_from_bytes(&[0xff, 0x01]).is_err());
+    assert!(tree_hash_from_bytes(&[0xff, 0x01, 0xfe, 0x02]).is_err());
 }
@@ -0,0 +1,73 @@
+// The sha256 implementation behind the sha256 operator and tree hashing.
+//
+// On unix this is openssl, which checks at startup for the x86 SHA extensions
+// (SHA-NI) and the ARMv8 crypto extensions and uses them when available.
+// Everywhere else (windows, wasm) it's the sha2 crate, which does the same
+// runtime detection (through cpufeatures). Both fall back to a portable
+// implementation on other CPUs.
+
+#[cfg(unix)]
+use openssl::sha;
+
+#[cfg(not(unix))]
+use sha2::Digest;
+
+#[cfg(unix)]
+pub struct Sha256(sha::Sha256);
+
+#[cfg(not(unix))]
+pub struct Sha256(sha2::Sha256);
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sha256 {
+    #[cfg(unix)]
+    pub fn new() -> Self {
+        Sha256(sha::Sha256::new())
+    }
+
+    #[cfg(not(unix))]
+    pub fn new() -> Self {
+        Sha256(sha2::Sha256::new())
+    }
+
+    pub fn update(&mut self, buf: &[u8]) {
+        self.0.update(buf);
+    }
+
+    #[cfg(unix)]
+    pub fn finish(self) -> [u8; 32] {
+        self.0.finish()
+    }
+
+    #[cfg(not(unix))]
+    pub fn finish(self) -> [u8; 32] {
+        self.0.finalize().into()
+    }
+}
+
+// hash the concatenation of the blobs
+pub fn sha256(blobs: &[&[u8]]) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    for b in blobs {
+        hasher.update(b);
+    }
+    hasher.finish()
+}
+
+#[test]
+fn test_sha256() {
+    assert_eq!(
+        hex::encode(sha256(&[])),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+    assert_eq!(
+        hex::encode(sha256(&[b"abc"])),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+    );
+    assert_eq!(sha256(&[b"a", b"", b"bc"]), sha256(&[b"abc"]));
+}
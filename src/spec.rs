@@ -0,0 +1,436 @@
+// Runs CLVM specification test vectors in the format of the clvm_tools test
+// suite: a directory tree of .txt files, each with a command line on its
+// first line and the output it should print on the rest, e.g.
+//
+//   brun -c '(+ (q . 2) (q . 5))'
+//   cost = 796
+//   7
+//
+// brun, opc and opd commands are run with this crate's evaluator and
+// serializer, and their output compared. Other commands (e.g. run, which
+// needs the chialisp compiler) and flags this runner doesn't know are
+// skipped. Results are tallied by the directory a file is in, so a checkout
+// of the upstream vectors gives a row per tool, and tests/spec a row each
+// for operators, serialization and unknown operators
+
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::allocator::Allocator;
+use crate::binutils::{assemble, disassemble};
+use crate::cost::Cost;
+use crate::int_allocator::IntAllocator;
+use crate::io_utils::decode_blob;
+use crate::node::Node;
+use crate::op_table::{op_table_for, HostOpTable, CHIA_DIALECT};
+use crate::recording::Strict;
+use crate::run_program::{run_program, OperatorHandler};
+use crate::serialize::{node_from_bytes, node_to_bytes};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpecCase {
+    // the file, relative to the directory the cases were loaded from
+    pub file: String,
+    // the directory the file is in, "." at the top
+    pub category: String,
+    pub command: Vec<String>,
+    pub expected: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Verdict {
+    Pass,
+    // with the output we printed instead
+    Fail(String),
+    // with the reason the case couldn't be run
+    Skip(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpecResult {
+    pub case: SpecCase,
+    pub verdict: Verdict,
+}
+
+impl fmt::Display for SpecResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let c = &self.case;
+        match &self.verdict {
+            Verdict::Pass => write!(f, "{}: pass", c.file),
+            Verdict::Fail(got) => write!(
+                f,
+                "{}: FAIL\n  {}\n  expected: {}\n  got: {}",
+                c.file,
+                c.command.join(" "),
+                c.expected.replace('\n', "\\n"),
+                got.replace('\n', "\\n")
+            ),
+            Verdict::Skip(why) => write!(f, "{}: skipped, {}", c.file, why),
+        }
+    }
+}
+
+// the number of cases that passed, failed and were skipped, by category
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SpecMatrix {
+    pub rows: BTreeMap<String, [usize; 3]>,
+}
+
+impl SpecMatrix {
+    pub fn new(results: &[SpecResult]) -> Self {
+        let mut rows = BTreeMap::new();
+        for r in results {
+            let row: &mut [usize; 3] = rows.entry(r.case.category.clone()).or_default();
+            let column = match r.verdict {
+                Verdict::Pass => 0,
+                Verdict::Fail(_) => 1,
+                Verdict::Skip(_) => 2,
+            };
+            row[column] += 1;
+        }
+        SpecMatrix { rows }
+    }
+
+    pub fn totals(&self) -> [usize; 3] {
+        let mut ret = [0; 3];
+        for row in self.rows.values() {
+            for i in 0..3 {
+                ret[i] += row[i];
+            }
+        }
+        ret
+    }
+
+    pub fn failed(&self) -> usize {
+        self.totals()[1]
+    }
+}
+
+impl fmt::Display for SpecMatrix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.rows.keys().map(|k| k.len()).max().unwrap_or(0).max(8);
+        writeln!(
+            f,
+            "{:<w$} {:>6} {:>6} {:>6}",
+            "",
+            "pass",
+            "fail",
+            "skip",
+            w = width
+        )?;
+        let total = ("total".to_string(), self.totals());
+        for (name, row) in self
+            .rows
+            .iter()
+            .chain(std::iter::once((&total.0, &total.1)))
+        {
+            writeln!(
+                f,
+                "{:<w$} {:>6} {:>6} {:>6}",
+                name,
+                row[0],
+                row[1],
+                row[2],
+                w = width
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// splits a command line into arguments the way a shell would, for the
+// quoting the vectors use: '...' is literal, and in "..." and unquoted text a
+// backslash escapes the next character
+pub fn split_command(line: &str) -> Result<Vec<String>, String> {
+    let mut ret = Vec::new();
+    let mut arg: Option<String> = None;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(arg) = arg.take() {
+                    ret.push(arg);
+                }
+            }
+            '\'' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => arg.push(c),
+                        None => return Err("unterminated '".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                let arg = arg.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c) => arg.push(c),
+                            None => return Err("unterminated \"".to_string()),
+                        },
+                        Some(c) => arg.push(c),
+                        None => return Err("unterminated \"".to_string()),
+                    }
+                }
+            }
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    arg.get_or_insert_with(String::new).push(c);
+                }
+            }
+            c => arg.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(arg) = arg {
+        ret.push(arg);
+    }
+    Ok(ret)
+}
+
+fn parse_case(dir: &Path, path: &Path, text: &str) -> Result<SpecCase, String> {
+    let file = path.strip_prefix(dir).unwrap_or(path);
+    let category = match file.parent() {
+        Some(p) if p != Path::new("") => p.to_string_lossy().to_string(),
+        _ => ".".to_string(),
+    };
+    let file = file.to_string_lossy().to_string();
+    let mut lines = text.splitn(2, '\n');
+    let command =
+        split_command(lines.next().unwrap_or("")).map_err(|e| format!("{}: {}", file, e))?;
+    if command.is_empty() {
+        return Err(format!("{}: missing command", file));
+    }
+    Ok(SpecCase {
+        file,
+        category,
+        command,
+        expected: lines.next().unwrap_or("").trim_end().to_string(),
+    })
+}
+
+fn find_txt_files(dir: &Path, out: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_txt_files(&path, out)?;
+        } else if path.extension() == Some(OsStr::new("txt")) {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+// the cases in every .txt file under a directory, ordered by path
+pub fn load_spec_cases(dir: &Path) -> io::Result<Vec<SpecCase>> {
+    let mut paths = Vec::new();
+    find_txt_files(dir, &mut paths)?;
+    paths.sort();
+
+    let mut ret = Vec::new();
+    for path in paths {
+        let text = fs::read_to_string(&path)?;
+        let case = parse_case(dir, &path, &text)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        ret.push(case);
+    }
+    Ok(ret)
+}
+
+// negative numbers aren't flags
+fn is_flag(arg: &str) -> bool {
+    arg.len() > 1 && arg.starts_with('-') && !arg[1..].starts_with(|c: char| c.is_ascii_digit())
+}
+
+fn parse_program(a: &mut IntAllocator, arg: &str, hex: bool) -> Result<i32, String> {
+    if hex {
+        node_from_bytes(a, &decode_blob(arg.as_bytes())).map_err(|e| e.to_string())
+    } else {
+        assemble(a, arg)
+    }
+}
+
+fn brun(args: &[String]) -> Result<String, String> {
+    let mut cost = false;
+    let mut hex = false;
+    let mut dump = false;
+    let mut strict = false;
+    let mut max_cost = Cost::ZERO;
+    let mut positional = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-c" | "--cost" => cost = true,
+            "-x" | "--hex" => hex = true,
+            "-d" | "--dump" => dump = true,
+            "--strict" => strict = true,
+            "-m" | "--max-cost" => {
+                let v = args.next().ok_or("missing max cost")?;
+                max_cost = v.parse().map_err(|_| format!("invalid max cost {}", v))?;
+            }
+            flag if is_flag(flag) => return Err(format!("unsupported flag {}", flag)),
+            _ => positional.push(arg),
+        }
+    }
+    if positional.is_empty() || positional.len() > 2 {
+        return Err("expected a program and optionally an environment".to_string());
+    }
+
+    let mut a = IntAllocator::new();
+    let program = match parse_program(&mut a, positional[0], hex) {
+        Ok(p) => p,
+        Err(e) => return Ok(format!("FAIL: {}", e)),
+    };
+    let env = match positional.get(1) {
+        Some(env) => match parse_program(&mut a, env, hex) {
+            Ok(e) => e,
+            Err(e) => return Ok(format!("FAIL: {}", e)),
+        },
+        None => a.null(),
+    };
+    let table = op_table_for(CHIA_DIALECT.opcodes);
+    let operators: Box<dyn OperatorHandler<IntAllocator>> = if strict {
+        Box::new(HostOpTable {
+            table,
+            host: Strict,
+        })
+    } else {
+        Box::new(table)
+    };
+    let quote_kw = CHIA_DIALECT.quote_kw;
+    let apply_kw = CHIA_DIALECT.apply_kw;
+    Ok(
+        match run_program(
+            &mut a, &program, &env, quote_kw, apply_kw, max_cost, operators, None,
+        ) {
+            Ok(r) => {
+                let result = if dump {
+                    hex::encode(node_to_bytes(&Node::new(&a, r.1)).map_err(|e| e.to_string())?)
+                } else {
+                    disassemble(&Node::new(&a, r.1))
+                };
+                if cost {
+                    format!("cost = {}\n{}", r.0, result)
+                } else {
+                    result
+                }
+            }
+            Err(e) => format!("FAIL: {} {}", e.1, disassemble(&Node::new(&a, e.0))),
+        },
+    )
+}
+
+// opc serializes each program given in text form, and opd shows each
+// serialized one as text
+fn opc_opd(args: &[String], opc: bool) -> Result<String, String> {
+    let mut out = Vec::new();
+    for arg in args {
+        if is_flag(arg) {
+            return Err(format!("unsupported flag {}", arg));
+        }
+        let mut a = IntAllocator::new();
+        let line = if opc {
+            assemble(&mut a, arg).and_then(|node| {
+                node_to_bytes(&Node::new(&a, node))
+                    .map(hex::encode)
+                    .map_err(|e| e.to_string())
+            })
+        } else {
+            node_from_bytes(&mut a, &decode_blob(arg.as_bytes()))
+                .map(|node| disassemble(&Node::new(&a, node)))
+                .map_err(|e| e.to_string())
+        };
+        out.push(line.unwrap_or_else(|e| format!("FAIL: {}", e)));
+    }
+    Ok(out.join("\n"))
+}
+
+// what a command prints, or why it can't be run here
+pub fn run_command(command: &[String]) -> Result<String, String> {
+    match command.split_first() {
+        Some((tool, args)) if tool == "brun" => brun(args),
+        Some((tool, args)) if tool == "opc" => opc_opd(args, true),
+        Some((tool, args)) if tool == "opd" => opc_opd(args, false),
+        Some((tool, _)) => Err(format!("unsupported command {}", tool)),
+        None => Err("missing command".to_string()),
+    }
+}
+
+pub fn check_spec_cases(cases: &[SpecCase]) -> Vec<SpecResult> {
+    cases
+        .iter()
+        .map(|case| {
+            let verdict = match run_command(&case.command) {
+                Ok(out) if out.trim_end() == case.expected => Verdict::Pass,
+                Ok(out) => Verdict::Fail(out.trim_end().to_string()),
+                Err(why) => Verdict::Skip(why),
+            };
+            SpecResult {
+                case: case.clone(),
+                verdict,
+            }
+        })
+        .collect()
+}
+
+// runs every case under a directory
+pub fn run_spec(dir: &Path) -> io::Result<(Vec<SpecResult>, SpecMatrix)> {
+    let results = check_spec_cases(&load_spec_cases(dir)?);
+    let matrix = SpecMatrix::new(&results);
+    Ok((results, matrix))
+}
+
+pub fn spec_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("spec")
+}
+
+#[test]
+fn test_split_command() {
+    assert_eq!(
+        split_command(r#"brun  -c '(+ 2 "a b")' "(\"x\")" a\ b"#).unwrap(),
+        vec!["brun", "-c", "(+ 2 \"a b\")", "(\"x\")", "a b"]
+    );
+    assert_eq!(split_command("opd ''").unwrap(), vec!["opd", ""]);
+    assert!(split_command("brun '(q").is_err());
+}
+
+#[test]
+fn test_run_command() {
+    let run = |line: &str| run_command(&split_command(line).unwrap());
+    assert_eq!(run("brun '(+ 2 5)' '(3 4)'").unwrap(), "7");
+    assert_eq!(run("brun -c '(+ 2 5)' '(3 4)'").unwrap(), "cost = 856\n7");
+    assert_eq!(run("brun -d '(q . 7)'").unwrap(), "07");
+    assert_eq!(run("brun -x ff10ff02ff0580 ff03ff0480").unwrap(), "7");
+    assert_eq!(run("brun '(0x7f)'").unwrap(), "()");
+    assert_eq!(
+        run("brun --strict '(0x7f)'").unwrap(),
+        "FAIL: unimplemented operator 127"
+    );
+    assert_eq!(run("opc '(q . 1)' -1").unwrap(), "ff0101\n81ff");
+    assert_eq!(run("opd ff0101 81ff").unwrap(), "(q . 1)\n-1");
+    assert_eq!(run("opd ff01").unwrap(), "FAIL: unexpected end of input");
+    assert!(run("brun -v '(q . 1)'").is_err());
+    assert!(run("run '(mod () 1)'").is_err());
+}
+
+#[test]
+fn test_shipped_spec() {
+    let (results, matrix) = run_spec(&spec_dir()).unwrap();
+    for r in &results {
+        if r.verdict != Verdict::Pass {
+            println!("{}", r);
+        }
+    }
+    for category in &["operators", "serialization", "unknown_ops"] {
+        assert!(matrix.rows.contains_key(*category), "no {}", category);
+    }
+    assert_eq!(matrix.totals(), [results.len(), 0, 0]);
+}
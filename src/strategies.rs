@@ -0,0 +1,175 @@
+// proptest strategies for CLVM values, for property testing puzzle logic
+// against realistic structures. Values are generated serialized, so they
+// don't depend on an allocator; node_from_bytes() turns them into nodes.
+// They shrink towards smaller atoms and trees. Only compiled with the
+// "testing" feature
+//
+//   proptest! {
+//       #[test]
+//       fn test_puzzle(solution in list(int_atom(), 5)) {
+//           let mut a = IntAllocator::new();
+//           let solution = node_from_bytes(&mut a, &solution).unwrap();
+//           ...
+//       }
+//   }
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use crate::allocator::Allocator;
+use crate::int_allocator::IntAllocator;
+use crate::node::Node;
+use crate::serialize::node_to_bytes;
+
+// the canonical encoding of an integer: big endian two's complement, with
+// no redundant leading bytes, and 0 as the empty atom
+pub fn int_to_atom(v: i64) -> Vec<u8> {
+    let bytes = v.to_be_bytes();
+    let mut start = 0;
+    while start < bytes.len() {
+        let redundant = match bytes[start] {
+            0x00 => start + 1 == bytes.len() || bytes[start + 1] & 0x80 == 0,
+            0xff => start + 1 < bytes.len() && bytes[start + 1] & 0x80 != 0,
+            _ => false,
+        };
+        if !redundant {
+            break;
+        }
+        start += 1;
+    }
+    bytes[start..].to_vec()
+}
+
+fn serialize_atom(atom: &[u8]) -> Vec<u8> {
+    let mut a = IntAllocator::new();
+    let node = a.new_atom(atom).unwrap();
+    node_to_bytes(&Node::new(&a, node)).unwrap()
+}
+
+fn serialize_pair(first: &[u8], rest: &[u8]) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(1 + first.len() + rest.len());
+    ret.push(0xff);
+    ret.extend_from_slice(first);
+    ret.extend_from_slice(rest);
+    ret
+}
+
+// mostly small integers, and the boundaries of byte lengths
+fn interesting_int() -> impl Strategy<Value = i64> {
+    prop_oneof![
+        -128_i64..=127,
+        prop::sample::select(vec![
+            0x80,
+            -0x81,
+            0x7fff,
+            0x8000,
+            -0x8001,
+            0x7fff_ffff,
+            0x8000_0000,
+            i64::MAX,
+            i64::MIN,
+        ]),
+        any::<i64>(),
+    ]
+}
+
+// canonical integers
+pub fn int_atom() -> impl Strategy<Value = Vec<u8>> {
+    interesting_int().prop_map(int_to_atom)
+}
+
+// atoms of up to max_len bytes, raw rather than serialized. Integers, 32 byte
+// hashes and arbitrary bytes
+pub fn atom(max_len: usize) -> impl Strategy<Value = Vec<u8>> {
+    // integers that don't fit are clamped to the largest ones that do
+    let (min, max) = match 8 * max_len.min(8) as u32 {
+        0 => (0, 0),
+        64 => (i64::MIN, i64::MAX),
+        bits => (-(1_i64 << (bits - 1)), (1_i64 << (bits - 1)) - 1),
+    };
+    prop_oneof![
+        2 => interesting_int().prop_map(move |v| int_to_atom(v.clamp(min, max))),
+        1 => vec(any::<u8>(), max_len.min(32)),
+        2 => vec(any::<u8>(), 0..=max_len),
+    ]
+}
+
+// serialized atoms of up to max_len bytes
+pub fn atom_tree(max_len: usize) -> impl Strategy<Value = Vec<u8>> {
+    atom(max_len).prop_map(|a| serialize_atom(&a))
+}
+
+// serialized proper lists of up to max_len items. The items come from
+// `item`, which generates serialized trees
+pub fn list<S>(item: S, max_len: usize) -> impl Strategy<Value = Vec<u8>>
+where
+    S: Strategy<Value = Vec<u8>>,
+{
+    vec(item, 0..=max_len).prop_map(|items| {
+        let mut ret = Vec::new();
+        for item in items {
+            ret.push(0xff);
+            ret.extend_from_slice(&item);
+        }
+        ret.push(0x80);
+        ret
+    })
+}
+
+// serialized trees of pairs at most `depth` deep, with about `size` nodes,
+// of atoms up to 64 bytes
+pub fn tree(depth: u32, size: u32) -> impl Strategy<Value = Vec<u8>> {
+    atom_tree(64).prop_recursive(depth, size, 2, |inner| {
+        prop_oneof![
+            (inner.clone(), inner.clone()).prop_map(|(f, r)| serialize_pair(&f, &r)),
+            list(inner, 4),
+        ]
+    })
+}
+
+#[test]
+fn test_int_to_atom() {
+    assert_eq!(int_to_atom(0), Vec::<u8>::new());
+    assert_eq!(int_to_atom(1), [1]);
+    assert_eq!(int_to_atom(-1), [0xff]);
+    assert_eq!(int_to_atom(127), [0x7f]);
+    assert_eq!(int_to_atom(128), [0x00, 0x80]);
+    assert_eq!(int_to_atom(-128), [0x80]);
+    assert_eq!(int_to_atom(-129), [0xff, 0x7f]);
+    assert_eq!(int_to_atom(i64::MIN), [0x80, 0, 0, 0, 0, 0, 0, 0]);
+}
+
+#[cfg(test)]
+proptest! {
+    #[test]
+    fn test_strategies(
+        int in int_atom(),
+        a in atom(10),
+        small in atom(1),
+        l in list(atom_tree(4), 5),
+        t in tree(4, 16),
+    ) {
+        use crate::allocator::SExp;
+        use crate::number::is_canonical_int;
+        use crate::serialize::node_from_bytes;
+
+        prop_assert!(is_canonical_int(&int));
+        prop_assert!(a.len() <= 10);
+        prop_assert!(small.len() <= 1);
+
+        let mut alloc = IntAllocator::new();
+        let mut node = node_from_bytes(&mut alloc, &l).unwrap();
+        let mut items = 0;
+        while let SExp::Pair(first, rest) = alloc.sexp(&node) {
+            prop_assert!(alloc.atom(&first).len() <= 4);
+            items += 1;
+            node = rest;
+        }
+        prop_assert!(items <= 5);
+        prop_assert!(alloc.atom(&node).is_empty());
+
+        // every tree is a valid, canonical serialization
+        let node = node_from_bytes(&mut alloc, &t).unwrap();
+        prop_assert_eq!(node_to_bytes(&Node::new(&alloc, node)).unwrap(), t);
+    }
+}
@@ -0,0 +1,327 @@
+use std::collections::{BTreeMap, HashMap};
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::allocator::Allocator;
+use crate::err_utils::{json_string, ErrReport};
+use crate::io_utils::{read_input, write_output};
+use crate::node::Node;
+use crate::reduction::EvalErr;
+use crate::tree_hash::tree_hash;
+
+// A map from tree hash to function name, as found in the `.sym` files produced
+// by the chialisp compiler. Those files are a JSON object whose keys are hex
+// encoded tree hashes of the compiled functions.
+#[derive(Default, Debug, Clone)]
+pub struct SymbolTable {
+    names: HashMap<[u8; 32], String>,
+    // the entries that don't name a function, like "<hash>_arguments" and
+    // "__chia__main_arguments", kept so they're written back out
+    other: BTreeMap<String, String>,
+}
+
+impl SymbolTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_sym_json(sym: &str) -> Result<Self, String> {
+        let mut table = Self::new();
+        for (key, name) in parse_string_map(sym)? {
+            // only plain hashes name functions
+            let hash = match hex::decode(&key) {
+                Ok(h) if h.len() == 32 => h,
+                _ => {
+                    table.other.insert(key, name);
+                    continue;
+                }
+            };
+            let mut k = [0_u8; 32];
+            k.copy_from_slice(&hash);
+            table.names.insert(k, name);
+        }
+        Ok(table)
+    }
+
+    // the table as a .sym file, with the keys in order
+    pub fn to_sym_json(&self) -> String {
+        let mut entries: BTreeMap<String, &str> = self
+            .other
+            .iter()
+            .map(|(k, v)| (k.clone(), v.as_str()))
+            .collect();
+        for (hash, name) in &self.names {
+            entries.insert(hex::encode(hash), name);
+        }
+        let entries: Vec<String> = entries
+            .iter()
+            .map(|(k, v)| format!("{}: {}", json_string(k), json_string(v)))
+            .collect();
+        format!("{{{}}}", entries.join(", "))
+    }
+
+    // "-" is stdin
+    pub fn read_sym_file(path: &str) -> Result<Self, String> {
+        let sym = read_input(path).map_err(|e| format!("{}: {}", path, e))?;
+        let sym = String::from_utf8(sym).map_err(|_| format!("{}: not utf-8", path))?;
+        Self::from_sym_json(&sym).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn write_sym_file(&self, path: &str) -> Result<(), String> {
+        write_output(path, self.to_sym_json().as_bytes()).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn insert(&mut self, hash: [u8; 32], name: &str) {
+        self.names.insert(hash, name.to_string());
+    }
+
+    pub fn get(&self, hash: &[u8; 32]) -> Option<&str> {
+        self.names.get(hash).map(|s| s.as_str())
+    }
+
+    // the argument list of the function with this hash, e.g. "(a b)"
+    pub fn arguments(&self, hash: &[u8; 32]) -> Option<&str> {
+        self.other
+            .get(&format!("{}_arguments", hex::encode(hash)))
+            .map(|s| s.as_str())
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    pub fn name_for<A: Allocator>(&self, node: &Node<A>) -> Option<&str> {
+        if self.names.is_empty() {
+            return None;
+        }
+        self.get(&tree_hash(node))
+    }
+
+    // render an error raised while the given function frames were active
+    // (outermost first) as "<msg> in `<fn>` (depth <n>)" followed by one line
+    // per frame, innermost first. Frames that aren't in the table are shown
+    // by a prefix of their tree hash
+    pub fn render_err<A: Allocator>(
+        &self,
+        allocator: &A,
+        err: &EvalErr<A::Ptr>,
+        frames: &[A::Ptr],
+    ) -> String {
+        let hashes: Vec<[u8; 32]> = frames
+            .iter()
+            .map(|f| tree_hash(&Node::new(allocator, f.clone())))
+            .collect();
+        let on = self.name_for(&Node::new(allocator, err.0.clone()));
+        self.render_backtrace(&err.1.to_string(), &hashes, on)
+    }
+
+    // the same for a report, e.g. one logged by a node without the .sym file
+    pub fn render_report(&self, report: &ErrReport) -> String {
+        self.render_backtrace(&report.msg, &report.backtrace, None)
+    }
+
+    fn render_backtrace(&self, msg: &str, frames: &[[u8; 32]], on: Option<&str>) -> String {
+        let names: Vec<Option<&str>> = frames.iter().map(|h| self.get(h)).collect();
+
+        let mut msg = msg.to_string();
+        if let Some(name) = names.iter().rev().find_map(|n| *n) {
+            msg += &format!(" in `{}` (depth {})", name, frames.len());
+        }
+        if let Some(name) = on {
+            msg += &format!(" on `{}`", name);
+        }
+        for (depth, (name, hash)) in names.iter().zip(frames).enumerate().rev() {
+            match name {
+                Some(name) => msg += &format!("\n  #{} `{}`", depth, name),
+                None => msg += &format!("\n  #{} {}", depth, hex::encode(&hash[..8])),
+            }
+        }
+        msg
+    }
+}
+
+// a minimal parser for a flat JSON object mapping strings to strings, which is
+// all a .sym file contains
+fn parse_string_map(input: &str) -> Result<Vec<(String, String)>, String> {
+    let mut chars = input.chars().peekable();
+    let mut ret = Vec::new();
+    expect(&mut chars, '{')?;
+    skip_ws(&mut chars);
+    if chars.peek() == Some(&'}') {
+        chars.next();
+    } else {
+        loop {
+            let key = parse_string(&mut chars)?;
+            expect(&mut chars, ':')?;
+            let value = parse_string(&mut chars)?;
+            ret.push((key, value));
+            skip_ws(&mut chars);
+            match chars.next() {
+                Some(',') => {}
+                Some('}') => break,
+                _ => return Err("expected ',' or '}'".into()),
+            }
+        }
+    }
+    skip_ws(&mut chars);
+    if chars.next().is_some() {
+        return Err("trailing characters after object".into());
+    }
+    Ok(ret)
+}
+
+fn skip_ws(chars: &mut Peekable<Chars>) {
+    while let Some(c) = chars.peek() {
+        if !c.is_whitespace() {
+            break;
+        }
+        chars.next();
+    }
+}
+
+fn expect(chars: &mut Peekable<Chars>, c: char) -> Result<(), String> {
+    skip_ws(chars);
+    match chars.next() {
+        Some(v) if v == c => Ok(()),
+        _ => Err(format!("expected '{}'", c)),
+    }
+}
+
+fn parse_string(chars: &mut Peekable<Chars>) -> Result<String, String> {
+    expect(chars, '"')?;
+    let mut ret = String::new();
+    loop {
+        match chars.next() {
+            None => return Err("unterminated string".into()),
+            Some('"') => return Ok(ret),
+            Some('\\') => match chars.next() {
+                Some('n') => ret.push('\n'),
+                Some('t') => ret.push('\t'),
+                Some('r') => ret.push('\r'),
+                Some('u') => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    let v = u32::from_str_radix(&code, 16)
+                        .ok()
+                        .and_then(std::char::from_u32)
+                        .ok_or_else(|| format!("invalid escape \\u{}", code))?;
+                    ret.push(v);
+                }
+                Some(c) if c == '"' || c == '\\' || c == '/' => ret.push(c),
+                _ => return Err("invalid escape".into()),
+            },
+            Some(c) => ret.push(c),
+        }
+    }
+}
+
+#[test]
+fn test_parse_string_map() {
+    assert_eq!(parse_string_map("{}").unwrap(), vec![]);
+    assert_eq!(
+        parse_string_map(" { \"a\" : \"b\\\"c\",\"d\":\"\\u0041\" } ").unwrap(),
+        vec![
+            ("a".to_string(), "b\"c".to_string()),
+            ("d".to_string(), "A".to_string())
+        ]
+    );
+    assert!(parse_string_map("{\"a\": 1}").is_err());
+    assert!(parse_string_map("{\"a\": \"b\"").is_err());
+    assert!(parse_string_map("{\"a\": \"b\"} x").is_err());
+}
+
+#[test]
+fn test_render_err() {
+    use crate::int_allocator::IntAllocator;
+
+    let mut a = IntAllocator::new();
+    let main = a.new_atom(b"main").unwrap();
+    let helper = a.new_atom(b"helper").unwrap();
+    let anon = a.new_atom(b"anon").unwrap();
+    let main_hash = hex::encode(tree_hash(&Node::new(&a, main)));
+    let helper_hash = hex::encode(tree_hash(&Node::new(&a, helper)));
+
+    let sym = format!(
+        "{{\"{}\": \"main\", \"{}\": \"assert_my_coin_id\", \"{}_arguments\": \"(a b)\"}}",
+        main_hash, helper_hash, helper_hash
+    );
+    let table = SymbolTable::from_sym_json(&sym).unwrap();
+    assert_eq!(table.len(), 2);
+
+    let e = EvalErr::new(a.null(), "clvm raise");
+    assert_eq!(
+        table.render_err(&a, &e, &[main, helper, anon]),
+        format!(
+            "clvm raise in `assert_my_coin_id` (depth 3)\n  #2 {}\n  #1 `assert_my_coin_id`\n  #0 `main`",
+            hex::encode(&tree_hash(&Node::new(&a, anon))[..8])
+        )
+    );
+
+    // no frames, no annotation
+    assert_eq!(table.render_err(&a, &e, &[]), "clvm raise");
+}
+
+#[test]
+fn test_sym_json_round_trip() {
+    let main = [1_u8; 32];
+    let helper = [2_u8; 32];
+    let sym = format!(
+        "{{\"{}\": \"main\", \"{}\": \"helper\", \"{}_arguments\": \"(a b)\", \"__chia__main_arguments\": \"(x)\"}}",
+        hex::encode(helper),
+        hex::encode(main),
+        hex::encode(helper)
+    );
+    let table = SymbolTable::from_sym_json(&sym).unwrap();
+    assert_eq!(table.len(), 2);
+    assert_eq!(table.get(&main), Some("helper"));
+    assert_eq!(table.arguments(&helper), Some("(a b)"));
+    assert_eq!(table.arguments(&main), None);
+
+    let json = table.to_sym_json();
+    assert_eq!(
+        json,
+        format!(
+            "{{\"{}\": \"helper\", \"{}\": \"main\", \"{}_arguments\": \"(a b)\", \"__chia__main_arguments\": \"(x)\"}}",
+            hex::encode(main),
+            hex::encode(helper),
+            hex::encode(helper)
+        )
+    );
+
+    let path = std::env::temp_dir().join("clvm_rs_symbols.sym");
+    let path = path.to_str().unwrap();
+    table.write_sym_file(path).unwrap();
+    let back = SymbolTable::read_sym_file(path).unwrap();
+    assert_eq!(back.to_sym_json(), json);
+    std::fs::remove_file(path).unwrap();
+    assert!(SymbolTable::read_sym_file(path).is_err());
+}
+
+#[test]
+fn test_render_report() {
+    use crate::cost::Cost;
+    use crate::int_allocator::IntAllocator;
+
+    let mut a = IntAllocator::new();
+    let main = a.new_atom(b"main").unwrap();
+    let anon = a.new_atom(b"anon").unwrap();
+    let e = EvalErr::new(a.null(), "clvm raise");
+    let report = ErrReport::new(&a, &e, Cost::new(100), &[main, anon]);
+
+    let mut table = SymbolTable::new();
+    table.insert(tree_hash(&Node::new(&a, main)), "main");
+    assert_eq!(
+        table.render_report(&report),
+        format!(
+            "clvm raise in `main` (depth 2)\n  #1 {}\n  #0 `main`",
+            hex::encode(&report.backtrace[1][..8])
+        )
+    );
+    assert_eq!(
+        table.render_report(&report),
+        table.render_err(&a, &e, &[main, anon])
+    );
+}
@@ -0,0 +1,343 @@
+// An allocator for evaluating many programs in parallel against the same
+// tree, e.g. the environment shared by a batch. That tree is built once, in
+// an IntAllocator, and frozen into a FrozenAllocator: the IntAllocator behind
+// an Arc, which can be read but not added to. Each thread then evaluates in a
+// SyncAllocator of its own, which reads the frozen nodes in place and appends
+// to an arena of its own. The frozen allocator is never written to, so any
+// number of threads can read it while each appends. SyncAllocator is Send
+// and Sync, so it can also be handed between threads, or read from several
+// once it's done
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::allocator::{Allocator, SExp};
+use crate::err_utils::{err, limit_err, oom_err};
+use crate::int_allocator::{IntAllocator, IntAtomBuf};
+use crate::reduction::EvalErr;
+
+// a node of the frozen allocator, or one allocated since
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SyncPtr {
+    Frozen(i32),
+    Atom(i32),
+    Pair(u32),
+}
+
+#[derive(Clone, Copy)]
+pub enum SyncAtomBuf {
+    Frozen(IntAtomBuf),
+    Arena(IntAtomBuf),
+}
+
+// an IntAllocator that can no longer be added to, made by
+// IntAllocator::freeze(). Clones share it. It's an Allocator so that it can be
+// read like any other (e.g. serialized or hashed), but allocating fails
+#[derive(Clone)]
+pub struct FrozenAllocator(Arc<IntAllocator>);
+
+impl FrozenAllocator {
+    pub fn new(allocator: IntAllocator) -> Self {
+        FrozenAllocator(Arc::new(allocator))
+    }
+
+    pub fn allocator(&self) -> &IntAllocator {
+        &self.0
+    }
+}
+
+impl Allocator for FrozenAllocator {
+    type Ptr = i32;
+    type AtomBuf = IntAtomBuf;
+
+    fn new_atom(&mut self, _v: &[u8]) -> Result<Self::Ptr, EvalErr<Self::Ptr>> {
+        err(self.null(), "allocator is frozen")
+    }
+
+    fn new_pair(
+        &mut self,
+        _first: Self::Ptr,
+        _rest: Self::Ptr,
+    ) -> Result<Self::Ptr, EvalErr<Self::Ptr>> {
+        err(self.null(), "allocator is frozen")
+    }
+
+    fn new_substr(
+        &mut self,
+        node: Self::Ptr,
+        _start: u32,
+        _end: u32,
+    ) -> Result<Self::Ptr, EvalErr<Self::Ptr>> {
+        err(node, "allocator is frozen")
+    }
+
+    fn atom<'a>(&'a self, node: &'a Self::Ptr) -> &'a [u8] {
+        self.0.atom(node)
+    }
+
+    fn buf<'a>(&'a self, node: &'a Self::AtomBuf) -> &'a [u8] {
+        self.0.buf(node)
+    }
+
+    fn atom_byte(&self, node: &Self::AtomBuf) -> Option<u8> {
+        self.0.atom_byte(node)
+    }
+
+    fn sexp(&self, node: &Self::Ptr) -> SExp<Self::Ptr, Self::AtomBuf> {
+        self.0.sexp(node)
+    }
+
+    fn null(&self) -> Self::Ptr {
+        self.0.null()
+    }
+
+    fn one(&self) -> Self::Ptr {
+        self.0.one()
+    }
+
+    fn arena_bytes(&self) -> Option<usize> {
+        self.0.arena_bytes()
+    }
+}
+
+fn atom_err(e: EvalErr<i32>) -> EvalErr<SyncPtr> {
+    EvalErr(SyncPtr::Atom(e.0), e.1, e.2)
+}
+
+pub struct SyncAllocator {
+    frozen: FrozenAllocator,
+    // only holds atoms. Pairs can refer to frozen nodes, so they're kept here
+    atoms: IntAllocator,
+    pairs: Vec<(SyncPtr, SyncPtr)>,
+}
+
+impl SyncAllocator {
+    pub fn new(frozen: FrozenAllocator) -> Self {
+        SyncAllocator {
+            frozen,
+            atoms: IntAllocator::new(),
+            pairs: Vec::new(),
+        }
+    }
+
+    pub fn frozen(&self) -> &FrozenAllocator {
+        &self.frozen
+    }
+
+    // a node of the frozen allocator
+    pub fn frozen_node(&self, node: i32) -> SyncPtr {
+        debug_assert!(self.frozen.allocator().contains(node));
+        SyncPtr::Frozen(node)
+    }
+
+    // drops everything allocated since the allocator was made, the frozen
+    // nodes are untouched
+    pub fn reset(&mut self) {
+        self.atoms.reset();
+        self.pairs.clear();
+    }
+}
+
+impl Allocator for SyncAllocator {
+    type Ptr = SyncPtr;
+    type AtomBuf = SyncAtomBuf;
+
+    fn new_atom(&mut self, v: &[u8]) -> Result<Self::Ptr, EvalErr<Self::Ptr>> {
+        self.atoms.new_atom(v).map(SyncPtr::Atom).map_err(atom_err)
+    }
+
+    fn new_pair(
+        &mut self,
+        first: Self::Ptr,
+        rest: Self::Ptr,
+    ) -> Result<Self::Ptr, EvalErr<Self::Ptr>> {
+        let r = self.pairs.len() as u32;
+        if self.pairs.len() == u32::MAX as usize {
+            return limit_err(self.null(), "too many pairs");
+        }
+        if self.pairs.try_reserve(1).is_err() {
+            return oom_err(self.null());
+        }
+        self.pairs.push((first, rest));
+        Ok(SyncPtr::Pair(r))
+    }
+
+    fn new_substr(
+        &mut self,
+        node: Self::Ptr,
+        start: u32,
+        end: u32,
+    ) -> Result<Self::Ptr, EvalErr<Self::Ptr>> {
+        match node {
+            SyncPtr::Atom(n) => self
+                .atoms
+                .new_substr(n, start, end)
+                .map(SyncPtr::Atom)
+                .map_err(atom_err),
+            SyncPtr::Pair(_) => err(node, "(internal error) substr expected atom, got pair"),
+            SyncPtr::Frozen(n) => {
+                if let SExp::Pair(..) = self.frozen.sexp(&n) {
+                    return err(node, "(internal error) substr expected atom, got pair");
+                }
+                // the slice is copied out of the frozen allocator
+                let atom = self.frozen.atom(&n);
+                let (start, end) = (start as usize, end as usize);
+                if start > atom.len() {
+                    return err(node, "substr start out of bounds");
+                }
+                if end > atom.len() {
+                    return err(node, "substr end out of bounds");
+                }
+                if end < start {
+                    return err(node, "substr invalid bounds");
+                }
+                self.atoms
+                    .new_atom(&atom[start..end])
+                    .map(SyncPtr::Atom)
+                    .map_err(atom_err)
+            }
+        }
+    }
+
+    fn atom<'a>(&'a self, node: &'a Self::Ptr) -> &'a [u8] {
+        match node {
+            SyncPtr::Frozen(n) => self.frozen.atom(n),
+            SyncPtr::Atom(n) => self.atoms.atom(n),
+            SyncPtr::Pair(_) => panic!("expected atom, got pair"),
+        }
+    }
+
+    fn buf<'a>(&'a self, node: &'a Self::AtomBuf) -> &'a [u8] {
+        match node {
+            SyncAtomBuf::Frozen(buf) => self.frozen.buf(buf),
+            SyncAtomBuf::Arena(buf) => self.atoms.buf(buf),
+        }
+    }
+
+    fn atom_byte(&self, node: &Self::AtomBuf) -> Option<u8> {
+        match node {
+            SyncAtomBuf::Frozen(buf) => self.frozen.atom_byte(buf),
+            SyncAtomBuf::Arena(buf) => self.atoms.atom_byte(buf),
+        }
+    }
+
+    fn sexp(&self, node: &Self::Ptr) -> SExp<Self::Ptr, Self::AtomBuf> {
+        match node {
+            SyncPtr::Frozen(n) => match self.frozen.sexp(n) {
+                SExp::Atom(buf) => SExp::Atom(SyncAtomBuf::Frozen(buf)),
+                SExp::Pair(first, rest) => {
+                    SExp::Pair(SyncPtr::Frozen(first), SyncPtr::Frozen(rest))
+                }
+            },
+            SyncPtr::Atom(n) => match self.atoms.sexp(n) {
+                SExp::Atom(buf) => SExp::Atom(SyncAtomBuf::Arena(buf)),
+                SExp::Pair(..) => unreachable!(),
+            },
+            SyncPtr::Pair(n) => {
+                let (first, rest) = self.pairs[*n as usize];
+                SExp::Pair(first, rest)
+            }
+        }
+    }
+
+    fn null(&self) -> Self::Ptr {
+        SyncPtr::Atom(self.atoms.null())
+    }
+
+    fn one(&self) -> Self::Ptr {
+        SyncPtr::Atom(self.atoms.one())
+    }
+
+    fn reserve(
+        &mut self,
+        atoms: usize,
+        pairs: usize,
+        atom_bytes: usize,
+    ) -> Result<(), EvalErr<Self::Ptr>> {
+        if self.pairs.try_reserve(pairs).is_err() {
+            return oom_err(self.null());
+        }
+        self.atoms.reserve(atoms, 0, atom_bytes).map_err(atom_err)
+    }
+}
+
+#[test]
+fn test_sync_allocator() {
+    use crate::binutils::assemble;
+    use crate::cost::Cost;
+    use crate::node::Node;
+    use crate::op_table::{op_table_for, CHIA_OPCODES};
+    use crate::run_program::run_program;
+    use crate::serialize::node_to_bytes;
+
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<FrozenAllocator>();
+    assert_send_sync::<SyncAllocator>();
+
+    let mut a = IntAllocator::new();
+    let env = assemble(&mut a, "(\"foobar\" 1000)").unwrap();
+    let mut frozen = a.freeze();
+
+    // the frozen allocator can be read, but not added to
+    assert_eq!(
+        node_to_bytes(&Node::new(&frozen, env)).unwrap(),
+        hex::decode("ff86666f6f626172ff8203e880").unwrap()
+    );
+    assert!(frozen.new_atom(b"foo").is_err());
+    assert!(frozen.new_pair(env, env).is_err());
+    assert!(frozen.new_substr(env, 0, 1).is_err());
+
+    let threads: Vec<_> = (0..4)
+        .map(|n| {
+            let frozen = frozen.clone();
+            std::thread::spawn(move || {
+                let mut a = SyncAllocator::new(frozen);
+                let env = a.frozen_node(env);
+                let program = format!("(c (substr 2 (q . {})) (+ 5 (q . {})))", n, n);
+                let program = assemble(&mut a, &program).unwrap();
+                let r = run_program(
+                    &mut a,
+                    &program,
+                    &env,
+                    1,
+                    2,
+                    Cost::ZERO,
+                    Box::new(op_table_for(&CHIA_OPCODES)),
+                    None,
+                )
+                .unwrap();
+                node_to_bytes(&Node::new(&a, r.1)).unwrap()
+            })
+        })
+        .collect();
+    for (n, t) in threads.into_iter().enumerate() {
+        let tail = &b"foobar"[n..];
+        let mut expected = vec![0xff, 0x80 | tail.len() as u8];
+        expected.extend_from_slice(tail);
+        expected.push(0x82);
+        expected.extend_from_slice(&(1000 + n as u16).to_be_bytes());
+        assert_eq!(t.join().unwrap(), expected);
+    }
+
+    // frozen nodes are read in place, not copied
+    let mut a = SyncAllocator::new(frozen);
+    let env = a.frozen_node(env);
+    let pair = a.new_pair(env, a.null()).unwrap();
+    match a.sexp(&pair) {
+        SExp::Pair(first, _) => assert_eq!(first, env),
+        SExp::Atom(_) => panic!("expected pair"),
+    }
+    let foobar = match a.sexp(&env) {
+        SExp::Pair(first, _) => first,
+        SExp::Atom(_) => panic!("expected pair"),
+    };
+    assert!(matches!(foobar, SyncPtr::Frozen(_)));
+    let oo = a.new_substr(foobar, 1, 3).unwrap();
+    assert_eq!(a.atom(&oo), b"oo");
+    assert!(a.new_substr(foobar, 4, 7).is_err());
+    assert!(a.new_substr(env, 0, 1).is_err());
+
+    // reset drops what was allocated, but not the frozen nodes
+    a.reset();
+    assert_eq!(a.atom(&foobar), b"foobar");
+}
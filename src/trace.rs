@@ -0,0 +1,153 @@
+// Writes a trace of evaluation, as the tracer of a run (see
+// RunParams::with_tracer()), as newline delimited JSON: one object per
+// operator call, written as soon as the operator returns. Nothing is kept in
+// memory, so it works for runs of any length, and the output can go straight
+// to log tooling (e.g. jq). A line looks like
+//
+// {"op":"+","opcode":"10","cost":999,"total":1234,"depth":1,"args":"<tree hash>","result":"<tree hash>"}
+//
+// where op is null for unknown operators, total is the cost so far including
+// this call and depth counts the programs entered via apply (the top level
+// program is depth 1)
+
+use std::io::{self, Write};
+
+use crate::binutils::Keywords;
+use crate::cost::Cost;
+use crate::err_utils::json_string;
+use crate::op_table::CHIA_DIALECT;
+use crate::run_program::CostTracer;
+
+pub struct NdjsonTracer<W: Write> {
+    out: W,
+    keywords: Keywords,
+    depth: usize,
+    total: Cost,
+    // the first write error. Nothing more is written after one
+    error: Option<io::Error>,
+}
+
+impl<W: Write> NdjsonTracer<W> {
+    // operators are named by the chia dialect's keywords
+    pub fn new(out: W) -> Self {
+        NdjsonTracer {
+            out,
+            keywords: Keywords::for_dialect(&CHIA_DIALECT),
+            depth: 0,
+            total: Cost::ZERO,
+            error: None,
+        }
+    }
+
+    pub fn with_keywords(mut self, keywords: Keywords) -> Self {
+        self.keywords = keywords;
+        self
+    }
+
+    // flushes the output and gives it back, or the first error writing to it
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+impl<P, W: Write> CostTracer<P> for NdjsonTracer<W> {
+    fn enter(&mut self, _program: &P) {
+        self.depth += 1;
+    }
+
+    fn leave(&mut self) {
+        self.depth = self.depth.saturating_sub(1);
+    }
+
+    fn charge(&mut self, _opcode: Option<&[u8]>, cost: Cost) {
+        self.total += cost;
+    }
+
+    fn wants_reductions(&self) -> bool {
+        true
+    }
+
+    fn reduction(&mut self, opcode: &[u8], args: &[u8; 32], result: &[u8; 32], cost: Cost) {
+        if self.error.is_some() {
+            return;
+        }
+        let op = match self.keywords.keyword(opcode) {
+            Some(kw) => json_string(kw),
+            None => "null".to_string(),
+        };
+        // charge() for this call comes after
+        let line = format!(
+            "{{\"op\":{},\"opcode\":\"{}\",\"cost\":{},\"total\":{},\"depth\":{},\"args\":\"{}\",\"result\":\"{}\"}}\n",
+            op,
+            hex::encode(opcode),
+            cost,
+            self.total + cost,
+            self.depth,
+            hex::encode(args),
+            hex::encode(result)
+        );
+        if let Err(e) = self.out.write_all(line.as_bytes()) {
+            self.error = Some(e);
+        }
+    }
+}
+
+#[test]
+fn test_ndjson_tracer() {
+    use crate::allocator::Allocator;
+    use crate::binutils::assemble;
+    use crate::int_allocator::IntAllocator;
+    use crate::node::Node;
+    use crate::op_table::op_table_for;
+    use crate::run_program::{run_program_with_params, RunParams};
+    use crate::tree_hash::tree_hash;
+
+    let mut a = IntAllocator::new();
+    let program = assemble(&mut a, "(a (q + 2 (q . 3)) 1)").unwrap();
+    let env = assemble(&mut a, "(4)").unwrap();
+    let args = assemble(&mut a, "(4 3)").unwrap();
+    let seven = a.new_atom(&[7]).unwrap();
+    let args_hash = hex::encode(tree_hash(&Node::new(&a, args)));
+    let seven_hash = hex::encode(tree_hash(&Node::new(&a, seven)));
+
+    let mut tracer = NdjsonTracer::new(Vec::new());
+    let r = run_program_with_params(
+        &mut a,
+        &program,
+        &env,
+        Box::new(op_table_for(CHIA_DIALECT.opcodes)),
+        None,
+        RunParams::new().with_tracer(&mut tracer),
+    )
+    .unwrap();
+    let out = String::from_utf8(tracer.finish().unwrap()).unwrap();
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 1);
+    let prefix = "{\"op\":\"+\",\"opcode\":\"10\",\"cost\":";
+    assert!(lines[0].starts_with(prefix));
+    assert!(lines[0].ends_with(&format!(
+        ",\"depth\":2,\"args\":\"{}\",\"result\":\"{}\"}}",
+        args_hash, seven_hash
+    )));
+    // the + is the last step, so the running total is the whole cost
+    assert!(lines[0].contains(&format!(",\"total\":{},", r.0)));
+
+    // unknown operators have no name
+    let program = assemble(&mut a, "(0x7f)").unwrap();
+    let mut tracer = NdjsonTracer::new(Vec::new());
+    run_program_with_params(
+        &mut a,
+        &program,
+        &env,
+        Box::new(op_table_for(CHIA_DIALECT.opcodes)),
+        None,
+        RunParams::new().with_tracer(&mut tracer),
+    )
+    .unwrap();
+    let out = String::from_utf8(tracer.finish().unwrap()).unwrap();
+    assert!(out.starts_with("{\"op\":null,\"opcode\":\"7f\","));
+}
@@ -0,0 +1,449 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+#[cfg(feature = "std")]
+use rayon::prelude::*;
+
+use crate::allocator::{Allocator, SExp};
+use crate::node::Node;
+use crate::sha256::sha256 as hash_blobs;
+
+// how many levels of nested lists tree_hash_parallel() splits up before
+// handing subtrees to the sequential tree_hash()
+#[cfg(feature = "std")]
+const PARALLEL_DEPTH: usize = 3;
+
+enum TreeOp<T> {
+    SExp(T),
+    Cons,
+}
+
+// compute the sha256 tree hash of a node. Atoms hash as sha256(1 + atom) and
+// pairs as sha256(2 + hash(first) + hash(rest))
+pub fn tree_hash<T: Allocator>(node: &Node<T>) -> [u8; 32] {
+    let a = node.allocator;
+    let mut hashes: Vec<[u8; 32]> = Vec::new();
+    let mut ops = vec![TreeOp::SExp(node.node.clone())];
+
+    while let Some(op) = ops.pop() {
+        match op {
+            TreeOp::SExp(n) => match a.sexp(&n) {
+                SExp::Atom(atom) => {
+                    hashes.push(hash_blobs(&[&[1], a.buf(&atom)]));
+                }
+                SExp::Pair(first, rest) => {
+                    ops.push(TreeOp::Cons);
+                    ops.push(TreeOp::SExp(first));
+                    ops.push(TreeOp::SExp(rest));
+                }
+            },
+            TreeOp::Cons => {
+                let first = hashes.pop().unwrap();
+                let rest = hashes.pop().unwrap();
+                hashes.push(hash_blobs(&[&[2], &first, &rest]));
+            }
+        }
+    }
+    hashes.pop().unwrap()
+}
+
+// the same as tree_hash(), but subtrees are hashed concurrently on the rayon
+// thread pool. The tree is split at pair boundaries by walking the spine of
+// the list (following rest) and hashing the items (the firsts) in parallel,
+// so long lists, like the spends in a generator, spread evenly across cores.
+// Items are split the same way, down to PARALLEL_DEPTH levels. Needs std for
+// the thread pool. WASI has no threads, so there it's the same as tree_hash()
+#[cfg(feature = "std")]
+pub fn tree_hash_parallel<T>(node: &Node<T>) -> [u8; 32]
+where
+    T: Allocator + Sync,
+    T::Ptr: Send + Sync,
+{
+    if cfg!(target_os = "wasi") {
+        return tree_hash(node);
+    }
+    hash_spine(node.allocator, node.node.clone(), PARALLEL_DEPTH)
+}
+
+#[cfg(feature = "std")]
+fn hash_spine<T>(a: &T, node: T::Ptr, depth: usize) -> [u8; 32]
+where
+    T: Allocator + Sync,
+    T::Ptr: Send + Sync,
+{
+    if depth == 0 {
+        return tree_hash(&Node::new(a, node));
+    }
+    let mut items = Vec::new();
+    let mut tail = node;
+    while let SExp::Pair(first, rest) = a.sexp(&tail) {
+        items.push(first);
+        tail = rest;
+    }
+    if items.len() < 2 {
+        // nothing to split here
+        return match items.pop() {
+            Some(first) => hash_blobs(&[
+                &[2],
+                &hash_spine(a, first, depth - 1),
+                &tree_hash(&Node::new(a, tail)),
+            ]),
+            None => tree_hash(&Node::new(a, tail)),
+        };
+    }
+    let hashes: Vec<[u8; 32]> = items
+        .into_par_iter()
+        .map(|item| hash_spine(a, item, depth - 1))
+        .collect();
+    let mut ret = tree_hash(&Node::new(a, tail));
+    for h in hashes.iter().rev() {
+        ret = hash_blobs(&[&[2], h, &ret]);
+    }
+    ret
+}
+
+// Puzzle hashes are shown to users as bech32m (BIP 350) addresses: a prefix
+// naming the network, "1", then the hash and a checksum in base 32
+pub const MAINNET_PREFIX: &str = "xch";
+pub const TESTNET_PREFIX: &str = "txch";
+
+const BECH32_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const BECH32M_CONST: u32 = 0x2bc8_30a3;
+// the longest string BIP 173 allows
+const BECH32_MAX_LEN: usize = 90;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AddressError {
+    // the prefix is empty or has characters outside ascii 33-126
+    InvalidPrefix,
+    // the address isn't all upper case or all lower case
+    MixedCase,
+    InvalidLength,
+    InvalidChar(char),
+    InvalidChecksum,
+    // the data isn't a whole number of bytes
+    InvalidPadding,
+    // the address decoded to something other than 32 bytes
+    InvalidHashLength(usize),
+    // the address is for another network
+    WrongPrefix(String),
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressError::InvalidPrefix => write!(f, "invalid address prefix"),
+            AddressError::MixedCase => write!(f, "address mixes upper and lower case"),
+            AddressError::InvalidLength => write!(f, "invalid address length"),
+            AddressError::InvalidChar(c) => write!(f, "invalid character {:?} in address", c),
+            AddressError::InvalidChecksum => write!(f, "invalid address checksum"),
+            AddressError::InvalidPadding => write!(f, "invalid address padding"),
+            AddressError::InvalidHashLength(len) => {
+                write!(f, "address holds {} bytes, not a 32 byte puzzle hash", len)
+            }
+            AddressError::WrongPrefix(prefix) => write!(f, "address is for prefix {}", prefix),
+        }
+    }
+}
+
+fn polymod(values: &[u8]) -> u32 {
+    const GEN: [u32; 5] = [
+        0x3b6a_57b2,
+        0x2650_8e6d,
+        0x1ea1_19fa,
+        0x3d42_33dd,
+        0x2a14_62b3,
+    ];
+    let mut chk: u32 = 1;
+    for v in values {
+        let top = chk >> 25;
+        chk = (chk & 0x01ff_ffff) << 5 ^ *v as u32;
+        for (i, g) in GEN.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= g;
+            }
+        }
+    }
+    chk
+}
+
+fn expand_prefix(prefix: &str) -> Vec<u8> {
+    let mut ret: Vec<u8> = prefix.bytes().map(|b| b >> 5).collect();
+    ret.push(0);
+    ret.extend(prefix.bytes().map(|b| b & 31));
+    ret
+}
+
+fn valid_prefix(prefix: &str) -> bool {
+    !prefix.is_empty() && prefix.bytes().all(|b| (33..=126).contains(&b))
+}
+
+// regroups bits, e.g. bytes into 5 bit groups. Going to fewer bits pads the
+// last group with zeros, going back requires that padding to be zeros
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Result<Vec<u8>, AddressError> {
+    let mut acc: u32 = 0;
+    let mut bits: u32 = 0;
+    let mut ret = Vec::new();
+    let max = (1 << to) - 1;
+    for v in data {
+        acc = acc << from | *v as u32;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            ret.push(((acc >> bits) & max) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            ret.push(((acc << (to - bits)) & max) as u8);
+        }
+    } else if bits >= from || ((acc << (to - bits)) & max) != 0 {
+        return Err(AddressError::InvalidPadding);
+    }
+    Ok(ret)
+}
+
+fn bech32m_encode(prefix: &str, data: &[u8]) -> String {
+    let mut values = expand_prefix(prefix);
+    values.extend_from_slice(data);
+    values.extend_from_slice(&[0; 6]);
+    let checksum = polymod(&values) ^ BECH32M_CONST;
+
+    let mut ret = String::from(prefix);
+    ret.push('1');
+    for v in data {
+        ret.push(BECH32_CHARSET[*v as usize] as char);
+    }
+    for i in 0..6 {
+        let v = (checksum >> (5 * (5 - i))) & 31;
+        ret.push(BECH32_CHARSET[v as usize] as char);
+    }
+    ret
+}
+
+// the (lower case) prefix and the 5 bit groups of a bech32m string
+fn bech32m_decode(s: &str) -> Result<(String, Vec<u8>), AddressError> {
+    if s.len() > BECH32_MAX_LEN {
+        return Err(AddressError::InvalidLength);
+    }
+    if let Some(c) = s.chars().find(|c| !(33..=126).contains(&(*c as u32))) {
+        return Err(AddressError::InvalidChar(c));
+    }
+    if s.to_lowercase() != s && s.to_uppercase() != s {
+        return Err(AddressError::MixedCase);
+    }
+    let s = s.to_lowercase();
+    let sep = match s.rfind('1') {
+        Some(0) | None => return Err(AddressError::InvalidPrefix),
+        Some(sep) => sep,
+    };
+    if sep + 7 > s.len() {
+        return Err(AddressError::InvalidLength);
+    }
+    let prefix = &s[..sep];
+    let mut data = Vec::with_capacity(s.len() - sep - 1);
+    for c in s[sep + 1..].chars() {
+        match BECH32_CHARSET.iter().position(|b| *b as char == c) {
+            Some(v) => data.push(v as u8),
+            None => return Err(AddressError::InvalidChar(c)),
+        }
+    }
+    let mut values = expand_prefix(prefix);
+    values.extend_from_slice(&data);
+    if polymod(&values) != BECH32M_CONST {
+        return Err(AddressError::InvalidChecksum);
+    }
+    data.truncate(data.len() - 6);
+    Ok((prefix.into(), data))
+}
+
+// the address of a puzzle hash, e.g. with MAINNET_PREFIX
+pub fn encode_puzzle_hash(puzzle_hash: &[u8; 32], prefix: &str) -> Result<String, AddressError> {
+    if !valid_prefix(prefix) || prefix.to_lowercase() != prefix {
+        return Err(AddressError::InvalidPrefix);
+    }
+    let data = convert_bits(puzzle_hash, 8, 5, true)?;
+    Ok(bech32m_encode(prefix, &data))
+}
+
+// the prefix and puzzle hash of an address
+pub fn decode_address(address: &str) -> Result<(String, [u8; 32]), AddressError> {
+    let (prefix, data) = bech32m_decode(address)?;
+    let bytes = convert_bits(&data, 5, 8, false)?;
+    if bytes.len() != 32 {
+        return Err(AddressError::InvalidHashLength(bytes.len()));
+    }
+    let mut ret = [0; 32];
+    ret.copy_from_slice(&bytes);
+    Ok((prefix, ret))
+}
+
+// the puzzle hash of an address, which must have the given prefix
+pub fn decode_puzzle_hash(address: &str, prefix: &str) -> Result<[u8; 32], AddressError> {
+    let (found, puzzle_hash) = decode_address(address)?;
+    if found != prefix {
+        return Err(AddressError::WrongPrefix(found));
+    }
+    Ok(puzzle_hash)
+}
+
+#[test]
+fn test_tree_hash() {
+    use crate::int_allocator::IntAllocator;
+
+    let mut a = IntAllocator::new();
+    let null = a.null();
+    assert_eq!(
+        hex::encode(tree_hash(&Node::new(&a, null))),
+        "4bf5122f344554c53bde2ebb8cd2b7e3d1600ad631c385a5d7cce23c7785459a"
+    );
+
+    let one = a.one();
+    assert_eq!(
+        hex::encode(tree_hash(&Node::new(&a, one))),
+        "9dcf97a184f32623d11a73124ceb99a5709b083721e878a16d78f596718ba7b2"
+    );
+
+    // (1 . ())
+    let pair = a.new_pair(one, null).unwrap();
+    let expected = hash_blobs(&[
+        &[2],
+        &tree_hash(&Node::new(&a, one)),
+        &tree_hash(&Node::new(&a, null)),
+    ]);
+    assert_eq!(tree_hash(&Node::new(&a, pair)), expected);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn test_tree_hash_parallel() {
+    use crate::int_allocator::IntAllocator;
+
+    let mut a = IntAllocator::new();
+    let null = a.null();
+    let one = a.one();
+    assert_eq!(
+        tree_hash_parallel(&Node::new(&a, null)),
+        tree_hash(&Node::new(&a, null))
+    );
+    let pair = a.new_pair(one, null).unwrap();
+    assert_eq!(
+        tree_hash_parallel(&Node::new(&a, pair)),
+        tree_hash(&Node::new(&a, pair))
+    );
+
+    // a list of lists, terminated by a non-nil atom, with a balanced tree
+    // as one of the items
+    let mut balanced = a.new_atom(&[1, 2, 3]).unwrap();
+    for _ in 0..10 {
+        balanced = a.new_pair(balanced, balanced).unwrap();
+    }
+    let mut list = one;
+    for i in 0..200_u32 {
+        let v = a.new_atom(&i.to_be_bytes()).unwrap();
+        let mut item = a.new_pair(v, null).unwrap();
+        item = a.new_pair(v, item).unwrap();
+        if i == 100 {
+            item = a.new_pair(balanced, item).unwrap();
+        }
+        list = a.new_pair(item, list).unwrap();
+    }
+    assert_eq!(
+        tree_hash_parallel(&Node::new(&a, list)),
+        tree_hash(&Node::new(&a, list))
+    );
+}
+
+#[test]
+fn test_bech32m() {
+    // the valid strings from BIP 350
+    for s in &[
+        "A1LQFN3A",
+        "a1lqfn3a",
+        "an83characterlonghumanreadablepartthatcontainsthetheexcludedcharactersbioandnumber11sg7hg6",
+        "abcdef1l7aum6echk45nj3s0wdvt2fg8x9yrzpqzd3ryx",
+        "11llllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllludsr8",
+        "split1checkupstagehandshakeupstreamerranterredcaperredlc445v",
+        "?1v759aa",
+    ] {
+        let (prefix, data) = bech32m_decode(s).unwrap();
+        assert_eq!(bech32m_encode(&prefix, &data), s.to_lowercase());
+    }
+    // and some of the invalid ones
+    assert_eq!(bech32m_decode("1xj0phk"), Err(AddressError::InvalidPrefix));
+    assert_eq!(
+        bech32m_decode("qyrz8wqd2c9m"),
+        Err(AddressError::InvalidPrefix)
+    );
+    assert_eq!(
+        bech32m_decode("M1VUXWEZ"),
+        Err(AddressError::InvalidChecksum)
+    );
+    assert_eq!(
+        bech32m_decode("au1s5cgom"),
+        Err(AddressError::InvalidChar('o'))
+    );
+    assert_eq!(bech32m_decode("16plkw9"), Err(AddressError::InvalidPrefix));
+    assert_eq!(bech32m_decode("a1qqqqq"), Err(AddressError::InvalidLength));
+    assert_eq!(bech32m_decode("A1lqfn3a"), Err(AddressError::MixedCase));
+    // a bech32 (not bech32m) checksum
+    assert_eq!(
+        bech32m_decode("a12uel5l"),
+        Err(AddressError::InvalidChecksum)
+    );
+}
+
+#[test]
+fn test_puzzle_hash_address() {
+    let mut puzzle_hash = [0; 32];
+    let address = encode_puzzle_hash(&puzzle_hash, MAINNET_PREFIX).unwrap();
+    assert_eq!(
+        address,
+        "xch1qqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqq2u30kz"
+    );
+    assert_eq!(
+        decode_puzzle_hash(&address, MAINNET_PREFIX),
+        Ok(puzzle_hash)
+    );
+    assert_eq!(
+        decode_puzzle_hash(&address.to_uppercase(), MAINNET_PREFIX),
+        Ok(puzzle_hash)
+    );
+    assert_eq!(
+        decode_puzzle_hash(&address, TESTNET_PREFIX),
+        Err(AddressError::WrongPrefix("xch".into()))
+    );
+
+    for (i, b) in puzzle_hash.iter_mut().enumerate() {
+        *b = i as u8 * 7;
+    }
+    for prefix in &[MAINNET_PREFIX, TESTNET_PREFIX, "custom"] {
+        let address = encode_puzzle_hash(&puzzle_hash, prefix).unwrap();
+        assert_eq!(
+            decode_address(&address),
+            Ok((prefix.to_string(), puzzle_hash))
+        );
+        // any change to the address is caught
+        let mut corrupt = address.into_bytes();
+        let last = corrupt.len() - 1;
+        corrupt[last] = if corrupt[last] == b'q' { b'p' } else { b'q' };
+        let corrupt = String::from_utf8(corrupt).unwrap();
+        assert_eq!(decode_address(&corrupt), Err(AddressError::InvalidChecksum));
+    }
+
+    assert_eq!(
+        encode_puzzle_hash(&puzzle_hash, "XCH"),
+        Err(AddressError::InvalidPrefix)
+    );
+    assert_eq!(
+        encode_puzzle_hash(&puzzle_hash, ""),
+        Err(AddressError::InvalidPrefix)
+    );
+    // a valid bech32m string that isn't a puzzle hash
+    let short = bech32m_encode("xch", &convert_bits(&[1, 2, 3], 8, 5, true).unwrap());
+    assert_eq!(
+        decode_address(&short),
+        Err(AddressError::InvalidHashLength(3))
+    );
+}
@@ -0,0 +1,213 @@
+// Golden test vectors: programs with the cost and result (or error) they're
+// expected to produce, loaded at runtime so any implementation can be checked
+// against the same files. tests/vectors has a file for each core operator.
+// Each line of a file is a vector:
+//
+//   <program> <env> <cost> <result>
+//   <program> <env> FAIL <message>
+//
+// with the program, environment and result serialized, in hex. Lines starting
+// with # are comments, and blank lines are ignored. Like the reference
+// operators, this is only compiled with the "testing" feature
+
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::conformance::{run_serialized, Outcome};
+use crate::cost::Cost;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expected {
+    // the cost and the serialized result
+    Result(Cost, Vec<u8>),
+    // the error message
+    Failure(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Vector {
+    // where the vector came from, for reporting
+    pub file: String,
+    pub line: usize,
+    pub program: Vec<u8>,
+    pub env: Vec<u8>,
+    pub expected: Expected,
+}
+
+// a vector the implementation didn't reproduce
+#[derive(Clone, Debug, PartialEq)]
+pub struct VectorFailure {
+    pub vector: Vector,
+    pub got: Outcome,
+}
+
+impl fmt::Display for VectorFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let v = &self.vector;
+        let expected = match &v.expected {
+            Expected::Result(cost, result) => {
+                format!("cost {} result {}", cost, hex::encode(result))
+            }
+            Expected::Failure(msg) => format!("failure: {}", msg),
+        };
+        write!(
+            f,
+            "{}:{}: expected {}, got {}",
+            v.file, v.line, expected, self.got
+        )
+    }
+}
+
+pub fn vectors_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("vectors")
+}
+
+fn parse_line(line: &str) -> Result<(Vec<u8>, Vec<u8>, Expected), String> {
+    let mut fields = line.splitn(4, ' ');
+    let mut next = |what: &str| fields.next().ok_or(format!("missing {}", what));
+    let blob = |s: &str, what: &str| hex::decode(s).map_err(|_| format!("invalid {}", what));
+
+    let program = blob(next("program")?, "program")?;
+    let env = blob(next("environment")?, "environment")?;
+    let expected = match next("cost")? {
+        "FAIL" => Expected::Failure(next("message")?.to_string()),
+        cost => {
+            let cost = cost.parse().map_err(|_| "invalid cost".to_string())?;
+            Expected::Result(cost, blob(next("result")?.trim_end(), "result")?)
+        }
+    };
+    Ok((program, env, expected))
+}
+
+// the vectors in a file's text
+pub fn parse_vectors(file: &str, text: &str) -> Result<Vec<Vector>, String> {
+    let mut ret = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (program, env, expected) =
+            parse_line(line).map_err(|e| format!("{}:{}: {}", file, i + 1, e))?;
+        ret.push(Vector {
+            file: file.to_string(),
+            line: i + 1,
+            program,
+            env,
+            expected,
+        });
+    }
+    Ok(ret)
+}
+
+// the vectors in every .txt file in a directory, ordered by file name
+pub fn load_vectors(dir: &Path) -> io::Result<Vec<Vector>> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<io::Result<_>>()?;
+    paths.retain(|p| p.extension() == Some(OsStr::new("txt")));
+    paths.sort();
+
+    let mut ret = Vec::new();
+    for path in paths {
+        let text = fs::read_to_string(&path)?;
+        let name = path.file_name().unwrap().to_string_lossy();
+        let vectors = parse_vectors(&name, &text)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        ret.extend(vectors);
+    }
+    Ok(ret)
+}
+
+fn reproduced(expected: &Expected, got: &Outcome) -> bool {
+    match (expected, got) {
+        (Expected::Result(c1, r1), Outcome::Success(c2, r2)) => c1 == c2 && r1 == r2,
+        (Expected::Failure(m1), Outcome::Failure(_, m2)) => m1 == m2,
+        _ => false,
+    }
+}
+
+// runs every vector through `run`, which is passed the serialized program and
+// environment, and returns the ones it didn't reproduce
+pub fn check_vectors<F>(vectors: &[Vector], mut run: F) -> Vec<VectorFailure>
+where
+    F: FnMut(&[u8], &[u8]) -> Outcome,
+{
+    let mut ret = Vec::new();
+    for v in vectors {
+        let got = run(&v.program, &v.env);
+        if !reproduced(&v.expected, &got) {
+            ret.push(VectorFailure {
+                vector: v.clone(),
+                got,
+            });
+        }
+    }
+    ret
+}
+
+// checks this implementation against the vectors in a directory
+pub fn run_vectors(dir: &Path) -> io::Result<Vec<VectorFailure>> {
+    let vectors = load_vectors(dir)?;
+    Ok(check_vectors(&vectors, |p, e| {
+        run_serialized(p, e, Cost::ZERO)
+    }))
+}
+
+#[test]
+fn test_parse_vectors() {
+    let text = "# (+ 2 5)\n\
+                ff10ff02ff0580 ff03ff0480 856 07\n\
+                \n\
+                ff08ffff010180 80 FAIL clvm raise\n";
+    let vectors = parse_vectors("add.txt", text).unwrap();
+    assert_eq!(vectors.len(), 2);
+    assert_eq!(vectors[0].line, 2);
+    assert_eq!(
+        vectors[0].expected,
+        Expected::Result(Cost::new(856), vec![7])
+    );
+    assert_eq!(vectors[1].line, 4);
+    assert_eq!(
+        vectors[1].expected,
+        Expected::Failure("clvm raise".to_string())
+    );
+
+    assert_eq!(
+        parse_vectors("bad.txt", "ff10 80 12").unwrap_err(),
+        "bad.txt:1: missing result"
+    );
+    assert!(parse_vectors("bad.txt", "zz 80 12 80").is_err());
+
+    // a failure is reported with where the vector came from
+    let failures = check_vectors(&vectors, |_, _| Outcome::Success(Cost::new(856), vec![8]));
+    assert_eq!(failures.len(), 2);
+    assert_eq!(
+        failures[0].to_string(),
+        "add.txt:2: expected cost 856 result 07, got cost 856 result 08"
+    );
+}
+
+#[test]
+fn test_shipped_vectors() {
+    let vectors = load_vectors(&vectors_dir()).unwrap();
+    // every core operator has vectors
+    for (_, name) in crate::op_table::CHIA_OPCODES.iter() {
+        let file = format!("{}.txt", name.trim_start_matches("op_"));
+        assert!(
+            vectors.iter().any(|v| v.file == file),
+            "no vectors for {}",
+            name
+        );
+    }
+    let failures = run_vectors(&vectors_dir()).unwrap();
+    for f in &failures {
+        println!("{}", f);
+    }
+    assert!(failures.is_empty());
+}
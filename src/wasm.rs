@@ -0,0 +1,293 @@
+use js_sys::{Array, Function, Uint8Array};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+use crate::allocator::{Allocator, SExp};
+use crate::cost::Cost;
+use crate::curry::curry as curry_node;
+use crate::int_allocator::IntAllocator;
+use crate::node::Node;
+use crate::op_table::{op_table_for, HostOpTable, HostOperators, CHIA_OPCODES};
+use crate::reduction::{EvalErr, Reduction, Response};
+use crate::run_program::{run_program as run_node, run_program_with_params, FuelMeter, RunParams};
+use crate::serialize::{node_from_bytes as from_bytes, node_to_bytes as to_bytes};
+use crate::tree_hash::tree_hash as hash_node;
+
+// JavaScript bindings. Programs and values are passed serialized, except to
+// node_from_bytes() and node_to_bytes(), which convert between the
+// serialized form and nested arrays: an atom is a Uint8Array and a pair is a
+// two element Array. Programs use chia's operator table, where quote is 1
+// and apply is 2
+
+fn js_err<E: ToString>(e: E) -> JsValue {
+    JsValue::from_str(&e.to_string())
+}
+
+#[wasm_bindgen]
+pub struct RunResult {
+    cost: u64,
+    result: Vec<u8>,
+}
+
+#[wasm_bindgen]
+impl RunResult {
+    #[wasm_bindgen(getter)]
+    pub fn cost(&self) -> u64 {
+        self.cost
+    }
+
+    // the serialized result
+    #[wasm_bindgen(getter)]
+    pub fn result(&self) -> Vec<u8> {
+        self.result.clone()
+    }
+}
+
+#[wasm_bindgen]
+pub fn run_program(program: &[u8], args: &[u8], max_cost: u64) -> Result<RunResult, JsValue> {
+    let mut a = IntAllocator::new();
+    let program = from_bytes(&mut a, program).map_err(js_err)?;
+    let args = from_bytes(&mut a, args).map_err(js_err)?;
+    let table = op_table_for(&CHIA_OPCODES);
+    let r = run_node(
+        &mut a,
+        &program,
+        &args,
+        1,
+        2,
+        Cost::new(max_cost),
+        Box::new(table),
+        None,
+    )
+    .map_err(|e| js_err(e.1))?;
+    let result = to_bytes(&Node::new(&a, r.1)).map_err(js_err)?;
+    Ok(RunResult {
+        cost: r.0.get(),
+        result,
+    })
+}
+
+// a FuelMeter backed by a host function, called with the cost of each step.
+// Anything but `true` (including an exception) stops the program
+struct HostFuel(Function);
+
+impl FuelMeter for HostFuel {
+    fn consume(&mut self, cost: Cost) -> bool {
+        self.0
+            .call1(&JsValue::NULL, &JsValue::from_f64(cost.get() as f64))
+            .map(|v| v.as_bool() == Some(true))
+            .unwrap_or(false)
+    }
+}
+
+// like run_program(), but charges each step's cost to the host's `consume`
+// function too, so the host can stop the program at a deterministic point
+#[wasm_bindgen]
+pub fn run_program_with_host_fuel(
+    program: &[u8],
+    args: &[u8],
+    max_cost: u64,
+    consume: Function,
+) -> Result<RunResult, JsValue> {
+    let mut a = IntAllocator::new();
+    let program = from_bytes(&mut a, program).map_err(js_err)?;
+    let args = from_bytes(&mut a, args).map_err(js_err)?;
+    let table = op_table_for(&CHIA_OPCODES);
+    let mut fuel = HostFuel(consume);
+    let r = run_program_with_params(
+        &mut a,
+        &program,
+        &args,
+        Box::new(table),
+        None,
+        RunParams::new()
+            .with_max_cost(Cost::new(max_cost))
+            .with_fuel(&mut fuel),
+    )
+    .map_err(|e| js_err(e.1))?;
+    let result = to_bytes(&Node::new(&a, r.1)).map_err(js_err)?;
+    Ok(RunResult {
+        cost: r.0.get(),
+        result,
+    })
+}
+
+// operators implemented by a host function, called with the opcode and the
+// serialized argument list. It returns `undefined` for opcodes it doesn't
+// implement, and `[cost, result]` (with the result serialized) for the ones
+// it does. An exception, or any other value, fails the program
+#[derive(Clone)]
+struct JsOperators(Function);
+
+impl HostOperators<IntAllocator> for JsOperators {
+    fn op(
+        &self,
+        allocator: &mut IntAllocator,
+        op: <IntAllocator as Allocator>::AtomBuf,
+        args: &i32,
+        _max_cost: Cost,
+    ) -> Option<Response<i32>> {
+        let failed = || Some(Err(EvalErr::new(*args, "host operator failed")));
+        let op = Uint8Array::from(allocator.buf(&op));
+        let serialized = match to_bytes(&Node::new(allocator, *args)) {
+            Ok(v) => Uint8Array::from(v.as_slice()),
+            Err(_) => return failed(),
+        };
+        let r = match self.0.call2(&JsValue::NULL, &op, &serialized) {
+            Ok(r) => r,
+            Err(_) => return failed(),
+        };
+        if r.is_undefined() {
+            return None;
+        }
+        let r = Array::from(&r);
+        let cost = r.get(0).as_f64();
+        let result = r.get(1).dyn_into::<Uint8Array>().ok();
+        match (cost, result) {
+            (Some(cost), Some(result)) if r.length() == 2 && cost >= 0.0 => {
+                match from_bytes(allocator, &result.to_vec()) {
+                    Ok(node) => Some(Ok(Reduction(Cost::new(cost as u64), node))),
+                    Err(_) => failed(),
+                }
+            }
+            _ => failed(),
+        }
+    }
+}
+
+// like run_program(), but operators chia's table doesn't implement are
+// offered to `operators` first (see JsOperators)
+#[wasm_bindgen]
+pub fn run_program_with_operators(
+    program: &[u8],
+    args: &[u8],
+    max_cost: u64,
+    operators: Function,
+) -> Result<RunResult, JsValue> {
+    let mut a = IntAllocator::new();
+    let program = from_bytes(&mut a, program).map_err(js_err)?;
+    let args = from_bytes(&mut a, args).map_err(js_err)?;
+    let table = HostOpTable {
+        table: op_table_for(&CHIA_OPCODES),
+        host: JsOperators(operators),
+    };
+    let r = run_node(
+        &mut a,
+        &program,
+        &args,
+        1,
+        2,
+        Cost::new(max_cost),
+        Box::new(table),
+        None,
+    )
+    .map_err(|e| js_err(e.1))?;
+    let result = to_bytes(&Node::new(&a, r.1)).map_err(js_err)?;
+    Ok(RunResult {
+        cost: r.0.get(),
+        result,
+    })
+}
+
+enum ToJsOp {
+    Visit(i32),
+    Cons,
+}
+
+fn to_js(a: &IntAllocator, node: i32) -> JsValue {
+    let mut ops = vec![ToJsOp::Visit(node)];
+    let mut values: Vec<JsValue> = Vec::new();
+    while let Some(op) = ops.pop() {
+        match op {
+            ToJsOp::Visit(node) => match a.sexp(&node) {
+                SExp::Atom(buf) => values.push(Uint8Array::from(a.buf(&buf)).into()),
+                SExp::Pair(left, right) => {
+                    ops.push(ToJsOp::Cons);
+                    ops.push(ToJsOp::Visit(right));
+                    ops.push(ToJsOp::Visit(left));
+                }
+            },
+            ToJsOp::Cons => {
+                let right = values.pop().unwrap();
+                let left = values.pop().unwrap();
+                values.push(Array::of2(&left, &right).into());
+            }
+        }
+    }
+    values.pop().unwrap()
+}
+
+enum FromJsOp {
+    Visit(JsValue),
+    Cons,
+}
+
+fn from_js(a: &mut IntAllocator, value: JsValue) -> Result<i32, JsValue> {
+    let mut ops = vec![FromJsOp::Visit(value)];
+    let mut values: Vec<i32> = Vec::new();
+    while let Some(op) = ops.pop() {
+        match op {
+            FromJsOp::Visit(v) => {
+                if let Some(bytes) = v.dyn_ref::<Uint8Array>() {
+                    values.push(a.new_atom(&bytes.to_vec()).map_err(|e| js_err(e.1))?);
+                } else if Array::is_array(&v) {
+                    let pair: Array = v.unchecked_into();
+                    if pair.length() != 2 {
+                        return Err(js_err("pairs must be arrays of length 2"));
+                    }
+                    ops.push(FromJsOp::Cons);
+                    ops.push(FromJsOp::Visit(pair.get(1)));
+                    ops.push(FromJsOp::Visit(pair.get(0)));
+                } else {
+                    return Err(js_err("expected Uint8Array or Array"));
+                }
+            }
+            FromJsOp::Cons => {
+                let right = values.pop().unwrap();
+                let left = values.pop().unwrap();
+                values.push(a.new_pair(left, right).map_err(|e| js_err(e.1))?);
+            }
+        }
+    }
+    Ok(values.pop().unwrap())
+}
+
+#[wasm_bindgen]
+pub fn node_from_bytes(blob: &[u8]) -> Result<JsValue, JsValue> {
+    let mut a = IntAllocator::new();
+    let node = from_bytes(&mut a, blob).map_err(js_err)?;
+    Ok(to_js(&a, node))
+}
+
+#[wasm_bindgen]
+pub fn node_to_bytes(value: JsValue) -> Result<Vec<u8>, JsValue> {
+    let mut a = IntAllocator::new();
+    let node = from_js(&mut a, value)?;
+    to_bytes(&Node::new(&a, node)).map_err(js_err)
+}
+
+// the tree hash of a serialized value
+#[wasm_bindgen]
+pub fn tree_hash(blob: &[u8]) -> Result<Vec<u8>, JsValue> {
+    let mut a = IntAllocator::new();
+    let node = from_bytes(&mut a, blob).map_err(js_err)?;
+    Ok(hash_node(&Node::new(&a, node)).to_vec())
+}
+
+// the serialized program with the serialized arguments (an Array of
+// Uint8Array) bound to it
+#[wasm_bindgen]
+pub fn curry(program: &[u8], args: Array) -> Result<Vec<u8>, JsValue> {
+    let mut a = IntAllocator::new();
+    let program = from_bytes(&mut a, program).map_err(js_err)?;
+    let mut arg_nodes = Vec::with_capacity(args.length() as usize);
+    for arg in args.iter() {
+        let blob = arg
+            .dyn_ref::<Uint8Array>()
+            .ok_or_else(|| js_err("curry arguments must be Uint8Array"))?
+            .to_vec();
+        arg_nodes.push(from_bytes(&mut a, &blob).map_err(js_err)?);
+    }
+    let curried = curry_node(&mut a, program, &arg_nodes).map_err(|e| js_err(e.1))?;
+    to_bytes(&Node::new(&a, curried)).map_err(js_err)
+}
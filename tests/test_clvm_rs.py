@@ -0,0 +1,207 @@
+# tests of the python bindings. Build them into the current virtualenv with
+# `maturin develop --cargo-extra-args="--features extension-module"`, then run
+# `python -m pytest tests`. The errors of some run functions are clvm's
+# EvalError, so clvm has to be installed too
+
+import gc
+import hashlib
+import weakref
+
+import pytest
+
+from clvm.EvalError import EvalError
+
+from clvm_rs import (
+    STRICT_MODE,
+    Arena,
+    NativeOpLookup,
+    PyNode,
+    RunParams,
+    py_run_program,
+    run_serialized_program_with_params,
+    run_serialized_to_bytes,
+    serialize_and_run_program,
+    serialize_from_bytes,
+    serialize_to_bytes,
+    tree_hash,
+)
+
+# (+ 2 5) run with (3 4), with the default operators
+ADD = bytes.fromhex("ff0cff02ff0580")
+ADD_ARGS = bytes.fromhex("ff03ff0480")
+ADD_COST = 856
+# (x (q . "bang"))
+RAISE = bytes.fromhex("ff09ffff018462616e6780")
+# (0x3f 1), an operator none of the tables have
+UNKNOWN = bytes.fromhex("ff3fff0180")
+NIL = bytes.fromhex("80")
+
+
+def test_serialize_round_trip():
+    node = serialize_from_bytes(ADD)
+    assert serialize_to_bytes(node) == ADD
+    assert node.pair[0].atom == b"\x0c"
+    assert node.at("rf").atom == b"\x02"
+    assert node.at(2).atom == b"\x0c"
+    assert node.at(5).atom == b"\x02"
+    assert node.at("ff") is None
+
+
+def sha256_tree(tree):
+    if isinstance(tree, tuple):
+        left, right = tree
+        return hashlib.sha256(b"\x02" + sha256_tree(left) + sha256_tree(right)).digest()
+    return hashlib.sha256(b"\x01" + tree).digest()
+
+
+def test_tree_hash():
+    assert tree_hash(NIL) == sha256_tree(b"")
+    assert tree_hash(ADD) == sha256_tree(serialize_from_bytes(ADD).to_python())
+
+
+def test_plain_values():
+    assert PyNode(b"abc").atom == b"abc"
+    assert PyNode(0).atom == b""
+    assert PyNode(5).atom == b"\x05"
+    assert PyNode(-1).atom == b"\xff"
+    assert PyNode(128).atom == b"\x00\x80"
+    assert PyNode(2 ** 70).atom == b"\x40" + bytes(8)
+    assert PyNode("hi").atom == b"hi"
+    assert PyNode(None).atom == b""
+    node = PyNode((1, [b"a", "b"]))
+    assert serialize_to_bytes(node) == bytes.fromhex("ff01ff61ff6280")
+
+
+def test_from_python():
+    tree = (b"a", (b"bc", b""))
+    assert PyNode.from_python(tree).to_python() == tree
+    assert PyNode.from_python([1, 2]).to_python() == (b"\x01", (b"\x02", b""))
+    # PyNodes can be leaves
+    leaf = PyNode((b"x", b"y"))
+    assert PyNode.from_python([leaf]).to_python() == ((b"x", b"y"), b"")
+
+
+def test_to_python_shares_objects():
+    # a subtree that appears twice becomes the same tuple
+    shared = PyNode((b"hello", b"world"))
+    left, right = PyNode.from_python((shared, shared)).to_python()
+    assert left is right
+
+    # so do small atoms with the same contents, but not large ones
+    left, right = PyNode.from_python((b"hello", b"hello")).to_python()
+    assert left is right
+    big = bytes(100)
+    left, right = PyNode.from_python((big, big)).to_python()
+    assert left == right
+    assert left is not right
+
+
+def test_bad_values():
+    with pytest.raises(ValueError):
+        PyNode((1, 2, 3))
+    with pytest.raises(TypeError):
+        PyNode(1.5)
+    with pytest.raises(TypeError):
+        PyNode.from_python([b"a", object()])
+    with pytest.raises(ValueError):
+        serialize_from_bytes(b"\xff")
+    with pytest.raises(TypeError):
+        serialize_to_bytes(b"abc")
+
+
+def test_costs_are_ints():
+    cost, result = serialize_and_run_program(ADD, ADD_ARGS, 1, 2, 0, 0)
+    assert (cost, result) == (ADD_COST, b"\x07")
+    assert type(cost) is int
+    params = RunParams()
+    assert params.max_cost == 0
+    params.max_cost = 2 ** 40
+    assert params.max_cost == 2 ** 40
+
+
+def test_run_params():
+    params = RunParams(max_cost=ADD_COST)
+    assert run_serialized_program_with_params(ADD, ADD_ARGS, params) == (
+        ADD_COST,
+        b"\x07",
+    )
+
+    params = RunParams(max_cost=ADD_COST - 1)
+    with pytest.raises(EvalError, match="cost exceeded"):
+        run_serialized_program_with_params(ADD, ADD_ARGS, params)
+
+    params = RunParams(max_steps=1)
+    with pytest.raises(EvalError, match="too many steps"):
+        run_serialized_program_with_params(ADD, ADD_ARGS, params)
+
+    assert run_serialized_program_with_params(UNKNOWN, NIL, RunParams()) == (46, NIL)
+    params = RunParams(flags=STRICT_MODE)
+    with pytest.raises(EvalError, match="unimplemented operator"):
+        run_serialized_program_with_params(UNKNOWN, NIL, params)
+
+
+def test_arena():
+    arena = Arena()
+    arena.load_program(ADD)
+    for _ in range(3):
+        r = serialize_and_run_program(ADD, ADD_ARGS, 1, 2, 0, 0, arena=arena)
+        assert r == (ADD_COST, b"\x07")
+    params = RunParams()
+    r = run_serialized_program_with_params(ADD, ADD_ARGS, params, arena=arena)
+    assert r == (ADD_COST, b"\x07")
+    assert arena.capacity_bytes > 0
+
+    # failures are rolled back too
+    with pytest.raises(EvalError):
+        serialize_and_run_program(RAISE, NIL, 1, 2, 0, 0, arena=arena)
+    arena.reset()
+    r = serialize_and_run_program(ADD, ADD_ARGS, 1, 2, 0, 0, arena=arena)
+    assert r == (ADD_COST, b"\x07")
+
+
+def test_run_serialized_to_bytes():
+    assert run_serialized_to_bytes(ADD, ADD_ARGS, 1, 2, 0, 0) == (ADD_COST, b"\x07")
+    assert run_serialized_to_bytes(UNKNOWN, NIL, 1, 2, 0, 0) == (46, NIL)
+
+    with pytest.raises(ValueError) as e:
+        run_serialized_to_bytes(RAISE, NIL, 1, 2, 0, 0)
+    assert e.value.args == ("clvm raise", bytes.fromhex("ff8462616e6780"))
+
+    with pytest.raises(ValueError) as e:
+        run_serialized_to_bytes(UNKNOWN, NIL, 1, 2, 0, STRICT_MODE)
+    assert e.value.args[0] == "unimplemented operator"
+
+    # the quote keyword can't be an operator
+    with pytest.raises(ValueError):
+        run_serialized_to_bytes(ADD, ADD_ARGS, 12, 2, 0, 0)
+
+
+def test_python_operators():
+    calls = []
+
+    def unknown_op(op, args):
+        calls.append((op, args.to_python()))
+        return 10, PyNode(b"ok")
+
+    lookup = NativeOpLookup({}, unknown_op)
+    program = PyNode.from_python([b"\x3f", 1])
+    cost, result = py_run_program(program, PyNode(b"hi"), 1, 2, 0, lookup, None)
+    assert result.atom == b"ok"
+    assert cost > 10
+    assert calls == [(b"\x3f", (b"hi", b""))]
+
+
+def test_lookup_cycle_is_collected():
+    # the operators are often a method of an object holding the lookup
+    class Operators:
+        def __init__(self):
+            self.lookup = NativeOpLookup({}, self.unknown_op)
+
+        def unknown_op(self, op, args):
+            return 0, args
+
+    ops = Operators()
+    ref = weakref.ref(ops)
+    del ops
+    gc.collect()
+    assert ref() is None